@@ -0,0 +1,144 @@
+use std::fs::{read_dir, remove_file, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use config::Config;
+
+/// Name of the file created to check that the state directory is writable
+const PROBE_FILE: &str = ".logdna-agent-access-probe";
+
+/// Checks that the configured sources can be read, and the state directory written, as the
+/// user the agent runs as. Sources that can't be accessed are dropped from `config` with a
+/// message saying why, unless `config.log.require_access` is set, in which case they are
+/// returned as errors for the agent to stop.
+pub fn audit(config: &mut Config) -> Result<(), Vec<String>> {
+    info!("running as uid {} with {}", uid(), capabilities());
+    let mut errors = Vec::new();
+
+    config.log.dirs.retain(|dir| match check_dir(dir) {
+        Ok(()) => true,
+        Err(e) => {
+            errors.push(format!(
+                "unable to read log directory {:?}: {}",
+                dir.as_ref(),
+                e
+            ));
+            false
+        }
+    });
+    config.journald.paths.retain(|path| match check(path) {
+        Ok(()) => true,
+        Err(e) => {
+            errors.push(format!("unable to read journald path {:?}: {}", path, e));
+            false
+        }
+    });
+    config.auditd.paths.retain(|path| match check(path) {
+        Ok(()) => true,
+        Err(e) => {
+            errors.push(format!("unable to read auditd path {:?}: {}", path, e));
+            false
+        }
+    });
+    if let Some(path) = config.log.db_path.as_ref() {
+        if let Err(e) = check_writable(path) {
+            errors.push(format!(
+                "unable to write state directory {:?}, offsets won't be saved: {}",
+                path, e
+            ));
+            config.log.db_path = None;
+        }
+    }
+
+    if config.log.require_access && !errors.is_empty() {
+        return Err(errors);
+    }
+    for error in errors {
+        warn!("{}, skipping it", error);
+    }
+    Ok(())
+}
+
+/// Missing paths aren't access errors, sources already report them or wait for them
+fn check(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        check_dir(path)
+    } else {
+        ignore_missing(File::open(path).map(|_| ()))
+    }
+}
+
+fn check_dir(path: &Path) -> io::Result<()> {
+    ignore_missing(read_dir(path).map(|_| ()))
+}
+
+fn check_writable(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let probe = path.join(PROBE_FILE);
+    OpenOptions::new().write(true).create(true).open(&probe)?;
+    remove_file(probe)
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+/// The effective capabilities of the process, as reported by /proc
+fn capabilities() -> String {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .map(|caps| format!("effective capabilities {}", caps.trim()))
+        })
+        .unwrap_or_else(|| "unknown capabilities".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn ignores_missing_sources() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(check(&missing).is_ok());
+        assert!(check_dir(&missing).is_ok());
+        assert!(check_writable(&missing).is_ok());
+    }
+
+    #[test]
+    fn checks_sources_can_be_read() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("app.log");
+        std::fs::write(&file, b"a line\n").unwrap();
+        assert!(check(dir.path()).is_ok());
+        assert!(check(&file).is_ok());
+        // A log directory that turned out to be a file can't be listed
+        assert!(check_dir(&file).is_err());
+    }
+
+    #[test]
+    fn checks_the_state_directory_can_be_written() {
+        let dir = tempdir().unwrap();
+        assert!(check_writable(dir.path()).is_ok());
+        // The probe is cleaned up
+        assert!(!dir.path().join(PROBE_FILE).exists());
+
+        let file = dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+        assert!(check_writable(&file).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use clap::ArgMatches;
+use config::Config;
+use fs::tail::{DirPathBuf, Lookback};
+use metrics::Metrics;
+
+/// How often the generator writes the lines due
+const TICK: Duration = Duration::from_millis(100);
+/// How long the pipeline is given to read the last lines written
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Lines written per tick when there's no rate limit
+const UNLIMITED_BATCH: u64 = 10_000;
+const FILLER: &str = "lorem ipsum dolor sit amet consectetur adipiscing elit ";
+
+/// `logdna-agent bench`, writes synthetic or replayed lines to files the agent tails through
+/// its whole pipeline in dry run mode, then reports the throughput and resource usage
+pub struct Options {
+    duration: Duration,
+    /// Lines written per second, as fast as possible when 0
+    rate: u64,
+    /// Bytes of the synthetic lines
+    line_size: usize,
+    files: usize,
+    /// Lines of a recorded corpus replayed in a loop instead of synthetic ones
+    replay: Option<Vec<String>>,
+    dir: PathBuf,
+}
+
+impl Options {
+    /// From the matches of the `bench` subcommand, also sets up the env so that the config
+    /// loads without an ingestion key, this must run before any thread is started
+    pub fn from_matches(matches: &ArgMatches) -> anyhow::Result<Options> {
+        let number = |name: &str| -> anyhow::Result<u64> {
+            let value = matches.value_of(name).expect("default value");
+            value
+                .parse()
+                .with_context(|| format!("--{} must be a number, got {}", name, value))
+        };
+        let replay = match matches.value_of("replay") {
+            Some(path) => {
+                let corpus = std::fs::read_to_string(path)
+                    .with_context(|| format!("unable to read {}", path))?;
+                let lines: Vec<String> = corpus
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if lines.is_empty() {
+                    return Err(anyhow!("{} has no lines to replay", path));
+                }
+                Some(lines)
+            }
+            None => None,
+        };
+        let dir = std::env::temp_dir().join(format!("logdna-agent-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).with_context(|| format!("unable to create {:?}", dir))?;
+        std::env::set_var("LOGDNA_DRY_RUN", "true");
+
+        Ok(Options {
+            duration: Duration::from_secs(number("duration")?),
+            rate: number("rate")?,
+            line_size: number("line-size")? as usize,
+            files: number("files")?.max(1) as usize,
+            replay,
+            dir,
+        })
+    }
+
+    /// Points the config at the generated files only, read from their start
+    pub fn apply(&self, config: &mut Config) -> anyhow::Result<()> {
+        let dir = DirPathBuf::try_from(self.dir.clone()).map_err(|e| anyhow!("{}", e))?;
+        config.log.dirs = vec![dir];
+        config.log.lookback = Lookback::Start;
+        config.log.db_path = None;
+        Ok(())
+    }
+
+    /// Starts writing the lines, then reports the results and exits once the duration elapsed
+    pub fn start(self) {
+        spawn(move || {
+            let started = Instant::now();
+            let code = match self.generate(started) {
+                Ok(written) => {
+                    // Lets the pipeline catch up with the last lines written
+                    let deadline = Instant::now() + DRAIN_TIMEOUT;
+                    while Metrics::fs().read_lines() < written && Instant::now() < deadline {
+                        sleep(TICK);
+                    }
+                    report(started.elapsed(), written);
+                    0
+                }
+                Err(e) => {
+                    error!("{:#}", e);
+                    1
+                }
+            };
+            if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+                warn!("unable to remove {:?}: {}", self.dir, e);
+            }
+            std::process::exit(code);
+        });
+    }
+
+    /// Writes the lines due at the rate, round robin across the files, until the duration
+    /// elapsed. Returns how many lines were written.
+    fn generate(&self, started: Instant) -> anyhow::Result<u64> {
+        let mut files = Vec::with_capacity(self.files);
+        for i in 0..self.files {
+            let path = self.dir.join(format!("bench-{}.log", i));
+            let file =
+                File::create(&path).with_context(|| format!("unable to create {:?}", path))?;
+            files.push(BufWriter::new(file));
+        }
+        info!(
+            "writing to {} files in {:?} for {}s",
+            self.files,
+            self.dir,
+            self.duration.as_secs()
+        );
+
+        let mut written = 0;
+        while started.elapsed() < self.duration {
+            let due = if self.rate == 0 {
+                written + UNLIMITED_BATCH
+            } else {
+                self.rate * started.elapsed().as_millis() as u64 / 1000
+            };
+            while written < due {
+                let file = &mut files[written as usize % self.files];
+                writeln!(file, "{}", self.line(written))?;
+                written += 1;
+            }
+            for file in files.iter_mut() {
+                file.flush()?;
+            }
+            if self.rate > 0 {
+                sleep(TICK);
+            }
+        }
+        Ok(written)
+    }
+
+    fn line(&self, n: u64) -> String {
+        if let Some(corpus) = self.replay.as_ref() {
+            return corpus[n as usize % corpus.len()].clone();
+        }
+        let mut line = format!("{} INFO bench line {} ", Utc::now().to_rfc3339(), n);
+        let missing = self.line_size.saturating_sub(line.len());
+        line.extend(FILLER.chars().cycle().take(missing));
+        line
+    }
+}
+
+/// CPU time of the process in seconds and its peak resident memory, in KiB on Linux
+fn usage() -> Option<(f64, i64)> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let usage = unsafe { usage.assume_init() };
+    let secs = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1_000_000.0;
+    Some((
+        secs(usage.ru_utime) + secs(usage.ru_stime),
+        usage.ru_maxrss as i64,
+    ))
+}
+
+fn report(elapsed: Duration, written: u64) {
+    let secs = elapsed.as_secs_f64();
+    let lines = Metrics::fs().read_lines();
+    let mib = Metrics::fs().read_bytes() as f64 / (1024.0 * 1024.0);
+    println!("duration         {:.1}s", secs);
+    println!("lines written    {}", written);
+    println!("lines tailed     {}", lines);
+    println!(
+        "throughput       {:.0} lines/s, {:.2} MiB/s",
+        lines as f64 / secs,
+        mib / secs
+    );
+    if let Some((cpu, max_resident)) = usage() {
+        println!("cpu              {:.1}% of a core", cpu / secs * 100.0);
+        println!(
+            "peak memory      {:.1} MiB resident",
+            max_resident as f64 / 1024.0
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Context};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use config::env::Config as EnvConfig;
+use config::legacy::LegacyConfig;
+use std::path::{Path, PathBuf};
+
+use crate::{bench, daemon, state_cli};
+
+const CONFIG_HEADING: &str = "CONFIG OPTIONS";
+
+/// What the agent was asked to do
+pub enum Command {
+    /// Tails and ships logs, also what the agent does without a subcommand
+    Run(daemon::Options),
+    /// Loads the config and reports what's wrong with it
+    CheckConfig,
+    /// Checks the config, access to the sources and the ingest API
+    Doctor,
+    State(state_cli::Options),
+    /// Measures the throughput of the pipeline on generated lines
+    Bench(bench::Options),
+    /// Converts the config of the v1 agent
+    MigrateConfig {
+        path: PathBuf,
+        env: bool,
+    },
+    Version {
+        json: bool,
+    },
+}
+
+/// The flag of an env var, e.g. `--batch-max-lines` for `LOGDNA_BATCH_MAX_LINES`
+fn flag(var: &str) -> String {
+    var.trim_start_matches("LOGDNA_")
+        .to_lowercase()
+        .replace('_', "-")
+}
+
+/// Parses the command line, exiting with usage on invalid arguments and `--help`.
+///
+/// Every env var of the config has a flag, set flags take precedence over the env vars by
+/// overriding them before the config is loaded, so this must run before any thread is
+/// started.
+pub fn parse() -> anyhow::Result<Command> {
+    let vars = EnvConfig::env_vars();
+    let bool_vars = EnvConfig::bool_env_vars();
+    let flags: Vec<(String, String)> = vars
+        .iter()
+        .map(|var| (flag(var), format!("Sets {}", var)))
+        .collect();
+
+    let daemon_args = [
+        Arg::new("daemon")
+            .long("daemon")
+            .overrides_with("foreground")
+            .help("Detaches from the terminal to run as a daemon"),
+        Arg::new("foreground")
+            .long("foreground")
+            .overrides_with("daemon")
+            .help("Stays attached to the terminal or supervisor, the default"),
+        Arg::new("pid-file")
+            .long("pid-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Where the daemon writes its process id"),
+        Arg::new("log-file")
+            .long("log-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Where the daemon appends its output, discarded otherwise"),
+    ];
+    let mut app = App::new("logdna-agent")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Tails the logs of the host and ships them to LogDNA")
+        .args(daemon_args.clone())
+        .subcommand(
+            App::new("run")
+                .about("Tails and ships logs, the default without a subcommand")
+                .args(daemon_args),
+        )
+        .subcommand(
+            App::new("check-config")
+                .about("Loads the config and reports what is invalid in it, without running"),
+        )
+        .subcommand(App::new("doctor").about(
+            "Checks the config, the access to the sources and state directory, and that the \
+             ingest API accepts the ingestion key",
+        ))
+        .subcommand(
+            App::new("state")
+                .about(
+                    "Exports or imports the offsets and timestamps of what was shipped, the \
+                     state db must not be in use by a running agent",
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("export")
+                        .about("Writes the state as JSON to the file, or to stdout")
+                        .arg(Arg::new("file")),
+                )
+                .subcommand(
+                    App::new("import")
+                        .about("Reads the state from the JSON file, or from stdin for -")
+                        .arg(Arg::new("file").required(true)),
+                ),
+        )
+        .subcommand(
+            App::new("bench")
+                .about(
+                    "Tails generated or replayed lines through the pipeline without sending \
+                     them, and reports the throughput, CPU and memory used",
+                )
+                .arg(
+                    Arg::new("duration")
+                        .long("duration")
+                        .takes_value(true)
+                        .value_name("SECONDS")
+                        .default_value("30")
+                        .help("How long lines are written for"),
+                )
+                .arg(
+                    Arg::new("rate")
+                        .long("rate")
+                        .takes_value(true)
+                        .value_name("LINES")
+                        .default_value("10000")
+                        .help("Lines written per second, 0 to write them as fast as possible"),
+                )
+                .arg(
+                    Arg::new("line-size")
+                        .long("line-size")
+                        .takes_value(true)
+                        .value_name("BYTES")
+                        .default_value("256")
+                        .help("Size of the generated lines"),
+                )
+                .arg(
+                    Arg::new("files")
+                        .long("files")
+                        .takes_value(true)
+                        .value_name("COUNT")
+                        .default_value("1")
+                        .help("Number of files the lines are spread across"),
+                )
+                .arg(
+                    Arg::new("replay")
+                        .long("replay")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Replays the lines of a recorded file in a loop instead"),
+                ),
+        )
+        .subcommand(
+            App::new("migrate-config")
+                .about("Prints the equivalent of the config of the v1 agent")
+                .arg(
+                    Arg::new("file")
+                        .default_value("/etc/logdna.conf")
+                        .help("The key = value config of the v1 agent"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["yaml", "env"])
+                        .default_value("yaml")
+                        .help("Prints a config file or env vars"),
+                ),
+        )
+        .subcommand(
+            App::new("version")
+                .about("Prints the version of the agent")
+                .arg(Arg::new("json").long("json").help("Prints it as JSON")),
+        );
+    for ((flag, help), var) in flags.iter().zip(vars.iter()) {
+        let arg = Arg::new(flag.as_str())
+            .long(flag.as_str())
+            .help(help.as_str())
+            .help_heading(CONFIG_HEADING)
+            .takes_value(true)
+            .value_name("VALUE")
+            .global(true);
+        app = app.arg(if bool_vars.contains(var) {
+            // Bare flags such as --dry-run enable boolean options, a value must then follow an
+            // equals sign so that the subcommand after the flag isn't taken as its value
+            arg.min_values(0)
+                .require_equals(true)
+                .default_missing_value("true")
+        } else {
+            arg
+        });
+    }
+
+    let matches = app.get_matches();
+    let (command, leaf) = match matches.subcommand() {
+        Some((name, sub)) => (Some(name), sub),
+        None => (None, &matches),
+    };
+    let leaf = match leaf.subcommand() {
+        Some((_, sub)) => sub,
+        None => leaf,
+    };
+    // Global flags are propagated down to the deepest subcommand
+    for ((flag, _), var) in flags.iter().zip(vars.iter()) {
+        if let Some(value) = leaf.value_of(flag.as_str()) {
+            std::env::set_var(var, value);
+        }
+    }
+
+    Ok(match command {
+        None => Command::Run(daemon_options(&matches)),
+        Some("run") => Command::Run(daemon_options(leaf)),
+        Some("check-config") => Command::CheckConfig,
+        Some("doctor") => Command::Doctor,
+        Some("state") => Command::State(state_cli::Options::from_matches(
+            matches
+                .subcommand_matches("state")
+                .expect("state subcommand"),
+        )?),
+        Some("bench") => Command::Bench(bench::Options::from_matches(leaf)?),
+        Some("migrate-config") => Command::MigrateConfig {
+            path: leaf.value_of("file").expect("default file").into(),
+            env: leaf.value_of("format") == Some("env"),
+        },
+        Some("version") => Command::Version {
+            json: leaf.is_present("json"),
+        },
+        Some(other) => unreachable!("unknown subcommand {}", other),
+    })
+}
+
+fn daemon_options(matches: &ArgMatches) -> daemon::Options {
+    daemon::Options {
+        daemon: matches.is_present("daemon"),
+        pid_file: matches.value_of("pid-file").map(Into::into),
+        log_file: matches.value_of("log-file").map(Into::into),
+    }
+}
+
+/// `logdna-agent migrate-config [<file>] [--format yaml|env]`, prints the options of the v1
+/// config as a config file or env vars, and lists the ones that have no equivalent
+pub fn migrate_config(path: &Path, env: bool) -> anyhow::Result<()> {
+    let legacy =
+        std::fs::read_to_string(path).with_context(|| format!("unable to read {:?}", path))?;
+    let legacy = LegacyConfig::parse(&legacy).map_err(|e| anyhow!("{}", e))?;
+    if env {
+        legacy.to_env().iter().for_each(|var| println!("{}", var));
+    } else {
+        print!("{}", serde_yaml::to_string(&legacy.to_raw())?);
+    }
+    for option in legacy.ignored.iter() {
+        warn!("{} has no equivalent and was left out", option);
+    }
+    Ok(())
+}
+
+/// `logdna-agent version [--json]`
+pub fn print_version(json: bool) {
+    let name = env!("CARGO_PKG_NAME");
+    let version = env!("CARGO_PKG_VERSION");
+    if json {
+        let version = serde_json::json!({
+            "name": name,
+            "version": version,
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+        });
+        println!("{}", version);
+    } else {
+        println!("{} {}", name, version);
+    }
+}
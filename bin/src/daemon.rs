@@ -0,0 +1,69 @@
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// How the agent runs on hosts managed by an init system, `--foreground`, the default, keeps
+/// it attached to the terminal or supervisor that started it, while `--daemon` detaches it
+/// for init scripts that expect the process to fork
+#[derive(Debug, Default)]
+pub struct Options {
+    pub daemon: bool,
+    /// Where the id of the detached process is written, `--pid-file <path>`
+    pub pid_file: Option<PathBuf>,
+    /// Where the output of the detached process is appended, `--log-file <path>`, it's
+    /// discarded otherwise
+    pub log_file: Option<PathBuf>,
+}
+
+/// Detaches the process from its terminal with the usual double fork, must be called before
+/// any thread is started since only the calling thread survives a fork
+pub fn daemonize(options: &Options) -> io::Result<()> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Forking again means the daemon isn't a session leader and can't acquire a terminal
+    fork_and_exit_parent()?;
+
+    redirect_stdio(options.log_file.as_deref())?;
+    if let Some(path) = options.pid_file.as_ref() {
+        std::fs::write(path, format!("{}\n", std::process::id()))?;
+    }
+    // Relative paths given as options are resolved before leaving the working directory
+    let root = CString::new("/").unwrap();
+    if unsafe { libc::chdir(root.as_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn fork_and_exit_parent() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+fn redirect_stdio(log_file: Option<&Path>) -> io::Result<()> {
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => null.try_clone()?,
+    };
+    for (from, to) in &[
+        (null.as_raw_fd(), libc::STDIN_FILENO),
+        (output.as_raw_fd(), libc::STDOUT_FILENO),
+        (output.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        if unsafe { libc::dup2(*from, *to) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use config::Config;
+use tokio::runtime::Builder;
+
+use crate::access;
+
+/// `logdna-agent check-config`, loads the config the way the agent would and reports whether
+/// it's valid, without touching the sources or the network
+pub fn check_config() -> bool {
+    match Config::new() {
+        Ok(_) => {
+            println!("config is valid");
+            true
+        }
+        Err(e) => {
+            println!("config error: {}", e);
+            false
+        }
+    }
+}
+
+fn report(check: &str, result: Result<(), String>) -> bool {
+    match result {
+        Ok(()) => {
+            println!("[ok]   {}", check);
+            true
+        }
+        Err(e) => {
+            println!("[fail] {}: {}", check, e);
+            false
+        }
+    }
+}
+
+/// `logdna-agent doctor`, runs the checks the agent runs on startup and reports all their
+/// failures instead of stopping at the first one
+pub fn run() -> bool {
+    let mut config = match Config::new() {
+        Ok(config) => config,
+        Err(e) => return report("config", Err(e.to_string())),
+    };
+    let mut healthy = report("config", Ok(()));
+
+    // Sources the agent would skip are failures here
+    config.log.require_access = true;
+    healthy &= report(
+        "access to the sources and state directory",
+        access::audit(&mut config).map_err(|errors| errors.join(", ")),
+    );
+
+    if !config.http.enabled || config.http.dry_run {
+        println!("[skip] ingest API, sending lines to LogDNA is disabled");
+        return healthy;
+    }
+    let validated = Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())
+        .and_then(|rt| {
+            rt.block_on(http::validate::validate(
+                config.http.template.clone(),
+                config.http.timeout,
+            ))
+            .map_err(|e| e.to_string())
+        });
+    healthy &= report("ingest API and ingestion key", validated);
+    healthy
+}
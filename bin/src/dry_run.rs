@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often the summary of a dry run is logged
+const REPORT_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct SourceStats {
+    lines: u64,
+    bytes: u64,
+}
+
+/// Counts what a dry run would have shipped, per source
+pub(crate) struct Summary {
+    sources: HashMap<String, SourceStats>,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Summary {
+    pub(crate) fn new() -> Self {
+        Summary {
+            sources: HashMap::new(),
+            started: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Records a line of `bytes` from `source`, its file or otherwise its app
+    pub(crate) fn record(&mut self, source: Option<&str>, bytes: usize) {
+        let stats = match self.sources.get_mut(source.unwrap_or("unknown")) {
+            Some(stats) => stats,
+            None => self
+                .sources
+                .entry(source.unwrap_or("unknown").to_string())
+                .or_default(),
+        };
+        stats.lines += 1;
+        stats.bytes += bytes as u64;
+    }
+
+    pub(crate) fn report_if_due(&mut self) {
+        if self.last_report.elapsed() < REPORT_PERIOD {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        let mut sources: Vec<_> = self.sources.iter().collect();
+        sources.sort_by(|(_, a), (_, b)| b.bytes.cmp(&a.bytes));
+        let (lines, bytes) = sources
+            .iter()
+            .fold((0, 0), |(l, b), (_, s)| (l + s.lines, b + s.bytes));
+        info!(
+            "dry run, would have shipped {} lines, {} bytes in {}s",
+            lines,
+            bytes,
+            self.started.elapsed().as_secs()
+        );
+        for (source, stats) in sources {
+            info!("  {}: {} lines, {} bytes", source, stats.lines, stats.bytes);
+        }
+    }
+}
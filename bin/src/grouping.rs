@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use fs::cache::tailed_file::LazyLineSerializer;
+use http::client::Client;
+use http::types::body::{Line, LineBufferMut, LineMeta};
+use state::Checkpoint;
+
+use crate::stream_adapter::StrictOrLazyLines;
+
+/// Lines held at most before they are handed to the client, whatever the time to the next tick
+const MAX_HELD_LINES: usize = 5_000;
+
+pub(crate) enum HeldLine {
+    Strict(Line, Option<Checkpoint>),
+    /// Its line buffer has to be cached, the file it's read from is read on past it meanwhile
+    Lazy(LazyLineSerializer),
+}
+
+/// The lines read since the last tick of the main loop, by source. They are handed to the
+/// client one source after another so that the lines of a file or app, which share most of
+/// their fields, are next to each other in the batches and compress better.
+#[derive(Default)]
+pub(crate) struct SourceGroups {
+    groups: Vec<Vec<HeldLine>>,
+    /// Index of the group of each source in `groups`
+    sources: HashMap<String, usize>,
+    held: usize,
+}
+
+impl SourceGroups {
+    /// Holds `line`, returns the lines to send right away: all of them once enough are held,
+    /// and `line` with the ones held before it when its line buffer can't be cached
+    pub(crate) fn hold(&mut self, mut line: HeldLine) -> Vec<HeldLine> {
+        if let HeldLine::Lazy(lazy) = &mut line {
+            if lazy.get_line_buffer().is_none() {
+                let mut lines = self.take();
+                lines.push(line);
+                return lines;
+            }
+        }
+        let source = match &line {
+            HeldLine::Strict(line, _) => line.get_file().or_else(|| line.get_app()),
+            HeldLine::Lazy(lazy) => lazy.get_file(),
+        }
+        .unwrap_or("unknown")
+        .to_string();
+
+        match self.sources.get(&source) {
+            Some(group) => self.groups[*group].push(line),
+            None => {
+                self.sources.insert(source, self.groups.len());
+                self.groups.push(vec![line]);
+            }
+        }
+        self.held += 1;
+        if self.held >= MAX_HELD_LINES {
+            self.take()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// The held lines, those of each source in the order they were read and the sources in
+    /// the order their first line was read
+    pub(crate) fn take(&mut self) -> Vec<HeldLine> {
+        self.sources.clear();
+        self.held = 0;
+        self.groups.drain(..).flatten().collect()
+    }
+}
+
+pub(crate) async fn send(client: &RefCell<Client>, lines: Vec<HeldLine>) {
+    for line in lines {
+        match line {
+            HeldLine::Strict(line, checkpoint) => {
+                client
+                    .borrow_mut()
+                    .send(StrictOrLazyLines::Strict(&line, checkpoint.as_ref()))
+                    .await
+            }
+            HeldLine::Lazy(line) => {
+                client
+                    .borrow_mut()
+                    .send(StrictOrLazyLines::Lazy(line))
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    fn line(text: &str, file: Option<&str>, app: Option<&str>) -> HeldLine {
+        let mut line = LineBuilder::new().line(text);
+        if let Some(file) = file {
+            line = line.file(file);
+        }
+        if let Some(app) = app {
+            line = line.app(app);
+        }
+        HeldLine::Strict(line.build().unwrap(), None)
+    }
+
+    fn texts(lines: Vec<HeldLine>) -> Vec<String> {
+        lines
+            .into_iter()
+            .map(|line| match line {
+                HeldLine::Strict(mut line, _) => {
+                    String::from_utf8_lossy(line.get_line_buffer().unwrap_or_default()).into_owned()
+                }
+                HeldLine::Lazy(_) => panic!("only strict lines are held in the tests"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_lines_by_source_in_read_order() {
+        let mut groups = SourceGroups::default();
+        for held in vec![
+            line("a1", Some("/var/log/a.log"), None),
+            line("b1", Some("/var/log/b.log"), None),
+            line("app1", None, Some("app")),
+            line("a2", Some("/var/log/a.log"), None),
+            line("none1", None, None),
+            line("b2", Some("/var/log/b.log"), None),
+            line("app2", None, Some("app")),
+            line("none2", None, None),
+        ] {
+            assert!(groups.hold(held).is_empty());
+        }
+        assert_eq!(
+            texts(groups.take()),
+            vec!["a1", "a2", "b1", "b2", "app1", "app2", "none1", "none2"]
+        );
+        assert!(groups.take().is_empty());
+    }
+
+    #[test]
+    fn hands_lines_over_once_enough_are_held() {
+        let mut groups = SourceGroups::default();
+        for i in 0..MAX_HELD_LINES - 1 {
+            let file = if i % 2 == 0 {
+                "/var/log/a.log"
+            } else {
+                "/var/log/b.log"
+            };
+            assert!(groups.hold(line("line", Some(file), None)).is_empty());
+        }
+        let lines = groups.hold(line("last", Some("/var/log/c.log"), None));
+        assert_eq!(lines.len(), MAX_HELD_LINES);
+        assert_eq!(texts(lines).last().map(String::as_str), Some("last"));
+        assert!(groups.take().is_empty());
+    }
+}
@@ -1,12 +1,13 @@
 #[macro_use]
 extern crate log;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::thread::spawn;
 
 use futures::Stream;
 
-use crate::stream_adapter::{StrictOrLazyLineBuilder, StrictOrLazyLines};
+use crate::grouping::{HeldLine, SourceGroups};
+use crate::stream_adapter::{to_owned_line, StrictOrLazyLineBuilder, StrictOrLazyLines};
 use config::Config;
 use env_logger::Env;
 use fs::tail::Lookback;
@@ -14,32 +15,72 @@ use fs::tail::Tailer as FSSource;
 use futures::future::Either;
 use futures::StreamExt;
 use http::client::Client;
+use http::pool::CodecPool;
+use http::types::body::{LineBufferMut, LineMeta};
+use http::types::request::RequestTemplate;
+use state::{FileOffsetFlushHandle, FileOffsetWriteHandle};
 
+#[cfg(feature = "journald_source")]
+use journald::limit::UnitLimits;
+#[cfg(feature = "journald_source")]
+use journald::options::{Backfill, ReadOptions};
+#[cfg(feature = "journald_source")]
 use journald::source::create_source;
 
+#[cfg(feature = "k8s_source")]
 use k8s::event_source::K8sEventStream;
 
-use k8s::middleware::K8sMetadata;
+#[cfg(feature = "k8s_source")]
+use k8s::middleware::{hold_pending_lines, ClusterRules, K8sIdentity, K8sMetadata};
 use k8s::K8sTrackingConf;
 use metrics::Metrics;
+use middleware::anonymize::IpAnonymizer;
 use middleware::line_rules::LineRules;
+use middleware::shadow::ShadowRules;
 use middleware::Executor;
 
 use pin_utils::pin_mut;
-use state::AgentState;
+#[cfg(feature = "remote_rules")]
+use remote_config::RemoteRules;
+#[cfg(feature = "journald_source")]
+use state::TimestampState;
+use state::{AgentState, CheckpointPolicy, DuplicateFilter};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::Duration;
 
-use tokio::runtime::Runtime;
+use tokio::runtime::Builder;
+use tokio::signal::unix::{signal, SignalKind};
 
 const POLL_PERIOD_MS: u64 = 100;
+const VALIDATION_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Agent state key recording when the journald backfill ran
+#[cfg(feature = "journald_source")]
+const JOURNALD_BACKFILL_KEY: &str = "journald:backfill";
 
+mod access;
+mod bench;
+mod cli;
+mod daemon;
 mod dep_audit;
+mod doctor;
+mod dry_run;
+mod grouping;
+mod routes;
+mod self_telemetry;
+mod state_cli;
 mod stream_adapter;
+mod tags_file;
+mod watchdog;
 
+#[cfg(feature = "jemalloc")]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static ALLOC: mimallocator::MiMalloc = mimallocator::MiMalloc;
+
 // Statically include the CARGO_PKG_NAME and CARGO_PKG_VERSIONs in the binary
 // and export under the PKG_NAME and PKG_VERSION symbols.
 // These are used to identify the application and version, for example as part
@@ -49,16 +90,203 @@ pub static PKG_NAME: &str = env!("CARGO_PKG_NAME");
 #[no_mangle]
 pub static PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Checks that lines can be sent to the ingest API, logging what to fix and checking again
+/// until they can, then marks the agent as ready. The readiness file of an earlier run is
+/// removed first, main removes it again once the agent shuts down, so that it's only there
+/// while this run is ready
+async fn validate_ingestion(
+    template: RequestTemplate,
+    timeout: Duration,
+    readiness_file: Option<PathBuf>,
+) {
+    if let Some(path) = readiness_file.as_ref() {
+        remove_readiness_file(path);
+    }
+    loop {
+        match http::validate::validate(template.clone(), timeout).await {
+            Ok(()) => break,
+            Err(e) => {
+                error!("ingestion check failed, {}", e);
+                Metrics::http().increment_validation_failures();
+                tokio::time::sleep(VALIDATION_RETRY_DELAY).await;
+            }
+        }
+    }
+    info!("ingestion key and connectivity validated");
+    if let Some(path) = readiness_file {
+        if let Err(e) = std::fs::write(&path, b"") {
+            error!("unable to create readiness file {:?}: {}", path, e);
+        }
+    }
+}
+
+fn remove_readiness_file(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => error!("unable to remove readiness file {:?}: {}", path, e),
+    }
+}
+
+/// Resolves once the agent is asked to stop, ending the main loop so that the agent shuts down
+/// by returning from main, removing its readiness file on the way
+async fn shutdown_requested() {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("unable to listen for SIGTERM: {}", e);
+                futures::future::pending::<()>().await
+            }
+        }
+    };
+    tokio::select! {
+        _ = terminate => info!("received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT, shutting down"),
+    }
+}
+
+/// The client sending lines to the ingest API with `template`
+fn ingest_client(
+    http: &config::HttpConfig,
+    template: RequestTemplate,
+    handles: Option<(FileOffsetWriteHandle, FileOffsetFlushHandle)>,
+    codec_pool: &CodecPool,
+    dry_run: bool,
+) -> Client {
+    let mut client = Client::new(
+        template,
+        handles,
+        http.retry.clone(),
+        http.retry_step_delay,
+        codec_pool.clone(),
+    );
+    client.set_max_buffer_size(http.body_size);
+    client.set_max_buffer_lines(http.batch_max_lines);
+    client.set_flush_interval(http.batch_max_latency);
+    client.set_timeout(http.timeout);
+    client.set_connection_max_lifetime(http.connection_max_lifetime);
+    client.set_slow_request_threshold(http.slow_request_threshold);
+    client.set_retry_encryption_key(http.retry_encryption_key.clone());
+    client.set_replay_ratio(http.retry_replay_ratio);
+    client.set_strict_ordering(http.strict_ordering);
+    if let Some((gzip_level, every)) = http.compression_sample {
+        client.set_compression_sample(gzip_level, every);
+    }
+    client.set_dry_run(dry_run);
+    client
+}
+
+/// Whether the line was shipped right before the agent restarted and is read again
+fn is_restart_duplicate(
+    filter: &RefCell<Option<DuplicateFilter>>,
+    line: &mut dyn LineBufferMut,
+) -> bool {
+    let duplicate = match filter.borrow_mut().as_mut() {
+        Some(filter) => line
+            .get_line_buffer()
+            .map_or(false, |line| filter.is_duplicate(line)),
+        None => false,
+    };
+    if duplicate {
+        middleware::trace::note("pipeline", || {
+            "dropped the line, it was shipped before the restart".to_string()
+        });
+    }
+    duplicate
+}
+
+/// Where the lines that went through the middlewares are sent, for the traces of lines
+fn trace_destinations(sinks: &[sink::Queue], ingestion_enabled: bool) {
+    middleware::trace::note("pipeline", || {
+        let mut names: Vec<&str> = sinks.iter().map(sink::Queue::name).collect();
+        if ingestion_enabled {
+            names.push("the ingest API");
+        }
+        if names.is_empty() {
+            "sent the line nowhere, ingestion and the sinks are disabled".to_string()
+        } else {
+            format!("sent the line to {}", names.join(", "))
+        }
+    });
+}
+
+/// The journald backfill, only run by the first agent start with a state db, which records it so
+/// that the same records aren't sent again on every restart
+#[cfg(feature = "journald_source")]
+fn first_journald_backfill(
+    backfill: Option<Backfill>,
+    state: Option<&TimestampState>,
+) -> Option<Backfill> {
+    let backfill = backfill?;
+    let state = match state {
+        Some(state) => state,
+        None => {
+            warn!("skipping the journald backfill, the agent state db is disabled");
+            return None;
+        }
+    };
+    match state.get(JOURNALD_BACKFILL_KEY) {
+        Ok(Some(_)) => None,
+        Ok(None) => {
+            let now = chrono::Utc::now().timestamp_nanos();
+            if let Err(e) = state.set(JOURNALD_BACKFILL_KEY, now) {
+                warn!("skipping the journald backfill, unable to record it: {}", e);
+                return None;
+            }
+            Some(backfill)
+        }
+        Err(e) => {
+            warn!(
+                "skipping the journald backfill, unable to read the agent state: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
 fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-    info!("running version: {}", env!("CARGO_PKG_VERSION"));
+    let command = cli::parse();
+    let telemetry = self_telemetry::init(env_logger::Builder::from_env(
+        Env::default().default_filter_or("info"),
+    ));
 
     // Actually use the data to work around a bug in rustc:
     // https://github.com/rust-lang/rust/issues/47384
     dep_audit::get_auditable_dependency_list()
         .map_or_else(|e| trace!("{}", e), |d| trace!("{}", d));
 
-    let config = match Config::new() {
+    let (daemon_options, bench) = match command {
+        Ok(cli::Command::Run(daemon_options)) => (daemon_options, None),
+        Ok(cli::Command::Bench(bench)) => (daemon::Options::default(), Some(bench)),
+        Ok(cli::Command::CheckConfig) => std::process::exit(!doctor::check_config() as i32),
+        Ok(cli::Command::Doctor) => std::process::exit(!doctor::run() as i32),
+        Ok(cli::Command::State(options)) => {
+            if let Err(e) = state_cli::run(options) {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(cli::Command::MigrateConfig { path, env }) => {
+            if let Err(e) = cli::migrate_config(&path, env) {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(cli::Command::Version { json }) => return cli::print_version(json),
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("running version: {}", env!("CARGO_PKG_VERSION"));
+
+    let mut config = match Config::new() {
         Ok(v) => v,
         Err(e) => {
             error!("config error: {}", e);
@@ -66,17 +294,102 @@ fn main() {
         }
     };
 
-    spawn(Metrics::start);
+    if let Some(bench) = bench.as_ref() {
+        if let Err(e) = bench.apply(&mut config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(errors) = access::audit(&mut config) {
+        for error in errors {
+            error!("{}", error);
+        }
+        std::process::exit(1);
+    }
+
+    if daemon_options.daemon {
+        info!("detaching from the terminal to run as a daemon");
+        if let Err(e) = daemon::daemonize(&daemon_options) {
+            error!("unable to run as a daemon: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    Metrics::sources().set_top(config.log.metrics_top_sources);
+    let telemetry = if config.log.self_telemetry {
+        info!("Shipping the warnings, errors and metrics of the agent");
+        Some(telemetry.enable())
+    } else {
+        telemetry.disable();
+        None
+    };
+    match bench {
+        // The benchmark reads the metrics over its whole duration, they aren't reset
+        Some(bench) => bench.start(),
+        None => {
+            spawn(Metrics::start);
+        }
+    }
+
+    // Offsets aren't saved during a dry run, so that a later run still ships every line
+    let dry_run = config.http.dry_run;
+    if dry_run {
+        info!("dry run, lines will be counted instead of sent");
+    }
+    #[cfg(feature = "cloud_metadata")]
+    let cloud_metadata = if config.cloud.metadata {
+        cloud::metadata::detect_blocking(config.cloud.metadata_timeout)
+    } else {
+        None
+    };
+    #[cfg(feature = "cloud_metadata")]
+    if let Some(metadata) = cloud_metadata.as_ref() {
+        info!(
+            "running on {} instance {}",
+            metadata.provider, metadata.instance_id
+        );
+        // Instance names take precedence over detected hostnames, not over configured ones
+        if let Some(name) = metadata.name.as_ref() {
+            if config.http.hostname_detected {
+                config.http.template.params.hostname = name.clone();
+            }
+        }
+        let params = &mut config.http.template.params;
+        if params.ip.is_none() {
+            params.ip = metadata.private_ip.clone();
+        }
+        if params.mac.is_none() {
+            params.mac = metadata.mac.clone();
+        }
+    }
+    #[cfg(not(feature = "cloud_metadata"))]
+    if config.cloud.metadata {
+        warn!("cloud metadata is enabled, but the cloud_metadata feature is disabled");
+    }
 
     let mut _agent_state = None;
     let mut offset_state = None;
     let mut initial_offsets = None;
-    if !matches!(config.log.lookback, Lookback::None) {
+    let mut duplicate_filter = None;
+    if !dry_run && !matches!(config.log.lookback, Lookback::None) {
         if let Some(path) = config.log.db_path {
             match AgentState::new(path) {
                 Ok(agent_state) => {
-                    let _offset_state = agent_state.get_offset_state();
+                    let _offset_state = agent_state
+                        .get_offset_state()
+                        .with_checkpoint_policy(CheckpointPolicy {
+                            interval: config.log.checkpoint_interval,
+                            max_pending: config.log.checkpoint_max_pending,
+                        })
+                        .with_restart_dedup_window(config.log.restart_dedup_window);
                     let offsets = _offset_state.offsets();
+                    match _offset_state.duplicate_filter() {
+                        Ok(filter) => duplicate_filter = filter,
+                        Err(e) => {
+                            warn!("couldn't retrieve shipped lines from agent state, {:?}", e)
+                        }
+                    }
                     _agent_state = Some(agent_state);
                     offset_state = Some(_offset_state);
                     match offsets {
@@ -94,31 +407,188 @@ fn main() {
         }
     }
 
+    // Only read by the sources recording when they last read
+    #[cfg(any(
+        feature = "docker_source",
+        feature = "journald_source",
+        feature = "k8s_source"
+    ))]
+    let timestamp_state = _agent_state
+        .as_ref()
+        .map(|agent_state| agent_state.get_timestamp_state());
+
     let handles = offset_state
         .as_ref()
         .map(|os| (os.write_handle(), os.flush_handle()));
-    let client = Rc::new(RefCell::new(Client::new(
-        config.http.template,
+    let validation_template = config.http.template.clone();
+    let hostname = config.http.template.params.hostname.clone();
+    let codec_pool = match CodecPool::new(config.http.codec_threads) {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("unable to start the codec pool: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let client = Rc::new(RefCell::new(ingest_client(
+        &config.http,
+        config.http.template.clone(),
         handles,
-        config.http.retry_base_delay,
-        config.http.retry_step_delay,
+        &codec_pool,
+        dry_run,
     )));
-    client
-        .borrow_mut()
-        .set_max_buffer_size(config.http.body_size);
-    client.borrow_mut().set_timeout(config.http.timeout);
+    // Each route has its own client, sending with its own key the lines it matches, and
+    // records the offsets of its lines in the state db apart from the default client
+    let local = tokio::task::LocalSet::new();
+    let key_routes = std::mem::take(&mut config.http.key_routes);
+    let http_config = &config.http;
+    let routes = routes::Routes::spawn(&local, key_routes, |route| {
+        let mut template = http_config.template.clone();
+        template.api_key = route.key.clone();
+        let handles = offset_state.as_ref().map(|os| {
+            (
+                os.write_handle().with_route(&route.name),
+                os.flush_handle().with_route(&route.name),
+            )
+        });
+        ingest_client(http_config, template, handles, &codec_pool, dry_run)
+    });
+
+    let tags_file = RefCell::new(
+        config
+            .http
+            .tags_file
+            .map(|path| tags_file::TagsFile::new(path, hostname.clone())),
+    );
+    #[cfg(feature = "k8s_source")]
+    let cluster_rules = config.log.k8s_config_map.as_deref().map(ClusterRules::new);
+    #[cfg(feature = "k8s_source")]
+    let cluster_tags = cluster_rules.as_ref().map(ClusterRules::tags);
+    #[cfg(not(feature = "k8s_source"))]
+    let cluster_tags: Option<middleware::reload::SharedTags> = {
+        if config.log.k8s_config_map.is_some() {
+            warn!("a cluster ConfigMap is configured, but the k8s_source feature is disabled");
+        }
+        None
+    };
+    #[cfg(feature = "remote_rules")]
+    let remote_rules = config.remote_config.map(RemoteRules::new);
+    #[cfg(feature = "remote_rules")]
+    let remote_tags = remote_rules.as_ref().map(RemoteRules::tags);
+    #[cfg(not(feature = "remote_rules"))]
+    let remote_tags: Option<middleware::reload::SharedTags> = None;
+    // The last tags read from the tags file, the cluster ConfigMap and the remote config, all
+    // sent as extra tags
+    let extra_tags: RefCell<(Vec<String>, Vec<String>, Vec<String>)> = RefCell::default();
+    let refresh_extra_tags = || {
+        let expand = |tags: Vec<String>| {
+            tags.iter()
+                .filter_map(|tag| config::tags::expand(tag, &hostname))
+                .collect::<Vec<_>>()
+        };
+        let file_tags = tags_file.borrow_mut().as_mut().and_then(|f| f.poll());
+        let cluster = cluster_tags.as_ref().and_then(|t| t.take()).map(expand);
+        let remote = remote_tags.as_ref().and_then(|t| t.take()).map(expand);
+        if file_tags.is_none() && cluster.is_none() && remote.is_none() {
+            return;
+        }
+        let mut extra_tags = extra_tags.borrow_mut();
+        if let Some(tags) = file_tags {
+            extra_tags.0 = tags;
+        }
+        if let Some(tags) = cluster {
+            extra_tags.1 = tags;
+        }
+        if let Some(tags) = remote {
+            extra_tags.2 = tags;
+        }
+        let (file_tags, cluster, remote) = &*extra_tags;
+        let mut tags = file_tags.clone();
+        for tag in cluster.iter().chain(remote.iter()) {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        client.borrow_mut().set_extra_tags(&tags);
+        routes.set_extra_tags(&tags);
+    };
+    refresh_extra_tags();
 
     let mut executor = Executor::new();
-    if config.log.use_k8s_enrichment == K8sTrackingConf::Always
-        && PathBuf::from("/var/log/containers/").exists()
+    if let Some(every) = config.log.trace_sample {
+        executor.trace(every);
+        info!("Tracing one in {} lines through the pipeline", every);
+    }
+    let k8s_enrichment = config.log.use_k8s_enrichment == K8sTrackingConf::Always
+        && (PathBuf::from("/var/log/containers/").exists() || config.kubelet.url.is_some());
+    // Lines are only tagged with the node and cluster names when running in a cluster
+    let k8s_identity =
+        std::env::var("KUBERNETES_SERVICE_HOST").is_ok() || config.log.k8s_cluster_name.is_some();
+    #[cfg(feature = "k8s_source")]
+    let mut pod_arrivals = None;
+    #[cfg(feature = "k8s_source")]
     {
-        match K8sMetadata::new() {
+        if k8s_enrichment {
+            match K8sMetadata::new(config.log.k8s_metadata_cache, config.log.k8s_metadata_wait) {
+                Ok(v) => {
+                    pod_arrivals = v.arrivals();
+                    executor.register(v);
+                    info!("Registered k8s metadata middleware");
+                }
+                Err(e) => warn!("{}", e),
+            };
+        }
+        if k8s_identity {
+            executor.register(K8sIdentity::new(config.log.k8s_cluster_name.clone()));
+            info!("Registered k8s node and cluster name middleware");
+        }
+    }
+    #[cfg(not(feature = "k8s_source"))]
+    if k8s_enrichment || k8s_identity {
+        warn!("running in Kubernetes, but the k8s_source feature is disabled");
+    }
+
+    #[cfg(feature = "cloud_metadata")]
+    if let Some(metadata) = cloud_metadata {
+        executor.register(cloud::middleware::CloudMetadata::new(
+            metadata,
+            config.cloud.metadata_fields,
+            config.cloud.metadata_timeout,
+        ));
+        info!("Registered cloud metadata middleware");
+    }
+
+    if !config.log.path_templates.is_empty() {
+        let templates = std::mem::take(&mut config.log.path_templates);
+        executor.register(middleware::path_template::PathTemplates::new(templates));
+        info!("Registered path templates middleware");
+    }
+
+    // After the k8s metadata so that the rules can select lines by the labels of their pod, and
+    // the path templates so that they take precedence over the fields taken from paths
+    if !config.log.field_overrides.is_empty() {
+        let rules = std::mem::take(&mut config.log.field_overrides);
+        executor.register(middleware::overrides::FieldOverrides::new(rules));
+        info!("Registered field overrides middleware");
+    }
+
+    // Ahead of the enforced rules so that shadow rules see the lines as they were read
+    if let Some(shadow) = config.log.shadow.take() {
+        match ShadowRules::new(
+            &shadow.line_exclusion_regex,
+            &shadow.line_inclusion_regex,
+            &shadow.line_redact_regex,
+            shadow.line_exclusion_expr,
+            shadow.line_inclusion_expr,
+        ) {
             Ok(v) => {
                 executor.register(v);
-                info!("Registered k8s metadata middleware");
+                info!("Registered shadow line rules middleware");
             }
-            Err(e) => warn!("{}", e),
-        };
+            Err(e) => {
+                error!("shadow line regex is invalid: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
 
     match LineRules::new(
@@ -126,15 +596,72 @@ fn main() {
         &config.log.line_inclusion_regex,
         &config.log.line_redact_regex,
     ) {
-        Ok(v) => executor.register(v),
+        Ok(v) => match config.log.redact_secrets {
+            Some(sensitivity) => executor.register(
+                v.with_secret_detector(middleware::secrets::SecretDetector::new(sensitivity)),
+            ),
+            None => executor.register(v),
+        },
         Err(e) => {
             error!("line regex is invalid: {}", e);
             std::process::exit(1);
         }
     };
 
+    if config.log.line_exclusion_expr.is_some() || config.log.line_inclusion_expr.is_some() {
+        executor.register(middleware::expr::ExprFilter::new(
+            config.log.line_exclusion_expr.take(),
+            config.log.line_inclusion_expr.take(),
+        ));
+        info!("Registered line expression middleware");
+    }
+
+    if config.log.anonymize_ips {
+        match IpAnonymizer::new(
+            config.log.anonymize_ipv4_prefix,
+            config.log.anonymize_ipv6_prefix,
+        ) {
+            Ok(v) => {
+                executor.register(v);
+                info!("Registered IP address anonymization middleware");
+            }
+            Err(e) => {
+                error!("IP address anonymization is misconfigured: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "k8s_source")]
+    if let Some(v) = cluster_rules {
+        executor.register(v);
+        info!("Registered cluster ConfigMap rules middleware");
+    }
+
+    #[cfg(feature = "remote_rules")]
+    if let Some(v) = remote_rules {
+        executor.register(v);
+        info!("Registered remote config rules middleware");
+    }
+
+    if let Some(path) = config.log.k8s_audit_path.clone() {
+        executor.register(middleware::k8s_audit::K8sAuditParser::new(path));
+        info!("Registered k8s audit log middleware");
+    }
+
     executor.init();
 
+    #[cfg(feature = "auditd_source")]
+    let auditd_source =
+        auditd::source::create_source(&config.auditd.paths, initial_offsets.as_ref());
+    #[cfg(not(feature = "auditd_source"))]
+    let auditd_source = {
+        if !config.auditd.paths.is_empty() {
+            warn!("auditd paths are configured, but the auditd_source feature is disabled");
+        }
+        futures::stream::empty()
+    };
+
     let mut fs_tailer_buf = [0u8; 4096];
     let mut fs_source = FSSource::new(
         config.log.dirs,
@@ -142,26 +669,144 @@ fn main() {
         config.log.lookback,
         initial_offsets,
     );
+    fs_source.set_event_coalesce_window(config.log.event_coalesce_window);
+    fs_source.set_priority_rules(config.log.priority_rules);
+    fs_source.set_recreated_files(config.log.recreated_files);
+    if let Some(bytes) = config.log.mmap_threshold {
+        warn!(
+            "mapping backlogs of {} bytes or more into memory, a file truncated while it's read \
+             this way crashes the agent",
+            bytes
+        );
+    }
+    fs_source.set_mmap_threshold(config.log.mmap_threshold);
+    fs::throttle::set_read_limit(config.log.read_limit_bytes_per_sec);
+    fs::lag::set_lag_threshold(config.log.lag_threshold);
 
-    let journald_source = create_source(&config.journald.paths);
+    #[cfg(feature = "journald_source")]
+    let journald_source = {
+        let options = ReadOptions {
+            max_lag: config.journald.max_lag,
+            unit_limits: UnitLimits {
+                lines_per_sec: config.journald.unit_lines_per_sec,
+                bytes_per_sec: config.journald.unit_bytes_per_sec,
+            },
+            field_limits: config.journald.field_limits,
+            ids: config.journald.ids,
+            backfill: first_journald_backfill(config.journald.backfill, timestamp_state.as_ref()),
+        };
+        create_source(&config.journald.paths, options)
+    };
+    #[cfg(not(feature = "journald_source"))]
+    let journald_source = {
+        if !config.journald.paths.is_empty() {
+            warn!("journald paths are configured, but the journald_source feature is disabled");
+        }
+        futures::stream::empty()
+    };
 
     // Create the runtime
-    let rt = Runtime::new().unwrap();
+    let mut rt_builder = Builder::new_multi_thread();
+    if let Some(threads) = config.profile.worker_threads() {
+        rt_builder.worker_threads(threads);
+    }
+    let rt = rt_builder.enable_all().build().unwrap();
 
     if let Some(offset_state) = offset_state {
         rt.spawn(offset_state.run().unwrap());
     }
 
     let log_k8s_events = config.log.log_k8s_events.clone();
-    // Execute the future, blocking the current thread until completion
-    rt.block_on(async move {
+    #[cfg(feature = "k8s_source")]
+    let k8s_event_dedup_window = config.log.k8s_event_dedup_window;
+    let docker_config = config.docker;
+    let kubelet_config = config.kubelet;
+    let receiver_config = config.receiver;
+    #[cfg(feature = "exec_source")]
+    let exec_commands = config.exec.commands;
+    let kafka_config = config.kafka;
+    let batch_group_by_source = config.http.batch_group_by_source;
+    // Nothing leaves the agent during a dry run
+    #[cfg(feature = "archive_sink")]
+    let archive_options = config.archive.filter(|_| !dry_run);
+    // The archive is keyed by the hostname lines are sent with, instance names included
+    #[cfg(feature = "archive_sink")]
+    let archive_host = hostname.clone();
+    #[cfg(feature = "elasticsearch_sink")]
+    let elasticsearch_options = config.elasticsearch.filter(|_| !dry_run);
+    #[cfg(feature = "otlp_sink")]
+    let otlp_options = config.otlp.filter(|_| !dry_run);
+    #[cfg(feature = "webhook_sink")]
+    let webhook_options = config.webhook.filter(|_| !dry_run);
+    #[cfg(feature = "syslog_sink")]
+    let syslog_options = config.syslog.filter(|_| !dry_run);
+    #[cfg(feature = "unix_socket_sink")]
+    let unix_socket_options = config.unix_socket.filter(|_| !dry_run);
+    let readiness_file = config.http.readiness_file.clone();
+    let ingestion_enabled = config.http.enabled;
+    if !ingestion_enabled {
+        info!("Sending lines to LogDNA is disabled");
+    } else if config.http.validate && !dry_run {
+        rt.spawn(validate_ingestion(
+            validation_template,
+            config.http.timeout,
+            readiness_file.clone(),
+        ));
+    }
+
+    let mut watchdog = watchdog::Watchdog::new(config.http.stall_threshold);
+    let event_loop_stage = watchdog.continuous("event_loop", Duration::from_millis(POLL_PERIOD_MS));
+    let ingest_stage = watchdog.on_demand("ingest");
+    watchdog.spawn();
+
+    // Borrowed by the main loop rather than moved into it, the tags refresh borrows them too
+    let (client, routes) = (&client, &routes);
+    // Execute the future, blocking the current thread until completion. The routes run on the
+    // same thread, each progressing while the others wait on their requests
+    local.block_on(&rt, async move {
         let fs_source = fs_source
             .process(&mut fs_tailer_buf)
             .expect("except Failed to create FS Tailer")
             .map(StrictOrLazyLineBuilder::Lazy);
 
-        let journald_source = journald_source.map(StrictOrLazyLineBuilder::Strict);
+        let journald_source = journald_source.map(StrictOrLazyLineBuilder::strict);
+        let auditd_source = auditd_source.map(StrictOrLazyLineBuilder::checkpointed);
 
+        #[cfg(feature = "k8s_source")]
+        let kubelet_state = timestamp_state.clone();
+        #[cfg(feature = "docker_source")]
+        let docker_source = {
+            let label_filters = docker_config.label_filters;
+            docker_config.socket.map(|socket| {
+                docker::source::create_source(socket, label_filters, timestamp_state)
+                    .map(|(line, checkpoint)| {
+                        StrictOrLazyLineBuilder::Strict(line, checkpoint, None)
+                    })
+            })
+        };
+        #[cfg(not(feature = "docker_source"))]
+        let docker_source: Option<futures::stream::Empty<StrictOrLazyLineBuilder>> = {
+            if docker_config.socket.is_some() {
+                warn!("a docker socket is configured, but the docker_source feature is disabled");
+            }
+            None
+        };
+        #[cfg(feature = "k8s_source")]
+        let kubelet_source = kubelet_config.url.and_then(|url| {
+            k8s::kubelet_source::create_source(&url, kubelet_config.insecure_tls, kubelet_state)
+                .map_err(|e| warn!("unable to follow container logs through the kubelet: {}", e))
+                .ok()
+                .map(|s| s.map(StrictOrLazyLineBuilder::strict))
+        });
+        #[cfg(not(feature = "k8s_source"))]
+        let kubelet_source: Option<futures::stream::Empty<StrictOrLazyLineBuilder>> = {
+            if kubelet_config.url.is_some() {
+                warn!("a kubelet url is configured, but the k8s_source feature is disabled");
+            }
+            None
+        };
+
+        #[cfg(feature = "k8s_source")]
         let k8s_event_stream = match log_k8s_events {
             K8sTrackingConf::Never => None,
             K8sTrackingConf::Always => {
@@ -171,6 +816,7 @@ fn main() {
                 match (pod_name, namespace, pod_label) {
                     (Some(pod_name), Some(namespace), Some(pod_label)) => {
                         K8sEventStream::try_default(pod_name, namespace, pod_label)
+                            .map(|stream| stream.with_dedup_window(k8s_event_dedup_window))
                             .map_err(|e|warn!("Error initialising Kubernetes event logging: {}", e)).ok()
                     },
                     (pn, n, pl) => {
@@ -190,23 +836,107 @@ fn main() {
             },
         };
 
+        #[cfg(feature = "k8s_source")]
         let k8s_event_source: Option<_> = if let Some(fut) = k8s_event_stream
             .map(|e| e.event_stream())
         {
             Some(
                 fut.await
                     .expect("Failed to create stream")
-                    .map(StrictOrLazyLineBuilder::Strict),
+                    .map(StrictOrLazyLineBuilder::strict),
             )
         } else {
             None
         };
+        #[cfg(not(feature = "k8s_source"))]
+        let k8s_event_source: Option<futures::stream::Empty<StrictOrLazyLineBuilder>> = {
+            if log_k8s_events == K8sTrackingConf::Always {
+                warn!("k8s event logging is configured, but the k8s_source feature is disabled");
+            }
+            None
+        };
 
         pin_mut!(fs_source);
         pin_mut!(journald_source);
+        pin_mut!(auditd_source);
+        let (ingest_key, ingest_tls) = (receiver_config.ingest_key, receiver_config.ingest_tls);
+        let ingest_source = receiver_config.ingest_address.map(|address| {
+            receiver::ingest::create_source(address, ingest_key, ingest_tls)
+                .map(StrictOrLazyLineBuilder::timed)
+        });
+        #[cfg(feature = "status_endpoint")]
+        if let Some(address) = receiver_config.status_address {
+            receiver::status::spawn(address);
+        }
+        #[cfg(not(feature = "status_endpoint"))]
+        if receiver_config.status_address.is_some() {
+            warn!("a status address is configured, but the status_endpoint feature is disabled");
+        }
+        let journal_remote_tls = receiver_config.journal_remote_tls;
+        let journal_remote_source = receiver_config.journal_remote_address.map(|address| {
+            receiver::journal_remote::create_source(address, journal_remote_tls)
+                .map(StrictOrLazyLineBuilder::strict)
+        });
+        let k8s_audit_tls = receiver_config.k8s_audit_tls;
+        let k8s_audit_source = receiver_config.k8s_audit_address.map(|address| {
+            receiver::k8s_audit::create_source(address, k8s_audit_tls)
+                .map(StrictOrLazyLineBuilder::strict)
+        });
+
+        #[cfg(feature = "exec_source")]
+        let exec_source = if exec_commands.is_empty() {
+            None
+        } else {
+            Some(exec::source::create_source(exec_commands).map(StrictOrLazyLineBuilder::strict))
+        };
+        #[cfg(not(feature = "exec_source"))]
+        let exec_source: Option<futures::stream::Empty<StrictOrLazyLineBuilder>> = None;
+        let self_telemetry_source = telemetry.map(|rx| {
+            self_telemetry::create_source(rx).map(StrictOrLazyLineBuilder::strict)
+        });
+
+        #[cfg(feature = "kafka_source")]
+        let kafka_source = if kafka_config.topics.is_empty() {
+            None
+        } else {
+            kafka::source::create_source(
+                &kafka_config.brokers,
+                &kafka_config.topics,
+                &kafka_config.group_id,
+            )
+            .map_err(|e| warn!("unable to create kafka consumer: {}", e))
+            .ok()
+            .map(|s| s.map(StrictOrLazyLineBuilder::strict))
+        };
+        #[cfg(not(feature = "kafka_source"))]
+        let kafka_source: Option<futures::stream::Empty<StrictOrLazyLineBuilder>> = {
+            if !kafka_config.topics.is_empty() {
+                warn!("kafka topics are configured, but the kafka_source feature is disabled");
+            }
+            None
+        };
+
         pin_mut!(k8s_event_source);
+        pin_mut!(docker_source);
+        pin_mut!(kubelet_source);
+        pin_mut!(ingest_source);
+        pin_mut!(journal_remote_source);
+        pin_mut!(k8s_audit_source);
+        pin_mut!(exec_source);
+        pin_mut!(self_telemetry_source);
+        pin_mut!(kafka_source);
 
         let mut k8s_event_source: Option<std::pin::Pin<&mut _>> = k8s_event_source.as_pin_mut();
+        let mut docker_source: Option<std::pin::Pin<&mut _>> = docker_source.as_pin_mut();
+        let mut kubelet_source: Option<std::pin::Pin<&mut _>> = kubelet_source.as_pin_mut();
+        let mut ingest_source: Option<std::pin::Pin<&mut _>> = ingest_source.as_pin_mut();
+        let mut journal_remote_source: Option<std::pin::Pin<&mut _>> =
+            journal_remote_source.as_pin_mut();
+        let mut k8s_audit_source: Option<std::pin::Pin<&mut _>> = k8s_audit_source.as_pin_mut();
+        let mut exec_source: Option<std::pin::Pin<&mut _>> = exec_source.as_pin_mut();
+        let mut self_telemetry_source: Option<std::pin::Pin<&mut _>> =
+            self_telemetry_source.as_pin_mut();
+        let mut kafka_source: Option<std::pin::Pin<&mut _>> = kafka_source.as_pin_mut();
 
         let mut sources: futures::stream::SelectAll<&mut (dyn Stream<Item = _> + Unpin)> =
             futures::stream::SelectAll::new();
@@ -214,12 +944,111 @@ fn main() {
         info!("Enabling filesystem");
         sources.push(&mut fs_source);
         sources.push(&mut journald_source);
+        sources.push(&mut auditd_source);
 
         if let Some(k) = k8s_event_source.as_mut() {
             info!("Enabling k8s_event_source");
             sources.push(k)
         };
 
+        if let Some(d) = docker_source.as_mut() {
+            info!("Enabling docker_source");
+            sources.push(d)
+        };
+
+        if let Some(k) = kubelet_source.as_mut() {
+            info!("Enabling kubelet_source");
+            sources.push(k)
+        };
+
+        if let Some(i) = ingest_source.as_mut() {
+            info!("Enabling ingest_source");
+            sources.push(i)
+        };
+
+        if let Some(j) = journal_remote_source.as_mut() {
+            info!("Enabling journal_remote_source");
+            sources.push(j)
+        };
+
+        if let Some(a) = k8s_audit_source.as_mut() {
+            info!("Enabling k8s_audit_source");
+            sources.push(a)
+        };
+
+        if let Some(e) = exec_source.as_mut() {
+            info!("Enabling exec_source");
+            sources.push(e)
+        };
+
+        if let Some(s) = self_telemetry_source.as_mut() {
+            info!("Enabling self_telemetry_source");
+            sources.push(s)
+        };
+
+        if let Some(k) = kafka_source.as_mut() {
+            info!("Enabling kafka_source");
+            sources.push(k)
+        };
+
+        // Sinks that get a copy of every line on top of LogDNA, each drains its own queue
+        // so a slow one only drops its own lines instead of delaying the others
+        let mut sinks: Vec<sink::Queue> = Vec::new();
+        #[cfg(feature = "archive_sink")]
+        if let Some(options) = archive_options {
+            match archive::s3::spawn(options, archive_host, codec_pool) {
+                Ok(archive) => sinks.push(archive),
+                Err(e) => error!("unable to start s3 archive: {}", e),
+            }
+        }
+        #[cfg(feature = "elasticsearch_sink")]
+        if let Some(options) = elasticsearch_options {
+            match elasticsearch::sink::spawn(options) {
+                Ok(es) => sinks.push(es),
+                Err(e) => error!("unable to start elasticsearch sink: {}", e),
+            }
+        }
+        #[cfg(feature = "otlp_sink")]
+        if let Some(options) = otlp_options {
+            match otlp::sink::spawn(options) {
+                Ok(otlp) => sinks.push(otlp),
+                Err(e) => error!("unable to start otlp exporter: {}", e),
+            }
+        }
+        #[cfg(feature = "webhook_sink")]
+        if let Some(options) = webhook_options {
+            match webhook::sink::spawn(options) {
+                Ok(webhook) => sinks.push(webhook),
+                Err(e) => error!("unable to start webhook sink: {}", e),
+            }
+        }
+        #[cfg(feature = "syslog_sink")]
+        if let Some(options) = syslog_options {
+            match syslog::sink::spawn(options) {
+                Ok(syslog) => sinks.push(syslog),
+                Err(e) => error!("unable to start syslog sink: {}", e),
+            }
+        }
+        #[cfg(feature = "unix_socket_sink")]
+        if let Some(options) = unix_socket_options {
+            sinks.push(unix_socket::sink::spawn(options));
+        }
+
+        let summary = RefCell::new(dry_run::Summary::new());
+        let duplicate_filter = RefCell::new(duplicate_filter.filter(|f| !f.is_empty()));
+        if let Some(filter) = duplicate_filter.borrow().as_ref() {
+            info!("skipping {} lines shipped before the restart if read again", filter.len());
+        }
+        let source_groups = if batch_group_by_source {
+            info!("Grouping the lines of each batch by source");
+            Some(RefCell::new(SourceGroups::default()))
+        } else {
+            None
+        };
+        // Lines of new containers wait for the metadata of their pod there, rather than in the
+        // middleware, so that the other lines keep flowing meanwhile
+        #[cfg(feature = "k8s_source")]
+        let sources = hold_pending_lines(sources, pod_arrivals, StrictOrLazyLineBuilder::file);
         let sources = sources.map(Either::Left);
 
         let sources = futures::stream::select(
@@ -231,17 +1060,53 @@ fn main() {
         );
 
         sources
+            .take_until(shutdown_requested())
             .for_each(|line| async {
                 match line {
                     Either::Left(line) => match line {
-                        StrictOrLazyLineBuilder::Strict(mut line) => {
+                        StrictOrLazyLineBuilder::Strict(mut line, checkpoint, timestamp) => {
                             if executor.process(&mut line).is_some() {
+                                let route = routes.find(&mut line).filter(|_| ingestion_enabled);
                                 match line.build() {
-                                    Ok(line) => {
-                                        client
-                                            .borrow_mut()
-                                            .send(StrictOrLazyLines::Strict(&line))
-                                            .await
+                                    Ok(mut line) => {
+                                        if let Some(timestamp) = timestamp {
+                                            line.timestamp = timestamp;
+                                        }
+                                        let source = line.get_file().or_else(|| line.get_app());
+                                        Metrics::sources().record(
+                                            source.unwrap_or("unknown"),
+                                            line.line.len() as u64,
+                                        );
+                                        if dry_run {
+                                            summary.borrow_mut().record(source, line.line.len());
+                                        }
+                                        trace_destinations(&sinks, ingestion_enabled);
+                                        for sink in sinks.iter() {
+                                            sink.send(line.clone());
+                                        }
+                                        if let Some(route) = route {
+                                            route.send(line, checkpoint);
+                                        } else if ingestion_enabled {
+                                            ingest_stage.waiting();
+                                            match source_groups.as_ref() {
+                                                Some(groups) => {
+                                                    let lines = groups
+                                                        .borrow_mut()
+                                                        .hold(HeldLine::Strict(line, checkpoint));
+                                                    grouping::send(&client, lines).await;
+                                                }
+                                                None => {
+                                                    client
+                                                        .borrow_mut()
+                                                        .send(StrictOrLazyLines::Strict(
+                                                            &line,
+                                                            checkpoint.as_ref(),
+                                                        ))
+                                                        .await
+                                                }
+                                            }
+                                            ingest_stage.progress();
+                                        }
                                     }
                                     Err(e) => {
                                         error!("Couldn't build line from linebuilder {:?}", e)
@@ -250,17 +1115,68 @@ fn main() {
                             }
                         }
                         StrictOrLazyLineBuilder::Lazy(mut line) => {
-                            if executor.process(&mut line).is_some() {
-                                client
-                                    .borrow_mut()
-                                    .send(StrictOrLazyLines::Lazy(line))
-                                    .await
+                            if executor.process(&mut line).is_some()
+                                && !is_restart_duplicate(&duplicate_filter, &mut line)
+                            {
+                                let source = line.get_file().or_else(|| line.get_app());
+                                let bytes = line.get_line_buffer().map_or(0, |b| b.len());
+                                Metrics::sources()
+                                    .record(source.unwrap_or("unknown"), bytes as u64);
+                                if dry_run {
+                                    summary.borrow_mut().record(source, bytes);
+                                }
+                                trace_destinations(&sinks, ingestion_enabled);
+                                if !sinks.is_empty() {
+                                    if let Some(owned) = to_owned_line(&mut line) {
+                                        for sink in sinks.iter() {
+                                            sink.send(owned.clone());
+                                        }
+                                    }
+                                }
+                                let route = routes.find(&mut line).filter(|_| ingestion_enabled);
+                                if let Some(route) = route {
+                                    route.send_lazy(&mut line);
+                                } else if ingestion_enabled {
+                                    ingest_stage.waiting();
+                                    match source_groups.as_ref() {
+                                        Some(groups) => {
+                                            let lines =
+                                                groups.borrow_mut().hold(HeldLine::Lazy(line));
+                                            grouping::send(&client, lines).await;
+                                        }
+                                        None => {
+                                            client
+                                                .borrow_mut()
+                                                .send(StrictOrLazyLines::Lazy(line))
+                                                .await
+                                        }
+                                    }
+                                    ingest_stage.progress();
+                                }
                             }
                         }
                     },
-                    Either::Right(_) => client.borrow_mut().poll().await,
+                    Either::Right(_) => {
+                        event_loop_stage.progress();
+                        if dry_run {
+                            summary.borrow_mut().report_if_due();
+                        }
+                        refresh_extra_tags();
+                        fs::lag::check();
+                        ingest_stage.waiting();
+                        if let Some(groups) = source_groups.as_ref() {
+                            let lines = groups.borrow_mut().take();
+                            grouping::send(&client, lines).await;
+                        }
+                        client.borrow_mut().poll().await;
+                        ingest_stage.progress();
+                    }
                 }
             })
             .await
     });
+
+    if let Some(path) = readiness_file.as_ref() {
+        remove_readiness_file(path);
+    }
 }
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use fs::cache::tailed_file::LazyLineSerializer;
+use http::client::Client;
+use http::types::body::{Line, LineBufferMut};
+use metrics::Metrics;
+use middleware::routes::KeyRoute;
+use state::{Checkpoint, GetOffset};
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::LocalSet;
+
+use crate::stream_adapter::{to_owned_line, StrictOrLazyLines};
+
+/// Lines queued for a route whose client is busy, more are dropped
+const QUEUE_SIZE: usize = 10_000;
+
+/// The ingestion key routes, each sending the lines it matches through a pipeline of its own: a
+/// client with its own batch, rate limiter, retry spool and metrics, fed through a queue and
+/// driven by a task of its own. A route being rate limited or unreachable only fills up its
+/// own queue and spool, the lines of the other keys keep flowing.
+pub(crate) struct Routes {
+    routes: Vec<Route>,
+    tags: watch::Sender<Vec<String>>,
+}
+
+pub(crate) struct Route {
+    route: KeyRoute,
+    tx: Sender<(Line, Option<Checkpoint>)>,
+}
+
+impl Routes {
+    /// Spawns the task of each route on `local`, `client` builds the client of a route from
+    /// its key
+    pub(crate) fn spawn(
+        local: &LocalSet,
+        routes: Vec<KeyRoute>,
+        client: impl Fn(&KeyRoute) -> Client,
+    ) -> Self {
+        let (tags, tags_rx) = watch::channel(Vec::new());
+        let routes = routes
+            .into_iter()
+            .map(|route| {
+                let (tx, rx) = channel(QUEUE_SIZE);
+                let mut client = client(&route);
+                client.set_route(&route.name);
+                info!(
+                    "sending the lines of route {} with its own ingestion key",
+                    route.name
+                );
+                local.spawn_local(run(client, rx, tags_rx.clone()));
+                Route { route, tx }
+            })
+            .collect();
+        Routes { routes, tags }
+    }
+
+    /// Sends the extra tags along with the lines of every route
+    pub(crate) fn set_extra_tags(&self, tags: &[String]) {
+        if !self.routes.is_empty() {
+            let _ = self.tags.send(tags.to_vec());
+        }
+    }
+
+    /// The first route matching `line`, its lines aren't sent with the default key
+    pub(crate) fn find(&self, line: &mut dyn LineBufferMut) -> Option<&Route> {
+        self.routes.iter().find(|route| route.route.matches(line))
+    }
+}
+
+impl Route {
+    /// Queues `line`, dropping and counting it when the queue is full rather than holding up
+    /// the other routes
+    pub(crate) fn send(&self, line: Line, checkpoint: Option<Checkpoint>) {
+        let name = &self.route.name;
+        match self.tx.try_send((line, checkpoint)) {
+            Ok(()) => Metrics::routes().increment_lines(name),
+            Err(TrySendError::Full(_)) => Metrics::routes().increment_dropped(name),
+            Err(TrySendError::Closed(_)) => warn!("route {} is no longer running", name),
+        }
+    }
+
+    /// Queues a copy of the lazy `line`, along with its offset
+    pub(crate) fn send_lazy(&self, line: &mut LazyLineSerializer) {
+        let checkpoint = match (line.get_key(), line.get_offset()) {
+            (Some(key), Some(offset)) => Some(Checkpoint::Offset { key, offset }),
+            _ => None,
+        };
+        match to_owned_line(line) {
+            Some(owned) => self.send(owned, checkpoint),
+            None => Metrics::routes().increment_dropped(&self.route.name),
+        }
+    }
+}
+
+/// Sends the lines queued for a route, polling its client between them as the main loop does
+async fn run(
+    mut client: Client,
+    mut lines: Receiver<(Line, Option<Checkpoint>)>,
+    mut tags: watch::Receiver<Vec<String>>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_millis(crate::POLL_PERIOD_MS));
+    loop {
+        tokio::select! {
+            line = lines.recv() => match line {
+                Some((line, checkpoint)) => {
+                    client
+                        .send(StrictOrLazyLines::Strict(&line, checkpoint.as_ref()))
+                        .await
+                }
+                None => break,
+            },
+            Ok(()) = tags.changed() => {
+                let extra = tags.borrow().clone();
+                client.set_extra_tags(&extra);
+            }
+            _ = interval.tick() => client.poll().await,
+        }
+    }
+}
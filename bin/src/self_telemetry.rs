@@ -0,0 +1,274 @@
+use env_logger::Builder;
+use futures::Stream;
+use http::types::body::LineBuilder;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use std::cmp;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The app of the lines the agent ships about itself
+const APP: &str = "logdna-agent";
+
+/// Records held until they are shipped, the ones over it are dropped so that an ingest outage
+/// can't pile up the warnings about its own failures
+const CAPACITY: usize = 1024;
+
+/// Target of the periodic summary of the metrics
+const METRICS_TARGET: &str = "metrics";
+
+/// Records of the http crate shipped per minute at most. It logs the failures to reach the
+/// ingest API, which would otherwise be shipped through the very API they are about
+const HTTP_RECORDS_PER_MINUTE: u32 = 10;
+const MINUTE: Duration = Duration::from_secs(60);
+
+// Until the config is read the warnings are held, in case LOGDNA_SELF_TELEMETRY is on
+const PENDING: u8 = 0;
+const ON: u8 = 1;
+const OFF: u8 = 2;
+
+/// The records of the http crate shipped in the current minute
+struct RateLimit {
+    window: Instant,
+    count: u32,
+}
+
+impl RateLimit {
+    fn allow(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window) >= MINUTE {
+            self.window = now;
+            self.count = 0;
+        }
+        if self.count < HTTP_RECORDS_PER_MINUTE {
+            self.count += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn is_http(target: &str) -> bool {
+    target == "http" || target.starts_with("http::")
+}
+
+/// Prints the records with env_logger and, once self telemetry is on, forwards the warnings,
+/// the errors and the metrics summaries to the self telemetry source. Once it's off the
+/// records are only printed, at the level the user picked
+struct Logger {
+    inner: env_logger::Logger,
+    tx: Sender<LineBuilder>,
+    state: Arc<AtomicU8>,
+    http_records: Mutex<RateLimit>,
+}
+
+impl Logger {
+    fn forwards(&self, metadata: &Metadata) -> bool {
+        match self.state.load(Ordering::Relaxed) {
+            PENDING => metadata.level() <= Level::Warn,
+            ON => {
+                metadata.level() <= Level::Warn
+                    || (metadata.level() == Level::Info && metadata.target() == METRICS_TARGET)
+            }
+            _ => false,
+        }
+    }
+
+    fn within_limit(&self, metadata: &Metadata) -> bool {
+        !is_http(metadata.target())
+            || self
+                .http_records
+                .lock()
+                .expect("Couldn't lock the rate limit")
+                .allow(Instant::now())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || self.forwards(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+        if self.forwards(record.metadata())
+            && !self.tx.is_closed()
+            && self.within_limit(record.metadata())
+        {
+            let line = LineBuilder::new()
+                .line(record.args().to_string())
+                .app(APP)
+                .level(record.level().as_str());
+            // Never waits, logging must not block the threads of the pipeline
+            let _ = self.tx.try_send(line);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Turns the shipping of the records on or off once the config is read
+pub struct Telemetry {
+    rx: Receiver<LineBuilder>,
+    state: Arc<AtomicU8>,
+    level: LevelFilter,
+}
+
+impl Telemetry {
+    /// Ships the records from now on, along with the warnings held since the agent started
+    pub fn enable(self) -> Receiver<LineBuilder> {
+        self.state.store(ON, Ordering::Relaxed);
+        // The metrics summary is shipped even when only warnings are printed
+        log::set_max_level(cmp::max(self.level, LevelFilter::Info));
+        self.rx
+    }
+
+    /// Drops the warnings held since the agent started, the records are only printed
+    pub fn disable(self) {
+        self.state.store(OFF, Ordering::Relaxed);
+    }
+}
+
+fn logger(mut builder: Builder) -> (Logger, Telemetry) {
+    let (tx, rx) = channel(CAPACITY);
+    let inner = builder.build();
+    let state = Arc::new(AtomicU8::new(PENDING));
+    let telemetry = Telemetry {
+        rx,
+        state: state.clone(),
+        level: inner.filter(),
+    };
+    let http_records = Mutex::new(RateLimit {
+        window: Instant::now(),
+        count: 0,
+    });
+    let logger = Logger {
+        inner,
+        tx,
+        state,
+        http_records,
+    };
+    (logger, telemetry)
+}
+
+/// Installs the logger of the agent. The warnings are held from the start, so that the ones
+/// logged while starting up are shipped too, until the returned [`Telemetry`] is enabled or
+/// disabled.
+pub fn init(builder: Builder) -> Telemetry {
+    let (logger, telemetry) = logger(builder);
+    log::set_max_level(telemetry.level);
+    log::set_boxed_logger(Box::new(logger)).expect("unable to set the logger");
+    telemetry
+}
+
+/// Streams the lines of the `logdna-agent` app
+pub fn create_source(rx: Receiver<LineBuilder>) -> impl Stream<Item = LineBuilder> {
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_logger(filter: &str) -> (Logger, Telemetry) {
+        let mut builder = Builder::new();
+        builder.parse_filters(filter);
+        logger(builder)
+    }
+
+    fn metadata(level: Level, target: &str) -> Metadata<'_> {
+        Metadata::builder().level(level).target(target).build()
+    }
+
+    fn log(logger: &Logger, level: Level, target: &str) {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("a record"))
+                .build(),
+        );
+    }
+
+    fn received(rx: &mut Receiver<LineBuilder>) -> usize {
+        let mut count = 0;
+        while rx.try_recv().is_ok() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn holds_the_warnings_until_enabled() {
+        let (logger, telemetry) = test_logger("error");
+        log(&logger, Level::Warn, "fs::tail");
+        log(&logger, Level::Info, METRICS_TARGET);
+        let mut rx = telemetry.enable();
+        assert_eq!(received(&mut rx), 1);
+
+        log(&logger, Level::Info, METRICS_TARGET);
+        log(&logger, Level::Info, "fs::tail");
+        log(&logger, Level::Debug, METRICS_TARGET);
+        assert_eq!(received(&mut rx), 1);
+    }
+
+    #[test]
+    fn only_prints_at_the_users_level_when_disabled() {
+        let (logger, telemetry) = test_logger("warn");
+        let state = telemetry.state.clone();
+        telemetry.disable();
+        assert_eq!(state.load(Ordering::Relaxed), OFF);
+        assert!(logger.enabled(&metadata(Level::Warn, "fs::tail")));
+        assert!(!logger.enabled(&metadata(Level::Info, METRICS_TARGET)));
+        assert!(!logger.forwards(&metadata(Level::Error, "fs::tail")));
+        // The receiver is gone, nothing is queued
+        log(&logger, Level::Error, "fs::tail");
+    }
+
+    #[test]
+    fn keeps_the_users_level_for_printing_when_enabled() {
+        let (logger, telemetry) = test_logger("warn");
+        assert_eq!(telemetry.level, LevelFilter::Warn);
+        let _rx = telemetry.enable();
+        let summary = metadata(Level::Info, METRICS_TARGET);
+        assert!(logger.enabled(&summary));
+        assert!(!logger.inner.enabled(&summary));
+        assert!(!logger.enabled(&metadata(Level::Info, "fs::tail")));
+    }
+
+    #[test]
+    fn rate_limits_the_records_of_the_http_crate() {
+        let (logger, telemetry) = test_logger("off");
+        let mut rx = telemetry.enable();
+        for _ in 0..HTTP_RECORDS_PER_MINUTE * 2 {
+            log(&logger, Level::Warn, "http::client");
+            log(&logger, Level::Error, "fs::tail");
+        }
+        assert_eq!(received(&mut rx), (HTTP_RECORDS_PER_MINUTE * 3) as usize);
+
+        let mut limit = RateLimit {
+            window: Instant::now(),
+            count: HTTP_RECORDS_PER_MINUTE,
+        };
+        let now = limit.window;
+        assert!(!limit.allow(now));
+        assert!(limit.allow(now + MINUTE));
+    }
+
+    #[test]
+    fn tells_the_records_of_the_http_crate() {
+        assert!(is_http("http"));
+        assert!(is_http("http::client"));
+        assert!(is_http("http::retry"));
+        assert!(!is_http("https_proxy"));
+        assert!(!is_http("hyper::client"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use clap::ArgMatches;
+use state::{AgentState, StateSnapshot};
+
+const DEFAULT_DB_PATH: &str = "/var/lib/logdna";
+
+enum Command {
+    /// Writes the state to the file, or to stdout
+    Export(Option<PathBuf>),
+    /// Reads the state from the file, or from stdin for `-`
+    Import(PathBuf),
+}
+
+/// `logdna-agent state export|import`, dumps the offsets and timestamps the agent keeps of
+/// what it already shipped as JSON and restores them, e.g. when moving the agent to another
+/// node along with the volume of its logs. The db is the one in `--db-path` or
+/// `LOGDNA_DB_PATH`, and must not be in use by a running agent.
+pub struct Options {
+    command: Command,
+    db_path: PathBuf,
+}
+
+impl Options {
+    /// From the matches of the `state` subcommand, once `--db-path` was applied to the env
+    pub fn from_matches(matches: &ArgMatches) -> anyhow::Result<Options> {
+        let command = match matches.subcommand() {
+            Some(("export", export)) => Command::Export(export.value_of("file").map(Into::into)),
+            Some(("import", import)) => {
+                Command::Import(import.value_of("file").expect("required file").into())
+            }
+            _ => return Err(anyhow!("state requires the export or import subcommand")),
+        };
+        let db_path = std::env::var("LOGDNA_DB_PATH")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        Ok(Options {
+            command,
+            db_path: db_path.unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH)),
+        })
+    }
+}
+
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let state = AgentState::open_existing(&options.db_path).with_context(|| {
+        format!(
+            "unable to open the state db in {:?}, it can't be used by a running agent",
+            options.db_path
+        )
+    })?;
+
+    match options.command {
+        Command::Export(file) => {
+            let snapshot = state.export()?;
+            let output: Box<dyn Write> = match file.as_ref() {
+                Some(path) => Box::new(
+                    File::create(path).with_context(|| format!("unable to create {:?}", path))?,
+                ),
+                None => Box::new(io::stdout()),
+            };
+            let mut output = BufWriter::new(output);
+            serde_json::to_writer_pretty(&mut output, &snapshot)?;
+            writeln!(output)?;
+            output.flush()?;
+            info!(
+                "exported {} file offsets and {} source timestamps",
+                snapshot.offsets.len(),
+                snapshot.timestamps.len()
+            );
+        }
+        Command::Import(path) => {
+            let input: Box<dyn Read> = if path.as_os_str() == "-" {
+                Box::new(io::stdin())
+            } else {
+                Box::new(File::open(&path).with_context(|| format!("unable to open {:?}", path))?)
+            };
+            let snapshot: StateSnapshot = serde_json::from_reader(BufReader::new(input))
+                .context("unable to parse the exported state")?;
+            state.import(&snapshot)?;
+            info!(
+                "imported {} file offsets and {} source timestamps",
+                snapshot.offsets.len(),
+                snapshot.timestamps.len()
+            );
+        }
+    }
+    Ok(())
+}
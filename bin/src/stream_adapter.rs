@@ -1,22 +1,75 @@
 use async_trait::async_trait;
 
 use fs::cache::tailed_file::LazyLineSerializer;
-use http::types::body::{Line, LineBuilder, LineMeta};
+use http::types::body::{Line, LineBufferMut, LineBuilder, LineMeta, LineMetaMut};
 use http::types::serialize::{
     IngestLineSerialize, IngestLineSerializeError, SerializeI64, SerializeMap, SerializeStr,
     SerializeUtf8, SerializeValue,
 };
-use state::GetOffset;
+use state::{Checkpoint, GetOffset};
 use std::collections::HashMap;
 
 pub(crate) enum StrictOrLazyLineBuilder {
-    Strict(LineBuilder),
+    /// A line along with where it leaves its source, for the sources that resume from it, and
+    /// when it was read in milliseconds since the epoch, for the lines not read right now
+    Strict(LineBuilder, Option<Checkpoint>, Option<i64>),
     Lazy(LazyLineSerializer),
 }
 
+impl StrictOrLazyLineBuilder {
+    pub(crate) fn strict(line: LineBuilder) -> Self {
+        StrictOrLazyLineBuilder::Strict(line, None, None)
+    }
+
+    pub(crate) fn checkpointed((line, checkpoint): (LineBuilder, Checkpoint)) -> Self {
+        StrictOrLazyLineBuilder::Strict(line, Some(checkpoint), None)
+    }
+
+    pub(crate) fn timed((line, timestamp): (LineBuilder, Option<i64>)) -> Self {
+        StrictOrLazyLineBuilder::Strict(line, None, timestamp)
+    }
+
+    /// The file the line was read from, if any
+    #[cfg(feature = "k8s_source")]
+    pub(crate) fn file(&self) -> Option<&str> {
+        match self {
+            StrictOrLazyLineBuilder::Strict(line, _, _) => line.get_file(),
+            StrictOrLazyLineBuilder::Lazy(line) => line.get_file(),
+        }
+    }
+}
+
+/// Reads a lazy line into an owned line, for sinks that need a copy of it
+pub(crate) fn to_owned_line(lazy: &mut LazyLineSerializer) -> Option<Line> {
+    let mut line = LineBuilder::new().line(String::from_utf8_lossy(lazy.get_line_buffer()?));
+    let _ = line.set_file(lazy.get_file()?.to_string());
+    if let Some(app) = lazy.get_app() {
+        let _ = line.set_app(app.to_string());
+    }
+    if let Some(env) = lazy.get_env() {
+        let _ = line.set_env(env.to_string());
+    }
+    if let Some(host) = lazy.get_host() {
+        let _ = line.set_host(host.to_string());
+    }
+    if let Some(level) = lazy.get_level() {
+        let _ = line.set_level(level.to_string());
+    }
+    if let Some(labels) = lazy.get_labels() {
+        let _ = line.set_labels(labels.clone());
+    }
+    if let Some(annotations) = lazy.get_annotations() {
+        let _ = line.set_annotations(annotations.clone());
+    }
+    if let Some(meta) = lazy.get_meta() {
+        let _ = line.set_meta(meta.clone());
+    }
+    line.build().ok()
+}
+
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum StrictOrLazyLines<'a> {
-    Strict(&'a Line),
+    Strict(&'a Line, Option<&'a Checkpoint>),
     Lazy(LazyLineSerializer),
 }
 
@@ -28,7 +81,7 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
 
     fn has_annotations(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_annotations().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_annotations().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_annotations().is_some(),
         }
     }
@@ -40,13 +93,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeMap<'b, HashMap<String, String>> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.annotations(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.annotations(writer).await,
             StrictOrLazyLines::Lazy(line) => line.annotations(writer).await,
         }
     }
     fn has_app(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_app().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_app().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_app().is_some(),
         }
     }
@@ -55,13 +108,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.app(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.app(writer).await,
             StrictOrLazyLines::Lazy(line) => line.app(writer).await,
         }
     }
     fn has_env(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_env().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_env().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_env().is_some(),
         }
     }
@@ -70,13 +123,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.env(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.env(writer).await,
             StrictOrLazyLines::Lazy(line) => line.env(writer).await,
         }
     }
     fn has_file(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_file().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_file().is_some(),
             StrictOrLazyLines::Lazy(_) => true,
         }
     }
@@ -85,13 +138,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.file(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.file(writer).await,
             StrictOrLazyLines::Lazy(line) => line.file(writer).await,
         }
     }
     fn has_host(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_host().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_host().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_host().is_some(),
         }
     }
@@ -100,13 +153,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.host(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.host(writer).await,
             StrictOrLazyLines::Lazy(line) => line.host(writer).await,
         }
     }
     fn has_labels(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_labels().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_labels().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_labels().is_some(),
         }
     }
@@ -115,13 +168,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeMap<'b, HashMap<String, String>> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.labels(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.labels(writer).await,
             StrictOrLazyLines::Lazy(line) => line.labels(writer).await,
         }
     }
     fn has_level(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_level().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_level().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_level().is_some(),
         }
     }
@@ -130,13 +183,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeStr<String> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.level(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.level(writer).await,
             StrictOrLazyLines::Lazy(line) => line.level(writer).await,
         }
     }
     fn has_meta(&self) -> bool {
         match self {
-            StrictOrLazyLines::Strict(line) => line.get_meta().is_some(),
+            StrictOrLazyLines::Strict(line, _) => line.get_meta().is_some(),
             StrictOrLazyLines::Lazy(line) => line.get_meta().is_some(),
         }
     }
@@ -145,7 +198,7 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeValue + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.meta(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.meta(writer).await,
             StrictOrLazyLines::Lazy(line) => line.meta(writer).await,
         }
     }
@@ -154,7 +207,7 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeUtf8<bytes::Bytes> + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.line(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.line(writer).await,
             StrictOrLazyLines::Lazy(line) => line.line(writer).await,
         }
     }
@@ -163,13 +216,13 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
         S: SerializeI64 + std::marker::Send,
     {
         match self {
-            StrictOrLazyLines::Strict(line) => line.timestamp(writer).await,
+            StrictOrLazyLines::Strict(line, _) => line.timestamp(writer).await,
             StrictOrLazyLines::Lazy(line) => line.timestamp(writer).await,
         }
     }
     fn field_count(&self) -> usize {
         match self {
-            StrictOrLazyLines::Strict(line) => line.field_count(),
+            StrictOrLazyLines::Strict(line, _) => line.field_count(),
             StrictOrLazyLines::Lazy(line) => line.field_count(),
         }
     }
@@ -178,15 +231,33 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
 impl GetOffset for StrictOrLazyLines<'_> {
     fn get_offset(&self) -> Option<u64> {
         match self {
-            StrictOrLazyLines::Strict(_) => None,
+            StrictOrLazyLines::Strict(_, Some(Checkpoint::Offset { offset, .. })) => Some(*offset),
+            StrictOrLazyLines::Strict(_, _) => None,
             StrictOrLazyLines::Lazy(line) => line.get_offset(),
         }
     }
 
     fn get_key(&self) -> Option<u64> {
         match self {
-            StrictOrLazyLines::Strict(_) => None,
+            StrictOrLazyLines::Strict(_, Some(Checkpoint::Offset { key, .. })) => Some(*key),
+            StrictOrLazyLines::Strict(_, _) => None,
             StrictOrLazyLines::Lazy(line) => line.get_key(),
         }
     }
+
+    fn get_line_hash(&self) -> Option<u64> {
+        match self {
+            StrictOrLazyLines::Strict(_, _) => None,
+            StrictOrLazyLines::Lazy(line) => line.get_line_hash(),
+        }
+    }
+
+    fn get_timestamp(&self) -> Option<(&str, i64)> {
+        match self {
+            StrictOrLazyLines::Strict(_, Some(Checkpoint::Timestamp { key, nanos })) => {
+                Some((key.as_str(), *nanos))
+            }
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the tags file is checked for changes
+const CHECK_PERIOD: Duration = Duration::from_secs(10);
+
+/// Reads the tags file again whenever it's modified
+pub(crate) struct TagsFile {
+    path: PathBuf,
+    hostname: String,
+    modified: Option<SystemTime>,
+    last_check: Option<Instant>,
+}
+
+impl TagsFile {
+    pub(crate) fn new(path: PathBuf, hostname: String) -> Self {
+        TagsFile {
+            path,
+            hostname,
+            modified: None,
+            last_check: None,
+        }
+    }
+
+    /// The tags of the file, when it changed since they were last returned
+    pub(crate) fn poll(&mut self) -> Option<Vec<String>> {
+        if matches!(self.last_check, Some(check) if check.elapsed() < CHECK_PERIOD) {
+            return None;
+        }
+        let first_check = self.last_check.is_none();
+        self.last_check = Some(Instant::now());
+
+        let modified = match std::fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                // Its tags are kept until the file comes back
+                if first_check || self.modified.take().is_some() {
+                    warn!("unable to read tags file {:?}: {}", self.path, e);
+                }
+                return None;
+            }
+        };
+        if self.modified == Some(modified) {
+            return None;
+        }
+        match config::tags::read_file(&self.path, &self.hostname) {
+            Ok(tags) => {
+                info!("read {} tags from {:?}", tags.len(), self.path);
+                self.modified = Some(modified);
+                Some(tags)
+            }
+            Err(e) => {
+                warn!("unable to read tags file {:?}: {}", self.path, e);
+                None
+            }
+        }
+    }
+}
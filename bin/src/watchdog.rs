@@ -0,0 +1,181 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use metrics::Metrics;
+
+/// How often stages are checked for progress
+const CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Value of `waiting_since` while a stage has no pending work
+const IDLE: u64 = u64::MAX;
+
+/// A part of the pipeline the watchdog expects to make progress, either continuously, like
+/// the event loop ticking, or whenever work is waiting on it, like a request being sent
+pub struct Stage {
+    name: &'static str,
+    /// How often a continuous stage is expected to make progress
+    period: Option<Duration>,
+    started: Instant,
+    /// When, in milliseconds since `started`, a continuous stage last made progress
+    last_progress: AtomicU64,
+    /// Since when, in milliseconds since `started`, work is waiting on the stage
+    waiting_since: AtomicU64,
+}
+
+impl Stage {
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    /// Records that work is waiting on the stage, keeping the earliest time it started waiting
+    pub fn waiting(&self) {
+        let _ = self.waiting_since.compare_exchange(
+            IDLE,
+            self.now(),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Records that the stage made progress and how late it was to make it
+    pub fn progress(&self) {
+        let now = self.now();
+        let delay = match self.period {
+            Some(period) => now
+                .saturating_sub(self.last_progress.swap(now, Ordering::Relaxed))
+                .saturating_sub(period.as_millis() as u64),
+            None => now.saturating_sub(self.waiting_since.swap(IDLE, Ordering::Relaxed)),
+        };
+        Metrics::watchdog().observe_delay(self.name, delay);
+    }
+
+    /// How long the stage went without progress, `now` being milliseconds since `started`
+    fn stalled_for(&self, now: u64) -> Duration {
+        let since = match self.period {
+            Some(_) => self.last_progress.load(Ordering::Relaxed),
+            None => self.waiting_since.load(Ordering::Relaxed),
+        };
+        Duration::from_millis(now.saturating_sub(since))
+    }
+}
+
+/// Reports stages that go without progress for longer than a threshold, from a thread of its
+/// own so that it keeps reporting when the event loop itself is blocked
+pub struct Watchdog {
+    threshold: Duration,
+    started: Instant,
+    stages: Vec<Arc<Stage>>,
+}
+
+impl Watchdog {
+    pub fn new(threshold: Duration) -> Self {
+        Watchdog {
+            threshold,
+            started: Instant::now(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Adds a stage expected to make progress every `period`
+    pub fn continuous(&mut self, name: &'static str, period: Duration) -> Arc<Stage> {
+        self.add(name, Some(period))
+    }
+
+    /// Adds a stage expected to make progress only while work is waiting on it
+    pub fn on_demand(&mut self, name: &'static str) -> Arc<Stage> {
+        self.add(name, None)
+    }
+
+    fn add(&mut self, name: &'static str, period: Option<Duration>) -> Arc<Stage> {
+        let stage = Arc::new(Stage {
+            name,
+            period,
+            started: self.started,
+            last_progress: AtomicU64::new(0),
+            waiting_since: AtomicU64::new(IDLE),
+        });
+        self.stages.push(stage.clone());
+        stage
+    }
+
+    pub fn spawn(self) {
+        spawn(move || self.run());
+    }
+
+    /// The stages without progress for longer than the threshold, `now` being milliseconds
+    /// since the watchdog started
+    fn stalled(&self, now: u64) -> Vec<&'static str> {
+        self.stages
+            .iter()
+            .filter(|stage| stage.stalled_for(now) > self.threshold)
+            .map(|stage| stage.name)
+            .collect()
+    }
+
+    fn run(self) {
+        let mut stalled: Vec<&'static str> = Vec::new();
+        loop {
+            sleep(CHECK_PERIOD);
+            let current = self.stalled(self.started.elapsed().as_millis() as u64);
+            if current == stalled {
+                continue;
+            }
+            for name in current.iter().filter(|name| !stalled.contains(*name)) {
+                warn!(
+                    "{} stage made no progress for over {:?}, lines are not being shipped",
+                    name, self.threshold
+                );
+                Metrics::watchdog().increment_stalls();
+            }
+            for name in stalled.iter().filter(|name| !current.contains(*name)) {
+                info!("{} stage is making progress again", name);
+            }
+            Metrics::watchdog().set_stalled(current.clone());
+            stalled = current;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_continuous_stages_without_progress() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(10));
+        let stage = watchdog.continuous("event_loop", Duration::from_millis(250));
+        assert!(watchdog.stalled(5_000).is_empty());
+        assert_eq!(watchdog.stalled(10_001), vec!["event_loop"]);
+
+        stage.last_progress.store(9_000, Ordering::Relaxed);
+        assert!(watchdog.stalled(10_001).is_empty());
+        assert_eq!(stage.stalled_for(12_000), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn only_reports_on_demand_stages_with_work_waiting() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(10));
+        let stage = watchdog.on_demand("ingest");
+        assert!(watchdog.stalled(60_000).is_empty());
+
+        stage.waiting();
+        let since = stage.waiting_since.load(Ordering::Relaxed);
+        assert_ne!(since, IDLE);
+        // Waiting again keeps when the work started waiting
+        stage
+            .waiting_since
+            .store(since.saturating_sub(1_000), Ordering::Relaxed);
+        stage.waiting();
+        assert_eq!(
+            stage.waiting_since.load(Ordering::Relaxed),
+            since.saturating_sub(1_000)
+        );
+        assert_eq!(watchdog.stalled(since + 10_001), vec!["ingest"]);
+
+        stage.progress();
+        assert_eq!(stage.waiting_since.load(Ordering::Relaxed), IDLE);
+        assert!(watchdog.stalled(since + 60_000).is_empty());
+    }
+}
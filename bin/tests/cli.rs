@@ -33,6 +33,40 @@ fn api_key_missing() {
         .failure();
 }
 
+#[test]
+#[cfg_attr(not(feature = "integration_tests"), ignore)]
+fn check_config_reports_errors() {
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .arg("check-config")
+        .assert()
+        .stdout(predicate::str::contains(
+            "config error: http.ingestion_key is missing",
+        ))
+        .failure();
+
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .args(&["check-config", "--ingestion-key", "dummy-test-key"])
+        .assert()
+        .stdout(predicate::str::contains("config is valid"))
+        .success();
+}
+
+#[test]
+#[cfg_attr(not(feature = "integration_tests"), ignore)]
+fn version_as_json() {
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .args(&["version", "--json"])
+        .assert()
+        .stdout(predicate::str::contains(format!(
+            r#""version":"{}""#,
+            env!("CARGO_PKG_VERSION")
+        )))
+        .success();
+}
+
 #[test]
 #[cfg_attr(not(feature = "integration_tests"), ignore)]
 fn api_key_present() {
@@ -801,6 +835,9 @@ async fn test_lookback_restarting_agent() {
 
     let mut settings = AgentSettings::with_mock_ingester(&dir.to_str().unwrap(), &addr);
     settings.state_db_dir = Some(&db_dir);
+    // The agent is killed every second, offsets are saved as soon as lines are shipped so
+    // that none are shipped twice
+    settings.checkpoint_interval_ms = Some("0");
     settings.exclusion_regex = Some(r"/var\w*");
 
     let line_count_target = 5_000;
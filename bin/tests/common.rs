@@ -119,6 +119,7 @@ pub struct AgentSettings<'a> {
     pub tags: Option<&'a str>,
     pub config_file: Option<&'a str>,
     pub state_db_dir: Option<&'a std::path::Path>,
+    pub checkpoint_interval_ms: Option<&'a str>,
     pub line_exclusion_regex: Option<&'a str>,
     pub line_inclusion_regex: Option<&'a str>,
     pub line_redact_regex: Option<&'a str>,
@@ -187,6 +188,10 @@ pub fn spawn_agent(settings: AgentSettings) -> Child {
         agent.env("LOGDNA_DB_PATH", state_db_dir);
     }
 
+    if let Some(interval) = settings.checkpoint_interval_ms {
+        agent.env("LOGDNA_CHECKPOINT_INTERVAL_MS", interval);
+    }
+
     if let Some(rules) = settings.exclusion_regex {
         agent.env("LOGDNA_EXCLUSION_REGEX_RULES", rules);
     }
@@ -1,8 +1,10 @@
 use common::AgentSettings;
 pub use common::*;
 use logdna_mock_ingester::{
-    http_ingester_with_processors, FileLineCounter, IngestError, ProcessFn,
+    http_ingester_with_faults, http_ingester_with_processors, FaultSchedule, FileLineCounter,
+    IngestError, ProcessFn,
 };
+use std::collections::HashSet;
 use std::fs::File;
 use std::future::Future;
 use std::io::Write;
@@ -132,6 +134,109 @@ async fn test_retry_is_not_made_before_retry_base_delay_ms() {
     agent_handle.kill().unwrap();
 }
 
+#[tokio::test]
+#[cfg_attr(not(feature = "integration_tests"), ignore)]
+async fn test_no_lines_lost_with_ingest_faults() {
+    let timeout = 500;
+    let config_file_path = get_config_file(timeout, 100, 50);
+
+    let dir = tempdir().unwrap().into_path();
+    let file_path = dir.join("test.log");
+    let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+
+    let port = common::get_available_port().expect("No ports free");
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+    // Slow responses past the timeout are retried after the lines were ingested
+    let faults = FaultSchedule::parse(&format!("429/3,500/5,reset/7,slow:{}/4", timeout + 200));
+    let (server, received, shutdown_handle) = http_ingester_with_faults(address, faults.unwrap());
+    let address = format!("localhost:{}", port);
+
+    let mut settings = AgentSettings::with_mock_ingester(&dir.to_str().unwrap(), &address);
+    settings.config_file = config_file_path.to_str();
+    let mut agent_handle = common::spawn_agent(settings);
+    let agent_stderr = agent_handle.stderr.take().unwrap();
+    common::consume_output(agent_stderr);
+
+    let total_lines = 200;
+    let (server_result, _) = tokio::join!(server, async move {
+        tokio::time::sleep(tokio::time::Duration::from_millis(400)).await;
+        for i in 0..total_lines {
+            writeln!(file, "line {}", i).unwrap();
+            if i % 20 == 0 {
+                // Spreads the lines across several requests
+                tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+            }
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
+        let map = received.lock().await;
+        let file_info = map.get(file_path.to_str().unwrap()).unwrap();
+        let unique: HashSet<&String> = file_info.values.iter().collect();
+        // Every line made it despite the failures
+        assert_eq!(unique.len(), total_lines);
+        // Only the requests answered too late were sent again
+        assert!(file_info.values.len() < total_lines * 2);
+        shutdown_handle();
+    });
+
+    server_result.unwrap();
+    agent_handle.kill().unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "integration_tests"), ignore)]
+async fn test_strict_ordering_keeps_lines_in_order_across_retries() {
+    let config_file_path = get_config_file(2000, 100, 50);
+    let config = std::fs::read_to_string(&config_file_path).unwrap();
+    let config = config.replacen("http:\n", "http:\n  strict_ordering: true\n", 1);
+    std::fs::write(&config_file_path, config).unwrap();
+
+    let dir = tempdir().unwrap().into_path();
+    let file_path = dir.join("test.log");
+    let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+
+    let port = common::get_available_port().expect("No ports free");
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+    let faults = FaultSchedule::parse("reset/3,500/4,reset/6");
+    let (server, received, shutdown_handle) = http_ingester_with_faults(address, faults.unwrap());
+    let address = format!("localhost:{}", port);
+
+    let mut settings = AgentSettings::with_mock_ingester(&dir.to_str().unwrap(), &address);
+    settings.config_file = config_file_path.to_str();
+    let mut agent_handle = common::spawn_agent(settings);
+    let agent_stderr = agent_handle.stderr.take().unwrap();
+    common::consume_output(agent_stderr);
+
+    let total_lines = 100;
+    let (server_result, _) = tokio::join!(server, async move {
+        tokio::time::sleep(tokio::time::Duration::from_millis(400)).await;
+        for i in 0..total_lines {
+            writeln!(file, "line {}", i).unwrap();
+            if i % 10 == 0 {
+                // Spreads the lines across several requests
+                tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+            }
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        let map = received.lock().await;
+        let file_info = map.get(file_path.to_str().unwrap()).unwrap();
+        let mut seen = HashSet::new();
+        let first_copies: Vec<&String> = file_info
+            .values
+            .iter()
+            .filter(|line| seen.insert(*line))
+            .collect();
+        let expected: Vec<String> = (0..total_lines).map(|i| format!("line {}", i)).collect();
+        // The lines of the failed requests arrived before any later line
+        assert_eq!(first_copies, expected.iter().collect::<Vec<_>>());
+        shutdown_handle();
+    });
+
+    server_result.unwrap();
+    agent_handle.kill().unwrap();
+}
+
 /// Creates a temp config file with required fields and the provided parameters
 fn get_config_file(timeout: u64, retry_base_delay_ms: u64, retry_step_delay_ms: u64) -> PathBuf {
     let config_dir = tempdir().unwrap().into_path();
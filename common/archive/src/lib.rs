@@ -0,0 +1,2 @@
+mod object;
+pub mod s3;
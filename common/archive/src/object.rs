@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::types::body::Line;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Returns the `YYYY/MM/DD/HH` prefix objects written at `time` are stored under
+pub(crate) fn hour_of(time: DateTime<Utc>) -> String {
+    time.format("%Y/%m/%d/%H").to_string()
+}
+
+/// A gzip compressed NDJSON object within one hour, handed out in parts as it grows
+pub(crate) struct ObjectWriter {
+    hour: String,
+    encoder: GzEncoder<Vec<u8>>,
+    lines: u64,
+    created: Instant,
+}
+
+impl ObjectWriter {
+    pub(crate) fn new(hour: String) -> Self {
+        Self {
+            hour,
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            lines: 0,
+            created: Instant::now(),
+        }
+    }
+
+    pub(crate) fn hour(&self) -> &str {
+        &self.hour
+    }
+
+    pub(crate) fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    pub(crate) fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub(crate) fn write(&mut self, line: &Line) -> io::Result<()> {
+        serde_json::to_writer(&mut self.encoder, line)?;
+        self.encoder.write_all(b"\n")?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    /// Takes the compressed bytes produced so far once there are at least `min` of them
+    pub(crate) fn take_part(&mut self, min: usize) -> Option<Vec<u8>> {
+        if self.encoder.get_ref().len() < min {
+            return None;
+        }
+        Some(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// Completes the gzip stream, returning the remaining compressed bytes
+    pub(crate) fn finish(self) -> io::Result<Vec<u8>> {
+        self.encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use flate2::read::GzDecoder;
+    use http::types::body::LineBuilder;
+    use std::io::Read;
+
+    #[test]
+    fn formats_hour_prefix() {
+        assert_eq!(
+            hour_of(Utc.ymd(2021, 3, 7).and_hms(9, 59, 59)),
+            "2021/03/07/09"
+        );
+    }
+
+    #[test]
+    fn parts_concatenate_into_ndjson() {
+        let mut writer = ObjectWriter::new("2021/03/07/09".into());
+        let mut object = Vec::new();
+        for i in 0..1000 {
+            let line = LineBuilder::new()
+                .line(format!("line {}", i))
+                .build()
+                .unwrap();
+            writer.write(&line).unwrap();
+            if let Some(part) = writer.take_part(512) {
+                object.extend(part);
+            }
+        }
+        assert_eq!(writer.lines(), 1000);
+        object.extend(writer.finish().unwrap());
+
+        let mut ndjson = String::new();
+        GzDecoder::new(&object[..])
+            .read_to_string(&mut ndjson)
+            .unwrap();
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1000);
+        assert_eq!(lines[999]["line"], "line 999");
+    }
+}
@@ -0,0 +1,345 @@
+use crate::object::{hour_of, ObjectWriter};
+
+use chrono::Utc;
+use http::pool::CodecPool;
+use http::types::body::Line;
+use log::{debug, error, info, warn};
+use metrics::Metrics;
+use rusoto_core::Region;
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, PutObjectRequest, S3Client, UploadPartRequest, S3,
+};
+use sink::{Policy, Queue};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+/// S3 requires every part but the last one of a multipart upload to be at least 5MiB
+const PART_SIZE: usize = 8 * 1024 * 1024;
+/// Longest time between checks of whether the current object has to be completed
+const ROTATE_INTERVAL: Duration = Duration::from_secs(60);
+const CONTENT_TYPE: &str = "application/x-ndjson";
+/// Lines already queued that are compressed along with the one received, in a single job of
+/// the codec pool
+const BATCH_LINES: usize = 512;
+
+#[derive(Clone, Debug)]
+pub struct S3Options {
+    pub bucket: String,
+    pub prefix: String,
+    pub region: String,
+    /// Endpoint of an S3 compatible service, e.g. a MinIO deployment
+    pub endpoint: Option<String>,
+    pub policy: Policy,
+    /// Longest time lines are held in memory before their object is completed, which bounds
+    /// the lines lost when the agent stops
+    pub flush_interval: Duration,
+}
+
+/// Starts archiving lines to `<prefix>YYYY/MM/DD/HH/<host>-<timestamp>.ndjson.gz` objects in
+/// `options.bucket`, completed every `options.flush_interval` and at the end of each hour.
+///
+/// Credentials are resolved through the usual AWS chain: environment variables, the shared
+/// credentials file and the instance or task role. Must be called from within a tokio runtime.
+pub fn spawn(options: S3Options, host: String, codec_pool: CodecPool) -> Result<Queue, String> {
+    let region = match options.endpoint.clone() {
+        Some(endpoint) => Region::Custom {
+            name: options.region.clone(),
+            endpoint,
+        },
+        None => options
+            .region
+            .parse::<Region>()
+            .map_err(|e| format!("invalid s3 region {}: {}", options.region, e))?,
+    };
+    info!(
+        "archiving lines to s3 bucket {} under {:?}",
+        options.bucket, options.prefix
+    );
+
+    let (queue, rx) = sink::queue("s3 archive", options.policy.buffer_size, || {
+        Metrics::archive().increment_dropped()
+    });
+    let archiver = Archiver {
+        client: S3Client::new(region),
+        options,
+        host,
+        object: None,
+        upload: None,
+        codec_pool,
+    };
+    tokio::spawn(archiver.run(rx));
+    Ok(queue)
+}
+
+struct Upload {
+    key: String,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+}
+
+struct Archiver {
+    client: S3Client,
+    options: S3Options,
+    host: String,
+    object: Option<ObjectWriter>,
+    upload: Option<Upload>,
+    /// The threads objects are compressed on
+    codec_pool: CodecPool,
+}
+
+impl Archiver {
+    async fn run(mut self, mut rx: Receiver<Line>) {
+        let period = std::cmp::min(self.options.flush_interval, ROTATE_INTERVAL);
+        let mut rotate = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => {
+                        let mut lines = vec![line];
+                        while lines.len() < BATCH_LINES {
+                            match rx.try_recv() {
+                                Ok(line) => lines.push(line),
+                                Err(_) => break,
+                            }
+                        }
+                        self.write(lines).await
+                    }
+                    None => break,
+                },
+                _ = rotate.tick() => self.rotate_if_stale().await,
+            }
+        }
+        self.finish().await;
+    }
+
+    /// Serializes and compresses `lines` into the current object on the codec pool
+    async fn write(&mut self, lines: Vec<Line>) {
+        self.rotate_if_stale().await;
+        let hour = hour_of(Utc::now());
+        let object = self
+            .object
+            .take()
+            .unwrap_or_else(|| ObjectWriter::new(hour));
+        let pending = object.lines() + lines.len() as u64;
+        let written = self.codec_pool.run(move || {
+            let mut object = object;
+            for line in lines.iter() {
+                match object.write(line) {
+                    Ok(()) => Metrics::archive().increment_lines(),
+                    Err(e) => warn!("unable to archive line: {}", e),
+                }
+            }
+            object
+        });
+        let mut object = match written.await {
+            Ok(object) => object,
+            Err(e) => {
+                warn!("unable to compress s3 archive object: {}", e);
+                return self.fail(pending).await;
+            }
+        };
+        let part = object.take_part(PART_SIZE);
+        let lines = object.lines();
+        self.object = Some(object);
+        if let Some(part) = part {
+            self.upload_part(part, lines).await;
+        }
+    }
+
+    async fn rotate_if_stale(&mut self) {
+        let hour = hour_of(Utc::now());
+        let flush_interval = self.options.flush_interval;
+        let stale = |o: &ObjectWriter| o.hour() != hour || o.age() >= flush_interval;
+        if self.object.as_ref().map(stale).unwrap_or(false) {
+            self.finish().await;
+        }
+    }
+
+    fn key(&self, hour: &str) -> String {
+        format!(
+            "{}{}/{}-{}.ndjson.gz",
+            self.options.prefix,
+            hour,
+            self.host,
+            Utc::now().timestamp()
+        )
+    }
+
+    /// Uploads the next part of the object holding `lines`
+    async fn upload_part(&mut self, part: Vec<u8>, lines: u64) {
+        if self.upload.is_none() {
+            let hour = match self.object.as_ref() {
+                Some(object) => object.hour().to_string(),
+                None => return,
+            };
+            let key = self.key(&hour);
+            let request = CreateMultipartUploadRequest {
+                bucket: self.options.bucket.clone(),
+                key: key.clone(),
+                content_type: Some(CONTENT_TYPE.into()),
+                ..Default::default()
+            };
+            let client = &self.client;
+            let policy = &self.options.policy;
+            let created = policy
+                .retry("s3 archive create multipart upload", || {
+                    client.create_multipart_upload(request.clone())
+                })
+                .await;
+            match created.and_then(|o| o.upload_id) {
+                Some(upload_id) => {
+                    self.upload = Some(Upload {
+                        key,
+                        upload_id,
+                        parts: Vec::new(),
+                    })
+                }
+                None => return self.fail(lines).await,
+            }
+        }
+
+        let upload = self.upload.as_mut().expect("upload was just created");
+        let part_number = upload.parts.len() as i64 + 1;
+        let size = part.len();
+        let client = &self.client;
+        let bucket = &self.options.bucket;
+        let policy = &self.options.policy;
+        let uploaded = policy
+            .retry("s3 archive upload part", || {
+                client.upload_part(UploadPartRequest {
+                    bucket: bucket.clone(),
+                    key: upload.key.clone(),
+                    upload_id: upload.upload_id.clone(),
+                    part_number,
+                    content_length: Some(size as i64),
+                    body: Some(part.clone().into()),
+                    ..Default::default()
+                })
+            })
+            .await;
+        match uploaded {
+            Some(output) => {
+                Metrics::archive().add_bytes(size as u64);
+                upload.parts.push(CompletedPart {
+                    e_tag: output.e_tag,
+                    part_number: Some(part_number),
+                });
+            }
+            None => self.fail(lines).await,
+        }
+    }
+
+    /// Uploads what is left of the current object and completes it
+    async fn finish(&mut self) {
+        let object = match self.object.take() {
+            Some(object) => object,
+            None => return,
+        };
+        let hour = object.hour().to_string();
+        let lines = object.lines();
+        let rest = match self.codec_pool.run(move || object.finish()).await {
+            Ok(Ok(rest)) => rest,
+            Ok(Err(e)) => {
+                warn!("unable to compress s3 archive object: {}", e);
+                return self.fail(lines).await;
+            }
+            Err(e) => {
+                warn!("unable to compress s3 archive object: {}", e);
+                return self.fail(lines).await;
+            }
+        };
+
+        if self.upload.is_none() {
+            // Objects smaller than a part are written with a single request
+            let key = self.key(&hour);
+            let size = rest.len();
+            let client = &self.client;
+            let bucket = &self.options.bucket;
+            let policy = &self.options.policy;
+            let put = policy
+                .retry("s3 archive put object", || {
+                    client.put_object(PutObjectRequest {
+                        bucket: bucket.clone(),
+                        key: key.clone(),
+                        content_type: Some(CONTENT_TYPE.into()),
+                        content_length: Some(size as i64),
+                        body: Some(rest.clone().into()),
+                        ..Default::default()
+                    })
+                })
+                .await;
+            if put.is_some() {
+                Metrics::archive().add_bytes(size as u64);
+                self.completed(&key, lines);
+            } else {
+                self.fail(lines).await;
+            }
+            return;
+        }
+
+        // The last part of a multipart upload may be smaller than the minimum part size
+        self.upload_part(rest, lines).await;
+
+        let upload = match self.upload.take() {
+            Some(upload) => upload,
+            // The final part failed and the upload was aborted
+            None => return,
+        };
+        let client = &self.client;
+        let bucket = &self.options.bucket;
+        let policy = &self.options.policy;
+        let completed = policy
+            .retry("s3 archive complete multipart upload", || {
+                client.complete_multipart_upload(CompleteMultipartUploadRequest {
+                    bucket: bucket.clone(),
+                    key: upload.key.clone(),
+                    upload_id: upload.upload_id.clone(),
+                    multipart_upload: Some(CompletedMultipartUpload {
+                        parts: Some(upload.parts.clone()),
+                    }),
+                    ..Default::default()
+                })
+            })
+            .await;
+        if completed.is_some() {
+            self.completed(&upload.key, lines);
+        } else {
+            self.upload = Some(upload);
+            self.fail(lines).await;
+        }
+    }
+
+    fn completed(&self, key: &str, lines: u64) {
+        debug!(
+            "archived {} lines to s3://{}/{}",
+            lines, self.options.bucket, key
+        );
+        Metrics::archive().increment_objects();
+    }
+
+    /// Gives up on the current object, holding `lines`, after its retries were exhausted
+    async fn fail(&mut self, lines: u64) {
+        error!(
+            "unable to archive {} lines to s3 bucket {}, they are lost",
+            lines, self.options.bucket
+        );
+        Metrics::archive().increment_failures();
+        Metrics::archive().add_lost_lines(lines);
+        self.object = None;
+        if let Some(upload) = self.upload.take() {
+            let result = self
+                .client
+                .abort_multipart_upload(AbortMultipartUploadRequest {
+                    bucket: self.options.bucket.clone(),
+                    key: upload.key.clone(),
+                    upload_id: upload.upload_id,
+                    ..Default::default()
+                })
+                .await;
+            if let Err(e) = result {
+                warn!("unable to abort s3 upload of {}: {}", upload.key, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Record type that terminates a multi-record event
+const END_OF_EVENT: &str = "EOE";
+/// Events are flushed once this many newer events have started, even without an EOE record
+const MAX_PENDING_EVENTS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingType,
+    MissingHeader,
+    BadHeader(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ParseError::MissingType => write!(f, "audit record is missing its type"),
+            ParseError::MissingHeader => write!(f, "audit record is missing msg=audit(..)"),
+            ParseError::BadHeader(header) => write!(f, "invalid audit record header {}", header),
+        }
+    }
+}
+
+/// A single line from the audit log
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Record {
+    #[serde(rename = "type")]
+    pub record_type: String,
+    #[serde(skip)]
+    pub timestamp: String,
+    #[serde(skip)]
+    pub serial: u64,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Record {
+    /// Parses a line like `type=SYSCALL msg=audit(1364481363.243:24287): arch=c000003e ...`
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut record_type = None;
+        let mut header = None;
+        let mut fields = BTreeMap::new();
+
+        for (key, value) in split_fields(line) {
+            match key {
+                "type" if record_type.is_none() => record_type = Some(value),
+                "msg" if header.is_none() && value.starts_with("audit(") => header = Some(value),
+                // USER_* records nest their fields inside msg='...'
+                "msg" => fields.extend(split_fields(&value).map(|(k, v)| (k.to_string(), v))),
+                _ => {
+                    fields.insert(key.to_string(), value);
+                }
+            }
+        }
+
+        let record_type = record_type.ok_or(ParseError::MissingType)?;
+        let header = header.ok_or(ParseError::MissingHeader)?;
+        let (timestamp, serial) = parse_header(&header)?;
+
+        Ok(Record {
+            record_type,
+            timestamp,
+            serial,
+            fields,
+        })
+    }
+}
+
+fn parse_header(header: &str) -> Result<(String, u64), ParseError> {
+    let bad_header = || ParseError::BadHeader(header.to_string());
+    let inner = header
+        .strip_prefix("audit(")
+        .and_then(|h| h.trim_end_matches(':').strip_suffix(')'))
+        .ok_or_else(bad_header)?;
+    let mut parts = inner.splitn(2, ':');
+    let timestamp = parts.next().ok_or_else(bad_header)?;
+    let serial = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(bad_header)?;
+    Ok((timestamp.to_string(), serial))
+}
+
+/// Splits `key=value` pairs separated by whitespace, values may be quoted with ' or "
+fn split_fields(line: &str) -> impl Iterator<Item = (&str, String)> {
+    let mut rest = line.trim();
+    std::iter::from_fn(move || loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let eq = match rest[..end].find('=') {
+            Some(eq) => eq,
+            None => {
+                // Not a key value pair, skip the token
+                rest = &rest[end..];
+                continue;
+            }
+        };
+        let key = &rest[..eq];
+        let after = &rest[eq + 1..];
+        let (value, remaining) = match after.chars().next() {
+            Some(quote) if quote == '\'' || quote == '"' => match after[1..].find(quote) {
+                Some(close) => (after[1..close + 1].to_string(), &after[close + 2..]),
+                None => (after[1..].to_string(), ""),
+            },
+            _ => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                (after[..end].to_string(), &after[end..])
+            }
+        };
+        rest = remaining;
+        return Some((key, value));
+    })
+}
+
+/// All the records that make up a single audit event
+#[derive(Debug, Serialize)]
+pub struct Event {
+    pub serial: u64,
+    pub timestamp: String,
+    pub records: Vec<Record>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// Reassembles records into events using their serial number
+#[derive(Default)]
+pub struct Assembler {
+    pending: BTreeMap<u64, Event>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether no event is waiting for more of its records
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Adds a record, returning any events that are complete
+    pub fn push(&mut self, record: Record) -> Vec<Event> {
+        let mut complete = Vec::new();
+        let serial = record.serial;
+
+        if record.record_type == END_OF_EVENT {
+            complete.extend(self.pending.remove(&serial));
+        } else {
+            self.pending
+                .entry(serial)
+                .or_insert_with(|| Event {
+                    serial,
+                    timestamp: record.timestamp.clone(),
+                    records: Vec::new(),
+                    started: Instant::now(),
+                })
+                .records
+                .push(record);
+        }
+
+        while self.pending.len() > MAX_PENDING_EVENTS {
+            let oldest = *self.pending.keys().next().expect("pending is not empty");
+            complete.extend(self.pending.remove(&oldest));
+        }
+        complete
+    }
+
+    /// Returns events that started more than `max_age` ago, used when no EOE record arrives
+    pub fn flush_stale(&mut self, max_age: Duration) -> Vec<Event> {
+        let stale: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, event)| event.started.elapsed() >= max_age)
+            .map(|(serial, _)| *serial)
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|serial| self.pending.remove(&serial))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSCALL: &str = r#"type=SYSCALL msg=audit(1364481363.243:24287): arch=c000003e syscall=2 success=no exit=-13 comm="cat" exe="/usr/bin/cat" key="sshd_config""#;
+    const CWD: &str = r#"type=CWD msg=audit(1364481363.243:24287):  cwd="/home/shadowman""#;
+    const EOE: &str = "type=EOE msg=audit(1364481363.243:24287): ";
+
+    #[test]
+    fn parses_a_record() {
+        let record = Record::parse(SYSCALL).unwrap();
+        assert_eq!(record.record_type, "SYSCALL");
+        assert_eq!(record.timestamp, "1364481363.243");
+        assert_eq!(record.serial, 24287);
+        assert_eq!(record.fields["syscall"], "2");
+        assert_eq!(record.fields["exe"], "/usr/bin/cat");
+        assert_eq!(record.fields["key"], "sshd_config");
+    }
+
+    #[test]
+    fn flattens_nested_user_messages() {
+        let record = Record::parse(
+            "type=USER_LOGIN msg=audit(1364475353.159:24270): pid=3280 uid=0 \
+             msg='op=login acct=\"root\" exe=\"/usr/sbin/sshd\" res=failed'",
+        )
+        .unwrap();
+        assert_eq!(record.fields["pid"], "3280");
+        assert_eq!(record.fields["acct"], "root");
+        assert_eq!(record.fields["res"], "failed");
+    }
+
+    #[test]
+    fn rejects_lines_without_a_header() {
+        assert_eq!(
+            Record::parse("type=SYSCALL arch=c000003e"),
+            Err(ParseError::MissingHeader)
+        );
+        assert_eq!(
+            Record::parse("msg=audit(1364481363.243:24287):"),
+            Err(ParseError::MissingType)
+        );
+    }
+
+    #[test]
+    fn reassembles_records_by_serial() {
+        let mut assembler = Assembler::new();
+        assert!(assembler.push(Record::parse(SYSCALL).unwrap()).is_empty());
+        assert!(assembler.push(Record::parse(CWD).unwrap()).is_empty());
+
+        let events = assembler.push(Record::parse(EOE).unwrap());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].serial, 24287);
+        assert_eq!(events[0].records.len(), 2);
+        assert_eq!(events[0].records[1].record_type, "CWD");
+    }
+
+    #[test]
+    fn flushes_events_without_an_end_record() {
+        let mut assembler = Assembler::new();
+        assembler.push(Record::parse(SYSCALL).unwrap());
+        assert!(assembler.flush_stale(Duration::from_secs(60)).is_empty());
+        assert_eq!(assembler.flush_stale(Duration::from_secs(0)).len(), 1);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::parser::{Assembler, Event, Record};
+
+use futures::stream::{self, select_all, Stream};
+use http::types::body::LineBuilder;
+use log::{info, warn};
+use metrics::Metrics;
+use state::{Checkpoint, FileId};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+
+const APP: &str = "auditd";
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long an event without an EOE record is held back waiting for more records
+const EVENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Creates a stream of reassembled audit events for each of the audit log files, along with
+/// the offset to resume the log from once they are shipped. The logs resume from the offsets
+/// saved for their inodes, or from their end when there are none.
+pub fn create_source(
+    paths: &[PathBuf],
+    offsets: Option<&HashMap<FileId, u64>>,
+) -> impl Stream<Item = (LineBuilder, Checkpoint)> {
+    let streams: Vec<_> = paths
+        .iter()
+        .cloned()
+        .map(|path| {
+            let saved = offsets.and_then(|offsets| {
+                let inode = std::fs::metadata(&path).ok()?.ino();
+                offsets.get(&FileId::from(&inode)).copied()
+            });
+            match saved {
+                Some(offset) => info!("monitoring audit log {:?} from {}", path, offset),
+                None => info!("monitoring audit log {:?}", path),
+            }
+            Box::pin(follow(path, saved))
+        })
+        .collect();
+    select_all(streams)
+}
+
+struct Follower {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    inode: u64,
+    offset: u64,
+    /// Offset of the oldest record of the events held by the assembler
+    held_from: u64,
+    assembler: Assembler,
+    buf: Vec<u8>,
+}
+
+impl Follower {
+    /// Opens the log at `from`, or at its end when there is no offset to resume from
+    async fn open(&mut self, from: Option<u64>) {
+        let file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("unable to open audit log {:?}: {}", self.path, e);
+                self.reader = None;
+                return;
+            }
+        };
+        let (inode, len) = match file.metadata().await {
+            Ok(m) => (m.ino(), m.len()),
+            Err(e) => {
+                warn!("unable to stat audit log {:?}: {}", self.path, e);
+                self.reader = None;
+                return;
+            }
+        };
+        let mut reader = BufReader::new(file);
+        self.offset = match from {
+            Some(offset) if offset <= len => offset,
+            // The log was truncated meanwhile
+            Some(_) => 0,
+            None => len,
+        };
+        if let Err(e) = reader.seek(SeekFrom::Start(self.offset)).await {
+            warn!("unable to seek audit log {:?}: {}", self.path, e);
+        }
+        self.inode = inode;
+        self.reader = Some(reader);
+    }
+
+    /// Reopens the log when it was rotated or truncated
+    async fn check_rotation(&mut self) {
+        match tokio::fs::metadata(&self.path).await {
+            Ok(m) if self.reader.is_none() || m.ino() != self.inode || m.len() < self.offset => {
+                info!("audit log {:?} was rotated, reopening", self.path);
+                self.open(Some(0)).await
+            }
+            _ => (),
+        }
+    }
+
+    /// The offset to resume from once the events returned so far are shipped, records of the
+    /// events still held are read again
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint::Offset {
+            key: self.inode,
+            offset: if self.assembler.is_empty() {
+                self.offset
+            } else {
+                self.held_from
+            },
+        }
+    }
+
+    /// Reads the next batch of complete events, waiting for new data when caught up
+    async fn next_events(&mut self) -> Vec<Event> {
+        loop {
+            let read = match self.reader.as_mut() {
+                Some(reader) => {
+                    self.buf.clear();
+                    reader.read_until(b'\n', &mut self.buf).await
+                }
+                None => Ok(0),
+            };
+            match read {
+                // A partial line is left in the buffer, rewind and try again later
+                Ok(n) if n > 0 && !self.buf.ends_with(b"\n") => self.rewind().await,
+                Ok(n) if n > 0 => {
+                    // Records that aren't valid UTF-8 are still read past, the offset has to
+                    // stay at the start of the next line to be read
+                    let start = self.offset;
+                    self.offset += n as u64;
+                    match Record::parse(String::from_utf8_lossy(&self.buf).trim_end()) {
+                        Ok(record) => {
+                            if self.assembler.is_empty() {
+                                self.held_from = start;
+                            }
+                            let events = self.assembler.push(record);
+                            if !events.is_empty() {
+                                return events;
+                            }
+                        }
+                        Err(e) => warn!("dropping audit record: {}", e),
+                    }
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("unable to read audit log {:?}: {}", self.path, e);
+                    self.rewind().await;
+                }
+            }
+
+            let stale = self.assembler.flush_stale(EVENT_TIMEOUT);
+            if !stale.is_empty() {
+                return stale;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+            self.check_rotation().await;
+        }
+    }
+
+    /// Moves the reader back to the start of the line that wasn't read entirely
+    async fn rewind(&mut self) {
+        if let Some(reader) = self.reader.as_mut() {
+            let _ = reader.seek(SeekFrom::Start(self.offset)).await;
+        }
+    }
+}
+
+fn follow(path: PathBuf, saved: Option<u64>) -> impl Stream<Item = (LineBuilder, Checkpoint)> {
+    let follower = Follower {
+        path,
+        reader: None,
+        inode: 0,
+        offset: 0,
+        held_from: 0,
+        assembler: Assembler::new(),
+        buf: Vec::new(),
+    };
+    stream::unfold((follower, true), move |(mut follower, first)| async move {
+        if first {
+            follower.open(saved).await;
+        }
+        let events = follower.next_events().await;
+        let checkpoint = follower.checkpoint();
+        let file = follower.path.to_string_lossy().to_string();
+        let lines: Vec<(LineBuilder, Checkpoint)> = events
+            .into_iter()
+            .filter_map(|event| match serde_json::to_string(&event) {
+                Ok(line) => {
+                    Metrics::auditd().increment_events();
+                    let line = LineBuilder::new().line(line).file(&file).app(APP);
+                    Some((line, checkpoint.clone()))
+                }
+                Err(e) => {
+                    warn!("unable to serialize audit event {}: {}", event.serial, e);
+                    None
+                }
+            })
+            .collect();
+        Some((stream::iter(lines), (follower, false)))
+    })
+    .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::io::Write;
+
+    #[test]
+    fn ships_appended_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "type=EOE msg=audit(1364481363.100:1): ").unwrap();
+
+        tokio_test::block_on(async {
+            let mut source = Box::pin(create_source(&[path.clone()], None));
+            let writer = async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                writeln!(
+                    file,
+                    "type=SYSCALL msg=audit(1364481363.243:24287): syscall=2 exe=\"/usr/bin/cat\""
+                )
+                .unwrap();
+                writeln!(
+                    file,
+                    "type=CWD msg=audit(1364481363.243:24287): cwd=\"/root\""
+                )
+                .unwrap();
+                writeln!(file, "type=EOE msg=audit(1364481363.243:24287): ").unwrap();
+            };
+            let (line, _) = futures::join!(source.next(), writer);
+
+            let (line, checkpoint) = line.expect("expected an audit event");
+            assert_eq!(line.app.as_deref(), Some(APP));
+            let event: serde_json::Value = serde_json::from_str(&line.line.unwrap()).unwrap();
+            assert_eq!(event["serial"], 24287);
+            assert_eq!(event["records"][0]["type"], "SYSCALL");
+            assert_eq!(event["records"][1]["fields"]["cwd"], "/root");
+
+            let metadata = std::fs::metadata(&path).unwrap();
+            assert_eq!(
+                checkpoint,
+                Checkpoint::Offset {
+                    key: metadata.ino(),
+                    offset: metadata.len(),
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn resumes_from_the_saved_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "type=CWD msg=audit(1364481363.100:1): cwd=\"/root\"").unwrap();
+        writeln!(file, "type=EOE msg=audit(1364481363.100:1): ").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        let offsets = vec![(FileId::from(&metadata.ino()), metadata.len())]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        // A record that isn't valid UTF-8 is dropped without holding back the ones after it
+        file.write_all(b"type=CWD msg=\xff\xfe\n").unwrap();
+        writeln!(file, "type=CWD msg=audit(1364481363.243:2): cwd=\"/tmp\"").unwrap();
+        writeln!(file, "type=EOE msg=audit(1364481363.243:2): ").unwrap();
+
+        tokio_test::block_on(async {
+            let mut source = Box::pin(create_source(&[path.clone()], Some(&offsets)));
+            let (line, checkpoint) = source.next().await.expect("expected an audit event");
+            let event: serde_json::Value = serde_json::from_str(&line.line.unwrap()).unwrap();
+            assert_eq!(event["serial"], 2);
+
+            let metadata = std::fs::metadata(&path).unwrap();
+            assert_eq!(
+                checkpoint,
+                Checkpoint::Offset {
+                    key: metadata.ino(),
+                    offset: metadata.len(),
+                }
+            );
+        });
+    }
+}
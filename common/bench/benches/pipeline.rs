@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bench::{lines, CARD_REGEX, EMAIL_REGEX};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use fs::rule::{GlobRule, Rules};
+use fs::tail::{DirPathBuf, Lookback, Tailer};
+use futures::{pin_mut, StreamExt};
+use http::types::body::{IngestBodyBuffer, Line, LineBufferMut, LineBuilder};
+use http::types::serialize::body_serializer_source;
+use middleware::line_rules::LineRules;
+use middleware::Middleware;
+use state::{FileId, OffsetMap};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+const LINES: usize = 10_000;
+const LINE_SIZE: usize = 256;
+/// Files whose offsets are looked up and updated at once
+const FILES: u64 = 4_096;
+/// Threads sharing the offsets
+const THREADS: u64 = 4;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("unable to start a runtime")
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn built_lines() -> Vec<Line> {
+    lines(LINES, LINE_SIZE)
+        .into_iter()
+        .map(|line| {
+            LineBuilder::new()
+                .line(line)
+                .file("/var/log/app.log")
+                .build()
+                .expect("valid line")
+        })
+        .collect()
+}
+
+/// Reads the lines of a file from its start through the tailer
+async fn tail(dir: &Path, count: usize) {
+    let mut rules = Rules::new();
+    rules.add_inclusion(GlobRule::new("**").expect("valid glob"));
+    let dir: DirPathBuf = dir
+        .try_into()
+        .unwrap_or_else(|_| panic!("{:?} is not a directory", dir));
+    let mut tailer = Tailer::new(vec![dir], rules, Lookback::Start, None);
+    let mut buf = [0u8; 4096];
+    let lines = tailer
+        .process(&mut buf)
+        .expect("unable to start the tailer");
+    pin_mut!(lines);
+    for _ in 0..count {
+        let mut line = lines.next().await.expect("the tailer stopped");
+        black_box(line.get_line_buffer());
+    }
+}
+
+/// Serializes the lines into a request body, as the client does before sending it
+async fn serialize(lines: &[Line]) -> IngestBodyBuffer {
+    let source = body_serializer_source(16 * 1024, 50, None, Some(100));
+    pin_mut!(source);
+    let mut serializer = source
+        .next()
+        .await
+        .expect("the serializer source stopped")
+        .expect("unable to allocate a serializer");
+    for line in lines {
+        serializer
+            .write_line(line)
+            .await
+            .expect("unable to serialize a line");
+    }
+    IngestBodyBuffer::from_buffer(serializer.end().expect("unable to close the body"))
+}
+
+fn split(c: &mut Criterion) {
+    let dir = tempdir().expect("unable to create a temp dir");
+    let content = lines(LINES, LINE_SIZE).join("\n") + "\n";
+    std::fs::write(dir.path().join("app.log"), &content).expect("unable to write the lines");
+    let runtime = runtime();
+
+    let mut group = c.benchmark_group("split");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+    group.bench_function("tailer", |b| {
+        b.iter(|| runtime.block_on(tail(dir.path(), LINES)))
+    });
+    group.finish();
+}
+
+/// Runs `iters` operations on `offsets` on each of the threads at once, one in eight of them
+/// an update and the others lookups, of files spread over the map
+fn contend<M: Send + Sync + 'static>(
+    offsets: &Arc<M>,
+    iters: u64,
+    op: fn(&M, FileId, bool),
+) -> Duration {
+    let started = Instant::now();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let offsets = offsets.clone();
+            std::thread::spawn(move || {
+                for i in 0..iters {
+                    let file = (i * 31 + thread * FILES / THREADS) % FILES;
+                    op(&offsets, FileId::from(&file), i % 8 == 0);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("a thread panicked");
+    }
+    started.elapsed()
+}
+
+/// Looks up and updates the offsets of many files from several threads, as the tasks reading
+/// the files do, through a single lock and through the sharded map
+fn offsets(c: &mut Criterion) {
+    let saved = || (0..FILES).map(|i| (FileId::from(&i), i * 100));
+    let locked = Arc::new(Mutex::new(saved().collect::<HashMap<_, _>>()));
+    let sharded = Arc::new(saved().collect::<OffsetMap>());
+
+    let mut group = c.benchmark_group("offsets");
+    group.throughput(Throughput::Elements(THREADS));
+    group.bench_function(BenchmarkId::new("mutex", FILES), |b| {
+        b.iter_custom(|iters| {
+            contend(&locked, iters, |offsets, key, update| {
+                let mut offsets = offsets.lock().expect("Couldn't lock offsets");
+                if update {
+                    offsets.insert(key, 0);
+                } else {
+                    black_box(offsets.get(&key));
+                }
+            })
+        })
+    });
+    group.bench_function(BenchmarkId::new("sharded", FILES), |b| {
+        b.iter_custom(|iters| {
+            contend(&sharded, iters, |offsets, key, update| {
+                if update {
+                    offsets.insert(key, 0);
+                } else {
+                    black_box(offsets.get(&key));
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+fn line_rules(c: &mut Criterion) {
+    let values = lines(LINES, LINE_SIZE);
+    let exclusion = strings(&["healthz", r"^\S+ DEBUG", "kube-probe"]);
+    let inclusion = strings(&[r"^\S+ (WARN|ERROR)", "payment"]);
+    let redact = strings(&[EMAIL_REGEX, CARD_REGEX]);
+    let rules = [
+        ("exclusion", LineRules::new(&exclusion, &[], &[])),
+        ("inclusion", LineRules::new(&[], &inclusion, &[])),
+        ("redaction", LineRules::new(&[], &[], &redact)),
+    ];
+
+    let mut group = c.benchmark_group("line_rules");
+    group.throughput(Throughput::Elements(LINES as u64));
+    for (name, rules) in rules.iter() {
+        let rules = rules.as_ref().expect("valid rules");
+        group.bench_function(*name, |b| {
+            // Redaction changes the lines, each run gets its own copy
+            b.iter_batched(
+                || {
+                    values
+                        .iter()
+                        .map(|value| LineBuilder::new().line(value.clone()))
+                        .collect::<Vec<_>>()
+                },
+                |mut lines| {
+                    for line in lines.iter_mut() {
+                        black_box(rules.process(line));
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    let lines = built_lines();
+    let runtime = runtime();
+
+    let mut group = c.benchmark_group("serialization");
+    group.throughput(Throughput::Elements(LINES as u64));
+    group.bench_function("ingest_body", |b| {
+        b.iter(|| runtime.block_on(serialize(&lines)))
+    });
+    group.finish();
+}
+
+fn compression(c: &mut Criterion) {
+    let body = runtime().block_on(serialize(&built_lines()));
+    let mut json = Vec::new();
+    body.reader()
+        .read_to_end(&mut json)
+        .expect("unable to read the body");
+
+    // Levels 1 to 9 of LOGDNA_GZIP_LEVEL, 2 by default
+    let mut group = c.benchmark_group("compression");
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    for level in [1, 2, 6, 9].iter() {
+        group.bench_with_input(BenchmarkId::new("gzip", level), level, |b, level| {
+            b.iter(|| {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::new(*level));
+                encoder.write_all(&json).expect("unable to compress");
+                encoder.finish().expect("unable to compress")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    split,
+    offsets,
+    line_rules,
+    serialization,
+    compression
+);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+//! Fixtures of the benchmarks of the line pipeline, run them with `cargo bench -p bench`
+
+/// Matches the email addresses of the fixture lines
+pub const EMAIL_REGEX: &str = r"[\w.+-]+@[\w-]+\.[\w.]+";
+/// Matches the card numbers of the fixture lines
+pub const CARD_REGEX: &str = r"\b(?:\d{4}[ -]?){3}\d{4}\b";
+
+const LEVELS: [&str; 4] = ["INFO", "DEBUG", "WARN", "ERROR"];
+const FILLER: &str = "lorem ipsum dolor sit amet consectetur adipiscing elit ";
+
+/// `count` lines shaped like application logs and padded to `size` bytes, one in every ten
+/// carries an email address and a card number for the redaction rules to find
+pub fn lines(count: usize, size: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let level = LEVELS[i % LEVELS.len()];
+            let mut line = format!("2021-06-01T12:00:00.{:03}Z {} ", i % 1000, level);
+            if i % 10 == 0 {
+                line.push_str(&format!("payment {} by jane.doe+{}@example.com ", i, i));
+                line.push_str("card 4111 1111 1111 1111 ");
+            } else {
+                line.push_str(&format!("GET /api/orders/{} 200 ", i));
+            }
+            let missing = size.saturating_sub(line.len());
+            line.extend(FILLER.chars().cycle().take(missing));
+            line
+        })
+        .collect()
+}
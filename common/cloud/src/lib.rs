@@ -0,0 +1,8 @@
+//! Detects the cloud instance the agent runs on through the instance metadata services of
+//! AWS, GCP and Azure, to enrich lines with the instance's identity
+
+#[macro_use]
+extern crate log;
+
+pub mod metadata;
+pub mod middleware;
@@ -0,0 +1,486 @@
+use std::time::Duration;
+
+use futures::Future;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+/// Link local address every supported provider serves its metadata on
+const METADATA_HOST: &str = "http://169.254.169.254";
+
+/// Fields that can be attached to lines
+pub const FIELDS: &[&str] = &[
+    "provider",
+    "instance_id",
+    "instance_type",
+    "region",
+    "zone",
+    "name",
+    "image_id",
+    "private_ip",
+    "mac",
+];
+
+/// Fields attached to lines unless configured otherwise
+pub const DEFAULT_FIELDS: &[&str] = &[
+    "provider",
+    "instance_id",
+    "instance_type",
+    "region",
+    "zone",
+    "name",
+];
+
+/// How long IMDSv2 session tokens are requested for, in seconds
+const AWS_TOKEN_TTL: &str = "21600";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    Request(#[from] hyper::http::Error),
+    #[error("metadata service answered {0}")]
+    Status(StatusCode),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Identity of the cloud instance the agent runs on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstanceMetadata {
+    pub provider: &'static str,
+    pub instance_id: String,
+    pub instance_type: Option<String>,
+    pub region: Option<String>,
+    pub zone: Option<String>,
+    /// Name given to the instance, when the provider exposes one
+    pub name: Option<String>,
+    pub image_id: Option<String>,
+    /// Address of the primary network interface
+    pub private_ip: Option<String>,
+    /// Hardware address of the primary network interface
+    pub mac: Option<String>,
+}
+
+impl InstanceMetadata {
+    /// The value of one of [`FIELDS`], when it's known
+    pub fn field(&self, field: &str) -> Option<&str> {
+        match field {
+            "provider" => Some(self.provider),
+            "instance_id" => Some(&self.instance_id),
+            "instance_type" => self.instance_type.as_deref(),
+            "region" => self.region.as_deref(),
+            "zone" => self.zone.as_deref(),
+            "name" => self.name.as_deref(),
+            "image_id" => self.image_id.as_deref(),
+            "private_ip" => self.private_ip.as_deref(),
+            "mac" => self.mac.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The `fields` of the metadata as attached to lines, leaving out the ones that aren't
+    /// known
+    pub fn to_json(&self, fields: &[String]) -> Value {
+        let mut object = Map::new();
+        for field in fields {
+            if let Some(value) = self.field(field) {
+                object.insert(field.clone(), json!(value));
+            }
+        }
+        Value::Object(object)
+    }
+}
+
+/// Queries the metadata service of every supported provider at once, each given `timeout`
+/// to answer, returning `None` when the agent doesn't run on any of them
+pub async fn detect(timeout: Duration) -> Option<InstanceMetadata> {
+    let client = Client::new();
+    let (aws, gcp, azure) = futures::join!(
+        within(timeout, "aws", aws(&client, METADATA_HOST)),
+        within(timeout, "gcp", gcp(&client, METADATA_HOST)),
+        within(timeout, "azure", azure(&client, METADATA_HOST)),
+    );
+    aws.or(gcp).or(azure)
+}
+
+/// Runs [`detect`] on a runtime of its own, for callers outside of one
+pub fn detect_blocking(timeout: Duration) -> Option<InstanceMetadata> {
+    match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime.block_on(detect(timeout)),
+        Err(e) => {
+            warn!("unable to build runtime to query instance metadata: {}", e);
+            None
+        }
+    }
+}
+
+async fn within(
+    timeout: Duration,
+    provider: &str,
+    query: impl Future<Output = Result<InstanceMetadata, Error>>,
+) -> Option<InstanceMetadata> {
+    match tokio::time::timeout(timeout, query).await {
+        Ok(Ok(metadata)) => Some(metadata),
+        Ok(Err(e)) => {
+            debug!("no {} instance metadata: {}", provider, e);
+            None
+        }
+        Err(_) => {
+            debug!("no {} instance metadata: timed out", provider);
+            None
+        }
+    }
+}
+
+pub(crate) async fn get(
+    client: &Client<HttpConnector>,
+    host: &str,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<Vec<u8>, Error> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("{}{}", host, path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = client.request(request.body(Body::empty())?).await?;
+    if !response.status().is_success() {
+        return Err(Error::Status(response.status()));
+    }
+    Ok(hyper::body::to_bytes(response.into_body()).await?.to_vec())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AwsIdentity {
+    instance_id: String,
+    instance_type: Option<String>,
+    region: Option<String>,
+    availability_zone: Option<String>,
+    image_id: Option<String>,
+    private_ip: Option<String>,
+}
+
+async fn aws(client: &Client<HttpConnector>, host: &str) -> Result<InstanceMetadata, Error> {
+    // Instances requiring IMDSv2 only answer requests with a session token, the ones that
+    // still allow IMDSv1 are queried without one when getting a token fails
+    let token = get(
+        client,
+        host,
+        Method::PUT,
+        "/latest/api/token",
+        &[("X-aws-ec2-metadata-token-ttl-seconds", AWS_TOKEN_TTL)],
+    )
+    .await
+    .map_err(|e| debug!("unable to get IMDSv2 token, falling back to IMDSv1: {}", e))
+    .ok()
+    .and_then(|token| String::from_utf8(token).ok());
+    let headers: Vec<(&str, &str)> = token
+        .iter()
+        .map(|token| ("X-aws-ec2-metadata-token", token.as_str()))
+        .collect();
+
+    let document = get(
+        client,
+        host,
+        Method::GET,
+        "/latest/dynamic/instance-identity/document",
+        &headers,
+    )
+    .await?;
+    let mut metadata = parse_aws(&document)?;
+    // Only available when instance tags are exposed through the metadata service
+    metadata.name = get_text(
+        client,
+        host,
+        "/latest/meta-data/tags/instance/Name",
+        &headers,
+    )
+    .await;
+    metadata.mac = get_text(client, host, "/latest/meta-data/mac", &headers).await;
+    Ok(metadata)
+}
+
+async fn get_text(
+    client: &Client<HttpConnector>,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Option<String> {
+    let text = get(client, host, Method::GET, path, headers).await.ok()?;
+    String::from_utf8(text)
+        .ok()
+        .map(|text| text.trim().to_string())
+}
+
+fn parse_aws(document: &[u8]) -> Result<InstanceMetadata, Error> {
+    let identity: AwsIdentity = serde_json::from_slice(document)?;
+    Ok(InstanceMetadata {
+        provider: "aws",
+        instance_id: identity.instance_id,
+        instance_type: identity.instance_type,
+        region: identity.region,
+        zone: identity.availability_zone,
+        name: None,
+        image_id: identity.image_id,
+        private_ip: identity.private_ip,
+        mac: None,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcpInstance {
+    id: u64,
+    name: Option<String>,
+    machine_type: Option<String>,
+    zone: Option<String>,
+    image: Option<String>,
+    #[serde(default)]
+    network_interfaces: Vec<GcpNetworkInterface>,
+}
+
+#[derive(Deserialize)]
+struct GcpNetworkInterface {
+    ip: Option<String>,
+    mac: Option<String>,
+}
+
+async fn gcp(client: &Client<HttpConnector>, host: &str) -> Result<InstanceMetadata, Error> {
+    let instance = get(
+        client,
+        host,
+        Method::GET,
+        "/computeMetadata/v1/instance/?recursive=true",
+        &[("Metadata-Flavor", "Google")],
+    )
+    .await?;
+    parse_gcp(&instance)
+}
+
+fn parse_gcp(instance: &[u8]) -> Result<InstanceMetadata, Error> {
+    let mut instance: GcpInstance = serde_json::from_slice(instance)?;
+    let (private_ip, mac) = match instance.network_interfaces.drain(..).next() {
+        Some(interface) => (interface.ip, interface.mac),
+        None => (None, None),
+    };
+    // The machine type and zone are resource paths, e.g. projects/1/zones/us-central1-a
+    let last_segment = |path: String| path.rsplit('/').next().map(str::to_string);
+    let zone = instance.zone.and_then(last_segment);
+    Ok(InstanceMetadata {
+        provider: "gcp",
+        instance_id: instance.id.to_string(),
+        instance_type: instance.machine_type.and_then(last_segment),
+        region: zone
+            .as_ref()
+            .and_then(|zone| zone.rsplit_once('-'))
+            .map(|(region, _)| region.to_string()),
+        zone,
+        name: instance.name,
+        image_id: instance.image.and_then(last_segment),
+        private_ip,
+        mac,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureCompute {
+    vm_id: String,
+    vm_size: Option<String>,
+    location: Option<String>,
+    zone: Option<String>,
+    name: Option<String>,
+}
+
+async fn azure(client: &Client<HttpConnector>, host: &str) -> Result<InstanceMetadata, Error> {
+    let compute = get(
+        client,
+        host,
+        Method::GET,
+        "/metadata/instance/compute?api-version=2021-02-01",
+        &[("Metadata", "true")],
+    )
+    .await?;
+    parse_azure(&compute)
+}
+
+fn parse_azure(compute: &[u8]) -> Result<InstanceMetadata, Error> {
+    let compute: AzureCompute = serde_json::from_slice(compute)?;
+    Ok(InstanceMetadata {
+        provider: "azure",
+        instance_id: compute.vm_id,
+        instance_type: compute.vm_size,
+        region: compute.location,
+        // Empty for machines outside of availability zones
+        zone: compute.zone.filter(|zone| !zone.is_empty()),
+        name: compute.name,
+        // Only part of the separate network metadata
+        image_id: None,
+        private_ip: None,
+        mac: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    const DOCUMENT: &str = r#"{"instanceId": "i-1234567890abcdef0", "region": "us-east-1"}"#;
+
+    /// Serves the AWS metadata of an instance, only to requests with a session token when
+    /// `imdsv2` is set and without handing out tokens otherwise, returns its address
+    fn serve_aws_metadata(imdsv2: bool) -> SocketAddr {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| async move {
+                let token = request
+                    .headers()
+                    .get("X-aws-ec2-metadata-token")
+                    .and_then(|token| token.to_str().ok())
+                    .map(str::to_string);
+                let response = match (request.method(), request.uri().path(), imdsv2) {
+                    (&Method::PUT, "/latest/api/token", true) => {
+                        let ttl = request
+                            .headers()
+                            .get("X-aws-ec2-metadata-token-ttl-seconds");
+                        assert_eq!(ttl.and_then(|ttl| ttl.to_str().ok()), Some(AWS_TOKEN_TTL));
+                        Response::new(Body::from("session-token"))
+                    }
+                    (&Method::PUT, "/latest/api/token", false) => not_found(),
+                    (_, _, true) if token.as_deref() != Some("session-token") => {
+                        Response::builder()
+                            .status(StatusCode::UNAUTHORIZED)
+                            .body(Body::empty())
+                            .unwrap()
+                    }
+                    (&Method::GET, "/latest/dynamic/instance-identity/document", _) => {
+                        Response::new(Body::from(DOCUMENT))
+                    }
+                    (&Method::GET, "/latest/meta-data/mac", _) => {
+                        Response::new(Body::from("0e:49:61:0f:c3:11\n"))
+                    }
+                    _ => not_found(),
+                };
+                Ok::<_, Infallible>(response)
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+        address
+    }
+
+    fn not_found() -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn queries_aws_metadata_with_an_imdsv2_token() {
+        let address = serve_aws_metadata(true);
+        let metadata = aws(&Client::new(), &format!("http://{}", address))
+            .await
+            .unwrap();
+        assert_eq!(metadata.instance_id, "i-1234567890abcdef0");
+        assert_eq!(metadata.region.as_deref(), Some("us-east-1"));
+        assert_eq!(metadata.mac.as_deref(), Some("0e:49:61:0f:c3:11"));
+        // Instance tags aren't exposed by this instance
+        assert_eq!(metadata.name, None);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_imdsv1_without_a_token() {
+        let address = serve_aws_metadata(false);
+        let metadata = aws(&Client::new(), &format!("http://{}", address))
+            .await
+            .unwrap();
+        assert_eq!(metadata.instance_id, "i-1234567890abcdef0");
+        assert_eq!(metadata.mac.as_deref(), Some("0e:49:61:0f:c3:11"));
+    }
+
+    #[test]
+    fn parses_aws_identity_document() {
+        let document = br#"{
+            "accountId": "123456789012",
+            "availabilityZone": "us-east-1a",
+            "imageId": "ami-0abcdef1234567890",
+            "instanceId": "i-1234567890abcdef0",
+            "instanceType": "m5.large",
+            "privateIp": "10.0.0.1",
+            "region": "us-east-1"
+        }"#;
+        let metadata = parse_aws(document).unwrap();
+        assert_eq!(metadata.instance_id, "i-1234567890abcdef0");
+        assert_eq!(metadata.instance_type.as_deref(), Some("m5.large"));
+        assert_eq!(metadata.region.as_deref(), Some("us-east-1"));
+        assert_eq!(metadata.zone.as_deref(), Some("us-east-1a"));
+        assert_eq!(metadata.image_id.as_deref(), Some("ami-0abcdef1234567890"));
+        assert_eq!(metadata.private_ip.as_deref(), Some("10.0.0.1"));
+    }
+
+    #[test]
+    fn parses_gcp_instance() {
+        let instance = br#"{
+            "id": 4520031799277581759,
+            "image": "projects/debian-cloud/global/images/debian-10-buster-v20210316",
+            "machineType": "projects/123/machineTypes/e2-medium",
+            "name": "web-1",
+            "networkInterfaces": [{"ip": "10.128.0.2", "mac": "42:01:0a:80:00:02"}],
+            "zone": "projects/123/zones/us-central1-a"
+        }"#;
+        let metadata = parse_gcp(instance).unwrap();
+        assert_eq!(metadata.instance_id, "4520031799277581759");
+        assert_eq!(metadata.instance_type.as_deref(), Some("e2-medium"));
+        assert_eq!(metadata.region.as_deref(), Some("us-central1"));
+        assert_eq!(metadata.zone.as_deref(), Some("us-central1-a"));
+        assert_eq!(metadata.name.as_deref(), Some("web-1"));
+        assert_eq!(
+            metadata.image_id.as_deref(),
+            Some("debian-10-buster-v20210316")
+        );
+        assert_eq!(metadata.private_ip.as_deref(), Some("10.128.0.2"));
+        assert_eq!(metadata.mac.as_deref(), Some("42:01:0a:80:00:02"));
+    }
+
+    #[test]
+    fn parses_azure_compute() {
+        let compute = br#"{
+            "location": "westeurope",
+            "name": "vm-1",
+            "vmId": "02aab8a4-74ef-476e-8182-f6d2ba4166a6",
+            "vmSize": "Standard_D2s_v3",
+            "zone": ""
+        }"#;
+        let metadata = parse_azure(compute).unwrap();
+        assert_eq!(metadata.instance_id, "02aab8a4-74ef-476e-8182-f6d2ba4166a6");
+        assert_eq!(metadata.instance_type.as_deref(), Some("Standard_D2s_v3"));
+        assert_eq!(metadata.region.as_deref(), Some("westeurope"));
+        assert_eq!(metadata.zone, None);
+        let fields: Vec<String> = DEFAULT_FIELDS.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            metadata.to_json(&fields),
+            json!({
+                "provider": "azure",
+                "instance_id": "02aab8a4-74ef-476e-8182-f6d2ba4166a6",
+                "instance_type": "Standard_D2s_v3",
+                "region": "westeurope",
+                "name": "vm-1",
+            })
+        );
+    }
+}
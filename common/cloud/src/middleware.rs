@@ -0,0 +1,132 @@
+use std::sync::RwLock;
+use std::time::Duration;
+
+use http::types::body::LineBufferMut;
+use middleware::{Middleware, Status};
+use serde_json::{Map, Value};
+
+use crate::metadata::{detect, InstanceMetadata};
+
+/// How often the metadata services are queried again to notice identity changes
+const REFRESH_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Key of the line meta the instance metadata is attached under
+const META_KEY: &str = "cloud";
+
+/// Attaches the identity of the cloud instance to the meta of every line
+pub struct CloudMetadata {
+    /// The metadata along with the selected fields of it attached to lines
+    metadata: RwLock<(InstanceMetadata, Value)>,
+    fields: Vec<String>,
+    timeout: Duration,
+}
+
+impl CloudMetadata {
+    pub fn new(metadata: InstanceMetadata, fields: Vec<String>, timeout: Duration) -> Self {
+        let json = metadata.to_json(&fields);
+        CloudMetadata {
+            metadata: RwLock::new((metadata, json)),
+            fields,
+            timeout,
+        }
+    }
+}
+
+impl Middleware for CloudMetadata {
+    fn run(&self) {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                warn!(
+                    "unable to build runtime to refresh instance metadata: {}",
+                    e
+                );
+                return;
+            }
+        };
+        loop {
+            std::thread::sleep(REFRESH_PERIOD);
+            // Keep the last known identity when the metadata service is unavailable
+            if let Some(refreshed) = runtime.block_on(detect(self.timeout)) {
+                let mut metadata = self.metadata.write().unwrap();
+                if metadata.0 != refreshed {
+                    info!("instance metadata changed to {:?}", refreshed);
+                    let json = refreshed.to_json(&self.fields);
+                    *metadata = (refreshed, json);
+                }
+            }
+        }
+    }
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        let cloud = self.metadata.read().unwrap().1.clone();
+        let meta = match line.get_meta() {
+            Some(Value::Object(meta)) => {
+                let mut meta = meta.clone();
+                meta.insert(META_KEY.into(), cloud);
+                meta
+            }
+            // Meta that isn't an object is left as it is rather than overwritten
+            Some(_) => return Status::Ok(line),
+            None => {
+                let mut meta = Map::new();
+                meta.insert(META_KEY.into(), cloud);
+                meta
+            }
+        };
+        if let Err(e) = line.set_meta(Value::Object(meta)) {
+            debug!("unable to attach instance metadata: {:?}", e);
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta};
+    use serde_json::json;
+
+    fn middleware() -> CloudMetadata {
+        CloudMetadata::new(
+            InstanceMetadata {
+                provider: "aws",
+                instance_id: "i-1234567890abcdef0".into(),
+                instance_type: None,
+                region: Some("us-east-1".into()),
+                zone: None,
+                name: None,
+                image_id: None,
+                private_ip: Some("10.0.0.1".into()),
+                mac: None,
+            },
+            vec!["instance_id".into(), "region".into(), "mac".into()],
+            Duration::from_secs(1),
+        )
+    }
+
+    #[test]
+    fn attaches_metadata_to_line_meta() {
+        let expected = json!({"instance_id": "i-1234567890abcdef0", "region": "us-east-1"});
+
+        let mut line = LineBuilder::new().line("abc");
+        match middleware().process(&mut line) {
+            Status::Ok(line) => {
+                assert_eq!(line.get_meta(), Some(&json!({ "cloud": expected })))
+            }
+            Status::Skip => panic!("line skipped"),
+        }
+
+        let mut line = LineBuilder::new().line("abc").meta(json!({"app": "web"}));
+        match middleware().process(&mut line) {
+            Status::Ok(line) => assert_eq!(
+                line.get_meta(),
+                Some(&json!({"app": "web", "cloud": expected}))
+            ),
+            Status::Skip => panic!("line skipped"),
+        }
+    }
+}
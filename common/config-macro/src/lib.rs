@@ -22,10 +22,16 @@ pub fn env_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let mut field_map = HashMap::new();
     let mut default_map = HashMap::new();
     let mut example_map = HashMap::new();
+    // the boolean fields, which are switched on by a bare flag
+    let mut bool_fields = Vec::new();
     // iterate over all field of the struct
     for field in input.fields.iter_mut() {
         // iterate over all the attributes in for the field
         let field_name = field.clone().ident.unwrap();
+        let field_type = field.ty.to_token_stream().to_string().replace(' ', "");
+        if field_type == "Option<bool>" || field_type == "bool" {
+            bool_fields.push(field_name.clone());
+        }
         field.attrs.retain(|attr| {
             // parse the attribute into a Meta::List
             if let Meta::List(list) = attr.parse_meta().unwrap() {
@@ -71,7 +77,12 @@ pub fn env_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
     }
 
     input.to_tokens(&mut new_item);
-    new_item.append_all(generate_env_vars(&name, &field_map, &default_map));
+    new_item.append_all(generate_env_vars(
+        &name,
+        &field_map,
+        &default_map,
+        &bool_fields,
+    ));
     new_item.append_all(generate_tests(
         &name,
         &field_map,
@@ -85,6 +96,7 @@ fn generate_env_vars(
     name: &Ident,
     field_map: &HashMap<Ident, Vec<String>>,
     default_map: &HashMap<Ident, Lit>,
+    bool_fields: &[Ident],
 ) -> TokenStream2 {
     let mut fields = TokenStream2::new();
 
@@ -106,6 +118,25 @@ fn generate_env_vars(
         }
     }
 
+    // the primary env var of every field, sorted so the generated list is stable
+    let mut primary_vars: Vec<&String> = field_map.values().filter_map(|v| v.first()).collect();
+    primary_vars.sort();
+    let mut primary_tokens = TokenStream2::new();
+    for env_var in primary_vars {
+        primary_tokens.append_all(quote!(#env_var,))
+    }
+
+    // the primary env var of every boolean field, sorted the same way
+    let mut bool_vars: Vec<&String> = bool_fields
+        .iter()
+        .filter_map(|field| field_map.get(field).and_then(|v| v.first()))
+        .collect();
+    bool_vars.sort();
+    let mut bool_tokens = TokenStream2::new();
+    for env_var in bool_vars {
+        bool_tokens.append_all(quote!(#env_var,))
+    }
+
     let mut methods = TokenStream2::new();
     for (field, env_vars) in field_map {
         let mut tokens = TokenStream2::new();
@@ -135,6 +166,16 @@ fn generate_env_vars(
 
             #methods
 
+            /// The primary env var of every field
+            pub fn env_vars() -> Vec<&'static str> {
+                vec![#primary_tokens]
+            }
+
+            /// The primary env var of every boolean field
+            pub fn bool_env_vars() -> Vec<&'static str> {
+                vec![#bool_tokens]
+            }
+
         }
 
         fn first_non_empty(envs: &[&str]) -> Option<String> {
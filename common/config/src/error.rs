@@ -12,6 +12,35 @@ pub enum ConfigError {
     Regex(pcre2::Error),
     NotADirectory(fs::cache::DirPathBufError),
     Lookback(fs::tail::ParseLookbackError),
+    RecreatedFiles(fs::recreated::ParseRecreatedFilesError),
+    FieldOverride(middleware::overrides::ParseOverrideError),
+    KeyRoute(middleware::routes::ParseRouteError),
+    PathTemplate(middleware::path_template::ParseTemplateError),
+    SecretSensitivity(middleware::secrets::ParseSensitivityError),
+    Address(std::net::AddrParseError),
+    #[cfg(feature = "elasticsearch_sink")]
+    IndexTemplate(elasticsearch::index::InvalidIndexTemplate),
+    #[cfg(feature = "webhook_sink")]
+    WebhookTemplate(webhook::body::InvalidTemplate),
+    SyslogFacility(String),
+    SocketFraming(String),
+    #[cfg(feature = "cloud_metadata")]
+    CloudMetadataField(String),
+    SidecarPod(&'static str),
+    RetryEncryptionKey(http::cipher::InvalidKey),
+    SpoolWhenFull(String),
+    JournaldMessageHandling(String),
+    JournaldIds(String),
+    Profile(String),
+    Include,
+    LegacyLine(String),
+    AggregatorUrl(String),
+    Proxy(&'static str, sink::InvalidProxy),
+    Tls(sink::InvalidTls),
+    Signer(&'static str, sink::InvalidSigner),
+    #[cfg(feature = "remote_rules")]
+    RemoteConfigKey(remote_config::InvalidKey),
+    Expr(&'static str, middleware::expr::ExprError),
 }
 
 impl Display for ConfigError {
@@ -33,6 +62,68 @@ impl Display for ConfigError {
             ConfigError::Regex(e) => write!(f, "{}", e),
             ConfigError::NotADirectory(e) => write!(f, "{}", e),
             ConfigError::Lookback(e) => write!(f, "{}", e),
+            ConfigError::RecreatedFiles(e) => write!(f, "{}", e),
+            ConfigError::FieldOverride(e) => write!(f, "{}", e),
+            ConfigError::KeyRoute(e) => write!(f, "{}", e),
+            ConfigError::PathTemplate(e) => write!(f, "{}", e),
+            ConfigError::SecretSensitivity(e) => write!(f, "{}", e),
+            ConfigError::Address(e) => write!(f, "{}", e),
+            #[cfg(feature = "elasticsearch_sink")]
+            ConfigError::IndexTemplate(e) => write!(f, "{}", e),
+            #[cfg(feature = "webhook_sink")]
+            ConfigError::WebhookTemplate(e) => write!(f, "invalid webhook template, {}", e),
+            ConfigError::SyslogFacility(facility) => {
+                write!(f, "{} is not a valid syslog facility", facility)
+            }
+            ConfigError::SocketFraming(framing) => {
+                write!(f, "{} is not a valid unix socket framing", framing)
+            }
+            #[cfg(feature = "cloud_metadata")]
+            ConfigError::CloudMetadataField(field) => write!(
+                f,
+                "{} is not a cloud metadata field, use one of {}",
+                field,
+                cloud::metadata::FIELDS.join(",")
+            ),
+            ConfigError::SidecarPod(var) => write!(
+                f,
+                "sidecar mode requires the {} env var, set it through the downward API",
+                var
+            ),
+            ConfigError::RetryEncryptionKey(e) => write!(f, "{}", e),
+            ConfigError::Include => write!(f, "include must be a path or a list of paths"),
+            ConfigError::LegacyLine(line) => write!(f, "{} is not a key = value option", line),
+            ConfigError::Profile(value) => write!(
+                f,
+                "{} is not a valid profile, use low-memory, balanced or high-throughput",
+                value
+            ),
+            ConfigError::SpoolWhenFull(value) => write!(
+                f,
+                "{} is not a valid retry spool policy, use drop-oldest or block-new",
+                value
+            ),
+            ConfigError::JournaldMessageHandling(value) => write!(
+                f,
+                "{} is not a valid journald message handling, use drop, truncate or base64",
+                value
+            ),
+            ConfigError::JournaldIds(value) => write!(
+                f,
+                "{} is not a valid journald id format, use full, short or off",
+                value
+            ),
+            ConfigError::Proxy(field, e) => write!(f, "invalid {}, {}", field, e),
+            ConfigError::Tls(e) => write!(f, "{}", e),
+            ConfigError::Signer(field, e) => write!(f, "invalid {}, {}", field, e),
+            #[cfg(feature = "remote_rules")]
+            ConfigError::RemoteConfigKey(e) => write!(f, "{}", e),
+            ConfigError::Expr(field, e) => write!(f, "invalid {}, {}", field, e),
+            ConfigError::AggregatorUrl(url) => write!(
+                f,
+                "{} is not a valid aggregator url, use http(s)://<host>:<port>",
+                url
+            ),
         }
     }
 }
@@ -78,3 +169,46 @@ impl From<fs::tail::ParseLookbackError> for ConfigError {
         ConfigError::Lookback(e)
     }
 }
+
+impl From<fs::recreated::ParseRecreatedFilesError> for ConfigError {
+    fn from(e: fs::recreated::ParseRecreatedFilesError) -> Self {
+        ConfigError::RecreatedFiles(e)
+    }
+}
+
+impl From<middleware::overrides::ParseOverrideError> for ConfigError {
+    fn from(e: middleware::overrides::ParseOverrideError) -> Self {
+        ConfigError::FieldOverride(e)
+    }
+}
+
+impl From<middleware::routes::ParseRouteError> for ConfigError {
+    fn from(e: middleware::routes::ParseRouteError) -> Self {
+        ConfigError::KeyRoute(e)
+    }
+}
+
+impl From<middleware::path_template::ParseTemplateError> for ConfigError {
+    fn from(e: middleware::path_template::ParseTemplateError) -> Self {
+        ConfigError::PathTemplate(e)
+    }
+}
+
+impl From<middleware::secrets::ParseSensitivityError> for ConfigError {
+    fn from(e: middleware::secrets::ParseSensitivityError) -> Self {
+        ConfigError::SecretSensitivity(e)
+    }
+}
+
+impl From<std::net::AddrParseError> for ConfigError {
+    fn from(e: std::net::AddrParseError) -> Self {
+        ConfigError::Address(e)
+    }
+}
+
+#[cfg(feature = "elasticsearch_sink")]
+impl From<elasticsearch::index::InvalidIndexTemplate> for ConfigError {
+    fn from(e: elasticsearch::index::InvalidIndexTemplate) -> Self {
+        ConfigError::IndexTemplate(e)
+    }
+}
@@ -0,0 +1,151 @@
+use http::types::params::Tags;
+
+use crate::error::ConfigError;
+use crate::raw::{Config as RawConfig, Rules as RawRules};
+
+/// The options of the `key = value` config of the v1 agent, usually `/etc/logdna.conf`, that
+/// have an equivalent in this agent
+#[derive(Debug, Default, PartialEq)]
+pub struct LegacyConfig {
+    pub key: Option<String>,
+    pub hostname: Option<String>,
+    pub logdirs: Vec<String>,
+    pub exclude: Vec<String>,
+    pub exclude_regex: Vec<String>,
+    pub tags: Vec<String>,
+    /// Options that aren't migrated, e.g. `autoupdate`, which only applied to the v1 agent
+    pub ignored: Vec<String>,
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl LegacyConfig {
+    pub fn parse(config: &str) -> Result<Self, ConfigError> {
+        let mut legacy = LegacyConfig::default();
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => return Err(ConfigError::LegacyLine(line.to_string())),
+            };
+            match name {
+                "key" => legacy.key = Some(value.to_string()),
+                "hostname" => legacy.hostname = Some(value.to_string()),
+                "logdir" => legacy.logdirs.extend(list(value)),
+                "exclude" => legacy.exclude.extend(list(value)),
+                "exclude_regex" => legacy.exclude_regex.push(value.to_string()),
+                "tags" => legacy.tags.extend(list(value)),
+                _ => legacy.ignored.push(name.to_string()),
+            }
+        }
+        Ok(legacy)
+    }
+
+    /// The equivalent env vars, as `NAME=value` lines
+    pub fn to_env(&self) -> Vec<String> {
+        let mut vars = Vec::new();
+        let mut push = |name: &str, value: String| vars.push(format!("{}={}", name, value));
+        if let Some(key) = self.key.as_ref() {
+            push("LOGDNA_INGESTION_KEY", key.clone());
+        }
+        if let Some(hostname) = self.hostname.as_ref() {
+            push("LOGDNA_HOSTNAME", hostname.clone());
+        }
+        let lists = [
+            ("LOGDNA_LOG_DIRS", &self.logdirs),
+            ("LOGDNA_EXCLUSION_RULES", &self.exclude),
+            ("LOGDNA_EXCLUSION_REGEX_RULES", &self.exclude_regex),
+            ("LOGDNA_TAGS", &self.tags),
+        ];
+        for (name, values) in lists {
+            if !values.is_empty() {
+                push(name, values.join(","));
+            }
+        }
+        vars
+    }
+
+    /// The equivalent config file, the defaults with the options of the v1 config on top. Like
+    /// `LOGDNA_LOG_DIRS`, the directories are watched on top of `/var/log`, which the v1
+    /// agent also watched.
+    pub fn to_raw(&self) -> RawConfig {
+        let mut raw = RawConfig::default();
+        if self.key.is_some() {
+            raw.http.ingestion_key = self.key.clone();
+        }
+        if let Some(params) = raw.http.params.as_mut() {
+            if let Some(hostname) = self.hostname.as_ref() {
+                params.hostname = hostname.clone();
+            }
+            if !self.tags.is_empty() {
+                params.tags = Some(Tags::from(self.tags.clone()));
+            }
+        }
+        for dir in self.logdirs.iter() {
+            let dir = dir.as_str().into();
+            if !raw.log.dirs.contains(&dir) {
+                raw.log.dirs.push(dir);
+            }
+        }
+        let exclude = raw.log.exclude.get_or_insert(RawRules {
+            glob: Vec::new(),
+            regex: Vec::new(),
+        });
+        exclude.glob.extend(self.exclude.iter().cloned());
+        exclude.regex.extend(self.exclude_regex.iter().cloned());
+        raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_CONFIG: &str = "
+# managed by puppet
+key = sdf79s6df3j4n3sdfs435
+logdir = /var/log/myapp,/opt/app/logs
+exclude = /var/log/myapp/debug.log
+exclude_regex = ^DEBUG
+tags = production, web
+autoupdate = 1
+";
+
+    #[test]
+    fn migrates_v1_config() {
+        let legacy = LegacyConfig::parse(V1_CONFIG).unwrap();
+        assert_eq!(legacy.ignored, vec!["autoupdate".to_string()]);
+        assert_eq!(
+            legacy.to_env(),
+            vec![
+                "LOGDNA_INGESTION_KEY=sdf79s6df3j4n3sdfs435",
+                "LOGDNA_LOG_DIRS=/var/log/myapp,/opt/app/logs",
+                "LOGDNA_EXCLUSION_RULES=/var/log/myapp/debug.log",
+                "LOGDNA_EXCLUSION_REGEX_RULES=^DEBUG",
+                "LOGDNA_TAGS=production,web",
+            ]
+        );
+
+        let raw = legacy.to_raw();
+        assert_eq!(raw.log.dirs.len(), 3);
+        assert!(raw
+            .log
+            .exclude
+            .unwrap()
+            .glob
+            .contains(&"/var/log/myapp/debug.log".to_string()));
+        assert!(matches!(
+            LegacyConfig::parse("logdir /var/log"),
+            Err(ConfigError::LegacyLine(_))
+        ));
+    }
+}
@@ -4,24 +4,45 @@ extern crate log;
 use std::convert::{TryFrom, TryInto};
 use std::fs::File;
 use std::io::Read;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 use sysinfo::{RefreshKind, System, SystemExt};
 
+#[cfg(feature = "archive_sink")]
+use archive::s3::S3Options;
 use async_compression::Level;
 
+use fs::lag::LagThreshold;
+use fs::priority::PriorityRules;
+use fs::recreated::RecreatedFiles;
 use fs::rule::{GlobRule, RegexRule, Rules};
 use fs::tail::{DirPathBuf, Lookback};
+use http::cipher::SpoolKey;
+use http::retry::{RetryPolicy, SpoolFull, SpoolPolicy};
 use http::types::request::{Encoding, RequestTemplate, Schema};
+use journald::field::{FieldHandling, FieldLimits, IdFormat};
+use journald::options::Backfill;
+use k8s::middleware::CacheLimits;
 use k8s::K8sTrackingConf;
+use middleware::expr::Expr;
+use middleware::overrides::OverrideRule;
+use middleware::path_template::PathTemplate;
+use middleware::routes::{KeyRoute, ParseRouteError};
+use middleware::secrets::Sensitivity;
+use receiver::TlsFiles;
 
 use crate::env::Config as EnvConfig;
 use crate::error::ConfigError;
+use crate::profile::Profile;
 use crate::raw::Config as RawConfig;
 
 pub mod env;
 pub mod error;
+pub mod legacy;
+pub mod profile;
 pub mod raw;
+pub mod tags;
 
 // Symbols that will be populated in the main.rs file
 extern "Rust" {
@@ -31,19 +52,82 @@ extern "Rust" {
 
 #[derive(Debug)]
 pub struct Config {
+    pub profile: Profile,
     pub http: HttpConfig,
     pub log: LogConfig,
     pub journald: JournaldConfig,
+    pub auditd: AuditdConfig,
+    pub docker: DockerConfig,
+    pub kubelet: KubeletConfig,
+    pub receiver: ReceiverConfig,
+    #[cfg(feature = "exec_source")]
+    pub exec: ExecConfig,
+    pub kafka: KafkaConfig,
+    #[cfg(feature = "archive_sink")]
+    pub archive: Option<S3Options>,
+    #[cfg(feature = "elasticsearch_sink")]
+    pub elasticsearch: Option<elasticsearch::sink::Options>,
+    #[cfg(feature = "otlp_sink")]
+    pub otlp: Option<otlp::sink::Options>,
+    #[cfg(feature = "webhook_sink")]
+    pub webhook: Option<webhook::sink::Options>,
+    #[cfg(feature = "syslog_sink")]
+    pub syslog: Option<syslog::sink::Options>,
+    #[cfg(feature = "unix_socket_sink")]
+    pub unix_socket: Option<unix_socket::sink::Options>,
+    pub cloud: CloudConfig,
+    #[cfg(feature = "remote_rules")]
+    pub remote_config: Option<remote_config::Options>,
 }
 
 #[derive(Debug)]
 pub struct HttpConfig {
     pub template: RequestTemplate,
+    /// Whether lines are sent to LogDNA, disabled when the agent only feeds other sinks
+    pub enabled: bool,
+    /// Whether lines go through the whole pipeline and are only counted instead of sent
+    pub dry_run: bool,
     pub timeout: Duration,
+    /// How long connections to the ingest API are reused before being reopened
+    pub connection_max_lifetime: Option<Duration>,
+    /// How long a request to the ingest API can take before it's logged as slow
+    pub slow_request_threshold: Option<Duration>,
+    /// The ingestion keys other than the default one, each sent its lines through a pipeline
+    /// of its own
+    pub key_routes: Vec<KeyRoute>,
+    /// Whether the ingestion key and connectivity are checked on startup
+    pub validate: bool,
+    /// Created once the ingest API accepted the validation request
+    pub readiness_file: Option<PathBuf>,
     pub body_size: usize,
+    /// File of tags sent on top of the static ones, read again when it changes
+    pub tags_file: Option<PathBuf>,
+    /// Whether the hostname was detected rather than configured
+    pub hostname_detected: bool,
+    /// The gzip level of the requests and one in how many batches are compressed again to
+    /// measure the compression ratio
+    pub compression_sample: Option<(u32, u32)>,
+    /// Threads the ingest batches are closed and the archive objects compressed on, None for one
+    /// per CPU
+    pub codec_threads: Option<usize>,
+    /// Lines after which a batch is sent, regardless of its size
+    pub batch_max_lines: Option<usize>,
+    /// How long lines are batched for at most before being sent
+    pub batch_max_latency: Duration,
+    /// Whether the lines read together are sent one source after another
+    pub batch_group_by_source: bool,
+    /// Whether failed requests are retried before any later one is sent
+    pub strict_ordering: bool,
+    /// How long a pipeline stage can go without making progress before it's reported stalled
+    pub stall_threshold: Duration,
+
+    pub retry: RetryPolicy,
+    /// Stored requests replayed for each new request delivered
+    pub retry_replay_ratio: u32,
+    /// Encrypts the requests stored on disk to be retried
+    pub retry_encryption_key: Option<SpoolKey>,
 
     // Development only settings
-    pub retry_base_delay: Duration,
     pub retry_step_delay: Duration,
 }
 
@@ -51,18 +135,128 @@ pub struct HttpConfig {
 pub struct LogConfig {
     pub dirs: Vec<DirPathBuf>,
     pub db_path: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub checkpoint_max_pending: usize,
+    /// How long before a restart shipped lines are skipped when read again after it
+    pub restart_dedup_window: Option<Duration>,
     pub rules: Rules,
     pub line_exclusion_regex: Vec<String>,
     pub line_inclusion_regex: Vec<String>,
     pub line_redact_regex: Vec<String>,
+    pub line_exclusion_expr: Option<Expr>,
+    pub line_inclusion_expr: Option<Expr>,
+    pub shadow: Option<ShadowConfig>,
+    pub redact_secrets: Option<Sensitivity>,
+    pub anonymize_ips: bool,
+    pub anonymize_ipv4_prefix: u8,
+    pub anonymize_ipv6_prefix: u8,
+    pub require_access: bool,
+    pub k8s_config_map: Option<String>,
+    pub k8s_cluster_name: Option<String>,
+    pub k8s_audit_path: Option<PathBuf>,
     pub lookback: Lookback,
+    /// What's done with a file created at the path of one deleted moments before
+    pub recreated_files: RecreatedFiles,
+    pub event_coalesce_window: Duration,
+    pub read_limit_bytes_per_sec: u64,
+    /// Unread bytes over which a file that isn't being written to is read through a memory map.
+    /// A mapped file truncated by another process before it's read crashes the agent with SIGBUS.
+    pub mmap_threshold: Option<u64>,
+    /// Unread bytes a file can hold for a while before it's reported as lagging
+    pub lag_threshold: Option<LagThreshold>,
+    pub metrics_top_sources: usize,
+    /// Whether the warnings, errors and metrics of the agent are shipped as the `logdna-agent` app
+    pub self_telemetry: bool,
+    /// One in how many lines are traced through the pipeline
+    pub trace_sample: Option<u64>,
+    pub priority_rules: PriorityRules,
+    /// The app, host and env of the lines of the files or pods the rules match
+    pub field_overrides: Vec<OverrideRule>,
+    /// Layouts of file paths the app, host, env and other fields of the lines are taken from
+    pub path_templates: Vec<PathTemplate>,
     pub use_k8s_enrichment: K8sTrackingConf,
     pub log_k8s_events: K8sTrackingConf,
+    pub k8s_event_dedup_window: Duration,
+    pub k8s_metadata_cache: CacheLimits,
+    pub k8s_metadata_wait: Option<Duration>,
+}
+
+/// Line rules counted in the metrics without being enforced
+#[derive(Debug)]
+pub struct ShadowConfig {
+    pub line_exclusion_regex: Vec<String>,
+    pub line_inclusion_regex: Vec<String>,
+    pub line_redact_regex: Vec<String>,
+    pub line_exclusion_expr: Option<Expr>,
+    pub line_inclusion_expr: Option<Expr>,
 }
 
 #[derive(Debug)]
 pub struct JournaldConfig {
     pub paths: Vec<PathBuf>,
+    /// How far behind the reader can fall before it skips ahead to the latest records
+    pub max_lag: Option<Duration>,
+    /// Lines and bytes a single unit can write per second before its next records are dropped
+    pub unit_lines_per_sec: Option<u64>,
+    pub unit_bytes_per_sec: Option<u64>,
+    pub field_limits: FieldLimits,
+    /// How the boot and machine ids of the records are added to the meta of the lines
+    pub ids: IdFormat,
+    /// The records written before the agent was first started to read, None to only read the
+    /// new ones
+    pub backfill: Option<Backfill>,
+}
+
+#[derive(Debug)]
+pub struct AuditdConfig {
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct DockerConfig {
+    pub socket: Option<PathBuf>,
+    pub label_filters: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct KubeletConfig {
+    pub url: Option<String>,
+    pub insecure_tls: bool,
+}
+
+#[derive(Debug)]
+pub struct ReceiverConfig {
+    pub ingest_address: Option<SocketAddr>,
+    pub ingest_key: Option<String>,
+    pub ingest_tls: Option<TlsFiles>,
+    pub journal_remote_address: Option<SocketAddr>,
+    pub journal_remote_tls: Option<TlsFiles>,
+    pub k8s_audit_address: Option<SocketAddr>,
+    pub k8s_audit_tls: Option<TlsFiles>,
+    pub status_address: Option<SocketAddr>,
+}
+
+#[cfg(feature = "exec_source")]
+#[derive(Debug)]
+pub struct ExecConfig {
+    pub commands: Vec<exec::source::Command>,
+}
+
+#[derive(Debug)]
+pub struct KafkaConfig {
+    pub brokers: Vec<String>,
+    pub topics: Vec<String>,
+    pub group_id: String,
+}
+
+#[derive(Debug)]
+pub struct CloudConfig {
+    /// Whether lines are enriched with the identity of the cloud instance
+    pub metadata: bool,
+    /// How long the instance metadata services are given to answer
+    pub metadata_timeout: Duration,
+    /// Fields of the instance metadata attached to lines
+    pub metadata_fields: Vec<String>,
 }
 
 impl Config {
@@ -86,6 +280,61 @@ impl Config {
         if let Some(ref mut key) = tmp_config.http.ingestion_key {
             *key = "REDACTED".to_string();
         }
+        if let Some(ref mut key) = tmp_config.http.retry_encryption_key {
+            *key = "REDACTED".to_string();
+        }
+        if let Some(ref mut routes) = tmp_config.http.ingestion_key_routes {
+            for route in routes.iter_mut() {
+                *route = route
+                    .split_whitespace()
+                    .map(|part| {
+                        if part.starts_with("key=") {
+                            "key=REDACTED"
+                        } else {
+                            part
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+            }
+        }
+        if let Some(ref mut key) = tmp_config.receiver.ingest_key {
+            *key = "REDACTED".to_string();
+        }
+        if let Some(ref mut password) = tmp_config.elasticsearch.password {
+            *password = "REDACTED".to_string();
+        }
+        let proxies = [
+            &mut tmp_config.elasticsearch.proxy,
+            &mut tmp_config.otlp.proxy,
+            &mut tmp_config.webhook.proxy,
+            &mut tmp_config.remote_config.proxy,
+        ];
+        for proxy in proxies {
+            if let Some(proxy) = proxy.as_mut().filter(|proxy| proxy.contains('@')) {
+                *proxy = "REDACTED".to_string();
+            }
+        }
+        let signers = [
+            &mut tmp_config.elasticsearch.signer,
+            &mut tmp_config.otlp.signer,
+            &mut tmp_config.webhook.signer,
+        ];
+        for signer in signers {
+            if let Some(signer) = signer.as_mut().filter(|s| s.starts_with("hmac")) {
+                *signer = "REDACTED".to_string();
+            }
+        }
+        if let Some(ref mut headers) = tmp_config.otlp.headers {
+            headers
+                .values_mut()
+                .for_each(|v| *v = "REDACTED".to_string());
+        }
+        if let Some(ref mut headers) = tmp_config.webhook.headers {
+            headers
+                .values_mut()
+                .for_each(|v| *v = "REDACTED".to_string());
+        }
         if let Ok(yaml) = serde_yaml::to_string(&tmp_config) {
             info!("current config: \n{}", yaml)
         }
@@ -97,20 +346,43 @@ impl Config {
 impl TryFrom<RawConfig> for Config {
     type Error = ConfigError;
 
-    fn try_from(raw: RawConfig) -> Result<Self, Self::Error> {
+    fn try_from(mut raw: RawConfig) -> Result<Self, Self::Error> {
+        let profile = match raw.profile.take().filter(|p| !p.trim().is_empty()) {
+            Some(profile) => Profile::parse(&profile).ok_or(ConfigError::Profile(profile))?,
+            None => Profile::Balanced,
+        };
+        profile.apply(&mut raw);
+
         let mut template_builder = RequestTemplate::builder();
 
-        template_builder.api_key(
-            raw.http
-                .ingestion_key
-                .filter(|s| !s.is_empty())
-                .ok_or_else(|| {
-                    ConfigError::MissingFieldOrEnvVar(
-                        "http.ingestion_key",
-                        EnvConfig::ingestion_key_vars(),
-                    )
-                })?,
-        );
+        let enabled = raw.http.ingestion_enabled.unwrap_or(true);
+        let dry_run = raw.http.dry_run.unwrap_or(false);
+        let ingestion_key = raw.http.ingestion_key.filter(|s| !s.is_empty());
+        template_builder.api_key(match ingestion_key {
+            Some(key) => key,
+            // The key is only needed when lines are actually sent to LogDNA
+            None if !enabled || dry_run => String::new(),
+            None => {
+                return Err(ConfigError::MissingFieldOrEnvVar(
+                    "http.ingestion_key",
+                    EnvConfig::ingestion_key_vars(),
+                ))
+            }
+        });
+
+        // Edge agents send their lines to the ingest listener of the aggregating agent
+        if let Some(url) = raw
+            .http
+            .aggregator_url
+            .take()
+            .filter(|u| !u.trim().is_empty())
+        {
+            let (use_ssl, host) = parse_aggregator_url(&url)?;
+            info!("forwarding lines to the aggregating agent at {}", url);
+            raw.http.use_ssl = Some(use_ssl);
+            raw.http.host = Some(host);
+            raw.http.endpoint = Some("/logs/agent".to_string());
+        }
 
         let use_ssl = raw.http.use_ssl.ok_or_else(|| {
             ConfigError::MissingFieldOrEnvVar("http.use_ssl", EnvConfig::use_ssl_vars())
@@ -147,11 +419,16 @@ impl TryFrom<RawConfig> for Config {
             || ConfigError::MissingFieldOrEnvVar("http.endpoint", EnvConfig::endpoint_vars()),
         )?);
 
-        template_builder.params(
-            raw.http
-                .params
-                .ok_or(ConfigError::MissingField("http.params"))?,
-        );
+        let mut params = raw
+            .http
+            .params
+            .ok_or(ConfigError::MissingField("http.params"))?;
+        let hostname_detected =
+            get_hostname().map_or(false, |detected| detected.trim() == params.hostname.trim());
+        if raw.http.strip_hostname_domain.unwrap_or(false) {
+            params.hostname = strip_domain(&params.hostname);
+        }
+        template_builder.params(params);
 
         let sys = System::new_with_specifics(RefreshKind::new());
         let info = str::replace(
@@ -174,32 +451,129 @@ impl TryFrom<RawConfig> for Config {
 
         template_builder.user_agent(format!("{}/{} ({})", pkg_name, pkg_version, info).as_str());
 
+        let retry_base_delay =
+            Duration::from_millis(raw.http.retry_base_delay_ms.unwrap_or(15_000) as u64);
         let http = HttpConfig {
             template: template_builder.build()?,
+            enabled,
+            dry_run,
             timeout: Duration::from_millis(
                 raw.http
                     .timeout
                     .ok_or(ConfigError::MissingField("http.timeout"))?,
             ),
+            connection_max_lifetime: match raw.http.connection_max_lifetime_secs {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => Some(Duration::from_secs(300)),
+            },
+            slow_request_threshold: raw
+                .http
+                .slow_request_threshold_ms
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+            key_routes: key_routes(raw.http.ingestion_key_routes.unwrap_or_default())?,
+            validate: raw.http.validate.unwrap_or(true),
+            readiness_file: raw.http.readiness_file,
             body_size: raw
                 .http
                 .body_size
                 .ok_or(ConfigError::MissingField("http.body_size"))?,
-            retry_base_delay: Duration::from_millis(
-                raw.http.retry_base_delay_ms.unwrap_or(15_000) as u64
-            ),
+            tags_file: raw.http.tags_file,
+            hostname_detected,
+            compression_sample: raw
+                .http
+                .compression_sample_rate
+                .filter(|every| use_compression && *every > 0)
+                .map(|every| (gzip_level, every)),
+            codec_threads: raw.http.codec_threads.filter(|threads| *threads > 0),
+            batch_max_lines: raw.http.batch_max_lines.filter(|lines| *lines > 0),
+            batch_max_latency: Duration::from_millis(raw.http.batch_max_latency_ms.unwrap_or(250)),
+            batch_group_by_source: raw.http.batch_group_by_source.unwrap_or(false),
+            strict_ordering: raw.http.strict_ordering.unwrap_or(false),
+            stall_threshold: Duration::from_millis(raw.http.stall_threshold_ms.unwrap_or(120_000)),
+            retry: RetryPolicy {
+                base_delay: retry_base_delay,
+                // Without a max delay every retry waits for the base delay
+                max_delay: raw
+                    .http
+                    .retry_max_delay_ms
+                    .map(|ms| Duration::from_millis(ms as u64))
+                    .unwrap_or(retry_base_delay),
+                jitter: raw.http.retry_jitter_percent.unwrap_or(0).min(100) as f64 / 100.0,
+                max_attempts: raw.http.retry_max_attempts.filter(|a| *a > 0),
+                budget: raw
+                    .http
+                    .retry_budget_percent
+                    .map(|percent| percent as f64 / 100.0),
+                spool: SpoolPolicy {
+                    max_bytes: raw.http.retry_spool_max_bytes.filter(|bytes| *bytes > 0),
+                    max_age: raw
+                        .http
+                        .retry_spool_max_age_secs
+                        .filter(|secs| *secs > 0)
+                        .map(Duration::from_secs),
+                    when_full: match raw.http.retry_spool_when_full {
+                        Some(when_full) => SpoolFull::parse(&when_full)
+                            .ok_or(ConfigError::SpoolWhenFull(when_full))?,
+                        None => SpoolFull::DropOldest,
+                    },
+                },
+            },
+            retry_replay_ratio: raw.http.retry_replay_ratio.unwrap_or(1),
+            retry_encryption_key: match (
+                raw.http.retry_encryption_key,
+                raw.http.retry_encryption_key_file,
+            ) {
+                (Some(key), _) => Some(key),
+                (None, Some(path)) => Some(std::fs::read_to_string(&path).map_err(|e| {
+                    std::io::Error::new(e.kind(), format!("unable to read {:?}: {}", path, e))
+                })?),
+                (None, None) => None,
+            }
+            .map(|key| SpoolKey::from_base64(&key))
+            .transpose()
+            .map_err(ConfigError::RetryEncryptionKey)?,
             retry_step_delay: Duration::from_millis(
                 raw.http.retry_step_delay_ms.unwrap_or(3_000) as u64
             ),
         };
 
+        if raw.log.k8s_sidecar.unwrap_or(false) {
+            let (includes, dir) = sidecar_rules()?;
+            raw.log.dirs = vec![dir];
+            raw.log.include = Some(raw::Rules {
+                glob: includes,
+                regex: Vec::new(),
+            });
+        }
+
+        // The audit log is tailed along with the other files, parsed by its own middleware
+        if let Some(path) = raw.log.k8s_audit_path.as_ref() {
+            if let Some(dir) = path.parent() {
+                if !raw.log.dirs.iter().any(|d| dir.starts_with(d)) {
+                    raw.log.dirs.push(dir.to_path_buf());
+                }
+            }
+            if let Some(include) = raw.log.include.as_mut() {
+                include.glob.push(path.to_string_lossy().into_owned());
+            }
+        }
+
         let mut log = LogConfig {
             dirs: raw
                 .log
                 .dirs
                 .into_iter()
-                // Filter off paths that are not directories and warn about them
+                // Filter off paths that are not directories and warn about them, the ones that
+                // don't exist yet are tailed once created
                 .filter_map(|d| {
+                    if !d.exists() {
+                        info!("{:?} doesn't exist yet, it will be tailed once created", d);
+                        return DirPathBuf::not_yet_created(d)
+                            .map_err(|e| warn!("{}", e))
+                            .ok();
+                    }
                     d.try_into()
                         .map_err(|e| {
                             warn!("{}", e);
@@ -208,15 +582,96 @@ impl TryFrom<RawConfig> for Config {
                 })
                 .collect(),
             db_path: raw.log.db_path,
+            checkpoint_interval: Duration::from_millis(
+                raw.log.checkpoint_interval_ms.unwrap_or(1_000),
+            ),
+            checkpoint_max_pending: raw.log.checkpoint_max_pending.unwrap_or(1_000),
+            restart_dedup_window: raw
+                .log
+                .restart_dedup_window_ms
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
             rules: Rules::new(),
             line_exclusion_regex: raw.log.line_exclusion_regex.unwrap_or_default(),
             line_inclusion_regex: raw.log.line_inclusion_regex.unwrap_or_default(),
             line_redact_regex: raw.log.line_redact_regex.unwrap_or_default(),
+            line_exclusion_expr: parse_expr(raw.log.line_exclusion_expr, "line_exclusion_expr")?,
+            line_inclusion_expr: parse_expr(raw.log.line_inclusion_expr, "line_inclusion_expr")?,
+            shadow: match raw.log.shadow {
+                Some(shadow) => Some(ShadowConfig {
+                    line_exclusion_regex: shadow.line_exclusion_regex.unwrap_or_default(),
+                    line_inclusion_regex: shadow.line_inclusion_regex.unwrap_or_default(),
+                    line_redact_regex: shadow.line_redact_regex.unwrap_or_default(),
+                    line_exclusion_expr: parse_expr(
+                        shadow.line_exclusion_expr,
+                        "shadow line_exclusion_expr",
+                    )?,
+                    line_inclusion_expr: parse_expr(
+                        shadow.line_inclusion_expr,
+                        "shadow line_inclusion_expr",
+                    )?,
+                }),
+                None => None,
+            },
+            redact_secrets: match raw.log.redact_secrets.as_deref().map(str::trim) {
+                None | Some("") | Some("off") => None,
+                Some(sensitivity) => Some(sensitivity.parse::<Sensitivity>()?),
+            },
+            anonymize_ips: raw.log.anonymize_ips.unwrap_or(false),
+            anonymize_ipv4_prefix: raw.log.anonymize_ipv4_prefix.unwrap_or(24),
+            anonymize_ipv6_prefix: raw.log.anonymize_ipv6_prefix.unwrap_or(48),
+            require_access: raw.log.require_access.unwrap_or(false),
+            k8s_config_map: raw
+                .log
+                .k8s_config_map
+                .filter(|name| !name.trim().is_empty()),
+            k8s_cluster_name: raw
+                .log
+                .k8s_cluster_name
+                .filter(|name| !name.trim().is_empty()),
+            k8s_audit_path: raw.log.k8s_audit_path.clone(),
             lookback: raw
                 .log
                 .lookback
                 .map(|s| s.parse::<Lookback>())
                 .unwrap_or_else(|| Ok(Lookback::default()))?,
+            recreated_files: raw
+                .log
+                .recreated_files
+                .map(|s| s.parse::<RecreatedFiles>())
+                .unwrap_or_else(|| Ok(RecreatedFiles::default()))?,
+            event_coalesce_window: Duration::from_millis(
+                raw.log.event_coalesce_window_ms.unwrap_or(10),
+            ),
+            read_limit_bytes_per_sec: raw.log.read_limit_bytes_per_sec.unwrap_or(0),
+            mmap_threshold: raw.log.mmap_threshold_bytes.filter(|bytes| *bytes != 0),
+            lag_threshold: match raw.log.lag_threshold_bytes {
+                None | Some(0) => None,
+                Some(bytes) => Some(LagThreshold {
+                    bytes,
+                    duration: Duration::from_secs(raw.log.lag_threshold_secs.unwrap_or(60)),
+                }),
+            },
+            metrics_top_sources: raw.log.metrics_top_sources.unwrap_or(10),
+            self_telemetry: raw.log.self_telemetry.unwrap_or(false),
+            trace_sample: raw.log.trace_sample.filter(|n| *n > 0),
+            priority_rules: PriorityRules::new(raw.log.priority_weight.unwrap_or(4)),
+            field_overrides: raw
+                .log
+                .field_overrides
+                .unwrap_or_default()
+                .iter()
+                .filter(|rule| !rule.trim().is_empty())
+                .map(|rule| OverrideRule::parse(rule))
+                .collect::<Result<_, _>>()?,
+            path_templates: raw
+                .log
+                .path_templates
+                .unwrap_or_default()
+                .iter()
+                .filter(|template| !template.trim().is_empty())
+                .map(|template| PathTemplate::parse(template.trim()))
+                .collect::<Result<_, _>>()?,
             use_k8s_enrichment: parse_k8s_tracking_or_warn(
                 raw.log.use_k8s_enrichment,
                 "LOGDNA_USE_K8S_LOG_ENRICHMENT",
@@ -227,6 +682,22 @@ impl TryFrom<RawConfig> for Config {
                 "LOGDNA_LOG_K8S_EVENTS",
                 K8sTrackingConf::Never,
             ),
+            k8s_event_dedup_window: Duration::from_millis(
+                raw.log.k8s_event_dedup_window_ms.unwrap_or(60_000),
+            ),
+            // 0 lifts either bound
+            k8s_metadata_cache: CacheLimits {
+                max_entries: Some(raw.log.k8s_metadata_cache_size.unwrap_or(10_000))
+                    .filter(|size| *size > 0),
+                ttl: raw
+                    .log
+                    .k8s_metadata_cache_ttl_ms
+                    .filter(|ms| *ms > 0)
+                    .map(Duration::from_millis),
+            },
+            k8s_metadata_wait: Some(raw.log.k8s_metadata_wait_ms.unwrap_or(1_000))
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
         };
 
         if log.use_k8s_enrichment == K8sTrackingConf::Never
@@ -240,6 +711,12 @@ impl TryFrom<RawConfig> for Config {
             );
         }
 
+        if let Some(paths) = raw.log.priority_paths {
+            for glob in paths {
+                log.priority_rules.add_high(GlobRule::new(&*glob)?)
+            }
+        }
+
         if let Some(rules) = raw.log.include {
             for glob in rules.glob {
                 log.rules.add_inclusion(GlobRule::new(&*glob)?)
@@ -262,40 +739,598 @@ impl TryFrom<RawConfig> for Config {
 
         let journald = JournaldConfig {
             paths: raw.journald.paths.unwrap_or_default().into_iter().collect(),
+            max_lag: match raw.journald.max_lag_secs {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => Some(Duration::from_secs(30)),
+            },
+            unit_lines_per_sec: raw.journald.unit_lines_per_sec.filter(|lines| *lines > 0),
+            unit_bytes_per_sec: raw.journald.unit_bytes_per_sec.filter(|bytes| *bytes > 0),
+            field_limits: FieldLimits {
+                max_size: raw.journald.max_message_size.filter(|size| *size > 0),
+                handling: match raw.journald.message_handling {
+                    Some(handling) => FieldHandling::parse(&handling)
+                        .ok_or(ConfigError::JournaldMessageHandling(handling))?,
+                    None => FieldHandling::default(),
+                },
+            },
+            ids: match raw.journald.ids {
+                Some(ids) => IdFormat::parse(&ids).ok_or(ConfigError::JournaldIds(ids))?,
+                None => IdFormat::default(),
+            },
+            backfill: match raw.journald.backfill_hours {
+                None | Some(0) => None,
+                Some(hours) => Some(Backfill {
+                    window: Duration::from_secs(hours * 60 * 60),
+                    max_bytes: match raw.journald.backfill_max_bytes {
+                        Some(0) => None,
+                        Some(bytes) => Some(bytes),
+                        None => Some(100 * 1024 * 1024),
+                    },
+                }),
+            },
+        };
+
+        let auditd = AuditdConfig {
+            paths: raw.auditd.paths.unwrap_or_default(),
+        };
+
+        let docker = DockerConfig {
+            socket: raw.docker.socket,
+            label_filters: raw.docker.label_filters.unwrap_or_default(),
+        };
+
+        let kubelet = KubeletConfig {
+            url: raw.kubelet.url.filter(|url| !url.trim().is_empty()),
+            insecure_tls: raw.kubelet.insecure_tls.unwrap_or(false),
+        };
+
+        if raw.receiver.aggregator.unwrap_or(false) {
+            raw.receiver
+                .ingest_address
+                .get_or_insert_with(|| "0.0.0.0:5100".to_string());
+            // Any client that can reach the listener could send lines with the agent's key
+            if raw
+                .receiver
+                .ingest_key
+                .as_ref()
+                .map_or(true, |k| k.is_empty())
+            {
+                return Err(ConfigError::MissingField("receiver.ingest_key"));
+            }
+        }
+
+        let receiver = ReceiverConfig {
+            ingest_address: raw
+                .receiver
+                .ingest_address
+                .map(|a| a.parse::<SocketAddr>())
+                .transpose()?,
+            ingest_key: raw.receiver.ingest_key.filter(|k| !k.is_empty()),
+            ingest_tls: tls_files(
+                raw.receiver.ingest_tls_cert,
+                raw.receiver.ingest_tls_key,
+                ("receiver.ingest_tls_cert", "receiver.ingest_tls_key"),
+            )?,
+            journal_remote_address: raw
+                .receiver
+                .journal_remote_address
+                .map(|a| a.parse::<SocketAddr>())
+                .transpose()?,
+            journal_remote_tls: match (
+                tls_files(
+                    raw.receiver.journal_remote_tls_cert,
+                    raw.receiver.journal_remote_tls_key,
+                    (
+                        "receiver.journal_remote_tls_cert",
+                        "receiver.journal_remote_tls_key",
+                    ),
+                )?,
+                raw.receiver.journal_remote_tls_ca,
+            ) {
+                (Some(tls), client_ca) => Some(TlsFiles { client_ca, ..tls }),
+                (None, Some(_)) => {
+                    return Err(ConfigError::MissingField(
+                        "receiver.journal_remote_tls_cert",
+                    ))
+                }
+                (None, None) => None,
+            },
+            k8s_audit_address: raw
+                .receiver
+                .k8s_audit_address
+                .map(|a| a.parse::<SocketAddr>())
+                .transpose()?,
+            k8s_audit_tls: tls_files(
+                raw.receiver.k8s_audit_tls_cert,
+                raw.receiver.k8s_audit_tls_key,
+                ("receiver.k8s_audit_tls_cert", "receiver.k8s_audit_tls_key"),
+            )?,
+            status_address: raw
+                .receiver
+                .status_address
+                .map(|a| a.parse::<SocketAddr>())
+                .transpose()?,
+        };
+
+        #[cfg(feature = "exec_source")]
+        let exec = ExecConfig {
+            commands: raw
+                .exec
+                .commands
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|c| !c.command.trim().is_empty())
+                .map(|c| {
+                    let mut command = exec::source::Command::new(c.command);
+                    command.interval = c
+                        .interval_secs
+                        .filter(|secs| *secs > 0)
+                        .map(Duration::from_secs);
+                    if let Some(app) = c.app {
+                        command.app = app;
+                    }
+                    command
+                })
+                .collect(),
+        };
+        #[cfg(not(feature = "exec_source"))]
+        if raw.exec.commands.map_or(false, |c| !c.is_empty()) {
+            warn!("exec commands are configured, but the exec_source feature is disabled");
+        }
+
+        let kafka = KafkaConfig {
+            brokers: raw.kafka.brokers.unwrap_or_default(),
+            topics: raw.kafka.topics.unwrap_or_default(),
+            group_id: raw
+                .kafka
+                .group_id
+                .unwrap_or_else(|| "logdna-agent".to_string()),
+        };
+
+        let cloud = CloudConfig {
+            // Only on by default when the agent can detect the instance
+            metadata: raw
+                .cloud
+                .metadata
+                .unwrap_or(cfg!(feature = "cloud_metadata")),
+            metadata_timeout: Duration::from_millis(raw.cloud.metadata_timeout_ms.unwrap_or(1_000)),
+            #[cfg(feature = "cloud_metadata")]
+            metadata_fields: match raw.cloud.metadata_fields {
+                Some(fields) => {
+                    if let Some(field) = fields
+                        .iter()
+                        .find(|f| !cloud::metadata::FIELDS.contains(&f.as_str()))
+                    {
+                        return Err(ConfigError::CloudMetadataField(field.clone()));
+                    }
+                    fields
+                }
+                None => cloud::metadata::DEFAULT_FIELDS
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+            },
+            #[cfg(not(feature = "cloud_metadata"))]
+            metadata_fields: raw.cloud.metadata_fields.unwrap_or_default(),
+        };
+
+        #[cfg(feature = "archive_sink")]
+        let archive = match raw.archive.s3_bucket {
+            Some(bucket) => Some(S3Options {
+                bucket,
+                prefix: raw.archive.s3_prefix.unwrap_or_default(),
+                region: raw
+                    .archive
+                    .s3_region
+                    .unwrap_or_else(|| "us-east-1".to_string()),
+                endpoint: raw.archive.s3_endpoint,
+                policy: sink_policy(raw.archive.buffer_size, raw.archive.max_attempts),
+                flush_interval: Duration::from_secs(
+                    raw.archive.flush_interval.unwrap_or(60).max(1),
+                ),
+            }),
+            None => None,
+        };
+        #[cfg(not(feature = "archive_sink"))]
+        if raw.archive.s3_bucket.is_some() {
+            warn!("an s3 archive is configured, but the archive_sink feature is disabled");
+        }
+
+        // Only read by the sinks connecting over the network that are compiled in
+        #[cfg(any(
+            feature = "elasticsearch_sink",
+            feature = "otlp_sink",
+            feature = "remote_rules",
+            feature = "syslog_sink",
+            feature = "webhook_sink"
+        ))]
+        let tls_options = sink::TlsOptions::new(
+            match raw.tls.min_version.as_deref() {
+                Some(version) => sink::TlsVersion::parse(version).map_err(ConfigError::Tls)?,
+                None => sink::TlsVersion::Tls12,
+            },
+            &raw.tls.cipher_suites.unwrap_or_default(),
+            raw.tls.insecure_skip_verify.unwrap_or(false),
+        )
+        .map_err(ConfigError::Tls)?
+        .with_pinned_certs(&raw.tls.pinned_certs.unwrap_or_default())
+        .map_err(ConfigError::Tls)?;
+
+        #[cfg(any(
+            feature = "elasticsearch_sink",
+            feature = "otlp_sink",
+            feature = "remote_rules",
+            feature = "webhook_sink"
+        ))]
+        let pool = {
+            let default = sink::PoolOptions::default();
+            sink::PoolOptions {
+                idle_timeout: raw
+                    .pool
+                    .idle_timeout_ms
+                    .map_or(default.idle_timeout, Duration::from_millis),
+                max_idle_per_host: raw
+                    .pool
+                    .max_idle_per_host
+                    .unwrap_or(default.max_idle_per_host),
+                connect_timeout: raw
+                    .pool
+                    .connect_timeout_ms
+                    .filter(|ms| *ms > 0)
+                    .map(Duration::from_millis),
+                tcp_keepalive: raw
+                    .pool
+                    .tcp_keepalive_ms
+                    .filter(|ms| *ms > 0)
+                    .map(Duration::from_millis),
+            }
+        };
+
+        #[cfg(feature = "remote_rules")]
+        let remote_config = match raw.remote_config.url.filter(|u| !u.is_empty()) {
+            Some(url) => {
+                // Unsigned documents could change the rules of the whole fleet
+                let public_key = raw
+                    .remote_config
+                    .public_key
+                    .ok_or(ConfigError::MissingField("remote_config.public_key"))?;
+                let interval_secs = raw.remote_config.interval_secs.filter(|s| *s > 0);
+                Some(remote_config::Options {
+                    url,
+                    public_key: remote_config::PublicKey::from_base64(&public_key)
+                        .map_err(ConfigError::RemoteConfigKey)?,
+                    interval: Duration::from_secs(interval_secs.unwrap_or(60)),
+                    proxy: sink_proxy(raw.remote_config.proxy, "remote_config.proxy")?,
+                    tls_options: tls_options.clone(),
+                    pool,
+                })
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "remote_rules"))]
+        if raw.remote_config.url.map_or(false, |u| !u.is_empty()) {
+            warn!("a remote config url is configured, but the remote_rules feature is disabled");
+        }
+
+        #[cfg(feature = "elasticsearch_sink")]
+        let elasticsearch = match raw.elasticsearch.url.filter(|u| !u.is_empty()) {
+            Some(url) => Some(elasticsearch::sink::Options {
+                url,
+                index: elasticsearch::index::IndexTemplate::parse(
+                    raw.elasticsearch
+                        .index
+                        .as_deref()
+                        .unwrap_or("logdna-%Y.%m.%d"),
+                )?,
+                username: raw.elasticsearch.username,
+                password: raw.elasticsearch.password,
+                proxy: sink_proxy(raw.elasticsearch.proxy, "elasticsearch.proxy")?,
+                tls_options: tls_options.clone(),
+                pool,
+                signer: sink_signer(raw.elasticsearch.signer, "elasticsearch.signer")?,
+                policy: sink_policy(
+                    raw.elasticsearch.buffer_size,
+                    raw.elasticsearch.max_attempts,
+                ),
+            }),
+            None => None,
+        };
+        #[cfg(not(feature = "elasticsearch_sink"))]
+        if raw.elasticsearch.url.map_or(false, |u| !u.is_empty()) {
+            warn!("elasticsearch is configured, but the elasticsearch_sink feature is disabled");
+        }
+
+        #[cfg(feature = "otlp_sink")]
+        let otlp = match raw.otlp.endpoint.filter(|e| !e.is_empty()) {
+            Some(endpoint) => Some(otlp::sink::Options {
+                endpoint,
+                headers: raw.otlp.headers.unwrap_or_default().into_iter().collect(),
+                proxy: sink_proxy(raw.otlp.proxy, "otlp.proxy")?,
+                tls_options: tls_options.clone(),
+                pool,
+                signer: sink_signer(raw.otlp.signer, "otlp.signer")?,
+                policy: sink_policy(raw.otlp.buffer_size, raw.otlp.max_attempts),
+            }),
+            None => None,
+        };
+        #[cfg(not(feature = "otlp_sink"))]
+        if raw.otlp.endpoint.map_or(false, |e| !e.is_empty()) {
+            warn!("an otlp endpoint is configured, but the otlp_sink feature is disabled");
+        }
+
+        #[cfg(feature = "webhook_sink")]
+        let webhook = match raw.webhook.url.filter(|u| !u.is_empty()) {
+            Some(url) => Some(webhook::sink::Options {
+                url,
+                template: webhook::body::BodyTemplate::parse(
+                    raw.webhook.envelope.as_deref(),
+                    &raw.webhook.fields.unwrap_or_default(),
+                )
+                .map_err(ConfigError::WebhookTemplate)?,
+                headers: raw
+                    .webhook
+                    .headers
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                proxy: sink_proxy(raw.webhook.proxy, "webhook.proxy")?,
+                tls_options: tls_options.clone(),
+                pool,
+                signer: sink_signer(raw.webhook.signer, "webhook.signer")?,
+                policy: sink_policy(raw.webhook.buffer_size, raw.webhook.max_attempts),
+            }),
+            None => None,
+        };
+        #[cfg(not(feature = "webhook_sink"))]
+        if raw.webhook.url.map_or(false, |u| !u.is_empty()) {
+            warn!("a webhook url is configured, but the webhook_sink feature is disabled");
+        }
+
+        #[cfg(feature = "syslog_sink")]
+        let syslog = match raw.syslog.address.filter(|a| !a.is_empty()) {
+            Some(address) => {
+                let facility = match raw.syslog.facility {
+                    Some(facility) => syslog::parse_facility(&facility)
+                        .ok_or(ConfigError::SyslogFacility(facility))?,
+                    // user-level messages
+                    None => 1,
+                };
+                Some(syslog::sink::Options {
+                    address,
+                    tls: raw.syslog.tls.unwrap_or(false),
+                    ca_cert: raw.syslog.ca_cert,
+                    tls_options,
+                    facility,
+                    policy: sink_policy(raw.syslog.buffer_size, raw.syslog.max_attempts),
+                })
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "syslog_sink"))]
+        if raw.syslog.address.map_or(false, |a| !a.is_empty()) {
+            warn!("a syslog address is configured, but the syslog_sink feature is disabled");
+        }
+
+        #[cfg(feature = "unix_socket_sink")]
+        let unix_socket = match raw.unix_socket.path {
+            Some(path) => Some(unix_socket::sink::Options {
+                path,
+                framing: match raw.unix_socket.framing {
+                    Some(framing) => unix_socket::Framing::parse(&framing)
+                        .ok_or(ConfigError::SocketFraming(framing))?,
+                    None => unix_socket::Framing::Ndjson,
+                },
+                policy: sink_policy(raw.unix_socket.buffer_size, raw.unix_socket.max_attempts),
+            }),
+            None => None,
         };
+        #[cfg(not(feature = "unix_socket_sink"))]
+        if raw.unix_socket.path.is_some() {
+            warn!("a unix socket path is configured, but the unix_socket_sink feature is disabled");
+        }
 
         Ok(Config {
+            profile,
             http,
             log,
             journald,
+            auditd,
+            docker,
+            kubelet,
+            receiver,
+            #[cfg(feature = "exec_source")]
+            exec,
+            kafka,
+            #[cfg(feature = "archive_sink")]
+            archive,
+            #[cfg(feature = "elasticsearch_sink")]
+            elasticsearch,
+            #[cfg(feature = "otlp_sink")]
+            otlp,
+            #[cfg(feature = "webhook_sink")]
+            webhook,
+            #[cfg(feature = "syslog_sink")]
+            syslog,
+            #[cfg(feature = "unix_socket_sink")]
+            unix_socket,
+            cloud,
+            #[cfg(feature = "remote_rules")]
+            remote_config,
         })
     }
 }
 
-pub fn get_hostname() -> Option<String> {
-    let path = PathBuf::from("/etc/logdna-hostname");
-    if path.exists() {
-        if let Ok(s) = File::open(&path).and_then(|mut f| {
-            let mut s = String::new();
-            f.read_to_string(&mut s).map(|_| s)
-        }) {
-            return Some(s);
+/// The directory and inclusion rules that restrict tailing to the containers of the pod the
+/// agent runs in, the kubelet names their logs `<pod>_<namespace>_<container>-<id>.log` and
+/// links them to files under /var/log/pods/<namespace>_<pod>_<uid>/, which must be included
+/// too for the links to be followed
+fn sidecar_rules() -> Result<(Vec<String>, PathBuf), ConfigError> {
+    let var = |name: &'static str| {
+        std::env::var(name)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .ok_or(ConfigError::SidecarPod(name))
+    };
+    let pod = var("POD_NAME")?;
+    // The manifests set NAMESPACE, like the agent uses for its own namespace elsewhere
+    let namespace = var("POD_NAMESPACE")
+        .or_else(|_| var("NAMESPACE"))
+        .map_err(|_| ConfigError::SidecarPod("POD_NAMESPACE"))?;
+    info!(
+        "sidecar mode, only tailing the containers of pod {}/{}",
+        namespace, pod
+    );
+    Ok((
+        vec![
+            format!("/var/log/containers/{}_{}_*.log", pod, namespace),
+            format!("/var/log/pods/{}_{}_*/**/*.log", namespace, pod),
+        ],
+        PathBuf::from("/var/log/containers/"),
+    ))
+}
+
+/// The certificate and key a receiver is served with over TLS, both or neither must be set
+fn tls_files(
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    (cert_field, key_field): (&'static str, &'static str),
+) -> Result<Option<TlsFiles>, ConfigError> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => Ok(Some(TlsFiles {
+            cert,
+            key,
+            client_ca: None,
+        })),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err(ConfigError::MissingField(key_field)),
+        (None, Some(_)) => Err(ConfigError::MissingField(cert_field)),
+    }
+}
+
+/// The scheme and `host:port` of the ingest listener of an aggregating agent
+fn parse_aggregator_url(url: &str) -> Result<(bool, String), ConfigError> {
+    let url = url.trim();
+    let (use_ssl, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(ConfigError::AggregatorUrl(url.to_string()));
+    };
+    let host = rest.trim_end_matches('/');
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok((use_ssl, host.to_string()))
+        }
+        _ => Err(ConfigError::AggregatorUrl(url.to_string())),
+    }
+}
+
+/// Overrides the default buffering and retries of a sink with the configured ones
+fn sink_policy(buffer_size: Option<usize>, max_attempts: Option<u32>) -> sink::Policy {
+    let default = sink::Policy::default();
+    sink::Policy {
+        buffer_size: buffer_size
+            .filter(|s| *s > 0)
+            .unwrap_or(default.buffer_size),
+        max_attempts: max_attempts
+            .filter(|a| *a > 0)
+            .unwrap_or(default.max_attempts),
+        ..default
+    }
+}
+
+/// Parses the ingestion key routes, whose names must be unique as they are the directories
+/// their retries are stored in
+fn key_routes(rules: Vec<String>) -> Result<Vec<KeyRoute>, ConfigError> {
+    let mut routes: Vec<KeyRoute> = Vec::new();
+    for rule in rules.iter().filter(|rule| !rule.trim().is_empty()) {
+        let route = KeyRoute::parse(rule)?;
+        if routes.iter().any(|other| other.name == route.name) {
+            return Err(ParseRouteError::Duplicate(route.name).into());
         }
+        routes.push(route);
+    }
+    Ok(routes)
+}
+
+/// The proxy a sink connects through, unset when empty
+#[cfg(any(
+    feature = "elasticsearch_sink",
+    feature = "otlp_sink",
+    feature = "remote_rules",
+    feature = "webhook_sink"
+))]
+fn sink_proxy(
+    url: Option<String>,
+    field: &'static str,
+) -> Result<Option<sink::Proxy>, ConfigError> {
+    url.filter(|u| !u.trim().is_empty())
+        .map(|u| sink::Proxy::parse(&u).map_err(|e| ConfigError::Proxy(field, e)))
+        .transpose()
+}
+
+/// The signer of the requests of a sink, unset when empty
+#[cfg(any(
+    feature = "elasticsearch_sink",
+    feature = "otlp_sink",
+    feature = "webhook_sink"
+))]
+fn sink_signer(
+    spec: Option<String>,
+    field: &'static str,
+) -> Result<Option<std::sync::Arc<dyn sink::Signer>>, ConfigError> {
+    spec.filter(|s| !s.trim().is_empty())
+        .map(|s| sink::parse_signer(&s).map_err(|e| ConfigError::Signer(field, e)))
+        .transpose()
+}
+
+/// Compiles a line expression, unset when empty
+fn parse_expr(expr: Option<String>, field: &'static str) -> Result<Option<Expr>, ConfigError> {
+    expr.filter(|e| !e.trim().is_empty())
+        .map(|e| Expr::parse(&e).map_err(|e| ConfigError::Expr(field, e)))
+        .transpose()
+}
+
+/// The hostname reported when none is configured, the first one found of the kubernetes
+/// node name set through the downward API, the host's hostname mounted at
+/// /etc/logdna-hostname, /etc/hostname and the system's hostname
+pub fn get_hostname() -> Option<String> {
+    if let Some(node) = std::env::var("NODE_NAME")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+    {
+        return Some(node);
     }
 
-    let path = PathBuf::from("/etc/hostname");
-    if path.exists() {
-        if let Ok(s) = File::open(&path).and_then(|mut f| {
-            let mut s = String::new();
-            f.read_to_string(&mut s).map(|_| s)
-        }) {
-            return Some(s);
+    for path in &["/etc/logdna-hostname", "/etc/hostname"] {
+        let path = PathBuf::from(path);
+        if path.exists() {
+            if let Ok(s) = File::open(&path).and_then(|mut f| {
+                let mut s = String::new();
+                f.read_to_string(&mut s).map(|_| s)
+            }) {
+                if !s.trim().is_empty() {
+                    return Some(s);
+                }
+            }
         }
     }
 
     System::new_with_specifics(RefreshKind::new()).get_host_name()
 }
 
+/// Drops the domain of a hostname, `web-1.example.com` becomes `web-1`, but leaves
+/// IP addresses untouched
+fn strip_domain(hostname: &str) -> String {
+    let hostname = hostname.trim();
+    if hostname.parse::<std::net::IpAddr>().is_ok() {
+        return hostname.to_string();
+    }
+    hostname.split('.').next().unwrap_or(hostname).to_string()
+}
+
 fn parse_k8s_tracking_or_warn(
     value: Option<String>,
     name: &str,
@@ -348,6 +1383,115 @@ mod tests {
         assert!(Config::try_from(raw).is_ok());
     }
 
+    #[test]
+    fn test_aggregator_url() {
+        assert_eq!(
+            parse_aggregator_url("https://aggregator.logging:5100/").unwrap(),
+            (true, "aggregator.logging:5100".to_string())
+        );
+        assert_eq!(
+            parse_aggregator_url("http://10.0.0.3:5100").unwrap(),
+            (false, "10.0.0.3:5100".to_string())
+        );
+        assert!(parse_aggregator_url("aggregator.logging:5100").is_err());
+        assert!(parse_aggregator_url("https://aggregator.logging").is_err());
+        assert!(parse_aggregator_url("https://aggregator.logging:5100/logs").is_err());
+    }
+
+    #[test]
+    fn test_dry_run_without_key() {
+        let mut raw = RawConfig::default();
+        raw.http.dry_run = Some(true);
+        let config = Config::try_from(raw).unwrap();
+        assert!(config.http.dry_run);
+    }
+
+    #[test]
+    fn test_key_routes() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.http.ingestion_key_routes = Some(vec![
+            "/var/log/nginx/*.log name=web key=webkey".to_string(),
+            "label.team=payments name=payments key=paymentskey".to_string(),
+        ]);
+        let config = Config::try_from(raw.clone()).unwrap();
+        let names: Vec<&str> = config
+            .http
+            .key_routes
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["web", "payments"]);
+
+        raw.http.ingestion_key_routes = Some(vec![
+            "/var/log/nginx/*.log name=web key=webkey".to_string(),
+            "/var/log/apache/*.log name=web key=otherkey".to_string(),
+        ]);
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::KeyRoute(ParseRouteError::Duplicate(_)))
+        ));
+    }
+
+    #[test]
+    fn test_sidecar_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.log.k8s_sidecar = Some(true);
+        assert!(matches!(
+            Config::try_from(raw.clone()),
+            Err(ConfigError::SidecarPod("POD_NAME"))
+        ));
+
+        env::set_var("POD_NAME", "web-1");
+        env::set_var("POD_NAMESPACE", "shop");
+        let config = Config::try_from(raw.clone()).unwrap();
+        env::remove_var("POD_NAME");
+        env::remove_var("POD_NAMESPACE");
+
+        env::set_var("POD_NAME", "web-1");
+        env::set_var("NAMESPACE", "shop");
+        let from_namespace = Config::try_from(raw.clone()).unwrap();
+        env::remove_var("POD_NAME");
+        env::remove_var("NAMESPACE");
+        assert!(from_namespace
+            .log
+            .rules
+            .included(&PathBuf::from(
+                "/var/log/containers/web-1_shop_app-0123.log"
+            ))
+            .is_ok());
+
+        for p in &[
+            "/var/log/containers/web-1_shop_app-0123.log",
+            "/var/log/pods/shop_web-1_52c36bc5/app/0.log",
+        ] {
+            assert!(
+                config.log.rules.included(&PathBuf::from(p)).is_ok(),
+                "{}",
+                p
+            );
+        }
+        for p in &[
+            "/var/log/containers/web-2_shop_app-0123.log",
+            "/var/log/containers/web-1_other_app-0123.log",
+            "/var/log/syslog.log",
+        ] {
+            assert!(
+                !config.log.rules.included(&PathBuf::from(p)).is_ok(),
+                "{}",
+                p
+            );
+        }
+    }
+
+    #[test]
+    fn test_strip_domain() {
+        assert_eq!(strip_domain("web-1.example.com\n"), "web-1");
+        assert_eq!(strip_domain("web-1"), "web-1");
+        assert_eq!(strip_domain("10.0.0.1"), "10.0.0.1");
+    }
+
     #[test]
     fn test_user_agent() {
         let result = get_default_config();
@@ -0,0 +1,129 @@
+use crate::raw::Config as RawConfig;
+
+/// Presets of the buffering, batching and checkpointing knobs, only filling in the ones
+/// that aren't configured so that any of them can still be tuned on top of a profile
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Profile {
+    /// Small buffers and batches and infrequent checkpoints, for edge and IoT devices
+    LowMemory,
+    /// The defaults
+    Balanced,
+    /// Large buffers and batches, for nodes shipping a lot of lines
+    HighThroughput,
+}
+
+struct Preset {
+    batch_max_lines: Option<usize>,
+    batch_max_latency_ms: Option<u64>,
+    checkpoint_interval_ms: Option<u64>,
+    checkpoint_max_pending: Option<usize>,
+    sink_buffer_size: Option<usize>,
+    k8s_metadata_cache_size: Option<usize>,
+    worker_threads: Option<usize>,
+}
+
+impl Profile {
+    pub fn parse(profile: &str) -> Option<Self> {
+        match profile.trim().to_lowercase().as_str() {
+            "low-memory" => Some(Profile::LowMemory),
+            "balanced" => Some(Profile::Balanced),
+            "high-throughput" => Some(Profile::HighThroughput),
+            _ => None,
+        }
+    }
+
+    fn preset(self) -> Preset {
+        match self {
+            Profile::LowMemory => Preset {
+                batch_max_lines: Some(500),
+                batch_max_latency_ms: Some(1_000),
+                checkpoint_interval_ms: Some(5_000),
+                checkpoint_max_pending: Some(100),
+                sink_buffer_size: Some(1_024),
+                k8s_metadata_cache_size: Some(1_000),
+                worker_threads: Some(1),
+            },
+            Profile::Balanced => Preset {
+                batch_max_lines: None,
+                batch_max_latency_ms: None,
+                checkpoint_interval_ms: None,
+                checkpoint_max_pending: None,
+                sink_buffer_size: None,
+                k8s_metadata_cache_size: None,
+                worker_threads: None,
+            },
+            Profile::HighThroughput => Preset {
+                batch_max_lines: None,
+                batch_max_latency_ms: Some(1_000),
+                checkpoint_interval_ms: Some(2_000),
+                checkpoint_max_pending: Some(10_000),
+                sink_buffer_size: Some(64 * 1024),
+                k8s_metadata_cache_size: Some(50_000),
+                worker_threads: None,
+            },
+        }
+    }
+
+    /// Threads of the runtime, as many as there are cores when unset
+    pub fn worker_threads(self) -> Option<usize> {
+        self.preset().worker_threads
+    }
+
+    pub fn apply(self, raw: &mut RawConfig) {
+        let preset = self.preset();
+        fill(&mut raw.http.batch_max_lines, preset.batch_max_lines);
+        fill(
+            &mut raw.http.batch_max_latency_ms,
+            preset.batch_max_latency_ms,
+        );
+        fill(
+            &mut raw.log.checkpoint_interval_ms,
+            preset.checkpoint_interval_ms,
+        );
+        fill(
+            &mut raw.log.checkpoint_max_pending,
+            preset.checkpoint_max_pending,
+        );
+        fill(
+            &mut raw.log.k8s_metadata_cache_size,
+            preset.k8s_metadata_cache_size,
+        );
+        let buffer_sizes = [
+            &mut raw.archive.buffer_size,
+            &mut raw.elasticsearch.buffer_size,
+            &mut raw.otlp.buffer_size,
+            &mut raw.webhook.buffer_size,
+            &mut raw.syslog.buffer_size,
+            &mut raw.unix_socket.buffer_size,
+        ];
+        for buffer_size in buffer_sizes {
+            fill(buffer_size, preset.sink_buffer_size);
+        }
+    }
+}
+
+fn fill<T>(field: &mut Option<T>, preset: Option<T>) {
+    if field.is_none() {
+        *field = preset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_configured_values() {
+        let mut raw = RawConfig::default();
+        raw.log.checkpoint_interval_ms = Some(250);
+        Profile::parse("Low-Memory").unwrap().apply(&mut raw);
+        assert_eq!(raw.log.checkpoint_interval_ms, Some(250));
+        assert_eq!(raw.log.checkpoint_max_pending, Some(100));
+        assert_eq!(raw.otlp.buffer_size, Some(1_024));
+
+        let mut raw = RawConfig::default();
+        Profile::Balanced.apply(&mut raw);
+        assert_eq!(raw, RawConfig::default());
+        assert_eq!(Profile::parse("fast"), None);
+    }
+}
@@ -4,19 +4,150 @@ use http::types::params::Params;
 
 use crate::error::ConfigError;
 use crate::get_hostname;
+use globber::Pattern;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
     pub http: HttpConfig,
     pub log: LogConfig,
     pub journald: JournaldConfig,
+    #[serde(default)]
+    pub auditd: AuditdConfig,
+    #[serde(default)]
+    pub docker: DockerConfig,
+    #[serde(default)]
+    pub kubelet: KubeletConfig,
+    #[serde(default)]
+    pub receiver: ReceiverConfig,
+    #[serde(default)]
+    pub exec: ExecConfig,
+    #[serde(default)]
+    pub kafka: KafkaConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub elasticsearch: ElasticsearchConfig,
+    #[serde(default)]
+    pub otlp: OtlpConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub syslog: SyslogConfig,
+    #[serde(default)]
+    pub unix_socket: UnixSocketConfig,
+    #[serde(default)]
+    pub cloud: CloudConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub pool: PoolConfig,
+    #[serde(default)]
+    pub remote_config: RemoteConfig,
 }
 
 impl Config {
+    /// Parses the config file along with the files of its `include` directive, merged on top
+    /// of it in the order of their names
     pub fn parse<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
-        Ok(serde_yaml::from_reader(File::open(path)?)?)
+        let path = path.as_ref();
+        let mut config = read_yaml(path)?;
+        let includes = match &mut config {
+            Value::Mapping(mapping) => mapping.remove(&Value::from("include")),
+            _ => None,
+        };
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for include in include_paths(includes, base_dir)? {
+            let mut included = read_yaml(&include)?;
+            if let Value::Mapping(mapping) = &mut included {
+                if mapping.remove(&Value::from("include")).is_some() {
+                    warn!(
+                        "ignoring the include directive of the included {:?}",
+                        include
+                    );
+                }
+            }
+            info!("merging config from {:?}", include);
+            merge_yaml(&mut config, included);
+        }
+        Ok(serde_yaml::from_value(config)?)
+    }
+}
+
+fn read_yaml(path: &Path) -> Result<Value, ConfigError> {
+    let file = File::open(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("unable to read {:?}: {}", path, e)))?;
+    Ok(serde_yaml::from_reader(file)?)
+}
+
+/// The files of the `include` directive, a path or a list of paths relative to the directory
+/// of the config file whose file names may be glob patterns, e.g. `conf.d/*.yaml`
+fn include_paths(includes: Option<Value>, base_dir: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    let patterns = match includes {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::String(pattern)) => vec![pattern],
+        Some(Value::Sequence(patterns)) => patterns
+            .into_iter()
+            .filter_map(|pattern| pattern.as_str().map(str::to_string))
+            .collect(),
+        Some(_) => return Err(ConfigError::Include),
+    };
+
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let pattern = base_dir.join(pattern);
+        let name = pattern
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !name.contains(&['*', '?', '['][..]) {
+            paths.push(pattern);
+            continue;
+        }
+
+        let matcher = Pattern::new(&name)?;
+        let dir = pattern.parent().unwrap_or_else(|| Path::new(""));
+        let mut matched = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .filter(|path| {
+                    path.file_name()
+                        .map_or(false, |name| matcher.matches(&name.to_string_lossy()))
+                })
+                .collect::<Vec<_>>(),
+            // An empty or missing drop-in directory isn't an error
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        matched.sort();
+        paths.extend(matched);
+    }
+    Ok(paths)
+}
+
+/// Merges `other` into `base`: mappings are merged key by key, lists such as rules are
+/// appended to and any other value of `other` replaces the one of `base`
+fn merge_yaml(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Mapping(base), Value::Mapping(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(other)) => base.extend(other),
+        (base, other) => *base = other,
     }
 }
 
@@ -27,19 +158,73 @@ pub struct HttpConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub endpoint: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregator_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub use_ssl: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_max_lifetime_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_request_threshold_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validate: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readiness_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub use_compression: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gzip_level: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression_sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ingestion_key: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingestion_key_routes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingestion_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub params: Option<Params>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_hostname_domain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub body_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_max_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_max_latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_group_by_source: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_ordering: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stall_threshold_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_max_delay_ms: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_jitter_percent: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_max_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_budget_percent: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_encryption_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_spool_max_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_spool_max_age_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_spool_when_full: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_replay_ratio: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_encryption_key_file: Option<PathBuf>,
 
     // Mostly for development, these settings are hidden from the user
     // There's no guarantee that these settings will exist in the future
@@ -65,15 +250,147 @@ pub struct LogConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub line_redact_regex: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_exclusion_expr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_inclusion_expr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowRules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redact_secrets: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymize_ips: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymize_ipv4_prefix: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymize_ipv6_prefix: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_access: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_config_map: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub lookback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recreated_files: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_coalesce_window_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_limit_bytes_per_sec: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mmap_threshold_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag_threshold_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag_threshold_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_top_sources: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_telemetry: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_sample: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint_interval_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint_max_pending: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_dedup_window_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority_paths: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority_weight: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_overrides: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_templates: Option<Vec<String>>,
     pub use_k8s_enrichment: Option<String>,
     pub log_k8s_events: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_sidecar: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_event_dedup_window_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_metadata_cache_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_metadata_cache_ttl_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_metadata_wait_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_cluster_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_audit_path: Option<PathBuf>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct JournaldConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paths: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lag_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_lines_per_sec: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_bytes_per_sec: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_message_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_handling: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ids: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backfill_hours: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backfill_max_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct AuditdConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<PathBuf>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct DockerConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_filters: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct KubeletConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insecure_tls: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ReceiverConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingest_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingest_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingest_tls_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingest_tls_key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregator: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_remote_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_remote_tls_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_remote_tls_key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_remote_tls_ca: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_audit_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_audit_tls_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub k8s_audit_tls_key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_address: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
@@ -85,9 +402,26 @@ pub struct Rules {
 impl Default for Config {
     fn default() -> Self {
         Config {
+            profile: None,
             http: HttpConfig::default(),
             log: LogConfig::default(),
             journald: JournaldConfig::default(),
+            auditd: AuditdConfig::default(),
+            docker: DockerConfig::default(),
+            kubelet: KubeletConfig::default(),
+            receiver: ReceiverConfig::default(),
+            exec: ExecConfig::default(),
+            kafka: KafkaConfig::default(),
+            archive: ArchiveConfig::default(),
+            elasticsearch: ElasticsearchConfig::default(),
+            otlp: OtlpConfig::default(),
+            webhook: WebhookConfig::default(),
+            syslog: SyslogConfig::default(),
+            unix_socket: UnixSocketConfig::default(),
+            cloud: CloudConfig::default(),
+            tls: TlsConfig::default(),
+            pool: PoolConfig::default(),
+            remote_config: RemoteConfig::default(),
         }
     }
 }
@@ -97,16 +431,43 @@ impl Default for HttpConfig {
         HttpConfig {
             host: Some("logs.logdna.com".to_string()),
             endpoint: Some("/logs/agent".to_string()),
+            aggregator_url: None,
             use_ssl: Some(true),
             timeout: Some(10_000),
+            connection_max_lifetime_secs: None,
+            slow_request_threshold_ms: None,
+            validate: None,
+            readiness_file: None,
             use_compression: Some(true),
             gzip_level: Some(2),
+            compression_sample_rate: None,
+            codec_threads: None,
             ingestion_key: None,
+            ingestion_key_routes: None,
+            ingestion_enabled: None,
+            dry_run: None,
             params: Params::builder()
                 .hostname(get_hostname().unwrap_or_default())
                 .build()
                 .ok(),
+            tags_file: None,
+            strip_hostname_domain: None,
             body_size: Some(2 * 1024 * 1024),
+            batch_max_lines: None,
+            batch_max_latency_ms: None,
+            batch_group_by_source: None,
+            strict_ordering: None,
+            stall_threshold_ms: None,
+            retry_max_delay_ms: None,
+            retry_jitter_percent: None,
+            retry_max_attempts: None,
+            retry_budget_percent: None,
+            retry_encryption_key: None,
+            retry_spool_max_bytes: None,
+            retry_spool_max_age_secs: None,
+            retry_spool_when_full: None,
+            retry_replay_ratio: None,
+            retry_encryption_key_file: None,
             retry_base_delay_ms: None,
             retry_step_delay_ms: None,
         }
@@ -142,16 +503,446 @@ impl Default for LogConfig {
             line_exclusion_regex: None,
             line_inclusion_regex: None,
             line_redact_regex: None,
+            line_exclusion_expr: None,
+            line_inclusion_expr: None,
+            shadow: None,
+            redact_secrets: None,
+            anonymize_ips: None,
+            anonymize_ipv4_prefix: None,
+            anonymize_ipv6_prefix: None,
+            require_access: None,
+            k8s_config_map: None,
             lookback: None,
+            recreated_files: None,
+            event_coalesce_window_ms: None,
+            read_limit_bytes_per_sec: None,
+            mmap_threshold_bytes: None,
+            lag_threshold_bytes: None,
+            lag_threshold_secs: None,
+            metrics_top_sources: None,
+            self_telemetry: None,
+            trace_sample: None,
+            checkpoint_interval_ms: None,
+            checkpoint_max_pending: None,
+            restart_dedup_window_ms: None,
+            priority_paths: None,
+            priority_weight: None,
+            field_overrides: None,
+            path_templates: None,
             use_k8s_enrichment: None,
             log_k8s_events: None,
+            k8s_sidecar: None,
+            k8s_event_dedup_window_ms: None,
+            k8s_metadata_cache_size: None,
+            k8s_metadata_cache_ttl_ms: None,
+            k8s_metadata_wait_ms: None,
+            k8s_cluster_name: None,
+            k8s_audit_path: None,
         }
     }
 }
 
 impl Default for JournaldConfig {
     fn default() -> Self {
-        JournaldConfig { paths: None }
+        JournaldConfig {
+            paths: None,
+            max_lag_secs: None,
+            unit_lines_per_sec: None,
+            unit_bytes_per_sec: None,
+            max_message_size: None,
+            message_handling: None,
+            ids: None,
+            backfill_hours: None,
+            backfill_max_bytes: None,
+        }
+    }
+}
+
+impl Default for AuditdConfig {
+    fn default() -> Self {
+        AuditdConfig { paths: None }
+    }
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        DockerConfig {
+            socket: None,
+            label_filters: None,
+        }
+    }
+}
+
+impl Default for KubeletConfig {
+    fn default() -> Self {
+        KubeletConfig {
+            url: None,
+            insecure_tls: None,
+        }
+    }
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        ReceiverConfig {
+            ingest_address: None,
+            ingest_key: None,
+            ingest_tls_cert: None,
+            ingest_tls_key: None,
+            aggregator: None,
+            journal_remote_address: None,
+            journal_remote_tls_cert: None,
+            journal_remote_tls_key: None,
+            journal_remote_tls_ca: None,
+            k8s_audit_address: None,
+            k8s_audit_tls_cert: None,
+            k8s_audit_tls_key: None,
+            status_address: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ExecConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commands: Option<Vec<ExecCommand>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ExecCommand {
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        ExecConfig { commands: None }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct KafkaConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brokers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topics: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        KafkaConfig {
+            brokers: None,
+            topics: None,
+            group_id: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ArchiveConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3_bucket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3_region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_interval: Option<u64>,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            s3_bucket: None,
+            s3_prefix: None,
+            s3_region: None,
+            s3_endpoint: None,
+            buffer_size: None,
+            max_attempts: None,
+            flush_interval: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ElasticsearchConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ElasticsearchConfig {
+    fn default() -> Self {
+        ElasticsearchConfig {
+            url: None,
+            index: None,
+            username: None,
+            password: None,
+            proxy: None,
+            signer: None,
+            buffer_size: None,
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct OtlpConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        OtlpConfig {
+            endpoint: None,
+            headers: None,
+            proxy: None,
+            signer: None,
+            buffer_size: None,
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct WebhookConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub envelope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            url: None,
+            envelope: None,
+            fields: None,
+            headers: None,
+            proxy: None,
+            signer: None,
+            buffer_size: None,
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct SyslogConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facility: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        SyslogConfig {
+            address: None,
+            tls: None,
+            ca_cert: None,
+            facility: None,
+            buffer_size: None,
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct UnixSocketConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub framing: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for UnixSocketConfig {
+    fn default() -> Self {
+        UnixSocketConfig {
+            path: None,
+            framing: None,
+            buffer_size: None,
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct CloudConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_fields: Option<Vec<String>>,
+}
+
+impl Default for CloudConfig {
+    fn default() -> Self {
+        CloudConfig {
+            metadata: None,
+            metadata_timeout_ms: None,
+            metadata_fields: None,
+        }
+    }
+}
+
+/// TLS settings of the connections of the sinks and the remote config client, not of the ones
+/// to LogDNA
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct TlsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher_suites: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insecure_skip_verify: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned_certs: Option<Vec<String>>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            min_version: None,
+            cipher_suites: None,
+            insecure_skip_verify: None,
+            pinned_certs: None,
+        }
+    }
+}
+
+/// How the clients of the sinks keep idle connections around for later requests
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct PoolConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_idle_per_host: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_ms: Option<u64>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            idle_timeout_ms: None,
+            max_idle_per_host: None,
+            connect_timeout_ms: None,
+            tcp_keepalive_ms: None,
+        }
+    }
+}
+
+/// Where the line rules applied on top of the configured ones are polled from
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RemoteConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            url: None,
+            public_key: None,
+            interval_secs: None,
+            proxy: None,
+        }
+    }
+}
+
+/// Line rules that are only counted in the metrics, not enforced
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ShadowRules {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_exclusion_regex: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_inclusion_regex: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_redact_regex: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_exclusion_expr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_inclusion_expr: Option<String>,
+}
+
+impl Default for ShadowRules {
+    fn default() -> Self {
+        ShadowRules {
+            line_exclusion_regex: None,
+            line_inclusion_regex: None,
+            line_redact_regex: None,
+            line_exclusion_expr: None,
+            line_inclusion_expr: None,
+        }
     }
 }
 
@@ -173,4 +964,36 @@ mod tests {
         let new_config = new_config.unwrap();
         assert_eq!(config, new_config);
     }
+
+    #[test]
+    fn test_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf_d = dir.path().join("conf.d");
+        std::fs::create_dir(&conf_d).unwrap();
+        let base = serde_yaml::to_string(&Config::default()).unwrap();
+        let base = format!(
+            "include: conf.d/*.yaml\n{}",
+            base.trim_start_matches("---\n")
+        );
+        std::fs::write(dir.path().join("config.yaml"), base).unwrap();
+        std::fs::write(
+            conf_d.join("20-app.yaml"),
+            "log:\n  line_exclusion_regex:\n    - ^DEBUG\n",
+        )
+        .unwrap();
+        std::fs::write(
+            conf_d.join("10-platform.yaml"),
+            "http:\n  timeout: 30000\nlog:\n  line_exclusion_regex:\n    - healthz\n",
+        )
+        .unwrap();
+        std::fs::write(conf_d.join("README"), "not: yaml: at all").unwrap();
+
+        let config = Config::parse(dir.path().join("config.yaml")).unwrap();
+        assert_eq!(config.http.timeout, Some(30_000));
+        assert_eq!(
+            config.log.line_exclusion_regex,
+            Some(vec!["healthz".to_string(), "^DEBUG".to_string()])
+        );
+        assert_eq!(config.http.host, Config::default().http.host);
+    }
 }
@@ -0,0 +1,83 @@
+use std::fs::read_to_string;
+use std::io;
+use std::path::Path;
+
+/// Resolves the placeholders of a tag, `{hostname}`, `{node}` (the `NODE_NAME` env var set
+/// through the downward API) and `{env:VAR}`. Tags are sent per request rather than per
+/// line, so placeholders that depend on a line's metadata, like `{namespace}`, can't be
+/// resolved and tags that can't be resolved are dropped.
+pub fn expand(tag: &str, hostname: &str) -> Option<String> {
+    let mut expanded = String::with_capacity(tag.len());
+    let mut rest = tag;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        expanded.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..end];
+        let value = match placeholder {
+            "hostname" => Some(hostname.trim().to_string()),
+            "node" => std::env::var("NODE_NAME").ok(),
+            _ => match placeholder.strip_prefix("env:") {
+                Some(var) => std::env::var(var).ok(),
+                None => None,
+            },
+        };
+        match value.filter(|v| !v.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => {
+                warn!(
+                    "dropping tag {}, {{{}}} can't be resolved",
+                    tag, placeholder
+                );
+                return None;
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Some(expanded)
+}
+
+/// Reads the tags of a file, separated by commas or new lines, skipping lines starting
+/// with `#`
+pub fn read_file(path: &Path, hostname: &str) -> io::Result<Vec<String>> {
+    Ok(read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .filter_map(|tag| expand(tag, hostname))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn expands_placeholders() {
+        std::env::set_var("TAGS_TEST_DEPLOY_ENV", "staging");
+        assert_eq!(expand("static", "host"), Some("static".to_string()));
+        assert_eq!(
+            expand("{env:TAGS_TEST_DEPLOY_ENV}-{hostname}", "host\n"),
+            Some("staging-host".to_string())
+        );
+        assert_eq!(expand("{env:TAGS_TEST_MISSING}", "host"), None);
+        assert_eq!(expand("{namespace}", "host"), None);
+        assert_eq!(expand("open{", "host"), Some("open{".to_string()));
+    }
+
+    #[test]
+    fn reads_tags_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "# comment\na, b\n\nc\n{{hostname}}\n").unwrap();
+        assert_eq!(
+            read_file(file.path(), "host").unwrap(),
+            vec!["a", "b", "c", "host"]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::error::DockerError;
+
+use hyper::body::Bytes;
+use hyper::client::Client;
+use hyper::{Body, Uri};
+use hyperlocal::{UnixClientExt, UnixConnector};
+
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerSummary {
+    pub id: String,
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub image: String,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+impl ContainerSummary {
+    /// Returns the container name without the leading slash docker adds
+    pub fn name(&self) -> &str {
+        self.names
+            .first()
+            .map(|name| name.trim_start_matches('/'))
+            .unwrap_or(&self.id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInspect {
+    config: ContainerInspectConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerInspectConfig {
+    #[serde(default)]
+    tty: bool,
+}
+
+/// A minimal client for the parts of the Docker Engine API used by the docker source
+#[derive(Clone)]
+pub struct DockerClient {
+    socket: PathBuf,
+    inner: Client<UnixConnector, Body>,
+}
+
+impl DockerClient {
+    pub fn new(socket: impl AsRef<Path>) -> Self {
+        Self {
+            socket: socket.as_ref().into(),
+            inner: Client::unix(),
+        }
+    }
+
+    fn uri(&self, path_and_query: &str) -> Uri {
+        hyperlocal::Uri::new(&self.socket, path_and_query).into()
+    }
+
+    async fn get(&self, path_and_query: &str) -> Result<Body, DockerError> {
+        let response = self.inner.get(self.uri(path_and_query)).await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            return Err(DockerError::Api(
+                status,
+                String::from_utf8_lossy(&body).into(),
+            ));
+        }
+        Ok(response.into_body())
+    }
+
+    /// Lists running containers, each filter is a `key` or `key=value` label
+    pub async fn list_containers(
+        &self,
+        label_filters: &[String],
+    ) -> Result<Vec<ContainerSummary>, DockerError> {
+        let mut path = String::from("/containers/json");
+        if !label_filters.is_empty() {
+            let filters = serde_json::json!({ "label": label_filters });
+            path.push_str("?filters=");
+            path.push_str(&encode_query_value(&filters.to_string()));
+        }
+        let body = hyper::body::to_bytes(self.get(&path).await?).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Ids of all the containers, including the stopped ones
+    pub async fn container_ids(&self) -> Result<HashSet<String>, DockerError> {
+        let body = hyper::body::to_bytes(self.get("/containers/json?all=1").await?).await?;
+        let containers: Vec<ContainerSummary> = serde_json::from_slice(&body)?;
+        Ok(containers
+            .into_iter()
+            .map(|container| container.id)
+            .collect())
+    }
+
+    /// Returns true if the container was started with a TTY, which changes the log format
+    pub async fn has_tty(&self, id: &str) -> Result<bool, DockerError> {
+        let path = format!("/containers/{}/json", id);
+        let body = hyper::body::to_bytes(self.get(&path).await?).await?;
+        Ok(serde_json::from_slice::<ContainerInspect>(&body)?
+            .config
+            .tty)
+    }
+
+    /// Follows the stdout and stderr of a container, starting at `since` (unix seconds with an
+    /// optional fractional part). Every line is prefixed with its RFC 3339 timestamp.
+    pub async fn logs(
+        &self,
+        id: &str,
+        since: &str,
+    ) -> Result<impl Stream<Item = Result<Bytes, DockerError>>, DockerError> {
+        let path = format!(
+            "/containers/{}/logs?follow=1&stdout=1&stderr=1&timestamps=1&since={}",
+            id,
+            encode_query_value(since)
+        );
+        Ok(self
+            .get(&path)
+            .await?
+            .map(|chunk| chunk.map_err(Into::into)))
+    }
+}
+
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_filters() {
+        assert_eq!(
+            encode_query_value(r#"{"label":["app=web"]}"#),
+            "%7B%22label%22%3A%5B%22app%3Dweb%22%5D%7D"
+        );
+        assert_eq!(encode_query_value("1600000000.5"), "1600000000.5");
+    }
+
+    #[test]
+    fn strips_container_name_slash() {
+        let summary: ContainerSummary =
+            serde_json::from_str(r#"{"Id":"abc","Names":["/web"],"Image":"nginx"}"#).unwrap();
+        assert_eq!(summary.name(), "web");
+    }
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DockerError {
+    #[error("{0}")]
+    Http(#[from] hyper::Error),
+    #[error("docker api returned {0}: {1}")]
+    Api(hyper::StatusCode, String),
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+}
@@ -0,0 +1,128 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+/// Size of the header docker prefixes to every frame of a multiplexed log stream
+const HEADER_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+/// Splits the body of a `/containers/{id}/logs` response into lines.
+///
+/// Containers without a TTY multiplex stdout and stderr into frames with an 8 byte header
+/// (stream type followed by a big endian payload length), containers with a TTY send the raw
+/// output as stdout.
+pub struct LineDecoder {
+    tty: bool,
+    frames: BytesMut,
+    stdout: BytesMut,
+    stderr: BytesMut,
+}
+
+impl LineDecoder {
+    pub fn new(tty: bool) -> Self {
+        Self {
+            tty,
+            frames: BytesMut::new(),
+            stdout: BytesMut::new(),
+            stderr: BytesMut::new(),
+        }
+    }
+
+    /// Adds a chunk of the response body, returning every line that is now complete
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<(Output, Bytes)> {
+        let mut lines = Vec::new();
+        if self.tty {
+            self.stdout.extend_from_slice(chunk);
+            take_lines(&mut self.stdout, Output::Stdout, &mut lines);
+        } else {
+            self.frames.extend_from_slice(chunk);
+            while self.frames.len() >= HEADER_LEN {
+                let size = u32::from_be_bytes([
+                    self.frames[4],
+                    self.frames[5],
+                    self.frames[6],
+                    self.frames[7],
+                ]) as usize;
+                if self.frames.len() < HEADER_LEN + size {
+                    break;
+                }
+                let stream_type = self.frames[0];
+                self.frames.advance(HEADER_LEN);
+                let payload = self.frames.split_to(size);
+                // Lines are taken per frame to keep the order stdout and stderr were written in
+                match stream_type {
+                    2 => {
+                        self.stderr.extend_from_slice(&payload);
+                        take_lines(&mut self.stderr, Output::Stderr, &mut lines);
+                    }
+                    // 0 is stdin, which is only echoed back for attached streams
+                    _ => {
+                        self.stdout.extend_from_slice(&payload);
+                        take_lines(&mut self.stdout, Output::Stdout, &mut lines);
+                    }
+                }
+            }
+        }
+        lines
+    }
+}
+
+fn take_lines(buf: &mut BytesMut, output: Output, lines: &mut Vec<(Output, Bytes)>) {
+    while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+        let mut line = buf.split_to(pos + 1);
+        line.truncate(pos);
+        if line.ends_with(b"\r") {
+            line.truncate(pos - 1);
+        }
+        lines.push((output, line.freeze()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(stream_type: u8, payload: &str) -> Vec<u8> {
+        let mut frame = vec![stream_type, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn decodes_multiplexed_frames() {
+        let mut body = frame(1, "out 1\nout");
+        body.extend(frame(2, "err 1\n"));
+        body.extend(frame(1, " 2\n"));
+
+        let mut decoder = LineDecoder::new(false);
+        // Split in the middle of a header to make sure partial frames are kept
+        let mut lines = decoder.push(&body[..20]);
+        lines.extend(decoder.push(&body[20..]));
+
+        assert_eq!(
+            lines,
+            vec![
+                (Output::Stdout, Bytes::from("out 1")),
+                (Output::Stderr, Bytes::from("err 1")),
+                (Output::Stdout, Bytes::from("out 2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_raw_tty_output() {
+        let mut decoder = LineDecoder::new(true);
+        assert!(decoder.push(b"partial").is_empty());
+        assert_eq!(
+            decoder.push(b" line\r\nnext\n"),
+            vec![
+                (Output::Stdout, Bytes::from("partial line")),
+                (Output::Stdout, Bytes::from("next")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,4 @@
+pub mod client;
+pub mod error;
+pub mod frame;
+pub mod source;
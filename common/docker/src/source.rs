@@ -0,0 +1,215 @@
+use crate::client::{ContainerSummary, DockerClient};
+use crate::error::DockerError;
+use crate::frame::LineDecoder;
+
+use chrono::DateTime;
+use futures::{Stream, StreamExt};
+use http::types::body::LineBuilder;
+use log::{debug, info, warn};
+use metrics::Metrics;
+use state::{Checkpoint, TimestampState};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{channel, Sender};
+
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+const KEY_PREFIX: &str = "docker:";
+
+type Lines = Sender<(LineBuilder, Option<Checkpoint>)>;
+
+/// Streams the logs of running docker containers that match all of `label_filters`.
+///
+/// Containers are discovered by polling the Docker Engine API. Lines come with the timestamp
+/// docker read them at, which is saved in `state` once they are shipped so a restarted agent
+/// resumes where it stopped. The timestamps of removed containers are deleted.
+/// Must be called from within a tokio runtime.
+pub fn create_source(
+    socket: PathBuf,
+    label_filters: Vec<String>,
+    state: Option<TimestampState>,
+) -> impl Stream<Item = (LineBuilder, Option<Checkpoint>)> {
+    let (tx, rx) = channel(1024);
+    let client = DockerClient::new(&socket);
+    info!("monitoring docker containers through {:?}", socket);
+
+    tokio::spawn(async move {
+        let active = Arc::new(Mutex::new(HashSet::new()));
+        let mut initial = true;
+        loop {
+            match client.list_containers(&label_filters).await {
+                Ok(containers) => {
+                    for container in containers {
+                        if !active.lock().unwrap().insert(container.id.clone()) {
+                            continue;
+                        }
+                        tokio::spawn(follow_container(
+                            client.clone(),
+                            container,
+                            state.clone(),
+                            tx.clone(),
+                            active.clone(),
+                            initial,
+                        ));
+                    }
+                    initial = false;
+                }
+                Err(e) => warn!("unable to list docker containers: {}", e),
+            }
+            if let Some(state) = state.as_ref() {
+                forget_removed(&client, state).await;
+            }
+            if tx.is_closed() {
+                break;
+            }
+            tokio::time::sleep(DISCOVERY_INTERVAL).await;
+        }
+    });
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    })
+}
+
+async fn follow_container(
+    client: DockerClient,
+    container: ContainerSummary,
+    state: Option<TimestampState>,
+    tx: Lines,
+    active: Arc<Mutex<HashSet<String>>>,
+    initial: bool,
+) {
+    let name = container.name().to_string();
+    let key = format!("{}{}", KEY_PREFIX, container.id);
+    let saved = state
+        .as_ref()
+        .and_then(|state| state.get(&key).map_err(|e| warn!("{}", e)).ok())
+        .flatten();
+    let since = match saved {
+        // Resume just after the last line that was read
+        Some(nanos) => format_since(nanos + 1),
+        // Containers running when the agent starts are tailed from now, like files and journald,
+        // containers started later are read from the beginning
+        None if initial => format_since(now_nanos()),
+        None => "0".into(),
+    };
+
+    info!(
+        "following docker container {} ({}) since {}",
+        name, container.id, since
+    );
+    if let Err(e) = read_logs(&client, &container.id, &name, &since, &key, &tx).await {
+        warn!("stopped following docker container {}: {}", name, e);
+    }
+    active.lock().unwrap().remove(&container.id);
+}
+
+async fn read_logs(
+    client: &DockerClient,
+    id: &str,
+    name: &str,
+    since: &str,
+    key: &str,
+    tx: &Lines,
+) -> Result<(), DockerError> {
+    let tty = client.has_tty(id).await?;
+    let mut logs = Box::pin(client.logs(id, since).await?);
+    let mut decoder = LineDecoder::new(tty);
+
+    while let Some(chunk) = logs.next().await {
+        for (_, line) in decoder.push(&chunk?) {
+            let line = String::from_utf8_lossy(&line);
+            let (timestamp, message) = split_timestamp(&line);
+            Metrics::docker().increment_lines();
+            Metrics::docker().add_bytes(message.len() as u64);
+            let line = LineBuilder::new().line(message).file(name).app(name);
+            let checkpoint = timestamp.map(|nanos| Checkpoint::Timestamp {
+                key: key.to_string(),
+                nanos,
+            });
+            if tx.send((line, checkpoint)).await.is_err() {
+                debug!("docker source was dropped, stopping {}", name);
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the saved timestamps of the containers that were removed, stopped containers keep
+/// theirs to resume from when they are started again
+async fn forget_removed(client: &DockerClient, state: &TimestampState) {
+    let ids = match client.container_ids().await {
+        Ok(ids) => ids,
+        Err(e) => {
+            warn!("unable to list docker containers: {}", e);
+            return;
+        }
+    };
+    let entries = match state.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("unable to read docker log positions: {}", e);
+            return;
+        }
+    };
+    for key in entries.keys() {
+        match key.strip_prefix(KEY_PREFIX) {
+            Some(id) if !ids.contains(id) => {
+                debug!(
+                    "docker container {} was removed, deleting its log position",
+                    id
+                );
+                if let Err(e) = state.delete(key) {
+                    warn!("unable to delete docker log position: {}", e);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Splits the RFC 3339 timestamp docker prefixes lines with, returning it in nanoseconds
+fn split_timestamp(line: &str) -> (Option<i64>, &str) {
+    let mut parts = line.splitn(2, ' ');
+    if let (Some(timestamp), Some(message)) = (parts.next(), parts.next()) {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp) {
+            return (Some(timestamp.timestamp_nanos()), message);
+        }
+    }
+    (None, line)
+}
+
+fn format_since(nanos: i64) -> String {
+    format!("{}.{:09}", nanos / 1_000_000_000, nanos % 1_000_000_000)
+}
+
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_docker_timestamps() {
+        let (timestamp, message) = split_timestamp("2021-05-11T14:22:33.000000001Z GET / 200");
+        assert_eq!(timestamp, Some(1_620_742_953_000_000_001));
+        assert_eq!(message, "GET / 200");
+
+        assert_eq!(split_timestamp("no timestamp"), (None, "no timestamp"));
+    }
+
+    #[test]
+    fn formats_since() {
+        assert_eq!(
+            format_since(1_620_742_953_000_000_001),
+            "1620742953.000000001"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use chrono::{SecondsFormat, TimeZone, Utc};
+use http::types::body::Line;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// NDJSON body of a `_bulk` request, indexing one document per line
+#[derive(Default)]
+pub(crate) struct BulkBody {
+    body: Vec<u8>,
+    lines: usize,
+}
+
+impl BulkBody {
+    pub(crate) fn push(&mut self, index: &str, line: &Line) -> Result<(), serde_json::Error> {
+        let mut document = serde_json::to_value(line)?;
+        document["@timestamp"] = Utc
+            .timestamp_millis(line.timestamp)
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into();
+
+        serde_json::to_writer(
+            &mut self.body,
+            &serde_json::json!({ "index": { "_index": index } }),
+        )?;
+        self.body.push(b'\n');
+        serde_json::to_writer(&mut self.body, &document)?;
+        self.body.push(b'\n');
+        self.lines += 1;
+        Ok(())
+    }
+
+    pub(crate) fn lines(&self) -> usize {
+        self.lines
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.body.len()
+    }
+
+    pub(crate) fn take(&mut self) -> (Vec<u8>, usize) {
+        let lines = std::mem::take(&mut self.lines);
+        (std::mem::take(&mut self.body), lines)
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    errors: bool,
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize)]
+struct BulkItem {
+    status: u16,
+}
+
+/// Counts the documents of a `_bulk` response that were not indexed
+pub(crate) fn failed_items(response: &[u8]) -> Result<usize, serde_json::Error> {
+    let response: BulkResponse = serde_json::from_slice(response)?;
+    if !response.errors {
+        return Ok(0);
+    }
+    Ok(response
+        .items
+        .iter()
+        .flat_map(|item| item.values())
+        .filter(|item| item.status >= 300)
+        .count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn writes_action_and_document_lines() {
+        let mut line = LineBuilder::new().line("hello").app("web").build().unwrap();
+        line.timestamp = 1_622_548_800_123;
+
+        let mut body = BulkBody::default();
+        body.push("logs-web", &line).unwrap();
+        assert_eq!(body.lines(), 1);
+
+        let (bytes, lines) = body.take();
+        assert_eq!(lines, 1);
+        assert_eq!(body.bytes(), 0);
+        let body = String::from_utf8(bytes).unwrap();
+        let mut body = body.lines();
+        let action: serde_json::Value = serde_json::from_str(body.next().unwrap()).unwrap();
+        assert_eq!(action["index"]["_index"], "logs-web");
+        let document: serde_json::Value = serde_json::from_str(body.next().unwrap()).unwrap();
+        assert_eq!(document["line"], "hello");
+        assert_eq!(document["@timestamp"], "2021-06-01T12:00:00.123Z");
+    }
+
+    #[test]
+    fn counts_failed_items() {
+        let response = br#"{"took":3,"errors":true,"items":[
+            {"index":{"_index":"logs","status":201}},
+            {"index":{"_index":"logs","status":429}},
+            {"index":{"_index":"logs","status":400}}
+        ]}"#;
+        assert_eq!(failed_items(response).unwrap(), 2);
+        assert_eq!(failed_items(br#"{"errors":false,"items":[]}"#).unwrap(), 0);
+    }
+}
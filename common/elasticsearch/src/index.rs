@@ -0,0 +1,124 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
+use http::types::body::{Line, LineMeta};
+use std::fmt;
+
+const FIELDS: [&str; 5] = ["app", "host", "env", "level", "file"];
+const MISSING: &str = "unknown";
+
+#[derive(Debug)]
+pub struct InvalidIndexTemplate(String);
+
+impl fmt::Display for InvalidIndexTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid elasticsearch index template: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidIndexTemplate {}
+
+/// Names the index each line is written to.
+///
+/// `{app}`, `{host}`, `{env}`, `{level}` and `{file}` are replaced with the line's fields and
+/// `strftime` specifiers with its timestamp, e.g. `logs-{app}-%Y.%m.%d`.
+#[derive(Clone, Debug)]
+pub struct IndexTemplate(String);
+
+impl IndexTemplate {
+    pub fn parse(template: &str) -> Result<Self, InvalidIndexTemplate> {
+        if StrftimeItems::new(template).any(|item| item == Item::Error) {
+            return Err(InvalidIndexTemplate(format!(
+                "{} has an unknown date specifier",
+                template
+            )));
+        }
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| InvalidIndexTemplate(format!("{} has an unclosed {{", template)))?;
+            let field = &rest[start + 1..start + end];
+            if !FIELDS.contains(&field) {
+                return Err(InvalidIndexTemplate(format!("unknown field {{{}}}", field)));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(Self(template.to_string()))
+    }
+
+    pub(crate) fn render(&self, line: &Line) -> String {
+        let dated = Utc
+            .timestamp_millis(line.timestamp)
+            .format(&self.0)
+            .to_string();
+
+        let mut index = String::with_capacity(dated.len());
+        let mut rest = dated.as_str();
+        while let Some(start) = rest.find('{') {
+            index.push_str(&rest[..start]);
+            let end = start + rest[start..].find('}').unwrap_or(0);
+            let value = match &rest[start + 1..end] {
+                "app" => line.get_app(),
+                "host" => line.get_host(),
+                "env" => line.get_env(),
+                "level" => line.get_level(),
+                "file" => line.get_file(),
+                _ => None,
+            };
+            index.push_str(value.filter(|v| !v.is_empty()).unwrap_or(MISSING));
+            rest = &rest[end + 1..];
+        }
+        index.push_str(rest);
+        sanitize(&index)
+    }
+}
+
+/// Index names must be lowercase and can't contain some characters
+fn sanitize(index: &str) -> String {
+    let index: String = index
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | '*' | '?' | '"' | '<' | '>' | '|' | ' ' | ',' | '#' | ':' => '_',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect();
+    index
+        .trim_start_matches(|c| c == '-' || c == '_' || c == '+')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    fn line() -> Line {
+        let mut line = LineBuilder::new()
+            .line("hello")
+            .app("Web Server")
+            .file("/var/log/web.log")
+            .build()
+            .unwrap();
+        line.timestamp = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0).timestamp_millis();
+        line
+    }
+
+    #[test]
+    fn renders_fields_and_date() {
+        let template = IndexTemplate::parse("logs-{app}-{level}-%Y.%m.%d").unwrap();
+        assert_eq!(
+            template.render(&line()),
+            "logs-web_server-unknown-2021.06.01"
+        );
+
+        let template = IndexTemplate::parse("{file}").unwrap();
+        assert_eq!(template.render(&line()), "var_log_web.log");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(IndexTemplate::parse("logs-{tenant}").is_err());
+        assert!(IndexTemplate::parse("logs-{app").is_err());
+        assert!(IndexTemplate::parse("logs-%Q").is_err());
+    }
+}
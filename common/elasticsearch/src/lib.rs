@@ -0,0 +1,3 @@
+mod bulk;
+pub mod index;
+pub mod sink;
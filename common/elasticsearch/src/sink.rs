@@ -0,0 +1,201 @@
+use crate::bulk::{failed_items, BulkBody};
+use crate::index::IndexTemplate;
+
+use http::types::body::Line;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use log::{info, warn};
+use metrics::Metrics;
+use sink::{Policy, PoolOptions, Proxy, ProxyConnector, Queue, SignError, Signer, TlsOptions};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+const MAX_BULK_LINES: usize = 5_000;
+const MAX_BULK_BYTES: usize = 5 * 1024 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Base url of the cluster, e.g. `https://elasticsearch:9200`
+    pub url: String,
+    pub index: IndexTemplate,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Proxy the cluster is connected to through
+    pub proxy: Option<Proxy>,
+    pub tls_options: TlsOptions,
+    pub pool: PoolOptions,
+    /// Signs the requests for gateways in front of the cluster that require it
+    pub signer: Option<Arc<dyn Signer>>,
+    pub policy: Policy,
+}
+
+/// Starts indexing lines into an Elasticsearch or OpenSearch cluster through its `_bulk` API.
+/// Must be called from within a tokio runtime.
+pub fn spawn(options: Options) -> Result<Queue, String> {
+    let uri = format!("{}/_bulk", options.url.trim_end_matches('/'))
+        .parse::<Uri>()
+        .map_err(|e| format!("invalid elasticsearch url {}: {}", options.url, e))?;
+    let authorization = options.username.as_ref().map(|username| {
+        let credentials = format!(
+            "{}:{}",
+            username,
+            options.password.as_deref().unwrap_or_default()
+        );
+        format!("Basic {}", base64::encode(credentials))
+    });
+    info!("indexing lines into elasticsearch at {}", options.url);
+
+    let (queue, rx) = sink::queue("elasticsearch sink", options.policy.buffer_size, || {
+        Metrics::elasticsearch().increment_dropped()
+    });
+    let indexer = Indexer {
+        client: sink::https_client(
+            "elasticsearch sink",
+            options.proxy,
+            &options.tls_options,
+            &options.pool,
+        ),
+        signer: options.signer,
+        uri,
+        authorization,
+        index: options.index,
+        policy: options.policy,
+        body: BulkBody::default(),
+    };
+    tokio::spawn(indexer.run(rx));
+    Ok(queue)
+}
+
+struct Indexer {
+    client: Client<HttpsConnector<ProxyConnector>>,
+    signer: Option<Arc<dyn Signer>>,
+    uri: Uri,
+    authorization: Option<String>,
+    index: IndexTemplate,
+    policy: Policy,
+    body: BulkBody,
+}
+
+enum BulkError {
+    Status(StatusCode),
+    Http(hyper::Error),
+    Sign(SignError),
+}
+
+impl BulkError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            BulkError::Status(status) => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            BulkError::Http(_) => true,
+            BulkError::Sign(_) => false,
+        }
+    }
+}
+
+impl Display for BulkError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BulkError::Status(status) => write!(f, "{}", status),
+            BulkError::Http(e) => write!(f, "{}", e),
+            BulkError::Sign(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Indexer {
+    async fn run(mut self, mut rx: Receiver<Line>) {
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => {
+                        let index = self.index.render(&line);
+                        if let Err(e) = self.body.push(&index, &line) {
+                            warn!("unable to serialize line for elasticsearch: {}", e);
+                        }
+                        if self.body.lines() >= MAX_BULK_LINES
+                            || self.body.bytes() >= MAX_BULK_BYTES
+                        {
+                            self.flush().await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    async fn flush(&mut self) {
+        if self.body.lines() == 0 {
+            return;
+        }
+        let (body, lines) = self.body.take();
+        let lines = lines as u64;
+        Metrics::elasticsearch().increment_requests();
+
+        let response = self
+            .policy
+            .retry_if(
+                "elasticsearch bulk request",
+                || self.send(body.clone()),
+                BulkError::is_retryable,
+            )
+            .await;
+        match response {
+            Some(response) => {
+                let failed = failed_items(&response).unwrap_or_else(|e| {
+                    warn!("unable to parse elasticsearch bulk response: {}", e);
+                    0
+                }) as u64;
+                if failed > 0 {
+                    warn!("elasticsearch rejected {} of {} documents", failed, lines);
+                }
+                Metrics::elasticsearch().add_lines(lines - failed);
+                Metrics::elasticsearch().add_failures(failed);
+            }
+            None => Metrics::elasticsearch().add_failures(lines),
+        }
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<hyper::body::Bytes, BulkError> {
+        let body = hyper::body::Bytes::from(body);
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(hyper::header::CONTENT_TYPE, "application/x-ndjson");
+        if let Some(authorization) = self.authorization.as_ref() {
+            request = request.header(hyper::header::AUTHORIZATION, authorization);
+        }
+        let mut request = request
+            .body(Body::from(body.clone()))
+            .expect("bulk request is valid");
+        if let Some(signer) = self.signer.as_ref() {
+            signer
+                .sign(&mut request, &body)
+                .await
+                .map_err(BulkError::Sign)?;
+        }
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(BulkError::Http)?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(BulkError::Http)?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(BulkError::Status(status))
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::backoff::Backoff;
+
+use futures::Stream;
+use http::types::body::LineBuilder;
+use log::{info, warn};
+use metrics::Metrics;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::{channel, Sender};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A shell command whose stdout lines are shipped as logs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    pub command: String,
+    /// Run the command every `interval`, when unset the command is expected to keep running
+    /// and is restarted whenever it exits
+    pub interval: Option<Duration>,
+    pub app: String,
+}
+
+impl Command {
+    /// Creates a long running command, named after the program it runs
+    pub fn new(command: String) -> Self {
+        let app = command
+            .split_whitespace()
+            .next()
+            .and_then(|program| program.rsplit('/').next())
+            .unwrap_or("exec")
+            .to_string();
+        Self {
+            command,
+            interval: None,
+            app,
+        }
+    }
+}
+
+/// Runs each of `commands` with `sh -c` and streams their stdout, their stderr is logged by
+/// the agent as warnings.
+///
+/// Commands that fail, or long running commands that exit, are restarted with an exponential
+/// backoff. Must be called from within a tokio runtime.
+pub fn create_source(commands: Vec<Command>) -> impl Stream<Item = LineBuilder> {
+    let (tx, rx) = channel(1024);
+    for command in commands {
+        info!("running command {:?}", command.command);
+        tokio::spawn(supervise(command, tx.clone()));
+    }
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    })
+}
+
+async fn supervise(command: Command, tx: Sender<LineBuilder>) {
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    loop {
+        let started = Instant::now();
+        let succeeded = match run(&command, &tx).await {
+            Ok(status) if status.success() => true,
+            Ok(status) => {
+                warn!("command {:?} exited with {}", command.command, status);
+                false
+            }
+            Err(e) => {
+                warn!("unable to run command {:?}: {}", command.command, e);
+                false
+            }
+        };
+        if tx.is_closed() {
+            return;
+        }
+
+        let elapsed = started.elapsed();
+        let delay = match command.interval {
+            Some(interval) => {
+                let remaining = interval.checked_sub(elapsed).unwrap_or_default();
+                if succeeded {
+                    backoff.reset();
+                    remaining
+                } else {
+                    std::cmp::max(remaining, backoff.next_delay())
+                }
+            }
+            None => {
+                // A process that stayed up for a while is considered healthy again
+                if elapsed >= backoff.max() {
+                    backoff.reset();
+                }
+                Metrics::exec().increment_restarts();
+                backoff.next_delay()
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn run(command: &Command, tx: &Sender<LineBuilder>) -> std::io::Result<ExitStatus> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(&command.command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(stderr) = child.stderr.take() {
+        let app = command.app.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).split(b'\n');
+            while let Ok(Some(line)) = lines.next_segment().await {
+                warn!("command {} wrote to stderr: {}", app, decode(&line));
+            }
+        });
+    }
+    if let Some(stdout) = child.stdout.take() {
+        let mut lines = BufReader::new(stdout).split(b'\n');
+        while let Some(line) = lines.next_segment().await? {
+            Metrics::exec().increment_lines();
+            let line = LineBuilder::new().line(decode(&line)).app(&command.app);
+            if tx.send(line).await.is_err() {
+                child.kill().await?;
+                break;
+            }
+        }
+    }
+    child.wait().await
+}
+
+/// Output that isn't valid UTF-8 is shipped with the invalid sequences replaced
+fn decode(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn names_commands_after_their_program() {
+        assert_eq!(Command::new("/usr/bin/vmstat -n 5".into()).app, "vmstat");
+        assert_eq!(Command::new("check.sh".into()).app, "check.sh");
+        assert_eq!(Command::new("".into()).app, "exec");
+    }
+
+    #[tokio::test]
+    async fn ships_stdout_lines() {
+        let mut command = Command::new("echo hello; echo world; echo ignored >&2".into());
+        command.interval = Some(Duration::from_secs(3600));
+        let lines: Vec<_> = create_source(vec![command]).take(2).collect().await;
+
+        assert_eq!(lines[0].line.as_deref(), Some("hello"));
+        assert_eq!(lines[0].app.as_deref(), Some("echo"));
+        assert_eq!(lines[1].line.as_deref(), Some("world"));
+    }
+
+    #[tokio::test]
+    async fn ships_lines_that_are_not_utf8() {
+        let mut command = Command::new("printf 'caf\\351\\r\\nok\\n'".into());
+        command.interval = Some(Duration::from_secs(3600));
+        let lines: Vec<_> = create_source(vec![command]).take(2).collect().await;
+
+        assert_eq!(lines[0].line.as_deref(), Some("caf\u{fffd}"));
+        assert_eq!(lines[1].line.as_deref(), Some("ok"));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::cache::event::Event;
+use crate::cache::EntryKey;
+
+use std::time::Duration;
+
+use futures::future::Either;
+use futures::{Stream, StreamExt};
+use metrics::Metrics;
+use smallvec::SmallVec;
+use tokio::time::Instant;
+
+enum EventOrTick {
+    Event(Event),
+    Tick,
+}
+
+/// Merges `Event::Write` events for the same entry that arrive within `window` into a single
+/// event, so a bursty writer results in one read instead of one read per inotify event.
+///
+/// Any other event flushes the pending writes first, which keeps the relative order of
+/// creates, writes and deletes for an entry intact. A zero window disables coalescing.
+pub fn coalesce_writes<'a, S>(events: S, window: Duration) -> impl Stream<Item = Event> + 'a
+where
+    S: Stream<Item = Event> + 'a,
+{
+    if window == Duration::from_millis(0) {
+        return Either::Left(events);
+    }
+
+    let mut pending: Vec<(Instant, EntryKey)> = Vec::new();
+    let ticks = tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(window));
+
+    Either::Right(
+        futures::stream::select(
+            events.map(EventOrTick::Event),
+            ticks.map(|_| EventOrTick::Tick),
+        )
+        .map(move |event_or_tick| {
+            let mut ready: SmallVec<[Event; 4]> = SmallVec::new();
+            match event_or_tick {
+                EventOrTick::Event(Event::Write(key)) => {
+                    if pending.iter().any(|(_, pending_key)| *pending_key == key) {
+                        Metrics::fs().increment_coalesced_events();
+                    } else {
+                        pending.push((Instant::now(), key));
+                    }
+                }
+                EventOrTick::Event(event) => {
+                    ready.extend(pending.drain(..).map(|(_, key)| Event::Write(key)));
+                    ready.push(event);
+                }
+                EventOrTick::Tick => {
+                    let now = Instant::now();
+                    pending.retain(|(since, key)| {
+                        if now.duration_since(*since) >= window {
+                            ready.push(Event::Write(*key));
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                }
+            }
+            futures::stream::iter(ready)
+        })
+        .flatten(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slotmap::SlotMap;
+
+    fn keys(count: usize) -> Vec<EntryKey> {
+        let mut map: SlotMap<EntryKey, ()> = SlotMap::new();
+        (0..count).map(|_| map.insert(())).collect()
+    }
+
+    #[test]
+    fn coalesces_writes_for_the_same_entry() {
+        let keys = keys(3);
+        let (a, b, c) = (keys[0], keys[1], keys[2]);
+        let events = futures::stream::iter(vec![
+            Event::Write(a),
+            Event::Write(a),
+            Event::Write(b),
+            Event::Write(a),
+            Event::New(c),
+        ]);
+
+        let result = tokio_test::block_on(
+            coalesce_writes(events, Duration::from_millis(50))
+                .take(3)
+                .collect::<Vec<_>>(),
+        );
+
+        assert!(matches!(result[0], Event::Write(k) if k == a));
+        assert!(matches!(result[1], Event::Write(k) if k == b));
+        assert!(matches!(result[2], Event::New(k) if k == c));
+    }
+
+    #[test]
+    fn flushes_pending_writes_after_the_window() {
+        let keys = keys(2);
+        let (a, b) = (keys[0], keys[1]);
+        let events = futures::stream::iter(vec![Event::Write(a), Event::Write(b), Event::Write(b)]);
+
+        let result = tokio_test::block_on(
+            coalesce_writes(events, Duration::from_millis(10))
+                .take(2)
+                .collect::<Vec<_>>(),
+        );
+
+        assert!(matches!(result[0], Event::Write(k) if k == a));
+        assert!(matches!(result[1], Event::Write(k) if k == b));
+    }
+
+    #[test]
+    fn zero_window_passes_events_through() {
+        let keys = keys(1);
+        let events = futures::stream::iter(vec![Event::Write(keys[0]), Event::Write(keys[0])]);
+
+        let result = tokio_test::block_on(
+            coalesce_writes(events, Duration::from_millis(0)).collect::<Vec<_>>(),
+        );
+
+        assert_eq!(result.len(), 2);
+    }
+}
@@ -1,3 +1,4 @@
+use std::convert::TryInto;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
@@ -6,17 +7,33 @@ use thiserror::Error;
 pub enum DirPathBufError {
     #[error("{0:?} is not a directory")]
     NotADirPath(PathBuf),
+    #[error("{0:?} is not an absolute path")]
+    NotAbsolute(PathBuf),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 }
 
 // Strongly typed wrapper around PathBuf, cannot be constructed unless
-// the directory it's referring to exists
+// the directory it's referring to exists or is expected to be created
 #[derive(std::fmt::Debug, Clone)]
 pub struct DirPathBuf {
     inner: PathBuf,
 }
 
+impl DirPathBuf {
+    /// Wraps a directory that doesn't exist yet, e.g. a volume mounted after the agent
+    /// started, its closest existing parent is watched until it's created
+    pub fn not_yet_created(path: PathBuf) -> Result<Self, DirPathBufError> {
+        if path.exists() {
+            return path.try_into();
+        }
+        if !path.is_absolute() {
+            return Err(DirPathBufError::NotAbsolute(path));
+        }
+        Ok(DirPathBuf { inner: path })
+    }
+}
+
 impl Deref for DirPathBuf {
     type Target = Path;
     fn deref(&self) -> &Path {
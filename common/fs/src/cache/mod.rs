@@ -1,5 +1,6 @@
 use crate::cache::entry::Entry;
 use crate::cache::event::Event;
+use crate::cache::mounts::Mounts;
 use crate::cache::tailed_file::TailedFile;
 use crate::cache::watch::{WatchEvent, Watcher};
 use crate::rule::{GlobRule, Rules, Status};
@@ -12,6 +13,7 @@ use std::ops::Deref;
 use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{fmt, io};
 
 use futures::{Stream, StreamExt};
@@ -22,12 +24,14 @@ use std::collections::hash_map::Entry as HashMapEntry;
 use std::collections::HashMap;
 use thiserror::Error;
 
+pub mod coalesce;
 pub mod dir_path;
 pub mod entry;
 pub mod event;
 pub mod tailed_file;
 pub use dir_path::{DirPathBuf, DirPathBufError};
 
+mod mounts;
 mod watch;
 
 type Children = HashMap<OsString, EntryKey>;
@@ -39,6 +43,9 @@ pub type EntryKey = DefaultKey;
 type EntryMap = SlotMap<EntryKey, entry::Entry>;
 type FsResult<T> = Result<T, Error>;
 
+/// How often the mount table is checked for filesystems mounted at or beneath the initial dirs
+const MOUNT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("error watching: {0:?} {1:?}")]
@@ -76,12 +83,14 @@ pub struct FileSystem {
     initial_dir_rules: Rules,
 
     initial_events: Vec<Event>,
+    mounts: Option<Mounts>,
 }
 
 impl FileSystem {
     pub fn new(initial_dirs: Vec<DirPathBuf>, rules: Rules) -> Self {
         initial_dirs.iter().for_each(|path| {
-            if !path.is_dir() {
+            // The dirs that don't exist yet are picked up once created
+            if path.exists() && !path.is_dir() {
                 panic!("initial dirs must be dirs")
             }
         });
@@ -110,6 +119,7 @@ impl FileSystem {
             initial_dir_rules,
             watcher,
             initial_events: Vec::new(),
+            mounts: Mounts::new(),
         };
 
         let entries = fs.entries.clone();
@@ -199,24 +209,110 @@ impl FileSystem {
             acc
         };
 
-        let events = events_stream.into_stream().map(move |event| {
+        let events = events_stream.into_stream().map({
             let fs = fs.clone();
-            {
-                let mut acc = Vec::new();
-
-                match event {
-                    Ok(event) => {
-                        fs.try_lock()
-                            .expect("couldn't lock filesystem cache")
-                            .process(event, &mut acc);
-                        futures::stream::iter(acc)
+            move |event| {
+                let fs = fs.clone();
+                {
+                    let mut acc = Vec::new();
+
+                    match event {
+                        Ok(event) => {
+                            fs.try_lock()
+                                .expect("couldn't lock filesystem cache")
+                                .process(event, &mut acc);
+                            futures::stream::iter(acc)
+                        }
+                        _ => panic!("Inotify error"),
                     }
-                    _ => panic!("Inotify error"),
                 }
             }
         });
 
-        Ok(futures::stream::iter(initial_events).chain(events.flatten()))
+        let mounts =
+            tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(MOUNT_POLL_INTERVAL))
+                .map(move |_| {
+                    let mut acc = Vec::new();
+                    fs.try_lock()
+                        .expect("couldn't lock filesystem cache")
+                        .process_mounts(&mut acc);
+                    futures::stream::iter(acc)
+                });
+
+        Ok(futures::stream::iter(initial_events)
+            .chain(futures::stream::select(events, mounts).flatten()))
+    }
+
+    /// Scans again the initial dirs a filesystem was mounted at or beneath since the last poll,
+    /// the entries under the mount point were of the dir it covers
+    fn process_mounts(&mut self, events: &mut Vec<Event>) {
+        let mounted = match self.mounts.as_mut() {
+            Some(mounts) => mounts.poll(),
+            None => return,
+        };
+        let mut rescans: Vec<PathBuf> = Vec::new();
+        for mount_point in mounted {
+            for dir in self.initial_dirs.iter().map(|dir| dir.as_ref()) {
+                // The dir itself when the mount covers it, the mount point when it's within
+                let rescan = if dir.starts_with(&mount_point) {
+                    dir.to_path_buf()
+                } else if mount_point.starts_with(dir) {
+                    mount_point.clone()
+                } else {
+                    continue;
+                };
+                if !rescans.contains(&rescan) {
+                    info!(
+                        "rescanning {:?}, a filesystem was mounted at {:?}",
+                        rescan, mount_point
+                    );
+                    rescans.push(rescan);
+                }
+            }
+        }
+
+        let entries = self.entries.clone();
+        let mut entries = entries.borrow_mut();
+        for path in rescans {
+            if let Err(e) = self.rescan(&path, events, &mut entries) {
+                warn!("Rescanning {:?} resulted in error: {}", path, e);
+            }
+        }
+    }
+
+    /// Drops the entries under `path` and inserts the ones found there now, the files found are
+    /// initialized like the ones found on startup
+    fn rescan(
+        &mut self,
+        path: &Path,
+        events: &mut Vec<Event>,
+        _entries: &mut EntryMap,
+    ) -> FsResult<()> {
+        if self.lookup(path, _entries).is_some() {
+            self.remove(path, events, _entries)?;
+        }
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let mut inserted = Vec::new();
+        let mut errors = vec![];
+        for path in recursive_scan(path) {
+            if let Err(e) = self.insert(&path, &mut inserted, _entries) {
+                errors.push(e);
+            }
+        }
+        for event in inserted {
+            match event {
+                Event::New(entry_key) => events.push(Event::Initialize(entry_key)),
+                event => events.push(event),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::InsertRecursively(errors));
+        }
+        Ok(())
     }
 
     /// Handles inotify events and may produce Event(s) that are returned upstream through sender
@@ -242,10 +338,11 @@ impl FileSystem {
                 to_name,
             } => {
                 // directories can't have hard links so we can expect just one entry for these watch
-                // descriptors
+                // descriptors. The rules were checked when the moved entry was added, its new path
+                // is checked against them again
                 let is_from_path_ok = self
                     .get_first_entry(&from_wd)
-                    .map(|entry| self.entry_path_passes(entry, &from_name, &_entries))
+                    .map(|entry| self.entry_path_tracked(entry, &from_name, &_entries))
                     .unwrap_or(false);
 
                 let is_to_path_ok = self
@@ -373,8 +470,41 @@ impl FileSystem {
         to_path.push(to_name);
 
         // the entry is expected to exist
-        self.rename(&from_path, &to_path, events, _entries)
-            .map(|_| ())
+        self.rename(&from_path, &to_path, events, _entries)?;
+        self.reevaluate(&to_path, events, _entries)
+    }
+
+    /// Checks the rules again for a renamed entry and the ones under it, removing the ones that
+    /// no longer pass and adding the ones that now do
+    fn reevaluate(
+        &mut self,
+        path: &Path,
+        events: &mut Vec<Event>,
+        _entries: &mut EntryMap,
+    ) -> FsResult<()> {
+        let paths = if path.is_dir() {
+            recursive_scan(path)
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut errors = vec![];
+        for path in paths {
+            let tracked = self.lookup(&path, _entries).is_some();
+            let result = match (tracked, self.passes(&path, _entries)) {
+                (true, false) => self.remove(&path, events, _entries),
+                (false, true) => self.insert(&path, events, _entries).map(|_| ()),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::InsertRecursively(errors));
+        }
+        Ok(())
     }
 
     pub fn resolve_direct_path(&self, entry: &Entry, _entries: &EntryMap) -> PathBuf {
@@ -955,6 +1085,16 @@ impl FileSystem {
         }
     }
 
+    fn entry_path_tracked(&self, entry: EntryKey, name: &OsStr, _entries: &EntryMap) -> bool {
+        if let Some(entry_ref) = _entries.get(entry) {
+            let mut path = self.resolve_direct_path(entry_ref, _entries);
+            path.push(name);
+            self.lookup(&path, _entries).is_some()
+        } else {
+            false
+        }
+    }
+
     /// Returns the first entry based on the `WatchDescriptor`, returning an `Err` when not found.
     fn get_first_entry(&self, wd: &WatchDescriptor) -> FsResult<EntryKey> {
         let entries = self
@@ -1184,6 +1324,88 @@ mod tests {
         });
     }
 
+    #[test]
+    fn filesystem_picks_up_dirs_created_later() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let pods = tempdir.path().join("pods");
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            let initial_dirs = vec![DirPathBuf::not_yet_created(pods.clone()).unwrap()];
+            let fs = Arc::new(Mutex::new(FileSystem::new(initial_dirs, rules)));
+
+            let sibling = tempdir.path().join("other.log");
+            File::create(&sibling).unwrap();
+            create_dir(&pods).unwrap();
+            take_events!(fs, 1);
+
+            let a = pods.join("a.log");
+            File::create(&a).unwrap();
+            take_events!(fs, 1);
+
+            assert!(lookup_entry!(fs, pods).is_some());
+            assert!(lookup_entry!(fs, a).is_some());
+            assert!(lookup_entry!(fs, sibling).is_none());
+        });
+    }
+
+    #[test]
+    fn filesystem_rescans_mounted_dirs() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let mut fs = new_fs::<()>(path.clone(), None);
+
+            // Created without an event being processed, as under a dir mounted over the path
+            let a = path.join("a.log");
+            File::create(&a).unwrap();
+            let entries = fs.entries.clone();
+            let mut entries = entries.borrow_mut();
+            assert!(fs.lookup(&a, &entries).is_none());
+
+            let mut events = Vec::new();
+            fs.rescan(&path, &mut events, &mut entries).unwrap();
+            assert!(fs.lookup(&path, &entries).is_some());
+            assert!(fs.lookup(&a, &entries).is_some());
+            assert!(matches!(events.as_slice(), [Event::Initialize(_)]));
+        });
+    }
+
+    #[test]
+    fn filesystem_reevaluates_rules_on_rename() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            rules.add_exclusion(GlobRule::new(r"**/*.gz").unwrap());
+            rules.add_exclusion(GlobRule::new(r"**/archive/**").unwrap());
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), Some(rules))));
+
+            let current = path.join("current");
+            create_dir(&current).unwrap();
+            File::create(current.join("a.log")).unwrap();
+            File::create(path.join("b.log")).unwrap();
+            take_events!(fs, 2);
+            assert!(lookup_entry!(fs, current.join("a.log")).is_some());
+
+            // Moved with the dir into an excluded one
+            let archive = path.join("archive");
+            rename(&current, &archive).unwrap();
+            take_events!(fs, 1);
+            assert!(lookup_entry!(fs, archive).is_some());
+            assert!(lookup_entry!(fs, archive.join("a.log")).is_none());
+
+            // Out of and back into the included files
+            rename(path.join("b.log"), path.join("b.log.gz")).unwrap();
+            take_events!(fs, 1);
+            assert!(lookup_entry!(fs, path.join("b.log.gz")).is_none());
+            rename(path.join("b.log.gz"), path.join("b.log")).unwrap();
+            take_events!(fs, 1);
+            assert!(lookup_entry!(fs, path.join("b.log")).is_some());
+        });
+    }
+
     // Simulates the `create_copy` log rotation strategy
     #[test]
     fn filesystem_rotate_create_copy() {
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// The mount points of the filesystems mounted so far, to find the ones mounted since the
+/// last poll. inotify doesn't report mounts, the files of a volume mounted over a watched dir
+/// would go unnoticed otherwise.
+pub(crate) struct Mounts {
+    known: HashSet<PathBuf>,
+}
+
+impl Mounts {
+    /// None when the mount table can't be read, e.g. without /proc
+    pub(crate) fn new() -> Option<Self> {
+        match std::fs::read_to_string(MOUNTINFO) {
+            Ok(mountinfo) => Some(Mounts {
+                known: parse_mount_points(&mountinfo),
+            }),
+            Err(e) => {
+                info!(
+                    "not watching for new mounts, can't read {}: {}",
+                    MOUNTINFO, e
+                );
+                None
+            }
+        }
+    }
+
+    /// The mount points that appeared since the last poll
+    pub(crate) fn poll(&mut self) -> Vec<PathBuf> {
+        match std::fs::read_to_string(MOUNTINFO) {
+            Ok(mountinfo) => self.update(parse_mount_points(&mountinfo)),
+            Err(e) => {
+                debug!("failed to read {}: {}", MOUNTINFO, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn update(&mut self, current: HashSet<PathBuf>) -> Vec<PathBuf> {
+        let mut mounted: Vec<PathBuf> = current.difference(&self.known).cloned().collect();
+        mounted.sort();
+        self.known = current;
+        mounted
+    }
+}
+
+/// The mount point of each line of a mountinfo file, the fifth field
+fn parse_mount_points(mountinfo: &str) -> HashSet<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|mount_point| PathBuf::from(unescape(mount_point)))
+        .collect()
+}
+
+/// Spaces, tabs, newlines and backslashes are written as octal escapes, e.g. `\040`
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(start) = rest.find('\\') {
+        unescaped.push_str(&rest[..start]);
+        let code = rest
+            .get(start + 1..start + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code as char);
+                rest = &rest[start + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:5 / /proc rw,nosuid shared:12 - proc proc rw
+";
+
+    #[test]
+    fn finds_new_mount_points() {
+        let known = parse_mount_points(MOUNTINFO);
+        assert_eq!(known.len(), 2);
+        assert!(known.contains(&PathBuf::from("/proc")));
+        let mut mounts = Mounts { known };
+
+        let mounted = format!(
+            "{}{}",
+            MOUNTINFO,
+            "31 22 8:17 / /var/log/containers rw shared:20 - ext4 /dev/sdb1 rw\n\
+             32 22 8:33 / /mnt/app\\040logs rw shared:21 - ext4 /dev/sdc1 rw\n"
+        );
+        assert_eq!(
+            mounts.update(parse_mount_points(&mounted)),
+            vec![
+                PathBuf::from("/mnt/app logs"),
+                PathBuf::from("/var/log/containers")
+            ]
+        );
+        assert!(mounts.update(parse_mount_points(&mounted)).is_empty());
+        assert!(mounts.update(parse_mount_points(MOUNTINFO)).is_empty());
+    }
+}
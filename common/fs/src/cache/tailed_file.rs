@@ -1,3 +1,4 @@
+use http::intern::intern;
 use http::types::body::{KeyValueMap, LineBufferMut, LineBuilder, LineMeta, LineMetaMut};
 use http::types::error::LineMetaError;
 use http::types::serialize::{
@@ -7,13 +8,18 @@ use http::types::serialize::{
 
 use state::GetOffset;
 
+use crate::lag;
+use crate::mmap::{self, MappedRegion};
+use crate::recreated::Fingerprint;
+use crate::throttle;
+
 use chrono::Utc;
 use metrics::Metrics;
 
 use futures::io::AsyncBufRead;
 use futures::lock::Mutex;
 use futures::task::{Context, Poll};
-use futures::{ready, Stream, StreamExt};
+use futures::{ready, Future, Stream, StreamExt};
 
 use async_trait::async_trait;
 use pin_project_lite::pin_project;
@@ -33,6 +39,7 @@ use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::io::{AsyncSeekExt, BufReader, SeekFrom};
+use tokio::time::Sleep;
 use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
 fn read_until_internal<R: AsyncBufRead + ?Sized>(
@@ -141,17 +148,19 @@ pub struct LazyLines {
     current_offset: Option<(u64, u64)>,
     read: usize,
     path: usize,
-    paths: Vec<String>,
+    paths: Vec<Arc<str>>,
+    throttle_delay: Option<Pin<Box<Sleep>>>,
 }
 
 impl LazyLines {
-    pub fn new(reader: Arc<Mutex<TailedFileInner>>, paths: Vec<String>) -> Self {
+    pub fn new(reader: Arc<Mutex<TailedFileInner>>, paths: Vec<Arc<str>>) -> Self {
         Self {
             reader,
             current_offset: None,
             read: 0,
             path: 0,
             paths,
+            throttle_delay: None,
         }
     }
 }
@@ -159,13 +168,14 @@ impl LazyLines {
 #[derive(Debug)]
 pub struct LazyLineSerializer {
     annotations: Option<KeyValueMap>,
-    app: Option<String>,
-    env: Option<String>,
-    host: Option<String>,
+    // The metadata values are repeated across the lines of a file, they are interned
+    app: Option<Arc<str>>,
+    env: Option<Arc<str>>,
+    host: Option<Arc<str>>,
     labels: Option<KeyValueMap>,
-    level: Option<String>,
+    level: Option<Arc<str>>,
     meta: Option<Value>,
-    path: String,
+    path: Arc<str>,
     line_buffer: Option<Bytes>,
 
     file_offset: (u64, u64),
@@ -314,7 +324,7 @@ impl IngestLineSerialize<String, bytes::Bytes, std::collections::HashMap<String,
 }
 
 impl LazyLineSerializer {
-    pub fn new(reader: Arc<Mutex<TailedFileInner>>, path: String, offset: (u64, u64)) -> Self {
+    pub fn new(reader: Arc<Mutex<TailedFileInner>>, path: Arc<str>, offset: (u64, u64)) -> Self {
         Self {
             reader,
             path,
@@ -342,7 +352,7 @@ impl LineMeta for LazyLineSerializer {
         self.env.as_deref()
     }
     fn get_file(&self) -> Option<&str> {
-        Some(self.path.as_str())
+        Some(self.path.as_ref())
     }
     fn get_host(&self) -> Option<&str> {
         self.host.as_deref()
@@ -364,19 +374,19 @@ impl LineMetaMut for LazyLineSerializer {
         Ok(())
     }
     fn set_app(&mut self, app: String) -> Result<(), LineMetaError> {
-        self.app = Some(app);
+        self.app = Some(intern(&app));
         Ok(())
     }
     fn set_env(&mut self, env: String) -> Result<(), LineMetaError> {
-        self.env = Some(env);
+        self.env = Some(intern(&env));
         Ok(())
     }
     fn set_file(&mut self, file: String) -> Result<(), LineMetaError> {
-        self.path = file;
+        self.path = intern(&file);
         Ok(())
     }
     fn set_host(&mut self, host: String) -> Result<(), LineMetaError> {
-        self.host = Some(host);
+        self.host = Some(intern(&host));
         Ok(())
     }
     fn set_labels(&mut self, labels: KeyValueMap) -> Result<(), LineMetaError> {
@@ -384,7 +394,7 @@ impl LineMetaMut for LazyLineSerializer {
         Ok(())
     }
     fn set_level(&mut self, level: String) -> Result<(), LineMetaError> {
-        self.level = Some(level);
+        self.level = Some(intern(&level));
         Ok(())
     }
     fn set_meta(&mut self, meta: Value) -> Result<(), LineMetaError> {
@@ -423,6 +433,9 @@ impl GetOffset for LazyLineSerializer {
     fn get_key(&self) -> Option<u64> {
         Some(self.file_offset.0)
     }
+    fn get_line_hash(&self) -> Option<u64> {
+        self.line_buffer.as_deref().map(state::line_hash)
+    }
 }
 
 impl Stream for LazyLines {
@@ -436,7 +449,7 @@ impl Stream for LazyLines {
             ref mut current_offset,
             ref mut path,
             paths,
-            ..
+            ref mut throttle_delay,
         } = self.get_mut();
         let rc_reader = reader;
         loop {
@@ -450,11 +463,21 @@ impl Stream for LazyLines {
                 Metrics::fs().increment_lines();
                 break Poll::Ready(Some(ret));
             }
+            // Wait for the global read throttle before touching the disk
+            if let Some(delay) = throttle_delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                *throttle_delay = None;
+            }
+            if let Some(delay) = throttle::read_delay() {
+                *throttle_delay = Some(Box::pin(tokio::time::sleep(delay)));
+                continue;
+            }
             // Get the next line
             let mut borrow = rc_reader.try_lock().unwrap();
             let TailedFileInner {
                 ref mut reader,
                 ref mut buf,
+                ref mut mapped,
                 ref mut offset,
                 ref inode,
                 ..
@@ -467,15 +490,18 @@ impl Stream for LazyLines {
                 buf.clear();
             }
 
-            let pinned_reader = Pin::new(reader);
-            let result = ready!(read_until_internal(pinned_reader, cx, b'\n', buf, read));
-            match result {
+            let result = match mapped {
+                Some(mapped) => read_until_internal(Pin::new(mapped), cx, b'\n', buf, read),
+                None => read_until_internal(Pin::new(reader), cx, b'\n', buf, read),
+            };
+            match ready!(result) {
                 Ok(Some(count)) => {
                     // Got a line
                     debug_assert_eq!(*read, 0);
                     debug!("tailer sendings lines for {:?}", &paths);
                     let count = TryInto::<u64>::try_into(count.get()).unwrap();
                     Metrics::fs().add_bytes(count);
+                    throttle::consume(count);
                     *offset += count;
                     *current_offset = Some((*inode, *offset))
                 }
@@ -483,7 +509,12 @@ impl Stream for LazyLines {
                 // will implicitly retry
                 Err(e) => warn!("{}", e),
                 // Reached the end of the file, but havn't hit a newline yet
-                Ok(None) => break Poll::Ready(None),
+                Ok(None) => {
+                    if let Some(path) = paths.first() {
+                        lag::observe(path, 0);
+                    }
+                    break Poll::Ready(None);
+                }
             }
         }
     }
@@ -492,6 +523,8 @@ impl Stream for LazyLines {
 #[derive(Debug)]
 pub struct TailedFileInner {
     reader: Compat<tokio::io::BufReader<tokio::fs::File>>,
+    /// The backlog being read through a memory map instead of the reader
+    mapped: Option<MappedRegion>,
     buf: Vec<u8>,
     offset: u64,
     file_path: PathBuf,
@@ -512,6 +545,7 @@ impl<T> TailedFile<T> {
                     OpenOptions::new().read(true).open(path)?,
                 ))
                 .compat(),
+                mapped: None,
                 buf: Vec::new(),
                 offset: 0,
                 file_path: path.into(),
@@ -523,6 +557,7 @@ impl<T> TailedFile<T> {
     pub(crate) async fn seek(&mut self, offset: u64) -> Result<(), std::io::Error> {
         let mut inner = self.inner.lock().await;
         inner.offset = offset;
+        inner.mapped = None;
         inner
             .reader
             .get_mut()
@@ -535,6 +570,20 @@ impl<T> TailedFile<T> {
         let inner = self.inner.lock().await;
         inner.inode
     }
+    /// The length of a deleted file, which it's read up to, and the fingerprint of its start
+    pub(crate) async fn deleted_position(&self) -> io::Result<(u64, Option<Fingerprint>)> {
+        let inner = self.inner.lock().await;
+        let file = inner
+            .reader
+            .get_ref()
+            .get_ref()
+            .try_clone()
+            .await?
+            .into_std()
+            .await;
+        let len = file.metadata()?.len();
+        Ok((len, Fingerprint::read(&file, len)?))
+    }
 }
 
 impl TailedFile<LineBuilder> {
@@ -557,6 +606,10 @@ impl TailedFile<LineBuilder> {
                     return None;
                 }
             };
+            lag::observe(
+                &paths[0].to_string_lossy(),
+                len.saturating_sub(inner.offset),
+            );
 
             // if we are at the end of the file there's no work to do
             if inner.offset == len {
@@ -618,19 +671,21 @@ impl TailedFile<LineBuilder> {
 }
 
 impl TailedFile<LazyLineSerializer> {
-    // tail a file for new line(s)
+    // tail a file for new line(s), a backlog of `mmap_threshold` bytes or more of a quiet file is
+    // read through a memory map
     pub(crate) async fn tail(
         &mut self,
         paths: Vec<PathBuf>,
+        mmap_threshold: Option<u64>,
     ) -> Option<impl Stream<Item = LazyLineSerializer>> {
         let mut inner = self.inner.lock().await;
-        let len = match inner
+        let (len, modified) = match inner
             .reader
             .get_ref()
             .get_ref()
             .metadata()
             .await
-            .map(|m| m.len())
+            .map(|m| (m.len(), m.modified().ok()))
         {
             Ok(v) => v,
             Err(e) => {
@@ -638,6 +693,10 @@ impl TailedFile<LazyLineSerializer> {
                 return None;
             }
         };
+        lag::observe(
+            &paths[0].to_string_lossy(),
+            len.saturating_sub(inner.offset),
+        );
 
         // if we are at the end of the file there's no work to do
         if inner.offset == len {
@@ -654,6 +713,7 @@ impl TailedFile<LazyLineSerializer> {
             // Reset offset back to the start... ish?
             // TODO: Work out the purpose of the 8192 something to do with lookback? That seems wrong.
             inner.offset = if len < 8192 { 0 } else { len };
+            inner.mapped = None;
             // seek to the offset, this creates the "tailing" effect
             let offset = inner.offset;
             if let Err(e) = inner
@@ -668,11 +728,37 @@ impl TailedFile<LazyLineSerializer> {
             }
         }
 
+        // Carry on after a mapped region read to its end with the reader, or map the rest of the
+        // backlog again if it's still a large one
+        if let Some(end) = inner
+            .mapped
+            .as_ref()
+            .filter(|mapped| mapped.is_exhausted())
+            .map(MappedRegion::end)
+        {
+            inner.mapped = None;
+            if let Err(e) = inner.reader.get_mut().seek(SeekFrom::Start(end)).await {
+                error!("error seeking {:?}", e);
+                return None;
+            }
+        }
+        let backlog = len.saturating_sub(inner.offset);
+        if inner.mapped.is_none() && mmap::should_map(backlog, mmap_threshold, modified) {
+            // The position of the reader, as the offset is behind it while a line is partly read
+            match inner.reader.get_mut().seek(SeekFrom::Current(0)).await {
+                Ok(start) => match inner.reader.get_ref().get_ref().try_clone().await {
+                    Ok(file) => inner.mapped = MappedRegion::map(file.into_std().await, start, len),
+                    Err(e) => debug!("unable to map {:?}: {}", &paths[0], e),
+                },
+                Err(e) => debug!("unable to get the position of {:?}: {}", &paths[0], e),
+            }
+        }
+
         Some(LazyLines::new(
             self.inner.clone(),
             paths
                 .into_iter()
-                .map(|path| path.to_string_lossy().into())
+                .map(|path| intern(&path.to_string_lossy()))
                 .collect(),
         ))
     }
@@ -740,11 +826,12 @@ mod tests {
                     .unwrap(),
             ))
             .compat(),
+            mapped: None,
             buf: Vec::new(),
             offset: 0,
             file_path,
             inode: 0,
         }));
-        LazyLineSerializer::new(file_inner, "file/path.log".to_owned(), (0, 0))
+        LazyLineSerializer::new(file_inner, "file/path.log".into(), (0, 0))
     }
 }
@@ -0,0 +1,192 @@
+use metrics::Metrics;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref LAG_MONITOR: LagMonitor = LagMonitor::new();
+}
+
+/// How far behind a file can fall before it's reported as lagging
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LagThreshold {
+    /// Bytes written to the file and not read yet
+    pub bytes: u64,
+    /// How long the file has to stay over `bytes`
+    pub duration: Duration,
+}
+
+/// Sets the threshold over which files are counted in the `files_lagging` gauge, None stops
+/// tracking them
+pub fn set_lag_threshold(threshold: Option<LagThreshold>) {
+    LAG_MONITOR.set_threshold(threshold);
+}
+
+/// Records the bytes of the file at `path` that are yet to be read
+pub(crate) fn observe(path: &str, backlog: u64) {
+    LAG_MONITOR.observe(path, backlog, Instant::now());
+}
+
+/// Stops tracking the file at `path`, which was deleted or rotated away, so that it isn't
+/// counted as lagging forever
+pub(crate) fn forget(path: &str) {
+    LAG_MONITOR.forget(path);
+}
+
+/// Updates the `files_lagging` gauge, logging the files that started lagging since the last check
+pub fn check() {
+    let lagging = LAG_MONITOR.check(Instant::now());
+    if !lagging.is_empty() {
+        let files: Vec<String> = lagging
+            .iter()
+            .map(|(path, bytes)| format!("{} ({} bytes)", path, bytes))
+            .collect();
+        warn!("collection is falling behind on {}", files.join(", "));
+    }
+}
+
+/// A file over the threshold
+struct Backlog {
+    bytes: u64,
+    over_since: Instant,
+    reported: bool,
+}
+
+struct LagMonitor {
+    threshold: Mutex<Option<LagThreshold>>,
+    files: Mutex<HashMap<String, Backlog>>,
+}
+
+impl LagMonitor {
+    fn new() -> Self {
+        Self {
+            threshold: Mutex::new(None),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn set_threshold(&self, threshold: Option<LagThreshold>) {
+        *self.threshold.lock().expect("Couldn't lock lag threshold") = threshold;
+        self.files
+            .lock()
+            .expect("Couldn't lock lagging files")
+            .clear();
+    }
+
+    fn observe(&self, path: &str, backlog: u64, now: Instant) {
+        let threshold = match *self.threshold.lock().expect("Couldn't lock lag threshold") {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        let mut files = self.files.lock().expect("Couldn't lock lagging files");
+        if backlog <= threshold.bytes {
+            if matches!(files.remove(path), Some(file) if file.reported) {
+                info!("collection caught up on {}", path);
+            }
+            return;
+        }
+        match files.get_mut(path) {
+            Some(file) => file.bytes = backlog,
+            None => {
+                files.insert(
+                    path.to_string(),
+                    Backlog {
+                        bytes: backlog,
+                        over_since: now,
+                        reported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    fn forget(&self, path: &str) {
+        self.files
+            .lock()
+            .expect("Couldn't lock lagging files")
+            .remove(path);
+    }
+
+    /// The files that have been over the threshold for long enough and weren't reported yet,
+    /// with their backlog
+    fn check(&self, now: Instant) -> Vec<(String, u64)> {
+        let threshold = match *self.threshold.lock().expect("Couldn't lock lag threshold") {
+            Some(threshold) => threshold,
+            None => return Vec::new(),
+        };
+        let mut files = self.files.lock().expect("Couldn't lock lagging files");
+        let mut lagging = 0;
+        let mut started = Vec::new();
+        for (path, file) in files.iter_mut() {
+            if now.duration_since(file.over_since) < threshold.duration {
+                continue;
+            }
+            lagging += 1;
+            if !file.reported {
+                file.reported = true;
+                started.push((path.clone(), file.bytes));
+            }
+        }
+        Metrics::fs().set_files_lagging(lagging);
+        started.sort();
+        started
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_files_over_the_threshold_for_long_enough() {
+        let monitor = LagMonitor::new();
+        let start = Instant::now();
+        monitor.observe("/var/log/a.log", 5000, start);
+        assert!(monitor.check(start + Duration::from_secs(60)).is_empty());
+
+        monitor.set_threshold(Some(LagThreshold {
+            bytes: 1000,
+            duration: Duration::from_secs(10),
+        }));
+        monitor.observe("/var/log/a.log", 5000, start);
+        monitor.observe("/var/log/b.log", 1000, start);
+        assert!(monitor.check(start + Duration::from_secs(5)).is_empty());
+
+        monitor.observe("/var/log/a.log", 8000, start + Duration::from_secs(6));
+        assert_eq!(
+            monitor.check(start + Duration::from_secs(10)),
+            vec![("/var/log/a.log".to_string(), 8000)]
+        );
+        // Only reported once while it stays behind
+        assert!(monitor.check(start + Duration::from_secs(20)).is_empty());
+
+        monitor.observe("/var/log/a.log", 0, start + Duration::from_secs(21));
+        monitor.observe("/var/log/a.log", 2000, start + Duration::from_secs(22));
+        assert!(monitor.check(start + Duration::from_secs(30)).is_empty());
+        assert_eq!(monitor.check(start + Duration::from_secs(32)).len(), 1);
+    }
+
+    #[test]
+    fn forgets_deleted_files() {
+        let monitor = LagMonitor::new();
+        let start = Instant::now();
+        monitor.set_threshold(Some(LagThreshold {
+            bytes: 1000,
+            duration: Duration::from_secs(10),
+        }));
+        monitor.observe("/var/log/a.log", 5000, start);
+        monitor.observe("/var/log/b.log", 5000, start);
+        assert_eq!(monitor.check(start + Duration::from_secs(10)).len(), 2);
+
+        monitor.forget("/var/log/a.log");
+        assert_eq!(monitor.files.lock().unwrap().len(), 1);
+        // Observed again once a file of the same name is tailed, it starts over
+        monitor.observe("/var/log/a.log", 5000, start + Duration::from_secs(15));
+        assert!(monitor.check(start + Duration::from_secs(20)).is_empty());
+        assert_eq!(
+            monitor.check(start + Duration::from_secs(25)),
+            vec![("/var/log/a.log".to_string(), 5000)]
+        );
+    }
+}
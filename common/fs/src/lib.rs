@@ -8,12 +8,22 @@ extern crate lazy_static;
 pub mod cache;
 /// Contains the error type(s) for this crate
 pub mod error;
+/// Tracks the files whose unread backlog stays over a threshold
+pub mod lag;
+/// Maps large backlogs of files into memory to read them
+pub mod mmap;
+/// Priority classes and the weighted scheduler used to read files under backpressure
+pub mod priority;
+/// What's done with files created again at the path of one just deleted
+pub mod recreated;
 /// Traits and types for defining exclusion and inclusion rules
 pub mod rule;
 /// Defines the source implementation for fs
 pub mod source;
 /// Defines the tailer used to tail directories or single files
 pub mod tail;
+/// Global limit on the rate files are read from disk
+pub mod throttle;
 
 #[cfg(test)]
 pub mod test {
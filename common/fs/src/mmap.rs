@@ -0,0 +1,186 @@
+use futures::io::{AsyncBufRead, AsyncRead};
+use memmap2::{Mmap, MmapOptions};
+use metrics::Metrics;
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+/// How long a file has to go unmodified before it's mapped, files still being written to are
+/// read with buffered reads
+const QUIET_PERIOD: Duration = Duration::from_secs(5);
+
+/// Bytes mapped at most at once, to stay clear of the address space limits of 32 bit targets.
+/// A larger backlog is mapped again once these are read.
+const MAX_MAPPED_BYTES: u64 = 1 << 30;
+
+/// Bytes of a region read at most before checking again that the file still covers them
+const CHECKED_BYTES: usize = 1 << 20;
+
+/// Whether a backlog of `bytes` of a file last modified at `modified` is to be mapped, it has
+/// to be at least `threshold` bytes, None never maps files, and the file has to be quiet
+pub(crate) fn should_map(bytes: u64, threshold: Option<u64>, modified: Option<SystemTime>) -> bool {
+    let over = threshold.map_or(false, |threshold| bytes >= threshold);
+    over && modified
+        .and_then(|modified| modified.elapsed().ok())
+        .map_or(false, |elapsed| elapsed >= QUIET_PERIOD)
+}
+
+/// The unread part of a file mapped into memory, read in place of buffered reads while catching
+/// up on a large backlog: lines are searched for across the whole region with the SIMD search
+/// of memchr instead of a buffer at a time, without copying the file into buffers first.
+///
+/// Reading the pages of a region past the end of a file truncated meanwhile raises SIGBUS and
+/// kills the agent. Files are only mapped once they haven't been modified for a while, and the
+/// length of the file is checked again every `CHECKED_BYTES`, the region ending early when the
+/// file no longer covers it. This narrows the window for a truncation to crash the agent but
+/// doesn't close it, which is why mapping is off unless a threshold is set.
+#[derive(Debug)]
+pub(crate) struct MappedRegion {
+    mmap: Mmap,
+    /// The mapped file, whose length is checked before reading further into the region
+    file: File,
+    pos: usize,
+    /// Bytes of the region read, short of the mapped ones when the file was truncated
+    len: usize,
+    /// The end of the bytes of the region the file covered when last checked
+    checked: usize,
+    /// Offset in the file the region starts at
+    start: u64,
+}
+
+impl MappedRegion {
+    /// Maps `file` from `start` up to `len`, or `MAX_MAPPED_BYTES` of it
+    pub(crate) fn map(file: File, start: u64, len: u64) -> Option<Self> {
+        let size = std::cmp::min(len.saturating_sub(start), MAX_MAPPED_BYTES);
+        // The region only outlives the file if the file is truncated, see above
+        let mmap = unsafe {
+            MmapOptions::new()
+                .offset(start)
+                .len(size.try_into().ok()?)
+                .map(&file)
+        };
+        match mmap {
+            Ok(mmap) => {
+                Metrics::fs().add_mapped_read(size);
+                Some(MappedRegion {
+                    len: mmap.len(),
+                    mmap,
+                    file,
+                    pos: 0,
+                    checked: 0,
+                    start,
+                })
+            }
+            Err(e) => {
+                debug!("unable to map file, reading it instead: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Offset in the file the region ends at, or at which it was cut short
+    pub(crate) fn end(&self) -> u64 {
+        self.start + self.len as u64
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.pos >= self.len
+    }
+
+    /// The unread bytes of the region, up to `CHECKED_BYTES` past the last check of the length
+    /// of the file. A region the file no longer covers is cut short to the bytes already read,
+    /// the truncation is then picked up by the reader
+    fn available(&mut self) -> &[u8] {
+        if self.pos >= self.checked {
+            let covered = match self.file.metadata() {
+                Ok(metadata) => metadata.len().saturating_sub(self.start),
+                Err(_) => 0,
+            };
+            let next = std::cmp::min(self.pos + CHECKED_BYTES, self.len);
+            if covered < next as u64 {
+                debug!("mapped file was truncated, reading it instead");
+                self.len = self.pos;
+                return &[];
+            }
+            self.checked = next;
+        }
+        &self.mmap[self.pos..self.checked]
+    }
+}
+
+impl AsyncRead for MappedRegion {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let available = self.available();
+        let read = std::cmp::min(available.len(), buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.pos += read;
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl AsyncBufRead for MappedRegion {
+    fn poll_fill_buf(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Poll::Ready(Ok(self.get_mut().available()))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.pos = std::cmp::min(self.pos + amt, self.checked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AsyncBufReadExt;
+    use std::io::Write;
+    use tempfile::tempfile;
+
+    #[test]
+    fn maps_the_backlog_of_quiet_files() {
+        let mut file = tempfile().unwrap();
+        file.write_all(b"skipped\nfirst\nsecond\npartial").unwrap();
+        let len = file.metadata().unwrap().len();
+        let old = SystemTime::now() - QUIET_PERIOD * 2;
+
+        assert!(!should_map(len - 8, None, Some(old)));
+        assert!(!should_map(len - 8, Some(16), Some(SystemTime::now())));
+        assert!(!should_map(8, Some(16), Some(old)));
+        assert!(should_map(len - 8, Some(16), Some(old)));
+
+        let mut region = MappedRegion::map(file.try_clone().unwrap(), 8, len).unwrap();
+        assert_eq!(region.end(), len);
+        let lines = tokio_test::block_on(async {
+            let mut lines = Vec::new();
+            let mut line = Vec::new();
+            while region.read_until(b'\n', &mut line).await.unwrap() > 0 {
+                lines.push(String::from_utf8(std::mem::take(&mut line)).unwrap());
+            }
+            lines
+        });
+        assert_eq!(lines, vec!["first\n", "second\n", "partial"]);
+        assert!(region.is_exhausted());
+    }
+
+    #[test]
+    fn cuts_the_regions_of_truncated_files_short() {
+        let mut file = tempfile().unwrap();
+        file.write_all(b"skipped\nfirst\nsecond\n").unwrap();
+        let len = file.metadata().unwrap().len();
+
+        let mut region = MappedRegion::map(file.try_clone().unwrap(), 8, len).unwrap();
+        file.set_len(10).unwrap();
+        let mut line = Vec::new();
+        let read = tokio_test::block_on(region.read_until(b'\n', &mut line)).unwrap();
+        assert_eq!(read, 0);
+        assert!(region.is_exhausted());
+        assert_eq!(region.end(), 8);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::rule::{Rule, RuleList};
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use pin_project_lite::pin_project;
+
+/// The maximum number of pending file readers that are buffered ahead of the scheduler
+const MAX_QUEUED_READERS: usize = 64;
+
+/// The class a tailed file is read with when the agent is under backpressure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    High,
+    Normal,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// Assigns priority classes to paths and sets how strongly high priority files are favoured
+#[derive(Debug)]
+pub struct PriorityRules {
+    high: RuleList,
+    weight: usize,
+}
+
+impl PriorityRules {
+    /// Creates rules where every path is normal priority, `weight` is the number of high
+    /// priority lines read for every normal priority line while both have lines available
+    pub fn new(weight: usize) -> Self {
+        Self {
+            high: Vec::new(),
+            weight: weight.max(1),
+        }
+    }
+    /// Adds a rule matching paths that should be read with high priority
+    pub fn add_high<T: Rule + Send + 'static>(&mut self, rule: T) {
+        self.high.push(Box::new(rule))
+    }
+    /// Returns true if no high priority rules were added
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty()
+    }
+    /// Getter for the weight of the high priority class
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+    /// Returns the priority class of a path
+    pub fn classify(&self, value: &Path) -> Priority {
+        if self.high.iter().any(|rule| rule.matches(value)) {
+            Priority::High
+        } else {
+            Priority::Normal
+        }
+    }
+}
+
+impl Default for PriorityRules {
+    fn default() -> Self {
+        PriorityRules::new(4)
+    }
+}
+
+pin_project! {
+    /// Flattens a stream of prioritized readers, taking up to `weight` items from high priority
+    /// readers for every item taken from a normal priority reader.
+    ///
+    /// Each item of the stream is the reader of an event that isn't created until the future
+    /// resolves, along with the key of the file it's for. The future of an event is only run
+    /// once the readers queued for the same file are drained, so that e.g. the delete of a
+    /// rotated file is handled after the lines written before it were read, while readers of
+    /// other files are queued ahead. Readers within a class are drained one at a time in the
+    /// order they arrived, so lines from the same file are never reordered.
+    #[must_use = "streams do nothing unless polled"]
+    pub struct WeightedFlatten<St, K, F, I> {
+        #[pin]
+        stream: St,
+        stream_done: bool,
+        /// The next event, waiting for the readers of its file or for its reader to be created
+        pending: Option<(Priority, K, Pin<Box<F>>)>,
+        high: VecDeque<(K, Pin<Box<I>>)>,
+        normal: VecDeque<(K, Pin<Box<I>>)>,
+        weight: usize,
+        served_high: usize,
+    }
+}
+
+impl<St, K, F, I> WeightedFlatten<St, K, F, I>
+where
+    St: Stream<Item = (Priority, K, F)>,
+    K: PartialEq,
+    F: Future<Output = Option<I>>,
+    I: Stream,
+{
+    pub fn new(stream: St, weight: usize) -> Self {
+        Self {
+            stream,
+            stream_done: false,
+            pending: None,
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+            weight: weight.max(1),
+            served_high: 0,
+        }
+    }
+}
+
+impl<St, K, F, I> Stream for WeightedFlatten<St, K, F, I>
+where
+    St: Stream<Item = (Priority, K, F)>,
+    K: PartialEq,
+    F: Future<Output = Option<I>>,
+    I: Stream,
+{
+    type Item = I::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            // Queue up every reader that is ready without waiting on the underlying stream
+            let mut queued_reader = false;
+            while this.high.len() + this.normal.len() < MAX_QUEUED_READERS {
+                if this.pending.is_none() && !*this.stream_done {
+                    match this.stream.as_mut().poll_next(cx) {
+                        Poll::Ready(Some((priority, key, event))) => {
+                            *this.pending = Some((priority, key, Box::pin(event)))
+                        }
+                        Poll::Ready(None) => *this.stream_done = true,
+                        Poll::Pending => {}
+                    }
+                }
+                let (_, key, event) = match this.pending.as_mut() {
+                    Some(pending) => pending,
+                    None => break,
+                };
+                let same_file = |(queued, _): &(K, Pin<Box<I>>)| *queued == *key;
+                if this.high.iter().any(same_file) || this.normal.iter().any(same_file) {
+                    break;
+                }
+                let reader = match event.as_mut().poll(cx) {
+                    Poll::Ready(reader) => reader,
+                    Poll::Pending => break,
+                };
+                let (priority, key, _) = this.pending.take().expect("the event is pending");
+                if let Some(reader) = reader {
+                    let queue = match priority {
+                        Priority::High => &mut *this.high,
+                        Priority::Normal => &mut *this.normal,
+                    };
+                    queue.push_back((key, Box::pin(reader)));
+                    queued_reader = true;
+                }
+            }
+
+            let order = if *this.served_high < *this.weight || this.normal.is_empty() {
+                [Priority::High, Priority::Normal]
+            } else {
+                [Priority::Normal, Priority::High]
+            };
+
+            let mut finished_reader = false;
+            for priority in order.iter() {
+                let queue = match priority {
+                    Priority::High => &mut *this.high,
+                    Priority::Normal => &mut *this.normal,
+                };
+                while let Some((_, reader)) = queue.front_mut() {
+                    match reader.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(item)) => {
+                            match priority {
+                                Priority::High => *this.served_high += 1,
+                                Priority::Normal => *this.served_high = 0,
+                            }
+                            return Poll::Ready(Some(item));
+                        }
+                        Poll::Ready(None) => {
+                            queue.pop_front();
+                            finished_reader = true;
+                        }
+                        Poll::Pending => break,
+                    }
+                }
+            }
+
+            if *this.stream_done
+                && this.pending.is_none()
+                && this.high.is_empty()
+                && this.normal.is_empty()
+            {
+                return Poll::Ready(None);
+            }
+
+            // A finished reader may have unblocked the pending event or freed up room for the
+            // underlying stream
+            if !finished_reader && !queued_reader {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::GlobRule;
+    use futures::future::{self, Ready};
+    use futures::stream::{self, StreamExt};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn classifies_paths() {
+        let mut rules = PriorityRules::default();
+        rules.add_high(GlobRule::new("/var/log/audit/*").unwrap());
+
+        assert_eq!(
+            rules.classify(Path::new("/var/log/audit/audit.log")),
+            Priority::High
+        );
+        assert_eq!(
+            rules.classify(Path::new("/var/log/app.log")),
+            Priority::Normal
+        );
+    }
+
+    /// An event whose reader is ready right away
+    fn ready<K, I>(priority: Priority, key: K, reader: I) -> (Priority, K, Ready<Option<I>>) {
+        (priority, key, future::ready(Some(reader)))
+    }
+
+    #[test]
+    fn favours_high_priority_readers() {
+        let readers = stream::iter(vec![
+            ready(Priority::Normal, 1, stream::iter(vec!["n1", "n2", "n3"])),
+            ready(
+                Priority::High,
+                2,
+                stream::iter(vec!["h1", "h2", "h3", "h4"]),
+            ),
+        ]);
+
+        let result = tokio_test::block_on(WeightedFlatten::new(readers, 2).collect::<Vec<_>>());
+
+        assert_eq!(result, vec!["h1", "h2", "n1", "h3", "h4", "n2", "n3"]);
+    }
+
+    #[test]
+    fn keeps_order_within_a_class() {
+        let readers = stream::iter(vec![
+            ready(Priority::Normal, 1, stream::iter(vec![1, 2])),
+            ready(Priority::Normal, 2, stream::iter(vec![3])),
+            ready(Priority::Normal, 3, stream::iter(vec![4, 5])),
+        ]);
+
+        let result = tokio_test::block_on(WeightedFlatten::new(readers, 4).collect::<Vec<_>>());
+
+        assert_eq!(result, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn handles_the_rotation_of_a_file_after_reading_its_lines() {
+        // The lines read and the events handled, in order
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let lines = |values: Vec<&'static str>| {
+            let log = log.clone();
+            stream::iter(values)
+                .map(move |line| {
+                    log.borrow_mut().push(line);
+                    line
+                })
+                .boxed_local()
+        };
+        // app.log is renamed while its last lines are unread, then created again
+        let events = vec![
+            ("app.log", "write", lines(vec!["a1", "a2"])),
+            ("app.log", "delete", lines(vec![])),
+            ("app.log.new", "create", lines(vec!["b1"])),
+        ];
+        let events = stream::iter(events).map(|(key, event, reader)| {
+            let log = log.clone();
+            let handled = future::lazy(move |_| {
+                log.borrow_mut().push(event);
+                Some(reader)
+            });
+            (Priority::Normal, key, handled)
+        });
+
+        let result = tokio_test::block_on(WeightedFlatten::new(events, 4).collect::<Vec<_>>());
+
+        assert_eq!(result, vec!["a1", "a2", "b1"]);
+        // The delete of app.log waits for its lines to be read
+        assert_eq!(
+            *log.borrow(),
+            vec!["write", "a1", "a2", "delete", "create", "b1"]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+/// How long after a file is deleted one created at the same path can be taken for it
+const RECREATE_WINDOW: Duration = Duration::from_secs(5);
+/// Bytes at the start of a file its fingerprint is taken from
+const FINGERPRINT_LEN: u64 = 1024;
+
+/// What's done with a file created at the path of one deleted moments before
+#[derive(Clone, Copy, std::fmt::Debug, PartialEq)]
+pub enum RecreatedFiles {
+    /// It's a new file, read from its start
+    Restart,
+    /// It's read from where the deleted file was read up to when they start with the same bytes
+    Resume,
+}
+
+#[derive(Error, Debug)]
+pub enum ParseRecreatedFilesError {
+    #[error("Unknown recreated files policy: {0}, use restart or resume")]
+    Unknown(String),
+}
+
+impl std::str::FromStr for RecreatedFiles {
+    type Err = ParseRecreatedFilesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "restart" => Ok(RecreatedFiles::Restart),
+            "resume" => Ok(RecreatedFiles::Resume),
+            _ => Err(ParseRecreatedFilesError::Unknown(s.into())),
+        }
+    }
+}
+
+impl Default for RecreatedFiles {
+    fn default() -> Self {
+        RecreatedFiles::Restart
+    }
+}
+
+/// Hash of the first bytes of a file
+#[derive(Clone, Copy, std::fmt::Debug, PartialEq)]
+pub(crate) struct Fingerprint {
+    len: u64,
+    hash: u64,
+}
+
+impl Fingerprint {
+    /// Takes the fingerprint of the first `len` bytes of `file`, at most `FINGERPRINT_LEN`,
+    /// None for an empty file
+    pub(crate) fn read(file: &File, len: u64) -> io::Result<Option<Self>> {
+        let len = len.min(FINGERPRINT_LEN);
+        if len == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0; len as usize];
+        file.read_exact_at(&mut buf, 0)?;
+        let mut hasher = DefaultHasher::new();
+        buf.hash(&mut hasher);
+        Ok(Some(Fingerprint {
+            len,
+            hash: hasher.finish(),
+        }))
+    }
+}
+
+struct DeletedFile {
+    at: Instant,
+    offset: u64,
+    fingerprint: Option<Fingerprint>,
+}
+
+/// The files deleted moments before, to resume reading the ones created again at their path
+#[derive(Default)]
+pub(crate) struct DeletedFiles {
+    files: HashMap<PathBuf, DeletedFile>,
+}
+
+impl DeletedFiles {
+    /// Records that the file at `path` was deleted once read up to `offset`
+    pub(crate) fn record(&mut self, path: &Path, offset: u64, fingerprint: Option<Fingerprint>) {
+        self.record_at(path, offset, fingerprint, Instant::now());
+    }
+
+    /// The offset to read the file created at `path` from, where the deleted one was read up to
+    /// when they start with the same bytes and the new one is as long
+    pub(crate) fn resume_offset(&mut self, path: &Path, file: &File) -> u64 {
+        self.resume_offset_at(path, file, Instant::now())
+    }
+
+    fn record_at(
+        &mut self,
+        path: &Path,
+        offset: u64,
+        fingerprint: Option<Fingerprint>,
+        now: Instant,
+    ) {
+        self.files
+            .retain(|_, file| now.duration_since(file.at) < RECREATE_WINDOW);
+        let file = DeletedFile {
+            at: now,
+            offset,
+            fingerprint,
+        };
+        self.files.insert(path.to_path_buf(), file);
+    }
+
+    fn resume_offset_at(&mut self, path: &Path, file: &File, now: Instant) -> u64 {
+        let deleted = match self.files.remove(path) {
+            Some(deleted) if now.duration_since(deleted.at) < RECREATE_WINDOW => deleted,
+            _ => return 0,
+        };
+        let fingerprint = match deleted.fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => return 0,
+        };
+        let len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        if len < deleted.offset {
+            return 0;
+        }
+        match Fingerprint::read(file, fingerprint.len) {
+            Ok(Some(read)) if read == fingerprint => {
+                info!(
+                    "resuming {:?} at {}, it was created again",
+                    path, deleted.offset
+                );
+                deleted.offset
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn resumes_files_created_again_with_the_same_start() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let write = |content: &str| {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+            File::open(&path).unwrap()
+        };
+
+        let deleted = write("started\nline 1\n");
+        let fingerprint = Fingerprint::read(&deleted, 15).unwrap();
+        let mut files = DeletedFiles::default();
+        let start = Instant::now();
+
+        files.record_at(&path, 15, fingerprint, start);
+        let recreated = write("started\nline 1\nline 2\n");
+        assert_eq!(files.resume_offset_at(&path, &recreated, start), 15);
+        // Only once
+        assert_eq!(files.resume_offset_at(&path, &recreated, start), 0);
+
+        files.record_at(&path, 15, fingerprint, start);
+        assert_eq!(
+            files.resume_offset_at(&path, &recreated, start + RECREATE_WINDOW),
+            0
+        );
+
+        files.record_at(&path, 15, fingerprint, start);
+        let different = write("restarted\nline 1\n");
+        assert_eq!(files.resume_offset_at(&path, &different, start), 0);
+
+        let policy = "Resume".parse::<RecreatedFiles>().unwrap();
+        assert_eq!(policy, RecreatedFiles::Resume);
+        assert!("keep".parse::<RecreatedFiles>().is_err());
+    }
+}
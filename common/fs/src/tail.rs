@@ -1,15 +1,22 @@
+use crate::cache::coalesce::coalesce_writes;
 use crate::cache::entry::Entry;
 use crate::cache::event::Event;
 use crate::cache::tailed_file::LazyLineSerializer;
 pub use crate::cache::DirPathBuf;
 use crate::cache::{EntryKey, FileSystem};
+use crate::lag;
+use crate::priority::{Priority, PriorityRules, WeightedFlatten};
+use crate::recreated::{DeletedFiles, RecreatedFiles};
 use crate::rule::Rules;
 use metrics::Metrics;
-use state::FileId;
+use state::{FileId, OffsetMap};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::{Stream, StreamExt};
 
@@ -56,7 +63,15 @@ impl Default for Lookback {
 pub struct Tailer {
     lookback_config: Lookback,
     fs_cache: Arc<Mutex<FileSystem>>,
-    initial_offsets: Option<HashMap<FileId, u64>>,
+    /// Offsets saved by the previous run, shared with the handling of every event rather than
+    /// copied for each of them, and forgotten once their file is deleted
+    initial_offsets: Option<Arc<OffsetMap>>,
+    event_coalesce_window: Duration,
+    priority_rules: Rc<PriorityRules>,
+    /// The files deleted moments before, only tracked to resume the ones created again
+    deleted_files: Option<Rc<RefCell<DeletedFiles>>>,
+    /// Unread bytes over which quiet files are read through a memory map, None never maps them
+    mmap_threshold: Option<u64>,
 }
 
 impl Tailer {
@@ -70,7 +85,60 @@ impl Tailer {
         Self {
             lookback_config,
             fs_cache: Arc::new(Mutex::new(FileSystem::new(watched_dirs, rules))),
-            initial_offsets,
+            initial_offsets: initial_offsets.map(|offsets| Arc::new(offsets.into())),
+            event_coalesce_window: Duration::from_millis(10),
+            priority_rules: Rc::new(PriorityRules::default()),
+            deleted_files: None,
+            mmap_threshold: None,
+        }
+    }
+
+    /// Sets the window in which write events for the same file are merged, zero disables it
+    pub fn set_event_coalesce_window(&mut self, window: Duration) {
+        self.event_coalesce_window = window;
+    }
+
+    /// Sets the rules used to pick which files are read first under backpressure
+    pub fn set_priority_rules(&mut self, rules: PriorityRules) {
+        self.priority_rules = Rc::new(rules);
+    }
+
+    /// Sets what's done with the files created at the path of one deleted moments before
+    pub fn set_recreated_files(&mut self, policy: RecreatedFiles) {
+        self.deleted_files = match policy {
+            RecreatedFiles::Restart => None,
+            RecreatedFiles::Resume => Some(Rc::new(RefCell::new(DeletedFiles::default()))),
+        };
+    }
+
+    /// Sets the unread bytes over which a file that isn't being written to is mapped into memory
+    /// to be read, None always reads files with buffered reads
+    pub fn set_mmap_threshold(&mut self, bytes: Option<u64>) {
+        self.mmap_threshold = bytes;
+    }
+
+    /// The entry an event is for
+    fn get_event_key(event: &Event) -> EntryKey {
+        match event {
+            Event::Initialize(key) | Event::New(key) | Event::Write(key) | Event::Delete(key) => {
+                *key
+            }
+        }
+    }
+
+    fn get_event_priority(event: &Event, fs: &FileSystem, rules: &PriorityRules) -> Priority {
+        if rules.is_empty() {
+            return Priority::Normal;
+        }
+        let entries = fs.entries.borrow();
+        match entries.get(Tailer::get_event_key(event)) {
+            Some(entry) => fs
+                .resolve_valid_paths(entry, &entries)
+                .iter()
+                .map(|path| rules.classify(path))
+                .find(|priority| *priority == Priority::High)
+                .unwrap_or_default(),
+            None => Priority::Normal,
         }
     }
 
@@ -91,15 +159,11 @@ impl Tailer {
     async fn get_initial_offset(
         target: &Path,
         fs: &FileSystem,
-        initial_offsets: Option<HashMap<FileId, u64>>,
+        initial_offsets: Option<&OffsetMap>,
         lookback_config: Lookback,
     ) -> Option<(EntryKey, u64)> {
-        fn _lookup_offset(
-            initial_offsets: &HashMap<FileId, u64>,
-            key: &FileId,
-            path: &Path,
-        ) -> u64 {
-            let offset = initial_offsets.get(key).copied().unwrap_or(0);
+        fn _lookup_offset(initial_offsets: &OffsetMap, key: &FileId, path: &Path) -> u64 {
+            let offset = initial_offsets.get(key).unwrap_or(0);
             debug!("Got offset {} from state using key {:?}", offset, path);
             offset
         }
@@ -112,15 +176,13 @@ impl Tailer {
             Some((
                 entry_key,
                 match lookback_config {
-                    Lookback::Start => match initial_offsets.as_ref() {
-                        Some(initial_offsets) => _lookup_offset(&initial_offsets, &inode, &path),
+                    Lookback::Start => match initial_offsets {
+                        Some(initial_offsets) => _lookup_offset(initial_offsets, &inode, &path),
                         None => 0,
                     },
                     Lookback::SmallFiles => {
-                        match initial_offsets.as_ref() {
-                            Some(initial_offsets) => {
-                                _lookup_offset(&initial_offsets, &inode, &path)
-                            }
+                        match initial_offsets {
+                            Some(initial_offsets) => _lookup_offset(initial_offsets, &inode, &path),
                             None => {
                                 // Check the actual file len
                                 let len = path.metadata().map(|m| m.len()).unwrap_or(0);
@@ -143,9 +205,11 @@ impl Tailer {
 
     async fn handle_event(
         event: Event,
-        initial_offsets: Option<HashMap<FileId, u64>>,
+        initial_offsets: Option<&OffsetMap>,
         lookback_config: Lookback,
         fs: &FileSystem,
+        deleted_files: Option<&RefCell<DeletedFiles>>,
+        mmap_threshold: Option<u64>,
     ) -> Option<impl Stream<Item = LazyLineSerializer>> {
         match event {
             Event::Initialize(entry_ptr) => {
@@ -173,7 +237,7 @@ impl Tailer {
                         info!("initialized {:?} with offset {}", name, offset);
 
                         if fs.is_initial_dir_target(&path) {
-                            return data.borrow_mut().tail(vec![path]).await;
+                            return data.borrow_mut().tail(vec![path], mmap_threshold).await;
                         }
                     }
                     Entry::Symlink { name, link, .. } => {
@@ -205,7 +269,7 @@ impl Tailer {
                                 .seek(offset)
                                 .await
                                 .unwrap_or_else(|e| error!("error seeking {:?}", e));
-                            return data.tail(vec![sym_path]).await;
+                            return data.tail(vec![sym_path], mmap_threshold).await;
                         }
                     }
                     _ => (),
@@ -223,7 +287,18 @@ impl Tailer {
                 }
                 if let Entry::File { data, .. } = entry {
                     info!("added {:?}", paths[0]);
-                    return data.borrow_mut().tail(paths.clone()).await;
+                    let resume_offset = deleted_files.and_then(|deleted_files| {
+                        let file = std::fs::File::open(&paths[0]).ok()?;
+                        Some(deleted_files.borrow_mut().resume_offset(&paths[0], &file))
+                    });
+                    if let Some(offset) = resume_offset.filter(|offset| *offset > 0) {
+                        data.borrow_mut()
+                            .deref_mut()
+                            .seek(offset)
+                            .await
+                            .unwrap_or_else(|e| error!("error seeking {:?}", e));
+                    }
+                    return data.borrow_mut().tail(paths.clone(), mmap_threshold).await;
                 }
             }
             Event::Write(entry_ptr) => {
@@ -237,7 +312,11 @@ impl Tailer {
                 }
 
                 if let Entry::File { data, .. } = entry {
-                    return data.borrow_mut().deref_mut().tail(paths).await;
+                    return data
+                        .borrow_mut()
+                        .deref_mut()
+                        .tail(paths, mmap_threshold)
+                        .await;
                 }
             }
             Event::Delete(entry_ptr) => {
@@ -261,7 +340,31 @@ impl Tailer {
                         }
 
                         if let Entry::File { data, .. } = entry {
-                            data.borrow_mut().deref_mut().tail(paths).await
+                            if let Some(initial_offsets) = initial_offsets {
+                                // A file created later with the same inode starts over
+                                let inode: FileId = (&data.borrow().get_inode().await).into();
+                                initial_offsets.remove(&inode);
+                            }
+                            if let Some(deleted_files) = deleted_files {
+                                match data.borrow().deleted_position().await {
+                                    Ok((offset, fingerprint)) => deleted_files.borrow_mut().record(
+                                        &paths[0],
+                                        offset,
+                                        fingerprint,
+                                    ),
+                                    Err(e) => debug!("unable to fingerprint {:?}: {}", paths[0], e),
+                                }
+                            }
+                            let path = paths[0].to_string_lossy().into_owned();
+                            let lines = data
+                                .borrow_mut()
+                                .deref_mut()
+                                .tail(paths, mmap_threshold)
+                                .await;
+                            // Whatever is left is read by `lines`, the file won't be written to
+                            // under this name anymore
+                            lag::forget(&path);
+                            lines
                         } else {
                             None
                         }
@@ -303,28 +406,41 @@ impl Tailer {
         };
 
         debug!("Tailer starting with lookback: {:?}", self.lookback_config);
-        Ok(events
-            .then({
-                let fs = self.fs_cache.clone();
-                let lookback_config = self.lookback_config.clone();
-                let initial_offsets = self.initial_offsets.clone();
-                move |event| {
-                    let fs = fs.clone();
-                    let lookback_config = lookback_config.clone();
-                    let initial_offsets = initial_offsets.clone();
-                    async move {
-                        Tailer::handle_event(
-                            event,
-                            initial_offsets,
-                            lookback_config,
-                            &fs.lock().expect("Couldn't lock fs"),
-                        )
-                        .await
-                    }
-                }
-            })
-            .filter_map(|x| async move { x })
-            .flatten())
+        // Events are handled by the scheduler, once the lines of the earlier events of the
+        // same file were read
+        let events = coalesce_writes(events, self.event_coalesce_window).map({
+            let fs = self.fs_cache.clone();
+            let lookback_config = self.lookback_config.clone();
+            let initial_offsets = self.initial_offsets.clone();
+            let priority_rules = self.priority_rules.clone();
+            let deleted_files = self.deleted_files.clone();
+            let mmap_threshold = self.mmap_threshold;
+            move |event| {
+                let fs = fs.clone();
+                let lookback_config = lookback_config.clone();
+                let initial_offsets = initial_offsets.clone();
+                let deleted_files = deleted_files.clone();
+                let priority = Tailer::get_event_priority(
+                    &event,
+                    &fs.lock().expect("Couldn't lock fs"),
+                    &priority_rules,
+                );
+                let key = Tailer::get_event_key(&event);
+                let lines = async move {
+                    Tailer::handle_event(
+                        event,
+                        initial_offsets.as_deref(),
+                        lookback_config,
+                        &fs.lock().expect("Couldn't lock fs"),
+                        deleted_files.as_deref(),
+                        mmap_threshold,
+                    )
+                    .await
+                };
+                (priority, key, lines)
+            }
+        });
+        Ok(WeightedFlatten::new(events, self.priority_rules.weight()))
     }
 }
 
@@ -0,0 +1,145 @@
+use metrics::Metrics;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+lazy_static! {
+    static ref READ_THROTTLE: ReadThrottle = ReadThrottle::new(0);
+}
+
+/// Sets the maximum number of bytes per second read from disk across all tailed files,
+/// zero removes the limit
+pub fn set_read_limit(bytes_per_sec: u64) {
+    READ_THROTTLE.set_limit(bytes_per_sec);
+}
+
+/// Returns how long a reader has to wait before reading from disk again
+pub(crate) fn read_delay() -> Option<Duration> {
+    READ_THROTTLE.delay()
+}
+
+/// Records bytes that were read from disk
+pub(crate) fn consume(bytes: u64) {
+    READ_THROTTLE.consume(bytes)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // Can go negative when a line is larger than what's left in the bucket,
+    // readers then wait until the debt is paid off
+    available: i64,
+    last_refill: Instant,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+/// A token bucket holding up to one second worth of reads
+#[derive(Debug)]
+struct ReadThrottle {
+    limit: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+impl ReadThrottle {
+    fn new(bytes_per_sec: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            limit: AtomicU64::new(bytes_per_sec),
+            bucket: Mutex::new(Bucket {
+                available: bytes_per_sec as i64,
+                last_refill: now,
+                window_start: now,
+                window_bytes: 0,
+            }),
+        }
+    }
+
+    fn set_limit(&self, bytes_per_sec: u64) {
+        self.limit.store(bytes_per_sec, Ordering::Relaxed);
+        let mut bucket = self.bucket.lock().expect("Couldn't lock read throttle");
+        bucket.available = bytes_per_sec as i64;
+        bucket.last_refill = Instant::now();
+    }
+
+    fn refill(bucket: &mut Bucket, limit: u64, now: Instant) {
+        let elapsed = now.duration_since(bucket.last_refill);
+        let refill = (elapsed.as_nanos() * limit as u128 / 1_000_000_000) as i64;
+        if refill > 0 {
+            bucket.available = (bucket.available + refill).min(limit as i64);
+            bucket.last_refill = now;
+        }
+    }
+
+    fn delay(&self) -> Option<Duration> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return None;
+        }
+        let mut bucket = self.bucket.lock().expect("Couldn't lock read throttle");
+        Self::refill(&mut bucket, limit, Instant::now());
+        if bucket.available > 0 {
+            return None;
+        }
+        let deficit = (1 - bucket.available) as u64;
+        Some(Duration::from_nanos(deficit * 1_000_000_000 / limit).max(Duration::from_millis(1)))
+    }
+
+    fn consume(&self, bytes: u64) {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut bucket = self.bucket.lock().expect("Couldn't lock read throttle");
+        Self::refill(&mut bucket, limit, now);
+        bucket.available -= bytes as i64;
+        bucket.window_bytes += bytes;
+
+        let window = now.duration_since(bucket.window_start);
+        if window >= Duration::from_secs(1) {
+            let allowed = limit as u128 * window.as_millis() / 1000;
+            let utilization = bucket.window_bytes as u128 * 100 / allowed.max(1);
+            Metrics::fs().set_read_throttle_utilization(utilization as u64);
+            bucket.window_start = now;
+            bucket.window_bytes = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_never_delays() {
+        let throttle = ReadThrottle::new(0);
+        throttle.consume(u32::MAX as u64);
+        assert_eq!(throttle.delay(), None);
+    }
+
+    #[test]
+    fn delays_once_the_bucket_is_empty() {
+        let throttle = ReadThrottle::new(1000);
+        assert_eq!(throttle.delay(), None);
+
+        throttle.consume(1500);
+        let delay = throttle.delay().expect("reader should be throttled");
+        assert!(delay >= Duration::from_millis(400));
+        assert!(delay <= Duration::from_millis(501));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        tokio_test::block_on(async {
+            let throttle = ReadThrottle::new(1000);
+            throttle.consume(1000);
+            assert!(throttle.delay().is_some());
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(throttle.delay(), None);
+        });
+    }
+}
@@ -0,0 +1,104 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Prefix of the retry files that are encrypted, telling them apart from the plaintext ones
+pub(crate) const MAGIC: &[u8] = b"LDENC1";
+
+#[derive(Debug, Error)]
+#[error("the retry encryption key must be 32 bytes encoded in base64")]
+pub struct InvalidKey;
+
+#[derive(Debug, Error)]
+pub enum CipherError {
+    #[error("unable to encrypt request")]
+    Seal,
+    #[error("unable to decrypt request, it was encrypted with another key or is corrupted")]
+    Open,
+}
+
+/// AES-256-GCM key encrypting the requests stored on disk to be retried
+#[derive(Clone)]
+pub struct SpoolKey(Arc<LessSafeKey>);
+
+impl SpoolKey {
+    pub fn from_base64(encoded: &str) -> Result<Self, InvalidKey> {
+        let bytes = base64::decode(encoded.trim()).map_err(|_| InvalidKey)?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| InvalidKey)?;
+        Ok(SpoolKey(Arc::new(LessSafeKey::new(key))))
+    }
+
+    /// The magic prefix, followed by a random nonce and the ciphertext with its tag
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| CipherError::Seal)?;
+        let mut sealed = plaintext.to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(MAGIC),
+                &mut sealed,
+            )
+            .map_err(|_| CipherError::Seal)?;
+
+        let mut data = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&sealed);
+        Ok(data)
+    }
+
+    /// Decrypts data written by `seal`, magic prefix included
+    pub(crate) fn open(&self, data: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let data = data.strip_prefix(MAGIC).ok_or(CipherError::Open)?;
+        if data.len() < NONCE_LEN {
+            return Err(CipherError::Open);
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| CipherError::Open)?;
+        let mut plaintext = sealed.to_vec();
+        let len = self
+            .0
+            .open_in_place(nonce, Aad::from(MAGIC), &mut plaintext)
+            .map_err(|_| CipherError::Open)?
+            .len();
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+}
+
+impl fmt::Debug for SpoolKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SpoolKey(REDACTED)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn sealed_data_only_opens_with_its_key() {
+        let key = SpoolKey::from_base64(KEY).unwrap();
+        let sealed = key.seal(b"{\"lines\":[]}").unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(5).any(|w| w == b"lines"));
+        assert_eq!(key.open(&sealed).unwrap(), b"{\"lines\":[]}");
+
+        let other = SpoolKey::from_base64(&base64::encode([7; 32])).unwrap();
+        assert!(other.open(&sealed).is_err());
+        assert!(key.open(&sealed[..sealed.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn keys_are_32_bytes() {
+        assert!(SpoolKey::from_base64(&base64::encode([7; 16])).is_err());
+        assert!(SpoolKey::from_base64("not base64!").is_err());
+    }
+}
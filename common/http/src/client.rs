@@ -1,14 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::pin::Pin;
 use std::time::{Duration, Instant};
 
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use futures::{Stream, StreamExt};
 
+use crate::cipher::SpoolKey;
 use crate::limit::RateLimiter;
-use crate::retry::Retry;
+use crate::pool::CodecPool;
+use crate::rejection::Rejection;
+use crate::retry::{self, batch_id, Pending, Retry, RetryBudget, RetryPolicy, SourceLines};
 use crate::types::body::IngestBodyBuffer;
 use crate::types::client::Client as HttpClient;
 use crate::types::error::HttpError;
+use crate::types::params::Tags;
 use crate::types::request::RequestTemplate;
 use crate::types::response::Response;
 use crate::types::serialize::{
@@ -22,20 +28,50 @@ use std::sync::Arc;
 /// Http(s) client used to send logs to the Ingest API
 pub struct Client {
     inner: HttpClient,
+    template: RequestTemplate,
+    /// Tags of the template, before the ones set through `set_extra_tags`
+    base_tags: Option<Tags>,
+    timeout: Option<Duration>,
+    connection_max_lifetime: Option<Duration>,
+    /// When `inner`, and with it its connection pool, was created
+    connected_at: Instant,
+    /// Requests taking longer are logged along with their size and endpoint
+    slow_request_threshold: Option<Duration>,
     buffer_source:
         Pin<Box<dyn Stream<Item = Result<IngestBodySerializer, IngestLineSerializeError>>>>,
     limiter: RateLimiter,
     retry: Arc<Retry>,
+    retry_budget: Option<RetryBudget>,
+    /// Failed requests that didn't fit in the full spool, with their batch id, their lines by
+    /// file, their attempts and when they first failed
+    held: VecDeque<(String, IngestBodyBuffer, SourceLines, u32, Option<i64>)>,
 
     buffer: Option<IngestBodySerializer>,
     offsets: Option<Vec<Offset>>,
+    /// Lines in `buffer` by file, counted as delivered once the ingest API acknowledged them
+    sources: SourceLines,
     buffer_max_size: usize,
+    buffer_max_lines: Option<usize>,
     buffer_bytes: usize,
+    flush_interval: Duration,
     last_flush: Instant,
     last_retry: Instant,
     state_write: Option<FileOffsetWriteHandle>,
     state_flush: Option<FileOffsetFlushHandle>,
     retry_step_delay: Duration,
+    /// Stored requests replayed right away for each new request delivered, on top of the one
+    /// replayed every `retry_step_delay`
+    replay_ratio: u32,
+    replay_credit: u32,
+    /// Whether failed requests are retried before any later one is sent
+    strict_ordering: bool,
+    /// The gzip level of the requests and one in how many batches are compressed again to
+    /// measure the compression ratio
+    compression_sample: Option<(u32, u32)>,
+    batches: u32,
+    dry_run: bool,
+    /// The threads batches are closed on
+    codec_pool: CodecPool,
 }
 
 impl Client {
@@ -44,8 +80,9 @@ impl Client {
     pub fn new(
         template: RequestTemplate,
         state_handles: Option<(FileOffsetWriteHandle, FileOffsetFlushHandle)>,
-        retry_base_delay: Duration,
+        retry_policy: RetryPolicy,
         retry_step_delay: Duration,
+        codec_pool: CodecPool,
     ) -> Self {
         let buffer_source = Box::pin(body_serializer_source(
             16 * 1024, /* 16 KB segments */
@@ -57,23 +94,42 @@ impl Client {
             .map(|(sw, sf)| (Some(Vec::new()), Some(sw), Some(sf)))
             .unwrap_or((None, None, None));
         Self {
-            inner: HttpClient::new(template),
+            inner: HttpClient::new(template.clone()),
+            base_tags: template.params.tags.clone(),
+            template,
+            timeout: None,
+            connection_max_lifetime: None,
+            connected_at: Instant::now(),
+            slow_request_threshold: None,
             buffer_source,
             limiter: RateLimiter::new(10),
-            retry: Arc::new(Retry::new(retry_base_delay)),
+            retry_budget: retry_policy.budget.map(RetryBudget::new),
+            retry: Arc::new(Retry::new(retry_policy)),
+            held: VecDeque::new(),
             buffer: None,
             offsets,
+            sources: SourceLines::new(),
             buffer_max_size: 2 * 1024 * 1024,
+            buffer_max_lines: None,
             buffer_bytes: 0,
+            flush_interval: Duration::from_millis(250),
             last_flush: Instant::now(),
             last_retry: Instant::now(),
             state_write,
             state_flush,
             retry_step_delay,
+            replay_ratio: 0,
+            replay_credit: 0,
+            strict_ordering: false,
+            compression_sample: None,
+            batches: 0,
+            dry_run: false,
+            codec_pool,
         }
     }
 
     pub async fn poll(&mut self) {
+        self.send_held().await;
         if self.buffer.is_none() {
             match self.buffer_source.next().await {
                 Some(Ok(buf)) => self.buffer = Some(buf),
@@ -84,23 +140,8 @@ impl Client {
                 }
             }
         }
-        if self.should_retry() {
-            self.last_retry = Instant::now();
-            match self.retry.poll().await {
-                Ok((offsets, Some(body))) => {
-                    if let (Some(sw), Some(offsets)) = (self.state_write.as_ref(), &offsets) {
-                        for (file_name, offset) in offsets {
-                            debug!("Updating offset for {:?} to {}", file_name, *offset);
-                            if let Err(e) = sw.update(file_name, *offset).await {
-                                error!("Unable to write offsets. error: {}", e);
-                            };
-                        }
-                    }
-                    self.make_request(body).await
-                }
-                Err(e) => error!("error polling retry: {}", e),
-                _ => {}
-            };
+        if self.take_replay() {
+            self.replay().await;
         }
 
         if self.should_flush() {
@@ -115,14 +156,32 @@ impl Client {
     ) {
         let key = line.get_key();
         let offset = line.get_offset();
+        let timestamp = line
+            .get_timestamp()
+            .map(|(key, nanos)| (key.to_string(), nanos));
+        let hash = match self.state_write.as_ref() {
+            Some(wh) if wh.tracks_lines() => line.get_line_hash(),
+            _ => None,
+        };
         self.poll().await;
-        match self.buffer.as_mut().unwrap(/* poll will panic if this isn't set */).write_line(line).await
-        {
+        let buffer = self.buffer.as_mut().unwrap(/* poll will panic if this isn't set */);
+        let started = Instant::now();
+        let written = buffer.write_line(line).await;
+        Metrics::http().add_serialize_time(started.elapsed().as_micros() as u64);
+        match written {
             Ok(_) => {
                 if let Some(wh) = self.state_write.as_ref() {
                     if let (Some(key), Some(offset)) = (key.as_ref(), offset) {
                         debug!("Updating offset for {:?} to {}", key, offset);
                         wh.update(key, offset).await.unwrap();
+                        wh.read(key).await.unwrap();
+                        *self.sources.entry(*key).or_default() += 1;
+                        if let Some(hash) = hash {
+                            wh.line(hash).await.unwrap();
+                        }
+                    }
+                    if let Some((key, nanos)) = timestamp {
+                        wh.timestamp(&key, nanos).await.unwrap();
                     }
                 }
                 self.buffer_bytes = self.buffer.as_ref().map(|b| b.bytes_len()).unwrap_or(0);
@@ -135,17 +194,145 @@ impl Client {
         self.buffer_max_size = size;
     }
 
+    pub fn set_max_buffer_lines(&mut self, lines: Option<usize>) {
+        self.buffer_max_lines = lines;
+    }
+
+    /// Sets how long lines can wait in the buffer before it's flushed
+    pub fn set_flush_interval(&mut self, interval: Duration) {
+        self.flush_interval = interval;
+    }
+
     pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
         self.inner.set_timeout(timeout)
     }
 
+    /// Encrypts the requests stored on disk to be retried with `key`
+    pub fn set_retry_encryption_key(&mut self, key: Option<SpoolKey>) {
+        let retry = Retry::new(self.retry.policy().clone()).with_key(key);
+        self.retry = Arc::new(match self.retry.route() {
+            Some(route) => retry.with_route(route),
+            None => retry,
+        });
+    }
+
+    /// Sends the lines of the ingestion key route `route`, storing the failed requests in a
+    /// directory of the route so that only this client retries them, and counting the requests
+    /// in the metrics of the route
+    pub fn set_route(&mut self, route: &str) {
+        let retry = Retry::new(self.retry.policy().clone()).with_key(self.retry.key().cloned());
+        self.retry = Arc::new(retry.with_route(route));
+    }
+
+    /// Replays `ratio` stored requests for each new request delivered, so that a backlog built
+    /// up during an outage is caught up with while new lines keep flowing
+    pub fn set_replay_ratio(&mut self, ratio: u32) {
+        self.replay_ratio = ratio;
+    }
+
+    /// Holds on to a failed request and retries it until it's delivered or runs out of
+    /// attempts, instead of storing it and sending the next ones meanwhile, so that no line
+    /// reaches the ingest API before the lines read ahead of it
+    pub fn set_strict_ordering(&mut self, strict: bool) {
+        self.strict_ordering = strict;
+    }
+
+    /// Compresses one in `every` batch again at `gzip_level` to measure how much compression
+    /// shrinks the requests and the time it takes, as the requests are compressed while being
+    /// sent by the inner client
+    pub fn set_compression_sample(&mut self, gzip_level: u32, every: u32) {
+        self.compression_sample = Some((gzip_level, every)).filter(|(_, every)| *every > 0);
+    }
+
+    /// Batches lines as usual but drops the batches instead of sending them
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Limits how long pooled connections are reused for, so that the ingest endpoint's
+    /// hostname is resolved again and connections follow it when its DNS record changes
+    pub fn set_connection_max_lifetime(&mut self, lifetime: Option<Duration>) {
+        self.connection_max_lifetime = lifetime;
+    }
+
+    /// Logs a warning for the requests that take longer than `threshold`, e.g. to tell an
+    /// intermittently degraded network apart from a slow pipeline
+    pub fn set_slow_request_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_request_threshold = threshold;
+    }
+
+    /// Sends `extra` on top of the template's tags, replacing the extra tags set before
+    pub fn set_extra_tags(&mut self, extra: &[String]) {
+        let mut tags = self.base_tags.clone();
+        for tag in extra {
+            match tags {
+                Some(ref mut tags) => {
+                    tags.add(tag);
+                }
+                None => tags = Some(Tags::from(vec![tag.clone()])),
+            }
+        }
+        self.template.params.tags = tags;
+        self.reconnect();
+    }
+
+    /// Replaces the inner client once its connections are past their max lifetime, dropping
+    /// its pool so the next request resolves the endpoint and connects again
+    fn recycle_connections(&mut self) {
+        match self.connection_max_lifetime {
+            Some(lifetime) if self.connected_at.elapsed() >= lifetime => {}
+            _ => return,
+        }
+        debug!("recycling ingest connections");
+        self.reconnect();
+        Metrics::http().increment_recycled_connections();
+    }
+
+    /// Replaces the inner client with one built from the current template
+    fn reconnect(&mut self) {
+        self.inner = HttpClient::new(self.template.clone());
+        if let Some(timeout) = self.timeout {
+            self.inner.set_timeout(timeout);
+        }
+        self.connected_at = Instant::now();
+    }
+
     fn should_flush(&self) -> bool {
         self.buffer_bytes >= self.buffer_max_size
-            || self.last_flush.elapsed() > Duration::from_millis(250)
+            || self.last_flush.elapsed() > self.flush_interval
+            || match (self.buffer_max_lines, self.buffer.as_ref()) {
+                (Some(max_lines), Some(buffer)) => buffer.count() >= max_lines,
+                _ => false,
+            }
+    }
+
+    /// Whether a stored request is replayed now, using up one of the replays earned by the
+    /// new requests delivered
+    fn take_replay(&mut self) -> bool {
+        if !self.should_retry() {
+            return false;
+        }
+        self.last_retry = Instant::now();
+        self.replay_credit = self.replay_credit.saturating_sub(1);
+        true
+    }
+
+    /// Earns the replays a delivered request is worth, replays themselves earn none so that
+    /// a backlog is caught up with at the pace of the new requests
+    fn earn_replays(&mut self, attempt: u32) {
+        if attempt == 1 {
+            self.replay_credit = self.replay_ratio;
+        }
     }
 
     fn should_retry(&self) -> bool {
-        self.last_retry.elapsed() > self.retry_step_delay
+        (self.replay_credit > 0 || self.last_retry.elapsed() > self.retry_step_delay)
+            && self
+                .retry_budget
+                .as_ref()
+                .map(|b| b.can_withdraw())
+                .unwrap_or(true)
     }
 
     async fn flush(&mut self) {
@@ -168,40 +355,203 @@ impl Client {
         };
         let buffer = self.buffer.replace(buffer).unwrap();
         let buffer_size = self.buffer_bytes as u64;
+        Metrics::http().observe_batch(buffer_size, buffer.count() as u64);
         self.buffer_bytes = 0;
         self.last_flush = Instant::now();
 
         Metrics::http().add_request_size(buffer_size);
         Metrics::http().increment_requests();
-        let body = buffer.end().expect("Failed to close ingest buffer");
-        self.make_request(IngestBodyBuffer::from_buffer(body)).await;
+        if let Some(route) = self.retry.route() {
+            Metrics::routes().add_request(route, buffer_size);
+        }
+        if let Some(budget) = self.retry_budget.as_mut() {
+            budget.deposit();
+        }
+        let sample_level = self.next_compression_sample();
+        let body = self.codec_pool.run(move || {
+            let started = Instant::now();
+            let body = buffer.end().expect("Failed to close ingest buffer");
+            Metrics::http().add_serialize_time(started.elapsed().as_micros() as u64);
+            let body = IngestBodyBuffer::from_buffer(body);
+            if let Some(level) = sample_level {
+                sample_compression(&body, level);
+            }
+            body
+        });
+        let sources = std::mem::take(&mut self.sources);
+        let body = match body.await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("unable to close the batch, its lines are dropped: {}", e);
+                return;
+            }
+        };
+        self.make_request(batch_id(), body, sources, 1, None).await;
     }
 
-    async fn make_request(&mut self, body: IngestBodyBuffer) {
-        let retry = self.retry.clone();
-        let sf = self.state_flush.as_ref();
+    /// The gzip level to compress the next batch at when it's sampled
+    fn next_compression_sample(&mut self) -> Option<u32> {
+        let (level, every) = self.compression_sample?;
+        self.batches = (self.batches + 1) % every;
+        Some(level).filter(|_| self.batches == 0)
+    }
+
+    /// Sends the next stored request due for a retry, returns whether there was one
+    async fn replay(&mut self) -> bool {
+        let Pending {
+            id,
+            offsets,
+            sources,
+            body,
+            attempts,
+            failed_at,
+        } = match self.retry.poll().await {
+            Ok(Some(pending)) => pending,
+            Ok(None) => return false,
+            Err(e) => {
+                error!("error polling retry: {}", e);
+                return false;
+            }
+        };
+        if let Some(budget) = self.retry_budget.as_mut() {
+            budget.withdraw();
+        }
+        if let (Some(sw), Some(offsets)) = (self.state_write.as_ref(), &offsets) {
+            for (file_name, offset) in offsets {
+                debug!("Updating offset for {:?} to {}", file_name, *offset);
+                if let Err(e) = sw.update(file_name, *offset).await {
+                    error!("Unable to write offsets. error: {}", e);
+                };
+            }
+        }
+        self.make_request(id, body, sources, attempts + 1, failed_at)
+            .await;
+        true
+    }
+
+    /// Sends the requests held back by a full spool again, not accepting more lines until
+    /// they were delivered or stored. The stored requests due, which are older, are sent
+    /// first, also making room in the spool for the held ones
+    async fn send_held(&mut self) {
+        while let Some((id, body, sources, attempts, failed_at)) = self.held.pop_front() {
+            if self.replay().await {
+                self.held
+                    .push_front((id, body, sources, attempts, failed_at));
+                continue;
+            }
+            tokio::time::sleep(self.retry.policy().delay(attempts)).await;
+            self.make_request(id, body, sources, attempts + 1, failed_at)
+                .await;
+        }
+    }
+
+    /// Stores a failed request to retry it, or holds on to it when the spool is full or the
+    /// order of the lines is kept
+    fn spool(
+        &mut self,
+        id: String,
+        body: &IngestBodyBuffer,
+        sources: SourceLines,
+        attempt: u32,
+        failed_at: Option<i64>,
+    ) {
+        if self.strict_ordering {
+            if !self.retry.exhausted(attempt) {
+                Metrics::http().increment_retries();
+                self.held
+                    .push_back((id, body.clone(), sources, attempt, failed_at));
+            }
+            return;
+        }
+        let offsets = self.offsets.as_ref();
         match self
+            .retry
+            .retry(&id, offsets, &sources, body, attempt, failed_at)
+        {
+            Ok(()) => {}
+            Err(retry::Error::Full) => {
+                warn!("retry spool is full, holding back new lines until the request is sent");
+                Metrics::http().increment_retries();
+                self.held
+                    .push_back((id, body.clone(), sources, attempt, failed_at));
+            }
+            Err(e) => error!("failed to retry request: {}", e),
+        }
+    }
+
+    fn check_duration(&self, duration: Duration, bytes: usize, id: &str) {
+        match self.slow_request_threshold {
+            Some(threshold) if duration > threshold => {}
+            _ => return,
+        }
+        Metrics::http().increment_slow_requests();
+        warn!(
+            "slow ingest request: id={} endpoint={}{} bytes={} duration_ms={} threshold_ms={}",
+            id,
+            self.template.host,
+            self.template.endpoint,
+            bytes,
+            duration.as_millis(),
+            self.slow_request_threshold.unwrap_or_default().as_millis()
+        );
+    }
+
+    /// Sends the request of batch `id` with the lines of `sources`, `attempt` counts the
+    /// attempts including this one and `failed_at` is when the first one failed
+    async fn make_request(
+        &mut self,
+        id: String,
+        body: IngestBodyBuffer,
+        sources: SourceLines,
+        attempt: u32,
+        failed_at: Option<i64>,
+    ) {
+        if self.dry_run {
+            debug!("dry run, dropping batch instead of sending it");
+            return;
+        }
+        self.recycle_connections();
+        let bytes = body.len();
+        let started = Instant::now();
+        let result = self
             .inner
             .send(self.limiter.get_slot(body).as_ref().clone())
-            .await
-        {
-            Ok(Response::Failed(_, s, r)) => warn!("bad response {}: {}", s, r),
+            .await;
+        self.check_duration(started.elapsed(), bytes, &id);
+        let failed = matches!(result, Err(_) | Ok(Response::Failed(..)));
+        if let Some(route) = self.retry.route().filter(|_| failed) {
+            Metrics::routes().increment_failures(route);
+        }
+        let sf = self.state_flush.as_ref();
+        match result {
+            Ok(Response::Failed(_, s, r)) => {
+                let rejection = Rejection::classify(s.as_u16(), &r);
+                rejection.count();
+                warn!("{}", rejection);
+            }
             Err(HttpError::Send(body, e)) => {
                 warn!("failed sending http request, retrying: {}", e);
-                if let Err(e) = retry.retry(self.offsets.as_ref(), &body) {
-                    error!("failed to retry request: {}", e)
-                }
+                self.spool(id, &body, sources, attempt, failed_at);
             }
             Err(HttpError::Timeout(body)) => {
-                warn!("failed sending http request, retrying: request timed out!");
-                if let Err(e) = retry.retry(self.offsets.as_ref(), &body) {
-                    error!("failed to retry request: {}", e)
-                };
+                warn!(
+                    "failed sending http request {}, retrying: request timed out!",
+                    id
+                );
+                self.spool(id, &body, sources, attempt, failed_at);
             }
             Err(e) => {
                 warn!("failed sending http request: {}", e);
             }
             Ok(Response::Sent) => {
+                self.earn_replays(attempt);
+                if let Some(sw) = self.state_write.as_ref() {
+                    for (key, lines) in sources.iter() {
+                        if let Err(e) = sw.delivered(key, *lines).await {
+                            error!("Unable to count delivered lines. error: {}", e);
+                        }
+                    }
+                }
                 if let Some(sf) = sf {
                     // Flush the state
                     if let Err(e) = sf.flush().await {
@@ -214,3 +564,75 @@ impl Client {
         }
     }
 }
+
+fn sample_compression(body: &IngestBodyBuffer, level: u32) {
+    let started = Instant::now();
+    match compressed_len(body, level) {
+        Ok((bytes, compressed)) => Metrics::http().add_compression_sample(
+            bytes,
+            compressed,
+            started.elapsed().as_micros() as u64,
+        ),
+        Err(e) => debug!("unable to sample the compression of a batch: {}", e),
+    }
+}
+
+/// Bytes of `body` and of it compressed at `level`
+fn compressed_len(body: &IngestBodyBuffer, level: u32) -> std::io::Result<(u64, u64)> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    let bytes = std::io::copy(&mut body.reader(), &mut encoder)?;
+    Ok((bytes, encoder.finish()?.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::params::Params;
+
+    fn test_client(retry_step_delay: Duration) -> Client {
+        let params = Params::builder()
+            .hostname("test-host".to_string())
+            .build()
+            .unwrap();
+        let template = RequestTemplate::builder()
+            .host("localhost".to_string())
+            .endpoint("/logs/ingest".to_string())
+            .api_key("key".to_string())
+            .params(params)
+            .build()
+            .unwrap();
+        Client::new(
+            template,
+            None,
+            RetryPolicy::constant(retry_step_delay),
+            retry_step_delay,
+            CodecPool::new(Some(1)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn replays_stored_requests_for_each_new_one_delivered() {
+        let mut client = test_client(Duration::from_secs(3600));
+        client.set_replay_ratio(2);
+        assert!(!client.take_replay());
+
+        // A replay delivered earns nothing, a new request earns the ratio
+        client.earn_replays(3);
+        assert!(!client.take_replay());
+        client.earn_replays(1);
+        assert!(client.take_replay());
+        assert!(client.take_replay());
+        assert!(!client.take_replay());
+    }
+
+    #[test]
+    fn replays_on_the_step_delay_without_a_ratio() {
+        let mut client = test_client(Duration::from_secs(3600));
+        client.earn_replays(1);
+        assert!(!client.take_replay());
+
+        let mut client = test_client(Duration::from_millis(0));
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(client.take_replay());
+    }
+}
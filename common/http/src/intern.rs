@@ -0,0 +1,89 @@
+use lazy_static::lazy_static;
+use metrics::Metrics;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Distinct values held before the ones no line uses anymore are dropped
+const MIN_PRUNE_LEN: usize = 1024;
+
+lazy_static! {
+    static ref VALUES: Mutex<Interner> = Mutex::new(Interner::default());
+}
+
+/// A copy of `value` shared with every line the same value was interned for, e.g. the path of
+/// the file or the app of its lines, so that lines share its allocation instead of each
+/// holding their own
+pub fn intern(value: &str) -> Arc<str> {
+    VALUES.lock().unwrap().intern(value)
+}
+
+/// Values interned for the lines in flight. Once they outnumber twice the values still in use
+/// after the last prune, the ones only the interner holds are dropped, so that the values of
+/// rotated files or deleted pods don't pile up.
+#[derive(Default)]
+struct Interner {
+    values: HashSet<Arc<str>>,
+    bytes: u64,
+    prune_len: usize,
+}
+
+impl Interner {
+    fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(interned) = self.values.get(value) {
+            Metrics::memory().add_interning_saved(value.len() as u64);
+            return interned.clone();
+        }
+        if self.values.len() >= std::cmp::max(self.prune_len, MIN_PRUNE_LEN) {
+            self.prune();
+        }
+        let interned: Arc<str> = Arc::from(value);
+        self.values.insert(interned.clone());
+        self.bytes += value.len() as u64;
+        self.report();
+        interned
+    }
+
+    fn prune(&mut self) {
+        let bytes = &mut self.bytes;
+        self.values.retain(|value| {
+            let used = Arc::strong_count(value) > 1;
+            if !used {
+                *bytes -= value.len() as u64;
+            }
+            used
+        });
+        self.prune_len = self.values.len() * 2;
+        self.report();
+    }
+
+    fn report(&self) {
+        Metrics::memory().set_interned(self.values.len() as u64, self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_values_and_drops_the_unused_ones() {
+        let mut interner = Interner::default();
+        let path = interner.intern("/var/log/containers/app.log");
+        let again = interner.intern("/var/log/containers/app.log");
+        assert!(Arc::ptr_eq(&path, &again));
+        assert_eq!(interner.values.len(), 1);
+
+        for i in 0..MIN_PRUNE_LEN {
+            interner.intern(&format!("/var/log/rotated.log.{}", i));
+        }
+        // Only the value still held by lines survives the prune
+        assert_eq!(interner.values.len(), 2);
+        assert_eq!(interner.prune_len, 2);
+        assert_eq!(
+            interner.bytes,
+            (path.len() + format!("/var/log/rotated.log.{}", MIN_PRUNE_LEN - 1).len()) as u64
+        );
+        assert!(Arc::ptr_eq(&path, &interner.intern(&path)));
+    }
+}
@@ -1,9 +1,14 @@
 #[macro_use]
 extern crate log;
 
+pub mod cipher;
 pub mod client;
+pub mod intern;
 pub mod limit;
+pub mod pool;
+pub mod rejection;
 pub mod retry;
+pub mod validate;
 
 pub mod types {
     pub use logdna_client::*;
@@ -0,0 +1,82 @@
+use futures::channel::oneshot;
+use metrics::Metrics;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use thiserror::Error;
+
+use std::sync::Arc;
+
+#[derive(Debug, Error)]
+#[error("a job of the codec pool panicked")]
+pub struct JobPanicked;
+
+/// The threads batches are closed on and archive objects compressed on, shared by the clients
+/// and sinks of the agent so that large batches don't hold up the tailing tasks sharing the
+/// runtime's threads
+#[derive(Clone)]
+pub struct CodecPool {
+    pool: Arc<ThreadPool>,
+}
+
+impl CodecPool {
+    /// Starts the pool with `threads` threads, None starting one per CPU
+    pub fn new(threads: Option<usize>) -> Result<Self, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .thread_name(|i| format!("logdna-codec-{}", i))
+            .panic_handler(|_| error!("a job of the codec pool panicked"))
+            .build()?;
+        Ok(CodecPool {
+            pool: Arc::new(pool),
+        })
+    }
+
+    /// Runs `job` on the pool and waits for its result, which is lost when the job panics
+    pub async fn run<T, F>(&self, job: F) -> Result<T, JobPanicked>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.spawn(move || {
+            // The caller may have stopped waiting for it
+            let _ = tx.send(job());
+        });
+        rx.await.map_err(|_| JobPanicked)
+    }
+
+    /// Runs `job` on the pool without waiting for it
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        Metrics::http().increment_codec_queue();
+        self.pool.spawn(move || {
+            Metrics::http().decrement_codec_queue();
+            job()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn runs_jobs_off_the_calling_thread() {
+        let pool = CodecPool::new(Some(1)).unwrap();
+        let caller = std::thread::current().id();
+        let (thread, name) = block_on(pool.run(|| {
+            let thread = std::thread::current();
+            (thread.id(), thread.name().map(String::from))
+        }))
+        .unwrap();
+        assert_ne!(thread, caller);
+        assert!(name.unwrap().starts_with("logdna-codec-"));
+    }
+
+    #[test]
+    fn returns_an_error_when_a_job_panics() {
+        let pool = CodecPool::new(Some(1)).unwrap();
+        let result = block_on(pool.run(|| -> u32 { panic!("broken batch") }));
+        assert!(result.is_err());
+        assert_eq!(block_on(pool.run(|| 1)).unwrap(), 1);
+    }
+}
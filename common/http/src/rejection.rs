@@ -0,0 +1,103 @@
+use metrics::Metrics;
+use serde_json::Value;
+use thiserror::Error;
+
+/// Characters of a response body kept in the message of a rejection
+const MAX_MESSAGE_CHARS: usize = 256;
+
+/// Why the ingest API refused a request, from the status and body of its response
+#[derive(Debug, Error, PartialEq)]
+pub enum Rejection {
+    #[error("ingestion key rejected with {0}: {1}, check that LOGDNA_INGESTION_KEY is correct")]
+    InvalidKey(u16, String),
+    #[error("ingestion quota exceeded, rejected with {0}: {1}")]
+    QuotaExceeded(u16, String),
+    #[error("malformed payload rejected with {0}: {1}")]
+    MalformedPayload(u16, String),
+    #[error("ingest API failed with {0}: {1}")]
+    Server(u16, String),
+    #[error("request rejected with {0}: {1}")]
+    Other(u16, String),
+}
+
+impl Rejection {
+    /// Classifies a response by its status, and by its message for the statuses the API
+    /// answers for several reasons. The message is the `error` or `message` field of a JSON
+    /// body, the body itself otherwise.
+    pub fn classify(status: u16, body: &str) -> Self {
+        let message = match serde_json::from_str::<Value>(body) {
+            Ok(Value::Object(fields)) => fields
+                .get("error")
+                .or_else(|| fields.get("message"))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            _ => None,
+        }
+        .unwrap_or_else(|| body.trim().to_string());
+        let message = match message.char_indices().nth(MAX_MESSAGE_CHARS) {
+            Some((end, _)) => format!("{}...", &message[..end]),
+            None => message,
+        };
+
+        match status {
+            401 | 403 if message.to_lowercase().contains("quota") => {
+                Rejection::QuotaExceeded(status, message)
+            }
+            401 | 403 => Rejection::InvalidKey(status, message),
+            402 | 429 => Rejection::QuotaExceeded(status, message),
+            400 | 413 | 415 | 422 => Rejection::MalformedPayload(status, message),
+            500..=599 => Rejection::Server(status, message),
+            _ => Rejection::Other(status, message),
+        }
+    }
+
+    /// Counts the rejection in the ingest metrics of its class
+    pub fn count(&self) {
+        match self {
+            Rejection::InvalidKey(..) => Metrics::http().increment_rejected_invalid_key(),
+            Rejection::QuotaExceeded(..) => Metrics::http().increment_rejected_quota(),
+            Rejection::MalformedPayload(..) => Metrics::http().increment_rejected_malformed(),
+            Rejection::Server(..) => Metrics::http().increment_rejected_server(),
+            Rejection::Other(..) => Metrics::http().increment_rejected_other(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_responses() {
+        assert_eq!(
+            Rejection::classify(401, r#"{"error":"Invalid API key","status":"error"}"#),
+            Rejection::InvalidKey(401, "Invalid API key".to_string())
+        );
+        assert_eq!(
+            Rejection::classify(403, r#"{"error":"Daily quota exceeded"}"#),
+            Rejection::QuotaExceeded(403, "Daily quota exceeded".to_string())
+        );
+        assert_eq!(
+            Rejection::classify(429, "Too Many Requests\n"),
+            Rejection::QuotaExceeded(429, "Too Many Requests".to_string())
+        );
+        assert_eq!(
+            Rejection::classify(400, r#"{"message":"lines must be an array"}"#),
+            Rejection::MalformedPayload(400, "lines must be an array".to_string())
+        );
+        assert_eq!(
+            Rejection::classify(502, "<html>Bad Gateway</html>"),
+            Rejection::Server(502, "<html>Bad Gateway</html>".to_string())
+        );
+        assert_eq!(
+            Rejection::classify(404, "[]"),
+            Rejection::Other(404, "[]".to_string())
+        );
+
+        let long = "x".repeat(MAX_MESSAGE_CHARS + 10);
+        match Rejection::classify(418, &long) {
+            Rejection::Other(_, message) => assert_eq!(message.len(), MAX_MESSAGE_CHARS + 3),
+            rejection => panic!("unexpected rejection {:?}", rejection),
+        }
+    }
+}
@@ -1,20 +1,24 @@
-use std::fs::{create_dir_all, read_dir, remove_file, File, OpenOptions};
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::str::FromStr;
 
 use chrono::prelude::Utc;
 use crossbeam::queue::SegQueue;
-use uuid::Uuid;
 
+use crate::cipher::{self, CipherError, SpoolKey};
 use crate::types::body::{IngestBody, IngestBodyBuffer, IntoIngestBodyBuffer};
 use crate::Offset;
 use metrics::Metrics;
 use serde::Deserialize;
 use std::path::{Path, PathBuf};
 
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
+/// Where the requests to retry are stored, those of ingestion key routes in a directory each
+const SPOOL_DIR: &str = "/tmp/logdna";
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -25,96 +29,361 @@ pub enum Error {
     Recv(#[from] crossbeam::channel::RecvError),
     #[error(transparent)]
     Send(#[from] crossbeam::channel::SendError<Box<IngestBodyBuffer>>),
-    #[error("{0:?} is not valid utf8")]
-    NonUtf8(std::path::PathBuf),
-    #[error("{0} is not a valid file name")]
-    InvalidFileName(std::string::String),
+    #[error(transparent)]
+    Cipher(#[from] CipherError),
+    #[error("{0:?} is encrypted, it can't be read without the retry encryption key")]
+    Encrypted(std::path::PathBuf),
+    #[error("the retry spool is full")]
+    Full,
+}
+
+/// What happens to a failed request when the spool has no room left for it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpoolFull {
+    /// The oldest requests are dropped to make room
+    DropOldest,
+    /// The request is held in memory, and no other request is sent, until it's delivered or
+    /// there is room for it
+    BlockNew,
+}
+
+impl SpoolFull {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "drop-oldest" => Some(SpoolFull::DropOldest),
+            "block-new" => Some(SpoolFull::BlockNew),
+            _ => None,
+        }
+    }
+}
+
+/// Bounds of the requests stored on disk to be retried
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpoolPolicy {
+    /// Bytes the stored requests can take up, unlimited when `None`
+    pub max_bytes: Option<u64>,
+    /// How long after it first failed a request is dropped, kept until delivered when `None`
+    pub max_age: Option<Duration>,
+    pub when_full: SpoolFull,
+}
+
+impl Default for SpoolPolicy {
+    fn default() -> Self {
+        SpoolPolicy {
+            max_bytes: None,
+            max_age: None,
+            when_full: SpoolFull::DropOldest,
+        }
+    }
+}
+
+/// How failed requests are retried
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry of a request
+    pub base_delay: Duration,
+    /// Upper bound of the delay, which doubles with every attempt until reaching it
+    pub max_delay: Duration,
+    /// Share of each delay, between 0 and 1, that is randomized so agents that failed at
+    /// the same time don't all retry at the same time
+    pub jitter: f64,
+    /// Attempts made to send a request before dropping it, unlimited when `None`
+    pub max_attempts: Option<u32>,
+    /// Share of requests that may be retries, unlimited when `None`
+    pub budget: Option<f64>,
+    pub spool: SpoolPolicy,
+}
+
+impl RetryPolicy {
+    /// A policy retrying every request indefinitely after the same delay
+    pub fn constant(delay: Duration) -> Self {
+        RetryPolicy {
+            base_delay: delay,
+            max_delay: delay,
+            jitter: 0.0,
+            max_attempts: None,
+            budget: None,
+            spool: SpoolPolicy::default(),
+        }
+    }
+
+    /// The delay before retrying a request that failed `attempts` times
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .checked_mul(1u32 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay.max(self.base_delay));
+        let jitter = self.jitter.max(0.0).min(1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        delay.mul_f64(1.0 - jitter * rand::random::<f64>())
+    }
+}
+
+/// Limits retries to a share of the requests sent, so that a fleet of agents recovering
+/// from an outage doesn't overwhelm the ingestion tier with its backlog
+pub(crate) struct RetryBudget {
+    ratio: f64,
+    balance: f64,
+}
+
+/// Retries that can be made in a row before the budget runs out, so that agents sending
+/// few requests can still retry
+const MAX_BUDGET_BALANCE: f64 = 10.0;
+
+impl RetryBudget {
+    pub(crate) fn new(ratio: f64) -> Self {
+        RetryBudget {
+            ratio,
+            balance: MAX_BUDGET_BALANCE,
+        }
+    }
+
+    /// Records a new request, which earns a fraction of a retry
+    pub(crate) fn deposit(&mut self) {
+        self.balance = (self.balance + self.ratio).min(MAX_BUDGET_BALANCE);
+    }
+
+    pub(crate) fn can_withdraw(&self) -> bool {
+        self.balance >= 1.0
+    }
+
+    pub(crate) fn withdraw(&mut self) {
+        self.balance = (self.balance - 1.0).max(0.0);
+    }
+}
+
+/// Lines of a request by the inode of the file they were read from
+pub type SourceLines = HashMap<u64, u64>;
+
+/// A request read back from disk to be retried
+pub struct Pending {
+    /// Id of the batch, shared by all the attempts at sending it
+    pub id: String,
+    pub offsets: Option<Vec<Offset>>,
+    pub sources: SourceLines,
+    pub body: IngestBodyBuffer,
+    /// Attempts already made to send the request
+    pub attempts: u32,
+    /// When the request first failed, in milliseconds since the epoch
+    pub failed_at: Option<i64>,
 }
 
-#[derive(Default)]
 pub struct Retry {
     waiting: SegQueue<PathBuf>,
-    retry_base_delay_secs: i64,
+    policy: RetryPolicy,
+    key: Option<SpoolKey>,
+    dir: PathBuf,
+    /// The ingestion key route the requests are sent for, None for the default key
+    route: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct DiskRead {
+    // Missing from the files written by older versions
+    #[serde(default)]
+    id: Option<String>,
     offsets: Option<Vec<Offset>>,
+    #[serde(default)]
+    sources: SourceLines,
     body: IngestBody,
+    // Missing from the files written by older versions
+    #[serde(default)]
+    attempts: Option<u32>,
+    #[serde(default)]
+    failed_at: Option<i64>,
 }
 
 impl Retry {
-    pub fn new(retry_base_delay: Duration) -> Retry {
-        create_dir_all("/tmp/logdna/").expect("can't create /tmp/logdna");
+    pub fn new(policy: RetryPolicy) -> Retry {
+        create_dir_all(SPOOL_DIR).expect("can't create /tmp/logdna");
         Retry {
             waiting: SegQueue::new(),
-            retry_base_delay_secs: retry_base_delay.as_secs() as i64,
+            policy,
+            key: None,
+            dir: PathBuf::from(SPOOL_DIR),
+            route: None,
         }
     }
 
+    /// Stores the requests of the ingestion key route `route` in a directory of its own, so
+    /// that they are only retried by the client of the route, and counts its spool usage in the
+    /// metrics of the route
+    pub fn with_route(mut self, route: &str) -> Self {
+        self.dir = Path::new(SPOOL_DIR).join("routes").join(route);
+        create_dir_all(&self.dir).expect("can't create the spool directory of the route");
+        self.route = Some(route.to_string());
+        self
+    }
+
+    /// Encrypts the requests written to disk with `key`, files written in plaintext before
+    /// are still read
+    pub fn with_key(mut self, key: Option<SpoolKey>) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    pub fn key(&self) -> Option<&SpoolKey> {
+        self.key.as_ref()
+    }
+
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    /// Stores the request of batch `id` that failed `attempts` times, the first time at
+    /// `failed_at`, to retry it once its delay elapsed, or drops it when it ran out of
+    /// attempts. Fails with `Error::Full` when the spool is full and blocks new requests.
     pub fn retry(
         &self,
+        id: &str,
         offsets: Option<&Vec<Offset>>,
+        sources: &SourceLines,
         body: &IngestBodyBuffer,
+        attempts: u32,
+        failed_at: Option<i64>,
     ) -> Result<(), Error> {
-        Metrics::http().increment_retries();
-        let mut file = OpenOptions::new().create(true).write(true).open(format!(
-            "/tmp/logdna/{}_{}.retry",
-            Utc::now().timestamp(),
-            Uuid::new_v4().to_string()
-        ))?;
-        file.write_all(b"{")?;
+        if self.exhausted(attempts) {
+            return Ok(());
+        }
+        // Files are named after when they can be retried, in milliseconds
+        let delay = self.policy.delay(attempts).as_millis() as i64;
+        let retry_at = Utc::now().timestamp_millis() + delay;
+        let failed_at = failed_at.unwrap_or_else(|| Utc::now().timestamp_millis());
+        let mut data = Vec::new();
+        write!(
+            data,
+            "{{\"attempts\":{},\"failed_at\":{},",
+            attempts, failed_at
+        )?;
+        data.write_all(b"\"id\":")?;
+        serde_json::to_writer(&mut data, id)?;
+        data.write_all(b",")?;
         if let Some(offsets) = offsets {
-            file.write_all(b"\"offsets\":")?;
-            serde_json::to_writer(&mut file, &offsets)?;
-            file.write_all(b",")?;
+            data.write_all(b"\"offsets\":")?;
+            serde_json::to_writer(&mut data, &offsets)?;
+            data.write_all(b",")?;
         };
-        file.write_all(b"\"body\":")?;
+        if !sources.is_empty() {
+            data.write_all(b"\"sources\":")?;
+            serde_json::to_writer(&mut data, sources)?;
+            data.write_all(b",")?;
+        }
+        data.write_all(b"\"body\":")?;
         let mut reader = body.reader();
-        let _bytes_written = std::io::copy(&mut reader, &mut file)?;
-        file.write_all(b"}")?;
+        let _bytes_written = std::io::copy(&mut reader, &mut data)?;
+        data.write_all(b"}")?;
+        if let Some(key) = self.key.as_ref() {
+            data = key.seal(&data)?;
+        }
+        if let Some(max_bytes) = self.policy.spool.max_bytes {
+            if !self.make_room(data.len() as u64, max_bytes)? {
+                return Ok(());
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(self.dir.join(format!("{}_{}.retry", retry_at, id)))?;
+        file.write_all(&data)?;
+        // Counted once stored, the client counts the requests it holds instead
+        Metrics::http().increment_retries();
         Ok(())
     }
 
-    pub async fn poll(&self) -> Result<(Option<Vec<Offset>>, Option<IngestBodyBuffer>), Error> {
+    /// Whether a request that failed `attempts` times ran out of attempts, it's then dropped
+    pub fn exhausted(&self, attempts: u32) -> bool {
+        match self.policy.max_attempts {
+            Some(max_attempts) if attempts >= max_attempts => {
+                warn!("dropping request after {} failed attempts", attempts);
+                Metrics::http().increment_retries_exhausted();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn poll(&self) -> Result<Option<Pending>, Error> {
         if self.waiting.is_empty() {
             self.fill_waiting()?
         }
 
         if let Some(path) = self.waiting.pop() {
-            let (offsets, ingest_body) = Self::read_from_disk(&path)?;
-            return Ok((
+            let DiskRead {
+                id,
                 offsets,
-                Some(IntoIngestBodyBuffer::into(ingest_body).await?),
-            ));
+                sources,
+                body,
+                attempts,
+                failed_at,
+            } = match self.read_from_disk(&path) {
+                Ok(read) => read,
+                // Dropped to make room since it was listed
+                Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if let (Some(failed_at), None) = (failed_at, self.route.as_ref()) {
+                let age = Utc::now().timestamp_millis() - failed_at;
+                Metrics::http().set_backlog_age(age.max(0) as u64);
+            }
+            if let (Some(max_age), Some(failed_at)) = (self.policy.spool.max_age, failed_at) {
+                if Utc::now().timestamp_millis() - failed_at >= max_age.as_millis() as i64 {
+                    warn!("dropping request that failed more than {:?} ago", max_age);
+                    Metrics::http().increment_spool_expired();
+                    return Ok(None);
+                }
+            }
+            return Ok(Some(Pending {
+                id: id.unwrap_or_else(batch_id),
+                offsets,
+                sources,
+                body: IntoIngestBodyBuffer::into(body).await?,
+                attempts: attempts.unwrap_or(1),
+                failed_at,
+            }));
         }
 
-        Ok((None, None))
+        Ok(None)
     }
 
     fn fill_waiting(&self) -> Result<(), Error> {
-        let files = read_dir("/tmp/logdna/")?;
+        self.expire()?;
+        let files = read_dir(&self.dir)?;
         for file in files {
             let path = file?.path();
-            if path.is_dir() {
+            // Only the requests are retried, other files may be kept in the directory
+            if path
+                .extension()
+                .map_or(true, |extension| extension != "retry")
+            {
                 continue;
             }
 
-            let file_name = path
+            let retry_at = match path
                 .file_name()
                 .and_then(|s| s.to_str())
-                .map(|s| s.to_string())
-                .ok_or_else(|| Error::NonUtf8(path.clone()))?;
-
-            let timestamp: i64 = file_name
-                .split('_')
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>()
-                .get(0)
-                .and_then(|s| FromStr::from_str(s).ok())
-                .ok_or_else(|| Error::InvalidFileName(file_name.clone()))?;
-
-            if Utc::now().timestamp() - timestamp < self.retry_base_delay_secs {
+                .and_then(|s| s.split('_').next())
+                .and_then(|s| i64::from_str(s).ok())
+            {
+                Some(retry_at) => retry_at,
+                None => {
+                    debug!("skipping {:?}, it isn't named after when to retry it", path);
+                    continue;
+                }
+            };
+
+            // Older versions named files after when they were written, in seconds, which are
+            // always in the past when read as milliseconds
+            if Utc::now().timestamp_millis() < retry_at {
                 continue;
             }
 
@@ -124,12 +393,289 @@ impl Retry {
         Ok(())
     }
 
-    fn read_from_disk(path: &Path) -> Result<(Option<Vec<Offset>>, IngestBody), Error> {
+    /// Drops the requests written more than the max age ago, which first failed even earlier,
+    /// and updates the spool usage
+    fn expire(&self) -> Result<(), Error> {
+        let mut files = spooled(&self.dir)?;
+        if let Some(max_age) = self.policy.spool.max_age {
+            let mut expired = Vec::new();
+            files.retain(|file| {
+                let keep = file.written.elapsed().map_or(true, |age| age < max_age);
+                if !keep {
+                    expired.push(file.path.clone());
+                }
+                keep
+            });
+            for path in expired {
+                warn!("dropping request that failed more than {:?} ago", max_age);
+                remove_file(&path)?;
+                Metrics::http().increment_spool_expired();
+            }
+        }
+        self.report_usage(&files);
+        Ok(())
+    }
+
+    /// Whether `bytes` more fit in the spool, dropping the oldest requests to make room
+    /// unless new requests are blocked
+    fn make_room(&self, bytes: u64, max_bytes: u64) -> Result<bool, Error> {
+        if bytes > max_bytes {
+            warn!(
+                "dropping request of {} bytes, larger than the retry spool",
+                bytes
+            );
+            Metrics::http().increment_spool_dropped();
+            return Ok(false);
+        }
+        let mut files = spooled(&self.dir)?;
+        let mut used: u64 = files.iter().map(|file| file.bytes).sum();
+        if used + bytes > max_bytes && self.policy.spool.when_full == SpoolFull::BlockNew {
+            self.report_usage(&files);
+            return Err(Error::Full);
+        }
+        while used + bytes > max_bytes && !files.is_empty() {
+            let oldest = files.remove(0);
+            warn!("retry spool is full, dropping request {:?}", oldest.path);
+            remove_file(&oldest.path)?;
+            Metrics::http().increment_spool_dropped();
+            used -= oldest.bytes;
+        }
+        self.set_spool_usage(used + bytes, files.len() as u64 + 1);
+        Ok(true)
+    }
+
+    /// Reads the request stored in `path` and removes it. A request that can't be decrypted or
+    /// parsed is moved aside with the `failed` extension instead, so that it's kept, e.g. to be
+    /// retried once the right key is set, without being read again on every poll
+    fn read_from_disk(&self, path: &Path) -> Result<DiskRead, Error> {
         let mut file = File::open(path)?;
-        let mut data = String::new();
-        file.read_to_string(&mut data)?;
-        remove_file(&path)?;
-        let DiskRead { offsets, body } = serde_json::from_str(&data)?;
-        Ok((offsets, body))
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        match self.decode(path, data) {
+            Ok(read) => {
+                remove_file(&path)?;
+                Ok(read)
+            }
+            Err(e) => {
+                let failed = path.with_extension("failed");
+                error!(
+                    "unable to read request {:?}, moving it to {:?}: {}",
+                    path, failed, e
+                );
+                rename(path, &failed)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn report_usage(&self, files: &[SpooledFile]) {
+        if files.is_empty() && self.route.is_none() {
+            Metrics::http().set_backlog_age(0);
+        }
+        self.set_spool_usage(
+            files.iter().map(|file| file.bytes).sum(),
+            files.len() as u64,
+        );
+    }
+
+    fn set_spool_usage(&self, bytes: u64, files: u64) {
+        match self.route.as_deref() {
+            Some(route) => Metrics::routes().set_spool_usage(route, bytes, files),
+            None => Metrics::http().set_spool_usage(bytes, files),
+        }
+    }
+
+    fn decode(&self, path: &Path, mut data: Vec<u8>) -> Result<DiskRead, Error> {
+        if data.starts_with(cipher::MAGIC) {
+            let key = self
+                .key
+                .as_ref()
+                .ok_or_else(|| Error::Encrypted(path.into()))?;
+            data = key.open(&data)?;
+        }
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+struct SpooledFile {
+    path: PathBuf,
+    bytes: u64,
+    written: SystemTime,
+}
+
+/// The requests stored in `dir`, the oldest first
+fn spooled(dir: &Path) -> Result<Vec<SpooledFile>, Error> {
+    let mut files = Vec::new();
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "retry")
+        {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            // Read by a poll since it was listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        files.push(SpooledFile {
+            path,
+            bytes: metadata.len(),
+            written: metadata.modified()?,
+        });
+    }
+    files.sort_by_key(|file| file.written);
+    Ok(files)
+}
+
+/// A new id for a batch, assigned once when it's flushed and kept by all its attempts
+pub fn batch_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_up_to_max_delay() {
+        let policy = RetryPolicy {
+            max_delay: Duration::from_secs(10),
+            max_attempts: Some(5),
+            ..RetryPolicy::constant(Duration::from_secs(1))
+        };
+        let delays: Vec<u64> = (1..=6).map(|a| policy.delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(policy.delay(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_shortens_delays() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..RetryPolicy::constant(Duration::from_secs(10))
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn parses_spool_full_policies() {
+        assert_eq!(SpoolFull::parse("drop-oldest"), Some(SpoolFull::DropOldest));
+        assert_eq!(SpoolFull::parse("block-new"), Some(SpoolFull::BlockNew));
+        assert_eq!(SpoolFull::parse("block"), None);
+    }
+
+    #[test]
+    fn budget_limits_retries_to_share_of_requests() {
+        let mut budget = RetryBudget::new(0.25);
+        while budget.can_withdraw() {
+            budget.withdraw();
+        }
+        for _ in 0..3 {
+            budget.deposit();
+        }
+        assert!(!budget.can_withdraw());
+        budget.deposit();
+        assert!(budget.can_withdraw());
+    }
+
+    #[test]
+    fn unreadable_requests_are_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let retry = Retry::new(RetryPolicy::constant(Duration::from_secs(1)));
+        let encrypted = dir.path().join("1_0123456789abcdef.retry");
+        std::fs::write(&encrypted, [cipher::MAGIC, &b"sealed"[..]].concat()).unwrap();
+        assert!(matches!(
+            retry.read_from_disk(&encrypted),
+            Err(Error::Encrypted(_))
+        ));
+        assert!(!encrypted.exists());
+        assert!(dir.path().join("1_0123456789abcdef.failed").exists());
+
+        let truncated = dir.path().join("2_0123456789abcdef.retry");
+        std::fs::write(&truncated, b"{\"attempts\":1,\"bo").unwrap();
+        assert!(matches!(
+            retry.read_from_disk(&truncated),
+            Err(Error::Serde(_))
+        ));
+        assert!(dir.path().join("2_0123456789abcdef.failed").exists());
+    }
+
+    fn expiring_retry(dir: &Path, max_age: Duration) -> Retry {
+        let mut retry = Retry::new(RetryPolicy {
+            spool: SpoolPolicy {
+                max_age: Some(max_age),
+                ..SpoolPolicy::default()
+            },
+            ..RetryPolicy::constant(Duration::from_secs(1))
+        });
+        retry.dir = dir.to_path_buf();
+        retry
+    }
+
+    fn spool_request(dir: &Path, name: &str, failed_at: i64) -> PathBuf {
+        let path = dir.join(name);
+        let request = serde_json::json!({
+            "attempts": 1,
+            "failed_at": failed_at,
+            "id": "0123456789abcdef",
+            "body": { "lines": [] },
+        });
+        std::fs::write(&path, request.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn drops_requests_stored_longer_than_the_max_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now().timestamp_millis();
+        let old = spool_request(dir.path(), "1_0123456789abcdef.retry", now);
+        std::thread::sleep(Duration::from_millis(50));
+
+        expiring_retry(dir.path(), Duration::from_millis(20))
+            .expire()
+            .unwrap();
+        assert!(!old.exists());
+
+        let recent = spool_request(dir.path(), "2_0123456789abcdef.retry", now);
+        expiring_retry(dir.path(), Duration::from_secs(3600))
+            .expire()
+            .unwrap();
+        assert!(recent.exists());
+    }
+
+    #[test]
+    fn drops_requests_that_first_failed_longer_than_the_max_age_ago() {
+        let dir = tempfile::tempdir().unwrap();
+        let two_hours_ago = Utc::now().timestamp_millis() - 2 * 3600 * 1000;
+        // Stored again recently, after failing for the first time long ago
+        let path = spool_request(dir.path(), "1_0123456789abcdef.retry", two_hours_ago);
+
+        let retry = expiring_retry(dir.path(), Duration::from_secs(3600));
+        let pending = futures::executor::block_on(retry.poll()).unwrap();
+        assert!(pending.is_none());
+        assert!(!path.exists());
+        assert!(spooled(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn batch_ids_are_unique() {
+        assert_ne!(batch_id(), batch_id());
+        assert_eq!(batch_id().len(), 16);
+    }
+
+    #[test]
+    fn routes_spool_in_a_directory_of_their_own() {
+        let retry = Retry::new(RetryPolicy::constant(Duration::from_secs(1))).with_route("test");
+        assert_eq!(retry.route(), Some("test"));
+        assert_eq!(retry.dir, Path::new("/tmp/logdna/routes/test"));
+        assert!(retry.dir.is_dir());
+        assert!(spooled(&retry.dir).unwrap().is_empty());
     }
 }
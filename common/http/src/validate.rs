@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::types::body::{IngestBody, IntoIngestBodyBuffer};
+use crate::types::client::Client as HttpClient;
+use crate::types::error::HttpError;
+use crate::types::request::RequestTemplate;
+use crate::types::response::Response;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("ingestion key rejected ({0}), check that LOGDNA_INGESTION_KEY is correct")]
+    Unauthorized(String),
+    #[error("ingest API answered {0}: {1}, check LOGDNA_HOST and LOGDNA_ENDPOINT")]
+    Rejected(String, String),
+    #[error("unable to reach the ingest API, check the network and TLS settings: {0}")]
+    Unreachable(String),
+    #[error("no answer from the ingest API within {0:?}, check LOGDNA_REQUEST_TIMEOUT_MS")]
+    Timeout(Duration),
+    #[error("unable to build the validation request: {0}")]
+    Request(String),
+}
+
+/// Sends a request without any line to check that the ingest API can be reached and accepts
+/// the configured ingestion key, before lines start piling up in retries
+pub async fn validate(template: RequestTemplate, timeout: Duration) -> Result<(), ValidationError> {
+    let mut client = HttpClient::new(template);
+    client.set_timeout(timeout);
+    let body = IntoIngestBodyBuffer::into(IngestBody::new(Vec::new()))
+        .await
+        .map_err(|e| ValidationError::Request(e.to_string()))?;
+    match client.send(body).await {
+        Ok(Response::Sent) => Ok(()),
+        Ok(Response::Failed(_, status, reason)) => match status.as_u16() {
+            401 | 403 => Err(ValidationError::Unauthorized(status.to_string())),
+            _ => Err(ValidationError::Rejected(status.to_string(), reason)),
+        },
+        Err(HttpError::Timeout(_)) => Err(ValidationError::Timeout(timeout)),
+        Err(e) => Err(ValidationError::Unreachable(e.to_string())),
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::warn;
+use metrics::Metrics;
+use systemd::Error;
+
+/// Corrupted entries read in a row before the reader gives up on the rest of the journal
+const SKIP_LIMIT: u64 = 100;
+
+/// Whether reading failed on a corrupted entry or journal file, e.g. one cut short when the
+/// host crashed, rather than on the journal itself
+pub(crate) fn is_corrupted(e: &Error) -> bool {
+    // sd-journal fails with EBADMSG on objects that don't check out and with EADDRNOTAVAIL on
+    // offsets past the end of a truncated file
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EBADMSG) | Some(libc::EADDRNOTAVAIL)
+    )
+}
+
+/// The corrupted entries skipped since the last one read, so that each run of them is logged
+/// once instead of on every failed read
+#[derive(Default)]
+pub(crate) struct Skips {
+    /// Timestamp of the last entry read
+    last_read: Option<SystemTime>,
+    skipped: u64,
+}
+
+impl Skips {
+    /// Counts a corrupted entry, returns whether the reader should skip past the rest of the
+    /// journal since reading on keeps failing
+    pub(crate) fn skip(&mut self) -> bool {
+        self.skipped += 1;
+        Metrics::journald().increment_corrupted_entries();
+        self.skipped >= SKIP_LIMIT
+    }
+
+    /// An entry written at `timestamp` was read, past the corrupted ones before it
+    pub(crate) fn read(&mut self, timestamp: Option<SystemTime>) {
+        if let Some(message) = self.resume(&describe(timestamp)) {
+            warn!("{}", message);
+        }
+        if timestamp.is_some() {
+            self.last_read = timestamp;
+        }
+    }
+
+    /// The reader reached the newest entries, either reading them or seeking to them
+    pub(crate) fn caught_up(&mut self) {
+        if let Some(message) = self.resume("the newest entries") {
+            warn!("{}", message);
+        }
+    }
+
+    fn resume(&mut self, until: &str) -> Option<String> {
+        if self.skipped == 0 {
+            return None;
+        }
+        let message = format!(
+            "skipped {} corrupted journald entries between {} and {}",
+            self.skipped,
+            describe(self.last_read),
+            until
+        );
+        self.skipped = 0;
+        Some(message)
+    }
+}
+
+fn describe(timestamp: Option<SystemTime>) -> String {
+    match timestamp {
+        Some(timestamp) => {
+            let timestamp: DateTime<Utc> = timestamp.into();
+            format!(
+                "the entry of {}",
+                timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+            )
+        }
+        None => "an entry without timestamp".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_each_run_of_corrupted_entries_once() {
+        assert!(is_corrupted(&Error::from_raw_os_error(libc::EBADMSG)));
+        assert!(!is_corrupted(&Error::from_raw_os_error(libc::EACCES)));
+
+        let mut skips = Skips::default();
+        skips.read(Some(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        ));
+        assert_eq!(skips.resume("the newest entries"), None);
+        assert!(!skips.skip());
+        assert!(!skips.skip());
+        assert_eq!(
+            skips.resume("the newest entries"),
+            Some(
+                "skipped 2 corrupted journald entries between the entry of \
+                 2020-09-13T12:26:40.000000Z and the newest entries"
+                    .to_string()
+            )
+        );
+        assert_eq!(skips.resume("the newest entries"), None);
+
+        assert!((1..SKIP_LIMIT).all(|_| !skips.skip()));
+        assert!(skips.skip());
+        assert!(Metrics::journald().read_corrupted_entries() >= SKIP_LIMIT + 2);
+    }
+}
@@ -0,0 +1,163 @@
+use metrics::Metrics;
+
+/// What's done with messages that aren't text or are larger than the limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldHandling {
+    /// The record is dropped
+    Drop,
+    /// Invalid bytes are replaced and the message is cut to the limit, ending with a marker
+    Truncate,
+    /// Binary messages are sent base64 encoded in the meta of the line, larger text messages
+    /// are truncated
+    Base64,
+}
+
+impl FieldHandling {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "drop" => Some(FieldHandling::Drop),
+            "truncate" => Some(FieldHandling::Truncate),
+            "base64" => Some(FieldHandling::Base64),
+            _ => None,
+        }
+    }
+}
+
+impl Default for FieldHandling {
+    fn default() -> Self {
+        FieldHandling::Truncate
+    }
+}
+
+/// How the boot and machine ids of a record are sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdFormat {
+    Full,
+    /// The first 12 characters, as docker shortens container ids
+    Short,
+    Off,
+}
+
+impl IdFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "full" => Some(IdFormat::Full),
+            "short" => Some(IdFormat::Short),
+            "off" => Some(IdFormat::Off),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn format(&self, id: &str) -> Option<String> {
+        match self {
+            IdFormat::Full => Some(id.to_string()),
+            IdFormat::Short => Some(id.chars().take(12).collect()),
+            IdFormat::Off => None,
+        }
+    }
+}
+
+impl Default for IdFormat {
+    fn default() -> Self {
+        IdFormat::Full
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FieldLimits {
+    /// Bytes of a message, unlimited when None
+    pub max_size: Option<usize>,
+    pub handling: FieldHandling,
+}
+
+impl FieldLimits {
+    /// The line to send for `message`, as read from the journal, and the message base64 encoded
+    /// when it's binary and kept as is. None when the record is dropped.
+    pub(crate) fn apply(&self, message: &[u8]) -> Option<(String, Option<String>)> {
+        let binary = match std::str::from_utf8(message) {
+            Ok(text) => text.contains('\0'),
+            Err(_) => true,
+        };
+        let oversized = matches!(self.max_size, Some(max) if message.len() > max);
+        if binary {
+            Metrics::journald().increment_binary_messages();
+        }
+        if oversized {
+            Metrics::journald().increment_oversized_messages();
+        }
+        if !binary && !oversized {
+            return Some((String::from_utf8_lossy(message).into_owned(), None));
+        }
+
+        match self.handling {
+            FieldHandling::Drop => None,
+            FieldHandling::Base64 if binary => {
+                let kept = &message[..self.max_size.unwrap_or(usize::MAX).min(message.len())];
+                let line = if kept.len() < message.len() {
+                    format!("[binary message of {} bytes, truncated]", message.len())
+                } else {
+                    format!("[binary message of {} bytes]", message.len())
+                };
+                Some((line, Some(base64::encode(kept))))
+            }
+            _ => Some((self.truncate(&String::from_utf8_lossy(message)), None)),
+        }
+    }
+
+    fn truncate(&self, text: &str) -> String {
+        let max = match self.max_size {
+            Some(max) if text.len() > max => max,
+            _ => return text.to_string(),
+        };
+        let mut end = max;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{} [truncated {} bytes]", &text[..end], text.len() - end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_binary_and_oversized_messages() {
+        let limits = |handling| FieldLimits {
+            max_size: Some(8),
+            handling,
+        };
+        let text = |line: &str| Some((line.to_string(), None));
+
+        assert_eq!(limits(FieldHandling::Drop).apply(b"short"), text("short"));
+        assert_eq!(limits(FieldHandling::Drop).apply(b"way too long"), None);
+        assert_eq!(limits(FieldHandling::Drop).apply(b"\xff\x00"), None);
+        assert_eq!(
+            limits(FieldHandling::Truncate).apply("way too lóng".as_bytes()),
+            text("way too  [truncated 5 bytes]")
+        );
+        assert_eq!(
+            limits(FieldHandling::Truncate).apply(b"a\xffb"),
+            text("a\u{fffd}b")
+        );
+        assert_eq!(
+            limits(FieldHandling::Base64).apply(b"\x00\x01"),
+            Some((
+                "[binary message of 2 bytes]".to_string(),
+                Some("AAE=".to_string())
+            ))
+        );
+        assert_eq!(
+            limits(FieldHandling::Base64).apply(b"way too long"),
+            text("way too  [truncated 4 bytes]")
+        );
+        assert_eq!(FieldHandling::parse(" Base64"), Some(FieldHandling::Base64));
+        assert_eq!(FieldHandling::parse("encode"), None);
+
+        let id = "5d4ea2b5ce6f4d3b8b4a3a0f5a0e2c1d";
+        assert_eq!(IdFormat::Full.format(id), Some(id.to_string()));
+        assert_eq!(IdFormat::Short.format(id), Some("5d4ea2b5ce6f".to_string()));
+        assert_eq!(IdFormat::Off.format(id), None);
+        assert_eq!(IdFormat::parse("short"), Some(IdFormat::Short));
+    }
+}
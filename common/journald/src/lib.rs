@@ -1,3 +1,14 @@
+// Without systemd only the types of the config are used
+#![cfg_attr(not(feature = "systemd"), allow(dead_code))]
+
+#[cfg(feature = "systemd")]
+mod corruption;
+#[cfg(feature = "systemd")]
 pub mod error;
+pub mod field;
+pub mod limit;
+pub mod options;
+#[cfg(feature = "systemd")]
 pub mod source;
+#[cfg(feature = "systemd")]
 pub mod stream;
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use metrics::Metrics;
+
+/// How much a single unit can write per second before its records are dropped, so that a
+/// service flooding the journal doesn't crowd out the others
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UnitLimits {
+    pub lines_per_sec: Option<u64>,
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl UnitLimits {
+    fn is_unlimited(&self) -> bool {
+        self.lines_per_sec.is_none() && self.bytes_per_sec.is_none()
+    }
+}
+
+/// What a unit wrote in the current second
+struct Window {
+    start: Instant,
+    lines: u64,
+    bytes: u64,
+    suppressed: u64,
+    /// Whether records were dropped in the previous second, to only warn when it starts
+    throttled: bool,
+}
+
+impl Window {
+    fn new(start: Instant, throttled: bool) -> Self {
+        Window {
+            start,
+            lines: 0,
+            bytes: 0,
+            suppressed: 0,
+            throttled,
+        }
+    }
+}
+
+pub(crate) struct UnitLimiter {
+    limits: UnitLimits,
+    units: HashMap<String, Window>,
+}
+
+impl UnitLimiter {
+    pub(crate) fn new(limits: UnitLimits) -> Self {
+        UnitLimiter {
+            limits,
+            units: HashMap::new(),
+        }
+    }
+
+    /// Whether a record of `bytes` written by `unit` is within its limits, it's counted in
+    /// the `suppressed` metrics of the unit otherwise
+    pub(crate) fn allow(&mut self, unit: &str, bytes: u64) -> bool {
+        self.allow_at(unit, bytes, Instant::now())
+    }
+
+    fn allow_at(&mut self, unit: &str, bytes: u64, now: Instant) -> bool {
+        if self.limits.is_unlimited() {
+            return true;
+        }
+        if !self.units.contains_key(unit) {
+            self.units.insert(unit.to_string(), Window::new(now, false));
+        }
+        let window = self.units.get_mut(unit).expect("inserted above");
+        if now.duration_since(window.start) >= Duration::from_secs(1) {
+            *window = Window::new(now, window.suppressed > 0);
+        }
+
+        let lines_over = matches!(self.limits.lines_per_sec, Some(limit) if window.lines >= limit);
+        // The first record of each second goes through whatever its size, records larger
+        // than the limit would never be sent otherwise
+        let bytes_over = matches!(
+            self.limits.bytes_per_sec,
+            Some(limit) if window.lines > 0 && window.bytes + bytes > limit
+        );
+        if lines_over || bytes_over {
+            if window.suppressed == 0 && !window.throttled {
+                warn!(
+                    "journald unit {} is over its rate limit, dropping its records",
+                    unit
+                );
+            }
+            window.suppressed += 1;
+            Metrics::journald().increment_suppressed(unit);
+            return false;
+        }
+        window.lines += 1;
+        window.bytes += bytes;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_each_unit_per_second() {
+        let mut limiter = UnitLimiter::new(UnitLimits {
+            lines_per_sec: Some(3),
+            bytes_per_sec: Some(100),
+        });
+        let start = Instant::now();
+        let allowed: Vec<bool> = (0..5)
+            .map(|_| limiter.allow_at("flood.service", 10, start))
+            .collect();
+        assert_eq!(allowed, vec![true, true, true, false, false]);
+        assert!(limiter.allow_at("quiet.service", 90, start));
+        assert!(!limiter.allow_at("quiet.service", 20, start));
+        assert!(limiter.allow_at("flood.service", 500, start + Duration::from_secs(1)));
+
+        let suppressed = Metrics::journald().read_suppressed();
+        assert!(suppressed.get("flood.service") >= Some(&2));
+        assert!(suppressed.get("quiet.service") >= Some(&1));
+    }
+}
@@ -0,0 +1,25 @@
+use crate::field::{FieldLimits, IdFormat};
+use crate::limit::UnitLimits;
+
+use std::time::Duration;
+
+/// How the records are read and turned into lines
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReadOptions {
+    /// How far behind the reader can fall before it skips ahead to the newest records
+    pub max_lag: Option<Duration>,
+    pub unit_limits: UnitLimits,
+    pub field_limits: FieldLimits,
+    /// How the boot and machine ids of the records are added to the meta of the lines
+    pub ids: IdFormat,
+    /// The records written before the stream started to read first, instead of only the new ones
+    pub backfill: Option<Backfill>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backfill {
+    /// How far back the records are read from
+    pub window: Duration,
+    /// Bytes of messages read before skipping ahead to the newest records, unlimited when None
+    pub max_bytes: Option<u64>,
+}
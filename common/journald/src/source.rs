@@ -1,10 +1,14 @@
-use crate::stream::{Path, Stream};
+use crate::stream::{Path, ReadOptions, Stream};
 use futures::stream::{select_all, SelectAll, Stream as FutureStream};
 use http::types::body::LineBuilder;
 use log::{info, warn};
 use std::path::PathBuf;
 
-pub fn create_source(paths: &[PathBuf]) -> impl FutureStream<Item = LineBuilder> {
+/// Reads the records written to journald `paths` from now on, as set in `options`
+pub fn create_source(
+    paths: &[PathBuf],
+    options: ReadOptions,
+) -> impl FutureStream<Item = LineBuilder> {
     let mut journal_files: Vec<PathBuf> = Vec::new();
     let mut journal_directories: Vec<PathBuf> = Vec::new();
     for path in paths {
@@ -22,10 +26,10 @@ pub fn create_source(paths: &[PathBuf]) -> impl FutureStream<Item = LineBuilder>
 
     let mut streams: Vec<Stream> = journal_directories
         .into_iter()
-        .map(|dir| Stream::new(Path::Directory(dir)))
+        .map(|dir| Stream::new(Path::Directory(dir), options))
         .collect();
     if !journal_files.is_empty() {
-        streams.push(Stream::new(Path::Files(journal_files)));
+        streams.push(Stream::new(Path::Files(journal_files), options));
     }
 
     let combined_stream: SelectAll<<Vec<Stream> as IntoIterator>::Item> = select_all(streams);
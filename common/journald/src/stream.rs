@@ -1,8 +1,12 @@
+use crate::corruption::{is_corrupted, Skips};
 use crate::error::JournalError;
+use crate::limit::UnitLimiter;
+use crate::options::ReadOptions;
 use futures::{channel::oneshot, stream::Stream as FutureStream};
 use http::types::body::LineBuilder;
 use log::{info, warn};
 use metrics::Metrics;
+use serde_json::{json, Map, Value};
 use std::{
     mem::drop,
     path::PathBuf,
@@ -21,7 +25,14 @@ const KEY_MESSAGE: &str = "MESSAGE";
 const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
 const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
 const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
+const KEY_BOOT_ID: &str = "_BOOT_ID";
+const KEY_MACHINE_ID: &str = "_MACHINE_ID";
 const DEFAULT_APP: &str = "UNKNOWN_SYSTEMD_APP";
+/// Records read back to back while behind, before checking whether the stream was dropped
+const CATCH_UP_BATCH: usize = 1000;
+/// How long the reader blocks for new records once caught up, before checking whether the
+/// stream was dropped
+const IDLE_WAIT: Duration = Duration::from_millis(500);
 
 #[derive(Clone)]
 pub enum Path {
@@ -38,16 +49,18 @@ pub struct Stream {
     receiver: Option<Receiver<LineBuilder>>,
     shared_state: Arc<Mutex<SharedState>>,
     path: Path,
+    options: ReadOptions,
     thread_stop_chan: Option<oneshot::Sender<()>>,
 }
 
 impl Stream {
-    pub fn new(path: Path) -> Self {
+    pub fn new(path: Path, options: ReadOptions) -> Self {
         let mut stream = Self {
             thread: None,
             receiver: None,
             shared_state: Arc::new(Mutex::new(SharedState { waker: None })),
             path,
+            options,
             thread_stop_chan: None,
         };
 
@@ -64,8 +77,11 @@ impl Stream {
         let (sender, receiver) = sync_channel(100);
         let thread_shared_state = self.shared_state.clone();
         let path = self.path.clone();
+        let options = self.options;
+        // The older records were already read when the thread is restarted
+        self.options.backfill = None;
         let thread = thread::spawn(move || {
-            let mut journal = Reader::new(path);
+            let mut journal = Reader::new(path, options);
 
             let call_waker = || {
                 let mut shared_state = match thread_shared_state.lock() {
@@ -84,30 +100,42 @@ impl Stream {
                 }
             };
 
-            while let Ok(None) = stop_receiver.try_recv() {
-                match journal.process_next_record() {
-                    Ok(Some(line)) => {
-                        if let Err(e) = sender.send(line) {
-                            warn!(
-                                "journald's worker thread unable to communicate with main thread: {}",
-                                e
-                            );
+            'read: while let Ok(None) = stop_receiver.try_recv() {
+                // While behind the records are read back to back, only waiting for new ones to
+                // be written once caught up
+                let mut caught_up = false;
+                for _ in 0..CATCH_UP_BATCH {
+                    match journal.process_next_record() {
+                        Ok(Some(line)) => {
+                            if let Err(e) = sender.send(line) {
+                                warn!(
+                                    "journald's worker thread unable to communicate with main thread: {}",
+                                    e
+                                );
+                                break 'read;
+                            }
+
+                            call_waker();
+                        }
+                        Ok(None) => {
+                            Metrics::journald().set_lag(0);
+                            caught_up = true;
                             break;
                         }
-
-                        call_waker();
-                    }
-                    Ok(None) => {}
-                    Err(JournalError::RecordMissingField(e)) => {
-                        warn!("dropping journald record: {:?}", e);
-                    }
-                    Err(JournalError::BadRead(e)) => {
-                        warn!("unable to read from journald: {:?}", e);
-                        break;
+                        Err(JournalError::RecordMissingField(e)) => {
+                            warn!("dropping journald record: {:?}", e);
+                        }
+                        Err(JournalError::BadRead(e)) => {
+                            warn!("unable to read from journald: {:?}", e);
+                            break 'read;
+                        }
                     }
                 }
 
-                if let Err(e) = journal.reader.wait(Some(Duration::from_millis(100))) {
+                if !caught_up {
+                    continue;
+                }
+                if let Err(e) = journal.reader.wait(Some(IDLE_WAIT)) {
                     warn!(
                         "journald's worker thread unable to poll journald for next record: {}",
                         e
@@ -181,10 +209,15 @@ impl Drop for Stream {
 
 struct Reader {
     reader: Journal,
+    options: ReadOptions,
+    limiter: UnitLimiter,
+    skips: Skips,
+    /// Bytes left to read while backfilling, None once the reader is past the older records
+    backfill_bytes: Option<u64>,
 }
 
 impl Reader {
-    fn new(path: Path) -> Self {
+    fn new(path: Path, options: ReadOptions) -> Self {
         let mut reader = match path {
             Path::Directory(path) => Journal::open_directory(&path, JournalFiles::All, false)
                 .expect("Could not open journald reader for directory"),
@@ -193,46 +226,115 @@ impl Reader {
                 Journal::open_files(&paths).expect("Could not open journald reader for paths")
             }
         };
-        reader
-            .seek(JournalSeek::Tail)
-            .expect("Could not seek to tail of journald logs");
+        let backfill = options.backfill.and_then(|backfill| {
+            let from = SystemTime::now().checked_sub(backfill.window)?;
+            Some((backfill, from.duration_since(SystemTime::UNIX_EPOCH).ok()?))
+        });
+        match backfill {
+            Some((backfill, from)) => {
+                info!(
+                    "Backfilling the journald records of the last {:?}",
+                    backfill.window
+                );
+                reader
+                    .seek(JournalSeek::ClockRealtime {
+                        usec: from.as_micros() as u64,
+                    })
+                    .expect("Could not seek to the start of the journald backfill")
+            }
+            None => reader
+                .seek(JournalSeek::Tail)
+                .expect("Could not seek to tail of journald logs"),
+        };
 
-        Self { reader }
+        Self {
+            reader,
+            options,
+            limiter: UnitLimiter::new(options.unit_limits),
+            skips: Skips::default(),
+            backfill_bytes: backfill.map(|(backfill, _)| backfill.max_bytes.unwrap_or(u64::MAX)),
+        }
     }
 
     fn process_next_record(&mut self) -> Result<Option<LineBuilder>, JournalError> {
-        let record = match self.reader.next_entry() {
-            Ok(Some(record)) => record,
-            Ok(None) => return Ok(None),
-            Err(e) => return Err(JournalError::BadRead(e)),
-        };
+        // Records dropped by the unit limits are skipped until one goes through
+        loop {
+            let record = match self.reader.next_entry() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.skips.caught_up();
+                    if self.backfill_bytes.take().is_some() {
+                        info!("Finished backfilling journald records");
+                    }
+                    return Ok(None);
+                }
+                // The reader moved past the corrupted entry, the next one can be read
+                Err(e) if is_corrupted(&e) => {
+                    if self.skips.skip() {
+                        info!("Unable to read past corrupted journald entries, reseeking pointer");
+                        if let Err(e) = self.reader.seek(JournalSeek::Tail) {
+                            return Err(JournalError::BadRead(e));
+                        }
+                        self.skips.caught_up();
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(JournalError::BadRead(e)),
+            };
 
-        match self
-            .reader
-            .timestamp()
-            .ok()
-            .map(|timestamp| SystemTime::now().duration_since(timestamp).ok())
-            .flatten()
-        {
-            Some(duration) => {
-                // Reject any records with a timestamp older than 30 seconds
-                if duration >= Duration::from_secs(30) {
-                    info!("Received a stale journald record, reseeking pointer");
-                    if let Err(e) = self.reader.seek(JournalSeek::Tail) {
-                        return Err(JournalError::BadRead(e));
+            let timestamp = self.reader.timestamp().ok();
+            self.skips.read(timestamp);
+            match timestamp
+                .map(|timestamp| SystemTime::now().duration_since(timestamp).ok())
+                .flatten()
+            {
+                Some(duration) => {
+                    Metrics::journald().set_lag(duration.as_secs());
+                    // Skip ahead past the records too far behind to catch up with, the backfilled
+                    // ones are behind on purpose
+                    let stale = self.backfill_bytes.is_none()
+                        && matches!(self.options.max_lag, Some(max_lag) if duration >= max_lag);
+                    if stale {
+                        info!("Received a stale journald record, reseeking pointer");
+                        if let Err(e) = self.reader.seek(JournalSeek::Tail) {
+                            return Err(JournalError::BadRead(e));
+                        }
                     }
                 }
+                None => {
+                    warn!("Unable to read timestamp associated with journald record");
+                }
+            } //TODO: Actually bake the timestamp into the outgoing line
+
+            if let Some(line) = self.process_default_record(&record)? {
+                let bytes = record.get(KEY_MESSAGE).map_or(0, String::len) as u64;
+                self.count_backfill(bytes)?;
+                return Ok(Some(line));
             }
-            None => {
-                warn!("Unable to read timestamp associated with journald record");
-            }
-        } //TODO: Actually bake the timestamp into the outgoing line
+        }
+    }
 
-        self.process_default_record(&record)
+    /// Skips ahead to the newest records once the backfill read all of its bytes
+    fn count_backfill(&mut self, bytes: u64) -> Result<(), JournalError> {
+        let remaining = match self.backfill_bytes {
+            Some(remaining) => remaining.saturating_sub(bytes),
+            None => return Ok(()),
+        };
+        if remaining > 0 {
+            self.backfill_bytes = Some(remaining);
+            return Ok(());
+        }
+        info!("Journald backfill reached its size limit, reseeking pointer");
+        self.backfill_bytes = None;
+        if let Err(e) = self.reader.seek(JournalSeek::Tail) {
+            return Err(JournalError::BadRead(e));
+        }
+        Ok(())
     }
 
     fn process_default_record(
-        &self,
+        &mut self,
         record: &JournalRecord,
     ) -> Result<Option<LineBuilder>, JournalError> {
         let message = match record.get(KEY_MESSAGE) {
@@ -249,10 +351,34 @@ impl Reader {
             .or_else(|| record.get(KEY_SYSTEMD_UNIT))
             .or_else(|| record.get(KEY_SYSLOG_IDENTIFIER))
             .unwrap_or(&default_app);
+        if !self.limiter.allow(app, message.len() as u64) {
+            return Ok(None);
+        }
+        // The values of the record aren't checked to be text when it's read, the message is
+        // handled as the bytes it was written as
+        let (message, encoded) = match self.options.field_limits.apply(message.as_bytes()) {
+            Some(handled) => handled,
+            None => return Ok(None),
+        };
+
+        let mut meta = Map::new();
+        if let Some(encoded) = encoded {
+            meta.insert("message_base64".to_string(), Value::String(encoded));
+        }
+        for (key, name) in [(KEY_BOOT_ID, "boot_id"), (KEY_MACHINE_ID, "machine_id")].iter() {
+            if let Some(id) = record.get(*key).and_then(|id| self.options.ids.format(id)) {
+                meta.insert(name.to_string(), Value::String(id));
+            }
+        }
 
         Metrics::journald().increment_lines();
         Metrics::journald().add_bytes(message.len() as u64);
-        Ok(Some(LineBuilder::new().line(message).file(app)))
+        let line = LineBuilder::new().line(message).file(app);
+        Ok(Some(if meta.is_empty() {
+            line
+        } else {
+            line.meta(json!({ "journald": meta }))
+        }))
     }
 }
 
@@ -275,7 +401,8 @@ mod tests {
     async fn reader_gets_new_logs() {
         journal::print(1, "Reader got the correct line!");
         sleep(Duration::from_millis(50));
-        let mut reader = Reader::new(Path::Directory(JOURNALD_LOG_PATH.into()));
+        let path = Path::Directory(JOURNALD_LOG_PATH.into());
+        let mut reader = Reader::new(path, ReadOptions::default());
 
         let record_status = reader.process_next_record();
         if let Ok(Some(line)) = record_status {
@@ -293,7 +420,8 @@ mod tests {
     async fn stream_gets_new_logs() {
         journal::print(1, "Reader got the correct line 1!");
         sleep(Duration::from_millis(50));
-        let mut stream = Stream::new(Path::Directory(JOURNALD_LOG_PATH.into()));
+        let path = Path::Directory(JOURNALD_LOG_PATH.into());
+        let mut stream = Stream::new(path, ReadOptions::default());
         sleep(Duration::from_millis(50));
         journal::print(1, "Reader got the correct line 2!");
 
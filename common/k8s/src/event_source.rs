@@ -1,8 +1,10 @@
 use core::cmp::{Ord, Ordering};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::convert::{Into, TryFrom};
 use std::num::NonZeroI64;
 use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
 
 use backoff::ExponentialBackoff;
 use crossbeam::atomic::AtomicCell;
@@ -12,6 +14,8 @@ use chrono_humanize::HumanTime;
 
 use futures::{stream::try_unfold, Stream, StreamExt, TryStreamExt};
 
+use parking_lot::Mutex;
+
 use k8s_openapi::api::core::v1::{Event, ObjectReference, Pod};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
 use kube::api::ListParams;
@@ -140,6 +144,7 @@ impl From<Event> for EventLog {
                     })
                     .flatten(),
                 count,
+                suppressed: None,
             },
         };
 
@@ -187,6 +192,9 @@ struct EventLogLineInner {
     age: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     count: Option<i32>,
+    /// Updates of the event that weren't logged since it was last logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suppressed: Option<u64>,
 }
 
 // TODO test from...
@@ -220,11 +228,93 @@ impl TryFrom<EventLog> for LineBuilder {
     }
 }
 
+/// The kind, namespace and name of the object an event is about, along with its reason
+type RecurrenceKey = (
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+struct Recurrence {
+    logged: Instant,
+    suppressed: u64,
+}
+
+/// Collapses the updates of recurring events, the same reason for the same object, into a
+/// line per `window`. The count of the event keeps the total number of occurrences, e.g. a
+/// pod in a crash loop otherwise logs its `BackOff` event every few seconds.
+struct EventDeduplicator {
+    window: StdDuration,
+    seen: HashMap<RecurrenceKey, Recurrence>,
+    last_prune: Instant,
+}
+
+impl EventDeduplicator {
+    fn new(window: StdDuration) -> Self {
+        EventDeduplicator {
+            window,
+            seen: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    /// The number of updates of the event suppressed since it was last logged when it should
+    /// be logged, `None` when it should be suppressed
+    fn admit(&mut self, event: &Event, now: Instant) -> Option<u64> {
+        if self.window == StdDuration::from_secs(0) {
+            return Some(0);
+        }
+        if now.duration_since(self.last_prune) >= self.window {
+            // Events that didn't recur for a while are forgotten, along with their suppressed
+            // updates, and logged as new ones when they do
+            let expiry = self.window * 2;
+            self.seen
+                .retain(|_, recurrence| now.duration_since(recurrence.logged) < expiry);
+            self.last_prune = now;
+        }
+
+        let object = &event.involved_object;
+        let key = (
+            object.kind.clone(),
+            object.namespace.clone(),
+            object.name.clone(),
+            event.reason.clone(),
+        );
+        match self.seen.get_mut(&key) {
+            Some(recurrence) if now.duration_since(recurrence.logged) < self.window => {
+                recurrence.suppressed += 1;
+                Metrics::k8s().increment_suppressed_events();
+                None
+            }
+            Some(recurrence) => {
+                let suppressed = recurrence.suppressed;
+                *recurrence = Recurrence {
+                    logged: now,
+                    suppressed: 0,
+                };
+                Some(suppressed)
+            }
+            None => {
+                self.seen.insert(
+                    key,
+                    Recurrence {
+                        logged: now,
+                        suppressed: 0,
+                    },
+                );
+                Some(0)
+            }
+        }
+    }
+}
+
 pub struct K8sEventStream {
     client: Client,
     pod_name: String,
     namespace: String,
     pod_label: String,
+    dedup_window: StdDuration,
 }
 
 pub enum StreamElem<T> {
@@ -244,9 +334,16 @@ impl K8sEventStream {
             pod_name,
             namespace,
             pod_label,
+            dedup_window: StdDuration::from_secs(0),
         })
     }
 
+    /// Logs updates of recurring events at most once per `window`, zero logs every update
+    pub fn with_dedup_window(mut self, window: StdDuration) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
     pub fn try_default(
         pod_name: String,
         namespace: String,
@@ -276,17 +373,18 @@ impl K8sEventStream {
                 if let (pod_gen, Some(pod_started_at), Some(pod_name)) =
                     // get pod generation, it will be there if there is an update strategy
                     (
-                    p.metadata
-                        .labels
-                        .as_ref()
-                        .map(|l| {
-                            l.get("pod-template-generation")
-                                .and_then(|g| g.parse::<u64>().ok())
-                        })
-                        .flatten(),
-                    get_pod_started_at(p),
-                    p.metadata.name.as_ref(),
-                ) {
+                        p.metadata
+                            .labels
+                            .as_ref()
+                            .map(|l| {
+                                l.get("pod-template-generation")
+                                    .and_then(|g| g.parse::<u64>().ok())
+                            })
+                            .flatten(),
+                        get_pod_started_at(p),
+                        p.metadata.name.as_ref(),
+                    )
+                {
                     Some((pod_gen, pod_started_at, pod_name))
                 } else {
                     None
@@ -413,9 +511,11 @@ impl K8sEventStream {
         client: Arc<Client>,
         latest_event_time: Arc<AtomicCell<Option<NonZeroI64>>>,
         previous_event_logger_delete_time: Arc<AtomicCell<Option<NonZeroI64>>>,
+        dedup_window: StdDuration,
     ) -> impl Stream<Item = Result<StreamElem<LineBuilder>, K8sEventStreamError>> {
         let events: Api<Event> = Api::all(client.as_ref().clone());
         let params = ListParams::default();
+        let dedup = Mutex::new(EventDeduplicator::new(dedup_window));
 
         let latest_event_time_w = latest_event_time.clone();
         try_flatten_touched(watcher(events, params))
@@ -440,6 +540,14 @@ impl K8sEventStream {
                 }
             })
             .map(move |event| {
+                let admitted = event
+                    .as_ref()
+                    .map(|e| dedup.lock().admit(e, Instant::now()));
+                let suppressed = match admitted {
+                    Ok(None) => return Ok(StreamElem::Waiting),
+                    Ok(Some(suppressed)) => suppressed,
+                    Err(_) => 0,
+                };
                 match event.map(|e| {
                     let latest_event_time = latest_event_time_w.clone();
                     let this_event_time = e
@@ -447,7 +555,11 @@ impl K8sEventStream {
                         .as_ref()
                         .and_then(|t| NonZeroI64::new(t.0.timestamp() - 2));
 
-                    let ret = LineBuilder::try_from(EventLog::from(e)).map(|l| {
+                    let mut log = EventLog::from(e);
+                    if suppressed > 0 {
+                        log.line.kube.suppressed = Some(suppressed);
+                    }
+                    let ret = LineBuilder::try_from(log).map(|l| {
                         Metrics::k8s().increment_lines();
                         l
                     });
@@ -469,6 +581,7 @@ impl K8sEventStream {
         pod_label: impl Into<String>,
         client: Arc<Client>,
         latest_event_time: Arc<AtomicCell<Option<NonZeroI64>>>,
+        dedup_window: StdDuration,
     ) -> impl Stream<Item = Result<LineBuilder, K8sEventStreamError>> {
         let pod_name = pod_name.into();
         let namespace = namespace.into();
@@ -488,6 +601,7 @@ impl K8sEventStream {
             client,
             latest_event_time,
             previous_event_logger_delete_time,
+            dedup_window,
         );
 
         waiting_stream.chain(event_stream).filter_map(|e| async {
@@ -507,6 +621,7 @@ impl K8sEventStream {
         let pod_name = self.pod_name.clone();
         let namespace = self.namespace.clone();
         let pod_label = self.pod_label.clone();
+        let dedup_window = self.dedup_window;
 
         let _latest_event_time = latest_event_time.clone();
         let _client = client.clone();
@@ -517,6 +632,7 @@ impl K8sEventStream {
                 pod_label.clone(),
                 _client.clone(),
                 _latest_event_time.clone(),
+                dedup_window,
             )
         };
 
@@ -551,3 +667,45 @@ fn get_pod_started_at(p: &Pod) -> Option<&Time> {
         })
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, reason: &str) -> Event {
+        Event {
+            involved_object: ObjectReference {
+                kind: Some("Pod".into()),
+                namespace: Some("default".into()),
+                name: Some(name.into()),
+                ..ObjectReference::default()
+            },
+            reason: Some(reason.into()),
+            ..Event::default()
+        }
+    }
+
+    #[test]
+    fn collapses_recurring_events() {
+        let window = StdDuration::from_secs(60);
+        let mut dedup = EventDeduplicator::new(window);
+        let start = Instant::now();
+
+        assert_eq!(dedup.admit(&event("web", "BackOff"), start), Some(0));
+        assert_eq!(dedup.admit(&event("web", "BackOff"), start), None);
+        assert_eq!(dedup.admit(&event("web", "BackOff"), start), None);
+        // Other reasons and objects aren't collapsed with it
+        assert_eq!(dedup.admit(&event("web", "Pulled"), start), Some(0));
+        assert_eq!(dedup.admit(&event("db", "BackOff"), start), Some(0));
+
+        assert_eq!(
+            dedup.admit(&event("web", "BackOff"), start + window),
+            Some(2)
+        );
+        assert_eq!(dedup.admit(&event("web", "BackOff"), start + window), None);
+
+        let mut dedup = EventDeduplicator::new(StdDuration::from_secs(0));
+        assert_eq!(dedup.admit(&event("web", "BackOff"), start), Some(0));
+        assert_eq!(dedup.admit(&event("web", "BackOff"), start), Some(0));
+    }
+}
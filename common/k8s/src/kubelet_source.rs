@@ -0,0 +1,327 @@
+use crate::errors::K8sError;
+
+use chrono::{DateTime, TimeZone, Utc};
+use futures::{Stream, StreamExt};
+use http::types::body::LineBuilder;
+use hyper::{Request, Uri};
+use kube::{config::Config, Client};
+use metrics::Metrics;
+use serde::Deserialize;
+use state::TimestampState;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{channel, Sender};
+
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The subset of the kubelet `/pods` response needed to find running containers
+#[derive(Debug, Deserialize)]
+struct PodList {
+    #[serde(default)]
+    items: Vec<Pod>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pod {
+    metadata: PodMeta,
+    #[serde(default)]
+    status: PodStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct PodMeta {
+    name: String,
+    namespace: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PodStatus {
+    #[serde(default)]
+    container_statuses: Vec<ContainerStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerStatus {
+    name: String,
+    #[serde(default)]
+    container_id: Option<String>,
+    #[serde(default)]
+    state: ContainerState,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ContainerState {
+    #[serde(default)]
+    running: Option<serde_json::Value>,
+}
+
+/// A running container of a pod scheduled on the node
+#[derive(Clone, Debug, PartialEq)]
+struct Container {
+    namespace: String,
+    pod: String,
+    name: String,
+    /// The id of the container without the runtime prefix, e.g. `containerd://`
+    id: String,
+}
+
+impl Container {
+    /// The path the kubelet links the container log to, used as the file of its lines so
+    /// that they look like, and are enriched like, lines tailed from the node
+    fn file(&self) -> String {
+        format!(
+            "/var/log/containers/{}_{}_{}-{}.log",
+            self.pod, self.namespace, self.name, self.id
+        )
+    }
+}
+
+fn running_containers(pods: PodList) -> Vec<Container> {
+    let mut containers = Vec::new();
+    for pod in pods.items {
+        for status in pod.status.container_statuses {
+            let id = match (status.state.running, status.container_id) {
+                (Some(_), Some(id)) => id,
+                _ => continue,
+            };
+            containers.push(Container {
+                namespace: pod.metadata.namespace.clone(),
+                pod: pod.metadata.name.clone(),
+                name: status.name,
+                id: id.rsplit("://").next().unwrap_or(&id).to_string(),
+            });
+        }
+    }
+    containers
+}
+
+/// Streams the logs of the containers running on the node through the kubelet API at `url`,
+/// following `/containerLogs/<namespace>/<pod>/<container>`, so that no host path has to be
+/// mounted. Requests are authenticated with the service account of the agent, which needs
+/// the `nodes/proxy` permission. Containers are discovered by polling the kubelet `/pods`
+/// endpoint, the timestamp of the last line read from each container is kept in `state`.
+/// Must be called from within a tokio runtime.
+pub fn create_source(
+    url: &str,
+    insecure_tls: bool,
+    state: Option<TimestampState>,
+) -> Result<impl Stream<Item = LineBuilder>, K8sError> {
+    let client = client(url, insecure_tls)?;
+    let (tx, rx) = channel(1024);
+    info!("monitoring pod containers through the kubelet at {}", url);
+
+    tokio::spawn(async move {
+        let active = Arc::new(Mutex::new(HashSet::new()));
+        let mut initial = true;
+        loop {
+            match list_containers(&client).await {
+                Ok(containers) => {
+                    for container in containers {
+                        if !active.lock().unwrap().insert(container.id.clone()) {
+                            continue;
+                        }
+                        tokio::spawn(follow_container(
+                            client.clone(),
+                            container,
+                            state.clone(),
+                            tx.clone(),
+                            active.clone(),
+                            initial,
+                        ));
+                    }
+                    initial = false;
+                }
+                Err(e) => warn!("unable to list pods from the kubelet: {}", e),
+            }
+            if tx.is_closed() {
+                break;
+            }
+            tokio::time::sleep(DISCOVERY_INTERVAL).await;
+        }
+    });
+
+    Ok(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    }))
+}
+
+/// A client of the kubelet using the credentials and CA of the in-cluster configuration
+fn client(url: &str, insecure_tls: bool) -> Result<Client, K8sError> {
+    let mut config = Config::from_cluster_env().map_err(|e| {
+        K8sError::InitializationError(format!("unable to get cluster configuration info: {}", e))
+    })?;
+    config.cluster_url = url.parse::<Uri>().map_err(|e| {
+        K8sError::InitializationError(format!("invalid kubelet url {}: {}", url, e))
+    })?;
+    // Kubelet serving certificates are commonly self-signed rather than issued by the
+    // cluster CA
+    config.accept_invalid_certs = insecure_tls;
+    Ok(Client::new(config.try_into()?))
+}
+
+async fn list_containers(client: &Client) -> Result<Vec<Container>, K8sError> {
+    let request = Request::get("/pods")
+        .body(Vec::new())
+        .map_err(kube::Error::HttpError)?;
+    let pods: PodList = client.request(request).await?;
+    Ok(running_containers(pods))
+}
+
+async fn follow_container(
+    client: Client,
+    container: Container,
+    state: Option<TimestampState>,
+    tx: Sender<LineBuilder>,
+    active: Arc<Mutex<HashSet<String>>>,
+    initial: bool,
+) {
+    let key = format!("kubelet:{}", container.id);
+    let saved = state
+        .as_ref()
+        .and_then(|state| state.get(&key).map_err(|e| warn!("{}", e)).ok())
+        .flatten();
+    let since = match saved {
+        // Resume just after the last line that was read
+        Some(nanos) => Some(format_since(nanos + 1)),
+        // Containers running when the agent starts are tailed from now, like files, containers
+        // started later are read from the beginning
+        None if initial => Some(format_since(Utc::now().timestamp_nanos())),
+        None => None,
+    };
+
+    let file = container.file();
+    info!("following container logs of {} through the kubelet", file);
+    if let Err(e) = read_logs(&client, &container, since, &file, &key, &state, &tx).await {
+        warn!("stopped following container logs of {}: {}", file, e);
+    }
+    active.lock().unwrap().remove(&container.id);
+}
+
+async fn read_logs(
+    client: &Client,
+    container: &Container,
+    since: Option<String>,
+    file: &str,
+    key: &str,
+    state: &Option<TimestampState>,
+    tx: &Sender<LineBuilder>,
+) -> Result<(), K8sError> {
+    let mut path = format!(
+        "/containerLogs/{}/{}/{}?follow=true&timestamps=true",
+        container.namespace, container.pod, container.name
+    );
+    if let Some(since) = since {
+        path.push_str("&sinceTime=");
+        path.push_str(&since);
+    }
+    let request = Request::get(path)
+        .body(Vec::new())
+        .map_err(kube::Error::HttpError)?;
+    let mut logs = Box::pin(client.request_text_stream(request).await?);
+    let mut pending = Vec::new();
+    let mut latest = None;
+    let mut last_checkpoint = Instant::now();
+
+    while let Some(chunk) = logs.next().await {
+        pending.extend_from_slice(&chunk?);
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&raw[..end]);
+            let (timestamp, message) = split_timestamp(&line);
+            Metrics::kubelet().increment_lines();
+            Metrics::kubelet().add_bytes(message.len() as u64);
+            if tx
+                .send(LineBuilder::new().line(message).file(file))
+                .await
+                .is_err()
+            {
+                debug!("kubelet source was dropped, stopping {}", file);
+                return Ok(());
+            }
+            latest = timestamp.or(latest);
+        }
+
+        if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            checkpoint(key, latest, state);
+            last_checkpoint = Instant::now();
+        }
+    }
+    checkpoint(key, latest, state);
+    Ok(())
+}
+
+fn checkpoint(key: &str, latest: Option<i64>, state: &Option<TimestampState>) {
+    if let (Some(nanos), Some(state)) = (latest, state) {
+        if let Err(e) = state.set(key, nanos) {
+            warn!("unable to save kubelet log position: {}", e);
+        }
+    }
+}
+
+/// Splits the RFC 3339 timestamp the kubelet prefixes lines with, returning it in nanoseconds
+fn split_timestamp(line: &str) -> (Option<i64>, &str) {
+    let mut parts = line.splitn(2, ' ');
+    if let (Some(timestamp), Some(message)) = (parts.next(), parts.next()) {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp) {
+            return (Some(timestamp.timestamp_nanos()), message);
+        }
+    }
+    (None, line)
+}
+
+fn format_since(nanos: i64) -> String {
+    Utc.timestamp_nanos(nanos)
+        .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_running_containers() {
+        let pods: PodList = serde_json::from_str(
+            r#"{"kind":"PodList","items":[{
+                "metadata":{"name":"web-1","namespace":"shop"},
+                "status":{"containerStatuses":[
+                    {"name":"app","containerID":"containerd://0123abcd","state":{"running":{}}},
+                    {"name":"init","containerID":"containerd://4567","state":{"terminated":{}}}
+                ]}
+            },{
+                "metadata":{"name":"pending","namespace":"shop"},
+                "status":{}
+            }]}"#,
+        )
+        .unwrap();
+        let containers = running_containers(pods);
+        assert_eq!(
+            containers,
+            vec![Container {
+                namespace: "shop".into(),
+                pod: "web-1".into(),
+                name: "app".into(),
+                id: "0123abcd".into(),
+            }]
+        );
+        assert_eq!(
+            containers[0].file(),
+            "/var/log/containers/web-1_shop_app-0123abcd.log"
+        );
+    }
+
+    #[test]
+    fn splits_kubelet_timestamps() {
+        let (timestamp, message) = split_timestamp("2021-05-11T14:22:33.000000001Z GET / 200");
+        assert_eq!(timestamp, Some(1_620_742_953_000_000_001));
+        assert_eq!(message, "GET / 200");
+        assert_eq!(
+            format_since(1_620_742_953_000_000_001),
+            "2021-05-11T14:22:33.000000001Z"
+        );
+    }
+}
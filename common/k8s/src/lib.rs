@@ -1,11 +1,18 @@
+#[cfg(feature = "api")]
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "api")]
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "api")]
 pub mod errors;
+#[cfg(feature = "api")]
 pub mod event_source;
+#[cfg(feature = "api")]
+pub mod kubelet_source;
 pub mod middleware;
+#[cfg(feature = "api")]
 pub mod restarting_stream;
 
 #[derive(Clone, std::fmt::Debug, PartialEq)]
@@ -0,0 +1,168 @@
+use metrics::Metrics;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::Instant;
+
+use super::CacheLimits;
+
+struct Entry<V> {
+    value: V,
+    tick: u64,
+    used: Instant,
+}
+
+/// A map evicting its least recently used entries beyond `max_entries`, along with the
+/// entries that haven't been used for `ttl`, counting hits, misses and evictions in
+/// `Metrics::k8s()`
+pub(crate) struct LruCache<K, V> {
+    limits: CacheLimits,
+    entries: HashMap<K, Entry<V>>,
+    /// Keys by the tick they were last used at, the least recently used first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> LruCache<K, V> {
+    pub fn new(limits: CacheLimits) -> Self {
+        LruCache {
+            limits,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Looks up `key`, marking its entry as the most recently used
+    pub fn get(&mut self, key: &K, now: Instant) -> Option<&V> {
+        self.evict_expired(now);
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => {
+                Metrics::k8s().increment_cache_misses();
+                return None;
+            }
+        };
+        Metrics::k8s().increment_cache_hits();
+        self.order.remove(&entry.tick);
+        self.order.insert(tick, key.clone());
+        entry.tick = tick;
+        entry.used = now;
+        Some(&entry.value)
+    }
+
+    /// Inserts or replaces the entry of `key`, evicting the least recently used entries
+    /// when the cache is full
+    pub fn insert(&mut self, key: K, value: V, now: Instant) {
+        self.tick += 1;
+        let tick = self.tick;
+        self.order.insert(tick, key.clone());
+        let entry = Entry {
+            value,
+            tick,
+            used: now,
+        };
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.order.remove(&previous.tick);
+        }
+
+        self.evict_expired(now);
+        if let Some(max_entries) = self.limits.max_entries {
+            while self.entries.len() > max_entries && self.evict_oldest() {}
+        }
+    }
+
+    /// Whether there is an entry for `key`, without using it or counting a hit or miss
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Removes the entry of `key`, which isn't counted as an eviction
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        Some(entry.value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Entries are ordered by when they were last used, so expired ones are all at the front
+    fn evict_expired(&mut self, now: Instant) {
+        let ttl = match self.limits.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        loop {
+            let expired = match self.order.values().next() {
+                Some(key) => self.entries.get(key).map_or(true, |entry| {
+                    now.saturating_duration_since(entry.used) >= ttl
+                }),
+                None => false,
+            };
+            if !expired || !self.evict_oldest() {
+                break;
+            }
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let tick = match self.order.keys().next() {
+            Some(tick) => *tick,
+            None => return false,
+        };
+        if let Some(key) = self.order.remove(&tick) {
+            self.entries.remove(&key);
+            Metrics::k8s().increment_cache_evictions();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let now = Instant::now();
+        let mut cache = LruCache::new(CacheLimits {
+            max_entries: Some(2),
+            ttl: None,
+        });
+        cache.insert("a", 1, now);
+        cache.insert("b", 2, now);
+        assert_eq!(cache.get(&"a", now), Some(&1));
+
+        cache.insert("c", 3, now);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&"b", now), None);
+        assert_eq!(cache.get(&"a", now), Some(&1));
+        assert_eq!(cache.get(&"c", now), Some(&3));
+
+        // Updating an entry doesn't count against the bound
+        cache.insert("c", 4, now);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.remove(&"c"), Some(4));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn evicts_entries_unused_for_the_ttl() {
+        let start = Instant::now();
+        let mut cache = LruCache::new(CacheLimits {
+            max_entries: None,
+            ttl: Some(Duration::from_secs(60)),
+        });
+        cache.insert("a", 1, start);
+        cache.insert("b", 2, start + Duration::from_secs(30));
+        assert_eq!(cache.get(&"b", start + Duration::from_secs(50)), Some(&2));
+
+        assert_eq!(cache.get(&"a", start + Duration::from_secs(60)), None);
+        assert_eq!(cache.get(&"b", start + Duration::from_secs(100)), Some(&2));
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::errors::K8sError;
+use futures::StreamExt;
+use http::types::body::LineBufferMut;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{api::ListParams, config::Config, Api, Client};
+use kube_runtime::watcher;
+use kube_runtime::watcher::Event as WatcherEvent;
+use middleware::reload::{ReloadableRules, RuleSet};
+use middleware::{Middleware, Status};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::env;
+use tokio::runtime::Builder;
+
+/// Keys of the ConfigMap holding regex lists, one pattern per line since patterns can
+/// contain commas
+const EXCLUSION_KEY: &str = "line_exclusion_regex";
+const INCLUSION_KEY: &str = "line_inclusion_regex";
+const REDACT_KEY: &str = "line_redact_regex";
+/// Key of the ConfigMap holding tags, separated by commas or new lines
+const TAGS_KEY: &str = "tags";
+
+/// The settings of the cluster-wide agent ConfigMap
+pub type ClusterConfig = RuleSet;
+
+/// Tags of the cluster-wide ConfigMap, taken by the client whenever they change
+pub use middleware::reload::SharedTags as ClusterTags;
+
+/// Parses the data of the agent ConfigMap
+pub fn cluster_config(data: &BTreeMap<String, String>) -> ClusterConfig {
+    let patterns = |key: &str| -> Vec<String> {
+        data.get(key)
+            .map(|value| {
+                value
+                    .lines()
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    ClusterConfig {
+        exclusion: patterns(EXCLUSION_KEY),
+        inclusion: patterns(INCLUSION_KEY),
+        redact: patterns(REDACT_KEY),
+        tags: data
+            .get(TAGS_KEY)
+            .map(|value| {
+                value
+                    .split(|c| c == ',' || c == '\n')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Applies the exclusion, inclusion and redaction rules of a ConfigMap watched for changes,
+/// so that the rules of every agent of a DaemonSet can be changed at once without rolling
+/// it out again
+pub struct ClusterRules {
+    namespace: String,
+    name: String,
+    rules: ReloadableRules,
+}
+
+impl ClusterRules {
+    /// `reference` is the name of the ConfigMap, prefixed by its namespace and a slash when
+    /// it isn't in the namespace of the agent
+    pub fn new(reference: &str) -> Self {
+        let (namespace, name) = match reference.find('/') {
+            Some(slash) => (
+                reference[..slash].to_string(),
+                reference[slash + 1..].to_string(),
+            ),
+            None => (
+                env::var("POD_NAMESPACE")
+                    .or_else(|_| env::var("NAMESPACE"))
+                    .unwrap_or_else(|_| "default".to_string()),
+                reference.to_string(),
+            ),
+        };
+        ClusterRules {
+            rules: ReloadableRules::new(format!("ConfigMap {}/{}", namespace, name)),
+            namespace,
+            name,
+        }
+    }
+
+    pub fn tags(&self) -> ClusterTags {
+        self.rules.tags()
+    }
+
+    fn apply(&self, config: ClusterConfig) {
+        self.rules.apply(config);
+    }
+
+    fn handle(&self, event: WatcherEvent<ConfigMap>) {
+        let config_map = match event {
+            WatcherEvent::Applied(config_map) => Some(config_map),
+            WatcherEvent::Deleted(_) => None,
+            WatcherEvent::Restarted(config_maps) => config_maps.into_iter().next(),
+        };
+        self.apply(
+            config_map
+                .and_then(|c| c.data)
+                .map(|data| cluster_config(&data))
+                .unwrap_or_default(),
+        );
+    }
+
+    async fn api(&self) -> Result<Api<ConfigMap>, K8sError> {
+        let config = Config::from_cluster_env().map_err(|e| {
+            K8sError::InitializationError(format!(
+                "unable to get cluster configuration info: {}",
+                e
+            ))
+        })?;
+        let client = Client::new(config.try_into()?);
+        Ok(Api::namespaced(client, &self.namespace))
+    }
+}
+
+impl Middleware for ClusterRules {
+    fn run(&self) {
+        let runtime = match Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!(
+                    "unable to build runtime to watch the agent ConfigMap: {}",
+                    e
+                );
+                return;
+            }
+        };
+        runtime.block_on(async {
+            let api = match self.api().await {
+                Ok(api) => api,
+                Err(e) => {
+                    error!("unable to watch the agent ConfigMap: {}", e);
+                    return;
+                }
+            };
+            let params = ListParams::default().fields(&format!("metadata.name={}", self.name));
+            watcher(api, params)
+                .for_each(|event| async {
+                    match event {
+                        Ok(event) => self.handle(event),
+                        Err(e) => {
+                            warn!("agent ConfigMap watch stream error: {}", e);
+                            // The watcher recovers on the next poll, avoid polling it eagerly
+                            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                        }
+                    }
+                })
+                .await;
+        });
+    }
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        self.rules.process(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn parses_config_map_data() {
+        let mut data = BTreeMap::new();
+        data.insert(
+            EXCLUSION_KEY.to_string(),
+            "DEBUG\n(?i:health, check)\n\n".to_string(),
+        );
+        data.insert(TAGS_KEY.to_string(), "prod, eu\nteam-a".to_string());
+        assert_eq!(
+            cluster_config(&data),
+            ClusterConfig {
+                exclusion: vec!["DEBUG".into(), "(?i:health, check)".into()],
+                inclusion: Vec::new(),
+                redact: Vec::new(),
+                tags: vec!["prod".into(), "eu".into(), "team-a".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn applies_config_changes() {
+        let rules = ClusterRules::new("logging/agent");
+        assert_eq!(rules.namespace, "logging");
+        assert!(matches!(
+            rules.process(&mut LineBuilder::new().line("DEBUG hello")),
+            Status::Ok(_)
+        ));
+
+        rules.apply(ClusterConfig {
+            exclusion: vec!["DEBUG".into()],
+            tags: vec!["prod".into()],
+            ..ClusterConfig::default()
+        });
+        assert!(matches!(
+            rules.process(&mut LineBuilder::new().line("DEBUG hello")),
+            Status::Skip
+        ));
+        assert_eq!(rules.tags().take(), Some(vec!["prod".to_string()]));
+        assert_eq!(rules.tags().take(), None);
+
+        // Invalid rules keep the previous ones
+        rules.apply(ClusterConfig {
+            exclusion: vec!["(".into()],
+            ..ClusterConfig::default()
+        });
+        assert!(matches!(
+            rules.process(&mut LineBuilder::new().line("DEBUG hello")),
+            Status::Skip
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::errors::K8sError;
+use http::types::body::LineBufferMut;
+use k8s_openapi::api::core::v1::Pod;
+use kube::{config::Config, Api, Client};
+use middleware::{Middleware, Status};
+use parking_lot::RwLock;
+use serde_json::{Map, Value};
+use std::convert::TryInto;
+use std::env;
+use tokio::runtime::Builder;
+
+/// Key of the line meta the node and cluster names are attached under
+const META_KEY: &str = "k8s";
+
+/// Attaches the name of the node the agent runs on and of its cluster to the meta of every
+/// line, so that accounts receiving lines from several clusters can tell them apart
+pub struct K8sIdentity {
+    /// The node name set through the downward API, looked up on `run` otherwise
+    node: Option<String>,
+    cluster: Option<String>,
+    meta: RwLock<Value>,
+}
+
+impl K8sIdentity {
+    /// The node name is taken from the `NODE_NAME` env var, set through the downward API,
+    /// and looked up from the pod of the agent when it isn't set
+    pub fn new(cluster: Option<String>) -> Self {
+        let node = env::var("NODE_NAME")
+            .ok()
+            .filter(|node| !node.trim().is_empty());
+        K8sIdentity {
+            meta: RwLock::new(to_json(node.as_deref(), cluster.as_deref())),
+            node,
+            cluster,
+        }
+    }
+
+    async fn node_from_api() -> Result<Option<String>, K8sError> {
+        let pod = env::var("POD_NAME")
+            .map_err(|_| K8sError::InitializationError("POD_NAME env var is not set".into()))?;
+        let namespace = env::var("POD_NAMESPACE")
+            .or_else(|_| env::var("NAMESPACE"))
+            .unwrap_or_else(|_| "default".to_string());
+        let config = Config::from_cluster_env().map_err(|e| {
+            K8sError::InitializationError(format!(
+                "unable to get cluster configuration info: {}",
+                e
+            ))
+        })?;
+        let client = Client::new(config.try_into()?);
+        let pod = Api::<Pod>::namespaced(client, &namespace).get(&pod).await?;
+        Ok(pod.spec.and_then(|spec| spec.node_name))
+    }
+}
+
+fn to_json(node: Option<&str>, cluster: Option<&str>) -> Value {
+    let mut meta = Map::new();
+    if let Some(node) = node {
+        meta.insert("node".into(), node.into());
+    }
+    if let Some(cluster) = cluster {
+        meta.insert("cluster".into(), cluster.into());
+    }
+    Value::Object(meta)
+}
+
+impl Middleware for K8sIdentity {
+    fn run(&self) {
+        if self.node.is_some() {
+            return;
+        }
+        let runtime = match Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                warn!("unable to build runtime to look up the node name: {}", e);
+                return;
+            }
+        };
+        match runtime.block_on(K8sIdentity::node_from_api()) {
+            Ok(Some(node)) => {
+                info!("attaching node name {} to lines", node);
+                *self.meta.write() = to_json(Some(&node), self.cluster.as_deref());
+            }
+            Ok(None) => warn!("the pod of the agent isn't scheduled on a node"),
+            Err(e) => warn!("unable to look up the node name: {}", e),
+        }
+    }
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        let identity = self.meta.read().clone();
+        if identity.as_object().map_or(true, Map::is_empty) {
+            return Status::Ok(line);
+        }
+        let meta = match line.get_meta() {
+            Some(Value::Object(meta)) => {
+                let mut meta = meta.clone();
+                meta.insert(META_KEY.into(), identity);
+                meta
+            }
+            // Meta that isn't an object is left as it is rather than overwritten
+            Some(_) => return Status::Ok(line),
+            None => {
+                let mut meta = Map::new();
+                meta.insert(META_KEY.into(), identity);
+                meta
+            }
+        };
+        if let Err(e) = line.set_meta(Value::Object(meta)) {
+            debug!("unable to attach node and cluster names: {:?}", e);
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta};
+    use serde_json::json;
+
+    #[test]
+    fn attaches_node_and_cluster_to_line_meta() {
+        let identity = K8sIdentity {
+            node: Some("node-1".into()),
+            cluster: Some("prod-eu".into()),
+            meta: RwLock::new(to_json(Some("node-1"), Some("prod-eu"))),
+        };
+        let mut line = LineBuilder::new().line("abc").meta(json!({"app": "web"}));
+        match identity.process(&mut line) {
+            Status::Ok(line) => assert_eq!(
+                line.get_meta(),
+                Some(&json!({"app": "web", "k8s": {"node": "node-1", "cluster": "prod-eu"}}))
+            ),
+            Status::Skip => panic!("line skipped"),
+        }
+    }
+
+    #[test]
+    fn leaves_lines_without_identity_untouched() {
+        let identity = K8sIdentity {
+            node: None,
+            cluster: None,
+            meta: RwLock::new(to_json(None, None)),
+        };
+        let mut line = LineBuilder::new().line("abc");
+        match identity.process(&mut line) {
+            Status::Ok(line) => assert_eq!(line.get_meta(), None),
+            Status::Skip => panic!("line skipped"),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::errors::K8sError;
-use crate::middleware::parse_container_path;
+use crate::middleware::cache::LruCache;
+use crate::middleware::{parse_container_path, CacheLimits};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use futures::stream::TryStreamExt;
 use futures::StreamExt;
 use http::types::body::{KeyValueMap, LineBufferMut};
@@ -15,10 +17,11 @@ use metrics::Metrics;
 use middleware::{Middleware, Status};
 use parking_lot::Mutex;
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::env;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::runtime::{Builder, Runtime};
 
@@ -32,15 +35,39 @@ enum Error {
     K8s(#[from] kube::Error),
 }
 
+pub(crate) type PodKey = (String, String);
+
+/// The pods whose metadata the watch delivers as it does, for the lines waiting on it, see
+/// [`hold_pending_lines`](super::hold_pending_lines)
+pub struct PodArrivals {
+    metadata: Arc<Mutex<LruCache<PodKey, PodMetadata>>>,
+    pub(crate) arrivals: UnboundedReceiver<PodKey>,
+    /// How long lines wait for the metadata of their pod
+    pub(crate) wait: Duration,
+}
+
+impl PodArrivals {
+    /// Whether the metadata of the pod is known already
+    pub(crate) fn contains(&self, key: &PodKey) -> bool {
+        self.metadata.lock().contains_key(key)
+    }
+}
+
 pub struct K8sMetadata {
-    metadata: Mutex<HashMap<(String, String), PodMetadata>>,
+    metadata: Arc<Mutex<LruCache<PodKey, PodMetadata>>>,
+    wait_timeout: Option<Duration>,
+    /// Told the pods whose metadata the watch delivers, once lines are held for it
+    arrivals: Mutex<Option<UnboundedSender<PodKey>>>,
     api: Api<Pod>,
     runtime: Mutex<Option<Runtime>>,
 }
 
 // TODO refactor to use kube-rs Reflector instead of manually managing hashmap
 impl K8sMetadata {
-    pub fn new() -> Result<Self, K8sError> {
+    /// The metadata of pods is cached within `limits`, pods evicted from the cache are only
+    /// enriched again once they are updated. Lines of containers whose pod the watch hasn't
+    /// delivered yet are held for up to `wait_timeout` by the stream of [`Self::arrivals`]
+    pub fn new(limits: CacheLimits, wait_timeout: Option<Duration>) -> Result<Self, K8sError> {
         let runtime = match Builder::new_multi_thread()
             .enable_all()
             .worker_threads(2)
@@ -71,7 +98,7 @@ impl K8sMetadata {
                 params = ListParams::default().fields(&format!("spec.nodeName={}", node));
             }
 
-            let mut metadata = HashMap::new();
+            let mut metadata = LruCache::new(limits);
 
             match Api::<Pod>::all(client.clone()).list(&params).await {
                 Ok(pods) => {
@@ -86,8 +113,10 @@ impl K8sMetadata {
                         metadata.insert(
                             (pod_meta_data.name.clone(), pod_meta_data.namespace.clone()),
                             pod_meta_data,
+                            Instant::now(),
                         );
                     }
+                    debug!("cached metadata of {} pods", metadata.len());
                 }
                 Err(e) => {
                     return Err(K8sError::InitializationError(format!(
@@ -98,7 +127,9 @@ impl K8sMetadata {
             }
 
             Ok(K8sMetadata {
-                metadata: Mutex::new(metadata),
+                metadata: Arc::new(Mutex::new(metadata)),
+                wait_timeout,
+                arrivals: Mutex::new(None),
                 api: Api::<Pod>::all(client),
                 runtime: Mutex::new(None),
             })
@@ -114,45 +145,64 @@ impl K8sMetadata {
         match event {
             WatcherEvent::Applied(pod) => {
                 let pod_meta_data = PodMetadata::try_from(pod)?;
-                let mut metadata = self.metadata.lock();
-                metadata
-                    .entry((pod_meta_data.name.clone(), pod_meta_data.namespace.clone()))
-                    .and_modify(|e| {
-                        Metrics::k8s().increment_creates();
-                        *e = pod_meta_data.clone()
-                    })
-                    .or_insert_with(|| {
-                        Metrics::k8s().increment_creates();
-                        pod_meta_data
-                    });
+                Metrics::k8s().increment_creates();
+                let key = (pod_meta_data.name.clone(), pod_meta_data.namespace.clone());
+                self.metadata
+                    .lock()
+                    .insert(key.clone(), pod_meta_data, Instant::now());
+                self.announce(vec![key]);
             } // insert or update
             WatcherEvent::Deleted(pod) => {
                 let pod_meta_data = PodMetadata::try_from(pod)?;
-                self.metadata
-                    .lock()
-                    .remove(&(pod_meta_data.name, pod_meta_data.namespace));
+                let key = (pod_meta_data.name, pod_meta_data.namespace);
+                self.metadata.lock().remove(&key);
                 Metrics::k8s().increment_deletes();
             } // remove
             WatcherEvent::Restarted(pods) => {
                 let mut metadata = self.metadata.lock();
+                let now = Instant::now();
+                let mut keys = Vec::with_capacity(pods.len());
                 for pod in pods {
                     let pod_meta_data = PodMetadata::try_from(pod)?;
-                    metadata
-                        .entry((pod_meta_data.name.clone(), pod_meta_data.namespace.clone()))
-                        .and_modify(|e| {
-                            Metrics::k8s().increment_creates();
-                            *e = pod_meta_data.clone()
-                        })
-                        .or_insert_with(|| {
-                            Metrics::k8s().increment_creates();
-                            pod_meta_data
-                        });
+                    Metrics::k8s().increment_creates();
+                    let key = (pod_meta_data.name.clone(), pod_meta_data.namespace.clone());
+                    metadata.insert(key.clone(), pod_meta_data, now);
+                    keys.push(key);
                 }
+                drop(metadata);
+                self.announce(keys);
             }
         }
         Ok(())
     }
 
+    /// The pods whose metadata arrives from now on, for holding the lines of containers whose
+    /// pod the watch hasn't delivered yet. None when lines don't wait for the metadata
+    pub fn arrivals(&self) -> Option<PodArrivals> {
+        let wait = self.wait_timeout?;
+        let (sender, arrivals) = mpsc::unbounded();
+        *self.arrivals.lock() = Some(sender);
+        Some(PodArrivals {
+            metadata: self.metadata.clone(),
+            arrivals,
+            wait,
+        })
+    }
+
+    /// Releases the lines held for the metadata of `keys`, which was just cached
+    fn announce(&self, keys: Vec<PodKey>) {
+        let mut arrivals = self.arrivals.lock();
+        if let Some(sender) = arrivals.as_ref() {
+            for key in keys {
+                if sender.unbounded_send(key).is_err() {
+                    // The lines aren't held anymore
+                    *arrivals = None;
+                    return;
+                }
+            }
+        }
+    }
+
     async fn add_delay(&self, backoff: &mut ExponentialBackoff) {
         let mut interval = backoff.next_backoff();
         if interval.is_none() {
@@ -205,7 +255,8 @@ impl Middleware for K8sMetadata {
     fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
         if let Some(ref file_name) = line.get_file() {
             if let Some(key) = parse_container_path(&file_name) {
-                if let Some(pod_meta_data) = self.metadata.lock().get(&key) {
+                let mut metadata = self.metadata.lock();
+                if let Some(pod_meta_data) = metadata.get(&key, Instant::now()) {
                     if line
                         .set_annotations(pod_meta_data.annotations.clone())
                         .is_err()
@@ -265,12 +316,18 @@ struct PodMetadata {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::middleware::hold_pending_lines;
+    use futures::Stream;
     use http::types::body::{LineBuilder, LineMeta};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use std::pin::Pin;
     use url::Url;
 
+    const FIRST_POD_FILE: &str = "/var/log/containers/first_file_sample-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log";
+
     #[tokio::test]
     async fn test_process_with_file_that_can_not_be_parsed() {
-        let k8s_meta = get_instance(HashMap::new());
+        let k8s_meta = get_instance(Vec::new());
         let mut line = LineBuilder::new().line("abc").file("abc.log");
         let result = k8s_meta.process(&mut line);
         assert!(matches!(&result, Status::Ok(_)));
@@ -284,9 +341,7 @@ mod tests {
     async fn test_process_with_different_files() {
         let matching_file1 = "/var/log/containers/first_file_sample-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log";
         let matching_file2 = "/var/log/containers/second_file_sample-f39155eb652f5161f4a34b1fbd89a4d361e76ccb6c3cdc0e2c18e0d0abb26516.log";
-        let mut map = HashMap::new();
-        map.insert(("first".into(), "file".into()), get_pod_metadata());
-        let k8s_meta = get_instance(map);
+        let k8s_meta = get_instance(vec![(("first".into(), "file".into()), get_pod_metadata())]);
         let mut lines = vec![
             LineBuilder::new().line("line 0").file(matching_file1),
             // 1: File not matching
@@ -318,10 +373,98 @@ mod tests {
         }
     }
 
-    fn get_instance(map: HashMap<(String, String), PodMetadata>) -> K8sMetadata {
+    #[tokio::test]
+    async fn test_holds_lines_until_their_pod_arrives() {
+        let mut k8s_meta = get_instance(Vec::new());
+        k8s_meta.wait_timeout = Some(Duration::from_secs(60));
+        let (sender, lines) = mpsc::unbounded();
+        let lines = hold_pending_lines(lines, k8s_meta.arrivals(), file_of);
+        futures::pin_mut!(lines);
+
+        sender
+            .unbounded_send(LineBuilder::new().line("held").file(FIRST_POD_FILE))
+            .unwrap();
+        sender
+            .unbounded_send(LineBuilder::new().line("other").file("/tmp/other.log"))
+            .unwrap();
+        let line = next_line(lines.as_mut()).await;
+        assert_eq!(line.line.as_deref(), Some("other"));
+        assert_eq!(lines.held(), 1);
+
+        k8s_meta
+            .handle_pod(WatcherEvent::Applied(get_pod()))
+            .unwrap();
+        let mut line = next_line(lines.as_mut()).await;
+        assert_eq!(line.line.as_deref(), Some("held"));
+        assert_eq!(lines.held(), 0);
+        assert!(matches!(k8s_meta.process(&mut line), Status::Ok(l) if l.get_labels().is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_releases_held_lines_at_the_deadline() {
+        let wait = Duration::from_millis(50);
+        let mut k8s_meta = get_instance(Vec::new());
+        k8s_meta.wait_timeout = Some(wait);
+        let (sender, lines) = mpsc::unbounded();
+        let lines = hold_pending_lines(lines, k8s_meta.arrivals(), file_of);
+        futures::pin_mut!(lines);
+        let timeouts = Metrics::k8s().read_metadata_timeouts();
+
+        let held_at = Instant::now();
+        sender
+            .unbounded_send(LineBuilder::new().line("held").file(FIRST_POD_FILE))
+            .unwrap();
+        let line = next_line(lines.as_mut()).await;
+        assert_eq!(line.line.as_deref(), Some("held"));
+        assert!(held_at.elapsed() >= wait);
+        assert!(Metrics::k8s().read_metadata_timeouts() > timeouts);
+
+        // The pod was given up on, its further lines don't wait
+        sender
+            .unbounded_send(LineBuilder::new().line("not held").file(FIRST_POD_FILE))
+            .unwrap();
+        let line = next_line(lines.as_mut()).await;
+        assert_eq!(line.line.as_deref(), Some("not held"));
+        assert_eq!(lines.held(), 0);
+    }
+
+    fn file_of(line: &LineBuilder) -> Option<&str> {
+        line.get_file()
+    }
+
+    async fn next_line<S: Stream<Item = LineBuilder>>(mut lines: Pin<&mut S>) -> LineBuilder {
+        tokio::time::timeout(Duration::from_secs(5), lines.next())
+            .await
+            .expect("no line was released")
+            .expect("the lines ended")
+    }
+
+    fn get_pod() -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                name: Some("first".to_string()),
+                namespace: Some("file".to_string()),
+                labels: Some(
+                    vec![("app".to_string(), "first".to_string())]
+                        .into_iter()
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn get_instance(pods: Vec<(PodKey, PodMetadata)>) -> K8sMetadata {
         let config = Config::new(Url::parse("https://sample.url/").unwrap());
+        let mut metadata = LruCache::new(CacheLimits::default());
+        for (key, pod) in pods {
+            metadata.insert(key, pod, Instant::now());
+        }
         K8sMetadata {
-            metadata: Mutex::new(map),
+            metadata: Arc::new(Mutex::new(metadata)),
+            wait_timeout: None,
+            arrivals: Mutex::new(None),
             api: Api::<Pod>::all(Client::new(config.try_into().unwrap())),
             runtime: Mutex::new(None),
         }
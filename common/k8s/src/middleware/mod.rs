@@ -1,15 +1,43 @@
+#[cfg(feature = "api")]
 use regex::Regex;
+use std::time::Duration;
 
+#[cfg(feature = "api")]
+mod cache;
+#[cfg(feature = "api")]
+mod cluster_rules;
+#[cfg(feature = "api")]
+mod identity;
+#[cfg(feature = "api")]
 mod metadata;
+#[cfg(feature = "api")]
+mod pending;
 
+#[cfg(feature = "api")]
+pub use cluster_rules::*;
+#[cfg(feature = "api")]
+pub use identity::*;
+#[cfg(feature = "api")]
 pub use metadata::*;
+#[cfg(feature = "api")]
+pub use pending::*;
 
+/// Bounds of the pod metadata cache, `None` leaves the cache unbounded in that dimension
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheLimits {
+    pub max_entries: Option<usize>,
+    /// How long an entry is kept without being looked up or updated
+    pub ttl: Option<Duration>,
+}
+
+#[cfg(feature = "api")]
 lazy_static! {
     static ref K8S_REG: Regex = Regex::new(
         r#"^/var/log/containers/([a-z0-9A-Z\-.]+)_([a-z0-9A-Z\-.]+)_([a-z0-9A-Z\-.]+)-([a-z0-9]{64}).log$"#
     ).unwrap_or_else(|e| panic!("K8S_REG Regex::new() failed: {}", e));
 }
 
+#[cfg(feature = "api")]
 fn parse_container_path(path: &str) -> Option<(String, String)> {
     let captures = K8S_REG.captures(path)?;
     Some((
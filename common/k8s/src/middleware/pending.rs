@@ -0,0 +1,192 @@
+use crate::middleware::metadata::{PodArrivals, PodKey};
+use crate::middleware::parse_container_path;
+use futures::stream::{Fuse, Stream, StreamExt};
+use metrics::Metrics;
+use pin_project_lite::pin_project;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::time::{self, Sleep};
+
+/// Pods whose metadata didn't arrive in time are remembered up to this many, the pods given up
+/// on the longest ago being forgotten first
+const MAX_GIVEN_UP: usize = 1024;
+
+/// Pods whose lines already waited for their metadata, so that pods whose metadata never
+/// arrives don't hold up all of their lines
+#[derive(Default)]
+struct GivenUp {
+    pods: HashSet<PodKey>,
+    order: VecDeque<PodKey>,
+}
+
+impl GivenUp {
+    fn contains(&self, key: &PodKey) -> bool {
+        self.pods.contains(key)
+    }
+
+    fn insert(&mut self, key: PodKey) {
+        if !self.pods.insert(key.clone()) {
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > MAX_GIVEN_UP {
+            if let Some(oldest) = self.order.pop_front() {
+                self.pods.remove(&oldest);
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// The lines of `lines`, those of containers whose pod metadata hasn't been delivered by the
+    /// watch yet, typically read right after the container started, being held back until it
+    /// is or the wait of the [`PodArrivals`] elapses. The lines of other pods and sources go
+    /// through meanwhile, the lines of each pod keep their order
+    pub struct PendingLines<S: Stream, F> {
+        #[pin]
+        lines: Fuse<S>,
+        pods: Option<PodArrivals>,
+        file_of: F,
+        // The lines of each pod waiting on its metadata, along with when they are released
+        // regardless
+        held: HashMap<PodKey, (Instant, VecDeque<S::Item>)>,
+        // The pods in the order they started waiting, the earliest deadline first
+        deadlines: VecDeque<(Instant, PodKey)>,
+        released: VecDeque<S::Item>,
+        given_up: GivenUp,
+        timer: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+/// Holds back the lines of `lines` waiting on the metadata of their pod, see [`PendingLines`].
+/// `file_of` tells the file a line was read from, lines go straight through without `pods`
+pub fn hold_pending_lines<S, F>(
+    lines: S,
+    pods: Option<PodArrivals>,
+    file_of: F,
+) -> PendingLines<S, F>
+where
+    S: Stream,
+    F: Fn(&S::Item) -> Option<&str>,
+{
+    PendingLines {
+        lines: lines.fuse(),
+        pods,
+        file_of,
+        held: HashMap::new(),
+        deadlines: VecDeque::new(),
+        released: VecDeque::new(),
+        given_up: GivenUp::default(),
+        timer: None,
+    }
+}
+
+impl<S, F> PendingLines<S, F>
+where
+    S: Stream,
+{
+    /// The number of lines held back
+    pub fn held(&self) -> usize {
+        self.held.values().map(|(_, lines)| lines.len()).sum()
+    }
+}
+
+impl<S, F> Stream for PendingLines<S, F>
+where
+    S: Stream,
+    F: Fn(&S::Item) -> Option<&str>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        let pods = match this.pods.as_mut() {
+            Some(pods) => pods,
+            None => return this.lines.poll_next(cx),
+        };
+
+        loop {
+            while let Poll::Ready(Some(key)) = pods.arrivals.poll_next_unpin(cx) {
+                if let Some((_, lines)) = this.held.remove(&key) {
+                    this.released.extend(lines);
+                }
+            }
+
+            let now = Instant::now();
+            while let Some((deadline, _)) = this.deadlines.front() {
+                if *deadline > now {
+                    break;
+                }
+                let (deadline, key) = this.deadlines.pop_front().expect("front was just seen");
+                // The lines of the pod may have been released by its metadata already, the pod
+                // being held again since when its metadata was evicted from the cache
+                match this.held.get(&key) {
+                    Some((held, _)) if *held == deadline => {}
+                    _ => continue,
+                }
+                if let Some((_, lines)) = this.held.remove(&key) {
+                    debug!("no metadata for pod {} in {} arrived in time", key.0, key.1);
+                    Metrics::k8s().increment_metadata_timeouts();
+                    this.released.extend(lines);
+                    this.given_up.insert(key);
+                }
+            }
+
+            if let Some(line) = this.released.pop_front() {
+                return Poll::Ready(Some(line));
+            }
+
+            match this.lines.as_mut().poll_next(cx) {
+                Poll::Ready(Some(line)) => {
+                    let key = (this.file_of)(&line).and_then(parse_container_path);
+                    match key {
+                        Some(key) if this.held.contains_key(&key) => {
+                            if let Some((_, lines)) = this.held.get_mut(&key) {
+                                lines.push_back(line);
+                            }
+                        }
+                        Some(key) if !this.given_up.contains(&key) && !pods.contains(&key) => {
+                            let deadline = now + pods.wait;
+                            this.deadlines.push_back((deadline, key.clone()));
+                            this.held
+                                .insert(key, (deadline, VecDeque::from(vec![line])));
+                        }
+                        _ => return Poll::Ready(Some(line)),
+                    }
+                    continue;
+                }
+                Poll::Ready(None) => {
+                    // Nothing is left to wait for once the sources ended
+                    for (_, key) in this.deadlines.drain(..) {
+                        if let Some((_, lines)) = this.held.remove(&key) {
+                            this.released.extend(lines);
+                        }
+                    }
+                    return Poll::Ready(this.released.pop_front());
+                }
+                Poll::Pending => {}
+            }
+
+            let deadline = match this.deadlines.front() {
+                Some((deadline, _)) => *deadline,
+                None => {
+                    *this.timer = None;
+                    return Poll::Pending;
+                }
+            };
+            let deadline = time::Instant::from_std(deadline);
+            match this.timer.as_mut() {
+                Some(timer) if timer.deadline() != deadline => timer.as_mut().reset(deadline),
+                Some(_) => {}
+                None => *this.timer = Some(Box::pin(time::sleep_until(deadline))),
+            }
+            let timer = this.timer.as_mut().expect("the timer was just set");
+            if timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
@@ -0,0 +1,2 @@
+#[cfg(feature = "rdkafka")]
+pub mod source;
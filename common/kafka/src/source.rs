@@ -0,0 +1,118 @@
+use futures::Stream;
+use http::types::body::{LineBuilder, LineMetaMut};
+use log::{info, warn};
+use metrics::Metrics;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+use std::time::Duration;
+use tokio::sync::mpsc::channel;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Consumes `topics` as part of the `group_id` consumer group and streams each record as a line.
+///
+/// Offsets are only stored once a record was handed to the agent, so records in flight when the
+/// agent stops are consumed again by the group. Must be called from within a tokio runtime.
+pub fn create_source(
+    brokers: &[String],
+    topics: &[String],
+    group_id: &str,
+) -> Result<impl Stream<Item = LineBuilder>, KafkaError> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &brokers.join(","))
+        .set("group.id", group_id)
+        .set("enable.auto.commit", "true")
+        .set("enable.auto.offset.store", "false")
+        .create()?;
+    let topics: Vec<&str> = topics.iter().map(String::as_str).collect();
+    consumer.subscribe(&topics)?;
+    info!("consuming kafka topics {:?} as group {}", topics, group_id);
+
+    let (tx, rx) = channel(1024);
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let message = match consumer.recv().await {
+                Ok(message) => message,
+                // Errors like an unreachable broker are returned right away until it's back
+                Err(e) => {
+                    warn!(
+                        "unable to consume kafka record, retrying in {:?}: {}",
+                        backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = INITIAL_BACKOFF;
+            let payload = message.payload().unwrap_or_default();
+            Metrics::kafka().increment_records();
+            Metrics::kafka().add_bytes(payload.len() as u64);
+
+            let line = to_line(
+                message.topic(),
+                message.partition(),
+                message.offset(),
+                message.key(),
+                payload,
+            );
+            if tx.send(line).await.is_err() {
+                break;
+            }
+            if let Err(e) = consumer.store_offset(&message) {
+                warn!("unable to store kafka offset: {}", e);
+            }
+        }
+    });
+
+    Ok(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    }))
+}
+
+fn to_line(
+    topic: &str,
+    partition: i32,
+    offset: i64,
+    key: Option<&[u8]>,
+    payload: &[u8],
+) -> LineBuilder {
+    let mut line = LineBuilder::new()
+        .line(String::from_utf8_lossy(payload))
+        .app(topic)
+        .file(format!("kafka:{}", topic));
+    let mut meta = serde_json::json!({
+        "kafka": {
+            "topic": topic,
+            "partition": partition,
+            "offset": offset,
+        }
+    });
+    if let Some(key) = key {
+        meta["kafka"]["key"] = String::from_utf8_lossy(key).into();
+    }
+    let _ = line.set_meta(meta);
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_lines_with_record_position() {
+        let line = to_line("payments", 3, 42, Some(b"order-1"), b"charged");
+        assert_eq!(line.line.as_deref(), Some("charged"));
+        assert_eq!(line.app.as_deref(), Some("payments"));
+        assert_eq!(line.file.as_deref(), Some("kafka:payments"));
+
+        let meta = line.meta.unwrap();
+        assert_eq!(meta["kafka"]["partition"], 3);
+        assert_eq!(meta["kafka"]["offset"], 42);
+        assert_eq!(meta["kafka"]["key"], "order-1");
+    }
+}
@@ -1,15 +1,20 @@
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::AtomicI64;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use chrono::Utc;
-use jemalloc_ctl::stats::{active, active_mib, allocated, allocated_mib, resident, resident_mib};
-use jemalloc_ctl::{epoch, epoch_mib};
-use json::object;
 use lazy_static::lazy_static;
 use log::info;
 use std::thread::sleep;
 use std::time::Duration;
 
+pub mod memory;
+pub mod snapshot;
+
+pub use memory::MemoryStats;
+pub use snapshot::MetricsSnapshot;
+
 lazy_static! {
     static ref METRICS: Metrics = Metrics::new();
 }
@@ -21,6 +26,25 @@ pub struct Metrics {
     http: Http,
     k8s: K8s,
     journald: Journald,
+    auditd: Auditd,
+    docker: Docker,
+    kubelet: Kubelet,
+    receiver: Receiver,
+    exec: Exec,
+    kafka: Kafka,
+    archive: Archive,
+    elasticsearch: Elasticsearch,
+    otlp: Otlp,
+    webhook: Webhook,
+    syslog: Syslog,
+    unix_socket: UnixSocket,
+    anonymizer: Anonymizer,
+    shadow: Shadow,
+    watchdog: Watchdog,
+    sources: Sources,
+    checkpoints: Checkpoints,
+    connections: Connections,
+    routes: Routes,
 }
 
 impl Metrics {
@@ -32,6 +56,25 @@ impl Metrics {
             http: Http::new(),
             k8s: K8s::new(),
             journald: Journald::new(),
+            auditd: Auditd::new(),
+            docker: Docker::new(),
+            kubelet: Kubelet::new(),
+            receiver: Receiver::new(),
+            exec: Exec::new(),
+            kafka: Kafka::new(),
+            archive: Archive::new(),
+            elasticsearch: Elasticsearch::new(),
+            otlp: Otlp::new(),
+            webhook: Webhook::new(),
+            syslog: Syslog::new(),
+            unix_socket: UnixSocket::new(),
+            anonymizer: Anonymizer::new(),
+            shadow: Shadow::new(),
+            watchdog: Watchdog::new(),
+            sources: Sources::new(),
+            checkpoints: Checkpoints::new(),
+            connections: Connections::new(),
+            routes: Routes::new(),
         }
     }
 
@@ -52,6 +95,25 @@ impl Metrics {
         Metrics::http().reset();
         Metrics::k8s().reset();
         Metrics::journald().reset();
+        Metrics::auditd().reset();
+        Metrics::docker().reset();
+        Metrics::kubelet().reset();
+        Metrics::receiver().reset();
+        Metrics::exec().reset();
+        Metrics::kafka().reset();
+        Metrics::archive().reset();
+        Metrics::elasticsearch().reset();
+        Metrics::otlp().reset();
+        Metrics::webhook().reset();
+        Metrics::syslog().reset();
+        Metrics::unix_socket().reset();
+        Metrics::anonymizer().reset();
+        Metrics::shadow().reset();
+        Metrics::watchdog().reset();
+        Metrics::sources().reset();
+        Metrics::checkpoints().reset();
+        Metrics::connections().reset();
+        Metrics::routes().reset();
     }
 
     pub fn elapsed() -> u64 {
@@ -78,49 +140,88 @@ impl Metrics {
         &METRICS.journald
     }
 
-    pub fn print() -> String {
-        let fs = Metrics::fs();
-        let memory = Metrics::memory();
-        let http = Metrics::http();
-        let k8s = Metrics::k8s();
-        let journald = Metrics::journald();
-
-        let object = object! {
-            "fs" => object!{
-                "events" => fs.read_events(),
-                "creates" => fs.read_creates(),
-                "deletes" => fs.read_deletes(),
-                "writes" => fs.read_writes(),
-                "lines" => fs.read_lines(),
-                "bytes" => fs.read_bytes(),
-                "partial_reads" => fs.read_partial_reads(),
-            },
-            "memory" => object!{
-                "active" => memory.read_active(),
-                "allocated" => memory.read_allocated(),
-                "resident" => memory.read_resident(),
-            },
-            "ingest" => object!{
-                "requests" => http.read_requests(),
-                "throughput" => http.read_request_size(),
-                "rate_limits" => http.read_limit_hits(),
-                "retries" => http.read_retries(),
-            },
-            "k8s" => object!{
-                "lines" => k8s.read_lines(),
-                "polls" => k8s.read_polls(),
-                "creates" => k8s.read_creates(),
-                "deletes" => k8s.read_deletes(),
-                "events" => k8s.read_events(),
-                "notifies" => k8s.read_notifies(),
-            },
-            "journald" => object!{
-                "lines" => journald.read_lines(),
-                "bytes" => journald.read_bytes(),
-            },
-        };
+    pub fn auditd() -> &'static Auditd {
+        &METRICS.auditd
+    }
+
+    pub fn docker() -> &'static Docker {
+        &METRICS.docker
+    }
+
+    pub fn kubelet() -> &'static Kubelet {
+        &METRICS.kubelet
+    }
+
+    pub fn receiver() -> &'static Receiver {
+        &METRICS.receiver
+    }
+
+    pub fn exec() -> &'static Exec {
+        &METRICS.exec
+    }
+
+    pub fn kafka() -> &'static Kafka {
+        &METRICS.kafka
+    }
+
+    pub fn archive() -> &'static Archive {
+        &METRICS.archive
+    }
+
+    pub fn elasticsearch() -> &'static Elasticsearch {
+        &METRICS.elasticsearch
+    }
+
+    pub fn otlp() -> &'static Otlp {
+        &METRICS.otlp
+    }
+
+    pub fn webhook() -> &'static Webhook {
+        &METRICS.webhook
+    }
+
+    pub fn syslog() -> &'static Syslog {
+        &METRICS.syslog
+    }
+
+    pub fn unix_socket() -> &'static UnixSocket {
+        &METRICS.unix_socket
+    }
+
+    pub fn anonymizer() -> &'static Anonymizer {
+        &METRICS.anonymizer
+    }
+
+    pub fn shadow() -> &'static Shadow {
+        &METRICS.shadow
+    }
+
+    pub fn watchdog() -> &'static Watchdog {
+        &METRICS.watchdog
+    }
+
+    pub fn sources() -> &'static Sources {
+        &METRICS.sources
+    }
+
+    pub fn checkpoints() -> &'static Checkpoints {
+        &METRICS.checkpoints
+    }
+
+    pub fn connections() -> &'static Connections {
+        &METRICS.connections
+    }
+
+    pub fn routes() -> &'static Routes {
+        &METRICS.routes
+    }
+
+    pub fn snapshot() -> MetricsSnapshot {
+        MetricsSnapshot::take()
+    }
 
-        object.to_string()
+    pub fn print() -> String {
+        serde_json::to_string(&Metrics::snapshot()).unwrap_or_default()
     }
 }
 
@@ -133,6 +234,12 @@ pub struct Fs {
     lines: AtomicU64,
     bytes: AtomicU64,
     partial_reads: AtomicU64,
+    coalesced_events: AtomicU64,
+    mapped_reads: AtomicU64,
+    mapped_bytes: AtomicU64,
+    read_throttle_utilization: AtomicU64,
+    /// Files whose unread backlog has been over the lag threshold for long enough
+    files_lagging: AtomicU64,
 }
 
 impl Fs {
@@ -145,6 +252,11 @@ impl Fs {
             lines: AtomicU64::new(0),
             bytes: AtomicU64::new(0),
             partial_reads: AtomicU64::new(0),
+            coalesced_events: AtomicU64::new(0),
+            mapped_reads: AtomicU64::new(0),
+            mapped_bytes: AtomicU64::new(0),
+            read_throttle_utilization: AtomicU64::new(0),
+            files_lagging: AtomicU64::new(0),
         }
     }
 
@@ -156,6 +268,9 @@ impl Fs {
         self.lines.store(0, Ordering::Relaxed);
         self.bytes.store(0, Ordering::Relaxed);
         self.partial_reads.store(0, Ordering::Relaxed);
+        self.coalesced_events.store(0, Ordering::Relaxed);
+        self.mapped_reads.store(0, Ordering::Relaxed);
+        self.mapped_bytes.store(0, Ordering::Relaxed);
     }
 
     pub fn increment_events(&self) {
@@ -213,40 +328,103 @@ impl Fs {
     pub fn read_partial_reads(&self) -> u64 {
         self.partial_reads.load(Ordering::Relaxed)
     }
+
+    pub fn increment_coalesced_events(&self) {
+        self.coalesced_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_coalesced_events(&self) -> u64 {
+        self.coalesced_events.load(Ordering::Relaxed)
+    }
+
+    /// A backlog of `bytes` mapped into memory to be read
+    pub fn add_mapped_read(&self, bytes: u64) {
+        self.mapped_reads.fetch_add(1, Ordering::Relaxed);
+        self.mapped_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn read_mapped_reads(&self) -> u64 {
+        self.mapped_reads.load(Ordering::Relaxed)
+    }
+
+    pub fn read_mapped_bytes(&self) -> u64 {
+        self.mapped_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_read_throttle_utilization(&self, percent: u64) {
+        self.read_throttle_utilization
+            .store(percent, Ordering::Relaxed);
+    }
+
+    pub fn read_read_throttle_utilization(&self) -> u64 {
+        self.read_throttle_utilization.load(Ordering::Relaxed)
+    }
+
+    pub fn set_files_lagging(&self, files: u64) {
+        self.files_lagging.store(files, Ordering::Relaxed);
+    }
+
+    pub fn read_files_lagging(&self) -> u64 {
+        self.files_lagging.load(Ordering::Relaxed)
+    }
 }
 
+/// Memory stats of the process, as told by the allocator the agent is built with, and the
+/// memory saved by sharing the metadata values of lines
 pub struct Memory {
-    epoch_mib: epoch_mib,
-    active_mib: active_mib,
-    allocated_mib: allocated_mib,
-    resident_mib: resident_mib,
+    stats: Box<dyn MemoryStats>,
+    interned_values: AtomicU64,
+    interned_bytes: AtomicU64,
+    interning_saved: AtomicU64,
 }
 
 impl Memory {
     pub fn new() -> Self {
         Self {
-            epoch_mib: epoch::mib().unwrap(),
-            active_mib: active::mib().unwrap(),
-            allocated_mib: allocated::mib().unwrap(),
-            resident_mib: resident::mib().unwrap(),
+            stats: memory::default_stats(),
+            interned_values: AtomicU64::new(0),
+            interned_bytes: AtomicU64::new(0),
+            interning_saved: AtomicU64::new(0),
         }
     }
 
-    pub fn reset(&self) {}
+    /// The interned values are gauges, they aren't reset
+    pub fn reset(&self) {
+        self.interning_saved.store(0, Ordering::Relaxed);
+    }
+
+    pub fn set_interned(&self, values: u64, bytes: u64) {
+        self.interned_values.store(values, Ordering::Relaxed);
+        self.interned_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Bytes a line shared with the interned copy of a value rather than allocating its own
+    pub fn add_interning_saved(&self, bytes: u64) {
+        self.interning_saved.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn read_interned_values(&self) -> u64 {
+        self.interned_values.load(Ordering::Relaxed)
+    }
+
+    pub fn read_interned_bytes(&self) -> u64 {
+        self.interned_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn read_interning_saved(&self) -> u64 {
+        self.interning_saved.load(Ordering::Relaxed)
+    }
 
-    pub fn read_active(&self) -> u64 {
-        self.epoch_mib.advance().unwrap();
-        self.active_mib.read().unwrap() as u64
+    pub fn read_active(&self) -> Option<u64> {
+        self.stats.active()
     }
 
-    pub fn read_allocated(&self) -> u64 {
-        self.epoch_mib.advance().unwrap();
-        self.allocated_mib.read().unwrap() as u64
+    pub fn read_allocated(&self) -> Option<u64> {
+        self.stats.allocated()
     }
 
-    pub fn read_resident(&self) -> u64 {
-        self.epoch_mib.advance().unwrap();
-        self.resident_mib.read().unwrap() as u64
+    pub fn read_resident(&self) -> Option<u64> {
+        self.stats.resident()
     }
 }
 
@@ -256,12 +434,55 @@ impl Default for Memory {
     }
 }
 
-#[derive(Default)]
+/// Upper bounds of the buckets batch sizes are counted in
+const BATCH_BYTES_BOUNDS: &[u64] = &[
+    16 * 1024,
+    64 * 1024,
+    256 * 1024,
+    512 * 1024,
+    1024 * 1024,
+    2 * 1024 * 1024,
+    4 * 1024 * 1024,
+];
+const BATCH_LINES_BOUNDS: &[u64] = &[10, 100, 500, 1_000, 5_000, 10_000];
+
 pub struct Http {
     requests: AtomicU64,
     limit_hits: AtomicU64,
     request_size: AtomicU64,
     retries: AtomicU64,
+    recycled_connections: AtomicU64,
+    /// Requests that took longer than the slow request threshold
+    slow_requests: AtomicU64,
+    /// Requests the ingest API refused, by the class of its response
+    rejected_invalid_key: AtomicU64,
+    rejected_quota: AtomicU64,
+    rejected_malformed: AtomicU64,
+    rejected_server: AtomicU64,
+    rejected_other: AtomicU64,
+    retries_exhausted: AtomicU64,
+    validation_failures: AtomicU64,
+    /// Bytes and number of the requests stored on disk to be retried
+    spool_bytes: AtomicU64,
+    spool_files: AtomicU64,
+    spool_dropped: AtomicU64,
+    spool_expired: AtomicU64,
+    /// How long ago the last stored request replayed first failed, in milliseconds
+    backlog_age: AtomicU64,
+    /// Time spent serializing lines into batches, in microseconds
+    serialize_time: AtomicU64,
+    /// Bytes of the batches compressed to sample the compression ratio, before and after, and
+    /// the time spent compressing them in microseconds
+    compression_sampled_bytes: AtomicU64,
+    compression_compressed_bytes: AtomicU64,
+    compression_time: AtomicU64,
+    /// Jobs submitted to the codec pool that no thread picked up yet
+    codec_queue_depth: AtomicU64,
+    /// Lines read from files and lines of them acknowledged, over the runs of the agent
+    delivery_read: AtomicU64,
+    delivery_acknowledged: AtomicU64,
+    batch_bytes: Histogram,
+    batch_lines: Histogram,
 }
 
 impl Http {
@@ -271,6 +492,29 @@ impl Http {
             limit_hits: AtomicU64::new(0),
             request_size: AtomicU64::new(0),
             retries: AtomicU64::new(0),
+            recycled_connections: AtomicU64::new(0),
+            slow_requests: AtomicU64::new(0),
+            rejected_invalid_key: AtomicU64::new(0),
+            rejected_quota: AtomicU64::new(0),
+            rejected_malformed: AtomicU64::new(0),
+            rejected_server: AtomicU64::new(0),
+            rejected_other: AtomicU64::new(0),
+            retries_exhausted: AtomicU64::new(0),
+            validation_failures: AtomicU64::new(0),
+            spool_bytes: AtomicU64::new(0),
+            spool_files: AtomicU64::new(0),
+            spool_dropped: AtomicU64::new(0),
+            spool_expired: AtomicU64::new(0),
+            backlog_age: AtomicU64::new(0),
+            serialize_time: AtomicU64::new(0),
+            compression_sampled_bytes: AtomicU64::new(0),
+            compression_compressed_bytes: AtomicU64::new(0),
+            compression_time: AtomicU64::new(0),
+            codec_queue_depth: AtomicU64::new(0),
+            delivery_read: AtomicU64::new(0),
+            delivery_acknowledged: AtomicU64::new(0),
+            batch_bytes: Histogram::new(BATCH_BYTES_BOUNDS),
+            batch_lines: Histogram::new(BATCH_LINES_BOUNDS),
         }
     }
 
@@ -279,6 +523,24 @@ impl Http {
         self.limit_hits.store(0, Ordering::Relaxed);
         self.request_size.store(0, Ordering::Relaxed);
         self.retries.store(0, Ordering::Relaxed);
+        self.recycled_connections.store(0, Ordering::Relaxed);
+        self.slow_requests.store(0, Ordering::Relaxed);
+        self.rejected_invalid_key.store(0, Ordering::Relaxed);
+        self.rejected_quota.store(0, Ordering::Relaxed);
+        self.rejected_malformed.store(0, Ordering::Relaxed);
+        self.rejected_server.store(0, Ordering::Relaxed);
+        self.rejected_other.store(0, Ordering::Relaxed);
+        self.retries_exhausted.store(0, Ordering::Relaxed);
+        self.validation_failures.store(0, Ordering::Relaxed);
+        self.spool_dropped.store(0, Ordering::Relaxed);
+        self.spool_expired.store(0, Ordering::Relaxed);
+        self.serialize_time.store(0, Ordering::Relaxed);
+        self.compression_sampled_bytes.store(0, Ordering::Relaxed);
+        self.compression_compressed_bytes
+            .store(0, Ordering::Relaxed);
+        self.compression_time.store(0, Ordering::Relaxed);
+        self.batch_bytes.reset();
+        self.batch_lines.reset();
     }
 
     pub fn increment_requests(&self) {
@@ -312,6 +574,211 @@ impl Http {
     pub fn read_retries(&self) -> u64 {
         self.retries.load(Ordering::Relaxed)
     }
+
+    pub fn increment_recycled_connections(&self) {
+        self.recycled_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_recycled_connections(&self) -> u64 {
+        self.recycled_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_slow_requests(&self) {
+        self.slow_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_slow_requests(&self) -> u64 {
+        self.slow_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_rejected_invalid_key(&self) {
+        self.rejected_invalid_key.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_rejected_invalid_key(&self) -> u64 {
+        self.rejected_invalid_key.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_rejected_quota(&self) {
+        self.rejected_quota.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_rejected_quota(&self) -> u64 {
+        self.rejected_quota.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_rejected_malformed(&self) {
+        self.rejected_malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_rejected_malformed(&self) -> u64 {
+        self.rejected_malformed.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_rejected_server(&self) {
+        self.rejected_server.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_rejected_server(&self) -> u64 {
+        self.rejected_server.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_rejected_other(&self) {
+        self.rejected_other.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_rejected_other(&self) -> u64 {
+        self.rejected_other.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_retries_exhausted(&self) {
+        self.retries_exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_retries_exhausted(&self) -> u64 {
+        self.retries_exhausted.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_validation_failures(&self) {
+        self.validation_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_validation_failures(&self) -> u64 {
+        self.validation_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn set_spool_usage(&self, bytes: u64, files: u64) {
+        self.spool_bytes.store(bytes, Ordering::Relaxed);
+        self.spool_files.store(files, Ordering::Relaxed);
+    }
+
+    pub fn read_spool_bytes(&self) -> u64 {
+        self.spool_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn read_spool_files(&self) -> u64 {
+        self.spool_files.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_spool_dropped(&self) {
+        self.spool_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_spool_dropped(&self) -> u64 {
+        self.spool_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_spool_expired(&self) {
+        self.spool_expired.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_spool_expired(&self) -> u64 {
+        self.spool_expired.load(Ordering::Relaxed)
+    }
+
+    pub fn set_backlog_age(&self, millis: u64) {
+        self.backlog_age.store(millis, Ordering::Relaxed);
+    }
+
+    pub fn read_backlog_age(&self) -> u64 {
+        self.backlog_age.load(Ordering::Relaxed)
+    }
+
+    pub fn add_serialize_time(&self, micros: u64) {
+        self.serialize_time.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn read_serialize_time(&self) -> u64 {
+        self.serialize_time.load(Ordering::Relaxed)
+    }
+
+    pub fn add_compression_sample(&self, bytes: u64, compressed: u64, micros: u64) {
+        self.compression_sampled_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+        self.compression_compressed_bytes
+            .fetch_add(compressed, Ordering::Relaxed);
+        self.compression_time.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn read_compression_sampled_bytes(&self) -> u64 {
+        self.compression_sampled_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn read_compression_compressed_bytes(&self) -> u64 {
+        self.compression_compressed_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn read_compression_time(&self) -> u64 {
+        self.compression_time.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_codec_queue(&self) {
+        self.codec_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement_codec_queue(&self) {
+        self.codec_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn read_codec_queue_depth(&self) -> u64 {
+        self.codec_queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn set_delivery(&self, read: u64, acknowledged: u64) {
+        self.delivery_read.store(read, Ordering::Relaxed);
+        self.delivery_acknowledged
+            .store(acknowledged, Ordering::Relaxed);
+    }
+
+    pub fn read_delivery_read(&self) -> u64 {
+        self.delivery_read.load(Ordering::Relaxed)
+    }
+
+    pub fn read_delivery_acknowledged(&self) -> u64 {
+        self.delivery_acknowledged.load(Ordering::Relaxed)
+    }
+
+    /// Records the size of a batch sent to the ingest API
+    pub fn observe_batch(&self, bytes: u64, lines: u64) {
+        self.batch_bytes.observe(bytes);
+        self.batch_lines.observe(lines);
+    }
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts values in buckets by upper bound, values above every bound go in a last bucket
+pub struct Histogram {
+    bounds: &'static [u64],
+    buckets: Vec<AtomicU64>,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
 }
 
 #[derive(Default)]
@@ -322,6 +789,11 @@ pub struct K8s {
     deletes: AtomicU64,
     events: AtomicU64,
     notifies: AtomicU64,
+    suppressed_events: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    cache_evictions: AtomicU64,
+    metadata_timeouts: AtomicU64,
 }
 
 impl K8s {
@@ -333,6 +805,11 @@ impl K8s {
             deletes: AtomicU64::new(0),
             events: AtomicU64::new(0),
             notifies: AtomicU64::new(0),
+            suppressed_events: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            cache_evictions: AtomicU64::new(0),
+            metadata_timeouts: AtomicU64::new(0),
         }
     }
 
@@ -343,6 +820,11 @@ impl K8s {
         self.deletes.store(0, Ordering::Relaxed);
         self.events.store(0, Ordering::Relaxed);
         self.notifies.store(0, Ordering::Relaxed);
+        self.suppressed_events.store(0, Ordering::Relaxed);
+        self.cache_hits.store(0, Ordering::Relaxed);
+        self.cache_misses.store(0, Ordering::Relaxed);
+        self.cache_evictions.store(0, Ordering::Relaxed);
+        self.metadata_timeouts.store(0, Ordering::Relaxed);
     }
 
     pub fn increment_lines(&self) {
@@ -392,12 +874,60 @@ impl K8s {
     pub fn read_notifies(&self) -> u64 {
         self.notifies.load(Ordering::Relaxed)
     }
+
+    pub fn increment_suppressed_events(&self) {
+        self.suppressed_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_suppressed_events(&self) -> u64 {
+        self.suppressed_events.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_cache_hits(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_cache_misses(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_cache_evictions(&self) {
+        self.cache_evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_cache_evictions(&self) -> u64 {
+        self.cache_evictions.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_metadata_timeouts(&self) {
+        self.metadata_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_metadata_timeouts(&self) -> u64 {
+        self.metadata_timeouts.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Default)]
 pub struct Journald {
     lines: AtomicU64,
     bytes: AtomicU64,
+    /// How far behind the newest record the last one read was, in seconds
+    lag: AtomicU64,
+    /// Records dropped by unit over its rate limit
+    suppressed: Mutex<BTreeMap<String, u64>>,
+    binary_messages: AtomicU64,
+    oversized_messages: AtomicU64,
+    /// Entries skipped because they couldn't be read from the journal files
+    corrupted_entries: AtomicU64,
 }
 
 impl Journald {
@@ -405,12 +935,21 @@ impl Journald {
         Self {
             lines: AtomicU64::new(0),
             bytes: AtomicU64::new(0),
+            lag: AtomicU64::new(0),
+            suppressed: Mutex::new(BTreeMap::new()),
+            binary_messages: AtomicU64::new(0),
+            oversized_messages: AtomicU64::new(0),
+            corrupted_entries: AtomicU64::new(0),
         }
     }
 
     pub fn reset(&self) {
         self.lines.store(0, Ordering::Relaxed);
         self.bytes.store(0, Ordering::Relaxed);
+        self.suppressed.lock().unwrap().clear();
+        self.binary_messages.store(0, Ordering::Relaxed);
+        self.oversized_messages.store(0, Ordering::Relaxed);
+        self.corrupted_entries.store(0, Ordering::Relaxed);
     }
 
     pub fn increment_lines(&self) {
@@ -428,4 +967,1029 @@ impl Journald {
     pub fn read_bytes(&self) -> u64 {
         self.bytes.load(Ordering::Relaxed)
     }
+
+    pub fn set_lag(&self, secs: u64) {
+        self.lag.store(secs, Ordering::Relaxed);
+    }
+
+    pub fn read_lag(&self) -> u64 {
+        self.lag.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_suppressed(&self, unit: &str) {
+        let mut suppressed = self.suppressed.lock().unwrap();
+        match suppressed.get_mut(unit) {
+            Some(count) => *count += 1,
+            None => {
+                suppressed.insert(unit.to_string(), 1);
+            }
+        }
+    }
+
+    pub fn read_suppressed(&self) -> BTreeMap<String, u64> {
+        self.suppressed.lock().unwrap().clone()
+    }
+
+    pub fn increment_binary_messages(&self) {
+        self.binary_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_binary_messages(&self) -> u64 {
+        self.binary_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_oversized_messages(&self) {
+        self.oversized_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_oversized_messages(&self) -> u64 {
+        self.oversized_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_corrupted_entries(&self) {
+        self.corrupted_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_corrupted_entries(&self) -> u64 {
+        self.corrupted_entries.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Auditd {
+    events: AtomicU64,
+}
+
+impl Auditd {
+    pub fn new() -> Self {
+        Self {
+            events: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.events.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_events(&self) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_events(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Docker {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Docker {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Kubelet {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Kubelet {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Receiver {
+    requests: AtomicU64,
+    lines: AtomicU64,
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            lines: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.lines.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_requests(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Exec {
+    lines: AtomicU64,
+    restarts: AtomicU64,
+}
+
+impl Exec {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            restarts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.restarts.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_restarts(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Kafka {
+    records: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Kafka {
+    pub fn new() -> Self {
+        Self {
+            records: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.records.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_records(&self) {
+        self.records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_records(&self) -> u64 {
+        self.records.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Archive {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+    objects: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+    lost_lines: AtomicU64,
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            objects: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            lost_lines: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.objects.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        self.lost_lines.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_objects(&self) {
+        self.objects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_objects(&self) -> u64 {
+        self.objects.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_failures(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Lines of the objects given up on once their uploads ran out of attempts
+    pub fn add_lost_lines(&self, num: u64) {
+        self.lost_lines.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_lost_lines(&self) -> u64 {
+        self.lost_lines.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Elasticsearch {
+    lines: AtomicU64,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Elasticsearch {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.requests.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add_lines(&self, num: u64) {
+        self.lines.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_requests(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn add_failures(&self, num: u64) {
+        self.failures.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Otlp {
+    lines: AtomicU64,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Otlp {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.requests.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add_lines(&self, num: u64) {
+        self.lines.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_requests(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn add_failures(&self, num: u64) {
+        self.failures.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Webhook {
+    lines: AtomicU64,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Webhook {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.requests.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add_lines(&self, num: u64) {
+        self.lines.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_requests(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn add_failures(&self, num: u64) {
+        self.failures.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Syslog {
+    lines: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl Syslog {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        self.reconnects.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_failures(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_reconnects(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct UnixSocket {
+    lines: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl UnixSocket {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        self.reconnects.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add_lines(&self, num: u64) {
+        self.lines.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_failures(&self, num: u64) {
+        self.failures.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_reconnects(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Anonymizer {
+    lines: AtomicU64,
+    addresses: AtomicU64,
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Self {
+            lines: AtomicU64::new(0),
+            addresses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.addresses.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_addresses(&self, num: u64) {
+        self.addresses.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_addresses(&self) -> u64 {
+        self.addresses.load(Ordering::Relaxed)
+    }
+}
+
+/// Lines each shadow rule would have dropped or redacted
+#[derive(Default)]
+pub struct Shadow {
+    matches: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Shadow {
+    pub fn new() -> Self {
+        Self {
+            matches: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.matches.lock().unwrap().clear();
+    }
+
+    pub fn increment_matches(&self, rule: &str) {
+        let mut matches = self.matches.lock().unwrap();
+        match matches.get_mut(rule) {
+            Some(count) => *count += 1,
+            None => {
+                matches.insert(rule.to_string(), 1);
+            }
+        }
+    }
+
+    pub fn read_matches(&self) -> BTreeMap<String, u64> {
+        self.matches.lock().unwrap().clone()
+    }
+}
+
+#[derive(Default)]
+pub struct Watchdog {
+    stalls: AtomicU64,
+    /// Stages currently stalled, this is a state rather than a counter so it isn't reset
+    stalled: Mutex<Vec<&'static str>>,
+    /// Longest delay of each stage, in milliseconds
+    max_delays: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self {
+            stalls: AtomicU64::new(0),
+            stalled: Mutex::new(Vec::new()),
+            max_delays: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.stalls.store(0, Ordering::Relaxed);
+        self.max_delays.lock().unwrap().clear();
+    }
+
+    pub fn increment_stalls(&self) {
+        self.stalls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+
+    pub fn set_stalled(&self, stages: Vec<&'static str>) {
+        *self.stalled.lock().unwrap() = stages;
+    }
+
+    pub fn read_stalled(&self) -> Vec<&'static str> {
+        self.stalled.lock().unwrap().clone()
+    }
+
+    pub fn observe_delay(&self, stage: &'static str, millis: u64) {
+        let mut max_delays = self.max_delays.lock().unwrap();
+        let max = max_delays.entry(stage).or_insert(0);
+        *max = (*max).max(millis);
+    }
+
+    pub fn read_max_delays(&self) -> BTreeMap<&'static str, u64> {
+        self.max_delays.lock().unwrap().clone()
+    }
+}
+
+/// Lines and bytes shipped from each file, or app for lines without a file, of which the
+/// noisiest are reported
+pub struct Sources {
+    /// How many sources are reported, none are counted when 0
+    top: AtomicUsize,
+    stats: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self {
+            top: AtomicUsize::new(10),
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    pub fn set_top(&self, top: usize) {
+        self.top.store(top, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, source: &str, bytes: u64) {
+        if self.top.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        let (lines, total) = match stats.get_mut(source) {
+            Some(stats) => stats,
+            None => stats.entry(source.to_string()).or_default(),
+        };
+        *lines += 1;
+        *total += bytes;
+    }
+
+    /// The sources with the most bytes, with their lines and bytes
+    pub fn read_top(&self) -> Vec<(String, u64, u64)> {
+        let mut top: Vec<_> = self
+            .stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(source, (lines, bytes))| (source.clone(), *lines, *bytes))
+            .collect();
+        top.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(self.top.load(Ordering::Relaxed));
+        top
+    }
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+pub struct Checkpoints {
+    written: AtomicU64,
+    /// Longest time the offsets of shipped lines waited to be written, in milliseconds
+    max_lag: AtomicU64,
+    /// Lines skipped after a restart for having been shipped right before it
+    restart_duplicates: AtomicU64,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Self {
+            written: AtomicU64::new(0),
+            max_lag: AtomicU64::new(0),
+            restart_duplicates: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.written.store(0, Ordering::Relaxed);
+        self.max_lag.store(0, Ordering::Relaxed);
+        self.restart_duplicates.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_written(&self) {
+        self.written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    pub fn observe_lag(&self, millis: u64) {
+        self.max_lag.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    pub fn read_max_lag(&self) -> u64 {
+        self.max_lag.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_restart_duplicates(&self) {
+        self.restart_duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_restart_duplicates(&self) -> u64 {
+        self.restart_duplicates.load(Ordering::Relaxed)
+    }
+}
+
+/// The connections of the sinks to their destinations
+pub struct Connections {
+    opened: AtomicU64,
+    open: AtomicU64,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self {
+            opened: AtomicU64::new(0),
+            open: AtomicU64::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.opened.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_opened(&self) {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_opened(&self) -> u64 {
+        self.opened.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_open(&self) {
+        self.open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement_open(&self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn read_open(&self) -> u64 {
+        self.open.load(Ordering::Relaxed)
+    }
+}
+
+/// Lines and requests of each ingestion key route, by the name of the route
+#[derive(Default)]
+pub struct Routes {
+    stats: Mutex<BTreeMap<String, RouteStats>>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct RouteStats {
+    lines: u64,
+    /// Lines dropped as the queue of the route was full
+    dropped: u64,
+    requests: u64,
+    request_bytes: u64,
+    /// Requests that failed, whether they are retried or not
+    failures: u64,
+    spool_bytes: u64,
+    spool_files: u64,
+}
+
+impl Routes {
+    pub fn new() -> Self {
+        Self {
+            stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Resets the counters, the spool usage of the routes is kept
+    pub fn reset(&self) {
+        for stats in self.stats.lock().unwrap().values_mut() {
+            *stats = RouteStats {
+                spool_bytes: stats.spool_bytes,
+                spool_files: stats.spool_files,
+                ..RouteStats::default()
+            };
+        }
+    }
+
+    fn update(&self, route: &str, update: impl FnOnce(&mut RouteStats)) {
+        let mut stats = self.stats.lock().unwrap();
+        match stats.get_mut(route) {
+            Some(stats) => update(stats),
+            None => update(stats.entry(route.to_string()).or_default()),
+        }
+    }
+
+    pub fn increment_lines(&self, route: &str) {
+        self.update(route, |stats| stats.lines += 1);
+    }
+
+    pub fn increment_dropped(&self, route: &str) {
+        self.update(route, |stats| stats.dropped += 1);
+    }
+
+    pub fn add_request(&self, route: &str, bytes: u64) {
+        self.update(route, |stats| {
+            stats.requests += 1;
+            stats.request_bytes += bytes;
+        });
+    }
+
+    pub fn increment_failures(&self, route: &str) {
+        self.update(route, |stats| stats.failures += 1);
+    }
+
+    pub fn set_spool_usage(&self, route: &str, bytes: u64, files: u64) {
+        self.update(route, |stats| {
+            stats.spool_bytes = bytes;
+            stats.spool_files = files;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_counts_values_by_bucket() {
+        let histogram = Histogram::new(&[10, 100]);
+        for value in &[0, 10, 11, 100, 1_000, 5_000] {
+            histogram.observe(*value);
+        }
+        let read = histogram.read();
+        assert_eq!(read.get("le_10"), Some(2));
+        assert_eq!(read.get("le_100"), Some(2));
+        assert_eq!(read.get("inf"), Some(2));
+        assert_eq!(
+            serde_json::to_string(&read).unwrap(),
+            r#"{"le_10":2,"le_100":2,"inf":2}"#
+        );
+
+        histogram.reset();
+        assert_eq!(histogram.read().get("inf"), Some(0));
+    }
+
+    #[test]
+    fn sources_report_the_noisiest() {
+        let sources = Sources::new();
+        sources.set_top(2);
+        sources.record("/var/log/a.log", 10);
+        sources.record("/var/log/b.log", 500);
+        sources.record("/var/log/a.log", 10);
+        sources.record("sshd", 100);
+        assert_eq!(
+            sources.read_top(),
+            vec![
+                ("/var/log/b.log".to_string(), 1, 500),
+                ("sshd".to_string(), 1, 100),
+            ]
+        );
+
+        sources.reset();
+        assert!(sources.read_top().is_empty());
+        sources.set_top(0);
+        sources.record("/var/log/a.log", 10);
+        assert!(sources.read_top().is_empty());
+    }
+
+    #[test]
+    fn route_counters_are_reset_but_not_the_spool_usage() {
+        let routes = Routes::new();
+        routes.increment_lines("payments");
+        routes.add_request("payments", 512);
+        routes.set_spool_usage("payments", 1024, 2);
+        routes.increment_dropped("search");
+        let snapshot = routes.snapshot();
+        assert_eq!(snapshot["payments"].lines, 1);
+        assert_eq!(snapshot["payments"].request_bytes, 512);
+        assert_eq!(snapshot["search"].dropped, 1);
+
+        routes.reset();
+        let snapshot = routes.snapshot();
+        assert_eq!(snapshot["payments"].lines, 0);
+        assert_eq!(snapshot["payments"].requests, 0);
+        assert_eq!(snapshot["payments"].spool_bytes, 1024);
+        assert_eq!(snapshot["payments"].spool_files, 2);
+    }
 }
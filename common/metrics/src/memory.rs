@@ -0,0 +1,173 @@
+#[cfg(feature = "jemalloc")]
+use jemalloc_ctl::stats::{active, active_mib, allocated, allocated_mib, resident, resident_mib};
+#[cfg(feature = "jemalloc")]
+use jemalloc_ctl::{epoch, epoch_mib};
+
+/// Where the memory stats of the process are read from, the allocator the agent is built with
+/// when it keeps them. All in bytes, `None` when the source doesn't report it.
+pub trait MemoryStats: Send + Sync {
+    /// Memory in the pages the allocator handed out
+    fn active(&self) -> Option<u64>;
+    /// Memory allocated by the agent
+    fn allocated(&self) -> Option<u64>;
+    /// Physical memory mapped by the process
+    fn resident(&self) -> Option<u64>;
+}
+
+/// The stats of the allocator the agent is built with, jemalloc taking precedence
+#[cfg(feature = "jemalloc")]
+pub(crate) fn default_stats() -> Box<dyn MemoryStats> {
+    Box::new(Jemalloc::new())
+}
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+pub(crate) fn default_stats() -> Box<dyn MemoryStats> {
+    Box::new(Mimalloc)
+}
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub(crate) fn default_stats() -> Box<dyn MemoryStats> {
+    Box::new(System)
+}
+
+#[cfg(feature = "jemalloc")]
+pub struct Jemalloc {
+    epoch_mib: epoch_mib,
+    active_mib: active_mib,
+    allocated_mib: allocated_mib,
+    resident_mib: resident_mib,
+}
+
+#[cfg(feature = "jemalloc")]
+impl Jemalloc {
+    pub fn new() -> Self {
+        Self {
+            epoch_mib: epoch::mib().unwrap(),
+            active_mib: active::mib().unwrap(),
+            allocated_mib: allocated::mib().unwrap(),
+            resident_mib: resident::mib().unwrap(),
+        }
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+impl Default for Jemalloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+impl MemoryStats for Jemalloc {
+    fn active(&self) -> Option<u64> {
+        self.epoch_mib.advance().ok()?;
+        self.active_mib.read().ok().map(|bytes| bytes as u64)
+    }
+
+    fn allocated(&self) -> Option<u64> {
+        self.epoch_mib.advance().ok()?;
+        self.allocated_mib.read().ok().map(|bytes| bytes as u64)
+    }
+
+    fn resident(&self) -> Option<u64> {
+        self.epoch_mib.advance().ok()?;
+        self.resident_mib.read().ok().map(|bytes| bytes as u64)
+    }
+}
+
+/// mimalloc only reports the memory of the whole process, the memory it committed is counted
+/// as active and allocations aren't tracked
+#[cfg(feature = "mimalloc")]
+pub struct Mimalloc;
+
+#[cfg(feature = "mimalloc")]
+impl Mimalloc {
+    /// The current resident and committed sizes of the process
+    fn process_info() -> (u64, u64) {
+        let (mut elapsed, mut user, mut system) = (0, 0, 0);
+        let (mut rss, mut peak_rss, mut commit, mut peak_commit, mut faults) = (0, 0, 0, 0, 0);
+        // Safe as every pointer is to a local that outlives the call
+        unsafe {
+            libmimalloc_sys::mi_process_info(
+                &mut elapsed,
+                &mut user,
+                &mut system,
+                &mut rss,
+                &mut peak_rss,
+                &mut commit,
+                &mut peak_commit,
+                &mut faults,
+            );
+        }
+        (rss as u64, commit as u64)
+    }
+}
+
+#[cfg(feature = "mimalloc")]
+impl MemoryStats for Mimalloc {
+    fn active(&self) -> Option<u64> {
+        Some(Mimalloc::process_info().1)
+    }
+
+    fn allocated(&self) -> Option<u64> {
+        None
+    }
+
+    fn resident(&self) -> Option<u64> {
+        Some(Mimalloc::process_info().0)
+    }
+}
+
+/// The system allocator doesn't expose its stats, so only the resident size of the process
+/// is reported, as read from /proc on Linux
+pub struct System;
+
+impl MemoryStats for System {
+    fn active(&self) -> Option<u64> {
+        None
+    }
+
+    fn allocated(&self) -> Option<u64> {
+        None
+    }
+
+    #[cfg(target_os = "linux")]
+    fn resident(&self) -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        parse_vm_rss(&status)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn resident(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The resident size in the `VmRSS` line of a /proc status file
+#[cfg(target_os = "linux")]
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_reports_the_resident_size() {
+        let status = "Name:\tlogdna-agent\nVmPeak:\t  20480 kB\nVmRSS:\t   1024 kB\n";
+        assert_eq!(parse_vm_rss(status), Some(1024 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tlogdna-agent\n"), None);
+
+        assert!(System.resident().unwrap() > 0);
+        assert_eq!(System.allocated(), None);
+    }
+}
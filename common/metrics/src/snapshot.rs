@@ -0,0 +1,650 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+
+use crate::{
+    Anonymizer, Archive, Auditd, Checkpoints, Connections, Docker, Elasticsearch, Exec, Fs,
+    Histogram, Http, Journald, K8s, Kafka, Kubelet, Memory, Metrics, Otlp, Receiver, Routes,
+    Shadow, Sources, Syslog, UnixSocket, Watchdog, Webhook,
+};
+
+/// The value of every metric at a point in time, serialized as the periodic metrics log
+/// line and served to tooling as JSON
+#[derive(Debug, Serialize)]
+pub struct MetricsSnapshot {
+    pub fs: FsSnapshot,
+    pub memory: MemorySnapshot,
+    pub ingest: IngestSnapshot,
+    pub k8s: K8sSnapshot,
+    pub journald: JournaldSnapshot,
+    pub auditd: AuditdSnapshot,
+    pub docker: DockerSnapshot,
+    pub kubelet: KubeletSnapshot,
+    pub receiver: ReceiverSnapshot,
+    pub exec: ExecSnapshot,
+    pub kafka: KafkaSnapshot,
+    pub archive: ArchiveSnapshot,
+    pub elasticsearch: ElasticsearchSnapshot,
+    pub otlp: OtlpSnapshot,
+    pub webhook: WebhookSnapshot,
+    pub syslog: SyslogSnapshot,
+    pub unix_socket: UnixSocketSnapshot,
+    pub anonymizer: AnonymizerSnapshot,
+    pub shadow: ShadowSnapshot,
+    pub watchdog: WatchdogSnapshot,
+    pub sources: Vec<SourceSnapshot>,
+    pub checkpoints: CheckpointsSnapshot,
+    pub connections: ConnectionsSnapshot,
+    pub routes: BTreeMap<String, RouteSnapshot>,
+}
+
+impl MetricsSnapshot {
+    pub(crate) fn take() -> Self {
+        MetricsSnapshot {
+            fs: Metrics::fs().snapshot(),
+            memory: Metrics::memory().snapshot(),
+            ingest: Metrics::http().snapshot(),
+            k8s: Metrics::k8s().snapshot(),
+            journald: Metrics::journald().snapshot(),
+            auditd: Metrics::auditd().snapshot(),
+            docker: Metrics::docker().snapshot(),
+            kubelet: Metrics::kubelet().snapshot(),
+            receiver: Metrics::receiver().snapshot(),
+            exec: Metrics::exec().snapshot(),
+            kafka: Metrics::kafka().snapshot(),
+            archive: Metrics::archive().snapshot(),
+            elasticsearch: Metrics::elasticsearch().snapshot(),
+            otlp: Metrics::otlp().snapshot(),
+            webhook: Metrics::webhook().snapshot(),
+            syslog: Metrics::syslog().snapshot(),
+            unix_socket: Metrics::unix_socket().snapshot(),
+            anonymizer: Metrics::anonymizer().snapshot(),
+            shadow: Metrics::shadow().snapshot(),
+            watchdog: Metrics::watchdog().snapshot(),
+            sources: Metrics::sources().snapshot(),
+            checkpoints: Metrics::checkpoints().snapshot(),
+            connections: Metrics::connections().snapshot(),
+            routes: Metrics::routes().snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsSnapshot {
+    pub events: u64,
+    pub creates: u64,
+    pub deletes: u64,
+    pub writes: u64,
+    pub lines: u64,
+    pub bytes: u64,
+    pub partial_reads: u64,
+    pub coalesced_events: u64,
+    pub mapped_reads: u64,
+    pub mapped_bytes: u64,
+    pub read_throttle_utilization: u64,
+    pub files_lagging: u64,
+}
+
+impl Fs {
+    pub fn snapshot(&self) -> FsSnapshot {
+        FsSnapshot {
+            events: self.read_events(),
+            creates: self.read_creates(),
+            deletes: self.read_deletes(),
+            writes: self.read_writes(),
+            lines: self.read_lines(),
+            bytes: self.read_bytes(),
+            partial_reads: self.read_partial_reads(),
+            coalesced_events: self.read_coalesced_events(),
+            mapped_reads: self.read_mapped_reads(),
+            mapped_bytes: self.read_mapped_bytes(),
+            read_throttle_utilization: self.read_read_throttle_utilization(),
+            files_lagging: self.read_files_lagging(),
+        }
+    }
+}
+
+/// Allocator stats, the ones the allocator doesn't expose are null, and the metadata values
+/// shared by lines
+#[derive(Debug, Serialize)]
+pub struct MemorySnapshot {
+    pub active: Option<u64>,
+    pub allocated: Option<u64>,
+    pub resident: Option<u64>,
+    pub interned_values: u64,
+    pub interned_bytes: u64,
+    /// Bytes lines didn't allocate over the interval as they shared the interned values
+    pub interning_saved_bytes: u64,
+}
+
+impl Memory {
+    pub fn snapshot(&self) -> MemorySnapshot {
+        MemorySnapshot {
+            active: self.read_active(),
+            allocated: self.read_allocated(),
+            resident: self.read_resident(),
+            interned_values: self.read_interned_values(),
+            interned_bytes: self.read_interned_bytes(),
+            interning_saved_bytes: self.read_interning_saved(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestSnapshot {
+    pub requests: u64,
+    pub throughput: u64,
+    pub rate_limits: u64,
+    pub retries: u64,
+    pub recycled_connections: u64,
+    pub slow_requests: u64,
+    pub rejected_invalid_key: u64,
+    pub rejected_quota: u64,
+    pub rejected_malformed: u64,
+    pub rejected_server: u64,
+    pub rejected_other: u64,
+    pub retries_exhausted: u64,
+    pub validation_failures: u64,
+    pub spool_bytes: u64,
+    pub spool_files: u64,
+    pub spool_dropped: u64,
+    pub spool_expired: u64,
+    pub backlog_age_ms: u64,
+    pub serialize_us: u64,
+    pub compression_sampled_bytes: u64,
+    pub compression_compressed_bytes: u64,
+    pub compression_us: u64,
+    /// Compressed bytes of the sampled batches per byte before compression, None without
+    /// samples
+    pub compression_ratio: Option<f64>,
+    /// Jobs waiting for a thread of the codec pool
+    pub codec_queue_depth: u64,
+    pub delivery_read: u64,
+    pub delivery_acknowledged: u64,
+    /// Lines read that weren't acknowledged, on their way or lost
+    pub delivery_gap: u64,
+    pub batch_bytes: HistogramSnapshot,
+    pub batch_lines: HistogramSnapshot,
+}
+
+impl Http {
+    pub fn snapshot(&self) -> IngestSnapshot {
+        IngestSnapshot {
+            requests: self.read_requests(),
+            throughput: self.read_request_size(),
+            rate_limits: self.read_limit_hits(),
+            retries: self.read_retries(),
+            recycled_connections: self.read_recycled_connections(),
+            slow_requests: self.read_slow_requests(),
+            rejected_invalid_key: self.read_rejected_invalid_key(),
+            rejected_quota: self.read_rejected_quota(),
+            rejected_malformed: self.read_rejected_malformed(),
+            rejected_server: self.read_rejected_server(),
+            rejected_other: self.read_rejected_other(),
+            retries_exhausted: self.read_retries_exhausted(),
+            validation_failures: self.read_validation_failures(),
+            spool_bytes: self.read_spool_bytes(),
+            spool_files: self.read_spool_files(),
+            spool_dropped: self.read_spool_dropped(),
+            spool_expired: self.read_spool_expired(),
+            backlog_age_ms: self.read_backlog_age(),
+            serialize_us: self.read_serialize_time(),
+            compression_sampled_bytes: self.read_compression_sampled_bytes(),
+            compression_compressed_bytes: self.read_compression_compressed_bytes(),
+            compression_us: self.read_compression_time(),
+            compression_ratio: match self.read_compression_sampled_bytes() {
+                0 => None,
+                sampled => Some(self.read_compression_compressed_bytes() as f64 / sampled as f64),
+            },
+            codec_queue_depth: self.read_codec_queue_depth(),
+            delivery_read: self.read_delivery_read(),
+            delivery_acknowledged: self.read_delivery_acknowledged(),
+            delivery_gap: self
+                .read_delivery_read()
+                .saturating_sub(self.read_delivery_acknowledged()),
+            batch_bytes: self.batch_bytes.read(),
+            batch_lines: self.batch_lines.read(),
+        }
+    }
+}
+
+/// The count of each bucket of a histogram, serialized as a map keyed by the upper bound of
+/// the bucket in increasing order
+#[derive(Debug, PartialEq, Eq)]
+pub struct HistogramSnapshot(pub Vec<(String, u64)>);
+
+impl HistogramSnapshot {
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, count)| *count)
+    }
+}
+
+impl Serialize for HistogramSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, count) in self.0.iter() {
+            map.serialize_entry(key, count)?;
+        }
+        map.end()
+    }
+}
+
+impl Histogram {
+    /// The count of each bucket, keyed by its upper bound
+    pub fn read(&self) -> HistogramSnapshot {
+        HistogramSnapshot(
+            self.buckets
+                .iter()
+                .enumerate()
+                .map(|(i, bucket)| {
+                    let key = match self.bounds.get(i) {
+                        Some(bound) => format!("le_{}", bound),
+                        None => "inf".to_string(),
+                    };
+                    (key, bucket.load(Ordering::Relaxed))
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct K8sSnapshot {
+    pub lines: u64,
+    pub polls: u64,
+    pub creates: u64,
+    pub deletes: u64,
+    pub events: u64,
+    pub notifies: u64,
+    pub suppressed_events: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub cache_evictions: u64,
+    pub metadata_timeouts: u64,
+}
+
+impl K8s {
+    pub fn snapshot(&self) -> K8sSnapshot {
+        K8sSnapshot {
+            lines: self.read_lines(),
+            polls: self.read_polls(),
+            creates: self.read_creates(),
+            deletes: self.read_deletes(),
+            events: self.read_events(),
+            notifies: self.read_notifies(),
+            suppressed_events: self.read_suppressed_events(),
+            cache_hits: self.read_cache_hits(),
+            cache_misses: self.read_cache_misses(),
+            cache_evictions: self.read_cache_evictions(),
+            metadata_timeouts: self.read_metadata_timeouts(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JournaldSnapshot {
+    pub lines: u64,
+    pub bytes: u64,
+    pub lag_secs: u64,
+    pub suppressed: BTreeMap<String, u64>,
+    pub binary_messages: u64,
+    pub oversized_messages: u64,
+    pub corrupted_entries: u64,
+}
+
+impl Journald {
+    pub fn snapshot(&self) -> JournaldSnapshot {
+        JournaldSnapshot {
+            lines: self.read_lines(),
+            bytes: self.read_bytes(),
+            lag_secs: self.read_lag(),
+            suppressed: self.read_suppressed(),
+            binary_messages: self.read_binary_messages(),
+            oversized_messages: self.read_oversized_messages(),
+            corrupted_entries: self.read_corrupted_entries(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditdSnapshot {
+    pub events: u64,
+}
+
+impl Auditd {
+    pub fn snapshot(&self) -> AuditdSnapshot {
+        AuditdSnapshot {
+            events: self.read_events(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DockerSnapshot {
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+impl Docker {
+    pub fn snapshot(&self) -> DockerSnapshot {
+        DockerSnapshot {
+            lines: self.read_lines(),
+            bytes: self.read_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct KubeletSnapshot {
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+impl Kubelet {
+    pub fn snapshot(&self) -> KubeletSnapshot {
+        KubeletSnapshot {
+            lines: self.read_lines(),
+            bytes: self.read_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReceiverSnapshot {
+    pub requests: u64,
+    pub lines: u64,
+}
+
+impl Receiver {
+    pub fn snapshot(&self) -> ReceiverSnapshot {
+        ReceiverSnapshot {
+            requests: self.read_requests(),
+            lines: self.read_lines(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExecSnapshot {
+    pub lines: u64,
+    pub restarts: u64,
+}
+
+impl Exec {
+    pub fn snapshot(&self) -> ExecSnapshot {
+        ExecSnapshot {
+            lines: self.read_lines(),
+            restarts: self.read_restarts(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct KafkaSnapshot {
+    pub records: u64,
+    pub bytes: u64,
+}
+
+impl Kafka {
+    pub fn snapshot(&self) -> KafkaSnapshot {
+        KafkaSnapshot {
+            records: self.read_records(),
+            bytes: self.read_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveSnapshot {
+    pub lines: u64,
+    pub bytes: u64,
+    pub objects: u64,
+    pub failures: u64,
+    pub dropped: u64,
+    pub lost_lines: u64,
+}
+
+impl Archive {
+    pub fn snapshot(&self) -> ArchiveSnapshot {
+        ArchiveSnapshot {
+            lines: self.read_lines(),
+            bytes: self.read_bytes(),
+            objects: self.read_objects(),
+            failures: self.read_failures(),
+            dropped: self.read_dropped(),
+            lost_lines: self.read_lost_lines(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ElasticsearchSnapshot {
+    pub lines: u64,
+    pub requests: u64,
+    pub failures: u64,
+    pub dropped: u64,
+}
+
+impl Elasticsearch {
+    pub fn snapshot(&self) -> ElasticsearchSnapshot {
+        ElasticsearchSnapshot {
+            lines: self.read_lines(),
+            requests: self.read_requests(),
+            failures: self.read_failures(),
+            dropped: self.read_dropped(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OtlpSnapshot {
+    pub lines: u64,
+    pub requests: u64,
+    pub failures: u64,
+    pub dropped: u64,
+}
+
+impl Otlp {
+    pub fn snapshot(&self) -> OtlpSnapshot {
+        OtlpSnapshot {
+            lines: self.read_lines(),
+            requests: self.read_requests(),
+            failures: self.read_failures(),
+            dropped: self.read_dropped(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookSnapshot {
+    pub lines: u64,
+    pub requests: u64,
+    pub failures: u64,
+    pub dropped: u64,
+}
+
+impl Webhook {
+    pub fn snapshot(&self) -> WebhookSnapshot {
+        WebhookSnapshot {
+            lines: self.read_lines(),
+            requests: self.read_requests(),
+            failures: self.read_failures(),
+            dropped: self.read_dropped(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyslogSnapshot {
+    pub lines: u64,
+    pub failures: u64,
+    pub dropped: u64,
+    pub reconnects: u64,
+}
+
+impl Syslog {
+    pub fn snapshot(&self) -> SyslogSnapshot {
+        SyslogSnapshot {
+            lines: self.read_lines(),
+            failures: self.read_failures(),
+            dropped: self.read_dropped(),
+            reconnects: self.read_reconnects(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnixSocketSnapshot {
+    pub lines: u64,
+    pub failures: u64,
+    pub dropped: u64,
+    pub reconnects: u64,
+}
+
+impl UnixSocket {
+    pub fn snapshot(&self) -> UnixSocketSnapshot {
+        UnixSocketSnapshot {
+            lines: self.read_lines(),
+            failures: self.read_failures(),
+            dropped: self.read_dropped(),
+            reconnects: self.read_reconnects(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnonymizerSnapshot {
+    pub lines: u64,
+    pub addresses: u64,
+}
+
+impl Anonymizer {
+    pub fn snapshot(&self) -> AnonymizerSnapshot {
+        AnonymizerSnapshot {
+            lines: self.read_lines(),
+            addresses: self.read_addresses(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShadowSnapshot {
+    pub matches: BTreeMap<String, u64>,
+}
+
+impl Shadow {
+    pub fn snapshot(&self) -> ShadowSnapshot {
+        ShadowSnapshot {
+            matches: self.read_matches(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WatchdogSnapshot {
+    pub stalls: u64,
+    pub stalled: Vec<&'static str>,
+    pub max_delay_ms: BTreeMap<&'static str, u64>,
+}
+
+impl Watchdog {
+    pub fn snapshot(&self) -> WatchdogSnapshot {
+        WatchdogSnapshot {
+            stalls: self.read_stalls(),
+            stalled: self.read_stalled(),
+            max_delay_ms: self.read_max_delays(),
+        }
+    }
+}
+
+/// Lines and bytes of one of the noisiest sources over the interval
+#[derive(Debug, Serialize)]
+pub struct SourceSnapshot {
+    pub source: String,
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+impl Sources {
+    pub fn snapshot(&self) -> Vec<SourceSnapshot> {
+        self.read_top()
+            .into_iter()
+            .map(|(source, lines, bytes)| SourceSnapshot {
+                source,
+                lines,
+                bytes,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckpointsSnapshot {
+    pub written: u64,
+    pub max_lag_ms: u64,
+    pub restart_duplicates: u64,
+}
+
+impl Checkpoints {
+    pub fn snapshot(&self) -> CheckpointsSnapshot {
+        CheckpointsSnapshot {
+            written: self.read_written(),
+            max_lag_ms: self.read_max_lag(),
+            restart_duplicates: self.read_restart_duplicates(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionsSnapshot {
+    pub opened: u64,
+    pub open: u64,
+}
+
+impl Connections {
+    pub fn snapshot(&self) -> ConnectionsSnapshot {
+        ConnectionsSnapshot {
+            opened: self.read_opened(),
+            open: self.read_open(),
+        }
+    }
+}
+
+/// Lines and requests of an ingestion key route over the interval, and its spool usage
+#[derive(Debug, Serialize)]
+pub struct RouteSnapshot {
+    pub lines: u64,
+    pub dropped: u64,
+    pub requests: u64,
+    pub request_bytes: u64,
+    pub failures: u64,
+    pub spool_bytes: u64,
+    pub spool_files: u64,
+}
+
+impl Routes {
+    pub fn snapshot(&self) -> BTreeMap<String, RouteSnapshot> {
+        self.stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, stats)| {
+                let snapshot = RouteSnapshot {
+                    lines: stats.lines,
+                    dropped: stats.dropped,
+                    requests: stats.requests,
+                    request_bytes: stats.request_bytes,
+                    failures: stats.failures,
+                    spool_bytes: stats.spool_bytes,
+                    spool_files: stats.spool_files,
+                };
+                (route.clone(), snapshot)
+            })
+            .collect()
+    }
+}
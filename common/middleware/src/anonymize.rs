@@ -0,0 +1,201 @@
+use crate::{Middleware, Status};
+use http::types::body::LineBufferMut;
+use metrics::Metrics;
+use regex::bytes::Regex;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::from_utf8;
+use thiserror::Error;
+
+/// Candidates for IPv6 addresses, which are validated by parsing them. The embedded IPv4 form
+/// is tried first so that the end of `::ffff:10.0.0.1` isn't taken for a hex group.
+const IPV6_CANDIDATE: &str =
+    r"(?i)(?:[0-9a-f]{0,4}:){2,7}(?:(?:\d{1,3}\.){3}\d{1,3}|[0-9a-f]{1,4})?";
+
+#[derive(Clone, Debug, Error)]
+pub enum IpAnonymizerError {
+    #[error("{0} is not a valid IPv4 prefix length, it must be between 0 and 32")]
+    Ipv4Prefix(u8),
+    #[error("{0} is not a valid IPv6 prefix length, it must be between 0 and 128")]
+    Ipv6Prefix(u8),
+}
+
+/// Masks the host portion of the IP addresses found in lines, keeping only the network
+/// prefix of each address, e.g. `192.168.1.15` becomes `192.168.1.0` with a /24 prefix
+pub struct IpAnonymizer {
+    ipv4_mask: u32,
+    ipv6_mask: u128,
+    ipv4: Regex,
+    ipv6: Regex,
+}
+
+impl IpAnonymizer {
+    pub fn new(ipv4_prefix: u8, ipv6_prefix: u8) -> Result<IpAnonymizer, IpAnonymizerError> {
+        if ipv4_prefix > 32 {
+            return Err(IpAnonymizerError::Ipv4Prefix(ipv4_prefix));
+        }
+        if ipv6_prefix > 128 {
+            return Err(IpAnonymizerError::Ipv6Prefix(ipv6_prefix));
+        }
+        Ok(IpAnonymizer {
+            ipv4_mask: u32::MAX.checked_shl(32 - ipv4_prefix as u32).unwrap_or(0),
+            ipv6_mask: u128::MAX.checked_shl(128 - ipv6_prefix as u32).unwrap_or(0),
+            ipv4: Regex::new(r"(?:\d{1,3}\.){3}\d{1,3}").unwrap(),
+            ipv6: Regex::new(IPV6_CANDIDATE).unwrap(),
+        })
+    }
+
+    /// The addresses found in `value` along with their masked form, ordered by position
+    fn anonymize(&self, value: &[u8]) -> Vec<(usize, usize, String)> {
+        let mut found = Vec::new();
+        for m in self.ipv6.find_iter(value) {
+            if !is_delimited(value, m.start(), m.end(), false) {
+                continue;
+            }
+            let parsed = from_utf8(m.as_bytes())
+                .ok()
+                .and_then(|s| s.parse::<Ipv6Addr>().ok());
+            if let Some(address) = parsed {
+                let masked = Ipv6Addr::from(u128::from(address) & self.ipv6_mask);
+                found.push((m.start(), m.end(), masked.to_string()));
+            }
+        }
+        for m in self.ipv4.find_iter(value) {
+            if !is_delimited(value, m.start(), m.end(), true) {
+                continue;
+            }
+            let parsed = from_utf8(m.as_bytes())
+                .ok()
+                .and_then(|s| s.parse::<Ipv4Addr>().ok());
+            if let Some(address) = parsed {
+                let masked = Ipv4Addr::from(u32::from(address) & self.ipv4_mask);
+                found.push((m.start(), m.end(), masked.to_string()));
+            }
+        }
+        found.sort_unstable_by_key(|(start, _, _)| *start);
+        found
+    }
+}
+
+/// Whether a candidate address isn't part of a longer word, number or path like `std::io`
+fn is_delimited(value: &[u8], start: usize, end: usize, ipv4: bool) -> bool {
+    let continues = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let before = start.checked_sub(1).map(|i| value[i]);
+    let after = value.get(end).copied();
+    let before_ok = match before {
+        Some(b'.') => false,
+        // Addresses often follow a label like `ip:10.0.0.1`
+        Some(b':') => ipv4,
+        Some(byte) => !continues(byte),
+        None => true,
+    };
+    let after_ok = match after {
+        // A dot ends a sentence unless it's followed by more digits, like in `1.2.3.4.5`
+        Some(b'.') => !value.get(end + 1).map_or(false, u8::is_ascii_digit),
+        Some(b':') => ipv4,
+        Some(byte) => !continues(byte),
+        None => true,
+    };
+    before_ok && after_ok
+}
+
+impl Middleware for IpAnonymizer {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        let value = match line.get_line_buffer() {
+            Some(value) => value,
+            None => return Status::Ok(line),
+        };
+
+        let mut anonymized = Vec::with_capacity(value.len());
+        let mut index = 0;
+        let mut addresses = 0;
+        for (start, end, masked) in self.anonymize(value) {
+            // Addresses overlap when an IPv4 one is embedded in an IPv6 one
+            if start < index {
+                continue;
+            }
+            if value[start..end] != *masked.as_bytes() {
+                addresses += 1;
+            }
+            anonymized.extend_from_slice(&value[index..start]);
+            anonymized.extend_from_slice(masked.as_bytes());
+            index = end;
+        }
+
+        if addresses == 0 {
+            return Status::Ok(line);
+        }
+        anonymized.extend_from_slice(&value[index..]);
+
+        if line.set_line_buffer(anonymized).is_err() {
+            return Status::Skip;
+        }
+        Metrics::anonymizer().increment_lines();
+        Metrics::anonymizer().add_addresses(addresses);
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    fn anonymized(anonymizer: &IpAnonymizer, line: &str) -> String {
+        match anonymizer.process(&mut LineBuilder::new().line(line)) {
+            Status::Ok(l) => from_utf8(l.get_line_buffer().unwrap()).unwrap().to_string(),
+            Status::Skip => panic!("should not have been skipped"),
+        }
+    }
+
+    #[test]
+    fn should_mask_ipv4_addresses() {
+        let a = IpAnonymizer::new(24, 48).unwrap();
+        assert_eq!(
+            anonymized(&a, "GET / from 192.168.1.15 via ip:10.20.30.40."),
+            "GET / from 192.168.1.0 via ip:10.20.30.0."
+        );
+        assert_eq!(
+            anonymized(
+                &IpAnonymizer::new(16, 48).unwrap(),
+                "client=172.16.254.1:443"
+            ),
+            "client=172.16.0.0:443"
+        );
+        // Not addresses: invalid octets, versions and longer numbers
+        for line in &["value 300.1.1.1", "v1.2.3.4.5", "build 1.2.3.4a"] {
+            assert_eq!(anonymized(&a, line), *line);
+        }
+    }
+
+    #[test]
+    fn should_mask_ipv6_addresses() {
+        let a = IpAnonymizer::new(24, 48).unwrap();
+        assert_eq!(
+            anonymized(
+                &a,
+                "from 2001:db8:85a3:8d3:1319:8a2e:370:7348 and [fe80::1]:8080"
+            ),
+            "from 2001:db8:85a3:: and [fe80::]:8080"
+        );
+        assert_eq!(
+            anonymized(
+                &IpAnonymizer::new(24, 96).unwrap(),
+                "mapped ::ffff:10.1.2.3"
+            ),
+            "mapped ::ffff:0.0.0.0"
+        );
+        // Not addresses: times, MAC addresses and paths
+        for line in &["at 12:30:45", "mac 00:1a:2b:3c:4d:5e", "call std::io::read"] {
+            assert_eq!(anonymized(&a, line), *line);
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_prefixes() {
+        assert!(IpAnonymizer::new(33, 48).is_err());
+        assert!(IpAnonymizer::new(24, 129).is_err());
+        assert!(IpAnonymizer::new(0, 128).is_ok());
+    }
+}
@@ -0,0 +1,581 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use http::types::body::LineBufferMut;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::{trace, Middleware, Status};
+
+/// Severities `level` is ordered by, from the least to the most severe
+const LEVELS: &[&[&str]] = &[
+    &["trace"],
+    &["debug"],
+    &["info", "information"],
+    &["notice"],
+    &["warn", "warning"],
+    &["error", "err"],
+    &["crit", "critical", "fatal"],
+    &["alert"],
+    &["emerg", "emergency", "panic"],
+];
+
+#[derive(Clone, Debug, Error, PartialEq)]
+#[error("{message} at character {position} of the expression")]
+pub struct ExprError {
+    pub position: usize,
+    pub message: String,
+}
+
+fn error<T>(position: usize, message: impl Into<String>) -> Result<T, ExprError> {
+    Err(ExprError {
+        position,
+        message: message.into(),
+    })
+}
+
+fn severity(level: &str) -> Option<usize> {
+    let level = level.trim().to_lowercase();
+    LEVELS
+        .iter()
+        .position(|names| names.contains(&level.as_str()))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Op(&'static str),
+    Dot,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+/// Longest operators first so that `<=` isn't read as `<`
+const OPS: &[&str] = &[
+    "==", "!=", "=~", "!~", "<=", ">=", "&&", "||", "<", ">", "!",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = expr.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '.' => Token::Dot,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '"' => {
+                let mut literal = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return error(start, "unterminated string"),
+                        Some('"') => break,
+                        // Only quotes and backslashes are unescaped, regexes keep e.g. `\d`
+                        Some('\\') => {
+                            i += 1;
+                            match chars.get(i) {
+                                Some(c) if *c == '"' || *c == '\\' => literal.push(*c),
+                                Some(c) => {
+                                    literal.push('\\');
+                                    literal.push(*c);
+                                }
+                                None => return error(start, "unterminated string"),
+                            }
+                        }
+                        Some(c) => literal.push(*c),
+                    }
+                    i += 1;
+                }
+                Token::Literal(literal)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let digit = |c: &char| c.is_ascii_digit() || *c == '.';
+                while chars.get(i + 1).map_or(false, digit) {
+                    i += 1;
+                }
+                Token::Literal(chars[start..=i].iter().collect())
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let word = |c: &char| c.is_alphanumeric() || *c == '_';
+                while chars.get(i + 1).map_or(false, word) {
+                    i += 1;
+                }
+                Token::Ident(chars[start..=i].iter().collect())
+            }
+            _ => {
+                let rest: String = chars[i..].iter().take(2).collect();
+                match OPS.iter().find(|op| rest.starts_with(**op)) {
+                    Some(&op) => {
+                        i += op.len() - 1;
+                        Token::Op(op)
+                    }
+                    None => return error(start, format!("unexpected {:?}", c)),
+                }
+            }
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+/// A field of the lines
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Line,
+    App,
+    Host,
+    Level,
+    File,
+    Env,
+    /// The namespace, pod and container names of a Kubernetes container log file
+    Namespace,
+    Pod,
+    Container,
+    Label(String),
+    Annotation(String),
+    /// The keys of a value nested in the meta object
+    Meta(Vec<String>),
+}
+
+/// The pod, namespace and container of `/var/log/containers/<pod>_<namespace>_<container>-<id>.log`
+fn container_path(file: &str) -> Option<(&str, &str, &str)> {
+    let name = file
+        .strip_prefix("/var/log/containers/")?
+        .strip_suffix(".log")?;
+    let mut parts = name.splitn(3, '_');
+    let (pod, namespace, rest) = (parts.next()?, parts.next()?, parts.next()?);
+    let container = &rest[..rest.rfind('-')?];
+    Some((pod, namespace, container))
+}
+
+impl Field {
+    fn value<'a>(&self, line: &'a mut dyn LineBufferMut) -> Option<Cow<'a, str>> {
+        let borrowed = |value: Option<&'a str>| value.map(Cow::Borrowed);
+        match self {
+            Field::Line => line.get_line_buffer().map(String::from_utf8_lossy),
+            Field::App => borrowed(line.get_app()),
+            Field::Host => borrowed(line.get_host()),
+            Field::Level => borrowed(line.get_level()),
+            Field::File => borrowed(line.get_file()),
+            Field::Env => borrowed(line.get_env()),
+            Field::Namespace => borrowed(line.get_file().and_then(container_path).map(|c| c.1)),
+            Field::Pod => borrowed(line.get_file().and_then(container_path).map(|c| c.0)),
+            Field::Container => borrowed(line.get_file().and_then(container_path).map(|c| c.2)),
+            Field::Label(key) => borrowed(line.get_labels()?.get(key.as_str()).map(String::as_str)),
+            Field::Annotation(key) => {
+                let value = line.get_annotations()?.get(key.as_str());
+                borrowed(value.map(String::as_str))
+            }
+            Field::Meta(keys) => {
+                let value = keys
+                    .iter()
+                    .try_fold(line.get_meta()?, |value, key| value.get(key))?;
+                match value {
+                    serde_json::Value::String(s) => Some(Cow::Borrowed(s.as_str())),
+                    serde_json::Value::Null
+                    | serde_json::Value::Array(_)
+                    | serde_json::Value::Object(_) => None,
+                    other => Some(Cow::Owned(other.to_string())),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug)]
+enum Node {
+    Const(bool),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    /// A bare field, true when the line has it
+    Present(Field),
+    Compare(Field, &'static str, String),
+    Matches(Field, Regex, bool),
+    Call(Field, Method, String),
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.len, |(position, _)| *position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), ExprError> {
+        let position = self.position();
+        match self.advance() {
+            Some(token) if token == expected => Ok(()),
+            _ => error(position, format!("expected {}", what)),
+        }
+    }
+
+    fn literal(&mut self) -> Result<String, ExprError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::Literal(literal)) => Ok(literal),
+            _ => error(position, "expected a string"),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.advance();
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.unary()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.advance();
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        match self.peek() {
+            Some(Token::Op("!")) => {
+                self.advance();
+                Ok(Node::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.advance();
+                let node = self.or()?;
+                self.expect(Token::RParen, "a closing parenthesis")?;
+                Ok(node)
+            }
+            _ => self.condition(),
+        }
+    }
+
+    /// `true`, `false`, a bare field, `field <op> "value"` or `field.method("value")`
+    fn condition(&mut self) -> Result<Node, ExprError> {
+        let position = self.position();
+        let name = match self.advance() {
+            Some(Token::Ident(name)) => name,
+            _ => return error(position, "expected a field"),
+        };
+        let field = match name.as_str() {
+            "true" => return Ok(Node::Const(true)),
+            "false" => return Ok(Node::Const(false)),
+            "line" => Field::Line,
+            "app" => Field::App,
+            "host" => Field::Host,
+            "level" => Field::Level,
+            "file" => Field::File,
+            "env" => Field::Env,
+            "namespace" => Field::Namespace,
+            "pod" => Field::Pod,
+            "container" => Field::Container,
+            "label" | "annotation" => {
+                let key = self.key()?;
+                match name.as_str() {
+                    "label" => Field::Label(key),
+                    _ => Field::Annotation(key),
+                }
+            }
+            "meta" => {
+                let mut keys = vec![self.key()?];
+                while self.is_key_next() {
+                    keys.push(self.key()?);
+                }
+                Field::Meta(keys)
+            }
+            _ => return error(position, format!("unknown field {}", name)),
+        };
+
+        if self.peek() == Some(&Token::Dot) {
+            self.advance();
+            let position = self.position();
+            let method = match self.advance() {
+                Some(Token::Ident(method)) if method == "contains" => Method::Contains,
+                Some(Token::Ident(method)) if method == "starts_with" => Method::StartsWith,
+                Some(Token::Ident(method)) if method == "ends_with" => Method::EndsWith,
+                _ => return error(position, "expected contains, starts_with or ends_with"),
+            };
+            self.expect(Token::LParen, "an opening parenthesis")?;
+            let argument = self.literal()?;
+            self.expect(Token::RParen, "a closing parenthesis")?;
+            return Ok(Node::Call(field, method, argument));
+        }
+
+        let op = match self.peek() {
+            Some(Token::Op(op)) if !matches!(*op, "&&" | "||" | "!") => *op,
+            _ => return Ok(Node::Present(field)),
+        };
+        self.advance();
+        let position = self.position();
+        let value = self.literal()?;
+        match op {
+            "=~" | "!~" => match Regex::new(&value) {
+                Ok(regex) => Ok(Node::Matches(field, regex, op == "=~")),
+                Err(e) => error(position, format!("invalid regex: {}", e)),
+            },
+            "<" | "<=" | ">" | ">=" if field == Field::Level && severity(&value).is_none() => {
+                error(position, format!("unknown level {}", value))
+            }
+            _ => Ok(Node::Compare(field, op, value)),
+        }
+    }
+
+    /// Whether another key of a meta field follows, rather than a method call
+    fn is_key_next(&self) -> bool {
+        let token = |offset| self.tokens.get(self.next + offset).map(|(_, token)| token);
+        match (token(0), token(1), token(2)) {
+            (Some(Token::LBracket), _, _) => true,
+            (Some(Token::Dot), Some(Token::Ident(_)), Some(Token::LParen)) => false,
+            (Some(Token::Dot), Some(Token::Ident(_)), _) => true,
+            _ => false,
+        }
+    }
+
+    /// `.key` or `["key"]`, for keys that aren't identifiers such as `app.kubernetes.io/name`
+    fn key(&mut self) -> Result<String, ExprError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::Dot) => match self.advance() {
+                Some(Token::Ident(key)) => Ok(key),
+                _ => error(position, "expected a key"),
+            },
+            Some(Token::LBracket) => {
+                let key = self.literal()?;
+                self.expect(Token::RBracket, "a closing bracket")?;
+                Ok(key)
+            }
+            _ => error(position, "expected .key or [\"key\"]"),
+        }
+    }
+}
+
+/// A condition on the fields of lines, compiled once when the config is loaded, e.g.
+/// `namespace == "payments" && level >= "warn" && !line.contains("healthz")`.
+///
+/// Fields are `line`, `app`, `host`, `level`, `file`, `env`, the `namespace`, `pod` and
+/// `container` of Kubernetes container logs, `label.<key>`, `annotation.<key>` and
+/// `meta.<key>...`, with `["key"]` for keys that aren't identifiers. Fields are compared to
+/// strings with `==`, `!=`, `<`, `<=`, `>`, `>=`, matched against regexes with `=~` and
+/// `!~`, and checked with `.contains()`, `.starts_with()` and `.ends_with()`. A bare field
+/// is true when the line has it. Levels are ordered by severity, other values numerically
+/// when both sides are numbers. Conditions on fields a line doesn't have are false, except
+/// for `!=` and `!~`.
+#[derive(Debug)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(expr: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(expr)?,
+            next: 0,
+            len: expr.chars().count(),
+        };
+        let root = parser.or()?;
+        if parser.peek().is_some() {
+            return error(parser.position(), "unexpected trailing input");
+        }
+        Ok(Expr {
+            source: expr.to_string(),
+            root,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, line: &mut dyn LineBufferMut) -> bool {
+        eval(&self.root, line)
+    }
+}
+
+fn compare(field: &Field, value: &str, expected: &str) -> Option<Ordering> {
+    if *field == Field::Level {
+        return Some(severity(value)?.cmp(&severity(expected)?));
+    }
+    match (value.trim().parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(value), Ok(expected)) => value.partial_cmp(&expected),
+        _ => Some(value.cmp(expected)),
+    }
+}
+
+fn eval(node: &Node, line: &mut dyn LineBufferMut) -> bool {
+    match node {
+        Node::Const(value) => *value,
+        Node::Not(node) => !eval(node, line),
+        Node::And(left, right) => eval(left, line) && eval(right, line),
+        Node::Or(left, right) => eval(left, line) || eval(right, line),
+        Node::Present(field) => field.value(line).map_or(false, |v| !v.is_empty()),
+        Node::Compare(field, op, expected) => {
+            let value = match field.value(line) {
+                Some(value) => value,
+                None => return *op == "!=",
+            };
+            match *op {
+                "==" if *field == Field::Level => value.eq_ignore_ascii_case(expected),
+                "!=" if *field == Field::Level => !value.eq_ignore_ascii_case(expected),
+                "==" => value == expected.as_str(),
+                "!=" => value != expected.as_str(),
+                op => match compare(field, &value, expected) {
+                    Some(ordering) => match op {
+                        "<" => ordering == Ordering::Less,
+                        "<=" => ordering != Ordering::Greater,
+                        ">" => ordering == Ordering::Greater,
+                        _ => ordering != Ordering::Less,
+                    },
+                    None => false,
+                },
+            }
+        }
+        Node::Matches(field, regex, expected) => match field.value(line) {
+            Some(value) => regex.is_match(&value) == *expected,
+            None => !*expected,
+        },
+        Node::Call(field, method, argument) => match field.value(line) {
+            Some(value) => match method {
+                Method::Contains => value.contains(argument.as_str()),
+                Method::StartsWith => value.starts_with(argument.as_str()),
+                Method::EndsWith => value.ends_with(argument.as_str()),
+            },
+            None => false,
+        },
+    }
+}
+
+/// Skips the lines matching `exclusion` and the ones not matching `inclusion`
+pub struct ExprFilter {
+    exclusion: Option<Expr>,
+    inclusion: Option<Expr>,
+}
+
+impl ExprFilter {
+    pub fn new(exclusion: Option<Expr>, inclusion: Option<Expr>) -> Self {
+        ExprFilter {
+            exclusion,
+            inclusion,
+        }
+    }
+}
+
+impl Middleware for ExprFilter {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        if let Some(inclusion) = self.inclusion.as_ref() {
+            if !inclusion.matches(line) {
+                trace::note("ExprFilter", || {
+                    format!("didn't match {}", inclusion.as_str())
+                });
+                return Status::Skip;
+            }
+        }
+        match self.exclusion.as_ref() {
+            Some(exclusion) if exclusion.matches(line) => {
+                trace::note("ExprFilter", || format!("matched {}", exclusion.as_str()));
+                Status::Skip
+            }
+            _ => Status::Ok(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMetaMut};
+    use std::collections::BTreeMap;
+
+    fn matches(expr: &str, line: &mut LineBuilder) -> bool {
+        Expr::parse(expr).unwrap().matches(line)
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let file = "/var/log/containers/api-7d9f_payments_server-0123456789abcdef.log";
+        let mut line = LineBuilder::new()
+            .line("GET /healthz 200")
+            .file(file)
+            .level("WARNING");
+        let mut labels = BTreeMap::new();
+        labels.insert("app.kubernetes.io/name".to_string(), "api".to_string());
+        line.set_labels(labels.into()).unwrap();
+
+        assert!(matches(
+            r#"namespace == "payments" && level >= "warn" && line.contains("healthz")"#,
+            &mut line
+        ));
+        assert!(!matches(r#"!line.contains("healthz")"#, &mut line));
+        assert!(matches(
+            r#"pod == "api-7d9f" && container == "server""#,
+            &mut line
+        ));
+        assert!(matches(
+            r#"label["app.kubernetes.io/name"] == "api""#,
+            &mut line
+        ));
+        assert!(matches(r#"level < "error" || false"#, &mut line));
+        assert!(matches(r#"line =~ "^GET /\w+ 2\d\d$""#, &mut line));
+        assert!(matches(r#"!(app || env) && app != "web""#, &mut line));
+        assert!(!matches(r#"annotation.team == "core""#, &mut line));
+
+        let filter = ExprFilter::new(
+            Some(Expr::parse(r#"line.contains("healthz")"#).unwrap()),
+            Some(Expr::parse(r#"namespace == "payments""#).unwrap()),
+        );
+        assert!(matches!(filter.process(&mut line), Status::Skip));
+        let mut other = LineBuilder::new().line("GET /orders 200").file(file);
+        assert!(matches!(filter.process(&mut other), Status::Ok(_)));
+    }
+
+    #[test]
+    fn reports_invalid_expressions() {
+        assert_eq!(Expr::parse(r#"level >= "wrn""#).unwrap_err().position, 9);
+        assert_eq!(Expr::parse(r#"namespaces == "a""#).unwrap_err().position, 0);
+        assert!(Expr::parse(r#"line =~ "(""#).is_err());
+        assert!(Expr::parse(r#"(app == "a""#).is_err());
+        assert!(Expr::parse(r#"app == "a" app"#).is_err());
+        assert!(Expr::parse(r#"app == "a"#).is_err());
+        assert!(Expr::parse(r#"meta.http.status >= 500"#).is_ok());
+    }
+}
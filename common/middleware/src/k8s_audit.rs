@@ -0,0 +1,162 @@
+use crate::{Middleware, Status};
+use http::types::body::LineBufferMut;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// Key of the line meta the fields of an audit event are attached under
+pub const META_KEY: &str = "k8s_audit";
+
+/// The fields of a kube-apiserver audit event, `audit.k8s.io/v1` or `v1beta1`, worth
+/// searching and alerting on, `None` when `event` isn't an audit event
+pub fn summarize(event: &Value) -> Option<Value> {
+    let api_version = event.get("apiVersion")?.as_str()?;
+    if event.get("kind")?.as_str()? != "Event" || !api_version.starts_with("audit.k8s.io/") {
+        return None;
+    }
+
+    let mut summary = Map::new();
+    let mut copy = |name: &str, value: Option<&Value>| {
+        if let Some(value) = value.filter(|v| !v.is_null()) {
+            summary.insert(name.into(), value.clone());
+        }
+    };
+    copy("audit_id", event.get("auditID"));
+    copy("level", event.get("level"));
+    copy("stage", event.get("stage"));
+    copy("verb", event.get("verb"));
+    copy("request_uri", event.get("requestURI"));
+    copy("user", event.pointer("/user/username"));
+    copy("groups", event.pointer("/user/groups"));
+    copy(
+        "impersonated_user",
+        event.pointer("/impersonatedUser/username"),
+    );
+    copy("source_ips", event.get("sourceIPs"));
+    copy("user_agent", event.get("userAgent"));
+    copy("resource", event.pointer("/objectRef/resource"));
+    copy("subresource", event.pointer("/objectRef/subresource"));
+    copy("api_group", event.pointer("/objectRef/apiGroup"));
+    copy("namespace", event.pointer("/objectRef/namespace"));
+    copy("name", event.pointer("/objectRef/name"));
+    copy("response_code", event.pointer("/responseStatus/code"));
+    copy(
+        "decision",
+        event
+            .get("annotations")
+            .and_then(|annotations| annotations.get("authorization.k8s.io/decision")),
+    );
+    Some(Value::Object(summary))
+}
+
+/// Adds `summary` to the meta of `line`, leaving meta that isn't an object untouched
+pub fn attach<'a>(line: &'a mut dyn LineBufferMut, summary: Value) -> &'a mut dyn LineBufferMut {
+    let meta = match line.get_meta() {
+        Some(Value::Object(meta)) => {
+            let mut meta = meta.clone();
+            meta.insert(META_KEY.into(), summary);
+            meta
+        }
+        Some(_) => return line,
+        None => {
+            let mut meta = Map::new();
+            meta.insert(META_KEY.into(), summary);
+            meta
+        }
+    };
+    let _ = line.set_meta(Value::Object(meta));
+    line
+}
+
+/// Parses the lines of the kube-apiserver audit log file, written with
+/// `--audit-log-format=json`, attaching the fields of each event to the meta of its line
+pub struct K8sAuditParser {
+    path: PathBuf,
+}
+
+impl K8sAuditParser {
+    pub fn new(path: PathBuf) -> Self {
+        K8sAuditParser { path }
+    }
+}
+
+impl Middleware for K8sAuditParser {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        if line.get_file().map(Path::new) != Some(self.path.as_path()) {
+            return Status::Ok(line);
+        }
+        let summary = line
+            .get_line_buffer()
+            .and_then(|buffer| serde_json::from_slice::<Value>(buffer).ok())
+            .and_then(|event| summarize(&event));
+        match summary {
+            Some(summary) => Status::Ok(attach(line, summary)),
+            None => Status::Ok(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta};
+    use serde_json::json;
+
+    const EVENT: &str = r#"{"kind":"Event","apiVersion":"audit.k8s.io/v1","level":"Metadata",
+        "auditID":"6f4c","stage":"ResponseComplete","requestURI":"/api/v1/namespaces/shop/secrets",
+        "verb":"list","user":{"username":"jane","groups":["dev"]},"sourceIPs":["10.0.0.7"],
+        "objectRef":{"resource":"secrets","namespace":"shop","apiVersion":"v1"},
+        "responseStatus":{"metadata":{},"code":403},
+        "annotations":{"authorization.k8s.io/decision":"forbid"}}"#;
+
+    #[test]
+    fn summarizes_audit_events() {
+        let event: Value = serde_json::from_str(EVENT).unwrap();
+        assert_eq!(
+            summarize(&event),
+            Some(json!({
+                "audit_id": "6f4c",
+                "level": "Metadata",
+                "stage": "ResponseComplete",
+                "verb": "list",
+                "request_uri": "/api/v1/namespaces/shop/secrets",
+                "user": "jane",
+                "groups": ["dev"],
+                "source_ips": ["10.0.0.7"],
+                "resource": "secrets",
+                "namespace": "shop",
+                "response_code": 403,
+                "decision": "forbid",
+            }))
+        );
+        assert_eq!(
+            summarize(&json!({"kind": "Event", "apiVersion": "v1"})),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_audit_log_lines() {
+        let parser = K8sAuditParser::new("/var/log/kubernetes/audit.log".into());
+        let mut line = LineBuilder::new()
+            .line(EVENT.replace('\n', ""))
+            .file("/var/log/kubernetes/audit.log");
+        match parser.process(&mut line) {
+            Status::Ok(line) => {
+                let meta = line.get_meta().unwrap();
+                assert_eq!(meta[META_KEY]["verb"], "list");
+                assert_eq!(meta[META_KEY]["user"], "jane");
+            }
+            Status::Skip => panic!("line skipped"),
+        }
+
+        let mut line = LineBuilder::new()
+            .line(EVENT.replace('\n', ""))
+            .file("/var/log/a.log");
+        match parser.process(&mut line) {
+            Status::Ok(line) => assert_eq!(line.get_meta(), None),
+            Status::Skip => panic!("line skipped"),
+        }
+    }
+}
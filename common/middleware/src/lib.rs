@@ -3,7 +3,19 @@ use std::sync::Arc;
 use http::types::body::LineBufferMut;
 use std::thread::spawn;
 
+use crate::trace::Tracer;
+
+pub mod anonymize;
+pub mod expr;
+pub mod k8s_audit;
 pub mod line_rules;
+pub mod overrides;
+pub mod path_template;
+pub mod reload;
+pub mod routes;
+pub mod secrets;
+pub mod shadow;
+pub mod trace;
 
 pub enum Status<T> {
     Ok(T),
@@ -13,20 +25,33 @@ pub enum Status<T> {
 pub trait Middleware: Send + Sync + 'static {
     fn run(&self);
     fn process<'a>(&self, lines: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut>;
+
+    /// Name of the middleware in the traces of lines
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 #[derive(Default)]
 pub struct Executor {
     middlewares: Vec<Arc<dyn Middleware>>,
+    tracer: Option<Tracer>,
 }
 
 impl Executor {
     pub fn new() -> Executor {
         Executor {
             middlewares: Vec::new(),
+            tracer: None,
         }
     }
 
+    /// Traces one in every `every` lines, see [`trace`]
+    pub fn trace(&mut self, every: u64) {
+        self.tracer = Some(Tracer::new(every));
+    }
+
     pub fn register<T: Middleware>(&mut self, middleware: T) {
         self.middlewares.push(Arc::new(middleware))
     }
@@ -42,6 +67,9 @@ impl Executor {
         &self,
         line: &'a mut dyn LineBufferMut,
     ) -> Option<&'a mut dyn LineBufferMut> {
+        if self.tracer.as_ref().map_or(false, Tracer::start) {
+            return self.process_traced(line);
+        }
         self.middlewares
             .iter()
             .try_fold(line, |l, m| match m.process(l) {
@@ -50,4 +78,39 @@ impl Executor {
             })
             .ok()
     }
+
+    /// Same as `process`, noting what each middleware did with the line
+    fn process_traced<'a>(
+        &self,
+        mut line: &'a mut dyn LineBufferMut,
+    ) -> Option<&'a mut dyn LineBufferMut> {
+        trace::note("source", || {
+            let value = line
+                .get_line_buffer()
+                .map(|value| String::from_utf8_lossy(value).into_owned());
+            let source = line.get_file().or_else(|| line.get_app());
+            format!(
+                "read {:?} from {}",
+                value.unwrap_or_default(),
+                source.unwrap_or("unknown")
+            )
+        });
+        for middleware in self.middlewares.iter() {
+            let before = line.get_line_buffer().map(<[u8]>::to_vec);
+            line = match middleware.process(line) {
+                Status::Ok(line) => line,
+                Status::Skip => {
+                    trace::note(middleware.name(), || "dropped the line".to_string());
+                    return None;
+                }
+            };
+            if line.get_line_buffer() != before.as_deref() {
+                trace::note(middleware.name(), || {
+                    let value = line.get_line_buffer().map(String::from_utf8_lossy);
+                    format!("changed the line to {:?}", value.unwrap_or_default())
+                });
+            }
+        }
+        Some(line)
+    }
 }
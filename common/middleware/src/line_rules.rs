@@ -1,3 +1,5 @@
+use crate::secrets::SecretDetector;
+use crate::trace;
 use crate::{Middleware, Status};
 use http::types::body::LineBufferMut;
 use regex::bytes::{Regex, RegexSet};
@@ -10,6 +12,7 @@ pub struct LineRules {
     exclusion: RegexSet,
     inclusion: RegexSet,
     redact: Vec<Regex>,
+    secrets: Option<SecretDetector>,
 }
 
 #[derive(Clone, Debug, Error)]
@@ -34,9 +37,16 @@ impl LineRules {
             exclusion: RegexSet::new(exclusion).map_err(LineRulesError::RegexError)?,
             inclusion: RegexSet::new(inclusion).map_err(LineRulesError::RegexError)?,
             redact: redact_vec,
+            secrets: None,
         })
     }
 
+    /// Also redacts the values that look like secrets to `detector`
+    pub fn with_secret_detector(mut self, detector: SecretDetector) -> Self {
+        self.secrets = Some(detector);
+        self
+    }
+
     /// Applies inclusion and exclusion rules and replaces the redacted values.
     fn process_line<'a>(
         &self,
@@ -46,15 +56,24 @@ impl LineRules {
 
         // If it doesn't match any inclusion rule -> skip
         if !self.inclusion.is_empty() && !self.inclusion.is_match(value) {
+            trace::note("LineRules", || "matched no line inclusion rule".to_string());
             return Status::Skip;
         }
 
         // If any exclusion rule matches -> skip
         if self.exclusion.is_match(value) {
+            trace::note("LineRules", || {
+                let rules = self.exclusion.matches(value);
+                let patterns: Vec<&str> = rules
+                    .iter()
+                    .map(|i| self.exclusion.patterns()[i].as_str())
+                    .collect();
+                format!("matched line exclusion rule {}", patterns.join(", "))
+            });
             return Status::Skip;
         }
 
-        if !self.redact.is_empty() {
+        if !self.redact.is_empty() || self.secrets.is_some() {
             return self.redact(value.to_owned(), line);
         }
 
@@ -68,42 +87,24 @@ impl LineRules {
     ) -> Status<&'a mut dyn LineBufferMut> {
         let mut matches: Vec<(usize, usize)> = vec![];
         for r in self.redact.iter() {
+            let mut spans = 0;
             for m in r.find_iter(&value) {
-                let mut overlapping_match = None;
-                let mut insert_index = None;
-                for (i, existing) in matches.iter().enumerate() {
-                    let overlaps =
-                        // Overlaps when it starts between an existing match
-                        (m.start() >= existing.0 && m.start() <= existing.1)
-                        // or it starts before an existing match
-                        // and ends after the existing match end
-                        || (m.start() <= existing.0 && m.end() >= existing.0);
-
-                    if overlaps {
-                        overlapping_match = Some((
-                            i,
-                            cmp::min(existing.0, m.start()),
-                            cmp::max(existing.1, m.end()),
-                        ));
-                        // Order is guaranteed so there's no need to continue processing
-                        break;
-                    }
-
-                    if m.start() < existing.0 {
-                        insert_index = Some(i);
-                    }
-                }
-
-                if let Some(item) = overlapping_match {
-                    // Replace existing
-                    matches[item.0] = (item.1, item.2);
-                } else if let Some(index) = insert_index {
-                    // Insert at position and shift all elements after it to the right
-                    matches.insert(index, (m.start(), m.end()));
-                } else {
-                    // Append
-                    matches.push((m.start(), m.end()));
-                }
+                add_match(&mut matches, m.start(), m.end());
+                spans += 1;
+            }
+            if spans > 0 {
+                trace::note("LineRules", || {
+                    format!("redaction rule {} replaced {} spans", r.as_str(), spans)
+                });
+            }
+        }
+        if let Some(secrets) = self.secrets.as_ref() {
+            let found = secrets.find(&value);
+            if !found.is_empty() {
+                trace::note("LineRules", || format!("redacted {} secrets", found.len()));
+            }
+            for (start, end) in found {
+                add_match(&mut matches, start, end);
             }
         }
 
@@ -131,11 +132,52 @@ impl LineRules {
     }
 }
 
+/// Adds a match to the ordered list of matches, merging it with the one it overlaps
+fn add_match(matches: &mut Vec<(usize, usize)>, start: usize, end: usize) {
+    let mut overlapping_match = None;
+    let mut insert_index = None;
+    for (i, existing) in matches.iter().enumerate() {
+        let overlaps =
+            // Overlaps when it starts between an existing match
+            (start >= existing.0 && start <= existing.1)
+            // or it starts before an existing match
+            // and ends after the existing match end
+            || (start <= existing.0 && end >= existing.0);
+
+        if overlaps {
+            overlapping_match = Some((i, cmp::min(existing.0, start), cmp::max(existing.1, end)));
+            // Order is guaranteed so there's no need to continue processing
+            break;
+        }
+
+        if start < existing.0 {
+            // Matches are kept ordered, so it goes before the first one starting after it
+            insert_index = Some(i);
+            break;
+        }
+    }
+
+    if let Some(item) = overlapping_match {
+        // Replace existing
+        matches[item.0] = (item.1, item.2);
+    } else if let Some(index) = insert_index {
+        // Insert at position and shift all elements after it to the right
+        matches.insert(index, (start, end));
+    } else {
+        // Append
+        matches.push((start, end));
+    }
+}
+
 impl Middleware for LineRules {
     fn run(&self) {}
 
     fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
-        if self.exclusion.is_empty() && self.inclusion.is_empty() && self.redact.is_empty() {
+        if self.exclusion.is_empty()
+            && self.inclusion.is_empty()
+            && self.redact.is_empty()
+            && self.secrets.is_none()
+        {
             // Avoid unnecessary allocations when no rules were defined
             return Status::Ok(line);
         }
@@ -313,6 +355,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_redact_secrets() {
+        let redact = &vec![s!("(?i:SENSITIVE)")];
+        let p = LineRules::new(&[], &[], redact)
+            .unwrap()
+            .with_secret_detector(SecretDetector::new(crate::secrets::Sensitivity::Medium));
+        redact_match!(p, "Hello INFO not redacted", "Hello INFO not redacted");
+        redact_match!(
+            p,
+            "sensitive login with password=hunter2",
+            "[REDACTED] login with password=[REDACTED]"
+        );
+        redact_match!(
+            p,
+            "using key aB3dE5gH7jK9mN1pQ2rS4tU6vW8xY0zC3",
+            "using key [REDACTED]"
+        );
+    }
+
     #[test]
     fn should_apply_rules_and_redact_lines() {
         let redact = &vec![s!("(?i:SENSITIVE)")];
@@ -0,0 +1,285 @@
+use globber::Pattern;
+use http::types::body::LineBufferMut;
+use thiserror::Error;
+
+use crate::expr::Expr;
+use crate::{Middleware, Status};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseOverrideError {
+    #[error("field override {0} doesn't set any of app, host or env")]
+    NoFields(String),
+    #[error("field override {0} has an unknown field {1}, use app, host or env")]
+    UnknownField(String, String),
+    #[error("field override {0} has an invalid path glob, {1}")]
+    Glob(String, String),
+    #[error("field override {0} has an invalid selector, {}", SELECTORS)]
+    Selector(String),
+    #[error("field override {0} has an invalid expression, {1}")]
+    Expr(String, String),
+}
+
+/// The forms of the selectors, for the errors of the rules
+pub(crate) const SELECTORS: &str = "use a path glob, label.<key>=<value> or {<expression>}";
+
+/// Why a selector couldn't be parsed
+pub(crate) enum SelectorError {
+    Glob(String),
+    Expr(String),
+    Invalid,
+}
+
+/// The lines a rule applies to
+#[derive(Debug)]
+pub(crate) enum Selector {
+    Path(Pattern),
+    Label(String, String),
+    Expr(Expr),
+}
+
+impl Selector {
+    /// Splits `rule` into its selector and the rest, the selector being its first word or a
+    /// filter expression between braces, which may contain spaces
+    pub(crate) fn split(rule: &str) -> (&str, &str) {
+        let rule = rule.trim_start();
+        let end = if rule.starts_with('{') {
+            let (mut quoted, mut escaped) = (false, false);
+            rule.char_indices().skip(1).find_map(|(i, c)| {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if quoted => escaped = true,
+                    '"' => quoted = !quoted,
+                    '}' if !quoted => return Some(i + 1),
+                    _ => {}
+                }
+                None
+            })
+        } else {
+            rule.find(char::is_whitespace)
+        };
+        rule.split_at(end.unwrap_or_else(|| rule.len()))
+    }
+
+    /// Parses a glob of the file path, e.g. `/var/log/nginx/*.log`, a k8s label, e.g.
+    /// `label.app=nginx`, or a filter expression between braces, e.g.
+    /// `{namespace == "payments" && level >= "warn"}`
+    pub(crate) fn parse(selector: &str) -> Result<Self, SelectorError> {
+        if let Some(expr) = selector.strip_prefix('{') {
+            let expr = expr.strip_suffix('}').ok_or(SelectorError::Invalid)?;
+            return Expr::parse(expr)
+                .map(Selector::Expr)
+                .map_err(|e| SelectorError::Expr(e.to_string()));
+        }
+        if selector.starts_with('/') {
+            return Pattern::new(selector)
+                .map(Selector::Path)
+                .map_err(|e| SelectorError::Glob(e.to_string()));
+        }
+        match selector
+            .strip_prefix("label.")
+            .and_then(|s| s.split_once('='))
+        {
+            Some((key, value)) if !key.is_empty() => {
+                Ok(Selector::Label(key.to_string(), value.to_string()))
+            }
+            _ => Err(SelectorError::Invalid),
+        }
+    }
+
+    pub(crate) fn matches(&self, line: &mut dyn LineBufferMut) -> bool {
+        match self {
+            Selector::Path(pattern) => line.get_file().map_or(false, |file| pattern.matches(file)),
+            Selector::Label(key, value) => line
+                .get_labels()
+                .and_then(|labels| labels.get(key.as_str()))
+                .map_or(false, |label| label == value),
+            Selector::Expr(expr) => expr.matches(line),
+        }
+    }
+}
+
+/// The app, host and env sent for the lines a selector matches, in place of the ones derived
+/// from their file name and the hostname
+#[derive(Debug)]
+pub struct OverrideRule {
+    selector: Selector,
+    app: Option<String>,
+    host: Option<String>,
+    env: Option<String>,
+}
+
+impl OverrideRule {
+    /// Parses `<selector> app=<app> host=<host> env=<env>`, where the selector is a glob of
+    /// the file path, e.g. `/var/log/nginx/*.log`, a k8s label, e.g. `label.app=nginx`, or a
+    /// filter expression between braces, and the fields left out keep their value
+    pub fn parse(rule: &str) -> Result<Self, ParseOverrideError> {
+        let (selector, parts) = Selector::split(rule);
+        let selector = Selector::parse(selector).map_err(|e| match e {
+            SelectorError::Glob(e) => ParseOverrideError::Glob(rule.to_string(), e),
+            SelectorError::Expr(e) => ParseOverrideError::Expr(rule.to_string(), e),
+            SelectorError::Invalid => ParseOverrideError::Selector(rule.to_string()),
+        })?;
+
+        let mut parsed = OverrideRule {
+            selector,
+            app: None,
+            host: None,
+            env: None,
+        };
+        for part in parts.split_whitespace() {
+            let (field, value) = part.split_once('=').unwrap_or((part, ""));
+            let slot = match field {
+                "app" => &mut parsed.app,
+                "host" => &mut parsed.host,
+                "env" => &mut parsed.env,
+                _ => {
+                    return Err(ParseOverrideError::UnknownField(
+                        rule.to_string(),
+                        field.to_string(),
+                    ))
+                }
+            };
+            *slot = Some(value.to_string()).filter(|value| !value.is_empty());
+        }
+        if parsed.app.is_none() && parsed.host.is_none() && parsed.env.is_none() {
+            return Err(ParseOverrideError::NoFields(rule.to_string()));
+        }
+        Ok(parsed)
+    }
+}
+
+/// Overrides the app, host and env of lines with the first rule matching them, registered
+/// after the k8s metadata middleware so that label selectors see the labels of the pods
+pub struct FieldOverrides {
+    rules: Vec<OverrideRule>,
+}
+
+impl FieldOverrides {
+    pub fn new(rules: Vec<OverrideRule>) -> Self {
+        FieldOverrides { rules }
+    }
+}
+
+impl Middleware for FieldOverrides {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        let rule = match self.rules.iter().find(|rule| rule.selector.matches(line)) {
+            Some(rule) => rule,
+            None => return Status::Ok(line),
+        };
+        if let Some(app) = &rule.app {
+            let _ = line.set_app(app.clone());
+        }
+        if let Some(host) = &rule.host {
+            let _ = line.set_host(host.clone());
+        }
+        if let Some(env) = &rule.env {
+            let _ = line.set_env(env.clone());
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta, LineMetaMut};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn overrides_fields_of_matching_lines() {
+        let overrides = FieldOverrides::new(vec![
+            OverrideRule::parse("/var/log/nginx/*.log app=nginx env=prod").unwrap(),
+            OverrideRule::parse("label.app.kubernetes.io/name=checkout app=checkout host=shop")
+                .unwrap(),
+            OverrideRule::parse("/var/log/** app=other").unwrap(),
+        ]);
+
+        let mut line = LineBuilder::new()
+            .line("GET /")
+            .file("/var/log/nginx/access.log")
+            .app("access.log");
+        match overrides.process(&mut line) {
+            Status::Ok(line) => {
+                assert_eq!(line.get_app(), Some("nginx"));
+                assert_eq!(line.get_env(), Some("prod"));
+                assert_eq!(line.get_host(), None);
+            }
+            Status::Skip => panic!("line skipped"),
+        }
+
+        let mut line = LineBuilder::new()
+            .line("paid")
+            .file("/var/log/containers/checkout.log");
+        let mut labels = BTreeMap::new();
+        labels.insert("app.kubernetes.io/name".to_string(), "checkout".to_string());
+        line.set_labels(labels.into()).unwrap();
+        match overrides.process(&mut line) {
+            Status::Ok(line) => {
+                assert_eq!(line.get_app(), Some("checkout"));
+                assert_eq!(line.get_host(), Some("shop"));
+            }
+            Status::Skip => panic!("line skipped"),
+        }
+
+        let mut line = LineBuilder::new().line("started").app("systemd");
+        match overrides.process(&mut line) {
+            Status::Ok(line) => assert_eq!(line.get_app(), Some("systemd")),
+            Status::Skip => panic!("line skipped"),
+        }
+
+        assert!(matches!(
+            OverrideRule::parse("/var/log/*.log"),
+            Err(ParseOverrideError::NoFields(_))
+        ));
+        assert!(matches!(
+            OverrideRule::parse("/var/log/*.log level=info"),
+            Err(ParseOverrideError::UnknownField(_, _))
+        ));
+        assert!(matches!(
+            OverrideRule::parse("nginx app=nginx"),
+            Err(ParseOverrideError::Selector(_))
+        ));
+    }
+
+    #[test]
+    fn selects_lines_with_expressions() {
+        let overrides = FieldOverrides::new(vec![OverrideRule::parse(
+            r#"{level >= "warn" && !line.contains("} healthz")} app=alerts env=prod"#,
+        )
+        .unwrap()]);
+
+        let mut line = LineBuilder::new().line("disk full").level("error");
+        match overrides.process(&mut line) {
+            Status::Ok(line) => {
+                assert_eq!(line.get_app(), Some("alerts"));
+                assert_eq!(line.get_env(), Some("prod"));
+            }
+            Status::Skip => panic!("line skipped"),
+        }
+
+        for mut line in vec![
+            LineBuilder::new().line("GET /").level("info"),
+            LineBuilder::new().line("GET } healthz").level("warn"),
+        ] {
+            match overrides.process(&mut line) {
+                Status::Ok(line) => assert_eq!(line.get_app(), None),
+                Status::Skip => panic!("line skipped"),
+            }
+        }
+
+        assert_eq!(
+            Selector::split(r#"{app == "a b"} app=c"#),
+            (r#"{app == "a b"}"#, " app=c")
+        );
+        assert!(matches!(
+            OverrideRule::parse("{level >=} app=alerts"),
+            Err(ParseOverrideError::Expr(_, _))
+        ));
+        assert!(matches!(
+            OverrideRule::parse(r#"{level == "warn" app=alerts"#),
+            Err(ParseOverrideError::Selector(_))
+        ));
+    }
+}
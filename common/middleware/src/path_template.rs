@@ -0,0 +1,216 @@
+use http::types::body::LineBufferMut;
+use regex::Regex;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::{Middleware, Status};
+
+/// Key of the line meta the fields of a template other than app, host and env are attached
+/// under
+pub const META_KEY: &str = "path";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseTemplateError {
+    #[error("path template {0} isn't an absolute path")]
+    NotAbsolute(String),
+    #[error("path template {0} has an unclosed {{")]
+    Unclosed(String),
+    #[error("path template {0} has an invalid field name {1}")]
+    FieldName(String, String),
+    #[error("path template {0} has the field {1} more than once")]
+    Duplicate(String, String),
+}
+
+/// A layout of log file paths with named fields, e.g.
+/// `/var/log/containers/{pod}_{namespace}_{app}-{id}.log`, where each field matches as much
+/// of a path segment as it can while the rest of the path still matches
+#[derive(Debug)]
+pub struct PathTemplate {
+    regex: Regex,
+    fields: Vec<String>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, ParseTemplateError> {
+        if !template.starts_with('/') {
+            return Err(ParseTemplateError::NotAbsolute(template.to_string()));
+        }
+        let mut pattern = String::from("^");
+        let mut fields = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            pattern.push_str(&regex::escape(&rest[..start]));
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| ParseTemplateError::Unclosed(template.to_string()))?;
+            let name = &rest[start + 1..start + end];
+            let valid = name
+                .chars()
+                .next()
+                .map_or(false, |c| c.is_ascii_alphabetic())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(ParseTemplateError::FieldName(
+                    template.to_string(),
+                    name.to_string(),
+                ));
+            }
+            if fields.iter().any(|field| field == name) {
+                return Err(ParseTemplateError::Duplicate(
+                    template.to_string(),
+                    name.to_string(),
+                ));
+            }
+            pattern.push_str(&format!("(?P<{}>[^/]+)", name));
+            fields.push(name.to_string());
+            rest = &rest[start + end + 1..];
+        }
+        pattern.push_str(&regex::escape(rest));
+        pattern.push('$');
+        let regex = Regex::new(&pattern).expect("template fields and escaped text are valid");
+        Ok(PathTemplate { regex, fields })
+    }
+
+    /// The value of each field in `path`, None when the path doesn't follow the template
+    pub fn extract<'a>(&self, path: &'a str) -> Option<Vec<(&str, &'a str)>> {
+        let captures = self.regex.captures(path)?;
+        Some(
+            self.fields
+                .iter()
+                .filter_map(|field| Some((field.as_str(), captures.name(field)?.as_str())))
+                .collect(),
+        )
+    }
+}
+
+/// Sets the app, host and env of lines from the fields of the first template their file
+/// follows, attaching the other fields to their meta
+pub struct PathTemplates {
+    templates: Vec<PathTemplate>,
+}
+
+impl PathTemplates {
+    pub fn new(templates: Vec<PathTemplate>) -> Self {
+        PathTemplates { templates }
+    }
+}
+
+impl Middleware for PathTemplates {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        let fields = match line.get_file() {
+            Some(file) => self
+                .templates
+                .iter()
+                .find_map(|template| template.extract(file))
+                .map(|fields| {
+                    fields
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect::<Vec<_>>()
+                }),
+            None => None,
+        };
+        let fields = match fields {
+            Some(fields) => fields,
+            None => return Status::Ok(line),
+        };
+
+        let mut extracted = Map::new();
+        for (name, value) in fields {
+            let _ = match name.as_str() {
+                "app" => line.set_app(value),
+                "host" => line.set_host(value),
+                "env" => line.set_env(value),
+                _ => {
+                    extracted.insert(name, Value::String(value));
+                    Ok(())
+                }
+            };
+        }
+        if extracted.is_empty() {
+            return Status::Ok(line);
+        }
+        let meta = match line.get_meta() {
+            Some(Value::Object(meta)) => {
+                let mut meta = meta.clone();
+                meta.insert(META_KEY.into(), Value::Object(extracted));
+                meta
+            }
+            Some(_) => return Status::Ok(line),
+            None => {
+                let mut meta = Map::new();
+                meta.insert(META_KEY.into(), Value::Object(extracted));
+                meta
+            }
+        };
+        let _ = line.set_meta(Value::Object(meta));
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMeta};
+
+    #[test]
+    fn extracts_fields_from_paths() {
+        let containers =
+            PathTemplate::parse("/var/log/containers/{pod}_{namespace}_{app}-{id}.log").unwrap();
+        assert_eq!(
+            containers.extract("/var/log/containers/web-6d4f_shop_nginx-proxy-0a1b.log"),
+            Some(vec![
+                ("pod", "web-6d4f"),
+                ("namespace", "shop"),
+                ("app", "nginx-proxy"),
+                ("id", "0a1b"),
+            ])
+        );
+        assert_eq!(containers.extract("/var/log/containers/web.log"), None);
+        assert_eq!(
+            containers.extract("/var/log/containers/a/b_c_d-e.log"),
+            None
+        );
+
+        let templates = PathTemplates::new(vec![
+            containers,
+            PathTemplate::parse("/srv/{env}/{app}/logs/{file}.log").unwrap(),
+        ]);
+        let mut line = LineBuilder::new()
+            .line("started")
+            .file("/srv/staging/billing/logs/server.log");
+        match templates.process(&mut line) {
+            Status::Ok(line) => {
+                assert_eq!(line.get_app(), Some("billing"));
+                assert_eq!(line.get_env(), Some("staging"));
+                assert_eq!(line.get_meta().unwrap()[META_KEY]["file"], "server");
+            }
+            Status::Skip => panic!("line skipped"),
+        }
+
+        let mut line = LineBuilder::new().line("started").file("/var/log/syslog");
+        match templates.process(&mut line) {
+            Status::Ok(line) => assert_eq!(line.get_app(), None),
+            Status::Skip => panic!("line skipped"),
+        }
+
+        assert!(matches!(
+            PathTemplate::parse("logs/{app}.log"),
+            Err(ParseTemplateError::NotAbsolute(_))
+        ));
+        assert!(matches!(
+            PathTemplate::parse("/var/log/{app.log"),
+            Err(ParseTemplateError::Unclosed(_))
+        ));
+        assert!(matches!(
+            PathTemplate::parse("/var/log/{1app}.log"),
+            Err(ParseTemplateError::FieldName(_, _))
+        ));
+        assert!(matches!(
+            PathTemplate::parse("/var/log/{app}/{app}.log"),
+            Err(ParseTemplateError::Duplicate(_, _))
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use http::types::body::LineBufferMut;
+use log::{info, warn};
+use parking_lot::{Mutex, RwLock};
+
+use crate::line_rules::LineRules;
+use crate::Status;
+
+/// Line rules and tags changed while the agent runs, e.g. by a ConfigMap or a remote config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuleSet {
+    pub exclusion: Vec<String>,
+    pub inclusion: Vec<String>,
+    pub redact: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl RuleSet {
+    fn has_line_rules(&self) -> bool {
+        !(self.exclusion.is_empty() && self.inclusion.is_empty() && self.redact.is_empty())
+    }
+}
+
+/// Tags of a [`RuleSet`], taken by the client whenever they change
+#[derive(Clone, Default)]
+pub struct SharedTags(Arc<Mutex<Option<Vec<String>>>>);
+
+impl SharedTags {
+    /// The tags when they changed since the last call
+    pub fn take(&self) -> Option<Vec<String>> {
+        self.0.lock().take()
+    }
+
+    fn set(&self, tags: Vec<String>) {
+        *self.0.lock() = Some(tags);
+    }
+}
+
+/// The rules of the last valid [`RuleSet`] applied, swapped without stopping the lines going
+/// through them
+pub struct ReloadableRules {
+    /// What the rule sets come from, for the logs
+    origin: String,
+    current: Mutex<RuleSet>,
+    rules: RwLock<Option<LineRules>>,
+    tags: SharedTags,
+}
+
+impl ReloadableRules {
+    pub fn new(origin: String) -> Self {
+        ReloadableRules {
+            origin,
+            current: Mutex::new(RuleSet::default()),
+            rules: RwLock::new(None),
+            tags: SharedTags::default(),
+        }
+    }
+
+    pub fn tags(&self) -> SharedTags {
+        self.tags.clone()
+    }
+
+    /// Replaces the rules, unless they are invalid in which case the previous ones are kept.
+    /// Returns whether the rule set was applied.
+    pub fn apply(&self, set: RuleSet) -> bool {
+        let mut current = self.current.lock();
+        if *current == set {
+            return true;
+        }
+        let rules = if set.has_line_rules() {
+            match LineRules::new(&set.exclusion, &set.inclusion, &set.redact) {
+                Ok(rules) => Some(rules),
+                Err(e) => {
+                    warn!(
+                        "ignoring rules of {}, keeping the previous ones: {}",
+                        self.origin, e
+                    );
+                    return false;
+                }
+            }
+        } else {
+            None
+        };
+        info!(
+            "applying {}: {} exclusion, {} inclusion, {} redaction rules and tags {:?}",
+            self.origin,
+            set.exclusion.len(),
+            set.inclusion.len(),
+            set.redact.len(),
+            set.tags
+        );
+        *self.rules.write() = rules;
+        if current.tags != set.tags {
+            self.tags.set(set.tags.clone());
+        }
+        *current = set;
+        true
+    }
+
+    pub fn process<'a>(
+        &self,
+        line: &'a mut dyn LineBufferMut,
+    ) -> Status<&'a mut dyn LineBufferMut> {
+        match self.rules.read().as_ref() {
+            Some(rules) => crate::Middleware::process(rules, line),
+            None => Status::Ok(line),
+        }
+    }
+}
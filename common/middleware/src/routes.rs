@@ -0,0 +1,141 @@
+use http::types::body::LineBufferMut;
+use thiserror::Error;
+
+use crate::overrides::{Selector, SelectorError, SELECTORS};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseRouteError {
+    #[error("ingestion key route {0} must set both name and key")]
+    Missing(String),
+    #[error("ingestion key route {0} has an unknown field {1}, use name or key")]
+    UnknownField(String, String),
+    #[error("ingestion key route {0} has an invalid name, use letters, digits, - and _")]
+    Name(String),
+    #[error("ingestion key route {0} has an invalid path glob, {1}")]
+    Glob(String, String),
+    #[error("ingestion key route {0} has an invalid selector, {}", SELECTORS)]
+    Selector(String),
+    #[error("ingestion key route {0} has an invalid expression, {1}")]
+    Expr(String, String),
+    #[error("ingestion key route name {0} is used by more than one route")]
+    Duplicate(String),
+}
+
+/// The ingestion key the lines a selector matches are sent with, instead of the default one,
+/// through a pipeline of their own named `name`
+pub struct KeyRoute {
+    selector: Selector,
+    pub name: String,
+    pub key: String,
+}
+
+impl KeyRoute {
+    /// Parses `<selector> name=<name> key=<ingestion key>`, where the selector is a glob of
+    /// the file path, a k8s label or a filter expression between braces, as in the field
+    /// overrides. Errors name the route by its selector so that they don't contain the key
+    pub fn parse(rule: &str) -> Result<Self, ParseRouteError> {
+        let (selector, parts) = Selector::split(rule);
+        let route = || selector.to_string();
+        let parsed_selector = Selector::parse(selector).map_err(|e| match e {
+            SelectorError::Glob(e) => ParseRouteError::Glob(route(), e),
+            SelectorError::Expr(e) => ParseRouteError::Expr(route(), e),
+            SelectorError::Invalid => ParseRouteError::Selector(route()),
+        })?;
+
+        let (mut name, mut key) = (None, None);
+        for part in parts.split_whitespace() {
+            let (field, value) = part.split_once('=').unwrap_or((part, ""));
+            let slot = match field {
+                "name" => &mut name,
+                "key" => &mut key,
+                _ => return Err(ParseRouteError::UnknownField(route(), field.to_string())),
+            };
+            *slot = Some(value.to_string()).filter(|value| !value.is_empty());
+        }
+        let (name, key) = match (name, key) {
+            (Some(name), Some(key)) => (name, key),
+            _ => return Err(ParseRouteError::Missing(route())),
+        };
+        // The name is a directory of the retry spool and a label of the metrics
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if !name.chars().all(valid) {
+            return Err(ParseRouteError::Name(route()));
+        }
+        Ok(KeyRoute {
+            selector: parsed_selector,
+            name,
+            key,
+        })
+    }
+
+    pub fn matches(&self, line: &mut dyn LineBufferMut) -> bool {
+        self.selector.matches(line)
+    }
+}
+
+impl std::fmt::Debug for KeyRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("KeyRoute")
+            .field("selector", &self.selector)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::{LineBuilder, LineMetaMut};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn routes_matching_lines() {
+        let nginx = KeyRoute::parse("/var/log/nginx/*.log name=web key=abc").unwrap();
+        assert_eq!(nginx.name, "web");
+        assert_eq!(nginx.key, "abc");
+        let payments = KeyRoute::parse("label.team=payments key=def name=payments").unwrap();
+
+        let mut line = LineBuilder::new()
+            .line("GET /")
+            .file("/var/log/nginx/access.log");
+        assert!(nginx.matches(&mut line));
+        assert!(!payments.matches(&mut line));
+
+        let mut line = LineBuilder::new()
+            .line("paid")
+            .file("/var/log/containers/checkout.log");
+        let mut labels = BTreeMap::new();
+        labels.insert("team".to_string(), "payments".to_string());
+        line.set_labels(labels.into()).unwrap();
+        assert!(payments.matches(&mut line));
+        assert!(!nginx.matches(&mut line));
+
+        let errors =
+            KeyRoute::parse(r#"{app == "api" && level >= "error"} name=errors key=ghi"#).unwrap();
+        assert_eq!(errors.name, "errors");
+        assert!(errors.matches(&mut LineBuilder::new().line("boom").app("api").level("fatal")));
+        assert!(!errors.matches(&mut LineBuilder::new().line("ok").app("api").level("info")));
+        assert!(matches!(
+            KeyRoute::parse("{app ==} name=errors key=ghi"),
+            Err(ParseRouteError::Expr(_, _))
+        ));
+
+        assert_eq!(
+            KeyRoute::parse("/var/log/*.log name=web").unwrap_err(),
+            ParseRouteError::Missing("/var/log/*.log".to_string())
+        );
+        assert_eq!(
+            KeyRoute::parse("/var/log/*.log name=../web key=abc").unwrap_err(),
+            ParseRouteError::Name("/var/log/*.log".to_string())
+        );
+        assert!(matches!(
+            KeyRoute::parse("/var/log/*.log name=web key=abc app=web"),
+            Err(ParseRouteError::UnknownField(_, _))
+        ));
+        assert!(matches!(
+            KeyRoute::parse("nginx name=web key=abc"),
+            Err(ParseRouteError::Selector(_))
+        ));
+        assert!(!format!("{:?}", nginx).contains("abc"));
+    }
+}
@@ -0,0 +1,178 @@
+use regex::bytes::Regex;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Names of the assignments whose values are considered secrets
+const SECRET_LABELS: &str =
+    "password|passwd|pwd|secret|token|api[_-]?key|access[_-]?key|private[_-]?key";
+
+/// How eagerly values are considered secrets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sensitivity {
+    /// Only credentials that are labelled as such, in authorization headers or assignments
+    /// like `password=...`
+    Low,
+    /// Also long high entropy tokens, such as API keys
+    Medium,
+    /// Also shorter and less random tokens, at the cost of more false positives
+    High,
+}
+
+#[derive(Clone, Debug, Error)]
+#[error("{0} is not a valid secret detection sensitivity, use low, medium or high")]
+pub struct ParseSensitivityError(String);
+
+impl FromStr for Sensitivity {
+    type Err = ParseSensitivityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "low" => Ok(Sensitivity::Low),
+            "medium" => Ok(Sensitivity::Medium),
+            "high" => Ok(Sensitivity::High),
+            _ => Err(ParseSensitivityError(s.to_string())),
+        }
+    }
+}
+
+/// Finds values that look like secrets in lines, to redact them along with the values
+/// matching the configured redaction rules
+pub struct SecretDetector {
+    labelled: Vec<Regex>,
+    token: Regex,
+    /// Minimum length and entropy, in bits per character, of tokens considered secrets
+    token_threshold: Option<(usize, f64)>,
+}
+
+impl SecretDetector {
+    pub fn new(sensitivity: Sensitivity) -> Self {
+        let token_threshold = match sensitivity {
+            Sensitivity::Low => None,
+            Sensitivity::Medium => Some((32, 4.3)),
+            Sensitivity::High => Some((20, 3.8)),
+        };
+        SecretDetector {
+            labelled: vec![
+                Regex::new(r"(?i)\b(?:bearer|basic|token)\s+([a-z0-9\-._~+/]+=*)").unwrap(),
+                Regex::new(&format!(
+                    r#"(?i)\b(?:{})["']?\s*[:=]\s*["']?([^\s"'&,;]+)"#,
+                    SECRET_LABELS
+                ))
+                .unwrap(),
+            ],
+            token: Regex::new(r"[A-Za-z0-9+/=_\-]+").unwrap(),
+            token_threshold,
+        }
+    }
+
+    /// The start and end of every secret found in `value`, in no particular order
+    pub fn find(&self, value: &[u8]) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        for regex in self.labelled.iter() {
+            for captures in regex.captures_iter(value) {
+                if let Some(secret) = captures.get(1) {
+                    found.push((secret.start(), secret.end()));
+                }
+            }
+        }
+        if let Some((min_length, min_entropy)) = self.token_threshold {
+            for token in self.token.find_iter(value) {
+                let token = (token.start(), token.end());
+                if looks_random(&value[token.0..token.1], min_length, min_entropy) {
+                    found.push(token);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Whether a token is long and random enough to be a secret, tokens made only of letters
+/// or only of digits are words and numbers rather than keys
+fn looks_random(token: &[u8], min_length: usize, min_entropy: f64) -> bool {
+    token.len() >= min_length
+        && token.iter().any(u8::is_ascii_alphabetic)
+        && token.iter().any(u8::is_ascii_digit)
+        && entropy(token) >= min_entropy
+}
+
+/// Shannon entropy of the bytes of a token, in bits per byte
+fn entropy(token: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in token {
+        counts[*byte as usize] += 1;
+    }
+    let len = token.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(detector: &SecretDetector, line: &str) -> Vec<String> {
+        let mut found = detector.find(line.as_bytes());
+        found.sort_unstable();
+        found
+            .into_iter()
+            .map(|(start, end)| line[start..end].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn finds_labelled_secrets() {
+        let detector = SecretDetector::new(Sensitivity::Low);
+        assert_eq!(
+            redacted(&detector, "Authorization: Bearer abc.def-123"),
+            vec!["abc.def-123"]
+        );
+        assert_eq!(
+            redacted(&detector, "login user=admin password=hunter2 ok"),
+            vec!["hunter2"]
+        );
+        assert_eq!(
+            redacted(&detector, r#"{"api_key": "k-1", "name": "x"}"#),
+            vec!["k-1"]
+        );
+        assert!(redacted(&detector, "the password is required").is_empty());
+    }
+
+    #[test]
+    fn finds_random_tokens_by_sensitivity() {
+        let line = "key aB3dE5gH7jK9mN1pQ2rS4tU6vW8xY0zC3 done";
+        assert!(redacted(&SecretDetector::new(Sensitivity::Low), line).is_empty());
+        assert_eq!(
+            redacted(&SecretDetector::new(Sensitivity::Medium), line),
+            vec!["aB3dE5gH7jK9mN1pQ2rS4tU6vW8xY0zC3"]
+        );
+
+        let line = "request 550e8400-e29b-41d4-a716-446655440000";
+        assert!(redacted(&SecretDetector::new(Sensitivity::High), line).is_empty());
+
+        let line = "commit 3f2a9c1b7d4e8f60a5b2c9d1e7f3a4b6c8d0e2f1";
+        assert!(redacted(&SecretDetector::new(Sensitivity::Medium), line).is_empty());
+        assert_eq!(
+            redacted(&SecretDetector::new(Sensitivity::High), line).len(),
+            1
+        );
+
+        let line = "a_very_long_identifier_made_of_plain_words only";
+        assert!(redacted(&SecretDetector::new(Sensitivity::High), line).is_empty());
+    }
+
+    #[test]
+    fn parses_sensitivity() {
+        assert_eq!(
+            "Medium".parse::<Sensitivity>().unwrap(),
+            Sensitivity::Medium
+        );
+        assert!("extreme".parse::<Sensitivity>().is_err());
+    }
+}
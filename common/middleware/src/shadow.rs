@@ -0,0 +1,105 @@
+use http::types::body::LineBufferMut;
+use metrics::Metrics;
+use regex::bytes::RegexSet;
+
+use crate::expr::Expr;
+use crate::line_rules::LineRulesError;
+use crate::{Middleware, Status};
+
+/// Line rules that are evaluated but not enforced: lines go through unchanged and the ones
+/// each rule would have dropped or redacted are counted in the `shadow` metrics, keyed by the
+/// kind of the rule and its pattern, to see the effect of new rules before enforcing them.
+pub struct ShadowRules {
+    exclusion: RegexSet,
+    inclusion: RegexSet,
+    redact: RegexSet,
+    exclusion_expr: Option<Expr>,
+    inclusion_expr: Option<Expr>,
+}
+
+impl ShadowRules {
+    pub fn new(
+        exclusion: &[String],
+        inclusion: &[String],
+        redact: &[String],
+        exclusion_expr: Option<Expr>,
+        inclusion_expr: Option<Expr>,
+    ) -> Result<ShadowRules, LineRulesError> {
+        Ok(ShadowRules {
+            exclusion: RegexSet::new(exclusion).map_err(LineRulesError::RegexError)?,
+            inclusion: RegexSet::new(inclusion).map_err(LineRulesError::RegexError)?,
+            redact: RegexSet::new(redact).map_err(LineRulesError::RegexError)?,
+            exclusion_expr,
+            inclusion_expr,
+        })
+    }
+
+    fn count(kind: &str, rule: &str) {
+        Metrics::shadow().increment_matches(&format!("{} {}", kind, rule));
+    }
+}
+
+impl Middleware for ShadowRules {
+    fn run(&self) {}
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        if let Some(value) = line.get_line_buffer() {
+            for i in self.exclusion.matches(value).iter() {
+                Self::count("line_exclusion_regex", &self.exclusion.patterns()[i]);
+            }
+            // Inclusion rules drop the lines together, when none of them match
+            if !self.inclusion.is_empty() && !self.inclusion.is_match(value) {
+                Self::count("line_inclusion_regex", &self.inclusion.patterns().join(","));
+            }
+            for i in self.redact.matches(value).iter() {
+                Self::count("line_redact_regex", &self.redact.patterns()[i]);
+            }
+        }
+        if let Some(expr) = self.exclusion_expr.as_ref() {
+            if expr.matches(line) {
+                Self::count("line_exclusion_expr", expr.as_str());
+            }
+        }
+        if let Some(expr) = self.inclusion_expr.as_ref() {
+            if !expr.matches(line) {
+                Self::count("line_inclusion_expr", expr.as_str());
+            }
+        }
+        Status::Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn counts_without_enforcing() {
+        let rules = ShadowRules::new(
+            &["^DEBUG".to_string()],
+            &["payments".to_string()],
+            &[r"\d{4}-\d{4}".to_string()],
+            Some(Expr::parse(r#"line.contains("card")"#).unwrap()),
+            None,
+        )
+        .unwrap();
+        let mut line = LineBuilder::new().line("DEBUG card 1234-5678");
+        match rules.process(&mut line) {
+            Status::Ok(line) => {
+                assert_eq!(line.get_line_buffer(), Some(&b"DEBUG card 1234-5678"[..]))
+            }
+            Status::Skip => panic!("shadow rules should not skip lines"),
+        }
+
+        let matches = Metrics::shadow().read_matches();
+        for rule in [
+            "line_exclusion_regex ^DEBUG",
+            "line_inclusion_regex payments",
+            r"line_redact_regex \d{4}-\d{4}",
+            r#"line_exclusion_expr line.contains("card")"#,
+        ] {
+            assert!(matches.get(rule) >= Some(&1), "{} wasn't counted", rule);
+        }
+    }
+}
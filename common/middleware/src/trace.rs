@@ -0,0 +1,64 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::info;
+
+thread_local! {
+    /// Id of the line traced on this thread, lines are processed on a single thread from the
+    /// middlewares to the sinks
+    static CURRENT: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Picks one in every N lines to log the decision of each stage of the pipeline about it
+pub struct Tracer {
+    every: u64,
+    lines: AtomicU64,
+}
+
+impl Tracer {
+    pub fn new(every: u64) -> Self {
+        Tracer {
+            every: every.max(1),
+            lines: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts tracing the next line when it's sampled, and stops tracing the previous one
+    pub(crate) fn start(&self) -> bool {
+        let line = self.lines.fetch_add(1, Ordering::Relaxed);
+        let id = if line % self.every == 0 {
+            Some(line / self.every + 1)
+        } else {
+            None
+        };
+        CURRENT.with(|current| current.set(id));
+        id.is_some()
+    }
+}
+
+/// Whether the line being processed is traced, to skip working out what to note otherwise
+pub fn active() -> bool {
+    CURRENT.with(|current| current.get().is_some())
+}
+
+/// Logs what `stage` did with the line being processed when it's traced
+pub fn note(stage: &str, decision: impl FnOnce() -> String) {
+    if let Some(id) = CURRENT.with(|current| current.get()) {
+        info!("trace #{}: {} {}", id, stage, decision());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_one_in_every_n_lines() {
+        let tracer = Tracer::new(3);
+        let traced: Vec<bool> = (0..6).map(|_| tracer.start()).collect();
+        assert_eq!(traced, vec![true, false, false, true, false, false]);
+        assert!(!active());
+        assert!(tracer.start());
+        assert!(active());
+    }
+}
@@ -0,0 +1,2 @@
+mod logs;
+pub mod sink;
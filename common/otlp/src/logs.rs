@@ -0,0 +1,175 @@
+use http::types::body::{Line, LineMeta};
+use serde_json::{json, Value};
+
+const SCOPE_NAME: &str = "logdna-agent";
+
+/// Builds the JSON encoding of an OTLP `ExportLogsServiceRequest`.
+///
+/// Lines are grouped into one resource per host and app, which become the `host.name` and
+/// `service.name` resource attributes, the other fields are mapped onto the log records.
+pub(crate) fn export_request(lines: &[Line]) -> Value {
+    let mut resources: Vec<((Option<&str>, Option<&str>), Vec<Value>)> = Vec::new();
+    for line in lines {
+        let key = (line.get_host(), line.get_app());
+        let record = log_record(line);
+        match resources.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, records)) => records.push(record),
+            None => resources.push((key, vec![record])),
+        }
+    }
+
+    let resource_logs: Vec<Value> = resources
+        .into_iter()
+        .map(|((host, app), records)| {
+            let mut attributes = Vec::new();
+            if let Some(host) = host {
+                attributes.push(attribute("host.name", host));
+            }
+            if let Some(app) = app {
+                attributes.push(attribute("service.name", app));
+            }
+            json!({
+                "resource": { "attributes": attributes },
+                "scopeLogs": [{
+                    "scope": { "name": SCOPE_NAME },
+                    "logRecords": records,
+                }],
+            })
+        })
+        .collect();
+    json!({ "resourceLogs": resource_logs })
+}
+
+fn log_record(line: &Line) -> Value {
+    let mut attributes = Vec::new();
+    if let Some(file) = line.get_file() {
+        attributes.push(attribute("log.file.path", file));
+    }
+    if let Some(env) = line.get_env() {
+        attributes.push(attribute("deployment.environment", env));
+    }
+    let maps = [
+        ("label", line.get_labels()),
+        ("annotation", line.get_annotations()),
+    ];
+    for &(prefix, map) in maps.iter() {
+        if let Some(Value::Object(map)) = map.and_then(|m| serde_json::to_value(m).ok()) {
+            for (key, value) in map {
+                if let Value::String(value) = value {
+                    attributes.push(attribute(&format!("{}.{}", prefix, key), &value));
+                }
+            }
+        }
+    }
+    if let Some(meta) = line.get_meta() {
+        attributes.push(json!({ "key": "meta", "value": any_value(meta) }));
+    }
+
+    let mut record = json!({
+        // int64 and fixed64 fields are encoded as strings in the JSON mapping of protobuf
+        "timeUnixNano": (line.timestamp as i128 * 1_000_000).to_string(),
+        "body": { "stringValue": line.line },
+        "attributes": attributes,
+    });
+    if let Some(level) = line.get_level() {
+        record["severityText"] = level.into();
+        if let Some(number) = severity_number(level) {
+            record["severityNumber"] = number.into();
+        }
+    }
+    record
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// Maps a JSON value onto an OTLP `AnyValue`
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({}),
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Array(values) => {
+            let values: Vec<Value> = values.iter().map(any_value).collect();
+            json!({ "arrayValue": { "values": values } })
+        }
+        Value::Object(map) => {
+            let values: Vec<Value> = map
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+                .collect();
+            json!({ "kvlistValue": { "values": values } })
+        }
+    }
+}
+
+/// The OTLP severity number of the first value of each severity range
+fn severity_number(level: &str) -> Option<u8> {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => Some(1),
+        "DEBUG" => Some(5),
+        "INFO" | "NOTICE" => Some(9),
+        "WARN" | "WARNING" => Some(13),
+        "ERROR" | "ERR" => Some(17),
+        "FATAL" | "CRITICAL" | "CRIT" | "EMERG" | "ALERT" | "PANIC" => Some(21),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn groups_lines_by_resource() {
+        let mut lines: Vec<Line> = ["a", "b", "c"]
+            .iter()
+            .zip(["web", "db", "web"].iter())
+            .map(|(line, app)| {
+                LineBuilder::new()
+                    .line(*line)
+                    .app(*app)
+                    .host("node-1")
+                    .level("warn")
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        lines[0].timestamp = 1_622_548_800_123;
+
+        let request = export_request(&lines);
+        let resources = request["resourceLogs"].as_array().unwrap();
+        assert_eq!(resources.len(), 2);
+        assert_eq!(
+            resources[0]["resource"]["attributes"][1]["value"]["stringValue"],
+            "web"
+        );
+
+        let records = resources[0]["scopeLogs"][0]["logRecords"]
+            .as_array()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["body"]["stringValue"], "a");
+        assert_eq!(records[0]["timeUnixNano"], "1622548800123000000");
+        assert_eq!(records[0]["severityNumber"], 13);
+        assert_eq!(records[1]["body"]["stringValue"], "c");
+    }
+
+    #[test]
+    fn maps_meta_to_any_value() {
+        let meta = json!({ "user": { "id": 7, "admin": false } });
+        assert_eq!(
+            any_value(&meta),
+            json!({ "kvlistValue": { "values": [{ "key": "user", "value": { "kvlistValue": {
+                "values": [
+                    { "key": "admin", "value": { "boolValue": false } },
+                    { "key": "id", "value": { "intValue": "7" } },
+                ]
+            }}}]}})
+        );
+    }
+}
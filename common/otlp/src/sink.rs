@@ -0,0 +1,199 @@
+use crate::logs::export_request;
+
+use http::types::body::Line;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use log::info;
+use metrics::Metrics;
+use sink::{Policy, PoolOptions, Proxy, ProxyConnector, Queue, SignError, Signer, TlsOptions};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+const LOGS_PATH: &str = "/v1/logs";
+const MAX_BATCH_LINES: usize = 1_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// OTLP/HTTP endpoint of the collector, e.g. `https://collector:4318`
+    pub endpoint: String,
+    /// Extra headers sent with every export, e.g. for authentication
+    pub headers: Vec<(String, String)>,
+    /// Proxy the collector is connected to through
+    pub proxy: Option<Proxy>,
+    pub tls_options: TlsOptions,
+    pub pool: PoolOptions,
+    /// Signs the requests for gateways in front of the collector that require it
+    pub signer: Option<Arc<dyn Signer>>,
+    pub policy: Policy,
+}
+
+/// Starts exporting lines as OpenTelemetry log records over OTLP/HTTP with JSON encoding.
+/// Must be called from within a tokio runtime.
+pub fn spawn(options: Options) -> Result<Queue, String> {
+    let endpoint = options.endpoint.trim_end_matches('/');
+    let uri = if endpoint.ends_with(LOGS_PATH) {
+        endpoint.to_string()
+    } else {
+        format!("{}{}", endpoint, LOGS_PATH)
+    };
+    let uri = uri
+        .parse::<Uri>()
+        .map_err(|e| format!("invalid otlp endpoint {}: {}", options.endpoint, e))?;
+    let headers = options
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("invalid otlp header {}: {}", name, e))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| format!("invalid value for otlp header {}: {}", name, e))?;
+            Ok((name, value))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    info!("exporting lines to otlp collector at {}", uri);
+
+    let (queue, rx) = sink::queue("otlp exporter", options.policy.buffer_size, || {
+        Metrics::otlp().increment_dropped()
+    });
+    let exporter = Exporter {
+        client: sink::https_client(
+            "otlp exporter",
+            options.proxy,
+            &options.tls_options,
+            &options.pool,
+        ),
+        signer: options.signer,
+        uri,
+        headers,
+        policy: options.policy,
+        batch: Vec::with_capacity(MAX_BATCH_LINES),
+    };
+    tokio::spawn(exporter.run(rx));
+    Ok(queue)
+}
+
+struct Exporter {
+    client: Client<HttpsConnector<ProxyConnector>>,
+    signer: Option<Arc<dyn Signer>>,
+    uri: Uri,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    policy: Policy,
+    batch: Vec<Line>,
+}
+
+enum ExportError {
+    Status(StatusCode),
+    Http(hyper::Error),
+    Sign(SignError),
+}
+
+impl ExportError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            // The statuses the OTLP specification marks as retryable
+            ExportError::Status(status) => matches!(
+                *status,
+                StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            ExportError::Http(_) => true,
+            ExportError::Sign(_) => false,
+        }
+    }
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ExportError::Status(status) => write!(f, "{}", status),
+            ExportError::Http(e) => write!(f, "{}", e),
+            ExportError::Sign(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Exporter {
+    async fn run(mut self, mut rx: Receiver<Line>) {
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => {
+                        self.batch.push(line);
+                        if self.batch.len() >= MAX_BATCH_LINES {
+                            self.flush().await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    async fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let lines = self.batch.len() as u64;
+        let body = export_request(&self.batch).to_string();
+        self.batch.clear();
+        Metrics::otlp().increment_requests();
+
+        let exported = self
+            .policy
+            .retry_if(
+                "otlp export",
+                || self.send(body.clone()),
+                ExportError::is_retryable,
+            )
+            .await;
+        match exported {
+            Some(_) => Metrics::otlp().add_lines(lines),
+            None => Metrics::otlp().add_failures(lines),
+        }
+    }
+
+    async fn send(&self, body: String) -> Result<(), ExportError> {
+        let body = hyper::body::Bytes::from(body);
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        for (name, value) in self.headers.iter() {
+            request = request.header(name, value);
+        }
+        let mut request = request
+            .body(Body::from(body.clone()))
+            .expect("export request is valid");
+        if let Some(signer) = self.signer.as_ref() {
+            signer
+                .sign(&mut request, &body)
+                .await
+                .map_err(ExportError::Sign)?;
+        }
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(ExportError::Http)?;
+        let status = response.status();
+        // Drain the body so the connection can be reused
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(ExportError::Http)?;
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(ExportError::Status(status))
+        }
+    }
+}
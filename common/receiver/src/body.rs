@@ -0,0 +1,121 @@
+use futures::TryStreamExt;
+use hyper::{Body, Request, StatusCode};
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+
+/// Largest request body accepted, once decompressed
+pub(crate) const MAX_BODY_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub(crate) enum BodyError {
+    #[error("request body is over {0} bytes")]
+    TooLarge(u64),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl BodyError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            BodyError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            BodyError::Io(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Reads a request body, decompressing it when it was sent with `Content-Encoding: gzip`.
+/// Bodies over `max_bytes`, before or after decompression, are rejected without being read
+/// any further.
+pub(crate) async fn read_body(req: Request<Body>, max_bytes: u64) -> Result<Vec<u8>, BodyError> {
+    let length = req
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<u64>().ok());
+    if length.map_or(false, |length| length > max_bytes) {
+        return Err(BodyError::TooLarge(max_bytes));
+    }
+    let gzip = req
+        .headers()
+        .get(hyper::header::CONTENT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .map(|e| e.eq_ignore_ascii_case("gzip"))
+        .unwrap_or(false);
+
+    let mut bytes = Vec::new();
+    // A byte past the limit is read to tell a body that is over it from one that is at it
+    let mut body = tokio_util::io::StreamReader::new(
+        req.into_body()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+    )
+    .take(max_bytes + 1);
+    if gzip {
+        let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(body);
+        let read = (&mut decoder)
+            .take(max_bytes + 1)
+            .read_to_end(&mut bytes)
+            .await;
+        // The compressed body was cut short, which fails decompressing it
+        if decoder.get_ref().limit() == 0 {
+            return Err(BodyError::TooLarge(max_bytes));
+        }
+        read?;
+    } else {
+        body.read_to_end(&mut bytes).await?;
+    }
+    if bytes.len() as u64 > max_bytes {
+        return Err(BodyError::TooLarge(max_bytes));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::write::GzipEncoder;
+    use tokio::io::AsyncWriteExt;
+
+    async fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzipEncoder::new(Vec::new());
+        encoder.write_all(bytes).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder.into_inner()
+    }
+
+    fn request(body: Vec<u8>, gzip: bool) -> Request<Body> {
+        let mut builder = Request::builder();
+        if gzip {
+            builder = builder.header(hyper::header::CONTENT_ENCODING, "gzip");
+        }
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_over_the_limit() {
+        let body = read_body(request(vec![b'a'; 16], false), 16).await.unwrap();
+        assert_eq!(body.len(), 16);
+        let err = read_body(request(vec![b'a'; 17], false), 16)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = Request::builder()
+            .header(hyper::header::CONTENT_LENGTH, "17")
+            .body(Body::from(vec![b'a'; 17]))
+            .unwrap();
+        assert!(matches!(
+            read_body(req, 16).await,
+            Err(BodyError::TooLarge(16))
+        ));
+
+        // Highly compressed bodies are limited once decompressed
+        let compressed = gzip(&[b'a'; 1024]).await;
+        assert!(compressed.len() < 64);
+        let body = read_body(request(compressed.clone(), true), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 1024);
+        let err = read_body(request(compressed, true), 64).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
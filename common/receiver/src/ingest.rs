@@ -0,0 +1,268 @@
+use crate::body::{read_body, MAX_BODY_BYTES};
+use crate::server::{self, Handler, TlsFiles};
+
+use futures::Stream;
+use http::types::body::{LineBuilder, LineMetaMut};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use metrics::Metrics;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+
+const PATHS: [&str; 2] = ["/logs/ingest", "/logs/agent"];
+
+/// Label the tags of a request are kept in, as the lines themselves have no tags
+const TAGS_LABEL: &str = "tags";
+
+#[derive(Debug, Deserialize)]
+struct IngestBody {
+    lines: Vec<IngestLine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IngestLine {
+    line: String,
+    /// When the line was read, in milliseconds since the epoch
+    timestamp: Option<i64>,
+    app: Option<String>,
+    level: Option<String>,
+    file: Option<String>,
+    env: Option<String>,
+    meta: Option<Value>,
+    // Sent by edge agents forwarding to an aggregator
+    labels: Option<BTreeMap<String, String>>,
+    annotations: Option<BTreeMap<String, String>>,
+}
+
+/// Accepts LogDNA ingest API requests from local applications, or from edge agents when the
+/// agent is an aggregator, and forwards their lines through the agent, so they get the same
+/// buffering, retries and enrichment as tailed files.
+///
+/// When `key` is set requests must authenticate with it, either with an `apikey` header or
+/// query parameter or as the basic auth username. Lines are yielded with the time they were
+/// sent with, which replaces the time they go through the agent at. Must be called from
+/// within a tokio runtime.
+pub fn create_source(
+    address: SocketAddr,
+    key: Option<String>,
+    tls: Option<TlsFiles>,
+) -> impl Stream<Item = (LineBuilder, Option<i64>)> {
+    let (tx, rx) = channel(1024);
+    let key = Arc::new(key);
+    let handler: Handler = Arc::new(move |req| Box::pin(handle(req, tx.clone(), key.clone())));
+    server::spawn("ingest", address, tls, handler);
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    })
+}
+
+async fn handle(
+    req: Request<Body>,
+    tx: Sender<(LineBuilder, Option<i64>)>,
+    key: Arc<Option<String>>,
+) -> Response<Body> {
+    Metrics::receiver().increment_requests();
+    if req.method() != Method::POST || !PATHS.contains(&req.uri().path()) {
+        return respond(StatusCode::NOT_FOUND, "not found");
+    }
+
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|q| {
+            url::form_urlencoded::parse(q.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(key) = key.as_ref() {
+        if !is_authorized(&req, &params, key) {
+            return respond(StatusCode::UNAUTHORIZED, "invalid api key");
+        }
+    }
+
+    let body = match read_body(req, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return respond(e.status(), &e.to_string()),
+    };
+    let body: IngestBody = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return respond(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let hostname = params.get("hostname");
+    let tags = params.get("tags").map(|tags| {
+        tags.split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    for line in body.lines {
+        Metrics::receiver().increment_lines();
+        let mut builder = LineBuilder::new().line(line.line);
+        if let Some(host) = hostname {
+            builder = builder.host(host);
+        }
+        if let Some(app) = line.app {
+            builder = builder.app(app);
+        }
+        if let Some(level) = line.level {
+            builder = builder.level(level);
+        }
+        if let Some(file) = line.file {
+            builder = builder.file(file);
+        }
+        if let Some(env) = line.env {
+            let _ = builder.set_env(env);
+        }
+        if let Some(meta) = line.meta {
+            let _ = builder.set_meta(meta);
+        }
+        let mut labels = line.labels.unwrap_or_default();
+        if let Some(tags) = tags.as_ref().filter(|tags| !tags.is_empty()) {
+            labels
+                .entry(TAGS_LABEL.to_string())
+                .or_insert_with(|| tags.clone());
+        }
+        if !labels.is_empty() {
+            let _ = builder.set_labels(labels.into());
+        }
+        if let Some(annotations) = line.annotations {
+            let _ = builder.set_annotations(annotations.into());
+        }
+        if tx.send((builder, line.timestamp)).await.is_err() {
+            return respond(StatusCode::SERVICE_UNAVAILABLE, "agent is shutting down");
+        }
+    }
+
+    respond(StatusCode::OK, "ok")
+}
+
+fn is_authorized(req: &Request<Body>, params: &HashMap<String, String>, key: &str) -> bool {
+    let headers = req.headers();
+    if let Some(apikey) = headers.get("apikey").and_then(|v| v.to_str().ok()) {
+        return is_key(apikey, key);
+    }
+    if let Some(apikey) = params.get("apikey") {
+        return is_key(apikey, key);
+    }
+    headers
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|v| base64::decode(v).ok())
+        .map(|credentials| {
+            let credentials = String::from_utf8_lossy(&credentials);
+            credentials
+                .split(':')
+                .next()
+                .map_or(false, |user| is_key(user, key))
+        })
+        .unwrap_or(false)
+}
+
+/// Compares in constant time, so that the time taken doesn't tell how much of a key matched
+fn is_key(candidate: &str, key: &str) -> bool {
+    ring::constant_time::verify_slices_are_equal(candidate.as_bytes(), key.as_bytes()).is_ok()
+}
+
+pub(crate) fn respond(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "status": message }).to_string();
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("response is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, auth: Option<(&str, &str)>) -> Request<Body> {
+        let mut builder = Request::builder().method(Method::POST).uri(uri);
+        if let Some((name, value)) = auth {
+            builder = builder.header(name, value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn authorizes_api_keys() {
+        let no_params = HashMap::new();
+        assert!(is_authorized(
+            &request("/logs/ingest", Some(("apikey", "secret"))),
+            &no_params,
+            "secret"
+        ));
+        assert!(!is_authorized(
+            &request("/logs/ingest", Some(("apikey", "wrong"))),
+            &no_params,
+            "secret"
+        ));
+        assert!(is_authorized(
+            &request(
+                "/logs/ingest",
+                Some((
+                    "authorization",
+                    &format!("Basic {}", base64::encode("secret:"))
+                ))
+            ),
+            &no_params,
+            "secret"
+        ));
+        assert!(!is_authorized(
+            &request("/logs/ingest", None),
+            &no_params,
+            "secret"
+        ));
+
+        let mut params = HashMap::new();
+        params.insert("apikey".to_string(), "secret".to_string());
+        assert!(is_authorized(
+            &request("/logs/ingest", None),
+            &params,
+            "secret"
+        ));
+    }
+
+    #[tokio::test]
+    async fn forwards_ingested_lines() {
+        let (tx, mut rx) = channel(10);
+        let body = r#"{"lines":[{"line":"hello","app":"web","level":"INFO"},
+            {"line":"world","timestamp":1622548800123,"labels":{"team":"web"}}]}"#;
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/logs/ingest?hostname=sidecar&tags=edge,%20us-east")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = handle(req, tx, Arc::new(None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (first, timestamp) = rx.recv().await.unwrap();
+        assert_eq!(first.line.as_deref(), Some("hello"));
+        assert_eq!(first.app.as_deref(), Some("web"));
+        assert_eq!(first.host.as_deref(), Some("sidecar"));
+        assert_eq!(timestamp, None);
+        let (second, timestamp) = rx.recv().await.unwrap();
+        assert_eq!(second.line.as_deref(), Some("world"));
+        assert_eq!(timestamp, Some(1_622_548_800_123));
+        let labels = second.get_labels().unwrap();
+        assert_eq!(labels.get("team"), Some(&"web".to_string()));
+        assert_eq!(labels.get("tags"), Some(&"edge,us-east".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_paths() {
+        let (tx, _rx) = channel(10);
+        let response = handle(request("/other", None), tx, Arc::new(None)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
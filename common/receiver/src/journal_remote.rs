@@ -0,0 +1,303 @@
+use crate::ingest::respond;
+use crate::server::{self, Handler, TlsFiles};
+
+use futures::{Stream, StreamExt};
+use http::types::body::LineBuilder;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::debug;
+use metrics::Metrics;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+
+const UPLOAD_PATH: &str = "/upload";
+const MAX_FIELD_SIZE: u64 = 64 * 1024 * 1024;
+/// Entries are held in memory until they are complete, as well as the fields they're made of
+const MAX_ENTRY_SIZE: u64 = 128 * 1024 * 1024;
+const DEFAULT_APP: &str = "UNKNOWN_SYSTEMD_APP";
+
+const KEY_MESSAGE: &str = "MESSAGE";
+const KEY_HOSTNAME: &str = "_HOSTNAME";
+const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
+const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
+const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ParseError {
+    #[error("journal field {0} is larger than {} bytes", MAX_FIELD_SIZE)]
+    FieldTooLarge(String),
+    #[error("binary journal field {0} is not terminated by a newline")]
+    Unterminated(String),
+    #[error("journal entry is larger than {0} bytes")]
+    EntryTooLarge(u64),
+}
+
+pub type Entry = HashMap<String, Vec<u8>>;
+
+/// Incremental parser for the journal export format sent by `systemd-journal-upload`.
+///
+/// Text fields are sent as `KEY=value\n`, binary fields as `KEY\n` followed by the little
+/// endian 64 bit length of the data, the data itself and `\n`. Entries end with an empty line.
+#[derive(Debug)]
+pub struct ExportParser {
+    buf: Vec<u8>,
+    entry: Entry,
+    /// Bytes of the fields of `entry`
+    entry_size: u64,
+    max_entry_size: u64,
+}
+
+impl Default for ExportParser {
+    fn default() -> Self {
+        Self {
+            buf: Vec::new(),
+            entry: Entry::new(),
+            entry_size: 0,
+            max_entry_size: MAX_ENTRY_SIZE,
+        }
+    }
+}
+
+impl ExportParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next chunk of the upload, returning the entries it completed
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Entry>, ParseError> {
+        self.buf.extend_from_slice(data);
+        let mut entries = Vec::new();
+        let mut pos = 0;
+
+        while pos < self.buf.len() {
+            if self.buf[pos] == b'\n' {
+                if !self.entry.is_empty() {
+                    entries.push(std::mem::take(&mut self.entry));
+                }
+                self.entry_size = 0;
+                pos += 1;
+                continue;
+            }
+
+            let newline = match self.buf[pos..].iter().position(|b| *b == b'\n') {
+                Some(offset) => pos + offset,
+                None => break,
+            };
+            let line = &self.buf[pos..newline];
+
+            if let Some(eq) = line.iter().position(|b| *b == b'=') {
+                let key = String::from_utf8_lossy(&line[..eq]).into_owned();
+                self.entry_size += line.len() as u64;
+                self.entry.insert(key, line[eq + 1..].to_vec());
+                pos = newline + 1;
+                continue;
+            }
+
+            let key = String::from_utf8_lossy(line).into_owned();
+            let data_start = newline + 1 + 8;
+            if self.buf.len() < data_start {
+                break;
+            }
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&self.buf[newline + 1..data_start]);
+            let len = u64::from_le_bytes(len);
+            if len > MAX_FIELD_SIZE {
+                return Err(ParseError::FieldTooLarge(key));
+            }
+            if self.entry_size + len > self.max_entry_size {
+                return Err(ParseError::EntryTooLarge(self.max_entry_size));
+            }
+            let data_end = data_start + len as usize;
+            if self.buf.len() <= data_end {
+                break;
+            }
+            if self.buf[data_end] != b'\n' {
+                return Err(ParseError::Unterminated(key));
+            }
+            self.entry_size += (data_end - pos) as u64;
+            self.entry
+                .insert(key, self.buf[data_start..data_end].to_vec());
+            pos = data_end + 1;
+        }
+
+        // The field waiting for more data counts towards the size of its entry
+        if self.entry_size + (self.buf.len() - pos) as u64 > self.max_entry_size {
+            return Err(ParseError::EntryTooLarge(self.max_entry_size));
+        }
+        self.buf.drain(..pos);
+        Ok(entries)
+    }
+}
+
+/// Accepts journal uploads from `systemd-journal-upload` on other hosts, e.g. with
+/// `systemd-journal-upload --url=https://agent-host:19532`, and forwards their entries.
+///
+/// Uploads are served over TLS when `tls` is set, as `systemd-journal-upload` expects for
+/// `https` urls. Must be called from within a tokio runtime.
+pub fn create_source(
+    address: SocketAddr,
+    tls: Option<TlsFiles>,
+) -> impl Stream<Item = LineBuilder> {
+    let (tx, rx) = channel(1024);
+    let handler: Handler = Arc::new(move |req| Box::pin(handle(req, tx.clone())));
+    server::spawn("journal upload", address, tls, handler);
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    })
+}
+
+async fn handle(req: Request<Body>, tx: Sender<LineBuilder>) -> Response<Body> {
+    Metrics::receiver().increment_requests();
+    if req.method() != Method::POST || req.uri().path() != UPLOAD_PATH {
+        return respond(StatusCode::NOT_FOUND, "not found");
+    }
+
+    let mut parser = ExportParser::new();
+    let mut body = req.into_body();
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                debug!("journal upload was interrupted: {}", e);
+                return respond(StatusCode::BAD_REQUEST, &e.to_string());
+            }
+        };
+        let entries = match parser.push(&chunk) {
+            Ok(entries) => entries,
+            Err(e) => return respond(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        for entry in entries {
+            if let Some(line) = to_line(&entry) {
+                if tx.send(line).await.is_err() {
+                    return respond(StatusCode::SERVICE_UNAVAILABLE, "agent is shutting down");
+                }
+            }
+        }
+    }
+
+    respond(StatusCode::OK, "ok")
+}
+
+/// Builds a line the same way the local journald source does, tagged with the sending host
+fn to_line(entry: &Entry) -> Option<LineBuilder> {
+    let field = |key| {
+        entry
+            .get(key)
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+    };
+
+    let message = field(KEY_MESSAGE)?;
+    let app = field(KEY_CONTAINER_NAME)
+        .or_else(|| field(KEY_SYSTEMD_UNIT))
+        .or_else(|| field(KEY_SYSLOG_IDENTIFIER))
+        .unwrap_or_else(|| DEFAULT_APP.into());
+
+    Metrics::receiver().increment_lines();
+    let mut line = LineBuilder::new().line(message).file(app);
+    if let Some(host) = field(KEY_HOSTNAME) {
+        line = line.host(host);
+    }
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_field(key: &str, data: &[u8]) -> Vec<u8> {
+        let mut field = format!("{}\n", key).into_bytes();
+        field.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        field.extend_from_slice(data);
+        field.push(b'\n');
+        field
+    }
+
+    #[test]
+    fn parses_text_and_binary_fields() {
+        let mut upload = b"__CURSOR=s=1\nMESSAGE=first\n_HOSTNAME=appliance\n\n".to_vec();
+        upload.extend(binary_field("MESSAGE", b"multi\nline"));
+        upload.extend_from_slice(b"_SYSTEMD_UNIT=sshd.service\n\n");
+
+        let entries = ExportParser::new().push(&upload).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["MESSAGE"], b"first");
+        assert_eq!(entries[0]["_HOSTNAME"], b"appliance");
+        assert_eq!(entries[1]["MESSAGE"], b"multi\nline");
+        assert_eq!(entries[1]["_SYSTEMD_UNIT"], b"sshd.service");
+    }
+
+    #[test]
+    fn parses_entries_split_across_chunks() {
+        let mut upload = b"MESSAGE=hello\n".to_vec();
+        upload.extend(binary_field("DATA", b"a=b"));
+        upload.push(b'\n');
+
+        let mut parser = ExportParser::new();
+        let mut entries = Vec::new();
+        for byte in &upload {
+            entries.extend(parser.push(&[*byte]).unwrap());
+        }
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["MESSAGE"], b"hello");
+        assert_eq!(entries[0]["DATA"], b"a=b");
+    }
+
+    #[test]
+    fn rejects_oversized_fields() {
+        let mut upload = b"MESSAGE\n".to_vec();
+        upload.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            ExportParser::new().push(&upload),
+            Err(ParseError::FieldTooLarge("MESSAGE".into()))
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_entries() {
+        let mut parser = ExportParser::new();
+        parser.max_entry_size = 32;
+        let entries = parser.push(b"MESSAGE=first\n\nMESSAGE=second\n\n").unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // Fields of the same entry add up, as do fields without their newline yet
+        assert_eq!(
+            parser.push(b"A=0123456789\nB=0123456789\n").unwrap().len(),
+            0
+        );
+        assert_eq!(
+            parser.push(b"C=0123456789"),
+            Err(ParseError::EntryTooLarge(32))
+        );
+
+        let mut parser = ExportParser::new();
+        parser.max_entry_size = 32;
+        assert_eq!(parser.push(&[b'a'; 33]), Err(ParseError::EntryTooLarge(32)));
+    }
+
+    #[tokio::test]
+    async fn forwards_uploaded_entries() {
+        let (tx, mut rx) = channel(10);
+        let body = "MESSAGE=started\n_SYSTEMD_UNIT=nginx.service\n_HOSTNAME=edge-1\n\n\
+                    MESSAGE=no unit\n\n\
+                    _HOSTNAME=edge-1\n\n";
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(UPLOAD_PATH)
+            .header(hyper::header::CONTENT_TYPE, "application/vnd.fdo.journal")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = handle(req, tx).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let first = rx.recv().await.unwrap();
+        assert_eq!(first.line.as_deref(), Some("started"));
+        assert_eq!(first.file.as_deref(), Some("nginx.service"));
+        assert_eq!(first.host.as_deref(), Some("edge-1"));
+        let second = rx.recv().await.unwrap();
+        assert_eq!(second.file.as_deref(), Some(DEFAULT_APP));
+        assert!(rx.recv().await.is_none());
+    }
+}
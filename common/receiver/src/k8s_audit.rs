@@ -0,0 +1,111 @@
+use crate::body::{read_body, MAX_BODY_BYTES};
+use crate::ingest::respond;
+use crate::server::{self, Handler, TlsFiles};
+
+use futures::Stream;
+use http::types::body::{LineBuilder, LineMetaMut};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use metrics::Metrics;
+use middleware::k8s_audit::{summarize, META_KEY};
+use serde_json::{Map, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+
+/// App of the lines of audit events received through the webhook
+const APP: &str = "kube-apiserver-audit";
+
+/// Accepts the batches of audit events kube-apiserver posts to a webhook backend, configured
+/// with `--audit-webhook-config-file`, and forwards each event as a line with its fields in
+/// the `k8s_audit` meta. Must be called from within a tokio runtime.
+pub fn create_source(
+    address: SocketAddr,
+    tls: Option<TlsFiles>,
+) -> impl Stream<Item = LineBuilder> {
+    let (tx, rx) = channel(1024);
+    let handler: Handler = Arc::new(move |req| Box::pin(handle(req, tx.clone())));
+    server::spawn("k8s audit", address, tls, handler);
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    })
+}
+
+async fn handle(req: Request<Body>, tx: Sender<LineBuilder>) -> Response<Body> {
+    Metrics::receiver().increment_requests();
+    if req.method() != Method::POST {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let body = match read_body(req, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return respond(e.status(), &e.to_string()),
+    };
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return respond(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    // Webhooks send an EventList, a single Event is accepted too for manual testing
+    let events = match body.get("items").and_then(Value::as_array) {
+        Some(items) => items.clone(),
+        None => vec![body],
+    };
+
+    for event in events {
+        let summary = match summarize(&event) {
+            Some(summary) => summary,
+            None => return respond(StatusCode::BAD_REQUEST, "not an audit event"),
+        };
+        Metrics::receiver().increment_lines();
+        let mut builder = LineBuilder::new().line(event.to_string()).app(APP);
+        let mut meta = Map::new();
+        meta.insert(META_KEY.into(), summary);
+        let _ = builder.set_meta(Value::Object(meta));
+        if tx.send(builder).await.is_err() {
+            return respond(StatusCode::SERVICE_UNAVAILABLE, "agent is shutting down");
+        }
+    }
+
+    respond(StatusCode::OK, "ok")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineMeta;
+
+    #[tokio::test]
+    async fn forwards_audit_events() {
+        let (tx, mut rx) = channel(10);
+        let body = r#"{"kind":"EventList","apiVersion":"audit.k8s.io/v1","items":[
+            {"kind":"Event","apiVersion":"audit.k8s.io/v1","verb":"delete",
+             "user":{"username":"jane"},"objectRef":{"resource":"pods","name":"web-1"}}
+        ]}"#;
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/audit")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = handle(req, tx).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let line = rx.recv().await.unwrap();
+        assert_eq!(line.app.as_deref(), Some(APP));
+        let meta = line.get_meta().unwrap();
+        assert_eq!(meta[META_KEY]["verb"], "delete");
+        assert_eq!(meta[META_KEY]["user"], "jane");
+        assert_eq!(meta[META_KEY]["name"], "web-1");
+    }
+
+    #[tokio::test]
+    async fn rejects_other_bodies() {
+        let (tx, _rx) = channel(10);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/audit")
+            .body(Body::from(r#"{"lines":[]}"#))
+            .unwrap();
+        let response = handle(req, tx).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
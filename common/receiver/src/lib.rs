@@ -0,0 +1,9 @@
+mod body;
+pub mod ingest;
+pub mod journal_remote;
+pub mod k8s_audit;
+mod server;
+#[cfg(feature = "status")]
+pub mod status;
+
+pub use server::TlsFiles;
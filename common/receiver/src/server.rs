@@ -0,0 +1,124 @@
+use futures::future::BoxFuture;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use log::{debug, info, warn};
+use rustls::internal::pemfile;
+use rustls::{AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+pub(crate) type Handler =
+    Arc<dyn Fn(Request<Body>) -> BoxFuture<'static, Response<Body>> + Send + Sync>;
+
+/// PEM encoded certificate chain and private key used to serve requests over TLS
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// PEM encoded CAs that sign the certificates clients have to present, when set
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Serves `handler` on `address` in the background, over TLS when `tls` is set
+pub(crate) fn spawn(
+    name: &'static str,
+    address: SocketAddr,
+    tls: Option<TlsFiles>,
+    handler: Handler,
+) {
+    tokio::spawn(async move {
+        let acceptor = match tls.as_ref().map(load_acceptor).transpose() {
+            Ok(acceptor) => acceptor,
+            Err(e) => {
+                warn!(
+                    "unable to load tls certificate for {} listener: {}",
+                    name, e
+                );
+                return;
+            }
+        };
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!(
+                    "unable to listen for {} requests on {}: {}",
+                    name, address, e
+                );
+                return;
+            }
+        };
+        info!("listening for {} requests on {}", name, address);
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("unable to accept {} connection: {}", name, e);
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let response = handler(req);
+                    async move { Ok::<_, Infallible>(response.await) }
+                });
+                let result = match acceptor {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => Http::new().serve_connection(stream, service).await,
+                        Err(e) => {
+                            debug!("tls handshake with {} failed: {}", peer, e);
+                            return;
+                        }
+                    },
+                    None => Http::new().serve_connection(stream, service).await,
+                };
+                if let Err(e) = result {
+                    debug!("{} connection from {} failed: {}", name, peer, e);
+                }
+            });
+        }
+    });
+}
+
+fn load_acceptor(tls: &TlsFiles) -> Result<TlsAcceptor, io::Error> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(&tls.cert)?))
+        .map_err(|_| invalid("invalid certificate"))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(&tls.key)?))
+        .map_err(|_| invalid("invalid private key"))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(&tls.key)?))
+            .map_err(|_| invalid("invalid private key"))?;
+    }
+    if keys.is_empty() {
+        return Err(invalid("no private key found"));
+    }
+
+    let mut config = match tls.client_ca.as_ref() {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots
+                .add_pem_file(&mut BufReader::new(File::open(client_ca)?))
+                .map_err(|_| invalid("invalid client ca"))?;
+            if added == 0 {
+                return Err(invalid("no client ca found"));
+            }
+            ServerConfig::new(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => ServerConfig::new(NoClientAuth::new()),
+    };
+    config
+        .set_single_cert(certs, keys.remove(0))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,81 @@
+use crate::ingest::respond;
+use crate::server::{self, Handler};
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use metrics::Metrics;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+const METRICS_PATH: &str = "/debug/metrics.json";
+const HEALTH_PATH: &str = "/health";
+
+/// Serves the state of the agent to local tooling, `/debug/metrics.json` answers with the
+/// current value of every metric and `/health` fails while a pipeline stage is stalled.
+/// Must be called from within a tokio runtime.
+pub fn spawn(address: SocketAddr) {
+    let handler: Handler = Arc::new(|req| Box::pin(handle(req)));
+    server::spawn("status", address, None, handler);
+}
+
+async fn handle(req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    match req.uri().path() {
+        METRICS_PATH => match serde_json::to_string(&Metrics::snapshot()) {
+            Ok(body) => Response::builder()
+                .status(StatusCode::OK)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .expect("response is valid"),
+            Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        HEALTH_PATH => {
+            let stalled = Metrics::watchdog().read_stalled();
+            if stalled.is_empty() {
+                respond(StatusCode::OK, "ok")
+            } else {
+                respond(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    &format!("stalled: {}", stalled.join(",")),
+                )
+            }
+        }
+        _ => respond(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_and_health() {
+        let response = handle(request(Method::GET, METRICS_PATH)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let snapshot: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(snapshot["ingest"]["requests"].is_u64());
+        assert!(snapshot["ingest"]["batch_bytes"]["inf"].is_u64());
+
+        let response = handle(request(Method::GET, HEALTH_PATH)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        Metrics::watchdog().set_stalled(vec!["ingest"]);
+        let response = handle(request(Method::GET, HEALTH_PATH)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        Metrics::watchdog().set_stalled(Vec::new());
+
+        let response = handle(request(Method::GET, "/other")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = handle(request(Method::POST, METRICS_PATH)).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
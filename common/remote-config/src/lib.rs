@@ -0,0 +1,366 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::types::body::LineBufferMut;
+use hyper::body::HttpBody;
+use hyper::client::connect::Connect;
+use hyper::header::{ETAG, IF_NONE_MATCH};
+use hyper::{Body, Client, Request, StatusCode};
+use log::{error, info, warn};
+use middleware::reload::{ReloadableRules, RuleSet, SharedTags};
+use middleware::{Middleware, Status};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::runtime::Builder;
+
+/// Header holding the base64 encoded Ed25519 signature of the document
+const SIGNATURE_HEADER: &str = "x-signature";
+/// How long a fetch, the request and the download of the document, may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Larger documents are rejected rather than buffered
+const MAX_DOCUMENT_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum InvalidKey {
+    #[error("the remote config public key must be a base64 encoded Ed25519 key of 32 bytes")]
+    Encoding,
+}
+
+#[derive(Debug, Error)]
+enum FetchError {
+    #[error("{0}")]
+    Http(#[from] hyper::Error),
+    #[error("the server answered {0}")]
+    Status(StatusCode),
+    #[error("the server didn't answer within {:?}", FETCH_TIMEOUT)]
+    TimedOut,
+    #[error("the document is larger than {} bytes", MAX_DOCUMENT_SIZE)]
+    TooLarge,
+    #[error(
+        "the document isn't signed, the {} header is missing",
+        SIGNATURE_HEADER
+    )]
+    Unsigned,
+    #[error("the signature of the document doesn't match the public key")]
+    Signature,
+    #[error("the document is invalid: {0}")]
+    Document(#[from] serde_json::Error),
+    #[error("the document version {version} is older than the applied version {applied}")]
+    Outdated { version: u64, applied: u64 },
+    #[error("the document expired at {0}")]
+    Expired(u64),
+}
+
+/// The Ed25519 public key the documents are signed with
+#[derive(Clone)]
+pub struct PublicKey(Vec<u8>);
+
+impl PublicKey {
+    pub fn from_base64(key: &str) -> Result<Self, InvalidKey> {
+        match base64::decode(key.trim()) {
+            Ok(key) if key.len() == 32 => Ok(PublicKey(key)),
+            _ => Err(InvalidKey::Encoding),
+        }
+    }
+
+    fn verify(&self, document: &[u8], signature: &str) -> Result<(), FetchError> {
+        let signature = base64::decode(signature.trim()).map_err(|_| FetchError::Signature)?;
+        UnparsedPublicKey::new(&ED25519, &self.0)
+            .verify(document, &signature)
+            .map_err(|_| FetchError::Signature)
+    }
+}
+
+impl std::fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PublicKey({})", base64::encode(&self.0))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Url of the config document
+    pub url: String,
+    pub public_key: PublicKey,
+    /// How long to wait between two fetches
+    pub interval: Duration,
+    pub proxy: Option<sink::Proxy>,
+    pub tls_options: sink::TlsOptions,
+    pub pool: sink::PoolOptions,
+}
+
+/// The config document, the rules replacing the ones of the previous document
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    /// Increased with each document, so that an older signed document can't be replayed
+    version: u64,
+    /// Unix time in seconds after which the document is no longer accepted
+    expires_at: u64,
+    #[serde(default)]
+    line_exclusion_regex: Vec<String>,
+    #[serde(default)]
+    line_inclusion_regex: Vec<String>,
+    #[serde(default)]
+    line_redact_regex: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Document {
+    /// Rejects documents older than the `applied` version and expired ones
+    fn check(&self, applied: Option<u64>, now: u64) -> Result<(), FetchError> {
+        if let Some(applied) = applied.filter(|applied| self.version < *applied) {
+            return Err(FetchError::Outdated {
+                version: self.version,
+                applied,
+            });
+        }
+        if self.expires_at <= now {
+            return Err(FetchError::Expired(self.expires_at));
+        }
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+/// Buffers the body unless it's larger than `max` bytes
+async fn read_body(mut body: Body, max: usize) -> Result<Vec<u8>, FetchError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max {
+            return Err(FetchError::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+impl From<Document> for RuleSet {
+    fn from(document: Document) -> Self {
+        RuleSet {
+            exclusion: document.line_exclusion_regex,
+            inclusion: document.line_inclusion_regex,
+            redact: document.line_redact_regex,
+            tags: document.tags,
+        }
+    }
+}
+
+/// Applies the line rules and tags of a config document fetched periodically from a url, so
+/// that the rules of a whole fleet of agents can be changed at once without redeploying them.
+/// Documents are only applied when signed with the private key of `options.public_key`, not
+/// expired and not older than the applied one, and the previous rules are kept when a document
+/// can't be fetched or is invalid.
+pub struct RemoteRules {
+    options: Options,
+    rules: ReloadableRules,
+}
+
+impl RemoteRules {
+    pub fn new(options: Options) -> Self {
+        RemoteRules {
+            rules: ReloadableRules::new(format!("remote config {}", options.url)),
+            options,
+        }
+    }
+
+    pub fn tags(&self) -> SharedTags {
+        self.rules.tags()
+    }
+
+    /// Returns the document unless it didn't change since the one of `etag`, along with the
+    /// etag of the new document
+    async fn fetch<C>(
+        &self,
+        client: &Client<C>,
+        etag: Option<&str>,
+    ) -> Result<Option<(Document, Option<String>)>, FetchError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        tokio::time::timeout(FETCH_TIMEOUT, self.download(client, etag))
+            .await
+            .map_err(|_| FetchError::TimedOut)?
+    }
+
+    async fn download<C>(
+        &self,
+        client: &Client<C>,
+        etag: Option<&str>,
+    ) -> Result<Option<(Document, Option<String>)>, FetchError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let mut request = Request::get(self.options.url.as_str());
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let request = request
+            .body(Body::empty())
+            .expect("remote config request is valid");
+        let response = client.request(request).await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => return Ok(None),
+            status if !status.is_success() => return Err(FetchError::Status(status)),
+            _ => {}
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let signature = header(SIGNATURE_HEADER).ok_or(FetchError::Unsigned)?;
+        let etag = header(ETAG.as_str());
+        let body = read_body(response.into_body(), MAX_DOCUMENT_SIZE).await?;
+        self.options.public_key.verify(&body, &signature)?;
+        Ok(Some((serde_json::from_slice(&body)?, etag)))
+    }
+}
+
+impl Middleware for RemoteRules {
+    fn run(&self) {
+        let runtime = match Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!("unable to build runtime to poll the remote config: {}", e);
+                return;
+            }
+        };
+        let client = sink::https_client(
+            "remote config",
+            self.options.proxy.clone(),
+            &self.options.tls_options,
+            &self.options.pool,
+        );
+        info!("polling remote config from {}", self.options.url);
+        runtime.block_on(async {
+            let mut etag = None;
+            let mut applied = None;
+            loop {
+                match self.fetch(&client, etag.as_deref()).await {
+                    Ok(Some((document, new_etag))) => {
+                        if let Err(e) = document.check(applied, unix_now()) {
+                            warn!(
+                                "ignoring remote config from {}, keeping the current rules: {}",
+                                self.options.url, e
+                            );
+                        } else {
+                            // A rejected document is fetched again, its etag isn't kept
+                            let version = document.version;
+                            if self.rules.apply(document.into()) {
+                                etag = new_etag;
+                                applied = Some(version);
+                            }
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!(
+                        "unable to fetch remote config from {}, keeping the current rules: {}",
+                        self.options.url, e
+                    ),
+                }
+                tokio::time::sleep(self.options.interval).await;
+            }
+        });
+    }
+
+    fn process<'a>(&self, line: &'a mut dyn LineBufferMut) -> Status<&'a mut dyn LineBufferMut> {
+        self.rules.process(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn verifies_and_applies_documents() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = PublicKey::from_base64(&base64::encode(pair.public_key().as_ref())).unwrap();
+
+        let document = br#"{
+            "version": 1,
+            "expires_at": 4102444800,
+            "line_exclusion_regex": ["^DEBUG"],
+            "tags": ["fleet-a"]
+        }"#;
+        let signature = base64::encode(pair.sign(document).as_ref());
+        assert!(key.verify(document, &signature).is_ok());
+        assert!(matches!(
+            key.verify(br#"{"line_exclusion_regex":[]}"#, &signature),
+            Err(FetchError::Signature)
+        ));
+
+        let rules = RemoteRules::new(Options {
+            url: "https://config.example.com/agent.json".into(),
+            public_key: key,
+            interval: Duration::from_secs(60),
+            proxy: None,
+            tls_options: sink::TlsOptions::default(),
+            pool: sink::PoolOptions::default(),
+        });
+        let document: Document = serde_json::from_slice(document).unwrap();
+        assert!(rules.rules.apply(document.into()));
+        assert!(matches!(
+            rules.process(&mut LineBuilder::new().line("DEBUG hello")),
+            Status::Skip
+        ));
+        assert_eq!(rules.tags().take(), Some(vec!["fleet-a".to_string()]));
+
+        let invalid = r#"{"version":1,"expires_at":4102444800,"line_exclude_regex":[]}"#;
+        assert!(serde_json::from_str::<Document>(invalid).is_err());
+        assert!(serde_json::from_str::<Document>(r#"{"tags":[]}"#).is_err());
+        assert!(PublicKey::from_base64("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn rejects_older_and_expired_documents() {
+        let document = Document {
+            version: 2,
+            expires_at: 1000,
+            ..Document::default()
+        };
+        assert!(document.check(None, 999).is_ok());
+        assert!(document.check(Some(2), 999).is_ok());
+        assert!(document.check(Some(1), 999).is_ok());
+        assert!(matches!(
+            document.check(Some(3), 999),
+            Err(FetchError::Outdated {
+                version: 2,
+                applied: 3
+            })
+        ));
+        assert!(matches!(
+            document.check(None, 1000),
+            Err(FetchError::Expired(1000))
+        ));
+    }
+
+    #[test]
+    fn rejects_large_documents() {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let body = read_body(Body::from(vec![b'x'; 16]), 16).await.unwrap();
+            assert_eq!(body.len(), 16);
+            assert!(matches!(
+                read_body(Body::from(vec![b'x'; 17]), 16).await,
+                Err(FetchError::TooLarge)
+            ));
+        });
+    }
+}
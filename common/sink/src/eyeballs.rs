@@ -0,0 +1,107 @@
+use futures::future::{self, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How long an attempt gets before the next address is tried alongside it, the delay
+/// recommended by RFC 8305
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to the first of the addresses `target` resolves to that accepts, racing them
+/// with Happy Eyeballs (RFC 8305) so that a family whose packets are dropped, e.g. IPv4 on an
+/// IPv6 first network, only delays the connection instead of hanging it
+pub(crate) async fn connect(target: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let addrs = lookup_host(target).await?.collect();
+    race(interleave(addrs)).await
+}
+
+/// Alternates the addresses of both families, starting with IPv6
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut interleaved = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return interleaved,
+            (v6, v4) => interleaved.extend(v6.into_iter().chain(v4)),
+        }
+    }
+}
+
+async fn attempt(addr: SocketAddr) -> (SocketAddr, io::Result<TcpStream>) {
+    (addr, TcpStream::connect(addr).await)
+}
+
+/// Starts an attempt for each address in turn, the next one as soon as an attempt failed or
+/// `ATTEMPT_DELAY` after the previous one started, until one of them succeeds
+async fn race(addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut error = None;
+    loop {
+        if attempts.is_empty() {
+            match addrs.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => {
+                    return Err(error.unwrap_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")
+                    }))
+                }
+            }
+        }
+        let delay = Box::pin(tokio::time::sleep(ATTEMPT_DELAY));
+        let attempted = match future::select(attempts.next(), delay).await {
+            Either::Left((attempted, _)) => attempted,
+            Either::Right(_) => None,
+        };
+        match attempted {
+            Some((addr, Ok(stream))) => {
+                let family = if addr.is_ipv6() { "IPv6" } else { "IPv4" };
+                debug!("connected to {} over {}", addr, family);
+                return Ok(stream);
+            }
+            Some((addr, Err(e))) => {
+                debug!("failed to connect to {}: {}", addr, e);
+                error = Some(e);
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+            None => {
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn connects_to_the_first_address_that_accepts() {
+        let first: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:80".parse().unwrap();
+        let v6: SocketAddr = "[fd00::1]:80".parse().unwrap();
+        assert_eq!(interleave(vec![first, second, v6]), vec![v6, first, second]);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            closed.local_addr().unwrap()
+        };
+        let listening = listener.local_addr().unwrap();
+        let stream = race(vec![refused, listening]).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listening);
+
+        assert!(race(vec![refused]).await.is_err());
+        assert!(race(Vec::new()).await.is_err());
+    }
+}
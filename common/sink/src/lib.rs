@@ -0,0 +1,20 @@
+//! Building blocks shared by the sinks lines are fanned out to next to LogDNA.
+//!
+//! Every sink owns a bounded queue and a task draining it, so a slow or unavailable
+//! destination only ever fills its own queue and drops its own lines without delaying
+//! the others.
+
+mod eyeballs;
+mod policy;
+mod pool;
+mod proxy;
+mod queue;
+mod sign;
+mod tls;
+
+pub use policy::Policy;
+pub use pool::{CountedStream, PoolOptions};
+pub use proxy::{https_client, InvalidProxy, Proxy, ProxyConnector, ProxyKind};
+pub use queue::{queue, Queue};
+pub use sign::{parse_signer, InvalidSigner, SignError, Signer};
+pub use tls::{InvalidTls, TlsOptions, TlsVersion};
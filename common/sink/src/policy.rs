@@ -0,0 +1,141 @@
+use log::{debug, warn};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+/// How much a sink buffers and how it retries its destination
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Policy {
+    /// Lines queued for the sink before new ones are dropped
+    pub buffer_size: usize,
+    /// Attempts made to deliver a batch before giving up on it
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every further attempt
+    pub retry_base_delay: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            buffer_size: 16 * 1024,
+            max_attempts: 5,
+            retry_base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Policy {
+    /// The delays to wait before each retry
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let base = self.retry_base_delay;
+        (0..self.max_attempts.saturating_sub(1)).map(move |n| base * 2u32.saturating_pow(n))
+    }
+
+    /// Retries `f` with an exponential backoff, returning `None` once all attempts failed
+    pub async fn retry<T, E, F, Fut>(&self, what: &str, f: F) -> Option<T>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.retry_if(what, f, |_| true).await
+    }
+
+    /// Like [`Policy::retry`], but gives up early on the errors `retryable` rejects
+    pub async fn retry_if<T, E, F, Fut, R>(&self, what: &str, mut f: F, retryable: R) -> Option<T>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        R: Fn(&E) -> bool,
+    {
+        let mut delays = self.delays();
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Ok(output) => return Some(output),
+                Err(e) if !retryable(&e) => {
+                    warn!("{} failed: {}", what, e);
+                    return None;
+                }
+                Err(e) => match delays.next() {
+                    Some(delay) => {
+                        debug!("{} failed, retrying in {:?}: {}", what, delay, e);
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    None => {
+                        warn!("{} failed after {} attempts: {}", what, attempt, e);
+                        return None;
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_delays() {
+        let policy = Policy {
+            max_attempts: 4,
+            ..Policy::default()
+        };
+        let delays: Vec<u64> = policy.delays().map(|d| d.as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        tokio::time::pause();
+        let mut calls = 0;
+        let result = Policy::default()
+            .retry("test", || {
+                calls += 1;
+                let attempt = calls;
+                async move {
+                    if attempt == 3 {
+                        Ok(attempt)
+                    } else {
+                        Err("unavailable")
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result, Some(3));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        tokio::time::pause();
+        let mut calls = 0;
+        let result: Option<()> = Policy::default()
+            .retry("test", || {
+                calls += 1;
+                async { Err("unavailable") }
+            })
+            .await;
+        assert_eq!(result, None);
+        assert_eq!(calls, 5);
+    }
+
+    #[tokio::test]
+    async fn stops_on_permanent_errors() {
+        let mut calls = 0;
+        let result: Option<()> = Policy::default()
+            .retry_if(
+                "test",
+                || {
+                    calls += 1;
+                    async { Err("bad request") }
+                },
+                |_| false,
+            )
+            .await;
+        assert_eq!(result, None);
+        assert_eq!(calls, 1);
+    }
+}
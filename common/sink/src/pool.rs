@@ -0,0 +1,90 @@
+use hyper::client::connect::{Connected, Connection};
+use metrics::Metrics;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// How the clients of the sinks keep connections to their destinations for later requests
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PoolOptions {
+    /// How long a connection can stay idle before it's closed
+    pub idle_timeout: Duration,
+    /// Idle connections kept per destination host, the ones over it are closed once their
+    /// requests complete
+    pub max_idle_per_host: usize,
+    /// How long connecting to a destination, or to its proxy and through the tunnel, can take
+    /// before failing the request, unlimited when `None`
+    pub connect_timeout: Option<Duration>,
+    /// How long a connection can stay silent before TCP keepalive probes are sent, so that
+    /// connections dropped by a NAT or firewall are noticed. Off when `None`
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for PoolOptions {
+    /// The defaults of hyper
+    fn default() -> Self {
+        PoolOptions {
+            idle_timeout: Duration::from_secs(90),
+            max_idle_per_host: usize::MAX,
+            connect_timeout: None,
+            tcp_keepalive: None,
+        }
+    }
+}
+
+/// A connection of a sink, counted in the `connections` metrics while it's open
+pub struct CountedStream {
+    inner: TcpStream,
+}
+
+impl CountedStream {
+    pub(crate) fn new(inner: TcpStream) -> Self {
+        Metrics::connections().increment_opened();
+        Metrics::connections().increment_open();
+        CountedStream { inner }
+    }
+}
+
+impl Drop for CountedStream {
+    fn drop(&mut self) {
+        Metrics::connections().decrement_open();
+    }
+}
+
+impl AsyncRead for CountedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CountedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Connection for CountedStream {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
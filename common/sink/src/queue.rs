@@ -0,0 +1,55 @@
+use http::types::body::Line;
+use log::warn;
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+
+/// The sending half of a sink's queue, which never waits on the sink
+#[derive(Clone)]
+pub struct Queue {
+    name: &'static str,
+    tx: Sender<Line>,
+    dropped: fn(),
+}
+
+/// Creates the queue of the sink called `name`, `dropped` is called for every line that
+/// didn't fit in it
+pub fn queue(name: &'static str, buffer_size: usize, dropped: fn()) -> (Queue, Receiver<Line>) {
+    let (tx, rx) = channel(buffer_size.max(1));
+    (Queue { name, tx, dropped }, rx)
+}
+
+impl Queue {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Queues `line`, dropping it when the sink can't keep up
+    pub fn send(&self, line: Line) {
+        match self.tx.try_send(line) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => (self.dropped)(),
+            Err(TrySendError::Closed(_)) => warn!("{} is no longer running", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn drops_lines_when_full() {
+        let (queue, mut rx) = queue("test sink", 2, || {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        });
+        for line in ["a", "b", "c"].iter() {
+            queue.send(LineBuilder::new().line(*line).build().unwrap());
+        }
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+        assert_eq!(rx.try_recv().unwrap().line, "a");
+        assert_eq!(rx.try_recv().unwrap().line, "b");
+    }
+}
@@ -0,0 +1,331 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, HOST};
+use hyper::{Body, Request};
+use ring::digest::{digest, SHA256};
+use ring::hmac;
+use rusoto_credential::{
+    AutoRefreshingProvider, AwsCredentials, ChainProvider, CredentialsError, ProvideAwsCredentials,
+};
+use thiserror::Error;
+
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Error)]
+pub enum InvalidSigner {
+    #[error("signers must be aws-sigv4:<region>:<service> or hmac-sha256:<header>:<key>")]
+    Spec,
+    #[error("unable to set up the AWS credentials: {0}")]
+    Credentials(CredentialsError),
+}
+
+#[derive(Debug, Error)]
+pub enum SignError {
+    #[error("unable to get the AWS credentials to sign requests: {0}")]
+    Credentials(CredentialsError),
+    #[error("{0} has no host to sign")]
+    NoHost(String),
+    #[error("unable to add the {0} header")]
+    InvalidHeader(&'static str),
+}
+
+/// Adds the headers a gateway in front of the destination of a sink authenticates requests
+/// with, e.g. an AWS SigV4 signature or an HMAC of the body
+#[async_trait]
+pub trait Signer: fmt::Debug + Send + Sync {
+    /// Signs `request`, whose body is `body`
+    async fn sign(&self, request: &mut Request<Body>, body: &[u8]) -> Result<(), SignError>;
+}
+
+/// Parses `aws-sigv4:<region>:<service>`, e.g. `aws-sigv4:us-east-1:es`, and
+/// `hmac-sha256:<header>:<key>`
+pub fn parse_signer(spec: &str) -> Result<Arc<dyn Signer>, InvalidSigner> {
+    let mut parts = spec.trim().splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("aws-sigv4"), Some(region), Some(service))
+            if !region.is_empty() && !service.is_empty() =>
+        {
+            let credentials = AutoRefreshingProvider::new(ChainProvider::new())
+                .map_err(InvalidSigner::Credentials)?;
+            Ok(Arc::new(AwsSigV4 {
+                region: region.to_string(),
+                service: service.to_string(),
+                credentials,
+            }))
+        }
+        (Some("hmac-sha256"), Some(header), Some(key)) if !key.is_empty() => {
+            Ok(Arc::new(HmacSha256 {
+                header: HeaderName::from_bytes(header.as_bytes())
+                    .map_err(|_| InvalidSigner::Spec)?,
+                key: hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            }))
+        }
+        _ => Err(InvalidSigner::Spec),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn header_value(name: &'static str, value: &str) -> Result<HeaderValue, SignError> {
+    HeaderValue::from_str(value).map_err(|_| SignError::InvalidHeader(name))
+}
+
+/// Signs with the `AWS4-HMAC-SHA256` scheme. Credentials are resolved through the usual AWS
+/// chain, like the ones of the s3 archive: environment variables, the shared credentials file
+/// and the instance or task role, and refreshed before they expire
+struct AwsSigV4 {
+    region: String,
+    service: String,
+    credentials: AutoRefreshingProvider<ChainProvider>,
+}
+
+impl AwsSigV4 {
+    fn sign_at(
+        &self,
+        request: &mut Request<Body>,
+        body: &[u8],
+        credentials: &AwsCredentials,
+        now: DateTime<Utc>,
+    ) -> Result<(), SignError> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let payload_hash = hex(digest(&SHA256, body).as_ref());
+        let host = match request.uri().authority() {
+            Some(authority) => authority.as_str().to_string(),
+            None => return Err(SignError::NoHost(request.uri().to_string())),
+        };
+
+        let headers = request.headers_mut();
+        headers.remove(AUTHORIZATION);
+        headers.insert(HOST, header_value("host", &host)?);
+        headers.insert("x-amz-date", header_value("x-amz-date", &amz_date)?);
+        // Only S3 requires the hash of the payload as a header
+        if self.service == "s3" {
+            headers.insert(
+                "x-amz-content-sha256",
+                header_value("x-amz-content-sha256", &payload_hash)?,
+            );
+        }
+        if let Some(token) = credentials.token().as_ref() {
+            headers.insert(
+                "x-amz-security-token",
+                header_value("x-amz-security-token", token)?,
+            );
+        }
+
+        let signed = canonical_headers(request.headers());
+        let signed_names = signed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n\n{}\n{}",
+            request.method(),
+            match request.uri().path() {
+                "" => "/",
+                path => path,
+            },
+            canonical_query(request.uri().query().unwrap_or_default()),
+            signed
+                .iter()
+                .map(|(name, value)| format!("{}:{}", name, value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            signed_names,
+            payload_hash
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(digest(&SHA256, canonical_request.as_bytes()).as_ref())
+        );
+        let key = signing_key(
+            credentials.aws_secret_access_key(),
+            date,
+            &self.region,
+            &self.service,
+        );
+        let signature = hex(hmac::sign(&key, string_to_sign.as_bytes()).as_ref());
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.aws_access_key_id(),
+            scope,
+            signed_names,
+            signature
+        );
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            header_value("authorization", &authorization)?,
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Signer for AwsSigV4 {
+    async fn sign(&self, request: &mut Request<Body>, body: &[u8]) -> Result<(), SignError> {
+        let credentials = self
+            .credentials
+            .credentials()
+            .await
+            .map_err(SignError::Credentials)?;
+        self.sign_at(request, body, &credentials, Utc::now())
+    }
+}
+
+impl fmt::Debug for AwsSigV4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AwsSigV4")
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+/// Lowercase names and trimmed values of the headers, sorted by name, with the values of
+/// repeated headers joined by commas
+fn canonical_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    let mut canonical: Vec<(String, String)> = headers
+        .keys()
+        .map(|name| {
+            let values = headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+                .collect::<Vec<_>>();
+            (name.as_str().to_string(), values.join(","))
+        })
+        .collect();
+    canonical.sort();
+    canonical
+}
+
+/// The already encoded query parameters, sorted by name and value
+fn canonical_query(query: &str) -> String {
+    let mut params: Vec<(&str, &str)> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| param.split_once('=').unwrap_or((param, "")))
+        .collect();
+    params.sort_unstable();
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> hmac::Key {
+    let mut key = format!("AWS4{}", secret).into_bytes();
+    for part in &[date, region, service, "aws4_request"] {
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key), part.as_bytes());
+        key = tag.as_ref().to_vec();
+    }
+    hmac::Key::new(hmac::HMAC_SHA256, &key)
+}
+
+/// Sets the header to `t=<unix timestamp>,v1=<signature>`, the signature being the hex encoded
+/// HMAC-SHA256 of the timestamp, a dot and the body, so that gateways can also reject replays
+struct HmacSha256 {
+    header: HeaderName,
+    key: hmac::Key,
+}
+
+impl HmacSha256 {
+    fn signature(&self, body: &[u8], timestamp: i64) -> String {
+        let mut ctx = hmac::Context::with_key(&self.key);
+        ctx.update(timestamp.to_string().as_bytes());
+        ctx.update(b".");
+        ctx.update(body);
+        format!("t={},v1={}", timestamp, hex(ctx.sign().as_ref()))
+    }
+}
+
+#[async_trait]
+impl Signer for HmacSha256 {
+    async fn sign(&self, request: &mut Request<Body>, body: &[u8]) -> Result<(), SignError> {
+        let signature = self.signature(body, Utc::now().timestamp());
+        request
+            .headers_mut()
+            .insert(self.header.clone(), header_value("signature", &signature)?);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for HmacSha256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HmacSha256")
+            .field("header", &self.header)
+            .field("key", &"REDACTED")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use hyper::header::CONTENT_TYPE;
+
+    // The example of the AWS documentation on signing requests
+    #[test]
+    fn signs_with_sigv4() {
+        let signer = AwsSigV4 {
+            region: "us-east-1".into(),
+            service: "iam".into(),
+            credentials: AutoRefreshingProvider::new(ChainProvider::new()).unwrap(),
+        };
+        let credentials = AwsCredentials::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            None,
+            None,
+        );
+        let uri = "https://iam.amazonaws.com/?Version=2010-05-08&Action=ListUsers";
+        let mut request = Request::get(uri)
+            .header(
+                CONTENT_TYPE,
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+        signer
+            .sign_at(&mut request, b"", &credentials, now)
+            .unwrap();
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+        assert_eq!(request.headers()["x-amz-date"], "20150830T123600Z");
+    }
+
+    #[test]
+    fn signs_with_hmac() {
+        let signer = HmacSha256 {
+            header: HeaderName::from_static("x-signature"),
+            key: hmac::Key::new(hmac::HMAC_SHA256, b"sdf79s6df3j4"),
+        };
+        assert_eq!(
+            signer.signature(br#"{"lines":[]}"#, 1_440_938_160),
+            "t=1440938160,v1=ff12652f89eda4c43ac9b8e4a7bfe4ae8cf7fa22f2804ccd8038e4dd0fc32771"
+        );
+    }
+
+    #[test]
+    fn parses_signers() {
+        assert!(parse_signer("aws-sigv4:eu-west-1:es").is_ok());
+        assert!(parse_signer("hmac-sha256:x-signature:key:with:colons").is_ok());
+        assert!(parse_signer("aws-sigv4:eu-west-1").is_err());
+        assert!(parse_signer("hmac-sha256:x-signature:").is_err());
+        assert!(parse_signer("basic:user:password").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use log::warn;
+use ring::digest::{digest, SHA256, SHA256_OUTPUT_LEN};
+use rustls::{
+    Certificate, ClientConfig, ProtocolVersion, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, SupportedCipherSuite, TLSError, WebPKIVerifier, ALL_CIPHERSUITES,
+};
+use thiserror::Error;
+use webpki::DNSNameRef;
+
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Error)]
+pub enum InvalidTls {
+    #[error("{0} is not a supported minimum TLS version, use 1.2 or 1.3")]
+    Version(String),
+    #[error("{0} is not a supported cipher suite, use any of {1}")]
+    CipherSuite(String, String),
+    #[error("none of the cipher suites can be used with TLS {0}")]
+    NoCipherSuite(&'static str),
+    #[error("{0} is not a SHA-256 certificate fingerprint, use its 64 hex digits")]
+    Pin(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    pub fn parse(version: &str) -> Result<Self, InvalidTls> {
+        match version.trim() {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(InvalidTls::Version(version.to_string())),
+        }
+    }
+
+    /// The versions negotiated, this one and the later ones
+    fn versions(self) -> Vec<ProtocolVersion> {
+        match self {
+            TlsVersion::Tls12 => vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+            TlsVersion::Tls13 => vec![ProtocolVersion::TLSv1_3],
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "1.2",
+            TlsVersion::Tls13 => "1.3",
+        }
+    }
+}
+
+/// How the sinks and the remote config client negotiate TLS with their destinations, the
+/// connections to LogDNA keep the settings of the ingest client
+#[derive(Clone)]
+pub struct TlsOptions {
+    pub min_version: TlsVersion,
+    /// Cipher suites offered, all the supported ones when empty
+    pub cipher_suites: Vec<&'static SupportedCipherSuite>,
+    /// Accepts any certificate, only meant for lab environments with self-signed ones
+    pub insecure_skip_verify: bool,
+    /// SHA-256 fingerprints of the certificates destinations can present, any certificate
+    /// trusted by the system when empty
+    pub pinned_certs: Vec<[u8; SHA256_OUTPUT_LEN]>,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        TlsOptions {
+            min_version: TlsVersion::Tls12,
+            cipher_suites: Vec::new(),
+            insecure_skip_verify: false,
+            pinned_certs: Vec::new(),
+        }
+    }
+}
+
+impl TlsOptions {
+    /// Fails on unknown cipher suites and on cipher suites none of which can be used with
+    /// the versions allowed
+    pub fn new(
+        min_version: TlsVersion,
+        cipher_suites: &[String],
+        insecure_skip_verify: bool,
+    ) -> Result<Self, InvalidTls> {
+        let cipher_suites = cipher_suites
+            .iter()
+            .map(|name| cipher_suite(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let versions = min_version.versions();
+        let usable = cipher_suites.iter().any(|suite| {
+            versions
+                .iter()
+                .any(|version| suite.usable_for_version(*version))
+        });
+        if !cipher_suites.is_empty() && !usable {
+            return Err(InvalidTls::NoCipherSuite(min_version.as_str()));
+        }
+        Ok(TlsOptions {
+            min_version,
+            cipher_suites,
+            insecure_skip_verify,
+            pinned_certs: Vec::new(),
+        })
+    }
+
+    /// Only accepts the certificates with the `fingerprints`, hex encoded and optionally
+    /// separated by colons as printed by `openssl x509 -noout -fingerprint -sha256`
+    pub fn with_pinned_certs(mut self, fingerprints: &[String]) -> Result<Self, InvalidTls> {
+        self.pinned_certs = fingerprints
+            .iter()
+            .map(|fingerprint| parse_fingerprint(fingerprint))
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// Restricts the versions and cipher suites `config` negotiates, and stops it from
+    /// verifying certificates when asked to
+    pub fn apply(&self, config: &mut ClientConfig, sink: &str) {
+        config.versions = self.min_version.versions();
+        if !self.cipher_suites.is_empty() {
+            config.ciphersuites = self.cipher_suites.clone();
+        }
+        if !self.pinned_certs.is_empty() {
+            // Pinned certificates are still checked to be valid for the destination, unless
+            // verification is skipped, so a pin of self-signed certificates works in labs
+            let chain = Some(WebPKIVerifier::new()).filter(|_| !self.insecure_skip_verify);
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCerts {
+                    pins: self.pinned_certs.clone(),
+                    chain,
+                }));
+            return;
+        }
+        if self.insecure_skip_verify {
+            warn!(
+                "TLS certificates are not verified for the {}, anyone on the network path to \
+                 its destination can impersonate it and read or alter the lines sent, only \
+                 disable verification in lab environments",
+                sink
+            );
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(SkipVerify));
+        }
+    }
+}
+
+impl fmt::Debug for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsOptions")
+            .field("min_version", &self.min_version)
+            .field(
+                "cipher_suites",
+                &self
+                    .cipher_suites
+                    .iter()
+                    .map(|suite| suite.suite)
+                    .collect::<Vec<_>>(),
+            )
+            .field("insecure_skip_verify", &self.insecure_skip_verify)
+            .field("pinned_certs", &self.pinned_certs.len())
+            .finish()
+    }
+}
+
+/// Matches the IANA name of a cipher suite, e.g. `TLS13_AES_256_GCM_SHA384` or
+/// `TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256`
+fn cipher_suite(name: &str) -> Result<&'static SupportedCipherSuite, InvalidTls> {
+    let name = name.trim();
+    ALL_CIPHERSUITES
+        .iter()
+        .copied()
+        .find(|suite| format!("{:?}", suite.suite).eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let supported = ALL_CIPHERSUITES
+                .iter()
+                .map(|suite| format!("{:?}", suite.suite))
+                .collect::<Vec<_>>();
+            InvalidTls::CipherSuite(name.to_string(), supported.join(", "))
+        })
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<[u8; SHA256_OUTPUT_LEN], InvalidTls> {
+    let invalid = || InvalidTls::Pin(fingerprint.to_string());
+    let digits: String = fingerprint.trim().chars().filter(|c| *c != ':').collect();
+    if digits.len() != SHA256_OUTPUT_LEN * 2 || !digits.is_ascii() {
+        return Err(invalid());
+    }
+    let mut pin = [0; SHA256_OUTPUT_LEN];
+    for (i, byte) in pin.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(pin)
+}
+
+/// Accepts the certificates whose fingerprint is pinned, once `chain` checked that they are
+/// trusted and valid for the destination
+struct PinnedCerts {
+    pins: Vec<[u8; SHA256_OUTPUT_LEN]>,
+    chain: Option<WebPKIVerifier>,
+}
+
+impl PinnedCerts {
+    fn is_pinned(&self, cert: &Certificate) -> bool {
+        let fingerprint = digest(&SHA256, &cert.0);
+        self.pins.iter().any(|pin| pin[..] == *fingerprint.as_ref())
+    }
+}
+
+impl ServerCertVerifier for PinnedCerts {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        if let Some(chain) = self.chain.as_ref() {
+            chain.verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+        }
+        let cert = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        if self.is_pinned(cert) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            let name: &str = dns_name.into();
+            Err(TLSError::General(format!(
+                "the certificate presented by {} is not pinned",
+                name
+            )))
+        }
+    }
+}
+
+struct SkipVerify;
+
+impl ServerCertVerifier for SkipVerify {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restricts_versions_and_cipher_suites() {
+        let options = TlsOptions::new(
+            TlsVersion::parse("1.3").unwrap(),
+            &["tls13_aes_256_gcm_sha384".to_string()],
+            false,
+        )
+        .unwrap();
+        let mut config = ClientConfig::new();
+        options.apply(&mut config, "test sink");
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_3]);
+        assert_eq!(config.ciphersuites.len(), 1);
+
+        assert!(TlsVersion::parse("1.1").is_err());
+        assert!(matches!(
+            TlsOptions::new(
+                TlsVersion::Tls12,
+                &["TLS_RSA_WITH_RC4_128_MD5".into()],
+                false
+            ),
+            Err(InvalidTls::CipherSuite(_, _))
+        ));
+        assert!(matches!(
+            TlsOptions::new(
+                TlsVersion::Tls13,
+                &["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256".into()],
+                false
+            ),
+            Err(InvalidTls::NoCipherSuite("1.3"))
+        ));
+    }
+
+    #[test]
+    fn pins_certificates_by_fingerprint() {
+        let cert = Certificate(b"not really a certificate".to_vec());
+        let fingerprint = digest(&SHA256, &cert.0);
+        let hex: Vec<String> = fingerprint
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+
+        let options = TlsOptions::default()
+            .with_pinned_certs(&[hex.join(":"), "00".repeat(SHA256_OUTPUT_LEN)])
+            .unwrap();
+        assert_eq!(options.pinned_certs.len(), 2);
+        let pinned = PinnedCerts {
+            pins: options.pinned_certs.clone(),
+            chain: None,
+        };
+        assert!(pinned.is_pinned(&cert));
+        assert!(!pinned.is_pinned(&Certificate(b"another one".to_vec())));
+
+        let dns_name = DNSNameRef::try_from_ascii_str("logs.example.com").unwrap();
+        let roots = RootCertStore::empty();
+        assert!(pinned
+            .verify_server_cert(&roots, &[cert], dns_name, &[])
+            .is_ok());
+        assert!(pinned
+            .verify_server_cert(&roots, &[], dns_name, &[])
+            .is_err());
+
+        let invalid = vec![
+            String::new(),
+            "0a:1b".into(),
+            "zz".repeat(SHA256_OUTPUT_LEN),
+        ];
+        for fingerprint in invalid.iter() {
+            assert!(matches!(
+                TlsOptions::default().with_pinned_certs(&[fingerprint.clone()]),
+                Err(InvalidTls::Pin(_))
+            ));
+        }
+    }
+}
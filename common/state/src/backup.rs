@@ -0,0 +1,199 @@
+use crate::{import, snapshot, StateSnapshot};
+
+use log::{info, warn};
+use rocksdb::DB;
+use thiserror::Error;
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub(crate) const BACKUP_NAME: &str = "agent_state.backup";
+
+/// Checkpoints are frequent, the backup is only rewritten on the first one after this long
+const BACKUP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub(crate) enum BackupError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+    #[error("checksum mismatch, the backup is truncated or corrupted")]
+    Checksum,
+}
+
+/// A copy of the state db, taken on checkpoints, that the db is recovered from when it's
+/// corrupted. The file holds the CRC32 of the state on its first line, followed by the state
+/// as JSON.
+pub(crate) struct Backup {
+    path: PathBuf,
+    last_written: Mutex<Option<Instant>>,
+}
+
+impl Backup {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Backup {
+            path,
+            last_written: Mutex::new(None),
+        }
+    }
+
+    /// Backs up `db` unless it was backed up less than `BACKUP_INTERVAL` ago
+    pub(crate) fn checkpoint(&self, db: &DB) {
+        let mut last_written = self.last_written.lock().unwrap();
+        if last_written.map_or(false, |at| at.elapsed() < BACKUP_INTERVAL) {
+            return;
+        }
+        *last_written = Some(Instant::now());
+        let result = snapshot(db)
+            .map_err(|e| warn!("unable to read state db to back it up: {}", e))
+            .ok()
+            .map(|snapshot| self.write(&snapshot));
+        if let Some(Err(e)) = result {
+            warn!("unable to back up state db to {:?}: {}", self.path, e);
+        }
+    }
+
+    /// The previous backup is only replaced once the new one is completely written
+    fn write(&self, snapshot: &StateSnapshot) -> Result<(), BackupError> {
+        let json = serde_json::to_vec(snapshot)?;
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        writeln!(file, "{:08x}", crc32fast::hash(&json))?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<StateSnapshot, BackupError> {
+        let content = fs::read(&self.path)?;
+        let split = content
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or(BackupError::Checksum)?;
+        let (checksum, json) = (&content[..split], &content[split + 1..]);
+        let checksum = std::str::from_utf8(checksum)
+            .ok()
+            .and_then(|checksum| u32::from_str_radix(checksum, 16).ok());
+        if checksum != Some(crc32fast::hash(json)) {
+            return Err(BackupError::Checksum);
+        }
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Restores the entries of the backup missing from `db` after it was repaired or reset,
+    /// entries still in `db` are more recent than the backup and kept
+    pub(crate) fn recover(&self, db: &DB) {
+        let backup = match self.read() {
+            Ok(backup) => backup,
+            Err(e) => {
+                warn!(
+                    "no usable state backup in {:?}, {}, the offsets lost with the state db \
+                     are reset and their files read according to the lookback",
+                    self.path, e
+                );
+                return;
+            }
+        };
+        let current = match snapshot(db) {
+            Ok(current) => current,
+            Err(e) => {
+                warn!("unable to read the recovered state db: {}", e);
+                return;
+            }
+        };
+
+        let restored = StateSnapshot {
+            offsets: backup
+                .offsets
+                .into_iter()
+                .filter(|(inode, _)| !current.offsets.contains_key(inode))
+                .collect(),
+            timestamps: backup
+                .timestamps
+                .into_iter()
+                .filter(|(key, _)| !current.timestamps.contains_key(key))
+                .collect(),
+        };
+        if let Err(e) = import(db, &restored) {
+            warn!("unable to restore state db from its backup: {}", e);
+            return;
+        }
+        warn!(
+            "recovered state db, kept {} file offsets and {} source timestamps, restored {} \
+             file offsets and {} source timestamps from its backup, any other file is read \
+             according to the lookback",
+            current.offsets.len(),
+            current.timestamps.len(),
+            restored.offsets.len(),
+            restored.timestamps.len()
+        );
+        for (inode, offset) in restored.offsets.iter() {
+            info!("restored offset {} of file with inode {}", offset, inode);
+        }
+        for (key, timestamp) in restored.timestamps.iter() {
+            info!("restored timestamp {} of source {}", timestamp, key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AgentState;
+    use tempfile::tempdir;
+
+    #[test]
+    fn backups_detect_corruption() {
+        let dir = tempdir().unwrap();
+        let backup = Backup::new(dir.path().join(BACKUP_NAME));
+        let mut snapshot = StateSnapshot::default();
+        snapshot.offsets.insert(1, 100);
+        backup.write(&snapshot).unwrap();
+        assert_eq!(backup.read().unwrap(), snapshot);
+
+        let content = fs::read(dir.path().join(BACKUP_NAME)).unwrap();
+        fs::write(dir.path().join(BACKUP_NAME), &content[..content.len() - 2]).unwrap();
+        assert!(matches!(backup.read(), Err(BackupError::Checksum)));
+    }
+
+    /// Backs up a db with an offset and a timestamp to `dir`, then closes it
+    fn back_up(dir: &std::path::Path) {
+        let state = AgentState::new(dir).unwrap();
+        let mut snapshot = StateSnapshot::default();
+        snapshot.offsets.insert(1_234, 5_678);
+        snapshot.timestamps.insert("docker:abc".into(), 42);
+        state.import(&snapshot).unwrap();
+        state.backup.checkpoint(&state.db);
+    }
+
+    #[test]
+    fn corrupted_state_is_recovered_from_backup() {
+        let dir = tempdir().unwrap();
+        back_up(dir.path());
+        // Leaves a db pointing at a manifest that doesn't exist, with none of its files
+        let db_path = dir.path().join("agent_state.db");
+        fs::remove_dir_all(&db_path).unwrap();
+        fs::create_dir(&db_path).unwrap();
+        fs::write(db_path.join("CURRENT"), "MANIFEST-000404\n").unwrap();
+
+        let state = AgentState::new(dir.path()).unwrap();
+        let recovered = state.export().unwrap();
+        assert_eq!(recovered.offsets.get(&1_234), Some(&5_678));
+        assert_eq!(recovered.timestamps.get("docker:abc"), Some(&42));
+    }
+
+    #[test]
+    fn removed_state_is_not_recovered() {
+        let dir = tempdir().unwrap();
+        back_up(dir.path());
+        fs::remove_dir_all(dir.path().join("agent_state.db")).unwrap();
+
+        let state = AgentState::new(dir.path()).unwrap();
+        assert_eq!(state.export().unwrap(), StateSnapshot::default());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::backup::Backup;
+use crate::delivery::{Deliveries, DELIVERY_NAME};
+use crate::{offset_key, FileOffsetStateError, OFFSET_NAME, TIMESTAMP_NAME};
+
+use log::error;
+use metrics::Metrics;
+use rocksdb::{WriteBatch, WriteOptions, DB};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// When the offsets of the lines shipped are written to the state db
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CheckpointPolicy {
+    /// Longest time the offsets of shipped lines are held before being written, which bounds
+    /// the lines shipped again after a crash
+    pub interval: Duration,
+    /// Number of files with shipped offsets past which they are written without waiting for
+    /// the interval
+    pub max_pending: usize,
+}
+
+impl Default for CheckpointPolicy {
+    fn default() -> Self {
+        CheckpointPolicy {
+            interval: Duration::from_secs(1),
+            max_pending: 1_000,
+        }
+    }
+}
+
+/// The ingestion key route of the client recording the updates, `None` for the default client
+type Route = Option<Arc<str>>;
+
+/// Updates of the lines a client hasn't shipped yet
+#[derive(Default)]
+struct Staged {
+    /// Offsets by inode, `None` deleting the offset of the file
+    offsets: HashMap<u64, Option<u64>>,
+    /// Timestamps by source key, of the sources resuming by time
+    timestamps: HashMap<String, i64>,
+}
+
+/// Offset updates waiting to be written, only the last update of each file is kept. The
+/// clients of the routes ship their lines on their own, so the updates are staged and shipped
+/// by route and the offsets of each route are written apart.
+pub(crate) struct Checkpoints {
+    policy: CheckpointPolicy,
+    /// Updates of lines that haven't been shipped yet, by route
+    staged: HashMap<Route, Staged>,
+    /// Offsets of lines shipped since the last checkpoint, by inode and route
+    shipped: HashMap<(u64, Route), Option<u64>>,
+    shipped_timestamps: HashMap<String, i64>,
+    /// When the oldest of the updates in `shipped` was shipped
+    shipped_since: Option<Instant>,
+}
+
+impl Checkpoints {
+    pub(crate) fn new(policy: CheckpointPolicy) -> Self {
+        Checkpoints {
+            policy,
+            staged: HashMap::new(),
+            shipped: HashMap::new(),
+            shipped_timestamps: HashMap::new(),
+            shipped_since: None,
+        }
+    }
+
+    pub(crate) fn stage(&mut self, route: Route, inode: u64, offset: Option<u64>) {
+        self.staged
+            .entry(route)
+            .or_default()
+            .offsets
+            .insert(inode, offset);
+    }
+
+    pub(crate) fn stage_timestamp(&mut self, route: Route, key: String, nanos: i64) {
+        self.staged
+            .entry(route)
+            .or_default()
+            .timestamps
+            .insert(key, nanos);
+    }
+
+    pub(crate) fn clear(&mut self, route: &Route) {
+        self.staged.remove(route);
+    }
+
+    /// Marks the updates staged by the client of `route` as shipped
+    pub(crate) fn ship(&mut self, route: &Route, now: Instant) {
+        let staged = match self.staged.remove(route) {
+            Some(staged) if !staged.offsets.is_empty() || !staged.timestamps.is_empty() => staged,
+            _ => return,
+        };
+        self.shipped.extend(
+            staged
+                .offsets
+                .into_iter()
+                .map(|(inode, offset)| ((inode, route.clone()), offset)),
+        );
+        self.shipped_timestamps.extend(staged.timestamps);
+        self.shipped_since.get_or_insert(now);
+    }
+
+    /// When the shipped updates have to be written by
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.shipped_since.map(|since| since + self.policy.interval)
+    }
+
+    pub(crate) fn is_due(&self, now: Instant) -> bool {
+        self.shipped.len() + self.shipped_timestamps.len() >= self.policy.max_pending
+            || self.deadline().map_or(false, |deadline| now >= deadline)
+    }
+
+    /// Writes the shipped updates along with the delivery counts and backs up the db, failed
+    /// checkpoints are attempted again after the interval
+    pub(crate) fn checkpoint(
+        &mut self,
+        db: &DB,
+        backup: &Backup,
+        deliveries: &mut Deliveries,
+        now: Instant,
+    ) {
+        match self.write(db, deliveries, now) {
+            Ok(true) => backup.checkpoint(db),
+            Ok(false) => {}
+            Err(e) => {
+                error!("unable to write offsets to the state db: {}", e);
+                self.shipped_since = Some(now);
+            }
+        }
+    }
+
+    /// Updates are written in a single batch synced to disk, so that a checkpoint is either
+    /// entirely there after a crash of the node or not at all
+    fn write(
+        &mut self,
+        db: &DB,
+        deliveries: &mut Deliveries,
+        now: Instant,
+    ) -> Result<bool, FileOffsetStateError> {
+        let since = match self.shipped_since {
+            Some(since) => since,
+            // Acknowledgements can come without any new offset, e.g. for the last request
+            None if deliveries.is_changed() => now,
+            None => return Ok(false),
+        };
+        let cf_handle = db.cf_handle(OFFSET_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        let delivery_cf_handle = db.cf_handle(DELIVERY_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        let timestamp_cf_handle = db.cf_handle(TIMESTAMP_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        let mut wb = WriteBatch::default();
+        for ((inode, route), offset) in self.shipped.iter() {
+            let key = offset_key(*inode, route.as_deref());
+            match offset {
+                Some(offset) => wb.put_cf(cf_handle, key, u64::to_be_bytes(*offset)),
+                None => wb.delete_cf(cf_handle, key),
+            }
+        }
+        for (key, nanos) in self.shipped_timestamps.iter() {
+            wb.put_cf(timestamp_cf_handle, key, i64::to_be_bytes(*nanos));
+        }
+        deliveries.stage_write(delivery_cf_handle, &mut wb);
+        let mut opts = WriteOptions::default();
+        opts.set_sync(true);
+        db.write_opt(wb, &opts)?;
+
+        deliveries.written();
+        self.shipped.clear();
+        self.shipped_timestamps.clear();
+        self.shipped_since = None;
+        Metrics::checkpoints().increment_written();
+        Metrics::checkpoints().observe_lag(now.saturating_duration_since(since).as_millis() as u64);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoints_are_batched() {
+        let start = Instant::now();
+        let mut checkpoints = Checkpoints::new(CheckpointPolicy {
+            interval: Duration::from_secs(5),
+            max_pending: 3,
+        });
+        checkpoints.stage(None, 1, Some(10));
+        assert_eq!(checkpoints.deadline(), None);
+        checkpoints.ship(&None, start);
+        assert_eq!(checkpoints.deadline(), Some(start + Duration::from_secs(5)));
+        assert!(!checkpoints.is_due(start + Duration::from_secs(1)));
+
+        // Later updates of a file replace the earlier ones, lines not shipped are discarded
+        checkpoints.stage(None, 1, Some(20));
+        checkpoints.stage(None, 2, None);
+        checkpoints.ship(&None, start + Duration::from_secs(1));
+        checkpoints.stage(None, 3, Some(30));
+        checkpoints.clear(&None);
+        assert_eq!(checkpoints.shipped.len(), 2);
+        assert_eq!(checkpoints.shipped.get(&(1, None)), Some(&Some(20)));
+        assert_eq!(checkpoints.deadline(), Some(start + Duration::from_secs(5)));
+        assert!(checkpoints.is_due(start + Duration::from_secs(5)));
+
+        checkpoints.stage(None, 3, Some(30));
+        checkpoints.ship(&None, start + Duration::from_secs(2));
+        assert!(checkpoints.is_due(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn timestamps_are_shipped_with_the_offsets() {
+        let start = Instant::now();
+        let mut checkpoints = Checkpoints::new(CheckpointPolicy::default());
+        checkpoints.stage_timestamp(None, "docker:a".into(), 10);
+        checkpoints.stage_timestamp(None, "docker:b".into(), 20);
+        checkpoints.clear(&None);
+        checkpoints.ship(&None, start);
+        assert_eq!(checkpoints.deadline(), None);
+
+        checkpoints.stage_timestamp(None, "docker:a".into(), 10);
+        checkpoints.stage_timestamp(None, "docker:a".into(), 30);
+        checkpoints.ship(&None, start);
+        assert_eq!(checkpoints.shipped_timestamps.len(), 1);
+        assert_eq!(checkpoints.shipped_timestamps.get("docker:a"), Some(&30));
+        assert_eq!(checkpoints.deadline(), Some(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn routes_are_shipped_apart() {
+        let start = Instant::now();
+        let mut checkpoints = Checkpoints::new(CheckpointPolicy::default());
+        let route: Route = Some("audit".into());
+        checkpoints.stage(None, 1, Some(10));
+        checkpoints.stage(route.clone(), 1, Some(5));
+        checkpoints.stage_timestamp(route.clone(), "docker:a".into(), 20);
+
+        // A client acknowledging its lines doesn't ship the ones the others are still sending
+        checkpoints.ship(&None, start);
+        assert_eq!(checkpoints.shipped.len(), 1);
+        assert_eq!(checkpoints.shipped.get(&(1, None)), Some(&Some(10)));
+        assert!(checkpoints.shipped_timestamps.is_empty());
+
+        checkpoints.clear(&None);
+        checkpoints.ship(&route, start);
+        assert_eq!(checkpoints.shipped.len(), 2);
+        assert_eq!(checkpoints.shipped.get(&(1, route)), Some(&Some(5)));
+        assert_eq!(checkpoints.shipped_timestamps.get("docker:a"), Some(&20));
+    }
+}
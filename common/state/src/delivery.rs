@@ -0,0 +1,211 @@
+use crate::FileOffsetStateError;
+
+use log::error;
+use metrics::Metrics;
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch, DB};
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+pub(crate) const DELIVERY_NAME: &str = "delivery_counts";
+
+/// Key of the counts of all the files read, including the ones deleted since
+const TOTAL_KEY: &[u8] = b"total";
+
+/// Lines read from a file and lines of it the ingest API acknowledged
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct DeliveryCounts {
+    read: u64,
+    acknowledged: u64,
+}
+
+impl DeliveryCounts {
+    fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.read.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.acknowledged.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<DeliveryCounts> {
+        Some(DeliveryCounts {
+            read: u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?),
+            acknowledged: u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?),
+        })
+    }
+}
+
+/// The counts of each file by inode and their total, as of the last checkpoint
+pub(crate) fn read_counts(
+    db: &DB,
+) -> Result<(HashMap<u64, DeliveryCounts>, DeliveryCounts), FileOffsetStateError> {
+    let cf_handle = db
+        .cf_handle(DELIVERY_NAME)
+        .ok_or_else(|| FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into()))?;
+    let mut counts = HashMap::new();
+    let mut total = DeliveryCounts::default();
+    for (k, v) in db.iterator_cf(cf_handle, IteratorMode::Start) {
+        let entry = match DeliveryCounts::from_bytes(&v) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if &*k == TOTAL_KEY {
+            total = entry;
+        } else if let Ok(inode) = (&*k).try_into() {
+            counts.insert(u64::from_be_bytes(inode), entry);
+        }
+    }
+    Ok((counts, total))
+}
+
+/// Counts the lines read from each file against the ones acknowledged, to tell lines lost on
+/// their way apart from lines that were never read. Reads are staged along with the offsets of
+/// their lines, lines read again after a restart because their offsets weren't saved are then
+/// only counted once.
+#[derive(Default)]
+pub(crate) struct Deliveries {
+    counts: HashMap<u64, DeliveryCounts>,
+    total: DeliveryCounts,
+    /// Lines read whose offsets haven't been shipped yet, by inode
+    staged: HashMap<u64, u64>,
+    /// Inodes whose entry in the db is out of date
+    changed: HashSet<u64>,
+    /// Whether the total in the db is out of date
+    dirty: bool,
+}
+
+impl Deliveries {
+    /// Picks up the counts of the previous runs
+    pub(crate) fn load(db: &DB) -> Self {
+        let (counts, total) = read_counts(db).unwrap_or_else(|e| {
+            error!("unable to read delivery counts from the state db: {}", e);
+            Default::default()
+        });
+        let deliveries = Deliveries {
+            counts,
+            total,
+            ..Default::default()
+        };
+        deliveries.report();
+        deliveries
+    }
+
+    pub(crate) fn read(&mut self, inode: u64) {
+        *self.staged.entry(inode).or_default() += 1;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.staged.clear();
+    }
+
+    /// Counts the staged reads, when the offsets of their lines are shipped
+    pub(crate) fn ship(&mut self) {
+        if self.staged.is_empty() {
+            return;
+        }
+        for (inode, lines) in self.staged.drain() {
+            self.counts.entry(inode).or_default().read += lines;
+            self.total.read += lines;
+            self.changed.insert(inode);
+        }
+        self.dirty = true;
+        self.report();
+    }
+
+    pub(crate) fn acknowledge(&mut self, inode: u64, lines: u64) {
+        // Deleted files only count in the total
+        if let Some(counts) = self.counts.get_mut(&inode) {
+            counts.acknowledged += lines;
+            self.changed.insert(inode);
+        }
+        self.total.acknowledged += lines;
+        self.dirty = true;
+        self.report();
+    }
+
+    pub(crate) fn delete(&mut self, inode: u64) {
+        if self.counts.remove(&inode).is_some() {
+            self.changed.insert(inode);
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn is_changed(&self) -> bool {
+        self.dirty
+    }
+
+    /// Adds the counts that changed to the batch of a checkpoint
+    pub(crate) fn stage_write(&self, cf_handle: &ColumnFamily, wb: &mut WriteBatch) {
+        if !self.dirty {
+            return;
+        }
+        for inode in self.changed.iter() {
+            match self.counts.get(inode) {
+                Some(counts) => wb.put_cf(cf_handle, u64::to_be_bytes(*inode), counts.to_bytes()),
+                None => wb.delete_cf(cf_handle, u64::to_be_bytes(*inode)),
+            }
+        }
+        wb.put_cf(cf_handle, TOTAL_KEY, self.total.to_bytes());
+    }
+
+    /// Once the batch of a checkpoint was written
+    pub(crate) fn written(&mut self) {
+        self.changed.clear();
+        self.dirty = false;
+    }
+
+    fn report(&self) {
+        Metrics::http().set_delivery(self.total.read, self.total.acknowledged);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AgentState;
+    use tempfile::tempdir;
+
+    #[test]
+    fn counts_reads_of_shipped_lines_and_acknowledgements() {
+        let dir = tempdir().unwrap();
+        let state = AgentState::new(dir.path()).unwrap();
+
+        let mut deliveries = Deliveries::load(&state.db);
+        for inode in &[1, 1, 2] {
+            deliveries.read(*inode);
+        }
+        deliveries.ship();
+        deliveries.read(2);
+        // Read again after a restart
+        deliveries.clear();
+        deliveries.ship();
+        deliveries.acknowledge(1, 1);
+        deliveries.delete(2);
+        deliveries.acknowledge(2, 1);
+        assert!(deliveries.is_changed());
+
+        let cf_handle = state.db.cf_handle(DELIVERY_NAME).unwrap();
+        let mut wb = WriteBatch::default();
+        deliveries.stage_write(cf_handle, &mut wb);
+        state.db.write(wb).unwrap();
+        deliveries.written();
+        assert!(!deliveries.is_changed());
+
+        let (counts, total) = read_counts(&state.db).unwrap();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(
+            counts.get(&1),
+            Some(&DeliveryCounts {
+                read: 2,
+                acknowledged: 1
+            })
+        );
+        assert_eq!(
+            total,
+            DeliveryCounts {
+                read: 3,
+                acknowledged: 2
+            }
+        );
+    }
+}
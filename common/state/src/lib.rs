@@ -1,17 +1,35 @@
 use rocksdb::{ColumnFamilyDescriptor, IteratorMode, Options, WriteBatch, DB};
 
 use derivative::Derivative;
-use futures::future::{Future, FutureExt};
-use futures::stream::StreamExt;
+use futures::future::Future;
 
-use log::{error, info, warn};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 
+use std::cmp;
+use std::collections::BTreeMap;
 use std::convert::{AsRef, Into, TryInto};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+mod backup;
+mod checkpoint;
+mod delivery;
+mod offsets;
+mod recent;
+
+use backup::Backup;
+pub use checkpoint::CheckpointPolicy;
+use checkpoint::Checkpoints;
+use delivery::Deliveries;
+pub use offsets::OffsetMap;
+use recent::RecentLines;
+pub use recent::{line_hash, DuplicateFilter};
+
 const OFFSET_NAME: &str = "file_offsets";
+const TIMESTAMP_NAME: &str = "source_timestamps";
 
 #[derive(Debug, Error)]
 pub enum StateError {
@@ -30,16 +48,27 @@ pub struct AgentState {
     db: Arc<DB>,
     #[derivative(Debug = "ignore")]
     offset_cf_opt: Options,
+    #[derivative(Debug = "ignore")]
+    backup: Arc<Backup>,
 }
 
 impl AgentState {
     pub fn new(path: impl AsRef<Path>) -> Result<Self, StateError> {
-        let path = path.as_ref();
+        Self::open(path.as_ref(), true)
+    }
+
+    /// Opens the state db without attempting to repair it, so that tools run next to the
+    /// agent fail rather than reset the db the agent holds
+    pub fn open_existing(path: impl AsRef<Path>) -> Result<Self, StateError> {
+        Self::open(path.as_ref(), false)
+    }
 
+    fn open(path: &Path, repair: bool) -> Result<Self, StateError> {
         if path.metadata()?.permissions().readonly() {
             return Err(StateError::PermissionDenied(path.into()));
         }
 
+        let backup = Backup::new(path.join(backup::BACKUP_NAME));
         let path = path.join("agent_state.db");
 
         let mut db_opts = Options::default();
@@ -47,54 +76,235 @@ impl AgentState {
         db_opts.create_if_missing(true);
 
         let offset_cf_opt = Options::default();
-        let offset_cf = ColumnFamilyDescriptor::new(OFFSET_NAME, offset_cf_opt.clone());
-        let cfs = vec![offset_cf];
+        let cfs = || {
+            vec![
+                ColumnFamilyDescriptor::new(OFFSET_NAME, offset_cf_opt.clone()),
+                ColumnFamilyDescriptor::new(TIMESTAMP_NAME, Options::default()),
+                ColumnFamilyDescriptor::new(recent::RECENT_NAME, Options::default()),
+                ColumnFamilyDescriptor::new(delivery::DELIVERY_NAME, Options::default()),
+            ]
+        };
 
         info!("Opening state db at {:?}", path);
 
-        let db = match DB::open_cf_descriptors(&db_opts, &path, cfs) {
+        let db = match DB::open_cf_descriptors(&db_opts, &path, cfs()) {
             Ok(db) => db,
+            Err(e) if !repair => return Err(e.into()),
             // Attempt to repair a badly closed DB
             Err(e) => {
                 warn!("error opening state db, attempted to repair: {}", e);
-                DB::repair(&db_opts, &path).map_or_else(
-                    |_| {
+                let db = match DB::repair(&db_opts, &path)
+                    .and_then(|_| DB::open_cf_descriptors(&db_opts, &path, cfs()))
+                {
+                    Ok(db) => db,
+                    Err(e) => {
+                        warn!("unable to repair state db, resetting it: {}", e);
                         DB::destroy(&db_opts, &path)?;
-                        DB::open_cf_descriptors(
-                            &db_opts,
-                            &path,
-                            vec![ColumnFamilyDescriptor::new(
-                                OFFSET_NAME,
-                                offset_cf_opt.clone(),
-                            )],
-                        )
-                    },
-                    |_| {
-                        DB::open_cf_descriptors(
-                            &db_opts,
-                            &path,
-                            vec![ColumnFamilyDescriptor::new(
-                                OFFSET_NAME,
-                                offset_cf_opt.clone(),
-                            )],
-                        )
-                    },
-                )?
+                        DB::open_cf_descriptors(&db_opts, &path, cfs())?
+                    }
+                };
+                backup.recover(&db);
+                db
             }
         };
+        // A missing db isn't recovered, it was most likely removed to reset the offsets
         Ok(Self {
             db: Arc::new(db),
             offset_cf_opt,
+            backup: Arc::new(backup),
         })
     }
 }
 
 impl AgentState {
     pub fn get_offset_state(&self) -> FileOffsetState {
-        FileOffsetState::new(self.db.clone(), self.offset_cf_opt.clone())
+        FileOffsetState::new(
+            self.db.clone(),
+            self.offset_cf_opt.clone(),
+            self.backup.clone(),
+        )
+    }
+
+    pub fn get_timestamp_state(&self) -> TimestampState {
+        TimestampState {
+            db: self.db.clone(),
+        }
+    }
+
+    pub fn export(&self) -> Result<StateSnapshot, FileOffsetStateError> {
+        snapshot(&self.db)
+    }
+
+    /// Writes the offsets and timestamps of `snapshot`, replacing the ones of the same files
+    /// and sources while keeping the others
+    pub fn import(&self, snapshot: &StateSnapshot) -> Result<(), FileOffsetStateError> {
+        import(&self.db, snapshot)
+    }
+}
+
+fn snapshot(db: &DB) -> Result<StateSnapshot, FileOffsetStateError> {
+    Ok(StateSnapshot {
+        offsets: read_offsets(db)?
+            .into_iter()
+            .map(|fo| (fo.key.0, fo.offset))
+            .collect(),
+        timestamps: TimestampState::read_entries(db)?,
+    })
+}
+
+fn import(db: &DB, snapshot: &StateSnapshot) -> Result<(), FileOffsetStateError> {
+    let offsets = db
+        .cf_handle(OFFSET_NAME)
+        .ok_or_else(|| FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into()))?;
+    let timestamps = db
+        .cf_handle(TIMESTAMP_NAME)
+        .ok_or_else(|| FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into()))?;
+    let mut wb = WriteBatch::default();
+    for (inode, offset) in snapshot.offsets.iter() {
+        wb.put_cf(offsets, u64::to_be_bytes(*inode), u64::to_be_bytes(*offset));
+    }
+    for (key, timestamp) in snapshot.timestamps.iter() {
+        wb.put_cf(timestamps, key, i64::to_be_bytes(*timestamp));
+    }
+    Ok(db.write(wb)?)
+}
+
+/// The offsets of the files, a file being resumed from the lowest of the offsets recorded by
+/// the default client and the clients of the routes, so that the lines a route hadn't shipped
+/// yet are read again rather than lost
+fn read_offsets(db: &DB) -> Result<Vec<FileOffset>, FileOffsetStateError> {
+    let cf_handle = db
+        .cf_handle(OFFSET_NAME)
+        .ok_or_else(|| FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into()))?;
+    let mut offsets = BTreeMap::new();
+    for (k, v) in db.iterator_cf(cf_handle, IteratorMode::Start) {
+        match (leading_u64(&k), leading_u64(&v)) {
+            (Some(key), Some(offset)) => {
+                let lowest = offsets.entry(key).or_insert(offset);
+                *lowest = cmp::min(*lowest, offset);
+            }
+            _ => warn!(
+                "skipping invalid offset of {} bytes for a file id of {} bytes",
+                v.len(),
+                k.len()
+            ),
+        }
+    }
+    Ok(offsets
+        .into_iter()
+        .map(|(key, offset)| FileOffset {
+            key: FileId(key),
+            offset,
+        })
+        .collect())
+}
+
+/// The key of the offset of a file: its inode, followed by the name of the route for the
+/// offsets recorded by the client of a route
+fn offset_key(inode: u64, route: Option<&str>) -> Vec<u8> {
+    let mut key = u64::to_be_bytes(inode).to_vec();
+    if let Some(route) = route {
+        key.extend_from_slice(route.as_bytes());
+    }
+    key
+}
+
+/// The big endian integer in the first 8 bytes of `bytes`, `None` when there are fewer
+fn leading_u64(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?))
+}
+
+/// The content of the state db, serialized as JSON by `logdna-agent state export` and read
+/// back by `logdna-agent state import`
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// Offsets of files by inode, they only apply to the filesystem the files were read from
+    #[serde(default)]
+    pub offsets: BTreeMap<u64, u64>,
+    /// Timestamps of the sources resuming by time, by source key
+    #[serde(default)]
+    pub timestamps: BTreeMap<String, i64>,
+}
+
+/// Stores the last seen timestamp, in nanoseconds since the epoch, for sources that resume
+/// reading by time rather than by file offset (e.g. docker containers)
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct TimestampState {
+    #[derivative(Debug = "ignore")]
+    db: Arc<DB>,
+}
+
+impl TimestampState {
+    pub fn get(&self, key: &str) -> Result<Option<i64>, FileOffsetStateError> {
+        let cf_handle = self.db.cf_handle(TIMESTAMP_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        self.db
+            .get_cf(cf_handle, key)?
+            .map(|v| decode_timestamp(key.as_bytes(), &v))
+            .transpose()
+    }
+
+    pub fn set(&self, key: &str, timestamp: i64) -> Result<(), FileOffsetStateError> {
+        let cf_handle = self.db.cf_handle(TIMESTAMP_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        Ok(self
+            .db
+            .put_cf(cf_handle, key, i64::to_be_bytes(timestamp))?)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), FileOffsetStateError> {
+        let cf_handle = self.db.cf_handle(TIMESTAMP_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        Ok(self.db.delete_cf(cf_handle, key)?)
+    }
+
+    pub fn entries(&self) -> Result<BTreeMap<String, i64>, FileOffsetStateError> {
+        Self::read_entries(&self.db)
+    }
+
+    fn read_entries(db: &DB) -> Result<BTreeMap<String, i64>, FileOffsetStateError> {
+        let cf_handle = db.cf_handle(TIMESTAMP_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        // Invalid rows are skipped so that the other sources keep resuming, and an export
+        // still lists them
+        Ok(db
+            .iterator_cf(cf_handle, IteratorMode::Start)
+            .filter_map(
+                |(k, v)| match (std::str::from_utf8(&k), decode_timestamp(&k, &v)) {
+                    (Ok(key), Ok(timestamp)) => Some((key.to_string(), timestamp)),
+                    (Err(_), _) => {
+                        warn!(
+                            "skipping the timestamp of a source key that isn't utf8: {:?}",
+                            k
+                        );
+                        None
+                    }
+                    (_, Err(e)) => {
+                        warn!("skipping {}", e);
+                        None
+                    }
+                },
+            )
+            .collect())
     }
 }
 
+fn decode_timestamp(key: &[u8], value: &[u8]) -> Result<i64, FileOffsetStateError> {
+    let bytes: [u8; 8] = value.try_into().map_err(|_| {
+        FileOffsetStateError::DbError(format!(
+            "invalid timestamp of {} bytes for {}",
+            value.len(),
+            String::from_utf8_lossy(key)
+        ))
+    })?;
+    Ok(i64::from_be_bytes(bytes))
+}
+
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
 pub struct FileId(u64);
 
@@ -128,18 +338,80 @@ pub enum FileOffsetUpdate {
     Delete(FileId),
 }
 
+/// Events sent through the handles, the updates, flushes and clears of the clients of the routes
+/// carrying the name of their route
 pub enum FileOffsetEvent {
-    Update(FileOffsetUpdate),
-    Clear,
-    Flush,
+    Update(Option<Arc<str>>, FileOffsetUpdate),
+    /// Hash of a line, sent along with the update of its offset when tracking lines
+    Line(u64),
+    /// A line of the file was read into a batch
+    Read(FileId),
+    /// Lines of the file were acknowledged by the ingest API
+    Delivered(FileId, u64),
+    /// Timestamp a source resuming by time was read up to, staged like the offsets of files
+    Timestamp(Option<Arc<str>>, String, i64),
+    Clear(Option<Arc<str>>),
+    Flush(Option<Arc<str>>),
 }
 
 #[derive(Clone)]
 pub struct FileOffsetWriteHandle {
     tx: async_channel::Sender<FileOffsetEvent>,
+    track_lines: bool,
+    route: Option<Arc<str>>,
 }
 
 impl FileOffsetWriteHandle {
+    /// Records the offsets apart from the ones of the other clients, for the client of the
+    /// ingestion key route `route`
+    pub fn with_route(mut self, route: &str) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+
+    /// Whether the hashes of the lines shipped are recorded, through `line`
+    pub fn tracks_lines(&self) -> bool {
+        self.track_lines
+    }
+
+    pub async fn line(&self, hash: u64) -> Result<(), FileOffsetStateError> {
+        Ok(self.tx.send(FileOffsetEvent::Line(hash)).await?)
+    }
+
+    /// Counts a line of the file read into a batch, sent along with the update of its offset
+    pub async fn read(&self, file_name: impl Into<FileId>) -> Result<(), FileOffsetStateError> {
+        Ok(self
+            .tx
+            .send(FileOffsetEvent::Read(file_name.into()))
+            .await?)
+    }
+
+    /// Counts `lines` of the file acknowledged by the ingest API, sent before the flush of the
+    /// request they were in
+    pub async fn delivered(
+        &self,
+        file_name: impl Into<FileId>,
+        lines: u64,
+    ) -> Result<(), FileOffsetStateError> {
+        Ok(self
+            .tx
+            .send(FileOffsetEvent::Delivered(file_name.into(), lines))
+            .await?)
+    }
+
+    /// Stages the timestamp of a source resuming by time, it's written to the `TimestampState`
+    /// once the line it was read from has been shipped
+    pub async fn timestamp(&self, key: &str, nanos: i64) -> Result<(), FileOffsetStateError> {
+        Ok(self
+            .tx
+            .send(FileOffsetEvent::Timestamp(
+                self.route.clone(),
+                key.to_string(),
+                nanos,
+            ))
+            .await?)
+    }
+
     pub async fn update(
         &self,
         file_name: impl Into<FileId>,
@@ -147,36 +419,51 @@ impl FileOffsetWriteHandle {
     ) -> Result<(), FileOffsetStateError> {
         Ok(self
             .tx
-            .send(FileOffsetEvent::Update(FileOffsetUpdate::Update(
-                FileOffset {
+            .send(FileOffsetEvent::Update(
+                self.route.clone(),
+                FileOffsetUpdate::Update(FileOffset {
                     key: file_name.into(),
                     offset,
-                },
-            )))
+                }),
+            ))
             .await?)
     }
 
     pub async fn delete(&self, file_name: impl Into<FileId>) -> Result<(), FileOffsetStateError> {
         Ok(self
             .tx
-            .send(FileOffsetEvent::Update(FileOffsetUpdate::Delete(
-                file_name.into(),
-            )))
+            .send(FileOffsetEvent::Update(
+                self.route.clone(),
+                FileOffsetUpdate::Delete(file_name.into()),
+            ))
             .await?)
     }
 }
 
 pub struct FileOffsetFlushHandle {
     tx: async_channel::Sender<FileOffsetEvent>,
+    route: Option<Arc<str>>,
 }
 
 impl FileOffsetFlushHandle {
+    /// Ships and clears only the offsets recorded through the write handle of the same route
+    pub fn with_route(mut self, route: &str) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+
     pub async fn flush(&self) -> Result<(), FileOffsetStateError> {
-        Ok(self.tx.send(FileOffsetEvent::Flush).await?)
+        Ok(self
+            .tx
+            .send(FileOffsetEvent::Flush(self.route.clone()))
+            .await?)
     }
 
     pub async fn clear(&self) -> Result<(), FileOffsetStateError> {
-        Ok(self.tx.send(FileOffsetEvent::Clear).await?)
+        Ok(self
+            .tx
+            .send(FileOffsetEvent::Clear(self.route.clone()))
+            .await?)
     }
 }
 
@@ -194,51 +481,66 @@ impl FileOffsetShutdownHandle {
 pub struct FileOffsetState {
     db: Arc<DB>,
     cf_opts: Options,
+    backup: Arc<Backup>,
+    checkpoint_policy: CheckpointPolicy,
+    restart_dedup_window: Option<Duration>,
     rx: std::cell::RefCell<Option<async_channel::Receiver<FileOffsetEvent>>>,
     shutdown: std::cell::RefCell<Option<async_channel::Sender<FileOffsetEvent>>>,
     tx: async_channel::Sender<FileOffsetEvent>,
 }
 
 impl FileOffsetState {
-    fn new(db: Arc<DB>, cf_opts: Options) -> Self {
+    fn new(db: Arc<DB>, cf_opts: Options, backup: Arc<Backup>) -> Self {
         let (tx, rx) = async_channel::unbounded();
 
         FileOffsetState {
             db,
             cf_opts,
+            backup,
+            checkpoint_policy: CheckpointPolicy::default(),
+            restart_dedup_window: None,
             rx: std::cell::RefCell::new(Some(rx)),
             shutdown: std::cell::RefCell::new(Some(tx.clone())),
             tx,
         }
     }
 
+    pub fn with_checkpoint_policy(mut self, policy: CheckpointPolicy) -> Self {
+        self.checkpoint_policy = policy;
+        self
+    }
+
+    /// Records the hashes of the lines shipped within `window`, for lines read again after a
+    /// restart to be skipped through `duplicate_filter`
+    pub fn with_restart_dedup_window(mut self, window: Option<Duration>) -> Self {
+        self.restart_dedup_window = window;
+        self
+    }
+
+    /// Matches the lines shipped before the restart, `None` unless a dedup window is set. It
+    /// has to be created before the state is run.
+    pub fn duplicate_filter(&self) -> Result<Option<DuplicateFilter>, FileOffsetStateError> {
+        self.restart_dedup_window
+            .map(|window| DuplicateFilter::load(&self.db, window))
+            .transpose()
+    }
+
     pub fn offsets(&self) -> Result<Vec<FileOffset>, FileOffsetStateError> {
-        let cf_handle = self.db.cf_handle(OFFSET_NAME).ok_or_else(|| {
-            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
-        })?;
-        Ok(self
-            .db
-            .iterator_cf(cf_handle, IteratorMode::Start)
-            .map(|(k, v)| {
-                let (k_bytes, _) = k.split_at(std::mem::size_of::<u64>());
-                let (v_bytes, _) = v.split_at(std::mem::size_of::<u64>());
-                FileOffset {
-                    key: FileId(u64::from_be_bytes(k_bytes.try_into().unwrap_or([0; 8]))),
-                    offset: u64::from_be_bytes(v_bytes.try_into().unwrap_or([0; 8])),
-                }
-            })
-            .collect::<Vec<_>>())
+        read_offsets(&self.db)
     }
 
     pub fn write_handle(&self) -> FileOffsetWriteHandle {
         FileOffsetWriteHandle {
             tx: self.tx.clone(),
+            track_lines: self.restart_dedup_window.is_some(),
+            route: None,
         }
     }
 
     pub fn flush_handle(&self) -> FileOffsetFlushHandle {
         FileOffsetFlushHandle {
             tx: self.tx.clone(),
+            route: None,
         }
     }
 
@@ -259,50 +561,106 @@ impl FileOffsetState {
             .take()
             .ok_or(FileOffsetStateError::AlreadyRunning)?;
         let db = self.db.clone();
-        Ok(rx
-            .fold(Some(WriteBatch::default()), move |acc, event| {
-                let db = db.clone();
-                async move {
-                    match db.cf_handle(OFFSET_NAME).ok_or_else(|| {
-                        FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
-                    }) {
-                        Ok(cf_handle) => match (acc, event) {
-                            (Some(wb), FileOffsetEvent::Flush) => {
-                                let ret = db.write(wb).map(|_| ());
-                                ret.map(|_| None).map_err(|e| e.into())
+        let backup = self.backup.clone();
+        let mut checkpoints = Checkpoints::new(self.checkpoint_policy);
+        let mut deliveries = Deliveries::load(&db);
+        let mut recent = self
+            .restart_dedup_window
+            .map(|window| RecentLines::load(&db, window));
+        Ok(async move {
+            loop {
+                let event = match checkpoints.deadline() {
+                    Some(deadline) => {
+                        let deadline = tokio::time::Instant::from_std(deadline);
+                        match tokio::time::timeout_at(deadline, rx.recv()).await {
+                            Ok(event) => event.ok(),
+                            // Shipped offsets are written once due even if nothing else is
+                            // shipped in the meantime
+                            Err(_) => {
+                                checkpoints.checkpoint(
+                                    &db,
+                                    &backup,
+                                    &mut deliveries,
+                                    Instant::now(),
+                                );
+                                continue;
                             }
-                            (None, FileOffsetEvent::Flush) => Ok(None),
-                            (wb, FileOffsetEvent::Update(e)) => {
-                                let mut wb = wb.unwrap_or_default();
-                                match e {
-                                    FileOffsetUpdate::Update(FileOffset { key, offset }) => wb
-                                        .put_cf(
-                                            cf_handle,
-                                            u64::to_be_bytes(key.0),
-                                            u64::to_be_bytes(offset),
-                                        ),
-                                    FileOffsetUpdate::Delete(key) => {
-                                        wb.delete_cf(cf_handle, u64::to_be_bytes(key.0))
-                                    }
-                                };
-                                Ok(Some(wb))
-                            }
-                            (_, FileOffsetEvent::Clear) => Ok(None),
-                        },
-                        Err(e) => Err(e),
+                        }
+                    }
+                    None => rx.recv().await.ok(),
+                };
+                match event {
+                    Some(FileOffsetEvent::Update(
+                        route,
+                        FileOffsetUpdate::Update(FileOffset { key, offset }),
+                    )) => checkpoints.stage(route, key.0, Some(offset)),
+                    Some(FileOffsetEvent::Update(route, FileOffsetUpdate::Delete(key))) => {
+                        checkpoints.stage(route, key.0, None);
+                        deliveries.delete(key.0);
+                    }
+                    Some(FileOffsetEvent::Line(hash)) => {
+                        if let Some(recent) = recent.as_mut() {
+                            recent.stage(hash);
+                        }
+                    }
+                    Some(FileOffsetEvent::Read(key)) => deliveries.read(key.0),
+                    Some(FileOffsetEvent::Delivered(key, lines)) => {
+                        deliveries.acknowledge(key.0, lines)
+                    }
+                    Some(FileOffsetEvent::Timestamp(route, key, nanos)) => {
+                        checkpoints.stage_timestamp(route, key, nanos)
+                    }
+                    Some(FileOffsetEvent::Clear(route)) => {
+                        checkpoints.clear(&route);
+                        deliveries.clear();
+                        if let Some(recent) = recent.as_mut() {
+                            recent.clear();
+                        }
+                    }
+                    Some(FileOffsetEvent::Flush(route)) => {
+                        let now = Instant::now();
+                        checkpoints.ship(&route, now);
+                        deliveries.ship();
+                        if let Some(recent) = recent.as_mut() {
+                            recent.flush(&db);
+                        }
+                        if checkpoints.is_due(now) {
+                            checkpoints.checkpoint(&db, &backup, &mut deliveries, now);
+                        }
+                    }
+                    // The channel is closed on shutdown
+                    None => {
+                        checkpoints.checkpoint(&db, &backup, &mut deliveries, Instant::now());
+                        break;
                     }
-                    .map_err(|e| error!("{:?}", e))
-                    .ok()
-                    .flatten()
                 }
-            })
-            .map(|_| ()))
+            }
+        })
     }
 }
 
+/// Where a line leaves the source it was read from, for the sources other than the tailed
+/// files. It's written to the state db along with the offsets of the files, once the line is
+/// acknowledged by the ingest API.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Checkpoint {
+    /// Offset to resume the file with the inode `key` from
+    Offset { key: u64, offset: u64 },
+    /// Timestamp, in nanoseconds since the epoch, the source `key` was read up to
+    Timestamp { key: String, nanos: i64 },
+}
+
 pub trait GetOffset {
     fn get_key(&self) -> Option<u64>;
     fn get_offset(&self) -> Option<u64>;
+    /// `line_hash` of the content of the line, if it was read
+    fn get_line_hash(&self) -> Option<u64> {
+        None
+    }
+    /// Key and timestamp of the `TimestampState` of the source, for sources resuming by time
+    fn get_timestamp(&self) -> Option<(&str, i64)> {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -320,7 +678,13 @@ mod test {
         // The times/delays are significant
         fn _test(db_path: &std::path::Path, initial_count: usize) {
             let agent_state = AgentState::new(db_path).unwrap();
-            let offset_state = agent_state.get_offset_state();
+            let offset_state =
+                agent_state
+                    .get_offset_state()
+                    .with_checkpoint_policy(CheckpointPolicy {
+                        interval: std::time::Duration::from_millis(0),
+                        ..Default::default()
+                    });
 
             let wh = offset_state.write_handle();
             let fh = offset_state.flush_handle();
@@ -375,4 +739,99 @@ mod test {
         _test(&data_dir, 0);
         _test(&data_dir, 2);
     }
+
+    #[test]
+    fn timestamps_persist() {
+        let data_dir = tempdir().expect("Could not create temp dir").into_path();
+        {
+            let state = AgentState::new(&data_dir).unwrap().get_timestamp_state();
+            assert_eq!(state.get("container").unwrap(), None);
+            state.set("container", 1_600_000_000_123_456_789).unwrap();
+            state.set("other", 42).unwrap();
+            state.delete("other").unwrap();
+        }
+        let state = AgentState::new(&data_dir).unwrap().get_timestamp_state();
+        assert_eq!(
+            state.get("container").unwrap(),
+            Some(1_600_000_000_123_456_789)
+        );
+        assert_eq!(state.get("other").unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_rows_are_skipped() {
+        let agent_state = AgentState::new(tempdir().unwrap().into_path()).unwrap();
+        let state = agent_state.get_timestamp_state();
+        let cf_handle = state.db.cf_handle(TIMESTAMP_NAME).unwrap();
+        state.db.put_cf(cf_handle, "short", [1, 2]).unwrap();
+        state
+            .db
+            .put_cf(cf_handle, [0xff, 0xfe], i64::to_be_bytes(7))
+            .unwrap();
+        state.set("docker:abc", 42).unwrap();
+        assert!(state.get("short").is_err());
+        let entries = state.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.get("docker:abc"), Some(&42));
+
+        let cf_handle = state.db.cf_handle(OFFSET_NAME).unwrap();
+        state
+            .db
+            .put_cf(cf_handle, [1, 2, 3], u64::to_be_bytes(9))
+            .unwrap();
+        state
+            .db
+            .put_cf(cf_handle, u64::to_be_bytes(1), u64::to_be_bytes(5))
+            .unwrap();
+        let snapshot = agent_state.export().unwrap();
+        assert_eq!(snapshot.offsets.len(), 1);
+        assert_eq!(snapshot.offsets.get(&1), Some(&5));
+    }
+
+    #[test]
+    fn files_resume_from_the_lowest_offset_of_the_routes() {
+        let agent_state = AgentState::new(tempdir().unwrap().into_path()).unwrap();
+        let offset_state = agent_state.get_offset_state();
+        let db = &offset_state.db;
+        let cf_handle = db.cf_handle(OFFSET_NAME).unwrap();
+        for (inode, route, offset) in &[
+            (1, None, 50),
+            (1, Some("audit"), 20),
+            (1, Some("billing"), 70),
+            (2, Some("audit"), 7),
+        ] {
+            db.put_cf(
+                cf_handle,
+                offset_key(*inode, *route),
+                u64::to_be_bytes(*offset),
+            )
+            .unwrap();
+        }
+        let offsets = offset_state.offsets().unwrap();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!((offsets[0].key.0, offsets[0].offset), (1, 20));
+        assert_eq!((offsets[1].key.0, offsets[1].offset), (2, 7));
+    }
+
+    #[test]
+    fn state_exports_and_imports() {
+        let source = AgentState::new(tempdir().unwrap().into_path()).unwrap();
+        source
+            .get_timestamp_state()
+            .set("kubelet:0123", 42)
+            .unwrap();
+        let mut snapshot = source.export().unwrap();
+        assert_eq!(snapshot.offsets.len(), 0);
+        assert_eq!(snapshot.timestamps.get("kubelet:0123"), Some(&42));
+
+        snapshot.offsets.insert(1_234, 5_678);
+        let target = AgentState::new(tempdir().unwrap().into_path()).unwrap();
+        target.get_timestamp_state().set("docker:abc", 7).unwrap();
+        target.import(&snapshot).unwrap();
+
+        let imported = target.export().unwrap();
+        assert_eq!(imported.offsets.get(&1_234), Some(&5_678));
+        assert_eq!(imported.timestamps.get("kubelet:0123"), Some(&42));
+        assert_eq!(imported.timestamps.get("docker:abc"), Some(&7));
+    }
 }
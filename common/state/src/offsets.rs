@@ -0,0 +1,129 @@
+use crate::FileId;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Number of shards, a power of two so that the shard of a file is picked by masking its hash
+const SHARDS: usize = 16;
+
+type Shard = RwLock<HashMap<FileId, u64>>;
+
+/// Offsets of files by their id, split in shards that are locked independently so that the
+/// tasks looking up and updating the offsets of different files rarely wait on each other
+pub struct OffsetMap {
+    shards: Vec<Shard>,
+}
+
+impl OffsetMap {
+    pub fn new() -> Self {
+        OffsetMap {
+            shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, key: &FileId) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize & (SHARDS - 1)]
+    }
+
+    fn read(shard: &Shard) -> RwLockReadGuard<'_, HashMap<FileId, u64>> {
+        shard.read().expect("Couldn't lock offsets")
+    }
+
+    fn write(shard: &Shard) -> RwLockWriteGuard<'_, HashMap<FileId, u64>> {
+        shard.write().expect("Couldn't lock offsets")
+    }
+
+    pub fn get(&self, key: &FileId) -> Option<u64> {
+        Self::read(self.shard(key)).get(key).copied()
+    }
+
+    /// Sets the offset of `key`, returning the one it replaced
+    pub fn insert(&self, key: FileId, offset: u64) -> Option<u64> {
+        Self::write(self.shard(&key)).insert(key, offset)
+    }
+
+    pub fn remove(&self, key: &FileId) -> Option<u64> {
+        Self::write(self.shard(key)).remove(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| Self::read(shard).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| Self::read(shard).is_empty())
+    }
+}
+
+impl Default for OffsetMap {
+    fn default() -> Self {
+        OffsetMap::new()
+    }
+}
+
+impl FromIterator<(FileId, u64)> for OffsetMap {
+    fn from_iter<I: IntoIterator<Item = (FileId, u64)>>(offsets: I) -> Self {
+        let map = OffsetMap::new();
+        for (key, offset) in offsets {
+            map.insert(key, offset);
+        }
+        map
+    }
+}
+
+impl From<HashMap<FileId, u64>> for OffsetMap {
+    fn from(offsets: HashMap<FileId, u64>) -> Self {
+        offsets.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn looks_up_and_updates_offsets() {
+        let offsets: OffsetMap = (0..100u64).map(|i| (FileId::from(&i), i * 10)).collect();
+        assert_eq!(offsets.len(), 100);
+        assert_eq!(offsets.get(&FileId::from(&42)), Some(420));
+        assert_eq!(offsets.get(&FileId::from(&100)), None);
+
+        assert_eq!(offsets.insert(FileId::from(&42), 500), Some(420));
+        assert_eq!(offsets.get(&FileId::from(&42)), Some(500));
+        assert_eq!(offsets.remove(&FileId::from(&42)), Some(500));
+        assert_eq!(offsets.get(&FileId::from(&42)), None);
+        assert_eq!(offsets.len(), 99);
+        assert!(!offsets.is_empty());
+        assert!(OffsetMap::default().is_empty());
+    }
+
+    #[test]
+    fn is_shared_between_threads() {
+        let offsets = Arc::new(OffsetMap::new());
+        let threads: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let offsets = offsets.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000u64 {
+                        let key = FileId::from(&(thread * 1000 + i));
+                        offsets.insert(key.clone(), i);
+                        assert_eq!(offsets.get(&key), Some(i));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(offsets.len(), 4000);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::FileOffsetStateError;
+
+use log::error;
+use metrics::Metrics;
+use rocksdb::{IteratorMode, WriteBatch, DB};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub(crate) const RECENT_NAME: &str = "recent_lines";
+
+/// Hashes the content of a line, with FNV-1a rather than the std hashers as the hashes are
+/// compared across restarts and upgrades of the agent
+pub fn line_hash(line: &[u8]) -> u64 {
+    line.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as i64)
+}
+
+/// When a line with a given hash was last shipped, in milliseconds since the epoch, and how
+/// many times it was shipped within the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Seen {
+    last: i64,
+    count: u32,
+}
+
+impl Seen {
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&self.last.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.count.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Seen> {
+        Some(Seen {
+            last: i64::from_be_bytes(bytes.get(..8)?.try_into().ok()?),
+            count: u32::from_be_bytes(bytes.get(8..12)?.try_into().ok()?),
+        })
+    }
+}
+
+fn read_seen(db: &DB) -> Result<HashMap<u64, Seen>, FileOffsetStateError> {
+    let cf_handle = db
+        .cf_handle(RECENT_NAME)
+        .ok_or_else(|| FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into()))?;
+    Ok(db
+        .iterator_cf(cf_handle, IteratorMode::Start)
+        .filter_map(|(k, v)| {
+            let hash = u64::from_be_bytes(k.get(..8)?.try_into().ok()?);
+            Some((hash, Seen::from_bytes(&v)?))
+        })
+        .collect())
+}
+
+/// Hashes of the lines shipped within the window, written to the state db on every flush so
+/// that they are there after a restart even when the offsets of their lines were not
+pub(crate) struct RecentLines {
+    window_ms: i64,
+    seen: HashMap<u64, Seen>,
+    /// When each hash was shipped and how many times, in the order they were shipped
+    shipped: VecDeque<(i64, u64, u32)>,
+    /// Hashes of lines that haven't been shipped yet
+    staged: Vec<u64>,
+    /// Hashes whose entry in the db is out of date
+    changed: HashSet<u64>,
+}
+
+impl RecentLines {
+    /// Picks up the hashes left in `db` by the previous run, so that they expire as well
+    pub(crate) fn load(db: &DB, window: Duration) -> Self {
+        let seen = read_seen(db).unwrap_or_else(|e| {
+            error!(
+                "unable to read recently shipped lines from the state db: {}",
+                e
+            );
+            HashMap::new()
+        });
+        let mut shipped: Vec<_> = seen
+            .iter()
+            .map(|(hash, seen)| (seen.last, *hash, seen.count))
+            .collect();
+        shipped.sort_unstable();
+        RecentLines {
+            window_ms: window.as_millis() as i64,
+            seen,
+            shipped: shipped.into(),
+            staged: Vec::new(),
+            changed: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn stage(&mut self, hash: u64) {
+        self.staged.push(hash);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.staged.clear();
+    }
+
+    /// Marks the staged hashes as shipped at `now`, in milliseconds since the epoch
+    pub(crate) fn ship(&mut self, now: i64) {
+        for hash in self.staged.drain(..) {
+            let seen = self.seen.entry(hash).or_insert(Seen {
+                last: now,
+                count: 0,
+            });
+            seen.last = now;
+            seen.count += 1;
+            self.shipped.push_back((now, hash, 1));
+            self.changed.insert(hash);
+        }
+    }
+
+    fn expire(&mut self, now: i64) {
+        while let Some((shipped_at, hash, count)) = self.shipped.front().copied() {
+            if shipped_at > now - self.window_ms {
+                break;
+            }
+            self.shipped.pop_front();
+            if let Some(seen) = self.seen.get_mut(&hash) {
+                seen.count = seen.count.saturating_sub(count);
+                if seen.count == 0 {
+                    self.seen.remove(&hash);
+                }
+            }
+            self.changed.insert(hash);
+        }
+    }
+
+    /// Ships the staged hashes and brings the db up to date. Unlike offsets, the hashes are a
+    /// best effort and not synced to disk.
+    pub(crate) fn flush(&mut self, db: &DB) {
+        let now = now_ms();
+        self.ship(now);
+        self.expire(now);
+        if let Err(e) = self.write(db) {
+            error!(
+                "unable to write recently shipped lines to the state db: {}",
+                e
+            );
+        }
+    }
+
+    fn write(&mut self, db: &DB) -> Result<(), FileOffsetStateError> {
+        if self.changed.is_empty() {
+            return Ok(());
+        }
+        let cf_handle = db.cf_handle(RECENT_NAME).ok_or_else(|| {
+            FileOffsetStateError::DbError("Failed to get ColumnFamily handle".into())
+        })?;
+        let mut wb = WriteBatch::default();
+        for hash in self.changed.iter() {
+            match self.seen.get(hash) {
+                Some(seen) => wb.put_cf(cf_handle, u64::to_be_bytes(*hash), seen.to_bytes()),
+                None => wb.delete_cf(cf_handle, u64::to_be_bytes(*hash)),
+            }
+        }
+        db.write(wb)?;
+        self.changed.clear();
+        Ok(())
+    }
+}
+
+/// Skips the lines shipped right before the agent was restarted, which are read again when
+/// their offsets weren't saved, by matching their hashes against the ones shipped within the
+/// window before the restart. Each shipped line skips one line read again, and only lines read
+/// within the window after the restart are matched.
+pub struct DuplicateFilter {
+    remaining: HashMap<u64, u32>,
+    until: Instant,
+}
+
+impl DuplicateFilter {
+    pub(crate) fn load(db: &DB, window: Duration) -> Result<Self, FileOffsetStateError> {
+        let seen = read_seen(db)?;
+        // The last line shipped stands for when the agent stopped
+        let stopped = seen.values().map(|seen| seen.last).max().unwrap_or(0);
+        let window_ms = window.as_millis() as i64;
+        Ok(DuplicateFilter {
+            remaining: seen
+                .into_iter()
+                .filter(|(_, seen)| seen.count > 0 && seen.last > stopped - window_ms)
+                .map(|(hash, seen)| (hash, seen.count))
+                .collect(),
+            until: Instant::now() + window,
+        })
+    }
+
+    /// Number of shipped lines still to be matched
+    pub fn len(&self) -> usize {
+        self.remaining.values().map(|count| *count as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining.is_empty()
+    }
+
+    pub fn is_duplicate(&mut self, line: &[u8]) -> bool {
+        if self.remaining.is_empty() {
+            return false;
+        }
+        if Instant::now() >= self.until {
+            self.remaining = HashMap::new();
+            return false;
+        }
+        let hash = line_hash(line);
+        match self.remaining.get_mut(&hash) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.remaining.remove(&hash);
+                }
+                Metrics::checkpoints().increment_restart_duplicates();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AgentState;
+    use tempfile::tempdir;
+
+    #[test]
+    fn lines_shipped_before_a_restart_are_skipped() {
+        let dir = tempdir().unwrap();
+        let window = Duration::from_secs(60);
+        let state = AgentState::new(dir.path()).unwrap();
+
+        let mut recent = RecentLines::load(&state.db, window);
+        recent.stage(line_hash(b"old"));
+        recent.ship(now_ms() - 120_000);
+        for line in &["repeated", "repeated", "last"] {
+            recent.stage(line_hash(line.as_bytes()));
+        }
+        recent.flush(&state.db);
+        recent.stage(line_hash(b"not shipped"));
+        recent.clear();
+        recent.flush(&state.db);
+        // The old line expired
+        assert_eq!(recent.seen.len(), 2);
+
+        let mut filter = DuplicateFilter::load(&state.db, window).unwrap();
+        assert_eq!(filter.len(), 3);
+        assert!(filter.is_duplicate(b"repeated"));
+        assert!(filter.is_duplicate(b"repeated"));
+        assert!(!filter.is_duplicate(b"repeated"));
+        assert!(!filter.is_duplicate(b"old"));
+        assert!(!filter.is_duplicate(b"not shipped"));
+        assert!(filter.is_duplicate(b"last"));
+        assert!(filter.is_empty());
+    }
+}
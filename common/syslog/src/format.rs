@@ -0,0 +1,103 @@
+use chrono::{SecondsFormat, TimeZone, Utc};
+use http::types::body::{Line, LineMeta};
+
+const NILVALUE: &str = "-";
+const MAX_HOSTNAME: usize = 255;
+const MAX_APP_NAME: usize = 48;
+/// Severity of lines without a level, or with one that doesn't map to a syslog severity
+const DEFAULT_SEVERITY: u8 = 6;
+
+/// Formats `line` as an RFC 5424 message framed with its length, as described by RFC 6587
+/// for syslog over TCP
+pub(crate) fn frame(line: &Line, facility: u8) -> Vec<u8> {
+    let message = format(line, facility);
+    let mut framed = format!("{} ", message.len()).into_bytes();
+    framed.extend_from_slice(message.as_bytes());
+    framed
+}
+
+fn format(line: &Line, facility: u8) -> String {
+    let severity = line
+        .get_level()
+        .and_then(severity)
+        .unwrap_or(DEFAULT_SEVERITY);
+    let timestamp = Utc
+        .timestamp_millis(line.timestamp)
+        .to_rfc3339_opts(SecondsFormat::Millis, true);
+    format!(
+        "<{}>1 {} {} {} {} {} {} {}",
+        facility as u16 * 8 + severity as u16,
+        timestamp,
+        header_field(line.get_host(), MAX_HOSTNAME),
+        header_field(line.get_app(), MAX_APP_NAME),
+        NILVALUE,
+        NILVALUE,
+        NILVALUE,
+        line.line
+    )
+}
+
+/// Header fields are limited to printable US-ASCII without spaces
+fn header_field(value: Option<&str>, max: usize) -> String {
+    let value: String = value
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if value.is_empty() {
+        NILVALUE.to_string()
+    } else {
+        value
+    }
+}
+
+fn severity(level: &str) -> Option<u8> {
+    match level.to_ascii_uppercase().as_str() {
+        "EMERG" | "EMERGENCY" | "PANIC" => Some(0),
+        "ALERT" => Some(1),
+        "CRIT" | "CRITICAL" | "FATAL" => Some(2),
+        "ERR" | "ERROR" => Some(3),
+        "WARN" | "WARNING" => Some(4),
+        "NOTICE" => Some(5),
+        "INFO" => Some(6),
+        "DEBUG" | "TRACE" => Some(7),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn formats_rfc5424_messages() {
+        let mut line = LineBuilder::new()
+            .line("disk almost full")
+            .host("node-1")
+            .app("disk monitor")
+            .level("warning")
+            .build()
+            .unwrap();
+        line.timestamp = 1_622_548_800_123;
+
+        // local0 (16) * 8 + warning (4)
+        let message = "<132>1 2021-06-01T12:00:00.123Z node-1 diskmonitor - - - disk almost full";
+        assert_eq!(format(&line, 16), message);
+        assert_eq!(
+            frame(&line, 16),
+            format!("{} {}", message.len(), message).into_bytes()
+        );
+    }
+
+    #[test]
+    fn uses_nil_values_for_missing_fields() {
+        let mut line = LineBuilder::new().line("hello").build().unwrap();
+        line.timestamp = 0;
+        assert_eq!(
+            format(&line, 1),
+            "<14>1 1970-01-01T00:00:00.000Z - - - - - hello"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+mod format;
+pub mod sink;
+
+/// Parses a syslog facility, either by its keyword, e.g. `local0`, or by its code
+pub fn parse_facility(facility: &str) -> Option<u8> {
+    const NAMES: [&str; 24] = [
+        "kern",
+        "user",
+        "mail",
+        "daemon",
+        "auth",
+        "syslog",
+        "lpr",
+        "news",
+        "uucp",
+        "cron",
+        "authpriv",
+        "ftp",
+        "ntp",
+        "security",
+        "console",
+        "solaris-cron",
+        "local0",
+        "local1",
+        "local2",
+        "local3",
+        "local4",
+        "local5",
+        "local6",
+        "local7",
+    ];
+    let facility = facility.trim().to_ascii_lowercase();
+    match facility.parse::<u8>() {
+        Ok(code) if (code as usize) < NAMES.len() => Some(code),
+        Ok(_) => None,
+        Err(_) => NAMES.iter().position(|n| *n == facility).map(|i| i as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_facilities() {
+        assert_eq!(parse_facility("user"), Some(1));
+        assert_eq!(parse_facility("LOCAL0"), Some(16));
+        assert_eq!(parse_facility("23"), Some(23));
+        assert_eq!(parse_facility("24"), None);
+        assert_eq!(parse_facility("local8"), None);
+    }
+}
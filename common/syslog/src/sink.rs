@@ -0,0 +1,194 @@
+use crate::format::frame;
+
+use http::types::body::Line;
+use log::{debug, info, warn};
+use metrics::Metrics;
+use rustls::ClientConfig;
+use sink::{Policy, Queue, TlsOptions};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Receiver;
+use tokio_rustls::TlsConnector;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// `host:port` of the syslog relay
+    pub address: String,
+    pub tls: bool,
+    /// PEM encoded CA certificates trusted on top of the public roots, for relays with a
+    /// certificate from a private CA
+    pub ca_cert: Option<PathBuf>,
+    pub tls_options: TlsOptions,
+    pub facility: u8,
+    pub policy: Policy,
+}
+
+/// Starts forwarding lines as RFC 5424 messages to a syslog relay over TCP, or TLS when
+/// `options.tls` is set. Must be called from within a tokio runtime.
+pub fn spawn(options: Options) -> Result<Queue, String> {
+    if options.facility > 23 {
+        return Err(format!("invalid syslog facility {}", options.facility));
+    }
+    let tls = if options.tls {
+        Some(tls_connector(&options).map_err(|e| format!("invalid syslog tls config: {}", e))?)
+    } else {
+        None
+    };
+    info!("forwarding lines to syslog relay {}", options.address);
+
+    let (queue, rx) = sink::queue("syslog sink", options.policy.buffer_size, || {
+        Metrics::syslog().increment_dropped()
+    });
+    let forwarder = Forwarder {
+        options,
+        tls,
+        connection: None,
+    };
+    tokio::spawn(forwarder.run(rx));
+    Ok(queue)
+}
+
+fn tls_connector(options: &Options) -> io::Result<TlsConnector> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    if let Some(path) = options.ca_cert.as_ref() {
+        config
+            .root_store
+            .add_pem_file(&mut BufReader::new(File::open(path)?))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid ca certificate"))?;
+    }
+    options.tls_options.apply(&mut config, "syslog sink");
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+type Connection = BufWriter<Box<dyn AsyncWrite + Send + Unpin>>;
+
+struct Forwarder {
+    options: Options,
+    tls: Option<TlsConnector>,
+    connection: Option<Connection>,
+}
+
+impl Forwarder {
+    async fn run(mut self, mut rx: Receiver<Line>) {
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => self.write(&frame(&line, self.options.facility)).await,
+                    None => break,
+                },
+                _ = flush.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    /// Writes a message, reconnecting to the relay as the retry policy allows
+    async fn write(&mut self, message: &[u8]) {
+        let mut delays = self.options.policy.delays();
+        loop {
+            match self.try_write(message).await {
+                Ok(_) => {
+                    Metrics::syslog().increment_lines();
+                    return;
+                }
+                Err(e) => {
+                    // The relay may have received part of the message, the rest would be garbage
+                    self.connection = None;
+                    match delays.next() {
+                        Some(delay) => {
+                            warn!(
+                                "unable to forward to syslog relay {}, retrying in {:?}: {}",
+                                self.options.address, delay, e
+                            );
+                            tokio::time::sleep(delay).await;
+                        }
+                        None => {
+                            warn!(
+                                "unable to forward to syslog relay {}, dropping message: {}",
+                                self.options.address, e
+                            );
+                            Metrics::syslog().increment_failures();
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn try_write(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.connection.is_none() {
+            let connection = self.connect().await?;
+            debug!("connected to syslog relay {}", self.options.address);
+            Metrics::syslog().increment_reconnects();
+            self.connection = Some(BufWriter::new(connection));
+        }
+        let connection = self.connection.as_mut().expect("connected");
+        connection.write_all(message).await
+    }
+
+    async fn flush(&mut self) {
+        if let Some(connection) = self.connection.as_mut() {
+            if let Err(e) = connection.flush().await {
+                warn!("lost connection to syslog relay: {}", e);
+                Metrics::syslog().increment_failures();
+                self.connection = None;
+            }
+        }
+    }
+
+    async fn connect(&self) -> io::Result<Box<dyn AsyncWrite + Send + Unpin>> {
+        let stream = TcpStream::connect(self.options.address.as_str()).await?;
+        match self.tls.as_ref() {
+            Some(connector) => {
+                let address = self.options.address.as_str();
+                let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+                let name = webpki::DNSNameRef::try_from_ascii_str(host).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "invalid relay hostname")
+                })?;
+                Ok(Box::new(connector.connect(name, stream).await?))
+            }
+            None => Ok(Box::new(stream)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn forwards_framed_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sender = spawn(Options {
+            address: listener.local_addr().unwrap().to_string(),
+            tls: false,
+            ca_cert: None,
+            facility: 1,
+            policy: Policy::default(),
+        })
+        .unwrap();
+        sender.send(LineBuilder::new().line("hello").build().unwrap());
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = vec![0; 64];
+        let read = stream.read(&mut received).await.unwrap();
+        let received = String::from_utf8_lossy(&received[..read]);
+        assert!(received.starts_with("46 <14>1 "), "{}", received);
+        assert!(received.ends_with(" - - - - - hello"), "{}", received);
+    }
+}
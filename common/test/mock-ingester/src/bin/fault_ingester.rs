@@ -0,0 +1,48 @@
+#[macro_use]
+extern crate log;
+
+use std::collections::HashSet;
+
+use logdna_mock_ingester::{http_ingester_with_faults, FaultSchedule};
+
+/// Runs an ingester failing requests on a schedule, to check the buffer and retry settings
+/// of an agent before relying on them, e.g.
+/// `fault_ingester 429/5,500/7,slow:30000/11,reset/13 0.0.0.0:1337`. Every 10 seconds it logs
+/// the lines received and how many of them were duplicates.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let faults = FaultSchedule::parse(&args.next().unwrap_or_default())?;
+    let addr = args
+        .next()
+        .unwrap_or_else(|| "0.0.0.0:1337".into())
+        .parse()?;
+    info!("Listening on http://{} with faults {:?}", addr, faults);
+
+    let (server, received, shutdown_handle) = http_ingester_with_faults(addr, faults);
+    let report = async {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(10));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = tokio::signal::ctrl_c() => break,
+            }
+            let files = received.lock().await;
+            for (file, info) in files.iter() {
+                let unique: HashSet<&String> = info.values.iter().collect();
+                info!(
+                    "{}: {} lines received, {} duplicates",
+                    file,
+                    info.values.len(),
+                    info.values.len() - unique.len()
+                );
+            }
+        }
+        info!("Shutting down");
+        shutdown_handle();
+    };
+    tokio::join!(report, server).1?;
+    Ok(())
+}
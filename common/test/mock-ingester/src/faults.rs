@@ -0,0 +1,96 @@
+// Only reachable through the `faults` feature, the ingester runs without faults otherwise
+#![cfg_attr(not(feature = "faults"), allow(dead_code))]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// A failure of the ingest API, injected instead of a regular response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Answers 429 Too Many Requests without ingesting the lines
+    TooManyRequests,
+    /// Answers 500 Internal Server Error without ingesting the lines
+    ServerError,
+    /// Ingests the lines but only answers after the delay, past the client's timeout the
+    /// lines are sent again
+    Slow(Duration),
+    /// Closes the connection without answering nor ingesting the lines
+    Reset,
+}
+
+#[derive(Debug, Error)]
+#[error("invalid fault {0}, use 429, 500, slow:<ms> or reset followed by /<every N requests>")]
+pub struct InvalidFault(String);
+
+/// Which requests get a fault, each fault is injected every N requests and the first one
+/// that's due wins
+#[derive(Debug, Default)]
+pub struct FaultSchedule {
+    faults: Vec<(Fault, u64)>,
+    requests: AtomicU64,
+}
+
+impl FaultSchedule {
+    pub fn new(faults: Vec<(Fault, u64)>) -> Self {
+        FaultSchedule {
+            faults: faults.into_iter().filter(|(_, every)| *every > 0).collect(),
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Parses a comma separated list of `<fault>/<every>`, e.g. `429/5,slow:2000/7,reset/11`
+    pub fn parse(spec: &str) -> Result<Self, InvalidFault> {
+        let mut faults = Vec::new();
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let invalid = || InvalidFault(item.to_string());
+            let (fault, every) = item.split_once('/').ok_or_else(invalid)?;
+            let every: u64 = every.parse().map_err(|_| invalid())?;
+            let fault = match fault.split_once(':') {
+                None if fault == "429" => Fault::TooManyRequests,
+                None if fault == "500" => Fault::ServerError,
+                None if fault == "reset" => Fault::Reset,
+                Some(("slow", ms)) => {
+                    Fault::Slow(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+                }
+                _ => return Err(invalid()),
+            };
+            faults.push((fault, every));
+        }
+        Ok(FaultSchedule::new(faults))
+    }
+
+    /// The fault of the next request, if any
+    pub fn next(&self) -> Option<Fault> {
+        let request = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        self.faults
+            .iter()
+            .find(|(_, every)| request % every == 0)
+            .map(|(fault, _)| *fault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_schedules_faults() {
+        let schedule = FaultSchedule::parse("500/3, slow:250/2,reset/5").unwrap();
+        let faults: Vec<Option<Fault>> = (0..6).map(|_| schedule.next()).collect();
+        let slow = Some(Fault::Slow(Duration::from_millis(250)));
+        let error = Some(Fault::ServerError);
+        assert_eq!(
+            faults,
+            vec![None, slow, error, slow, Some(Fault::Reset), error]
+        );
+        assert!(FaultSchedule::parse("503/2").is_err());
+        assert!(FaultSchedule::parse("429").is_err());
+        assert!(FaultSchedule::parse("").unwrap().next().is_none());
+    }
+}
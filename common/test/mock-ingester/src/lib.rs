@@ -22,6 +22,11 @@ use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
 use tokio_stream::wrappers::TcpListenerStream;
 
+mod faults;
+
+#[cfg(feature = "faults")]
+pub use faults::{Fault, FaultSchedule, InvalidFault};
+
 const ROOT: &str = "/logs/agent";
 
 pub type FileLineCounter = Arc<Mutex<HashMap<String, FileInfo>>>;
@@ -42,6 +47,8 @@ pub enum IngestError {
     HyperError(#[from] hyper::Error),
     #[error(transparent)]
     IoError(#[from] std::io::Error),
+    #[error("connection reset by the fault schedule")]
+    Reset,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -62,6 +69,7 @@ pub struct Line {
 pub struct Svc {
     files: FileLineCounter,
     process_fn: Arc<ProcessFn>,
+    faults: Arc<faults::FaultSchedule>,
 }
 
 impl Unpin for Svc {}
@@ -80,6 +88,7 @@ impl Service<Request<Body>> for Svc {
         info!("Received {:?}", req);
         let files = self.files.clone();
         let process_fn = self.process_fn.clone();
+        let faults = self.faults.clone();
         Box::pin(async move {
             let rsp = Response::builder();
 
@@ -88,6 +97,19 @@ impl Service<Request<Body>> for Svc {
                 return Ok(rsp.status(404).body(Body::empty()).unwrap());
             }
 
+            let fault = faults.next();
+            match fault {
+                Some(faults::Fault::TooManyRequests) => {
+                    return Ok(rsp.status(429).body(Body::empty()).unwrap());
+                }
+                Some(faults::Fault::ServerError) => {
+                    return Ok(rsp.status(500).body(Body::empty()).unwrap());
+                }
+                // Hyper drops the connection of requests that fail
+                Some(faults::Fault::Reset) => return Err(IngestError::Reset),
+                Some(faults::Fault::Slow(_)) | None => {}
+            }
+
             let encoding = {
                 &req.headers()
                     .get("content-encoding")
@@ -166,6 +188,9 @@ impl Service<Request<Body>> for Svc {
                 }
             }
 
+            if let Some(faults::Fault::Slow(delay)) = fault {
+                tokio::time::sleep(delay).await;
+            }
             Ok(rsp.status(200).body(Body::empty()).unwrap())
         })
     }
@@ -174,6 +199,7 @@ impl Service<Request<Body>> for Svc {
 pub struct MakeSvc {
     files: FileLineCounter,
     process_fn: Arc<ProcessFn>,
+    faults: Arc<faults::FaultSchedule>,
 }
 
 impl MakeSvc {
@@ -181,6 +207,7 @@ impl MakeSvc {
         MakeSvc {
             files: Arc::new(Mutex::new(HashMap::new())),
             process_fn: Arc::new(process_fn),
+            faults: Arc::new(faults::FaultSchedule::default()),
         }
     }
 }
@@ -204,6 +231,7 @@ impl<T> Service<T> for MakeSvc {
         future::ok(Svc {
             files: self.files.clone(),
             process_fn: self.process_fn.clone(),
+            faults: self.faults.clone(),
         })
     }
 }
@@ -225,9 +253,35 @@ pub fn http_ingester_with_processors(
     impl Future<Output = std::result::Result<(), IngestError>>,
     FileLineCounter,
     impl FnOnce(),
+) {
+    serve_http(addr, MakeSvc::new(process_fn))
+}
+
+/// Like [`http_ingester`], but answers with the faults of the schedule when they're due, to
+/// check that clients neither lose nor duplicate too many lines when the ingest API fails
+#[cfg(feature = "faults")]
+pub fn http_ingester_with_faults(
+    addr: SocketAddr,
+    faults: FaultSchedule,
+) -> (
+    impl Future<Output = std::result::Result<(), IngestError>>,
+    FileLineCounter,
+    impl FnOnce(),
+) {
+    let mut mk_svc = MakeSvc::default();
+    mk_svc.faults = Arc::new(faults);
+    serve_http(addr, mk_svc)
+}
+
+fn serve_http(
+    addr: SocketAddr,
+    mk_svc: MakeSvc,
+) -> (
+    impl Future<Output = std::result::Result<(), IngestError>>,
+    FileLineCounter,
+    impl FnOnce(),
 ) {
     let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-    let mk_svc = MakeSvc::new(process_fn);
     let received = mk_svc.files.clone();
     (
         async move {
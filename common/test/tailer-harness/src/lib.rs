@@ -0,0 +1,241 @@
+//! Scripts filesystem scenarios against the tailer and records the exact lines it emits, e.g.
+//!
+//! ```ignore
+//! Scenario::new()
+//!     .append("app.log", &["one", "two"])
+//!     .rotate("app.log")
+//!     .append("app.log", &["three"])
+//!     .expect(&[("app.log", "one"), ("app.log", "two"), ("app.log", "three")]);
+//! ```
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use fs::rule::{GlobRule, Rules};
+use fs::tail::{DirPathBuf, Lookback, Tailer};
+use futures::{pin_mut, Stream, StreamExt};
+use http::types::body::{LineBufferMut, LineMeta};
+use tempfile::tempdir;
+
+/// How long the tailer is given to emit the lines of a step when none is set
+const DEFAULT_SETTLE: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+enum Action {
+    Create(PathBuf),
+    Append(PathBuf, Vec<String>),
+    /// Renames the file to `<file>.1` and creates a new one in its place, as logrotate does
+    Rotate(PathBuf),
+    Truncate(PathBuf),
+    Delete(PathBuf),
+    Rename(PathBuf, PathBuf),
+    Mkdir(PathBuf),
+}
+
+impl Action {
+    fn apply(&self, root: &Path) {
+        let result = match self {
+            Action::Create(path) => File::create(root.join(path)).map(drop),
+            Action::Append(path, lines) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(root.join(path))
+                .and_then(|mut file| {
+                    for line in lines {
+                        writeln!(file, "{}", line)?;
+                    }
+                    file.sync_all()
+                }),
+            Action::Rotate(path) => {
+                let mut rotated = root.join(path).into_os_string();
+                rotated.push(".1");
+                std::fs::rename(root.join(path), rotated)
+                    .and_then(|_| File::create(root.join(path)).map(drop))
+            }
+            Action::Truncate(path) => OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(root.join(path))
+                .map(drop),
+            Action::Delete(path) => std::fs::remove_file(root.join(path)),
+            Action::Rename(from, to) => std::fs::rename(root.join(from), root.join(to)),
+            Action::Mkdir(path) => std::fs::create_dir_all(root.join(path)),
+        };
+        if let Err(e) = result {
+            panic!("unable to {:?}: {}", self, e);
+        }
+    }
+}
+
+struct Step {
+    action: Action,
+    /// Whether the lines are drained before the next step
+    settle: bool,
+}
+
+/// A sequence of filesystem changes applied to a temp dir the tailer watches. After each step
+/// the lines emitted are collected until none arrives within the settle window, so each step
+/// observes the effect of the previous ones.
+pub struct Scenario {
+    steps: Vec<Step>,
+    /// Steps before this one are applied before the tailer starts
+    start: usize,
+    lookback: Lookback,
+    settle: Duration,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario::new()
+    }
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Scenario {
+            steps: Vec::new(),
+            start: 0,
+            lookback: Lookback::default(),
+            settle: DEFAULT_SETTLE,
+        }
+    }
+
+    pub fn lookback(mut self, lookback: Lookback) -> Self {
+        self.lookback = lookback;
+        self
+    }
+
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Starts the tailer after the steps so far, by default it starts before the first step
+    pub fn start_tailing(mut self) -> Self {
+        self.start = self.steps.len();
+        self
+    }
+
+    /// Applies the next step right after the last one, e.g. to change a file while it's read
+    pub fn without_settling(mut self) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.settle = false;
+        }
+        self
+    }
+
+    pub fn create(self, path: impl Into<PathBuf>) -> Self {
+        self.step(Action::Create(path.into()))
+    }
+
+    /// Appends the lines to the file, creating it when missing
+    pub fn append(self, path: impl Into<PathBuf>, lines: &[&str]) -> Self {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        self.step(Action::Append(path.into(), lines))
+    }
+
+    pub fn rotate(self, path: impl Into<PathBuf>) -> Self {
+        self.step(Action::Rotate(path.into()))
+    }
+
+    pub fn truncate(self, path: impl Into<PathBuf>) -> Self {
+        self.step(Action::Truncate(path.into()))
+    }
+
+    pub fn delete(self, path: impl Into<PathBuf>) -> Self {
+        self.step(Action::Delete(path.into()))
+    }
+
+    pub fn rename(self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) -> Self {
+        self.step(Action::Rename(from.into(), to.into()))
+    }
+
+    pub fn mkdir(self, path: impl Into<PathBuf>) -> Self {
+        self.step(Action::Mkdir(path.into()))
+    }
+
+    fn step(mut self, action: Action) -> Self {
+        self.steps.push(Step {
+            action,
+            settle: true,
+        });
+        self
+    }
+
+    /// Plays the scenario, returns the path relative to the watched dir and the value of each
+    /// line emitted, in order
+    pub fn run(self) -> Vec<(String, String)> {
+        let dir = tempdir().expect("unable to create a temp dir");
+        let root = dir
+            .path()
+            .canonicalize()
+            .expect("unable to resolve the temp dir");
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to start a runtime")
+            .block_on(self.play(&root))
+    }
+
+    /// Plays the scenario and asserts the lines emitted are exactly `expected`
+    pub fn expect(self, expected: &[(&str, &str)]) {
+        let emitted = self.run();
+        let emitted: Vec<(&str, &str)> = emitted
+            .iter()
+            .map(|(path, line)| (path.as_str(), line.as_str()))
+            .collect();
+        assert_eq!(emitted, expected);
+    }
+
+    async fn play(self, root: &Path) -> Vec<(String, String)> {
+        let (setup, live) = self.steps.split_at(self.start);
+        for step in setup {
+            step.action.apply(root);
+        }
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("**").expect("valid glob"));
+        let dir: DirPathBuf = root
+            .try_into()
+            .unwrap_or_else(|_| panic!("{:?} is not a directory", root));
+        let mut tailer = Tailer::new(vec![dir], rules, self.lookback.clone(), None);
+        let mut buf = [0u8; 4096];
+        let lines = tailer
+            .process(&mut buf)
+            .expect("unable to start the tailer");
+        pin_mut!(lines);
+
+        let mut emitted = Vec::new();
+        drain(&mut lines, root, self.settle, &mut emitted).await;
+        for step in live {
+            step.action.apply(root);
+            if step.settle {
+                drain(&mut lines, root, self.settle, &mut emitted).await;
+            }
+        }
+        emitted
+    }
+}
+
+/// Collects the lines emitted until none arrives within `settle`
+async fn drain<S, L>(
+    lines: &mut S,
+    root: &Path,
+    settle: Duration,
+    emitted: &mut Vec<(String, String)>,
+) where
+    S: Stream<Item = L> + Unpin,
+    L: LineBufferMut + LineMeta,
+{
+    while let Ok(Some(mut line)) = tokio::time::timeout(settle, lines.next()).await {
+        let value = line
+            .get_line_buffer()
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+            .unwrap_or_default();
+        let path = Path::new(line.get_file().unwrap_or_default());
+        let path = path.strip_prefix(root).unwrap_or(path);
+        emitted.push((path.to_string_lossy().into_owned(), value));
+    }
+}
@@ -0,0 +1,86 @@
+use fs::tail::Lookback;
+use tailer_harness::Scenario;
+
+#[test]
+fn reads_existing_lines_from_start() {
+    Scenario::new()
+        .append("app.log", &["one", "two"])
+        .start_tailing()
+        .lookback(Lookback::Start)
+        .append("app.log", &["three"])
+        .expect(&[("app.log", "one"), ("app.log", "two"), ("app.log", "three")]);
+}
+
+#[test]
+fn skips_existing_lines_without_lookback() {
+    Scenario::new()
+        .append("app.log", &["one"])
+        .start_tailing()
+        .lookback(Lookback::None)
+        .append("app.log", &["two"])
+        .create("new.log")
+        .append("new.log", &["three"])
+        .expect(&[("app.log", "two"), ("new.log", "three")]);
+}
+
+#[test]
+fn follows_rotated_files() {
+    Scenario::new()
+        .append("app.log", &["one", "two"])
+        .rotate("app.log")
+        .append("app.log", &["three"])
+        .rotate("app.log")
+        .append("app.log", &["four"])
+        .expect(&[
+            ("app.log", "one"),
+            ("app.log", "two"),
+            ("app.log", "three"),
+            ("app.log", "four"),
+        ]);
+}
+
+#[test]
+fn rereads_truncated_files() {
+    // The lines after the truncation must be shorter than the ones before, a file that grew
+    // past its last offset can't be told apart from one that was only appended to
+    Scenario::new()
+        .append("app.log", &["first line", "second line"])
+        .truncate("app.log")
+        .append("app.log", &["third"])
+        .expect(&[
+            ("app.log", "first line"),
+            ("app.log", "second line"),
+            ("app.log", "third"),
+        ]);
+}
+
+#[test]
+fn follows_files_renamed_across_dirs() {
+    Scenario::new()
+        .mkdir("a")
+        .mkdir("b")
+        .append("a/app.log", &["one"])
+        .rename("a/app.log", "b/app.log")
+        .append("b/app.log", &["two"])
+        .expect(&[("a/app.log", "one"), ("b/app.log", "two")]);
+}
+
+#[test]
+fn survives_deletion_mid_read() {
+    let lines: Vec<String> = (0..2000).map(|i| format!("line {}", i)).collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let emitted = Scenario::new()
+        .append("app.log", &lines)
+        .without_settling()
+        .delete("app.log")
+        .append("app.log", &["after"])
+        .run();
+
+    // Whatever was read before the deletion comes in order and only once, then the new file
+    // at the same path is read from its start
+    let (last, before) = emitted.split_last().expect("no lines emitted");
+    assert_eq!(last, &("app.log".to_string(), "after".to_string()));
+    for (i, (path, line)) in before.iter().enumerate() {
+        assert_eq!((path.as_str(), line.as_str()), ("app.log", lines[i]));
+    }
+}
@@ -0,0 +1,71 @@
+use http::types::body::Line;
+
+/// How lines are delimited on the socket
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// One JSON document per line
+    Ndjson,
+    /// Each JSON document is preceded by its length as a big endian `u32`
+    LengthPrefixed,
+}
+
+impl Framing {
+    pub fn parse(framing: &str) -> Option<Framing> {
+        match framing.trim().to_ascii_lowercase().as_str() {
+            "ndjson" | "json" => Some(Framing::Ndjson),
+            "length-prefixed" | "length_prefixed" => Some(Framing::LengthPrefixed),
+            _ => None,
+        }
+    }
+
+    /// Appends `line` to `buf`
+    pub(crate) fn encode(self, line: &Line, buf: &mut Vec<u8>) -> serde_json::Result<()> {
+        match self {
+            Framing::Ndjson => {
+                serde_json::to_writer(&mut *buf, line)?;
+                buf.push(b'\n');
+            }
+            Framing::LengthPrefixed => {
+                let start = buf.len();
+                buf.extend_from_slice(&[0; 4]);
+                serde_json::to_writer(&mut *buf, line)?;
+                let len = (buf.len() - start - 4) as u32;
+                buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+
+    #[test]
+    fn encodes_lines() {
+        let mut line = LineBuilder::new().line("hello").build().unwrap();
+        line.timestamp = 1;
+        let json = serde_json::to_vec(&line).unwrap();
+
+        let mut buf = Vec::new();
+        Framing::Ndjson.encode(&line, &mut buf).unwrap();
+        Framing::Ndjson.encode(&line, &mut buf).unwrap();
+        assert_eq!(buf, [&json[..], b"\n", &json[..], b"\n"].concat());
+
+        let mut buf = Vec::new();
+        Framing::LengthPrefixed.encode(&line, &mut buf).unwrap();
+        let len = (json.len() as u32).to_be_bytes();
+        assert_eq!(buf, [&len[..], &json[..]].concat());
+    }
+
+    #[test]
+    fn parses_framing() {
+        assert_eq!(Framing::parse("NDJSON"), Some(Framing::Ndjson));
+        assert_eq!(
+            Framing::parse("length-prefixed"),
+            Some(Framing::LengthPrefixed)
+        );
+        assert_eq!(Framing::parse("xml"), None);
+    }
+}
@@ -0,0 +1,4 @@
+mod framing;
+pub mod sink;
+
+pub use framing::Framing;
@@ -0,0 +1,155 @@
+use crate::framing::Framing;
+
+use http::types::body::Line;
+use log::{debug, info, warn};
+use metrics::Metrics;
+use sink::{Policy, Queue};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::Receiver;
+
+const MAX_BATCH_LINES: usize = 1_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub path: PathBuf,
+    pub framing: Framing,
+    pub policy: Policy,
+}
+
+/// Starts writing batches of lines to the Unix domain socket a node-local forwarder listens
+/// on. Must be called from within a tokio runtime.
+pub fn spawn(options: Options) -> Queue {
+    info!(
+        "writing lines to unix socket {} as {:?}",
+        options.path.display(),
+        options.framing
+    );
+    let (queue, rx) = sink::queue("unix socket sink", options.policy.buffer_size, || {
+        Metrics::unix_socket().increment_dropped()
+    });
+    let writer = Writer {
+        options,
+        stream: None,
+        batch: Vec::new(),
+        lines: 0,
+    };
+    tokio::spawn(writer.run(rx));
+    queue
+}
+
+struct Writer {
+    options: Options,
+    /// Opened on the first write and after the forwarder closes it
+    stream: Option<UnixStream>,
+    batch: Vec<u8>,
+    lines: usize,
+}
+
+impl Writer {
+    async fn run(mut self, mut rx: Receiver<Line>) {
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => {
+                        match self.options.framing.encode(&line, &mut self.batch) {
+                            Ok(_) => self.lines += 1,
+                            Err(e) => warn!("unable to serialize line for unix socket: {}", e),
+                        }
+                        if self.lines >= MAX_BATCH_LINES {
+                            self.flush().await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    async fn flush(&mut self) {
+        if self.lines == 0 {
+            return;
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let lines = std::mem::take(&mut self.lines) as u64;
+
+        let mut delays = self.options.policy.delays();
+        loop {
+            match self.write(&batch).await {
+                Ok(_) => {
+                    Metrics::unix_socket().add_lines(lines);
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "unable to write to unix socket {}: {}",
+                        self.options.path.display(),
+                        e
+                    );
+                    // A partially written batch leaves the stream in an unknown state
+                    self.stream = None;
+                }
+            }
+            match delays.next() {
+                Some(delay) => {
+                    debug!("retrying unix socket write in {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                }
+                None => break,
+            }
+        }
+        Metrics::unix_socket().add_failures(lines);
+    }
+
+    async fn write(&mut self, batch: &[u8]) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(UnixStream::connect(&self.options.path).await?);
+            debug!("connected to unix socket {}", self.options.path.display());
+            Metrics::unix_socket().increment_reconnects();
+        }
+        let stream = self.stream.as_mut().expect("connected");
+        stream.write_all(batch).await?;
+        stream.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn writes_batches_to_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forwarder.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let sender = spawn(Options {
+            path,
+            framing: Framing::Ndjson,
+            policy: Policy::default(),
+        });
+        sender.send(LineBuilder::new().line("a").build().unwrap());
+        sender.send(LineBuilder::new().line("b").build().unwrap());
+        drop(sender);
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).await.unwrap();
+        let lines: Vec<serde_json::Value> = received
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["line"], "a");
+        assert_eq!(lines[1]["line"], "b");
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::{SecondsFormat, TimeZone, Utc};
+use http::types::body::{Line, LineMeta};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+/// The mapping used when none is configured, keeping the field names of LogDNA lines
+const DEFAULT_FIELDS: &[&str] = &[
+    "line=line",
+    "app=app",
+    "host=host",
+    "level=level",
+    "file=file",
+    "env=env",
+    "timestamp=timestamp",
+    "meta=meta",
+    "labels=labels",
+    "annotations=annotations",
+];
+
+#[derive(Debug, Error)]
+pub enum InvalidTemplate {
+    #[error("{0} is not a name=field mapping")]
+    Mapping(String),
+    #[error(
+        "{0} is not a line field, use line, app, host, level, file, env, timestamp, \
+         timestamp_rfc3339, meta, labels or annotations"
+    )]
+    Field(String),
+    #[error("{0} is not a valid name, names are dot separated keys")]
+    Name(String),
+}
+
+/// A field of the lines
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Field {
+    Line,
+    App,
+    Host,
+    Level,
+    File,
+    Env,
+    /// Milliseconds since the epoch
+    Timestamp,
+    Rfc3339Timestamp,
+    Meta,
+    Labels,
+    Annotations,
+}
+
+impl Field {
+    fn parse(field: &str) -> Result<Self, InvalidTemplate> {
+        Ok(match field {
+            "line" => Field::Line,
+            "app" => Field::App,
+            "host" => Field::Host,
+            "level" => Field::Level,
+            "file" => Field::File,
+            "env" => Field::Env,
+            "timestamp" => Field::Timestamp,
+            "timestamp_rfc3339" => Field::Rfc3339Timestamp,
+            "meta" => Field::Meta,
+            "labels" => Field::Labels,
+            "annotations" => Field::Annotations,
+            _ => return Err(InvalidTemplate::Field(field.to_string())),
+        })
+    }
+
+    fn value(self, line: &Line) -> Option<Value> {
+        let string = |s: Option<&str>| s.map(|s| Value::String(s.to_string()));
+        match self {
+            Field::Line => Some(Value::String(line.line.clone())),
+            Field::App => string(line.get_app()),
+            Field::Host => string(line.get_host()),
+            Field::Level => string(line.get_level()),
+            Field::File => string(line.get_file()),
+            Field::Env => string(line.get_env()),
+            Field::Timestamp => Some(line.timestamp.into()),
+            Field::Rfc3339Timestamp => Some(Value::String(
+                Utc.timestamp_millis(line.timestamp)
+                    .to_rfc3339_opts(SecondsFormat::Millis, true),
+            )),
+            Field::Meta => line.get_meta().cloned(),
+            Field::Labels => line
+                .get_labels()
+                .and_then(|labels| serde_json::to_value(labels).ok()),
+            Field::Annotations => line
+                .get_annotations()
+                .and_then(|annotations| serde_json::to_value(annotations).ok()),
+        }
+    }
+}
+
+/// The shape of the JSON bodies posted, for HTTP APIs that don't take LogDNA lines.
+///
+/// Each line becomes an object with the fields of the mapping under their names, where
+/// dotted names nest, e.g. `attributes.service=app`, and fields the line doesn't have are
+/// left out. The objects are posted as a JSON array, or as the array under the envelope,
+/// e.g. an envelope of `data.logs` posts `{"data":{"logs":[...]}}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BodyTemplate {
+    envelope: Vec<String>,
+    fields: Vec<(Vec<String>, Field)>,
+}
+
+fn path(name: &str) -> Result<Vec<String>, InvalidTemplate> {
+    let path: Vec<String> = name.trim().split('.').map(str::to_string).collect();
+    if path.iter().any(String::is_empty) {
+        return Err(InvalidTemplate::Name(name.to_string()));
+    }
+    Ok(path)
+}
+
+impl BodyTemplate {
+    /// Parses the `name=field` mappings, the default mapping is used when there are none
+    pub fn parse(envelope: Option<&str>, fields: &[String]) -> Result<Self, InvalidTemplate> {
+        let envelope = match envelope.map(str::trim).filter(|e| !e.is_empty()) {
+            Some(envelope) => path(envelope)?,
+            None => Vec::new(),
+        };
+        let mappings: Vec<&str> = if fields.is_empty() {
+            DEFAULT_FIELDS.to_vec()
+        } else {
+            fields.iter().map(String::as_str).collect()
+        };
+        let fields = mappings
+            .into_iter()
+            .map(|mapping| match mapping.split_once('=') {
+                Some((name, field)) => Ok((path(name)?, Field::parse(field.trim())?)),
+                None => Err(InvalidTemplate::Mapping(mapping.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BodyTemplate { envelope, fields })
+    }
+
+    pub fn render(&self, lines: &[Line]) -> Value {
+        let records = lines.iter().map(|line| self.record(line)).collect();
+        let mut body = Value::Array(records);
+        for key in self.envelope.iter().rev() {
+            let mut envelope = Map::new();
+            envelope.insert(key.clone(), body);
+            body = Value::Object(envelope);
+        }
+        body
+    }
+
+    fn record(&self, line: &Line) -> Value {
+        let mut record = Map::new();
+        for (path, field) in self.fields.iter() {
+            if let Some(value) = field.value(line) {
+                insert(&mut record, path, value);
+            }
+        }
+        Value::Object(record)
+    }
+}
+
+/// Inserts `value` under the nested keys of `path`, replacing anything that isn't an object
+/// along the way
+fn insert(object: &mut Map<String, Value>, path: &[String], value: Value) {
+    match path {
+        [] => {}
+        [key] => {
+            object.insert(key.clone(), value);
+        }
+        [key, rest @ ..] => {
+            let child = object
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            if let Value::Object(child) = child {
+                insert(child, rest, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::types::body::LineBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn renders_the_mapped_fields() {
+        let template = BodyTemplate::parse(
+            Some("data.logs"),
+            &[
+                "message=line".to_string(),
+                "attributes.service=app".to_string(),
+                "attributes.severity=level".to_string(),
+                "@timestamp=timestamp_rfc3339".to_string(),
+            ],
+        )
+        .unwrap();
+        let mut line = LineBuilder::new()
+            .line("connection refused")
+            .app("web")
+            .build()
+            .unwrap();
+        line.timestamp = 1_622_548_800_123;
+
+        assert_eq!(
+            template.render(&[line]),
+            json!({ "data": { "logs": [{
+                "message": "connection refused",
+                "attributes": { "service": "web" },
+                "@timestamp": "2021-06-01T12:00:00.123Z",
+            }]}})
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_mappings() {
+        assert!(BodyTemplate::parse(None, &[]).is_ok());
+        assert!(matches!(
+            BodyTemplate::parse(None, &["message".to_string()]),
+            Err(InvalidTemplate::Mapping(_))
+        ));
+        assert!(matches!(
+            BodyTemplate::parse(None, &["message=body".to_string()]),
+            Err(InvalidTemplate::Field(_))
+        ));
+        assert!(matches!(
+            BodyTemplate::parse(Some("data..logs"), &[]),
+            Err(InvalidTemplate::Name(_))
+        ));
+    }
+}
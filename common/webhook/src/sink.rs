@@ -0,0 +1,190 @@
+use crate::body::BodyTemplate;
+
+use http::types::body::Line;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use log::info;
+use metrics::Metrics;
+use sink::{Policy, PoolOptions, Proxy, ProxyConnector, Queue, SignError, Signer, TlsOptions};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+const MAX_BATCH_LINES: usize = 1_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Url the batches of lines are posted to
+    pub url: String,
+    pub template: BodyTemplate,
+    /// Extra headers sent with every request, e.g. for authentication
+    pub headers: Vec<(String, String)>,
+    /// Proxy the API is connected to through
+    pub proxy: Option<Proxy>,
+    pub tls_options: TlsOptions,
+    pub pool: PoolOptions,
+    /// Signs the requests for gateways in front of the API that require it
+    pub signer: Option<Arc<dyn Signer>>,
+    pub policy: Policy,
+}
+
+/// Starts posting batches of lines as JSON, shaped by `options.template`, to a generic log
+/// HTTP API. Must be called from within a tokio runtime.
+pub fn spawn(options: Options) -> Result<Queue, String> {
+    let uri = options
+        .url
+        .parse::<Uri>()
+        .map_err(|e| format!("invalid webhook url {}: {}", options.url, e))?;
+    let headers = options
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("invalid webhook header {}: {}", name, e))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| format!("invalid value for webhook header {}: {}", name, e))?;
+            Ok((name, value))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    info!("posting lines to webhook at {}", uri);
+
+    let (queue, rx) = sink::queue("webhook sink", options.policy.buffer_size, || {
+        Metrics::webhook().increment_dropped()
+    });
+    let poster = Poster {
+        client: sink::https_client(
+            "webhook sink",
+            options.proxy,
+            &options.tls_options,
+            &options.pool,
+        ),
+        signer: options.signer,
+        uri,
+        headers,
+        template: options.template,
+        policy: options.policy,
+        batch: Vec::with_capacity(MAX_BATCH_LINES),
+    };
+    tokio::spawn(poster.run(rx));
+    Ok(queue)
+}
+
+struct Poster {
+    client: Client<HttpsConnector<ProxyConnector>>,
+    signer: Option<Arc<dyn Signer>>,
+    uri: Uri,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    template: BodyTemplate,
+    policy: Policy,
+    batch: Vec<Line>,
+}
+
+enum PostError {
+    Status(StatusCode),
+    Http(hyper::Error),
+    Sign(SignError),
+}
+
+impl PostError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            PostError::Status(status) => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            PostError::Http(_) => true,
+            PostError::Sign(_) => false,
+        }
+    }
+}
+
+impl Display for PostError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            PostError::Status(status) => write!(f, "{}", status),
+            PostError::Http(e) => write!(f, "{}", e),
+            PostError::Sign(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Poster {
+    async fn run(mut self, mut rx: Receiver<Line>) {
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => {
+                        self.batch.push(line);
+                        if self.batch.len() >= MAX_BATCH_LINES {
+                            self.flush().await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    async fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let lines = self.batch.len() as u64;
+        let body = hyper::body::Bytes::from(self.template.render(&self.batch).to_string());
+        self.batch.clear();
+        Metrics::webhook().increment_requests();
+
+        let posted = self
+            .policy
+            .retry_if(
+                "webhook post",
+                || self.send(body.clone()),
+                PostError::is_retryable,
+            )
+            .await;
+        match posted {
+            Some(_) => Metrics::webhook().add_lines(lines),
+            None => Metrics::webhook().add_failures(lines),
+        }
+    }
+
+    async fn send(&self, body: hyper::body::Bytes) -> Result<(), PostError> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        for (name, value) in self.headers.iter() {
+            request = request.header(name, value);
+        }
+        let mut request = request
+            .body(Body::from(body.clone()))
+            .expect("webhook request is valid");
+        if let Some(signer) = self.signer.as_ref() {
+            signer
+                .sign(&mut request, &body)
+                .await
+                .map_err(PostError::Sign)?;
+        }
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(PostError::Http)?;
+        let status = response.status();
+        // Drain the body so the connection can be reused
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(PostError::Http)?;
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(PostError::Status(status))
+        }
+    }
+}